@@ -0,0 +1,806 @@
+//! Account deletion.
+//!
+//! Deleting an account happens in two parts. While the request waits, we remove the user item and
+//! the user's org memberships. Every session of the user is checked against its org membership, so
+//! this also logs the user out everywhere at once.
+//!
+//! The rest runs in the background as a job, since it grows with the user's history: we remove the
+//! user's sharing permissions, follows, pending notifications, signing keys, read markers, links to
+//! identity providers, and profile photos, and replace the user's id with a tombstone id wherever
+//! others' data refers to them, like the authors of revisions. Documents that others can see are
+//! left in place, but nothing in them leads back to the user.
+//!
+//! Users who belong to no org can never log in. Sign ups used to write the user and their org
+//! separately, and could leave such users behind, so `delete_orphaned_users` removes them.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use actix_web::error;
+use futures::future::BoxFuture;
+use rusoto_dynamodb::{AttributeValue, DeleteItemInput, QueryInput, ScanInput, UpdateItemInput};
+
+use ot::writing_proto::{DeleteAccountRequest, DeleteAccountResponse};
+
+use crate::avatars;
+use crate::blob_store::BlobStore;
+use crate::dynamodb::{self, av_get_s, av_map, av_n, av_s, table_name, DynamoDbClient};
+use crate::http::SessionUser;
+use crate::ids::{Id, IdType};
+use crate::jobs::{self, Job, JobHandler};
+use crate::utils::time;
+
+/// The `job_type` of account deletion jobs in the `jobs` table.
+pub const DELETE_ACCOUNT_JOB_TYPE: &str = "delete_account";
+
+/// Delete the session user's account. The account is gone, and the user is logged out of every
+/// session, as soon as this returns. The rest of the user's data is removed in the background by a
+/// job, handled by `AccountDeletionJobHandler`.
+///
+/// If the password is not the user's password, returns 403 Forbidden.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn delete_account(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &DeleteAccountRequest,
+) -> actix_web::Result<DeleteAccountResponse> {
+    let log_error = |error_message: String| {
+        // Leave the request out of the log, since it holds the user's password.
+        log::error!(
+            "Error occurred: \"{}\" [delete_account] [session_user: {:?}]",
+            error_message,
+            session_user,
+        );
+    };
+    let output = dynamodb_client
+        .query(QueryInput {
+            table_name: table_name("users"),
+            index_name: Some(String::from("id-index")),
+            key_condition_expression: Some(String::from("id = :id")),
+            expression_attribute_values: Some(av_map(&[av_s(
+                ":id",
+                session_user.user_id.as_str(),
+            )])),
+            projection_expression: Some(String::from("email, hashed_password")),
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    let item = match output.items.unwrap_or_default().into_iter().next() {
+        Some(item) => item,
+        None => {
+            log_error("user does not exist".to_string());
+            return Err(error::ErrorInternalServerError(""));
+        }
+    };
+    let (email, hashed_password) =
+        match (av_get_s(&item, "email"), av_get_s(&item, "hashed_password")) {
+            (Some(email), Some(hashed_password)) => (email, hashed_password),
+            _ => {
+                log_error("user is missing a field".to_string());
+                return Err(error::ErrorInternalServerError(""));
+            }
+        };
+    let password_matched = bcrypt::verify(&request.password, hashed_password).unwrap_or(false);
+    if !password_matched {
+        return Err(error::ErrorForbidden(""));
+    }
+
+    // Create the job first, so that if anything below fails, the user can try again.
+    let tombstone_user_id = Id::new(IdType::User);
+    let job_id = jobs::create_job(
+        dynamodb_client,
+        DELETE_ACCOUNT_JOB_TYPE,
+        session_user,
+        &[av_s("tombstone_user_id", tombstone_user_id.as_str())],
+    )
+    .await
+    .map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+
+    let memberships = dynamodb::query_all_items(
+        dynamodb_client,
+        QueryInput {
+            table_name: table_name("organization_users"),
+            index_name: Some(String::from("user_id-last_login_at-index")),
+            key_condition_expression: Some(String::from("user_id = :user_id")),
+            expression_attribute_values: Some(av_map(&[av_s(
+                ":user_id",
+                session_user.user_id.as_str(),
+            )])),
+            projection_expression: Some(String::from("org_id, user_id")),
+            ..Default::default()
+        },
+    )
+    .await
+    .map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    delete_items(
+        dynamodb_client,
+        "organization_users",
+        &["org_id", "user_id"],
+        &memberships,
+    )
+    .await
+    .map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    dynamodb_client
+        .delete_item(DeleteItemInput {
+            table_name: table_name("users"),
+            key: av_map(&[av_s("email", email)]),
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+
+    Ok(DeleteAccountResponse {
+        job_id: job_id.as_str().to_string(),
+    })
+}
+
+/// Removes the rest of a deleted user's data, as started by `delete_account`. Every step can
+/// safely be run again.
+pub struct AccountDeletionJobHandler {
+    pub upload_store: Arc<dyn BlobStore>,
+    pub photo_store: Arc<dyn BlobStore>,
+}
+
+impl JobHandler for AccountDeletionJobHandler {
+    fn job_type(&self) -> &'static str {
+        DELETE_ACCOUNT_JOB_TYPE
+    }
+
+    fn run<'a>(
+        &'a self,
+        dynamodb_client: &'a DynamoDbClient,
+        job: &'a Job,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(anonymize_account(
+            dynamodb_client,
+            self.upload_store.as_ref(),
+            self.photo_store.as_ref(),
+            job,
+        ))
+    }
+}
+
+/// The number of steps in `anonymize_account`, for the job's progress.
+const NUM_ANONYMIZE_STEPS: i64 = 12;
+
+async fn anonymize_account(
+    dynamodb_client: &DynamoDbClient,
+    upload_store: &dyn BlobStore,
+    photo_store: &dyn BlobStore,
+    job: &Job,
+) -> anyhow::Result<()> {
+    let job_id = job.job_id.as_str();
+    let user_id = job.created_by_user_id.as_str();
+    let tombstone_user_id = av_get_s(&job.item, "tombstone_user_id")
+        .ok_or_else(|| anyhow::anyhow!("job has no tombstone_user_id"))?;
+    jobs::update_job(
+        dynamodb_client,
+        job_id,
+        &[av_n(":progress_total", NUM_ANONYMIZE_STEPS)],
+    )
+    .await?;
+
+    // TODO(cliff): Most of these tables have no index on the user id, so we scan them. Add indexes
+    // once scanning gets slow.
+    let user_id_values = av_map(&[av_s(":user_id", user_id)]);
+    let scan_for_user = |table: &str, attribute: &str, projection: &str| ScanInput {
+        table_name: table_name(table),
+        filter_expression: Some(format!("{} = :user_id", attribute)),
+        expression_attribute_values: Some(user_id_values.clone()),
+        projection_expression: Some(String::from(projection)),
+        ..Default::default()
+    };
+
+    let items = dynamodb::scan_all_items(
+        dynamodb_client,
+        scan_for_user(
+            "document_user_sharing_permissions",
+            "user_id",
+            "doc_id, user_id",
+        ),
+    )
+    .await?;
+    delete_items(
+        dynamodb_client,
+        "document_user_sharing_permissions",
+        &["doc_id", "user_id"],
+        &items,
+    )
+    .await?;
+    jobs::set_job_progress(dynamodb_client, job_id, 1).await?;
+
+    let items = dynamodb::scan_all_items(
+        dynamodb_client,
+        scan_for_user("document_followers", "user_id", "doc_id, user_id"),
+    )
+    .await?;
+    delete_items(
+        dynamodb_client,
+        "document_followers",
+        &["doc_id", "user_id"],
+        &items,
+    )
+    .await?;
+    jobs::set_job_progress(dynamodb_client, job_id, 2).await?;
+
+    let items = dynamodb::query_all_items(
+        dynamodb_client,
+        QueryInput {
+            table_name: table_name("pending_notifications"),
+            key_condition_expression: Some(String::from("user_id = :user_id")),
+            expression_attribute_values: Some(user_id_values.clone()),
+            projection_expression: Some(String::from("user_id, doc_id")),
+            ..Default::default()
+        },
+    )
+    .await?;
+    delete_items(
+        dynamodb_client,
+        "pending_notifications",
+        &["user_id", "doc_id"],
+        &items,
+    )
+    .await?;
+    jobs::set_job_progress(dynamodb_client, job_id, 3).await?;
+
+    let items = dynamodb::scan_all_items(
+        dynamodb_client,
+        scan_for_user("signing_keys", "user_id", "key_id"),
+    )
+    .await?;
+    delete_items(dynamodb_client, "signing_keys", &["key_id"], &items).await?;
+    jobs::set_job_progress(dynamodb_client, job_id, 4).await?;
+
+    let items = dynamodb::scan_all_items(
+        dynamodb_client,
+        scan_for_user(
+            "document_revisions",
+            "author_user_id",
+            "doc_id, revision_number",
+        ),
+    )
+    .await?;
+    set_user_id(
+        dynamodb_client,
+        "document_revisions",
+        &["doc_id", "revision_number"],
+        &items,
+        "author_user_id",
+        &["author_display_name"],
+        tombstone_user_id,
+    )
+    .await?;
+    jobs::set_job_progress(dynamodb_client, job_id, 5).await?;
+
+    let items = dynamodb::query_all_items(
+        dynamodb_client,
+        QueryInput {
+            table_name: table_name("documents"),
+            index_name: Some(String::from("created_by_user_id-updated_at-index")),
+            key_condition_expression: Some(String::from("created_by_user_id = :user_id")),
+            expression_attribute_values: Some(user_id_values.clone()),
+            projection_expression: Some(String::from("id")),
+            ..Default::default()
+        },
+    )
+    .await?;
+    set_user_id(
+        dynamodb_client,
+        "documents",
+        &["id"],
+        &items,
+        "created_by_user_id",
+        &[],
+        tombstone_user_id,
+    )
+    .await?;
+    let items = dynamodb::scan_all_items(
+        dynamodb_client,
+        scan_for_user("documents", "owner_user_id", "id"),
+    )
+    .await?;
+    set_user_id(
+        dynamodb_client,
+        "documents",
+        &["id"],
+        &items,
+        "owner_user_id",
+        &[],
+        tombstone_user_id,
+    )
+    .await?;
+    jobs::set_job_progress(dynamodb_client, job_id, 6).await?;
+
+    let items = dynamodb::scan_all_items(
+        dynamodb_client,
+        ScanInput {
+            // "token" is a reserved word.
+            expression_attribute_names: Some(maplit::hashmap! {
+                String::from("#token") => String::from("token"),
+            }),
+            ..scan_for_user("share_tokens", "created_by_user_id", "#token")
+        },
+    )
+    .await?;
+    set_user_id(
+        dynamodb_client,
+        "share_tokens",
+        &["token"],
+        &items,
+        "created_by_user_id",
+        &[],
+        tombstone_user_id,
+    )
+    .await?;
+    jobs::set_job_progress(dynamodb_client, job_id, 7).await?;
+
+    let items = dynamodb::query_all_items(
+        dynamodb_client,
+        QueryInput {
+            table_name: table_name("document_views"),
+            key_condition_expression: Some(String::from("user_id = :user_id")),
+            expression_attribute_values: Some(user_id_values.clone()),
+            projection_expression: Some(String::from("user_id, doc_id")),
+            ..Default::default()
+        },
+    )
+    .await?;
+    delete_items(
+        dynamodb_client,
+        "document_views",
+        &["user_id", "doc_id"],
+        &items,
+    )
+    .await?;
+    jobs::set_job_progress(dynamodb_client, job_id, 8).await?;
+
+    let items = dynamodb::query_all_items(
+        dynamodb_client,
+        QueryInput {
+            table_name: table_name("starred_documents"),
+            key_condition_expression: Some(String::from("user_id = :user_id")),
+            expression_attribute_values: Some(user_id_values.clone()),
+            projection_expression: Some(String::from("user_id, doc_id")),
+            ..Default::default()
+        },
+    )
+    .await?;
+    delete_items(
+        dynamodb_client,
+        "starred_documents",
+        &["user_id", "doc_id"],
+        &items,
+    )
+    .await?;
+    jobs::set_job_progress(dynamodb_client, job_id, 9).await?;
+
+    let items = dynamodb::scan_all_items(
+        dynamodb_client,
+        scan_for_user("read_markers", "user_id", "doc_id, user_id"),
+    )
+    .await?;
+    delete_items(
+        dynamodb_client,
+        "read_markers",
+        &["doc_id", "user_id"],
+        &items,
+    )
+    .await?;
+    jobs::set_job_progress(dynamodb_client, job_id, 10).await?;
+
+    let items = dynamodb::scan_all_items(
+        dynamodb_client,
+        scan_for_user("external_identities", "user_id", "provider, subject"),
+    )
+    .await?;
+    delete_items(
+        dynamodb_client,
+        "external_identities",
+        &["provider", "subject"],
+        &items,
+    )
+    .await?;
+    jobs::set_job_progress(dynamodb_client, job_id, 11).await?;
+
+    avatars::delete_avatars(upload_store, photo_store, user_id).await?;
+    jobs::set_job_progress(dynamodb_client, job_id, 12).await
+}
+
+/// Users created this long ago who still belong to no org are orphans. Anything newer may be a sign
+/// up in progress.
+pub const ORPHANED_USER_MIN_AGE_SECONDS: i64 = 60 * 60;
+
+/// Deletes users created before `created_before` who belong to no org, and returns how many were
+/// deleted. Run with `--clean_up_orphaned_users`.
+pub async fn delete_orphaned_users(
+    dynamodb_client: &DynamoDbClient,
+    created_before: &chrono::DateTime<chrono::Utc>,
+) -> anyhow::Result<usize> {
+    let users = dynamodb::scan_all_items(
+        dynamodb_client,
+        ScanInput {
+            table_name: table_name("users"),
+            filter_expression: Some(String::from("created_at < :created_before")),
+            expression_attribute_values: Some(av_map(&[av_s(
+                ":created_before",
+                &time::date_time_iso_str(created_before),
+            )])),
+            projection_expression: Some(String::from("id, email")),
+            ..Default::default()
+        },
+    )
+    .await?;
+    let mut num_deleted = 0;
+    for user in users.iter() {
+        let user_id = av_get_s(user, "id").ok_or_else(|| anyhow::anyhow!("user has no id"))?;
+        let output = dynamodb_client
+            .query(QueryInput {
+                table_name: table_name("organization_users"),
+                index_name: Some(String::from("user_id-last_login_at-index")),
+                key_condition_expression: Some(String::from("user_id = :user_id")),
+                expression_attribute_values: Some(av_map(&[av_s(":user_id", user_id)])),
+                limit: Some(1),
+                ..Default::default()
+            })
+            .await?;
+        if !output.items.unwrap_or_default().is_empty() {
+            continue;
+        }
+        log::info!("Deleting orphaned user {}", user_id);
+        delete_items(dynamodb_client, "users", &["email"], &[user.clone()]).await?;
+        num_deleted += 1;
+    }
+    Ok(num_deleted)
+}
+
+/// The key of the item, for a table whose key has the given attributes.
+fn item_key(
+    key_names: &[&str],
+    item: &HashMap<String, AttributeValue>,
+) -> anyhow::Result<HashMap<String, AttributeValue>> {
+    key_names
+        .iter()
+        .map(|key_name| match item.get(*key_name) {
+            Some(value) => Ok((key_name.to_string(), value.clone())),
+            None => Err(anyhow::anyhow!("item is missing key {}", key_name)),
+        })
+        .collect()
+}
+
+async fn delete_items(
+    dynamodb_client: &DynamoDbClient,
+    table: &str,
+    key_names: &[&str],
+    items: &[HashMap<String, AttributeValue>],
+) -> anyhow::Result<()> {
+    for item in items {
+        dynamodb_client
+            .delete_item(DeleteItemInput {
+                table_name: table_name(table),
+                key: item_key(key_names, item)?,
+                ..Default::default()
+            })
+            .await?;
+    }
+    Ok(())
+}
+
+/// Replaces the deleted user's id in the given attribute of each item with the tombstone id, and
+/// removes the `name_attributes` that name the user, like the author's name on revisions.
+async fn set_user_id(
+    dynamodb_client: &DynamoDbClient,
+    table: &str,
+    key_names: &[&str],
+    items: &[HashMap<String, AttributeValue>],
+    attribute: &str,
+    name_attributes: &[&str],
+    tombstone_user_id: &str,
+) -> anyhow::Result<()> {
+    let mut update_expression = format!("SET {} = :tombstone_user_id", attribute);
+    if !name_attributes.is_empty() {
+        update_expression.push_str(&format!(" REMOVE {}", name_attributes.join(", ")));
+    }
+    for item in items {
+        dynamodb_client
+            .update_item(UpdateItemInput {
+                table_name: table_name(table),
+                key: item_key(key_names, item)?,
+                update_expression: Some(update_expression.clone()),
+                expression_attribute_values: Some(av_map(&[av_s(
+                    ":tombstone_user_id",
+                    tombstone_user_id,
+                )])),
+                ..Default::default()
+            })
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rusoto_dynamodb::{GetItemInput, PutItemInput};
+
+    use ot::writing_proto::{ChangeSet, DocumentSharingPermission};
+
+    use crate::dynamodb::av_get_n;
+    use crate::identity_providers::{self, ExternalIdentity};
+    use crate::jobs::{JobStatus, JobWorker};
+    use crate::testing::fixtures::{
+        create_organization_user, create_user, DocumentFixture, RevisionFixture,
+    };
+    use crate::testing::utils::{MemoryBlobStore, TestDynamoDb};
+    use crate::users::UserRole;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[tokio::test]
+    async fn test_delete_account() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let org_id = Id::new(IdType::Organization);
+        let user_id = Id::new(IdType::User);
+        db.dynamodb_client
+            .put_item(PutItemInput {
+                table_name: table_name("users"),
+                item: av_map(&[
+                    av_s("id", user_id.as_str()),
+                    av_s("email", "leaving@example.com"),
+                    av_s("hashed_password", &bcrypt::hash("password", 4)?),
+                ]),
+                ..Default::default()
+            })
+            .await?;
+        create_organization_user(&db.dynamodb_client, &org_id, &user_id, &chrono::Utc::now()).await;
+        let session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: org_id.clone(),
+            user_role: UserRole::Default,
+        };
+
+        let colleague_id = Id::new(IdType::User);
+        let mut change_set = ChangeSet::new();
+        change_set.insert("Hello");
+        let doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_created_by_user_id(&user_id)
+            .with_revisions(vec![RevisionFixture::new(
+                &user_id,
+                &change_set,
+                &chrono::Utc::now(),
+            )])
+            .with_sharing(&colleague_id, DocumentSharingPermission::CanEdit);
+        doc.create(&db.dynamodb_client).await;
+        // Revisions record their author's name as well as their id.
+        db.dynamodb_client
+            .update_item(UpdateItemInput {
+                table_name: table_name("document_revisions"),
+                key: av_map(&[
+                    av_s("doc_id", doc.doc_id.as_str()),
+                    av_n("revision_number", 1),
+                ]),
+                update_expression: Some(String::from("SET author_display_name = :name")),
+                expression_attribute_values: Some(av_map(&[av_s(":name", "Leaving")])),
+                ..Default::default()
+            })
+            .await?;
+        let shared_doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_created_by_user_id(&colleague_id)
+            .with_sharing(&user_id, DocumentSharingPermission::CanView);
+        shared_doc.create(&db.dynamodb_client).await;
+        let identity = ExternalIdentity {
+            subject: String::from("1234"),
+            email: String::from("leaving@example.com"),
+            email_verified: true,
+            name: String::new(),
+        };
+        identity_providers::link_identity(
+            &db.dynamodb_client,
+            "google",
+            &identity,
+            &user_id,
+            "leaving@example.com",
+        )
+        .await?;
+        let upload_store = Arc::new(MemoryBlobStore::default());
+        let photo_store = Arc::new(MemoryBlobStore::default());
+        let photo_key = format!("avatars/{}/avatar/32.png", user_id.as_str());
+        photo_store.put(&photo_key, "image/png", vec![0]).await?;
+        let colleague_photo_key = format!("avatars/{}/avatar/32.png", colleague_id.as_str());
+        photo_store
+            .put(&colleague_photo_key, "image/png", vec![0])
+            .await?;
+
+        let result = delete_account(
+            &db.dynamodb_client,
+            &session_user,
+            &DeleteAccountRequest {
+                password: "wrong".to_string(),
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+
+        let job_id = delete_account(
+            &db.dynamodb_client,
+            &session_user,
+            &DeleteAccountRequest {
+                password: "password".to_string(),
+            },
+        )
+        .await?
+        .job_id;
+
+        // The account and its memberships are gone right away.
+        let output = db
+            .dynamodb_client
+            .get_item(GetItemInput {
+                table_name: table_name("users"),
+                key: av_map(&[av_s("email", "leaving@example.com")]),
+                ..Default::default()
+            })
+            .await?;
+        assert!(output.item.is_none());
+        let output = db
+            .dynamodb_client
+            .get_item(GetItemInput {
+                table_name: table_name("organization_users"),
+                key: av_map(&[
+                    av_s("org_id", org_id.as_str()),
+                    av_s("user_id", user_id.as_str()),
+                ]),
+                ..Default::default()
+            })
+            .await?;
+        assert!(output.item.is_none());
+
+        let mut worker = JobWorker::default();
+        worker.register(Arc::new(AccountDeletionJobHandler {
+            upload_store: upload_store.clone(),
+            photo_store: photo_store.clone(),
+        }));
+        assert!(
+            worker
+                .run_next_job(&db.dynamodb_client, chrono::Utc::now())
+                .await?
+        );
+        let job = jobs::get_job(&db.dynamodb_client, &job_id).await?.unwrap();
+        assert_eq!(job.job_status, JobStatus::Complete);
+        assert_eq!(job.progress_done, NUM_ANONYMIZE_STEPS);
+        let tombstone_user_id = av_get_s(&job.item, "tombstone_user_id").unwrap();
+
+        let output = db
+            .dynamodb_client
+            .get_item(GetItemInput {
+                table_name: table_name("document_revisions"),
+                key: av_map(&[
+                    av_s("doc_id", doc.doc_id.as_str()),
+                    av_n("revision_number", 1),
+                ]),
+                ..Default::default()
+            })
+            .await?;
+        let revision = output.item.unwrap();
+        assert_eq!(
+            av_get_s(&revision, "author_user_id"),
+            Some(tombstone_user_id)
+        );
+        assert_eq!(av_get_s(&revision, "author_display_name"), None);
+        assert_eq!(av_get_n::<i64>(&revision, "revision_number"), Some(1));
+        let output = db
+            .dynamodb_client
+            .get_item(GetItemInput {
+                table_name: table_name("documents"),
+                key: av_map(&[av_s("id", doc.doc_id.as_str())]),
+                ..Default::default()
+            })
+            .await?;
+        assert_eq!(
+            av_get_s(&output.item.unwrap(), "created_by_user_id"),
+            Some(tombstone_user_id)
+        );
+
+        // The user's sharing permissions are gone, but others' are kept.
+        let sharing_key = |doc: &DocumentFixture, user_id: &Id| {
+            av_map(&[
+                av_s("doc_id", doc.doc_id.as_str()),
+                av_s("user_id", user_id.as_str()),
+            ])
+        };
+        let output = db
+            .dynamodb_client
+            .get_item(GetItemInput {
+                table_name: table_name("document_user_sharing_permissions"),
+                key: sharing_key(&shared_doc, &user_id),
+                ..Default::default()
+            })
+            .await?;
+        assert!(output.item.is_none());
+        let output = db
+            .dynamodb_client
+            .get_item(GetItemInput {
+                table_name: table_name("document_user_sharing_permissions"),
+                key: sharing_key(&doc, &colleague_id),
+                ..Default::default()
+            })
+            .await?;
+        assert!(output.item.is_some());
+
+        assert_eq!(
+            identity_providers::find_identity_link(&db.dynamodb_client, "google", &identity)
+                .await?,
+            None
+        );
+        assert!(photo_store.head(&photo_key).await?.is_none());
+        assert!(photo_store.head(&colleague_photo_key).await?.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_orphaned_users() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let member_id = create_user(&db.dynamodb_client, "member@example.com", "Member").await;
+        create_organization_user(
+            &db.dynamodb_client,
+            &Id::new(IdType::Organization),
+            &member_id,
+            &chrono::Utc::now(),
+        )
+        .await;
+        create_user(&db.dynamodb_client, "orphan@example.com", "Orphan").await;
+        let user_exists = |email: &str| {
+            let input = GetItemInput {
+                table_name: table_name("users"),
+                key: av_map(&[av_s("email", email)]),
+                ..Default::default()
+            };
+            let dynamodb_client = &db.dynamodb_client;
+            async move {
+                dynamodb_client
+                    .get_item(input)
+                    .await
+                    .map(|output| output.item.is_some())
+            }
+        };
+
+        // Users created after the cutoff may still be signing up.
+        let an_hour_ago = chrono::Utc::now() - chrono::Duration::hours(1);
+        assert_eq!(
+            delete_orphaned_users(&db.dynamodb_client, &an_hour_ago).await?,
+            0
+        );
+        assert!(user_exists("orphan@example.com").await?);
+
+        let in_a_minute = chrono::Utc::now() + chrono::Duration::minutes(1);
+        assert_eq!(
+            delete_orphaned_users(&db.dynamodb_client, &in_a_minute).await?,
+            1
+        );
+        assert!(!user_exists("orphan@example.com").await?);
+        assert!(user_exists("member@example.com").await?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+//! APIs for an org admin's dashboard of operational health: how large each document's revision log
+//! has grown, which regions people are editing right now, how often change set submissions
+//! conflict, and how much DynamoDB capacity each endpoint consumes.
+//!
+//! Region locks, sync metrics and DynamoDB capacity are kept in memory, so those three only
+//! describe the server that handles the request.
+
+use std::time::Instant;
+
+use actix_web::error;
+use rusoto_dynamodb::QueryInput;
+
+use ot::writing_proto::{
+    DynamoDbCapacityUsage, GetDocumentHealthRequest, GetDocumentHealthResponse,
+    GetDynamoDbCapacityRequest, GetDynamoDbCapacityResponse, GetSyncMetricsRequest,
+    GetSyncMetricsResponse, ListRegionLocksRequest, ListRegionLocksResponse, RegionLockInfo,
+};
+
+use crate::document_stats;
+use crate::dynamodb::{
+    self, av_get_b, av_get_s, av_map, av_s, table_name, CapacityMetrics, DynamoDbClient,
+    CAPACITY_METRICS_REPORT_INTERVAL,
+};
+use crate::http::SessionUser;
+use crate::region_locks::RegionLocks;
+use crate::sync_metrics::{SyncMetrics, SYNC_METRICS_REPORT_INTERVAL};
+use crate::users::UserRole;
+
+/// Describe the size and age of a document's revision log, and the stats that editors last
+/// reported for it.
+///
+/// If the session user is not an org admin, returns 403 Forbidden.
+///
+/// If the document does not exist in the session user's org, returns 404 Not Found.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn get_document_health(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &GetDocumentHealthRequest,
+) -> actix_web::Result<GetDocumentHealthResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [get_document_health] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    if session_user.user_role != UserRole::OrgAdmin {
+        return Err(error::ErrorForbidden(""));
+    }
+    let input = QueryInput {
+        table_name: table_name("documents"),
+        key_condition_expression: Some(String::from("id = :doc_id")),
+        filter_expression: Some(String::from("org_id = :org_id")),
+        projection_expression: Some(String::from("id")),
+        expression_attribute_values: Some(av_map(&[
+            av_s(":doc_id", &request.doc_id),
+            av_s(":org_id", session_user.org_id.as_str()),
+        ])),
+        ..Default::default()
+    };
+    let output = dynamodb_client.query(input).await.map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    if output.items.unwrap_or_default().is_empty() {
+        return Err(error::ErrorNotFound(""));
+    }
+
+    let items = dynamodb::query_all_items(
+        dynamodb_client,
+        QueryInput {
+            table_name: table_name("document_revisions"),
+            key_condition_expression: Some(String::from("doc_id = :doc_id")),
+            expression_attribute_values: Some(av_map(&[av_s(":doc_id", &request.doc_id)])),
+            projection_expression: Some(String::from(
+                "change_set, encrypted_change_set, committed_at",
+            )),
+            ..Default::default()
+        },
+    )
+    .await
+    .map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    let mut response = GetDocumentHealthResponse {
+        revision_count: items.len() as i64,
+        ..Default::default()
+    };
+    for item in items.iter() {
+        let change_set =
+            av_get_b(item, "encrypted_change_set").or_else(|| av_get_b(item, "change_set"));
+        response.change_set_bytes += change_set.map_or(0, |change_set| change_set.len() as i64);
+    }
+    if let (Some(first), Some(last)) = (items.first(), items.last()) {
+        response.first_revision_committed_at = av_get_s(first, "committed_at")
+            .unwrap_or_default()
+            .to_string();
+        response.last_revision_committed_at = av_get_s(last, "committed_at")
+            .unwrap_or_default()
+            .to_string();
+    }
+    if let Some(stats) =
+        document_stats::get_document_stats(dynamodb_client, &request.doc_id).await?
+    {
+        response.stats_revision_number = stats.revision_number;
+        response.word_count = stats.word_count;
+        response.character_count = stats.character_count;
+    }
+    Ok(response)
+}
+
+/// List the active region locks in the org's documents that this server knows about.
+///
+/// If the session user is not an org admin, returns 403 Forbidden.
+pub fn list_region_locks(
+    region_locks: &RegionLocks,
+    session_user: &SessionUser,
+    _request: &ListRegionLocksRequest,
+) -> actix_web::Result<ListRegionLocksResponse> {
+    if session_user.user_role != UserRole::OrgAdmin {
+        return Err(error::ErrorForbidden(""));
+    }
+    let region_locks = region_locks
+        .active_in_org(session_user.org_id.as_str(), Instant::now())
+        .into_iter()
+        .map(|lock| RegionLockInfo {
+            doc_id: lock.doc_id,
+            user_id: lock.user_id,
+            display_name: lock.display_name,
+            revision_number: lock.revision_number,
+            region: Some(lock.region),
+        })
+        .collect();
+    Ok(ListRegionLocksResponse { region_locks })
+}
+
+/// Describe the DynamoDB capacity that each endpoint consumed on each table on this server over
+/// the last complete reporting interval.
+///
+/// If the session user is not an org admin, returns 403 Forbidden.
+pub fn get_dynamodb_capacity(
+    capacity_metrics: &CapacityMetrics,
+    session_user: &SessionUser,
+    _request: &GetDynamoDbCapacityRequest,
+) -> actix_web::Result<GetDynamoDbCapacityResponse> {
+    if session_user.user_role != UserRole::OrgAdmin {
+        return Err(error::ErrorForbidden(""));
+    }
+    let usage = capacity_metrics
+        .last_interval()
+        .into_iter()
+        .map(|usage| DynamoDbCapacityUsage {
+            endpoint: usage.endpoint,
+            table_name: usage.table_name,
+            read_capacity_units: usage.read_capacity_units,
+            write_capacity_units: usage.write_capacity_units,
+        })
+        .collect();
+    Ok(GetDynamoDbCapacityResponse {
+        interval_seconds: CAPACITY_METRICS_REPORT_INTERVAL.as_secs() as i64,
+        usage,
+    })
+}
+
+/// Describe how change set submissions turned out on this server over the last complete
+/// reporting interval.
+///
+/// If the session user is not an org admin, returns 403 Forbidden.
+pub fn get_sync_metrics(
+    sync_metrics: &SyncMetrics,
+    session_user: &SessionUser,
+    _request: &GetSyncMetricsRequest,
+) -> actix_web::Result<GetSyncMetricsResponse> {
+    if session_user.user_role != UserRole::OrgAdmin {
+        return Err(error::ErrorForbidden(""));
+    }
+    let snapshot = sync_metrics.last_interval();
+    Ok(GetSyncMetricsResponse {
+        interval_seconds: SYNC_METRICS_REPORT_INTERVAL.as_secs() as i64,
+        acks: snapshot.acks as i64,
+        discovered_new_revisions: snapshot.discovered_new_revisions as i64,
+        throttled: snapshot.throttled as i64,
+        errors: snapshot.errors as i64,
+        conflict_rate: snapshot.conflict_rate(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ot::writing_proto::{ChangeSet, UpdateDocumentStatsRequest};
+
+    use crate::ids::{Id, IdType};
+    use crate::testing::fixtures::{DocumentFixture, RevisionFixture};
+    use crate::testing::utils::TestDynamoDb;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[tokio::test]
+    async fn test_get_document_health() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let user_id = Id::new(IdType::User);
+        let mut hello = ChangeSet::new();
+        hello.insert("Hello");
+        let mut world = ChangeSet::new();
+        world.retain(5);
+        world.insert(" world");
+        let now = chrono::Utc::now();
+        let doc = DocumentFixture::new()
+            .with_created_by_user_id(&user_id)
+            .with_revisions(vec![
+                RevisionFixture::new(&user_id, &hello, &now),
+                RevisionFixture::new(&user_id, &world, &now),
+            ]);
+        doc.create(&db.dynamodb_client).await;
+        let mut session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: doc.org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let request = GetDocumentHealthRequest {
+            doc_id: doc.doc_id.as_str().to_string(),
+        };
+
+        // Only org admins can see document health.
+        let result = get_document_health(&db.dynamodb_client, &session_user, &request).await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+
+        session_user.user_role = UserRole::OrgAdmin;
+        document_stats::update_document_stats(
+            &db.dynamodb_client,
+            &session_user,
+            &UpdateDocumentStatsRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                revision_number: 2,
+                word_count: 2,
+                character_count: 11,
+                paragraph_count: 1,
+            },
+        )
+        .await?;
+        let response = get_document_health(&db.dynamodb_client, &session_user, &request).await?;
+        assert_eq!(response.revision_count, 2);
+        assert!(response.change_set_bytes > 0);
+        assert!(!response.first_revision_committed_at.is_empty());
+        assert!(!response.last_revision_committed_at.is_empty());
+        assert_eq!(response.stats_revision_number, 2);
+        assert_eq!(response.word_count, 2);
+        assert_eq!(response.character_count, 11);
+
+        // Admins of other orgs cannot see it.
+        let other_admin = SessionUser {
+            org_id: Id::new(IdType::Organization),
+            ..session_user.clone()
+        };
+        let result = get_document_health(&db.dynamodb_client, &other_admin, &request).await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 404);
+        Ok(())
+    }
+}
@@ -0,0 +1,339 @@
+//! Archiving documents. Archived documents are kept out of the way, in the document list, the
+//! recently viewed list, and title search, but are otherwise untouched: they can still be opened
+//! by link, edited, and shared, and they show up in the archived list.
+
+use actix_web::error;
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{QueryInput, UpdateItemError, UpdateItemInput};
+
+use ot::writing_proto::{
+    ArchiveDocumentRequest, ArchiveDocumentResponse, DocumentSharingPermission,
+    ListArchivedRequest, ListArchivedResponse, UnarchiveDocumentRequest, UnarchiveDocumentResponse,
+};
+
+use crate::documents;
+use crate::dynamodb::{av_get_s, av_map, av_s, table_name, DynamoDbClient};
+use crate::http::SessionUser;
+use crate::starred_documents;
+use crate::utils::time;
+
+const LIST_ARCHIVED_DEFAULT_LIMIT: i64 = 20;
+const LIST_ARCHIVED_MAX_LIMIT: i64 = 100;
+
+/// Archive a document. Archiving an archived document has no effect.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If the session user cannot edit the document, returns 403 Forbidden.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn archive_document(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &ArchiveDocumentRequest,
+) -> actix_web::Result<ArchiveDocumentResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [archive_document] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    documents::get_document_if_some_permission_valid(
+        dynamodb_client,
+        session_user,
+        &request.doc_id,
+        &[DocumentSharingPermission::CanEdit],
+    )
+    .await?;
+    let input = UpdateItemInput {
+        table_name: table_name("documents"),
+        key: av_map(&[av_s("id", &request.doc_id)]),
+        // Keep the original archive time if it is already archived.
+        condition_expression: Some(String::from(
+            "org_id = :org_id AND attribute_not_exists(archived_at)",
+        )),
+        update_expression: Some(String::from("SET archived_at = :archived_at")),
+        expression_attribute_values: Some(av_map(&[
+            av_s(":org_id", session_user.org_id.as_str()),
+            av_s(
+                ":archived_at",
+                &time::date_time_iso_str(&chrono::Utc::now()),
+            ),
+        ])),
+        ..Default::default()
+    };
+    match dynamodb_client.update_item(input).await {
+        Ok(_) | Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => {
+            Ok(ArchiveDocumentResponse {})
+        }
+        Err(e) => {
+            log_error(e.to_string());
+            Err(error::ErrorInternalServerError(""))
+        }
+    }
+}
+
+/// Unarchive a document, so that it shows up in lists and search again. Unarchiving a document
+/// that is not archived has no effect.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If the session user cannot edit the document, returns 403 Forbidden.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn unarchive_document(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &UnarchiveDocumentRequest,
+) -> actix_web::Result<UnarchiveDocumentResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [unarchive_document] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    documents::get_document_if_some_permission_valid(
+        dynamodb_client,
+        session_user,
+        &request.doc_id,
+        &[DocumentSharingPermission::CanEdit],
+    )
+    .await?;
+    let input = UpdateItemInput {
+        table_name: table_name("documents"),
+        key: av_map(&[av_s("id", &request.doc_id)]),
+        condition_expression: Some(String::from("org_id = :org_id")),
+        update_expression: Some(String::from("REMOVE archived_at")),
+        expression_attribute_values: Some(av_map(&[av_s(":org_id", session_user.org_id.as_str())])),
+        ..Default::default()
+    };
+    dynamodb_client.update_item(input).await.map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    Ok(UnarchiveDocumentResponse {})
+}
+
+/// List the archived documents in the session user's org that the session user can access, most
+/// recently archived first.
+///
+/// Documents the session user cannot access are left out, so a page may hold fewer documents than
+/// the limit even when there are more to read.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+///
+/// Upon success, returns a page of documents, and the time to pass as `archived_before_date_time`
+/// to read the next page.
+pub async fn list_archived(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &ListArchivedRequest,
+) -> actix_web::Result<ListArchivedResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [list_archived] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    let limit = match request.limit as i64 {
+        limit if limit <= 0 => LIST_ARCHIVED_DEFAULT_LIMIT,
+        limit => limit.min(LIST_ARCHIVED_MAX_LIMIT),
+    };
+    let mut values = vec![av_s(":org_id", session_user.org_id.as_str())];
+    let key_condition_expression = if request.archived_before_date_time.is_empty() {
+        "org_id = :org_id"
+    } else {
+        values.push(av_s(":archived_before", &request.archived_before_date_time));
+        "org_id = :org_id AND archived_at < :archived_before"
+    };
+    // Only archived documents are in this index.
+    let input = QueryInput {
+        table_name: table_name("documents"),
+        index_name: Some(String::from("org_id-archived_at-index")),
+        scan_index_forward: Some(false),
+        key_condition_expression: Some(String::from(key_condition_expression)),
+        expression_attribute_values: Some(av_map(&values)),
+        projection_expression: Some(String::from(
+            "id, org_id, title, created_by_user_id, owner_user_id, org_level_sharing_permission, \
+            created_at, updated_at, encryption_key_fingerprint, archived_at",
+        )),
+        limit: Some(limit),
+        ..Default::default()
+    };
+    let output = dynamodb_client.query(input).await.map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    let items = output.items.unwrap_or_default();
+    let mut response = ListArchivedResponse::default();
+    if output.last_evaluated_key.is_some() {
+        if let Some(archived_at) = items.last().and_then(|item| av_get_s(item, "archived_at")) {
+            response.next_archived_before_date_time = archived_at.to_string();
+        }
+    }
+    let documents = items
+        .iter()
+        .map(documents::document_from_item)
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| {
+            log_error("document is missing a field".to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    response.documents =
+        documents::filter_accessible_documents(dynamodb_client, session_user, documents)
+            .await
+            .map_err(|e| {
+                log_error(e);
+                error::ErrorInternalServerError("")
+            })?;
+    starred_documents::mark_starred(dynamodb_client, session_user, &mut response.documents)
+        .await
+        .map_err(|e| {
+            log_error(e);
+            error::ErrorInternalServerError("")
+        })?;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ot::writing_proto::{
+        GetDocumentRequest, ListMyDocumentsRequest, SearchDocumentTitlesRequest,
+    };
+
+    use crate::ids::{Id, IdType};
+    use crate::testing::fixtures::DocumentFixture;
+    use crate::testing::utils::TestDynamoDb;
+    use crate::users::UserRole;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[tokio::test]
+    async fn test_archive_and_unarchive() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let org_id = Id::new(IdType::Organization);
+        let user_id = Id::new(IdType::User);
+        let session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let viewer = SessionUser {
+            user_id: Id::new(IdType::User),
+            org_id: org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_created_by_user_id(&user_id)
+            .with_org_level_sharing_permission(DocumentSharingPermission::CanView)
+            .with_title("Old plans");
+        doc.create(&db.dynamodb_client).await;
+        let doc_id = doc.doc_id.as_str().to_string();
+
+        // Only editors can archive.
+        let result = archive_document(
+            &db.dynamodb_client,
+            &viewer,
+            &ArchiveDocumentRequest {
+                doc_id: doc_id.clone(),
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+
+        for _ in 0..2 {
+            archive_document(
+                &db.dynamodb_client,
+                &session_user,
+                &ArchiveDocumentRequest {
+                    doc_id: doc_id.clone(),
+                },
+            )
+            .await?;
+        }
+
+        // Left out of lists and search.
+        let response = documents::list_my_documents(
+            &db.dynamodb_client,
+            &session_user,
+            &ListMyDocumentsRequest {
+                updated_before_date_time: time::date_time_iso_str(&chrono::Utc::now()),
+            },
+        )
+        .await?;
+        assert!(response.documents.is_empty());
+        let response = documents::search_document_titles(
+            &db.dynamodb_client,
+            &session_user,
+            &SearchDocumentTitlesRequest {
+                prefix: String::from("old"),
+                limit: 0,
+            },
+        )
+        .await?;
+        assert!(response.documents.is_empty());
+
+        // Still readable directly, and listed as archived for anyone who can view it.
+        let response = documents::get_document(
+            &db.dynamodb_client,
+            Some(&viewer),
+            &GetDocumentRequest {
+                doc_id: doc_id.clone(),
+                ..Default::default()
+            },
+        )
+        .await?;
+        let archived_at = response.document.unwrap().archived_at;
+        assert!(!archived_at.is_empty());
+        let response = list_archived(
+            &db.dynamodb_client,
+            &viewer,
+            &ListArchivedRequest::default(),
+        )
+        .await?;
+        assert_eq!(response.documents.len(), 1);
+        assert_eq!(response.documents[0].id, doc_id);
+        assert_eq!(response.documents[0].archived_at, archived_at);
+
+        unarchive_document(
+            &db.dynamodb_client,
+            &session_user,
+            &UnarchiveDocumentRequest {
+                doc_id: doc_id.clone(),
+            },
+        )
+        .await?;
+        let response = list_archived(
+            &db.dynamodb_client,
+            &session_user,
+            &ListArchivedRequest::default(),
+        )
+        .await?;
+        assert!(response.documents.is_empty());
+        let response = documents::search_document_titles(
+            &db.dynamodb_client,
+            &session_user,
+            &SearchDocumentTitlesRequest {
+                prefix: String::from("old"),
+                limit: 0,
+            },
+        )
+        .await?;
+        assert_eq!(response.documents.len(), 1);
+        assert!(response.documents[0].archived_at.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,885 @@
+//! Images embedded in documents.
+//!
+//! Attachments are kept in the attachment store, under keys made from the document id and the
+//! attachment id, and listed in the `document_attachments` table. Browsers upload them straight to
+//! the store with a pre-signed URL from `create_attachment`, call `confirm_attachment_upload` once
+//! the upload is done, then insert a reference into the document's markdown, like
+//! `![A cat](attachment:a_QCar3LwOwBPIeKonywpCpB)`. The text is the only place that says where an
+//! image goes, so attachments move with the text through transforms like any other characters.
+//!
+//! The upload URL does not hold the browser to the content type and length it asked for, so
+//! attachments cannot be downloaded until `confirm_attachment_upload` has checked what was actually
+//! uploaded, and set `confirmed_at` on the item.
+//!
+//! Deleting the reference leaves the image in the store, since undo may bring the reference back.
+//! `collect_attachments` deletes the images that the latest revision no longer refers to.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use actix_web::error;
+use lazy_static::lazy_static;
+use regex::Regex;
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{
+    AttributeValue, GetItemInput, PutItemInput, QueryInput, UpdateItemError, UpdateItemInput,
+};
+
+use ot::writing_proto::{
+    Attachment, CollectAttachmentsRequest, CollectAttachmentsResponse,
+    ConfirmAttachmentUploadRequest, ConfirmAttachmentUploadResponse, CreateAttachmentRequest,
+    CreateAttachmentResponse, DocumentSharingPermission, GetAttachmentUrlRequest,
+    GetAttachmentUrlResponse, ListAttachmentsRequest, ListAttachmentsResponse,
+};
+
+use crate::blob_store::BlobStore;
+use crate::documents;
+use crate::dynamodb::{self, av_get_n, av_get_s, av_map, av_n, av_s, table_name, DynamoDbClient};
+use crate::http::{Requester, SessionUser};
+use crate::ids::{Id, IdType};
+use crate::permission_cache::PermissionCache;
+use crate::utils::time;
+
+/// The kinds of images that may be attached.
+const ATTACHMENT_CONTENT_TYPES: &[&str] = &["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+/// Attachments may be at most this many bytes.
+pub const MAX_ATTACHMENT_BYTES: i64 = 10 * 1024 * 1024;
+
+/// How long the user has to upload the image, once they ask to.
+const UPLOAD_URL_EXPIRY: Duration = Duration::from_secs(15 * 60);
+
+/// How long download URLs work for. Editors ask for a new one when they load the document again.
+const DOWNLOAD_URL_EXPIRY: Duration = Duration::from_secs(60 * 60);
+
+/// Attachments younger than this are never collected. The editor that uploaded one may not have
+/// submitted its reference yet, for example because it is offline.
+const COLLECT_GRACE_PERIOD_HOURS: i64 = 24;
+
+lazy_static! {
+    /// Matches references to attachments in a document's markdown, and captures the attachment id.
+    /// Any mention of an attachment counts, not only ones in image syntax, so that collecting
+    /// errs towards keeping images.
+    static ref ATTACHMENT_REFERENCE_REGEX: Regex =
+        Regex::new(r"attachment:(a_[0-9A-Za-z]+)").unwrap();
+}
+
+/// Add an attachment to the document, and hand out a URL to upload the image to. The session user
+/// needs edit permission.
+///
+/// If the content type is not one of `ATTACHMENT_CONTENT_TYPES`, or the content length is not
+/// between 1 and `MAX_ATTACHMENT_BYTES`, returns 400 Bad Request.
+///
+/// If the session user cannot edit the document, returns 403 Forbidden.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn create_attachment(
+    dynamodb_client: &DynamoDbClient,
+    attachment_store: &dyn BlobStore,
+    session_user: &SessionUser,
+    request: &CreateAttachmentRequest,
+) -> actix_web::Result<CreateAttachmentResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [create_attachment] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    if !ATTACHMENT_CONTENT_TYPES.contains(&request.content_type.as_str())
+        || request.content_length <= 0
+        || request.content_length > MAX_ATTACHMENT_BYTES
+    {
+        return Err(error::ErrorBadRequest(""));
+    }
+    documents::get_document_if_some_permission_valid(
+        dynamodb_client,
+        session_user,
+        &request.doc_id,
+        &[DocumentSharingPermission::CanEdit],
+    )
+    .await?;
+
+    let attachment_id = Id::new(IdType::Attachment);
+    let input = PutItemInput {
+        table_name: table_name("document_attachments"),
+        item: av_map(&[
+            av_s("doc_id", &request.doc_id),
+            av_s("attachment_id", attachment_id.as_str()),
+            av_s("content_type", &request.content_type),
+            av_n("content_length", request.content_length),
+            av_s("created_by_user_id", session_user.user_id.as_str()),
+            av_s("created_at", &time::date_time_iso_str(&chrono::Utc::now())),
+        ]),
+        ..Default::default()
+    };
+    dynamodb_client.put_item(input).await.map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    // Nothing stops the upload from being bigger than the content length it claims, so
+    // `confirm_attachment_upload` checks it again.
+    let upload_url = attachment_store
+        .upload_url(
+            &attachment_key(&request.doc_id, attachment_id.as_str()),
+            &request.content_type,
+            UPLOAD_URL_EXPIRY,
+        )
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    Ok(CreateAttachmentResponse {
+        reference: format!("![](attachment:{})", attachment_id.as_str()),
+        attachment_id: attachment_id.as_str().to_string(),
+        upload_url,
+    })
+}
+
+/// Checks the image uploaded for an attachment, and lets it be downloaded. The session user needs
+/// edit permission. The item's content type and length are replaced with the upload's.
+///
+/// If the upload is not one of `ATTACHMENT_CONTENT_TYPES`, or is not between 1 and
+/// `MAX_ATTACHMENT_BYTES` long, deletes the attachment, and returns 400 Bad Request.
+///
+/// If the session user cannot edit the document, returns 403 Forbidden.
+///
+/// If the document or the attachment does not exist, or nothing was uploaded for the attachment,
+/// returns 404 Not Found.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn confirm_attachment_upload(
+    dynamodb_client: &DynamoDbClient,
+    attachment_store: &dyn BlobStore,
+    session_user: &SessionUser,
+    request: &ConfirmAttachmentUploadRequest,
+) -> actix_web::Result<ConfirmAttachmentUploadResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [confirm_attachment_upload] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    documents::get_document_if_some_permission_valid(
+        dynamodb_client,
+        session_user,
+        &request.doc_id,
+        &[DocumentSharingPermission::CanEdit],
+    )
+    .await?;
+    let key = av_map(&[
+        av_s("doc_id", &request.doc_id),
+        av_s("attachment_id", &request.attachment_id),
+    ]);
+    // The attachment id is only let into a blob key once the item shows that it names an
+    // attachment of the document.
+    let item = dynamodb_client
+        .get_item(GetItemInput {
+            table_name: table_name("document_attachments"),
+            key: key.clone(),
+            consistent_read: Some(true),
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?
+        .item
+        .ok_or_else(|| error::ErrorNotFound(""))?;
+    let metadata = attachment_store
+        .head(&attachment_key(&request.doc_id, &request.attachment_id))
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?
+        .ok_or_else(|| error::ErrorNotFound(""))?;
+    if !ATTACHMENT_CONTENT_TYPES.contains(&metadata.content_type.as_str())
+        || metadata.content_length <= 0
+        || metadata.content_length > MAX_ATTACHMENT_BYTES
+    {
+        delete_attachments(
+            dynamodb_client,
+            attachment_store,
+            &request.doc_id,
+            vec![item],
+        )
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+        return Err(error::ErrorBadRequest(""));
+    }
+    let input = UpdateItemInput {
+        table_name: table_name("document_attachments"),
+        key,
+        // The attachment may have been collected in the meantime.
+        condition_expression: Some(String::from("attribute_exists(attachment_id)")),
+        update_expression: Some(String::from(
+            "SET content_type = :content_type, content_length = :content_length, \
+            confirmed_at = :confirmed_at",
+        )),
+        expression_attribute_values: Some(av_map(&[
+            av_s(":content_type", &metadata.content_type),
+            av_n(":content_length", metadata.content_length),
+            av_s(
+                ":confirmed_at",
+                &time::date_time_iso_str(&chrono::Utc::now()),
+            ),
+        ])),
+        ..Default::default()
+    };
+    match dynamodb_client.update_item(input).await {
+        Ok(_) => Ok(ConfirmAttachmentUploadResponse {}),
+        Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => {
+            Err(error::ErrorNotFound(""))
+        }
+        Err(e) => {
+            log_error(e.to_string());
+            Err(error::ErrorInternalServerError(""))
+        }
+    }
+}
+
+/// Hand out a URL to download an attachment from. Access is granted either through the
+/// requester's permissions or through a share token. The requester needs view or edit permission.
+///
+/// If there is no session user, and no valid share token, returns 401 Unauthorized.
+///
+/// If the requester does not have permission to view the document, returns 403 Forbidden.
+///
+/// If the document or the attachment does not exist, or its upload was not confirmed, returns 404
+/// Not Found.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn get_attachment_url(
+    dynamodb_client: &DynamoDbClient,
+    permission_cache: &PermissionCache,
+    attachment_store: &dyn BlobStore,
+    requester: &Requester,
+    request: &GetAttachmentUrlRequest,
+) -> actix_web::Result<GetAttachmentUrlResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [get_attachment_url] \
+            [requester: {:?}, request: {:?}]",
+            error_message,
+            requester,
+            request,
+        );
+    };
+    documents::validate_some_access_cached(
+        dynamodb_client,
+        permission_cache,
+        requester.session_user(),
+        &request.doc_id,
+        &request.share_token,
+        &[
+            DocumentSharingPermission::CanView,
+            DocumentSharingPermission::CanComment,
+            DocumentSharingPermission::CanEdit,
+        ],
+    )
+    .await?;
+    let output = dynamodb_client
+        .get_item(GetItemInput {
+            table_name: table_name("document_attachments"),
+            key: av_map(&[
+                av_s("doc_id", &request.doc_id),
+                av_s("attachment_id", &request.attachment_id),
+            ]),
+            projection_expression: Some(String::from("attachment_id, confirmed_at")),
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    match output.item {
+        Some(item) if av_get_s(&item, "confirmed_at").is_some() => {}
+        _ => return Err(error::ErrorNotFound("")),
+    }
+    let download_url = attachment_store
+        .download_url(
+            &attachment_key(&request.doc_id, &request.attachment_id),
+            DOWNLOAD_URL_EXPIRY,
+        )
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    Ok(GetAttachmentUrlResponse { download_url })
+}
+
+/// List the document's attachments, and whether its latest revision refers to each of them. The
+/// session user needs view or edit permission.
+///
+/// If the session user does not have permission to view the document, returns 403 Forbidden.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn list_attachments(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &ListAttachmentsRequest,
+) -> actix_web::Result<ListAttachmentsResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [list_attachments] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    let document = documents::get_document_if_some_permission_valid(
+        dynamodb_client,
+        session_user,
+        &request.doc_id,
+        &[
+            DocumentSharingPermission::CanView,
+            DocumentSharingPermission::CanComment,
+            DocumentSharingPermission::CanEdit,
+        ],
+    )
+    .await?;
+    let items = get_attachment_items(dynamodb_client, &request.doc_id)
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    // The server cannot read encrypted documents, so it assumes they refer to everything.
+    let referenced_ids = if document.encryption_key_fingerprint.is_empty() {
+        Some(
+            get_referenced_attachment_ids(dynamodb_client, &request.doc_id)
+                .await
+                .map_err(|e| {
+                    log_error(e.to_string());
+                    error::ErrorInternalServerError("")
+                })?,
+        )
+    } else {
+        None
+    };
+    let attachments = items
+        .iter()
+        .map(|item| {
+            let attachment_id = av_get_s(item, "attachment_id").unwrap_or_default();
+            Attachment {
+                attachment_id: attachment_id.to_string(),
+                content_type: av_get_s(item, "content_type")
+                    .unwrap_or_default()
+                    .to_string(),
+                content_length: av_get_n(item, "content_length").unwrap_or(0),
+                created_by_user_id: av_get_s(item, "created_by_user_id")
+                    .unwrap_or_default()
+                    .to_string(),
+                created_at: av_get_s(item, "created_at").unwrap_or_default().to_string(),
+                referenced: referenced_ids
+                    .as_ref()
+                    .map_or(true, |ids| ids.contains(attachment_id)),
+            }
+        })
+        .collect();
+    Ok(ListAttachmentsResponse { attachments })
+}
+
+/// Delete the document's attachments that its latest revision does not refer to, other than ones
+/// created in the last `COLLECT_GRACE_PERIOD_HOURS`. The session user needs edit permission.
+///
+/// If the document is end-to-end encrypted, returns 400 Bad Request, since the server cannot tell
+/// which attachments it refers to.
+///
+/// If the session user cannot edit the document, returns 403 Forbidden.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn collect_attachments(
+    dynamodb_client: &DynamoDbClient,
+    attachment_store: &dyn BlobStore,
+    session_user: &SessionUser,
+    request: &CollectAttachmentsRequest,
+) -> actix_web::Result<CollectAttachmentsResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [collect_attachments] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    let document = documents::get_document_if_some_permission_valid(
+        dynamodb_client,
+        session_user,
+        &request.doc_id,
+        &[DocumentSharingPermission::CanEdit],
+    )
+    .await?;
+    if !document.encryption_key_fingerprint.is_empty() {
+        return Err(error::ErrorBadRequest(""));
+    }
+    let items = get_attachment_items(dynamodb_client, &request.doc_id)
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    // The items are read before the revisions, so an attachment created in between is left out.
+    // One that was created earlier, and only referenced in between, is kept by the grace period.
+    let referenced_ids = get_referenced_attachment_ids(dynamodb_client, &request.doc_id)
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    let cutoff = time::date_time_iso_str(
+        &(chrono::Utc::now() - chrono::Duration::hours(COLLECT_GRACE_PERIOD_HOURS)),
+    );
+    let unreferenced_items: Vec<_> = items
+        .into_iter()
+        .filter(|item| {
+            let attachment_id = av_get_s(item, "attachment_id").unwrap_or_default();
+            let created_at = av_get_s(item, "created_at").unwrap_or_default();
+            !referenced_ids.contains(attachment_id) && created_at < cutoff.as_str()
+        })
+        .collect();
+    let deleted_attachment_ids = delete_attachments(
+        dynamodb_client,
+        attachment_store,
+        &request.doc_id,
+        unreferenced_items,
+    )
+    .await
+    .map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    Ok(CollectAttachmentsResponse {
+        deleted_attachment_ids,
+    })
+}
+
+/// Deletes all of the document's attachments, when the document is purged.
+pub async fn delete_document_attachments(
+    dynamodb_client: &DynamoDbClient,
+    attachment_store: &dyn BlobStore,
+    doc_id: &str,
+) -> anyhow::Result<()> {
+    let items = get_attachment_items(dynamodb_client, doc_id).await?;
+    delete_attachments(dynamodb_client, attachment_store, doc_id, items).await?;
+    Ok(())
+}
+
+/// Returns the ids of the attachments that the document's markdown refers to.
+pub fn referenced_attachment_ids(text: &str) -> HashSet<String> {
+    ATTACHMENT_REFERENCE_REGEX
+        .captures_iter(text)
+        .map(|captures| captures[1].to_string())
+        .collect()
+}
+
+/// Returns the document's attachment items, oldest first.
+async fn get_attachment_items(
+    dynamodb_client: &DynamoDbClient,
+    doc_id: &str,
+) -> anyhow::Result<Vec<HashMap<String, AttributeValue>>> {
+    let mut items = dynamodb::query_all_items(
+        dynamodb_client,
+        QueryInput {
+            table_name: table_name("document_attachments"),
+            key_condition_expression: Some(String::from("doc_id = :doc_id")),
+            expression_attribute_values: Some(av_map(&[av_s(":doc_id", doc_id)])),
+            ..Default::default()
+        },
+    )
+    .await?;
+    // Attachment ids are random, so the table keeps them in no useful order.
+    items.sort_by(|a, b| av_get_s(a, "created_at").cmp(&av_get_s(b, "created_at")));
+    Ok(items)
+}
+
+/// Replays the document's revisions, and returns the ids of the attachments that the result
+/// refers to.
+async fn get_referenced_attachment_ids(
+    dynamodb_client: &DynamoDbClient,
+    doc_id: &str,
+) -> anyhow::Result<HashSet<String>> {
+    let (change_sets, _) = documents::get_change_sets(dynamodb_client, doc_id, 0, 0).await?;
+    let text = ot::apply("", &ot::compose_iter(&change_sets)?)?;
+    Ok(referenced_attachment_ids(&text))
+}
+
+/// Deletes the attachments' images, then their items, and returns their ids. Images go first, so
+/// that if this fails part way, every image left still has an item to find it by.
+async fn delete_attachments(
+    dynamodb_client: &DynamoDbClient,
+    attachment_store: &dyn BlobStore,
+    doc_id: &str,
+    items: Vec<HashMap<String, AttributeValue>>,
+) -> anyhow::Result<Vec<String>> {
+    let mut attachment_ids = Vec::with_capacity(items.len());
+    let mut keys = Vec::with_capacity(items.len());
+    for item in items {
+        let attachment_id = av_get_s(&item, "attachment_id")
+            .ok_or_else(|| anyhow::anyhow!("attachment item has no attachment_id"))?;
+        attachment_store
+            .delete(&attachment_key(doc_id, attachment_id))
+            .await?;
+        keys.push(av_map(&[
+            av_s("doc_id", doc_id),
+            av_s("attachment_id", attachment_id),
+        ]));
+        attachment_ids.push(attachment_id.to_string());
+    }
+    dynamodb::batch_delete_all_items(dynamodb_client, &table_name("document_attachments"), keys)
+        .await?;
+    Ok(attachment_ids)
+}
+
+fn attachment_key(doc_id: &str, attachment_id: &str) -> String {
+    format!("attachments/{}/{}", doc_id, attachment_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ot::writing_proto::ChangeSet;
+
+    use crate::testing::fixtures::{self, DocumentFixture, RevisionFixture};
+    use crate::testing::utils::{MemoryBlobStore, TestDynamoDb};
+    use crate::users::UserRole;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[test]
+    fn test_referenced_attachment_ids() {
+        let ids = referenced_attachment_ids(
+            "![A cat](attachment:a_1) and ![](attachment:a_2)\n[a link](attachment:a_1) \
+            attachment:d_3",
+        );
+        let mut ids = ids.into_iter().collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, vec!["a_1", "a_2"]);
+    }
+
+    #[tokio::test]
+    async fn test_attachments() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let permission_cache = PermissionCache::default();
+        let attachment_store = MemoryBlobStore::default();
+
+        let editor_id =
+            fixtures::create_user(&db.dynamodb_client, "edna@example.com", "Edna").await;
+        let viewer_id = fixtures::create_user(&db.dynamodb_client, "vic@example.com", "Vic").await;
+        // Made more than a day ago: one still in the document, and one since deleted from it.
+        let kept_id = Id::new(IdType::Attachment);
+        let unreferenced_id = Id::new(IdType::Attachment);
+        let mut change_set = ChangeSet::new();
+        change_set.insert(&format!("# Cats\n![](attachment:{})\n", kept_id.as_str()));
+        let doc = DocumentFixture::new()
+            .with_created_by_user_id(&editor_id)
+            .with_sharing(&editor_id, DocumentSharingPermission::CanEdit)
+            .with_sharing(&viewer_id, DocumentSharingPermission::CanView)
+            .with_revisions(vec![RevisionFixture::new(
+                &editor_id,
+                &change_set,
+                &chrono::Utc::now(),
+            )]);
+        doc.create(&db.dynamodb_client).await;
+        let doc_id = doc.doc_id.as_str();
+        let two_days_ago = chrono::Utc::now() - chrono::Duration::days(2);
+        for attachment_id in &[&kept_id, &unreferenced_id] {
+            db.dynamodb_client
+                .put_item(PutItemInput {
+                    table_name: table_name("document_attachments"),
+                    item: av_map(&[
+                        av_s("doc_id", doc_id),
+                        av_s("attachment_id", attachment_id.as_str()),
+                        av_s("content_type", "image/png"),
+                        av_n("content_length", 4),
+                        av_s("created_by_user_id", editor_id.as_str()),
+                        av_s("created_at", &time::date_time_iso_str(&two_days_ago)),
+                        av_s("confirmed_at", &time::date_time_iso_str(&two_days_ago)),
+                    ]),
+                    ..Default::default()
+                })
+                .await?;
+            attachment_store
+                .put(
+                    &attachment_key(doc_id, attachment_id.as_str()),
+                    "image/png",
+                    b"\x89PNG".to_vec(),
+                )
+                .await?;
+        }
+        let editor = SessionUser {
+            user_id: editor_id.clone(),
+            org_id: doc.org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let viewer = SessionUser {
+            user_id: viewer_id.clone(),
+            ..editor.clone()
+        };
+
+        // Only editors can attach images, and only images that are not too big.
+        let create = |content_type: &str, content_length| CreateAttachmentRequest {
+            doc_id: doc_id.to_string(),
+            content_type: content_type.to_string(),
+            content_length,
+        };
+        for (session_user, request, status_code) in vec![
+            (&viewer, create("image/png", 100), 403),
+            (&editor, create("text/html", 100), 400),
+            (&editor, create("image/png", MAX_ATTACHMENT_BYTES + 1), 400),
+        ] {
+            let result = create_attachment(
+                &db.dynamodb_client,
+                &attachment_store,
+                session_user,
+                &request,
+            )
+            .await;
+            assert_eq!(
+                result.err().unwrap().as_response_error().status_code(),
+                status_code
+            );
+        }
+        let response = create_attachment(
+            &db.dynamodb_client,
+            &attachment_store,
+            &editor,
+            &create("image/jpeg", 100),
+        )
+        .await?;
+        let new_id = response.attachment_id;
+        assert_eq!(response.reference, format!("![](attachment:{})", new_id));
+        assert_eq!(
+            response.upload_url,
+            format!(
+                "memory://upload/attachments/{}/{}?content_type=image/jpeg",
+                doc_id, new_id
+            )
+        );
+
+        // Viewers can download attachments, but only ones that exist.
+        let get_url = |attachment_id: &str| GetAttachmentUrlRequest {
+            doc_id: doc_id.to_string(),
+            share_token: String::new(),
+            attachment_id: attachment_id.to_string(),
+        };
+        let viewer_requester = Requester::User(viewer.clone());
+        let response = get_attachment_url(
+            &db.dynamodb_client,
+            &permission_cache,
+            &attachment_store,
+            &viewer_requester,
+            &get_url(kept_id.as_str()),
+        )
+        .await?;
+        assert_eq!(
+            response.download_url,
+            format!("memory://attachments/{}/{}", doc_id, kept_id.as_str())
+        );
+        let result = get_attachment_url(
+            &db.dynamodb_client,
+            &permission_cache,
+            &attachment_store,
+            &viewer_requester,
+            &get_url(Id::new(IdType::Attachment).as_str()),
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 404);
+
+        let list_request = ListAttachmentsRequest {
+            doc_id: doc_id.to_string(),
+        };
+        let response = list_attachments(&db.dynamodb_client, &viewer, &list_request).await?;
+        let mut old = response.attachments[..2]
+            .iter()
+            .map(|a| (a.attachment_id.as_str(), a.referenced))
+            .collect::<Vec<_>>();
+        old.sort();
+        let mut expected = vec![(kept_id.as_str(), true), (unreferenced_id.as_str(), false)];
+        expected.sort();
+        assert_eq!(old, expected);
+        assert_eq!(response.attachments[2].attachment_id, new_id);
+        assert!(!response.attachments[2].referenced);
+
+        // Only old attachments that the document no longer refers to are collected.
+        let collect_request = CollectAttachmentsRequest {
+            doc_id: doc_id.to_string(),
+        };
+        let result = collect_attachments(
+            &db.dynamodb_client,
+            &attachment_store,
+            &viewer,
+            &collect_request,
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+        let response = collect_attachments(
+            &db.dynamodb_client,
+            &attachment_store,
+            &editor,
+            &collect_request,
+        )
+        .await?;
+        assert_eq!(
+            response.deleted_attachment_ids,
+            vec![unreferenced_id.as_str().to_string()]
+        );
+        assert!(attachment_store
+            .head(&attachment_key(doc_id, unreferenced_id.as_str()))
+            .await?
+            .is_none());
+        assert!(attachment_store
+            .head(&attachment_key(doc_id, kept_id.as_str()))
+            .await?
+            .is_some());
+        let response = list_attachments(&db.dynamodb_client, &viewer, &list_request).await?;
+        assert_eq!(response.attachments.len(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_confirm_attachment_upload() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let permission_cache = PermissionCache::default();
+        let attachment_store = MemoryBlobStore::default();
+
+        let editor_id =
+            fixtures::create_user(&db.dynamodb_client, "edna@example.com", "Edna").await;
+        let doc = DocumentFixture::new()
+            .with_created_by_user_id(&editor_id)
+            .with_sharing(&editor_id, DocumentSharingPermission::CanEdit);
+        doc.create(&db.dynamodb_client).await;
+        let doc_id = doc.doc_id.as_str();
+        let editor = SessionUser {
+            user_id: editor_id.clone(),
+            org_id: doc.org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let create_request = CreateAttachmentRequest {
+            doc_id: doc_id.to_string(),
+            content_type: "image/png".to_string(),
+            content_length: 4,
+        };
+        let confirm = |attachment_id: &str| ConfirmAttachmentUploadRequest {
+            doc_id: doc_id.to_string(),
+            attachment_id: attachment_id.to_string(),
+        };
+        let get_url = |attachment_id: &str| GetAttachmentUrlRequest {
+            doc_id: doc_id.to_string(),
+            share_token: String::new(),
+            attachment_id: attachment_id.to_string(),
+        };
+        let requester = Requester::User(editor.clone());
+
+        // Nothing can be downloaded or confirmed before it is uploaded.
+        let attachment_id = create_attachment(
+            &db.dynamodb_client,
+            &attachment_store,
+            &editor,
+            &create_request,
+        )
+        .await?
+        .attachment_id;
+        let result = get_attachment_url(
+            &db.dynamodb_client,
+            &permission_cache,
+            &attachment_store,
+            &requester,
+            &get_url(&attachment_id),
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 404);
+        for request in vec![confirm(&attachment_id), confirm("a_../../other")] {
+            let result = confirm_attachment_upload(
+                &db.dynamodb_client,
+                &attachment_store,
+                &editor,
+                &request,
+            )
+            .await;
+            assert_eq!(result.err().unwrap().as_response_error().status_code(), 404);
+        }
+
+        // Once confirmed, the attachment has the type and size of what was actually uploaded.
+        let key = attachment_key(doc_id, &attachment_id);
+        attachment_store
+            .put(&key, "image/gif", b"GIF89a".to_vec())
+            .await?;
+        confirm_attachment_upload(
+            &db.dynamodb_client,
+            &attachment_store,
+            &editor,
+            &confirm(&attachment_id),
+        )
+        .await?;
+        get_attachment_url(
+            &db.dynamodb_client,
+            &permission_cache,
+            &attachment_store,
+            &requester,
+            &get_url(&attachment_id),
+        )
+        .await?;
+        let list_request = ListAttachmentsRequest {
+            doc_id: doc_id.to_string(),
+        };
+        let response = list_attachments(&db.dynamodb_client, &editor, &list_request).await?;
+        assert_eq!(response.attachments[0].content_type, "image/gif");
+        assert_eq!(response.attachments[0].content_length, 6);
+
+        // Uploads of another type, or too big, are deleted.
+        for (content_type, content_length) in vec![
+            ("text/html", 4),
+            ("image/png", MAX_ATTACHMENT_BYTES as usize + 1),
+        ] {
+            let attachment_id = create_attachment(
+                &db.dynamodb_client,
+                &attachment_store,
+                &editor,
+                &create_request,
+            )
+            .await?
+            .attachment_id;
+            let key = attachment_key(doc_id, &attachment_id);
+            attachment_store
+                .put(&key, content_type, vec![0; content_length])
+                .await?;
+            let result = confirm_attachment_upload(
+                &db.dynamodb_client,
+                &attachment_store,
+                &editor,
+                &confirm(&attachment_id),
+            )
+            .await;
+            assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+            assert!(attachment_store.head(&key).await?.is_none());
+        }
+        let response = list_attachments(&db.dynamodb_client, &editor, &list_request).await?;
+        assert_eq!(response.attachments.len(), 1);
+
+        Ok(())
+    }
+}
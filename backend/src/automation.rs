@@ -0,0 +1,442 @@
+//! Convenience endpoints for bots and integrations.
+//!
+//! Appending a log line or rewriting a status section should not need a full OT client. These
+//! endpoints build the change set on the server, against the document's latest revision, and
+//! submit it like any other change set. If someone else commits first, they rebuild the change set
+//! against the new latest revision and try again.
+//!
+//! The server cannot read end-to-end encrypted documents, so it cannot edit them either.
+
+use actix_web::error;
+use regex::{Regex, RegexBuilder};
+
+use ot::writing_proto::{
+    submit_document_change_set_response::ResponseCode, AppendToDocumentRequest,
+    AppendToDocumentResponse, ChangeSet, GetDocumentRequest, ReplacePatternRequest,
+    ReplacePatternResponse, SubmitDocumentChangeSetRequest,
+};
+
+use crate::documents;
+use crate::dynamodb::DynamoDbClient;
+use crate::http::Requester;
+use crate::permission_cache::PermissionCache;
+use crate::rate_limiter::RateLimiter;
+
+/// How many times to rebuild and submit the change set before giving up, when other revisions
+/// keep getting committed first.
+const MAX_SUBMIT_ATTEMPTS: usize = 5;
+
+// Patterns are compiled from user input, so cap how big they can get.
+const MAX_PATTERN_SIZE: usize = 1 << 20;
+
+/// Append text to the end of the document. Returns the number of the revision that appended it.
+///
+/// Access is granted either through the requester's permissions or through a share token. The
+/// requester needs edit permission.
+///
+/// If the document is end-to-end encrypted, returns 400 Bad Request.
+///
+/// If the requester does not have permission to edit the document, returns 403 Forbidden.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If other revisions keep getting committed first, returns 409 Conflict. Try again.
+///
+/// If the requester has used up their edit budget for the document, returns 429 Too Many Requests.
+/// See `documents::submit_document_change_set`.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn append_to_document(
+    dynamodb_client: &DynamoDbClient,
+    permission_cache: &PermissionCache,
+    edit_rate_limiter: &RateLimiter,
+    requester: &Requester,
+    request: &AppendToDocumentRequest,
+) -> actix_web::Result<AppendToDocumentResponse> {
+    let content = ot::utils::str_to_u32_vec(&request.text);
+    let (revision_number, _) = edit_document(
+        dynamodb_client,
+        permission_cache,
+        edit_rate_limiter,
+        requester,
+        &request.doc_id,
+        &request.share_token,
+        |value| {
+            let mut builder = ot::SpliceBuilder::new(value.len() as i64);
+            let len = value.len() as i64;
+            builder.splice(len..len, &content)?;
+            Ok((builder.finish(), 1))
+        },
+    )
+    .await?;
+    Ok(AppendToDocumentResponse { revision_number })
+}
+
+/// Replace every match of a regular expression in the document. The replacement may refer to
+/// capture groups, like `$1`. Returns the number of the revision that replaced the matches, and
+/// how many there were.
+///
+/// If the pattern is invalid or too big, or if the document is end-to-end encrypted, returns 400
+/// Bad Request.
+///
+/// If the requester does not have permission to edit the document, returns 403 Forbidden.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If other revisions keep getting committed first, returns 409 Conflict. Try again.
+///
+/// If the requester has used up their edit budget for the document, returns 429 Too Many Requests.
+/// See `documents::submit_document_change_set`.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn replace_pattern(
+    dynamodb_client: &DynamoDbClient,
+    permission_cache: &PermissionCache,
+    edit_rate_limiter: &RateLimiter,
+    requester: &Requester,
+    request: &ReplacePatternRequest,
+) -> actix_web::Result<ReplacePatternResponse> {
+    let regex = RegexBuilder::new(&request.pattern)
+        .size_limit(MAX_PATTERN_SIZE)
+        .build()
+        .map_err(|_| error::ErrorBadRequest(""))?;
+    let (revision_number, replacement_count) = edit_document(
+        dynamodb_client,
+        permission_cache,
+        edit_rate_limiter,
+        requester,
+        &request.doc_id,
+        &request.share_token,
+        |value| build_replacements(&regex, &request.replacement, value),
+    )
+    .await?;
+    Ok(ReplacePatternResponse {
+        revision_number,
+        replacement_count: replacement_count as i64,
+    })
+}
+
+/// Builds a change set with `build_change_set` against the latest revision of the document, and
+/// submits it, retrying if other revisions are committed first. `build_change_set` is given the
+/// document's UTF-16 code units, and returns the change set along with a count to pass back.
+///
+/// Returns the number of the revision that was committed, or 0 if the change set changed nothing,
+/// along with the count.
+async fn edit_document<F>(
+    dynamodb_client: &DynamoDbClient,
+    permission_cache: &PermissionCache,
+    edit_rate_limiter: &RateLimiter,
+    requester: &Requester,
+    doc_id: &str,
+    share_token: &str,
+    build_change_set: F,
+) -> actix_web::Result<(i64, usize)>
+where
+    F: Fn(&[u16]) -> Result<(ChangeSet, usize), ot::OtError>,
+{
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [edit_document] [requester: {:?}, doc_id: {}]",
+            error_message,
+            requester,
+            doc_id,
+        );
+    };
+    let document = documents::get_document(
+        dynamodb_client,
+        requester.session_user(),
+        &GetDocumentRequest {
+            doc_id: doc_id.to_string(),
+            share_token: share_token.to_string(),
+        },
+    )
+    .await?
+    .document
+    .unwrap_or_default();
+    if !document.encryption_key_fingerprint.is_empty() {
+        return Err(error::ErrorBadRequest(""));
+    }
+
+    for _ in 0..MAX_SUBMIT_ATTEMPTS {
+        let (change_sets, last_revision_number) =
+            documents::get_change_sets(dynamodb_client, doc_id, 0, 0)
+                .await
+                .map_err(|e| {
+                    log_error(e.to_string());
+                    error::ErrorInternalServerError("")
+                })?;
+        let value = ot::compose_iter(&change_sets)
+            .and_then(|snapshot| ot::apply_slice(&[], &snapshot))
+            .map_err(|e| {
+                log_error(e.to_string());
+                error::ErrorInternalServerError("")
+            })?;
+        let (change_set, count) = build_change_set(&value).map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+        if ot::is_identity(&change_set, None) {
+            return Ok((0, count));
+        }
+        let response = documents::submit_document_change_set(
+            dynamodb_client,
+            permission_cache,
+            edit_rate_limiter,
+            requester,
+            &SubmitDocumentChangeSetRequest {
+                doc_id: doc_id.to_string(),
+                on_revision_number: last_revision_number,
+                change_set: Some(change_set),
+                share_token: share_token.to_string(),
+                // Marks the revision as made by an automation rather than an editor.
+                client_id: String::from("automation"),
+                ..Default::default()
+            },
+        )
+        .await?;
+        match response.response_code() {
+            ResponseCode::Ack => return Ok((response.last_revision_number, count)),
+            ResponseCode::Throttled => return Err(error::ErrorTooManyRequests("")),
+            _ => {}
+        }
+    }
+    Err(error::ErrorConflict(""))
+}
+
+/// Builds a change set that replaces every match of `regex` in `value`, expanding capture groups
+/// in `replacement`. Returns the change set and the number of matches.
+fn build_replacements(
+    regex: &Regex,
+    replacement: &str,
+    value: &[u16],
+) -> Result<(ChangeSet, usize), ot::OtError> {
+    let text = String::from_utf16_lossy(value);
+    let mut builder = ot::SpliceBuilder::new(value.len() as i64);
+    // Regex offsets are in bytes of UTF-8, and change sets count UTF-16 code units, so keep a
+    // running conversion from one to the other.
+    let mut byte_offset = 0;
+    let mut utf16_offset = 0;
+    let mut to_utf16_offset = |offset: usize| {
+        utf16_offset += text[byte_offset..offset].encode_utf16().count();
+        byte_offset = offset;
+        utf16_offset as i64
+    };
+    let mut count = 0;
+    for captures in regex.captures_iter(&text) {
+        let range = captures.get(0).unwrap().range();
+        let mut expanded = String::new();
+        captures.expand(replacement, &mut expanded);
+        let content = ot::utils::str_to_u32_vec(&expanded);
+        let start = to_utf16_offset(range.start);
+        let end = to_utf16_offset(range.end);
+        builder.splice(start..end, &content)?;
+        count += 1;
+    }
+    Ok((builder.finish(), count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ot::writing_proto::{CreateDocumentRequest, DocumentSharingPermission};
+
+    use crate::http::SessionUser;
+    use crate::ids::{Id, IdType};
+    use crate::testing::fixtures::{DocumentFixture, RevisionFixture};
+    use crate::testing::utils::TestDynamoDb;
+    use crate::users::UserRole;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    fn replace(pattern: &str, replacement: &str, value: &str) -> (String, usize) {
+        let value = ot::utils::str_to_u16_vec(value);
+        let (change_set, count) =
+            build_replacements(&Regex::new(pattern).unwrap(), replacement, &value).unwrap();
+        let replaced = ot::apply_slice(&value, &change_set).unwrap();
+        (String::from_utf16_lossy(&replaced), count)
+    }
+
+    #[test]
+    fn test_build_replacements() {
+        assert_eq!(
+            replace(r"status: \w+", "status: green", "build\nstatus: red\n"),
+            (String::from("build\nstatus: green\n"), 1)
+        );
+        assert_eq!(
+            replace(r"(\w+)@(\w+)", "$2 at $1", "a@b, c@d"),
+            (String::from("b at a, d at c"), 2)
+        );
+        // Offsets past characters outside the BMP, which take two UTF-16 code units.
+        assert_eq!(replace("x", "y", "😀x😀x"), (String::from("😀y😀y"), 2));
+        assert_eq!(replace("z", "y", "abc"), (String::from("abc"), 0));
+    }
+
+    #[tokio::test]
+    async fn test_append_and_replace() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let session_user = SessionUser {
+            user_id: Id::new(IdType::User),
+            org_id: Id::new(IdType::Organization),
+            user_role: UserRole::Default,
+        };
+        let mut change_set = ChangeSet::new();
+        change_set.insert("status: red\n");
+        let doc = DocumentFixture::new()
+            .with_org_id(&session_user.org_id)
+            .with_created_by_user_id(&session_user.user_id)
+            .with_revisions(vec![RevisionFixture::new(
+                &session_user.user_id,
+                &change_set,
+                &chrono::Utc::now(),
+            )]);
+        doc.create(&db.dynamodb_client).await;
+        let requester = Requester::User(session_user.clone());
+        let doc_id = doc.doc_id.as_str().to_string();
+
+        let response = append_to_document(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
+            &RateLimiter::default(),
+            &requester,
+            &AppendToDocumentRequest {
+                doc_id: doc_id.clone(),
+                text: String::from("deployed\n"),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(response.revision_number, 2);
+
+        let response = replace_pattern(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
+            &RateLimiter::default(),
+            &requester,
+            &ReplacePatternRequest {
+                doc_id: doc_id.clone(),
+                pattern: String::from(r"status: \w+"),
+                replacement: String::from("status: green"),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(response.revision_number, 3);
+        assert_eq!(response.replacement_count, 1);
+
+        let (change_sets, _) = documents::get_change_sets(&db.dynamodb_client, &doc_id, 0, 0)
+            .await
+            .unwrap();
+        let snapshot = ot::compose_iter(&change_sets)?;
+        assert_eq!(ot::apply("", &snapshot)?, "status: green\ndeployed\n");
+
+        // Invalid patterns are rejected.
+        let result = replace_pattern(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
+            &RateLimiter::default(),
+            &requester,
+            &ReplacePatternRequest {
+                doc_id: doc_id.clone(),
+                pattern: String::from("("),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+
+        // Automations spend the requester's edit budget, like any other change set.
+        let edit_rate_limiter = RateLimiter::new(1, std::time::Duration::from_secs(60));
+        let request = AppendToDocumentRequest {
+            doc_id: doc_id.clone(),
+            text: String::from("deployed again\n"),
+            ..Default::default()
+        };
+        let response = append_to_document(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
+            &edit_rate_limiter,
+            &requester,
+            &request,
+        )
+        .await?;
+        assert_eq!(response.revision_number, 4);
+        let result = append_to_document(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
+            &edit_rate_limiter,
+            &requester,
+            &request,
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 429);
+
+        // Viewers cannot edit.
+        let viewer = SessionUser {
+            user_id: Id::new(IdType::User),
+            org_id: session_user.org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        DocumentFixture::new()
+            .with_doc_id(&doc.doc_id)
+            .with_org_id(&session_user.org_id)
+            .with_created_by_user_id(&session_user.user_id)
+            .with_sharing(&viewer.user_id, DocumentSharingPermission::CanView)
+            .create(&db.dynamodb_client)
+            .await;
+        let result = append_to_document(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
+            &RateLimiter::default(),
+            &Requester::User(viewer),
+            &AppendToDocumentRequest {
+                doc_id: doc_id.clone(),
+                text: String::from("hi"),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_document() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let session_user = SessionUser {
+            user_id: Id::new(IdType::User),
+            org_id: Id::new(IdType::Organization),
+            user_role: UserRole::Default,
+        };
+        let response = documents::create_document(
+            &db.dynamodb_client,
+            &session_user,
+            &CreateDocumentRequest {
+                title: String::from("Secret"),
+                encryption_key_fingerprint: String::from(
+                    "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08",
+                ),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        // The server cannot read the document, so it cannot edit it.
+        let result = append_to_document(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
+            &RateLimiter::default(),
+            &Requester::User(session_user),
+            &AppendToDocumentRequest {
+                doc_id: response.doc_id,
+                text: String::from("hi"),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+
+        Ok(())
+    }
+}
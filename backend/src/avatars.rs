@@ -0,0 +1,553 @@
+//! Profile photos, uploaded by users and resized to standard sizes.
+//!
+//! Browsers upload photos straight to the upload store, in three steps:
+//!
+//! 1. `request_avatar_upload` hands out a pre-signed URL to upload the image to, under a new
+//!    upload id.
+//! 2. The browser PUTs the image to the URL.
+//! 3. `confirm_avatar_upload` checks the uploaded image's type and size, and starts a job to
+//!    resize it.
+//!
+//! `AvatarResizeJobHandler` resizes the image to each of `AVATAR_SIZES`, writes the resized photos
+//! to the public photo store as PNGs, and points the user's profile at them. The user item keeps
+//! `avatar_key`, which the photos' keys start with, and `photo_url`, the URL of the largest photo.
+//! Each upload gets new keys, so that nobody sees the old photo at the new photo's URL from a
+//! cache. The old photos are deleted once the profile points at the new ones.
+//!
+//! Every key of a user's uploads and photos starts with `avatars/<user_id>/`, so that
+//! `delete_avatars` can find them all when the user deletes their account.
+
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::error;
+use futures::future::BoxFuture;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageOutputFormat};
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{AttributeValue, UpdateItemError, UpdateItemInput};
+
+use ot::writing_proto::{
+    ConfirmAvatarUploadRequest, ConfirmAvatarUploadResponse, ProfilePhoto,
+    RequestAvatarUploadRequest, RequestAvatarUploadResponse,
+};
+
+use crate::blob_store::BlobStore;
+use crate::dynamodb::{av_get_s, av_map, av_s, table_name, DynamoDbClient};
+use crate::http::SessionUser;
+use crate::ids::Id;
+use crate::jobs::{self, Job, JobHandler};
+use crate::users;
+use crate::utils::time;
+
+/// The `job_type` of avatar resize jobs in the `jobs` table.
+pub const RESIZE_AVATAR_JOB_TYPE: &str = "resize_avatar";
+
+/// The widths and heights of the resized photos, in pixels, smallest first.
+pub const AVATAR_SIZES: &[u32] = &[32, 64, 128, 256];
+
+/// The kinds of images that may be uploaded.
+const AVATAR_CONTENT_TYPES: &[&str] = &["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+/// Uploads may be at most this many bytes.
+pub const MAX_AVATAR_BYTES: i64 = 5 * 1024 * 1024;
+
+/// Uploads may be at most this many pixels wide and high, so that a small file cannot decode to an
+/// enormous image.
+const MAX_AVATAR_DIMENSION: u32 = 4096;
+
+/// How long the user has to upload the image, once they ask to.
+const UPLOAD_URL_EXPIRY: Duration = Duration::from_secs(15 * 60);
+
+/// Hands out a URL for the session user to upload a new profile photo to.
+///
+/// If the content type is not one of `AVATAR_CONTENT_TYPES`, or the content length is not between
+/// 1 and `MAX_AVATAR_BYTES`, returns 400 Bad Request.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn request_avatar_upload(
+    upload_store: &dyn BlobStore,
+    session_user: &SessionUser,
+    request: &RequestAvatarUploadRequest,
+) -> actix_web::Result<RequestAvatarUploadResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [request_avatar_upload] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    if !AVATAR_CONTENT_TYPES.contains(&request.content_type.as_str())
+        || request.content_length <= 0
+        || request.content_length > MAX_AVATAR_BYTES
+    {
+        return Err(error::ErrorBadRequest(""));
+    }
+    let upload_id = uuid::Uuid::new_v4().to_simple().to_string();
+    let upload_url = upload_store
+        .upload_url(
+            &upload_key(&session_user.user_id, &upload_id),
+            &request.content_type,
+            UPLOAD_URL_EXPIRY,
+        )
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    Ok(RequestAvatarUploadResponse {
+        upload_id,
+        upload_url,
+    })
+}
+
+/// Checks the image that the session user uploaded, and starts resizing it in the background, with
+/// a job handled by `AvatarResizeJobHandler`.
+///
+/// If nothing was uploaded for the upload id, returns 404 Not Found.
+///
+/// If the upload is not one of `AVATAR_CONTENT_TYPES`, or is bigger than `MAX_AVATAR_BYTES`,
+/// deletes it, and returns 400 Bad Request.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn confirm_avatar_upload(
+    dynamodb_client: &DynamoDbClient,
+    upload_store: &dyn BlobStore,
+    session_user: &SessionUser,
+    request: &ConfirmAvatarUploadRequest,
+) -> actix_web::Result<ConfirmAvatarUploadResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [confirm_avatar_upload] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    // Upload ids are only ever made by `request_avatar_upload`, so anything else cannot name an
+    // upload, and must not be let into a key.
+    let is_upload_id = request.upload_id.len() == 32
+        && request
+            .upload_id
+            .chars()
+            .all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase());
+    if !is_upload_id {
+        return Err(error::ErrorNotFound(""));
+    }
+    let upload_key = upload_key(&session_user.user_id, &request.upload_id);
+    let metadata = upload_store
+        .head(&upload_key)
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?
+        .ok_or_else(|| error::ErrorNotFound(""))?;
+    if !AVATAR_CONTENT_TYPES.contains(&metadata.content_type.as_str())
+        || metadata.content_length > MAX_AVATAR_BYTES
+    {
+        upload_store.delete(&upload_key).await.map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+        return Err(error::ErrorBadRequest(""));
+    }
+    jobs::create_job(
+        dynamodb_client,
+        RESIZE_AVATAR_JOB_TYPE,
+        session_user,
+        &[av_s("upload_id", &request.upload_id)],
+    )
+    .await
+    .map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    Ok(ConfirmAvatarUploadResponse {})
+}
+
+/// The resized photos of the user item's avatar, smallest first. Empty if the user has none.
+pub fn profile_photos(
+    photo_store: &dyn BlobStore,
+    user_item: &HashMap<String, AttributeValue>,
+) -> Vec<ProfilePhoto> {
+    match av_get_s(user_item, "avatar_key") {
+        Some(avatar_key) => AVATAR_SIZES
+            .iter()
+            .map(|&size| ProfilePhoto {
+                size,
+                url: photo_store.public_url(&photo_key(avatar_key, size)),
+            })
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Resizes the uploads confirmed by `confirm_avatar_upload`, and sets them as their users' photos.
+pub struct AvatarResizeJobHandler {
+    pub upload_store: Arc<dyn BlobStore>,
+    pub photo_store: Arc<dyn BlobStore>,
+}
+
+impl JobHandler for AvatarResizeJobHandler {
+    fn job_type(&self) -> &'static str {
+        RESIZE_AVATAR_JOB_TYPE
+    }
+
+    fn run<'a>(
+        &'a self,
+        dynamodb_client: &'a DynamoDbClient,
+        job: &'a Job,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(resize_avatar(
+            dynamodb_client,
+            self.upload_store.as_ref(),
+            self.photo_store.as_ref(),
+            job,
+        ))
+    }
+}
+
+async fn resize_avatar(
+    dynamodb_client: &DynamoDbClient,
+    upload_store: &dyn BlobStore,
+    photo_store: &dyn BlobStore,
+    job: &Job,
+) -> anyhow::Result<()> {
+    let user_id = Id::parse(&job.created_by_user_id)
+        .ok_or_else(|| anyhow::anyhow!("job has an invalid created_by_user_id"))?;
+    let upload_id =
+        av_get_s(&job.item, "upload_id").ok_or_else(|| anyhow::anyhow!("job has no upload_id"))?;
+    let upload_key = upload_key(&user_id, upload_id);
+    // An earlier attempt may have finished, all but the last step of deleting the upload.
+    let original = match upload_store.get(&upload_key).await? {
+        Some(original) => original,
+        None => return Ok(()),
+    };
+    let photos = match tokio::task::spawn_blocking(move || resize(&original)).await? {
+        Ok(photos) => photos,
+        Err(e) => {
+            // Trying again would not help. The user's photo stays as it was.
+            log::warn!(
+                "Could not resize avatar: \"{}\" [resize_avatar] [job_id: {}]",
+                e,
+                job.job_id
+            );
+            upload_store.delete(&upload_key).await?;
+            return Ok(());
+        }
+    };
+
+    let avatar_key = format!("avatars/{}/{}", user_id.as_str(), upload_id);
+    for (size, photo) in AVATAR_SIZES.iter().zip(photos) {
+        photo_store
+            .put(&photo_key(&avatar_key, *size), "image/png", photo)
+            .await?;
+    }
+    let largest_size = AVATAR_SIZES[AVATAR_SIZES.len() - 1];
+    let replaced_avatar_key = set_avatar(
+        dynamodb_client,
+        &user_id,
+        &avatar_key,
+        &photo_store.public_url(&photo_key(&avatar_key, largest_size)),
+    )
+    .await?;
+    // An earlier attempt may have set the same photos already.
+    if let Some(replaced_avatar_key) = replaced_avatar_key.filter(|key| *key != avatar_key) {
+        for size in AVATAR_SIZES {
+            photo_store
+                .delete(&photo_key(&replaced_avatar_key, *size))
+                .await?;
+        }
+    }
+    upload_store.delete(&upload_key).await
+}
+
+/// Decodes the image, and returns it as a PNG in each of `AVATAR_SIZES`. Images that are not
+/// square are cropped to the middle.
+fn resize(original: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+    let reader = image::io::Reader::new(Cursor::new(original)).with_guessed_format()?;
+    let (width, height) = reader.into_dimensions()?;
+    if width > MAX_AVATAR_DIMENSION || height > MAX_AVATAR_DIMENSION {
+        anyhow::bail!("image is {}x{}", width, height);
+    }
+    let image = image::io::Reader::new(Cursor::new(original))
+        .with_guessed_format()?
+        .decode()?;
+    AVATAR_SIZES
+        .iter()
+        .map(|&size| {
+            let resized: DynamicImage = image.resize_to_fill(size, size, FilterType::Lanczos3);
+            debug_assert_eq!(resized.dimensions(), (size, size));
+            let mut png = Vec::new();
+            resized.write_to(&mut png, ImageOutputFormat::Png)?;
+            Ok(png)
+        })
+        .collect()
+}
+
+/// Points the user's profile at their new photos, and returns the `avatar_key` of the photos that
+/// they replace, if any. Does nothing if the user has since deleted their account.
+async fn set_avatar(
+    dynamodb_client: &DynamoDbClient,
+    user_id: &Id,
+    avatar_key: &str,
+    photo_url: &str,
+) -> anyhow::Result<Option<String>> {
+    let user_item = match users::get_user_item(dynamodb_client, user_id).await {
+        Ok(user_item) => user_item,
+        Err(_) => return Ok(None),
+    };
+    let email =
+        av_get_s(&user_item, "email").ok_or_else(|| anyhow::anyhow!("user has no email"))?;
+    let input = UpdateItemInput {
+        table_name: table_name("users"),
+        key: av_map(&[av_s("email", email)]),
+        condition_expression: Some(String::from("id = :id")),
+        update_expression: Some(String::from(
+            "SET avatar_key = :avatar_key, photo_url = :photo_url, updated_at = :updated_at",
+        )),
+        expression_attribute_values: Some(av_map(&[
+            av_s(":id", user_id.as_str()),
+            av_s(":avatar_key", avatar_key),
+            av_s(":photo_url", photo_url),
+            av_s(":updated_at", &time::date_time_iso_str(&chrono::Utc::now())),
+        ])),
+        return_values: Some(String::from("UPDATED_OLD")),
+        ..Default::default()
+    };
+    match dynamodb_client.update_item(input).await {
+        Ok(output) => Ok(output
+            .attributes
+            .as_ref()
+            .and_then(|attributes| av_get_s(attributes, "avatar_key"))
+            .map(String::from)),
+        Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Deletes all of the user's uploads and photos, for when they delete their account.
+pub async fn delete_avatars(
+    upload_store: &dyn BlobStore,
+    photo_store: &dyn BlobStore,
+    user_id: &str,
+) -> anyhow::Result<()> {
+    let prefix = format!("avatars/{}/", user_id);
+    for store in &[upload_store, photo_store] {
+        for key in store.list(&prefix).await? {
+            store.delete(&key).await?;
+        }
+    }
+    Ok(())
+}
+
+fn upload_key(user_id: &Id, upload_id: &str) -> String {
+    format!("avatars/{}/{}", user_id.as_str(), upload_id)
+}
+
+fn photo_key(avatar_key: &str, size: u32) -> String {
+    format!("{}/{}.png", avatar_key, size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::{ImageBuffer, Rgb};
+    use ot::writing_proto::GetMyProfileRequest;
+
+    use crate::ids::IdType;
+    use crate::jobs::JobWorker;
+    use crate::testing::fixtures::create_user;
+    use crate::testing::utils::{MemoryBlobStore, TestDynamoDb};
+    use crate::users::UserRole;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(width, height, Rgb([0, 0, 0])));
+        let mut png = Vec::new();
+        image.write_to(&mut png, ImageOutputFormat::Png).unwrap();
+        png
+    }
+
+    #[tokio::test]
+    async fn test_upload_avatar() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let upload_store = Arc::new(MemoryBlobStore::default());
+        let photo_store = Arc::new(MemoryBlobStore::default());
+        let mut worker = JobWorker::default();
+        worker.register(Arc::new(AvatarResizeJobHandler {
+            upload_store: upload_store.clone(),
+            photo_store: photo_store.clone(),
+        }));
+        let user_id = create_user(&db.dynamodb_client, "jane@smith.com", "Jane Smith").await;
+        let session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: Id::new(IdType::Organization),
+            user_role: UserRole::Default,
+        };
+
+        // Uploads that are not images, or are too big, are turned down.
+        for request in vec![
+            RequestAvatarUploadRequest {
+                content_type: String::from("text/html"),
+                content_length: 100,
+            },
+            RequestAvatarUploadRequest {
+                content_type: String::from("image/png"),
+                content_length: MAX_AVATAR_BYTES + 1,
+            },
+        ] {
+            let result =
+                request_avatar_upload(upload_store.as_ref(), &session_user, &request).await;
+            assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+        }
+
+        let image = png(300, 200);
+        let response = request_avatar_upload(
+            upload_store.as_ref(),
+            &session_user,
+            &RequestAvatarUploadRequest {
+                content_type: String::from("image/png"),
+                content_length: image.len() as i64,
+            },
+        )
+        .await?;
+        let key = upload_key(&user_id, &response.upload_id);
+        assert_eq!(
+            response.upload_url,
+            format!("memory://upload/{}?content_type=image/png", key)
+        );
+
+        // Nothing has been uploaded yet.
+        let request = ConfirmAvatarUploadRequest {
+            upload_id: response.upload_id.clone(),
+        };
+        let result = confirm_avatar_upload(
+            &db.dynamodb_client,
+            upload_store.as_ref(),
+            &session_user,
+            &request,
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 404);
+
+        // Uploads with another content type than the ones allowed are deleted.
+        upload_store.put(&key, "text/html", image.clone()).await?;
+        let result = confirm_avatar_upload(
+            &db.dynamodb_client,
+            upload_store.as_ref(),
+            &session_user,
+            &request,
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+        assert!(upload_store.head(&key).await?.is_none());
+
+        upload_store.put(&key, "image/png", image).await?;
+        confirm_avatar_upload(
+            &db.dynamodb_client,
+            upload_store.as_ref(),
+            &session_user,
+            &request,
+        )
+        .await?;
+        assert!(
+            worker
+                .run_next_job(&db.dynamodb_client, chrono::Utc::now())
+                .await?
+        );
+        assert!(upload_store.head(&key).await?.is_none());
+
+        let profile = users::get_my_profile(
+            &db.dynamodb_client,
+            photo_store.as_ref(),
+            &session_user,
+            &GetMyProfileRequest {},
+        )
+        .await?
+        .profile
+        .unwrap();
+        assert_eq!(
+            profile.photos.iter().map(|p| p.size).collect::<Vec<_>>(),
+            AVATAR_SIZES
+        );
+        assert_eq!(profile.photo_url, profile.photos.last().unwrap().url);
+        for photo in &profile.photos {
+            let key = photo.url.strip_prefix("memory://public/").unwrap();
+            let body = photo_store.get(key).await?.unwrap();
+            let resized = image::load_from_memory(&body)?;
+            assert_eq!(resized.dimensions(), (photo.size, photo.size));
+        }
+
+        // New photos replace the old ones.
+        let image = png(64, 64);
+        let response = request_avatar_upload(
+            upload_store.as_ref(),
+            &session_user,
+            &RequestAvatarUploadRequest {
+                content_type: String::from("image/png"),
+                content_length: image.len() as i64,
+            },
+        )
+        .await?;
+        upload_store
+            .put(
+                &upload_key(&user_id, &response.upload_id),
+                "image/png",
+                image,
+            )
+            .await?;
+        confirm_avatar_upload(
+            &db.dynamodb_client,
+            upload_store.as_ref(),
+            &session_user,
+            &ConfirmAvatarUploadRequest {
+                upload_id: response.upload_id.clone(),
+            },
+        )
+        .await?;
+        assert!(
+            worker
+                .run_next_job(&db.dynamodb_client, chrono::Utc::now())
+                .await?
+        );
+        for photo in &profile.photos {
+            let key = photo.url.strip_prefix("memory://public/").unwrap();
+            assert!(photo_store.head(key).await?.is_none());
+        }
+        assert_eq!(photo_store.blobs.lock().unwrap().len(), AVATAR_SIZES.len());
+
+        // Deleting the account deletes all of the user's photos, and any uploads left behind.
+        upload_store
+            .put(
+                &upload_key(&user_id, &"0".repeat(32)),
+                "image/png",
+                png(1, 1),
+            )
+            .await?;
+        delete_avatars(
+            upload_store.as_ref(),
+            photo_store.as_ref(),
+            user_id.as_str(),
+        )
+        .await?;
+        assert!(upload_store.blobs.lock().unwrap().is_empty());
+        assert!(photo_store.blobs.lock().unwrap().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resize_turns_down_huge_images() {
+        assert!(resize(&png(MAX_AVATAR_DIMENSION + 1, 1)).is_err());
+        assert!(resize(b"not an image").is_err());
+    }
+}
@@ -0,0 +1,255 @@
+//! Storage for blobs: files that are too big for DynamoDB, like export archives and photos.
+//!
+//! Code depends on the `BlobStore` trait rather than on S3 directly, so that tests can keep blobs
+//! in memory. Each store is one bucket. Browsers upload and download blobs straight from the
+//! bucket, with pre-signed URLs, so that big files never pass through the backend.
+//!
+//! The backend uses a few stores, one per bucket:
+//!
+//! - `export_store`: org export archives. Private.
+//! - `upload_store`: files that users upload, before they are checked. Private.
+//! - `photo_store`: profile photos, resized from uploads. Anyone can read them, so that anyone who
+//!   can see a user's name can see their photo too.
+//! - `attachment_store`: images embedded in documents. Private, since documents are.
+
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::TryStreamExt;
+use rusoto_core::{Region, RusotoError};
+use rusoto_credential::{DefaultCredentialsProvider, ProvideAwsCredentials};
+use rusoto_s3::util::{PreSignedRequest, PreSignedRequestOption};
+use rusoto_s3::{
+    DeleteObjectRequest, GetObjectError, GetObjectRequest, HeadObjectError, HeadObjectRequest,
+    ListObjectsV2Request, PutObjectRequest, S3Client, S3,
+};
+
+/// What the store knows about a blob, without reading it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BlobMetadata {
+    pub content_type: String,
+    pub content_length: i64,
+}
+
+pub trait BlobStore: Send + Sync {
+    /// Stores the blob under the given key, replacing any blob already there.
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        content_type: &'a str,
+        body: Vec<u8>,
+    ) -> BoxFuture<'a, anyhow::Result<()>>;
+
+    /// Returns the blob with the given key, or `None` if there is none.
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<Option<Vec<u8>>>>;
+
+    /// Returns what the store knows about the blob with the given key, or `None` if there is none.
+    fn head<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<Option<BlobMetadata>>>;
+
+    /// Deletes the blob with the given key, if there is one.
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<()>>;
+
+    /// Returns the keys of the blobs whose keys start with the prefix, in no particular order.
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, anyhow::Result<Vec<String>>>;
+
+    /// Returns a URL that anyone can upload a blob to under the given key, with a PUT request with
+    /// the given Content-Type, until it expires. Nothing limits the size of the blob, so check it
+    /// with `head` before using the blob.
+    fn upload_url<'a>(
+        &'a self,
+        key: &'a str,
+        content_type: &'a str,
+        expires_in: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<String>>;
+
+    /// Returns a URL that anyone can download the blob from until it expires.
+    fn download_url<'a>(
+        &'a self,
+        key: &'a str,
+        expires_in: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<String>>;
+
+    /// Returns the URL that the blob is always at. Only stores whose bucket allows public reads
+    /// can serve it.
+    fn public_url(&self, key: &str) -> String;
+}
+
+/// Keeps blobs in an S3 bucket.
+pub struct S3BlobStore {
+    s3_client: S3Client,
+    region: Region,
+    bucket: String,
+    credentials_provider: DefaultCredentialsProvider,
+}
+
+impl S3BlobStore {
+    pub fn new(region: Region, bucket: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            s3_client: S3Client::new(region.clone()),
+            region,
+            bucket: bucket.to_string(),
+            credentials_provider: DefaultCredentialsProvider::new()?,
+        })
+    }
+}
+
+impl BlobStore for S3BlobStore {
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        content_type: &'a str,
+        body: Vec<u8>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let request = PutObjectRequest {
+                bucket: self.bucket.clone(),
+                key: key.to_string(),
+                content_length: Some(body.len() as i64),
+                content_type: Some(content_type.to_string()),
+                body: Some(body.into()),
+                ..Default::default()
+            };
+            self.s3_client.put_object(request).await?;
+            Ok(())
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<Option<Vec<u8>>>> {
+        Box::pin(async move {
+            let request = GetObjectRequest {
+                bucket: self.bucket.clone(),
+                key: key.to_string(),
+                ..Default::default()
+            };
+            let output = match self.s3_client.get_object(request).await {
+                Ok(output) => output,
+                Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            let body = match output.body {
+                Some(body) => body.map_ok(|bytes| bytes.to_vec()).try_concat().await?,
+                None => Vec::new(),
+            };
+            Ok(Some(body))
+        })
+    }
+
+    fn head<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<Option<BlobMetadata>>> {
+        Box::pin(async move {
+            let request = HeadObjectRequest {
+                bucket: self.bucket.clone(),
+                key: key.to_string(),
+                ..Default::default()
+            };
+            let output = match self.s3_client.head_object(request).await {
+                Ok(output) => output,
+                Err(RusotoError::Service(HeadObjectError::NoSuchKey(_))) => return Ok(None),
+                // HEAD responses have no body to tell what went wrong, so a missing blob is only a
+                // 404.
+                Err(RusotoError::Unknown(response)) if response.status.as_u16() == 404 => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e.into()),
+            };
+            Ok(Some(BlobMetadata {
+                content_type: output.content_type.unwrap_or_default(),
+                content_length: output.content_length.unwrap_or(0),
+            }))
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let request = DeleteObjectRequest {
+                bucket: self.bucket.clone(),
+                key: key.to_string(),
+                ..Default::default()
+            };
+            self.s3_client.delete_object(request).await?;
+            Ok(())
+        })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, anyhow::Result<Vec<String>>> {
+        Box::pin(async move {
+            let mut keys = Vec::new();
+            let mut continuation_token = None;
+            loop {
+                let request = ListObjectsV2Request {
+                    bucket: self.bucket.clone(),
+                    prefix: Some(prefix.to_string()),
+                    continuation_token,
+                    ..Default::default()
+                };
+                let output = self.s3_client.list_objects_v2(request).await?;
+                keys.extend(
+                    output
+                        .contents
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter_map(|object| object.key),
+                );
+                match output.next_continuation_token {
+                    Some(token) if output.is_truncated == Some(true) => {
+                        continuation_token = Some(token)
+                    }
+                    _ => return Ok(keys),
+                }
+            }
+        })
+    }
+
+    fn upload_url<'a>(
+        &'a self,
+        key: &'a str,
+        content_type: &'a str,
+        expires_in: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<String>> {
+        Box::pin(async move {
+            let credentials = self.credentials_provider.credentials().await?;
+            let request = PutObjectRequest {
+                bucket: self.bucket.clone(),
+                key: key.to_string(),
+                content_type: Some(content_type.to_string()),
+                ..Default::default()
+            };
+            Ok(request.get_presigned_url(
+                &self.region,
+                &credentials,
+                &PreSignedRequestOption { expires_in },
+            ))
+        })
+    }
+
+    fn download_url<'a>(
+        &'a self,
+        key: &'a str,
+        expires_in: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<String>> {
+        Box::pin(async move {
+            let credentials = self.credentials_provider.credentials().await?;
+            let request = GetObjectRequest {
+                bucket: self.bucket.clone(),
+                key: key.to_string(),
+                ..Default::default()
+            };
+            Ok(request.get_presigned_url(
+                &self.region,
+                &credentials,
+                &PreSignedRequestOption { expires_in },
+            ))
+        })
+    }
+
+    fn public_url(&self, key: &str) -> String {
+        match &self.region {
+            Region::Custom { endpoint, .. } => format!("{}/{}/{}", endpoint, self.bucket, key),
+            region => format!(
+                "https://{}.s3.{}.amazonaws.com/{}",
+                self.bucket,
+                region.name(),
+                key
+            ),
+        }
+    }
+}
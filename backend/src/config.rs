@@ -14,6 +14,34 @@ pub struct Config {
     pub http_port: u32,
     pub cookie_secret: String,
     pub cookie_secure: bool,
+    /// Origins other than the backend's own whose pages may call the API. See
+    /// `http::configure_cors`.
+    pub cors_allowed_origins: Vec<String>,
+    /// Whether cross-origin API requests from `cors_allowed_origins` may carry cookies.
+    pub cors_allow_credentials: bool,
+    pub send_notification_digests: bool,
+    /// The region of every S3 bucket. See `blob_store`.
+    pub s3_region: rusoto_core::Region,
+    pub export_s3_bucket: String,
+    pub upload_s3_bucket: String,
+    pub photo_s3_bucket: String,
+    pub attachment_s3_bucket: String,
+    pub job_worker_tasks: usize,
+    /// The largest request body that most API endpoints accept, in bytes.
+    pub max_request_body_size: usize,
+    /// The largest request body that endpoints taking document text accept, in bytes. See
+    /// `http::RequestBodyLimits`.
+    pub max_document_request_body_size: usize,
+    pub stream_processor: bool,
+    pub derived_data_from_stream: bool,
+    pub clean_up_orphaned_users: bool,
+    /// A global feature flag default to set, as NAME=true, NAME=false or NAME=default, before
+    /// exiting. See `feature_flags`.
+    pub set_global_feature_flag: Option<String>,
+    /// OAuth client credentials for logging in with Google. Unless both are set, users cannot log
+    /// in with Google.
+    pub google_client_id: Option<String>,
+    pub google_client_secret: Option<String>,
 }
 
 pub fn config() -> &'static Config {
@@ -50,13 +78,171 @@ fn parse_command_line_flags() -> Config {
                 .value_name("PORT")
                 .default_value("8080"),
         )
+        .arg(
+            Arg::with_name("dynamodb_endpoint")
+                .help(
+                    "The DynamoDB endpoint to use when DYNAMODB_REGION is \"local\". Default value points
+                       at the local DynamoDB docker container. Ignored for other regions.",
+                )
+                .takes_value(true)
+                .value_name("DYNAMODB_ENDPOINT")
+                .default_value("http://127.0.0.1:8000"),
+        )
+        .arg(
+            Arg::with_name("send_notification_digests")
+                .long("send_notification_digests")
+                .help(
+                    "Periodically email followers a digest of edits to the documents they follow.
+                       Enable on exactly one server.",
+                ),
+        )
+        .arg(
+            Arg::with_name("s3_region")
+                .long("s3_region")
+                .help(
+                    "The AWS region for the S3 buckets. Default value is \"local\", for
+                       dev/testing.",
+                )
+                .takes_value(true)
+                .value_name("S3_REGION")
+                .default_value("local"),
+        )
+        .arg(
+            Arg::with_name("s3_endpoint")
+                .long("s3_endpoint")
+                .help(
+                    "The S3 endpoint to use when S3_REGION is \"local\". Default value points at a
+                       local S3-compatible server. Ignored for other regions.",
+                )
+                .takes_value(true)
+                .value_name("S3_ENDPOINT")
+                .default_value("http://127.0.0.1:9000"),
+        )
+        .arg(
+            Arg::with_name("export_s3_bucket")
+                .long("export_s3_bucket")
+                .help("The S3 bucket that org exports are written to.")
+                .takes_value(true)
+                .value_name("EXPORT_S3_BUCKET")
+                .default_value("local-writing-exports"),
+        )
+        .arg(
+            Arg::with_name("upload_s3_bucket")
+                .long("upload_s3_bucket")
+                .help("The S3 bucket that users upload files to, before they are checked.")
+                .takes_value(true)
+                .value_name("UPLOAD_S3_BUCKET")
+                .default_value("local-writing-uploads"),
+        )
+        .arg(
+            Arg::with_name("photo_s3_bucket")
+                .long("photo_s3_bucket")
+                .help("The S3 bucket for resized profile photos. It must allow public reads.")
+                .takes_value(true)
+                .value_name("PHOTO_S3_BUCKET")
+                .default_value("local-writing-photos"),
+        )
+        .arg(
+            Arg::with_name("attachment_s3_bucket")
+                .long("attachment_s3_bucket")
+                .help("The S3 bucket for images embedded in documents.")
+                .takes_value(true)
+                .value_name("ATTACHMENT_S3_BUCKET")
+                .default_value("local-writing-attachments"),
+        )
+        .arg(
+            Arg::with_name("job_worker_tasks")
+                .long("job_worker_tasks")
+                .help("How many background jobs this server runs at once. 0 runs none.")
+                .takes_value(true)
+                .value_name("JOB_WORKER_TASKS")
+                .default_value("4"),
+        )
+        .arg(
+            Arg::with_name("cors_allowed_origins")
+                .long("cors_allowed_origins")
+                .help(
+                    "Comma-separated origins, like https://writing.example.com, whose pages may
+                       call the API. Same-origin pages always may. Wildcards are not allowed.",
+                )
+                .takes_value(true)
+                .value_name("ORIGINS")
+                .default_value("")
+                .validator(|origins| {
+                    if origins.split(',').any(|origin| origin.trim() == "*") {
+                        Err("Wildcard origins are not allowed".to_string())
+                    } else {
+                        Ok(())
+                    }
+                }),
+        )
+        .arg(
+            Arg::with_name("cors_allow_credentials")
+                .long("cors_allow_credentials")
+                .help("Let cross-origin API requests from cors_allowed_origins carry cookies."),
+        )
+        .arg(
+            Arg::with_name("max_request_body_size")
+                .long("max_request_body_size")
+                .help("The largest request body that most API endpoints accept, in bytes.")
+                .takes_value(true)
+                .value_name("BYTES")
+                .default_value("65536"),
+        )
+        .arg(
+            Arg::with_name("max_document_request_body_size")
+                .long("max_document_request_body_size")
+                .help(
+                    "The largest request body that API endpoints taking document text, like
+                       submitting a change set, accept, in bytes.",
+                )
+                .takes_value(true)
+                .value_name("BYTES")
+                .default_value("4194304"),
+        )
+        .arg(
+            Arg::with_name("stream_processor")
+                .long("stream_processor")
+                .help(
+                    "Run the revision stream processor instead of the HTTP server. It reads the
+                       document_revisions stream and maintains data derived from revisions. Run
+                       exactly one.",
+                ),
+        )
+        .arg(
+            Arg::with_name("derived_data_from_stream")
+                .long("derived_data_from_stream")
+                .help(
+                    "Leave data derived from revisions, like edit notifications, to the revision
+                       stream processor, instead of writing it while handling requests. Set on every
+                       server once the stream processor is running.",
+                ),
+        )
+        .arg(
+            Arg::with_name("clean_up_orphaned_users")
+                .long("clean_up_orphaned_users")
+                .help(
+                    "Delete users who belong to no org, which sign ups could once leave behind, and
+                       exit instead of running the HTTP server.",
+                ),
+        )
+        .arg(
+            Arg::with_name("set_global_feature_flag")
+                .long("set_global_feature_flag")
+                .takes_value(true)
+                .value_name("NAME=true|false|default")
+                .help(
+                    "Set the global default of a feature flag, which orgs follow unless an admin
+                       sets it for their org, and exit instead of running the HTTP server.",
+                ),
+        )
         .get_matches();
 
     Config {
         dynamodb_region: match matches.value_of("dynamodb_region").unwrap() {
             "local" => rusoto_core::Region::Custom {
                 name: "local".to_string(),
-                endpoint: "http://127.0.0.1:8000".to_string(),
+                endpoint: matches.value_of("dynamodb_endpoint").unwrap().to_string(),
             },
             region_str => rusoto_core::Region::from_str(region_str).unwrap(),
         },
@@ -73,5 +259,52 @@ fn parse_command_line_flags() -> Config {
             .unwrap_or_else(|_| "true".to_string())
             .parse::<bool>()
             .unwrap(),
+        cors_allowed_origins: matches
+            .value_of("cors_allowed_origins")
+            .unwrap()
+            .split(',')
+            .map(str::trim)
+            .filter(|origin| !origin.is_empty())
+            .map(String::from)
+            .collect(),
+        cors_allow_credentials: matches.is_present("cors_allow_credentials"),
+        send_notification_digests: matches.is_present("send_notification_digests"),
+        s3_region: match matches.value_of("s3_region").unwrap() {
+            "local" => rusoto_core::Region::Custom {
+                name: "local".to_string(),
+                endpoint: matches.value_of("s3_endpoint").unwrap().to_string(),
+            },
+            region_str => rusoto_core::Region::from_str(region_str).unwrap(),
+        },
+        export_s3_bucket: matches.value_of("export_s3_bucket").unwrap().to_string(),
+        upload_s3_bucket: matches.value_of("upload_s3_bucket").unwrap().to_string(),
+        photo_s3_bucket: matches.value_of("photo_s3_bucket").unwrap().to_string(),
+        attachment_s3_bucket: matches
+            .value_of("attachment_s3_bucket")
+            .unwrap()
+            .to_string(),
+        job_worker_tasks: matches
+            .value_of("job_worker_tasks")
+            .unwrap()
+            .parse::<usize>()
+            .unwrap(),
+        max_request_body_size: matches
+            .value_of("max_request_body_size")
+            .unwrap()
+            .parse::<usize>()
+            .unwrap(),
+        max_document_request_body_size: matches
+            .value_of("max_document_request_body_size")
+            .unwrap()
+            .parse::<usize>()
+            .unwrap(),
+        stream_processor: matches.is_present("stream_processor"),
+        derived_data_from_stream: matches.is_present("derived_data_from_stream"),
+        clean_up_orphaned_users: matches.is_present("clean_up_orphaned_users"),
+        set_global_feature_flag: matches
+            .value_of("set_global_feature_flag")
+            .map(String::from),
+        google_client_id: std::env::var("GOOGLE_CLIENT_ID").ok(),
+        google_client_secret: std::env::var("GOOGLE_CLIENT_SECRET").ok(),
     }
 }
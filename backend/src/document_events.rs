@@ -0,0 +1,527 @@
+//! Live document events.
+//!
+//! Clients that cannot use WebSockets can stream events about a document from
+//! `/api/documents.events`, as server-sent events, instead of polling for new revisions. Each time
+//! a revision is committed, subscribers get a `revision_committed` event with the revision number
+//! and author, and sync to fetch the revision itself.
+//!
+//! While someone edits the document, their client sends a typing heartbeat every couple of
+//! seconds with `/api/documents.send_typing`, and the other subscribers get `typing` events. There
+//! is no event for when someone stops typing: clients forget a typist after a few seconds without
+//! a heartbeat. A heartbeat may also name the region of the document that its sender is editing,
+//! for `region_lock` events. See `region_locks`.
+//!
+//! Events are broadcast in process, so a subscriber only hears about revisions committed through
+//! the server that it is connected to. Clients keep syncing periodically to catch the rest.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use futures::stream::{self, Stream};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::broadcast::{self, RecvError};
+
+use ot::writing_proto::{DocumentSharingPermission, SendTypingRequest, SendTypingResponse};
+
+use crate::documents;
+use crate::dynamodb::DynamoDbClient;
+use crate::http::{Requester, SessionUser};
+use crate::ids::Id;
+use crate::permission_cache::PermissionCache;
+use crate::rate_limiter::RateLimiter;
+use crate::region_locks::{RegionLock, RegionLocks};
+use crate::users;
+
+/// How many events a subscriber may fall behind by before it starts missing them. A subscriber
+/// that misses events gets a `lagged` event instead, and should sync.
+const CHANNEL_CAPACITY: usize = 64;
+
+// When there are this many channels, channels that nobody listens to any more are swept out on
+// the next subscribe.
+const SWEEP_THRESHOLD: usize = 10_000;
+
+/// How often to send a comment down an idle event stream, so that proxies do not close it. The
+/// subscriber's access is checked again just as often.
+pub const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RevisionCommitted {
+    pub doc_id: String,
+    pub revision_number: i64,
+    pub author_user_id: String,
+}
+
+/// A typing heartbeat.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Typing {
+    pub doc_id: String,
+    /// The user id, or the guest id for guests.
+    pub user_id: String,
+    pub display_name: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DocumentEvent {
+    RevisionCommitted(RevisionCommitted),
+    Typing(Typing),
+    RegionLock(RegionLock),
+}
+
+impl DocumentEvent {
+    pub fn doc_id(&self) -> &str {
+        match self {
+            DocumentEvent::RevisionCommitted(event) => &event.doc_id,
+            DocumentEvent::Typing(event) => &event.doc_id,
+            DocumentEvent::RegionLock(event) => &event.doc_id,
+        }
+    }
+
+    /// Who the event is about, for events that their subject need not hear about.
+    fn user_id(&self) -> Option<&str> {
+        match self {
+            DocumentEvent::RevisionCommitted(_) => None,
+            DocumentEvent::Typing(event) => Some(&event.user_id),
+            DocumentEvent::RegionLock(event) => Some(&event.user_id),
+        }
+    }
+}
+
+impl From<RevisionCommitted> for DocumentEvent {
+    fn from(event: RevisionCommitted) -> Self {
+        DocumentEvent::RevisionCommitted(event)
+    }
+}
+
+impl From<Typing> for DocumentEvent {
+    fn from(event: Typing) -> Self {
+        DocumentEvent::Typing(event)
+    }
+}
+
+impl From<RegionLock> for DocumentEvent {
+    fn from(event: RegionLock) -> Self {
+        DocumentEvent::RegionLock(event)
+    }
+}
+
+/// The query string of `/api/documents.events`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DocumentEventsQuery {
+    pub doc_id: String,
+    #[serde(default)]
+    pub share_token: String,
+}
+
+/// Broadcasts events about each document to the subscribers of that document on this server.
+#[derive(Default)]
+pub struct DocumentEvents {
+    channels: Mutex<HashMap<String, broadcast::Sender<DocumentEvent>>>,
+}
+
+impl DocumentEvents {
+    pub fn subscribe(&self, doc_id: &str) -> broadcast::Receiver<DocumentEvent> {
+        let mut channels = self.channels.lock().unwrap();
+        if channels.len() >= SWEEP_THRESHOLD {
+            channels.retain(|_, sender| sender.receiver_count() > 0);
+        }
+        match channels.get(doc_id) {
+            Some(sender) => sender.subscribe(),
+            None => {
+                let (sender, receiver) = broadcast::channel(CHANNEL_CAPACITY);
+                channels.insert(doc_id.to_string(), sender);
+                receiver
+            }
+        }
+    }
+
+    /// Sends the event to every subscriber of its document. Does nothing if there are none.
+    pub fn publish(&self, event: impl Into<DocumentEvent>) {
+        let event = event.into();
+        let mut channels = self.channels.lock().unwrap();
+        if let Some(sender) = channels.get(event.doc_id()) {
+            let doc_id = event.doc_id().to_string();
+            if sender.send(event).is_err() {
+                // Every subscriber has gone away.
+                channels.remove(&doc_id);
+            }
+        }
+    }
+}
+
+/// Subscribe to events about a document. The caller needs the same access as for reading the
+/// document's revisions: view or edit permission, either as the session user or through the share
+/// token. See `validate_subscriber_access`.
+pub async fn subscribe_to_document_events(
+    dynamodb_client: &DynamoDbClient,
+    permission_cache: &PermissionCache,
+    document_events: &DocumentEvents,
+    session_user: Option<&SessionUser>,
+    query: &DocumentEventsQuery,
+) -> actix_web::Result<broadcast::Receiver<DocumentEvent>> {
+    // Subscribe before checking permissions, so that no revision committed in the meantime is
+    // missed.
+    let receiver = document_events.subscribe(&query.doc_id);
+    validate_subscriber_access(dynamodb_client, permission_cache, session_user, query).await?;
+    Ok(receiver)
+}
+
+/// Validates that the caller may subscribe to events about the document, when they subscribe and
+/// again while they stay subscribed.
+///
+/// If there is no session user, and no valid share token, returns 401 Unauthorized.
+///
+/// If the user does not have permission to view the document, returns 403 Forbidden.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn validate_subscriber_access(
+    dynamodb_client: &DynamoDbClient,
+    permission_cache: &PermissionCache,
+    session_user: Option<&SessionUser>,
+    query: &DocumentEventsQuery,
+) -> actix_web::Result<()> {
+    documents::validate_some_access_cached(
+        dynamodb_client,
+        permission_cache,
+        session_user,
+        &query.doc_id,
+        &query.share_token,
+        &[
+            DocumentSharingPermission::CanView,
+            DocumentSharingPermission::CanComment,
+            DocumentSharingPermission::CanEdit,
+        ],
+    )
+    .await?;
+    Ok(())
+}
+
+/// Tell the other subscribers to the document that the requester is typing. The requester needs
+/// edit permission, either as the session user or through the share token.
+///
+/// Heartbeats beyond `rate_limiter::TYPING_MAX_HEARTBEATS_PER_WINDOW` per window are dropped
+/// without an error, since the earlier ones already told everyone.
+///
+/// If the heartbeat names the region that the requester is editing, registers it as their region
+/// lock, and tells the other subscribers about it too.
+///
+/// If the editing region is out of bounds, returns 400 Bad Request.
+///
+/// If the user does not have permission to edit the document, returns 403 Forbidden.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn send_typing(
+    dynamodb_client: &DynamoDbClient,
+    permission_cache: &PermissionCache,
+    document_events: &DocumentEvents,
+    typing_rate_limiter: &RateLimiter,
+    region_locks: &RegionLocks,
+    requester: &Requester,
+    request: &SendTypingRequest,
+) -> actix_web::Result<SendTypingResponse> {
+    if let Some(region) = &request.editing_region {
+        if region.offset < 0 || region.count < 0 || request.revision_number < 0 {
+            return Err(actix_web::error::ErrorBadRequest(""));
+        }
+    }
+    let org_id = documents::validate_some_access_cached(
+        dynamodb_client,
+        permission_cache,
+        requester.session_user(),
+        &request.doc_id,
+        &request.share_token,
+        &[DocumentSharingPermission::CanEdit],
+    )
+    .await?;
+    let rate_limit_key = format!("{}:{}", requester.id().as_str(), &request.doc_id);
+    if !typing_rate_limiter.try_acquire(&rate_limit_key) {
+        return Ok(SendTypingResponse {});
+    }
+    let display_name = match requester {
+        Requester::User(session_user) => {
+            users::get_user_name(dynamodb_client, &session_user.user_id)
+                .await
+                .map_err(|e| {
+                    log::error!(
+                        "Error occurred: \"{}\" [send_typing] [requester: {:?}, request: {:?}]",
+                        e,
+                        requester,
+                        request,
+                    );
+                    actix_web::error::ErrorInternalServerError("")
+                })?
+        }
+        Requester::Guest(guest_user) => guest_user.display_name.clone(),
+    };
+    document_events.publish(Typing {
+        doc_id: request.doc_id.clone(),
+        user_id: requester.id().as_str().to_string(),
+        display_name: display_name.clone(),
+    });
+    if let Some(region) = &request.editing_region {
+        let lock = RegionLock {
+            doc_id: request.doc_id.clone(),
+            org_id,
+            user_id: requester.id().as_str().to_string(),
+            display_name,
+            revision_number: request.revision_number,
+            region: region.clone(),
+        };
+        if let Some(lock) = region_locks.register(lock, Instant::now()) {
+            document_events.publish(lock);
+        }
+    }
+    Ok(SendTypingResponse {})
+}
+
+/// Turns the subscription into the body of a `text/event-stream` response, starting with the
+/// `initial_events`, like the region locks that were registered before the subscriber connected.
+/// The subscriber's own typing and region lock events are left out.
+///
+/// A stream could otherwise outlive the access that it was opened with, like a share token that
+/// was revoked or a sharing permission that was removed. So with every keep-alive, `check_access`
+/// is run again, and the stream ends if it returns false. Otherwise, the stream ends only when the
+/// client disconnects.
+pub fn event_stream<F, Fut>(
+    receiver: broadcast::Receiver<DocumentEvent>,
+    subscriber_id: Option<Id>,
+    initial_events: Vec<DocumentEvent>,
+    check_access: F,
+) -> impl Stream<Item = actix_web::Result<bytes::Bytes>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let keep_alive = tokio::time::interval(KEEP_ALIVE_INTERVAL);
+    stream::unfold(
+        (
+            receiver,
+            keep_alive,
+            subscriber_id,
+            initial_events.into_iter(),
+            check_access,
+        ),
+        |state| async move {
+            let (mut receiver, mut keep_alive, subscriber_id, mut initial_events, mut check) =
+                state;
+            let message = loop {
+                let result = match initial_events.next() {
+                    Some(event) => Ok(event),
+                    None => tokio::select! {
+                        result = receiver.recv() => result,
+                        _ = keep_alive.tick() => {
+                            if !check().await {
+                                return None;
+                            }
+                            break String::from(": keep-alive\n\n");
+                        }
+                    },
+                };
+                break match result {
+                    Ok(event) => {
+                        let is_own = event.user_id().is_some()
+                            && event.user_id() == subscriber_id.as_ref().map(Id::as_str);
+                        if is_own {
+                            continue;
+                        }
+                        format_event(&event)
+                    }
+                    Err(RecvError::Lagged(_)) => String::from("event: lagged\ndata: {}\n\n"),
+                    Err(RecvError::Closed) => return None,
+                };
+            };
+            Some((
+                Ok(bytes::Bytes::from(message)),
+                (receiver, keep_alive, subscriber_id, initial_events, check),
+            ))
+        },
+    )
+}
+
+fn format_event(event: &DocumentEvent) -> String {
+    match event {
+        DocumentEvent::RevisionCommitted(event) => format_revision_committed(event),
+        DocumentEvent::Typing(event) => format_typing(event),
+        DocumentEvent::RegionLock(event) => format_region_lock(event),
+    }
+}
+
+fn format_revision_committed(event: &RevisionCommitted) -> String {
+    let data = json!({
+        "revision_number": event.revision_number,
+        "author_user_id": event.author_user_id,
+    });
+    format!("event: revision_committed\ndata: {}\n\n", data)
+}
+
+fn format_typing(event: &Typing) -> String {
+    let data = json!({
+        "user_id": event.user_id,
+        "display_name": event.display_name,
+    });
+    format!("event: typing\ndata: {}\n\n", data)
+}
+
+fn format_region_lock(event: &RegionLock) -> String {
+    let data = json!({
+        "user_id": event.user_id,
+        "display_name": event.display_name,
+        "revision_number": event.revision_number,
+        "offset": event.region.offset,
+        "count": event.region.count,
+    });
+    format!("event: region_lock\ndata: {}\n\n", data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::StreamExt;
+
+    use crate::ids::IdType;
+
+    fn revision_committed(doc_id: &str, revision_number: i64) -> RevisionCommitted {
+        RevisionCommitted {
+            doc_id: doc_id.to_string(),
+            revision_number,
+            author_user_id: String::from("u_author"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_publish_and_subscribe() {
+        let document_events = DocumentEvents::default();
+
+        // Publishing without subscribers does nothing.
+        document_events.publish(revision_committed("d_1", 1));
+
+        let mut receiver1 = document_events.subscribe("d_1");
+        let mut receiver2 = document_events.subscribe("d_1");
+        let mut other_receiver = document_events.subscribe("d_2");
+        document_events.publish(revision_committed("d_1", 2));
+        assert_eq!(
+            receiver1.recv().await,
+            Ok(DocumentEvent::from(revision_committed("d_1", 2)))
+        );
+        assert_eq!(
+            receiver2.recv().await,
+            Ok(DocumentEvent::from(revision_committed("d_1", 2)))
+        );
+        assert!(other_receiver.try_recv().is_err());
+
+        // Once every subscriber is gone, the channel is dropped on the next publish.
+        drop(receiver1);
+        drop(receiver2);
+        document_events.publish(revision_committed("d_1", 3));
+        assert!(!document_events.channels.lock().unwrap().contains_key("d_1"));
+        assert!(document_events.channels.lock().unwrap().contains_key("d_2"));
+    }
+
+    #[test]
+    fn test_format_revision_committed() {
+        assert_eq!(
+            format_revision_committed(&revision_committed("d_1", 7)),
+            "event: revision_committed\n\
+            data: {\"author_user_id\":\"u_author\",\"revision_number\":7}\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_event_stream_leaves_out_own_typing() {
+        let document_events = DocumentEvents::default();
+        let typist = Id::new(IdType::User);
+        let receiver = document_events.subscribe("d_1");
+        let mut events = Box::pin(event_stream(
+            receiver,
+            Some(typist.clone()),
+            Vec::new(),
+            || async { true },
+        ));
+        document_events.publish(Typing {
+            doc_id: String::from("d_1"),
+            user_id: typist.as_str().to_string(),
+            display_name: String::from("Me"),
+        });
+        document_events.publish(Typing {
+            doc_id: String::from("d_1"),
+            user_id: String::from("g_guest"),
+            display_name: String::from("Someone"),
+        });
+        let mut message = events.next().await.unwrap().unwrap();
+        while message.starts_with(b":") {
+            message = events.next().await.unwrap().unwrap();
+        }
+        assert_eq!(
+            message,
+            "event: typing\n\
+            data: {\"display_name\":\"Someone\",\"user_id\":\"g_guest\"}\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_event_stream_starts_with_initial_events() {
+        let document_events = DocumentEvents::default();
+        let subscriber = Id::new(IdType::User);
+        let region_lock = |user_id: &str| {
+            DocumentEvent::from(RegionLock {
+                doc_id: String::from("d_1"),
+                org_id: String::from("o_1"),
+                user_id: user_id.to_string(),
+                display_name: String::from("Someone"),
+                revision_number: 3,
+                region: ot::writing_proto::Selection {
+                    offset: 4,
+                    count: 10,
+                },
+            })
+        };
+        let receiver = document_events.subscribe("d_1");
+        let mut events = Box::pin(event_stream(
+            receiver,
+            Some(subscriber.clone()),
+            vec![region_lock(subscriber.as_str()), region_lock("u_other")],
+            || async { true },
+        ));
+        document_events.publish(revision_committed("d_1", 4));
+        let mut messages = Vec::new();
+        while messages.len() < 2 {
+            let message = events.next().await.unwrap().unwrap();
+            if !message.starts_with(b":") {
+                messages.push(message);
+            }
+        }
+        assert_eq!(
+            messages,
+            vec![
+                "event: region_lock\n\
+                data: {\"count\":10,\"display_name\":\"Someone\",\"offset\":4,\
+                \"revision_number\":3,\"user_id\":\"u_other\"}\n\n",
+                "event: revision_committed\n\
+                data: {\"author_user_id\":\"u_author\",\"revision_number\":4}\n\n",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_event_stream_ends_without_access() {
+        let document_events = DocumentEvents::default();
+        let receiver = document_events.subscribe("d_1");
+        // The first keep-alive is due right away, after the initial events.
+        let mut events = Box::pin(event_stream(
+            receiver,
+            None,
+            vec![DocumentEvent::from(revision_committed("d_1", 1))],
+            || async { false },
+        ));
+        let message = events.next().await.unwrap().unwrap();
+        assert!(message.starts_with(b"event: revision_committed"));
+        assert!(events.next().await.is_none());
+    }
+}
@@ -0,0 +1,226 @@
+//! Document stats, like word counts, for the document list.
+//!
+//! Editors' clients count the stats incrementally as they apply changes, and report them after
+//! syncing. We keep the report for the newest revision, so the stats may trail the document a
+//! little, but we never need to replay a document's revisions to count them.
+
+use std::collections::HashMap;
+
+use actix_web::error;
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{GetItemInput, UpdateItemError, UpdateItemInput};
+
+use ot::writing_proto::{
+    Document, DocumentSharingPermission, UpdateDocumentStatsRequest, UpdateDocumentStatsResponse,
+};
+
+use crate::documents;
+use crate::dynamodb::{self, av_get_n, av_get_s, av_map, av_n, av_s, table_name, DynamoDbClient};
+use crate::http::SessionUser;
+use crate::utils::time;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct DocumentStats {
+    pub revision_number: i64,
+    pub word_count: i64,
+    pub character_count: i64,
+    pub paragraph_count: i64,
+}
+
+/// Store the stats that the session user's client counted for the document at the given revision.
+/// Stats for a revision older than the stored stats are ignored, so that a client that fell behind
+/// cannot overwrite newer stats.
+///
+/// If any count is negative, or the revision number is not positive, returns 400 Bad Request.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If the session user cannot edit the document, returns 403 Forbidden.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn update_document_stats(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &UpdateDocumentStatsRequest,
+) -> actix_web::Result<UpdateDocumentStatsResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [update_document_stats] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    if request.revision_number <= 0
+        || request.word_count < 0
+        || request.character_count < 0
+        || request.paragraph_count < 0
+    {
+        return Err(error::ErrorBadRequest(""));
+    }
+    documents::get_document_if_some_permission_valid(
+        dynamodb_client,
+        session_user,
+        &request.doc_id,
+        &[DocumentSharingPermission::CanEdit],
+    )
+    .await?;
+    let input = UpdateItemInput {
+        table_name: table_name("document_stats"),
+        key: av_map(&[av_s("doc_id", &request.doc_id)]),
+        condition_expression: Some(String::from(
+            "attribute_not_exists(doc_id) OR revision_number < :revision_number",
+        )),
+        update_expression: Some(String::from(
+            "SET revision_number = :revision_number, word_count = :word_count, \
+            character_count = :character_count, paragraph_count = :paragraph_count, \
+            updated_at = :updated_at",
+        )),
+        expression_attribute_values: Some(av_map(&[
+            av_n(":revision_number", request.revision_number),
+            av_n(":word_count", request.word_count),
+            av_n(":character_count", request.character_count),
+            av_n(":paragraph_count", request.paragraph_count),
+            av_s(":updated_at", &time::date_time_iso_str(&chrono::Utc::now())),
+        ])),
+        ..Default::default()
+    };
+    match dynamodb_client.update_item(input).await {
+        Ok(_) | Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => {
+            Ok(UpdateDocumentStatsResponse {})
+        }
+        Err(e) => {
+            log_error(e.to_string());
+            Err(error::ErrorInternalServerError(""))
+        }
+    }
+}
+
+/// Returns the stored stats for the document, or `None` if no client has reported any yet. Does
+/// not check permissions.
+pub async fn get_document_stats(
+    dynamodb_client: &DynamoDbClient,
+    doc_id: &str,
+) -> actix_web::Result<Option<DocumentStats>> {
+    let input = GetItemInput {
+        table_name: table_name("document_stats"),
+        key: av_map(&[av_s("doc_id", doc_id)]),
+        ..Default::default()
+    };
+    let output = dynamodb_client.get_item(input).await.map_err(|e| {
+        log::error!(
+            "Error occurred: \"{}\" [get_document_stats] [doc_id: {}]",
+            e,
+            doc_id
+        );
+        error::ErrorInternalServerError("")
+    })?;
+    let item = match output.item {
+        Some(item) => item,
+        None => return Ok(None),
+    };
+    Ok(Some(DocumentStats {
+        revision_number: av_get_n(&item, "revision_number").unwrap_or(0),
+        word_count: av_get_n(&item, "word_count").unwrap_or(0),
+        character_count: av_get_n(&item, "character_count").unwrap_or(0),
+        paragraph_count: av_get_n(&item, "paragraph_count").unwrap_or(0),
+    }))
+}
+
+/// Sets the word count of each document to the stored count. Documents that no client reported
+/// stats for yet are left at 0. Does not check permissions.
+pub async fn set_word_counts(
+    dynamodb_client: &DynamoDbClient,
+    documents: &mut [Document],
+) -> Result<(), String> {
+    let keys = documents
+        .iter()
+        .map(|document| av_map(&[av_s("doc_id", &document.id)]))
+        .collect();
+    let items = dynamodb::batch_get_all_items(
+        dynamodb_client,
+        &table_name("document_stats"),
+        keys,
+        "doc_id, word_count",
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+    let word_counts: HashMap<&str, i64> = items
+        .iter()
+        .filter_map(|item| Some((av_get_s(item, "doc_id")?, av_get_n(item, "word_count")?)))
+        .collect();
+    for document in documents.iter_mut() {
+        document.word_count = word_counts.get(document.id.as_str()).copied().unwrap_or(0);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ids::{Id, IdType};
+    use crate::testing::fixtures::DocumentFixture;
+    use crate::testing::utils::TestDynamoDb;
+    use crate::users::UserRole;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[tokio::test]
+    async fn test_update_document_stats() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let user_id = Id::new(IdType::User);
+        let doc = DocumentFixture::new().with_created_by_user_id(&user_id);
+        doc.create(&db.dynamodb_client).await;
+        let session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: doc.org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let doc_id = doc.doc_id.as_str();
+        assert_eq!(get_document_stats(&db.dynamodb_client, doc_id).await?, None);
+
+        let request = UpdateDocumentStatsRequest {
+            doc_id: doc_id.to_string(),
+            revision_number: 5,
+            word_count: 10,
+            character_count: 50,
+            paragraph_count: 2,
+        };
+        update_document_stats(&db.dynamodb_client, &session_user, &request).await?;
+        let expected_stats = DocumentStats {
+            revision_number: 5,
+            word_count: 10,
+            character_count: 50,
+            paragraph_count: 2,
+        };
+        assert_eq!(
+            get_document_stats(&db.dynamodb_client, doc_id).await?,
+            Some(expected_stats)
+        );
+
+        // Stats for an older revision are ignored.
+        let stale_request = UpdateDocumentStatsRequest {
+            revision_number: 4,
+            word_count: 8,
+            ..request.clone()
+        };
+        update_document_stats(&db.dynamodb_client, &session_user, &stale_request).await?;
+        assert_eq!(
+            get_document_stats(&db.dynamodb_client, doc_id).await?,
+            Some(expected_stats)
+        );
+
+        // Users who cannot edit the document cannot report stats.
+        let other_user = SessionUser {
+            user_id: Id::new(IdType::User),
+            ..session_user.clone()
+        };
+        let result = update_document_stats(&db.dynamodb_client, &other_user, &request).await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,292 @@
+//! Tracks when each user last opened each document, so that the home screen can list the
+//! documents they viewed recently, separately from the ones they created.
+
+use actix_web::error;
+use chrono::{DateTime, Utc};
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{QueryInput, UpdateItemError, UpdateItemInput};
+
+use ot::writing_proto::{ListRecentlyViewedRequest, ListRecentlyViewedResponse, ViewedDocument};
+
+use crate::documents;
+use crate::dynamodb::{self, av_get_s, av_map, av_s, table_name, DynamoDbClient};
+use crate::http::SessionUser;
+use crate::starred_documents;
+use crate::utils::time;
+
+/// Opening a document again within this many minutes of the last recorded view does not write
+/// anything. The editor loads the document on every page load, and the order of the "Recent" list
+/// does not need to be more precise than this.
+pub const VIEW_DEBOUNCE_MINUTES: i64 = 5;
+
+const LIST_RECENTLY_VIEWED_DEFAULT_LIMIT: i64 = 20;
+const LIST_RECENTLY_VIEWED_MAX_LIMIT: i64 = 100;
+
+/// Records that the session user viewed the document at `now`. Does nothing if the last recorded
+/// view is less than `VIEW_DEBOUNCE_MINUTES` old.
+///
+/// The caller must have checked that the session user can view the document.
+///
+/// Returns an error message to log on failure.
+pub async fn record_document_view(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    doc_id: &str,
+    now: DateTime<Utc>,
+) -> Result<(), String> {
+    let debounce_before = now - chrono::Duration::minutes(VIEW_DEBOUNCE_MINUTES);
+    let input = UpdateItemInput {
+        table_name: table_name("document_views"),
+        key: av_map(&[
+            av_s("user_id", session_user.user_id.as_str()),
+            av_s("doc_id", doc_id),
+        ]),
+        condition_expression: Some(String::from(
+            "attribute_not_exists(last_viewed_at) OR last_viewed_at < :debounce_before",
+        )),
+        update_expression: Some(String::from(
+            "SET org_id = :org_id, last_viewed_at = :last_viewed_at",
+        )),
+        expression_attribute_values: Some(av_map(&[
+            av_s(":org_id", session_user.org_id.as_str()),
+            av_s(":last_viewed_at", &time::date_time_iso_str(&now)),
+            av_s(
+                ":debounce_before",
+                &time::date_time_iso_str(&debounce_before),
+            ),
+        ])),
+        ..Default::default()
+    };
+    match dynamodb_client.update_item(input).await {
+        Ok(_) | Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// List the documents the session user viewed most recently, most recent first.
+///
+/// Documents that were deleted or archived, or that the session user can no longer view, are left
+/// out, so a page may hold fewer documents than the limit even when there are more to read.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+///
+/// Upon success, returns a page of documents, and the time to pass as `viewed_before_date_time` to
+/// read the next page.
+pub async fn list_recently_viewed(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &ListRecentlyViewedRequest,
+) -> actix_web::Result<ListRecentlyViewedResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [list_recently_viewed] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    let limit = match request.limit as i64 {
+        limit if limit <= 0 => LIST_RECENTLY_VIEWED_DEFAULT_LIMIT,
+        limit => limit.min(LIST_RECENTLY_VIEWED_MAX_LIMIT),
+    };
+    let mut values = vec![av_s(":user_id", session_user.user_id.as_str())];
+    let key_condition_expression = if request.viewed_before_date_time.is_empty() {
+        "user_id = :user_id"
+    } else {
+        values.push(av_s(":viewed_before", &request.viewed_before_date_time));
+        "user_id = :user_id AND last_viewed_at < :viewed_before"
+    };
+    let input = QueryInput {
+        table_name: table_name("document_views"),
+        index_name: Some(String::from("user_id-last_viewed_at-index")),
+        scan_index_forward: Some(false),
+        key_condition_expression: Some(String::from(key_condition_expression)),
+        expression_attribute_values: Some(av_map(&values)),
+        projection_expression: Some(String::from("doc_id, org_id, last_viewed_at")),
+        limit: Some(limit),
+        ..Default::default()
+    };
+    let output = dynamodb_client.query(input).await.map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    let items = output.items.unwrap_or_default();
+    let mut response = ListRecentlyViewedResponse::default();
+    if output.last_evaluated_key.is_some() {
+        if let Some(last_viewed_at) = items
+            .last()
+            .and_then(|item| av_get_s(item, "last_viewed_at"))
+        {
+            response.next_viewed_before_date_time = last_viewed_at.to_string();
+        }
+    }
+
+    // Views from another org the user belonged to are not shown in this one.
+    let views: Vec<(&str, &str)> = items
+        .iter()
+        .filter(|item| av_get_s(item, "org_id") == Some(session_user.org_id.as_str()))
+        .filter_map(|item| Some((av_get_s(item, "doc_id")?, av_get_s(item, "last_viewed_at")?)))
+        .collect();
+    let keys = views
+        .iter()
+        .map(|(doc_id, _)| av_map(&[av_s("id", doc_id)]))
+        .collect();
+    let document_items = dynamodb::batch_get_all_items(
+        dynamodb_client,
+        &table_name("documents"),
+        keys,
+        "id, org_id, title, created_by_user_id, owner_user_id, org_level_sharing_permission, \
+        created_at, updated_at, encryption_key_fingerprint, archived_at",
+    )
+    .await
+    .map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    let documents = document_items
+        .iter()
+        .filter(|item| av_get_s(item, "org_id") == Some(session_user.org_id.as_str()))
+        .map(documents::document_from_item)
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| {
+            log_error("document is missing a field".to_string());
+            error::ErrorInternalServerError("")
+        })?
+        .into_iter()
+        .filter(|document| document.archived_at.is_empty())
+        .collect();
+    let mut documents =
+        documents::filter_accessible_documents(dynamodb_client, session_user, documents)
+            .await
+            .map_err(|e| {
+                log_error(e);
+                error::ErrorInternalServerError("")
+            })?;
+    starred_documents::mark_starred(dynamodb_client, session_user, &mut documents)
+        .await
+        .map_err(|e| {
+            log_error(e);
+            error::ErrorInternalServerError("")
+        })?;
+
+    // Batch reads come back in no particular order.
+    for (doc_id, last_viewed_at) in views.into_iter() {
+        if let Some(i) = documents.iter().position(|document| document.id == doc_id) {
+            response.documents.push(ViewedDocument {
+                document: Some(documents.swap_remove(i)),
+                last_viewed_at: last_viewed_at.to_string(),
+            });
+        }
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ot::writing_proto::DocumentSharingPermission;
+
+    use crate::ids::{Id, IdType};
+    use crate::testing::fixtures::DocumentFixture;
+    use crate::testing::utils::TestDynamoDb;
+    use crate::users::UserRole;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[tokio::test]
+    async fn test_list_recently_viewed() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let org_id = Id::new(IdType::Organization);
+        let user_id = Id::new(IdType::User);
+        let session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let first_doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_org_level_sharing_permission(DocumentSharingPermission::CanView);
+        let second_doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_created_by_user_id(&user_id);
+        // Viewed once, but no longer shared with the user.
+        let unshared_doc = DocumentFixture::new().with_org_id(&org_id);
+        for doc in [&first_doc, &second_doc, &unshared_doc].iter() {
+            doc.create(&db.dynamodb_client).await;
+        }
+
+        let start = Utc::now() - chrono::Duration::hours(1);
+        let view = |doc: &DocumentFixture, minutes: i64| {
+            let doc_id = doc.doc_id.as_str().to_string();
+            let dynamodb_client = &db.dynamodb_client;
+            let session_user = &session_user;
+            async move {
+                record_document_view(
+                    dynamodb_client,
+                    session_user,
+                    &doc_id,
+                    start + chrono::Duration::minutes(minutes),
+                )
+                .await
+            }
+        };
+        view(&first_doc, 0).await?;
+        view(&unshared_doc, 1).await?;
+        view(&second_doc, 10).await?;
+        // Too soon after the last view to be recorded.
+        view(&second_doc, 12).await?;
+        view(&first_doc, 20).await?;
+
+        let list = |viewed_before_date_time: &str, limit: i32| {
+            let request = ListRecentlyViewedRequest {
+                viewed_before_date_time: viewed_before_date_time.to_string(),
+                limit,
+            };
+            let dynamodb_client = &db.dynamodb_client;
+            let session_user = &session_user;
+            async move { list_recently_viewed(dynamodb_client, session_user, &request).await }
+        };
+        let doc_ids = |response: &ListRecentlyViewedResponse| -> Vec<String> {
+            response
+                .documents
+                .iter()
+                .map(|viewed| viewed.document.as_ref().unwrap().id.clone())
+                .collect()
+        };
+
+        let response = list("", 0).await?;
+        assert_eq!(
+            doc_ids(&response),
+            vec![first_doc.doc_id.as_str(), second_doc.doc_id.as_str()]
+        );
+        assert_eq!(
+            response.documents[1].last_viewed_at,
+            time::date_time_iso_str(&(start + chrono::Duration::minutes(10)))
+        );
+        assert!(response.next_viewed_before_date_time.is_empty());
+
+        // Paging.
+        let response = list("", 1).await?;
+        assert_eq!(doc_ids(&response), vec![first_doc.doc_id.as_str()]);
+        let response = list(&response.next_viewed_before_date_time, 1).await?;
+        assert_eq!(doc_ids(&response), vec![second_doc.doc_id.as_str()]);
+
+        // Another user's views are their own.
+        let other_user = SessionUser {
+            user_id: Id::new(IdType::User),
+            org_id: org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let response = list_recently_viewed(
+            &db.dynamodb_client,
+            &other_user,
+            &ListRecentlyViewedRequest::default(),
+        )
+        .await?;
+        assert!(response.documents.is_empty());
+        Ok(())
+    }
+}
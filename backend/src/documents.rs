@@ -1,30 +1,57 @@
+use std::collections::{HashMap, HashSet};
+
 use actix_web::error;
 use bytes::Bytes;
+use futures::future::BoxFuture;
 use prost::Message;
 use rusoto_core::RusotoError;
 use rusoto_dynamodb::{
-    DynamoDb, DynamoDbClient, PutItemError, PutItemInput, QueryInput, UpdateItemError,
+    AttributeValue, GetItemInput, PutItemError, PutItemInput, QueryInput, UpdateItemError,
     UpdateItemInput,
 };
 
 use ot::writing_proto::{
     submit_document_change_set_response::ResponseCode, ChangeSet, CreateDocumentRequest,
-    CreateDocumentResponse, Document, DocumentRevision, DocumentSharingPermission,
+    CreateDocumentResponse, DebugDecodeRevisionRequest, DebugDecodeRevisionResponse,
+    DiagnoseDocumentRevisionsRequest, DiagnoseDocumentRevisionsResponse, Document,
+    DocumentPermission, DocumentRevision, DocumentSharingPermission, GetDocumentExcerptRequest,
+    GetDocumentExcerptResponse, GetDocumentHeadRequest, GetDocumentHeadResponse,
     GetDocumentRequest, GetDocumentResponse, GetDocumentRevisionsRequest,
-    GetDocumentRevisionsResponse, ListMyDocumentsRequest, ListMyDocumentsResponse,
-    SubmitDocumentChangeSetRequest, SubmitDocumentChangeSetResponse, UpdateDocumentTitleRequest,
-    UpdateDocumentTitleResponse,
+    GetDocumentRevisionsResponse, GetMyPermissionsRequest, GetMyPermissionsResponse,
+    ListMyDocumentsRequest, ListMyDocumentsResponse, RevisionDiagnostics, RevisionSignature,
+    SearchDocumentTitlesRequest, SearchDocumentTitlesResponse, SubmitDocumentChangeSetRequest,
+    SubmitDocumentChangeSetResponse, TransferOwnershipRequest, TransferOwnershipResponse,
+    UpdateDocumentTitleRequest, UpdateDocumentTitleResponse,
 };
+use ot::OtError;
 
-use crate::dynamodb::{av_b, av_get_b, av_get_n, av_get_s, av_map, av_n, av_s, table_name};
-use crate::http::SessionUser;
+use crate::document_stats;
+use crate::dynamodb::{
+    self, av_b, av_get_b, av_get_n, av_get_s, av_map, av_n, av_s, table_name, DynamoDbClient,
+};
+use crate::http::{Requester, SessionUser};
 use crate::ids::{Id, IdType};
+use crate::permission_cache::PermissionCache;
+use crate::protected_ranges::{self, ChangeSetCheck};
+use crate::rate_limiter::RateLimiter;
+use crate::revision_signatures;
+use crate::revision_stream::{CommittedRevision, RevisionStreamHandler};
+use crate::share_tokens;
+use crate::starred_documents;
+use crate::users::{self, UserRole};
 use crate::utils::{proto, time};
 
+/// `get_document_excerpt` includes at most this many UTF-16 code units on each side of the offset.
+pub const MAX_EXCERPT_RADIUS: i64 = 1000;
+
 /// Create a new document with the given title in a given org.
 ///
 /// If the title is empty, we use "Untitled Document" as the new title.
 ///
+/// If an encryption key fingerprint is given, the document is end-to-end encrypted: clients must
+/// submit its change sets encrypted with that key. If the fingerprint is not a hex-encoded SHA-256
+/// hash, returns 400 Bad Request.
+///
 /// If the session user does not have permission to create the document in this org, returns 403
 /// Forbidden.
 ///
@@ -50,22 +77,33 @@ pub async fn create_document(
     } else {
         &request.title
     };
+    let fingerprint = &request.encryption_key_fingerprint;
+    if !fingerprint.is_empty() && !is_valid_encryption_key_fingerprint(fingerprint) {
+        return Err(error::ErrorBadRequest(""));
+    }
     let doc_id = Id::new(IdType::Document);
     let now = time::date_time_iso_str(&chrono::Utc::now());
+    let mut item = av_map(&[
+        av_s("id", doc_id.as_str()),
+        av_s("org_id", session_user.org_id.as_str()),
+        av_s("title", title),
+        av_s("title_lc", &title_search_key(title)),
+        av_s("created_by_user_id", session_user.user_id.as_str()),
+        av_s("owner_user_id", session_user.user_id.as_str()),
+        av_n(
+            "org_level_sharing_permission",
+            request.org_level_sharing_permission as i32,
+        ),
+        av_s("created_at", &now),
+        av_s("updated_at", &now),
+    ]);
+    if !fingerprint.is_empty() {
+        let (key, value) = av_s("encryption_key_fingerprint", fingerprint);
+        item.insert(key, value);
+    }
     let input = PutItemInput {
         table_name: table_name("documents"),
-        item: av_map(&[
-            av_s("id", doc_id.as_str()),
-            av_s("org_id", session_user.org_id.as_str()),
-            av_s("title", title),
-            av_s("created_by_user_id", session_user.user_id.as_str()),
-            av_n(
-                "org_level_sharing_permission",
-                request.org_level_sharing_permission as i32,
-            ),
-            av_s("created_at", &now),
-            av_s("updated_at", &now),
-        ]),
+        item,
         ..Default::default()
     };
     dynamodb_client.put_item(input).await.map_err(|e| {
@@ -78,17 +116,22 @@ pub async fn create_document(
 }
 
 /// Get metadata about the document, like its title, org-level sharing permissions, etc.
+///
+/// Access is granted either through the session user's permissions or through a share token. A
+/// request with a valid share token does not need a session user.
 pub async fn get_document(
     dynamodb_client: &DynamoDbClient,
-    session_user: &SessionUser,
+    session_user: Option<&SessionUser>,
     request: &GetDocumentRequest,
 ) -> actix_web::Result<GetDocumentResponse> {
-    let document = get_document_if_some_permission_valid(
-        &dynamodb_client,
-        &session_user,
+    let document = get_document_if_some_access_valid(
+        dynamodb_client,
+        session_user,
         &request.doc_id,
+        &request.share_token,
         &[
             DocumentSharingPermission::CanView,
+            DocumentSharingPermission::CanComment,
             DocumentSharingPermission::CanEdit,
         ],
     )
@@ -98,11 +141,160 @@ pub async fn get_document(
     })
 }
 
+/// Get the number of the document's last revision, along with its title and when it was last
+/// updated. Clients call this to check whether anything changed before reading revisions.
+///
+/// There is no cached head record, so this reads the document item and the last revision's
+/// number, which is one item either way.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If the session user does not have permission to read the document, returns 403 Forbidden. A
+/// request with a valid share token does not need a session user. If there is neither, returns 401
+/// Unauthorized.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn get_document_head(
+    dynamodb_client: &DynamoDbClient,
+    session_user: Option<&SessionUser>,
+    request: &GetDocumentHeadRequest,
+) -> actix_web::Result<GetDocumentHeadResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [get_document_head] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    let document = get_document_if_some_access_valid(
+        dynamodb_client,
+        session_user,
+        &request.doc_id,
+        &request.share_token,
+        &[
+            DocumentSharingPermission::CanView,
+            DocumentSharingPermission::CanComment,
+            DocumentSharingPermission::CanEdit,
+        ],
+    )
+    .await?;
+    let last_revision_number = get_last_revision_number(dynamodb_client, &request.doc_id)
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    Ok(GetDocumentHeadResponse {
+        last_revision_number,
+        title: document.title,
+        updated_at: document.updated_at,
+    })
+}
+
+/// Get the text around an offset into the document at a revision: up to `request.radius` UTF-16
+/// code units on each side of it. Comments and notifications use it to quote the place they are
+/// about.
+///
+/// If `request.revision_number` is set, the excerpt is read from that revision, or from the last
+/// revision if the document has fewer. Otherwise, it is read from the last revision.
+///
+/// The excerpt's bounds are clamped to the document, and widened where they would split a
+/// surrogate pair, so the excerpt is always valid UTF-16. The response says what they ended up
+/// being.
+///
+/// If the offset is outside the document, the radius is negative or more than
+/// `MAX_EXCERPT_RADIUS`, or the document is end-to-end encrypted, returns 400 Bad Request.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If the session user does not have permission to read the document, returns 403 Forbidden. A
+/// request with a valid share token does not need a session user. If there is neither, returns 401
+/// Unauthorized.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn get_document_excerpt(
+    dynamodb_client: &DynamoDbClient,
+    session_user: Option<&SessionUser>,
+    request: &GetDocumentExcerptRequest,
+) -> actix_web::Result<GetDocumentExcerptResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [get_document_excerpt] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    if request.revision_number < 0
+        || request.offset < 0
+        || request.radius < 0
+        || request.radius > MAX_EXCERPT_RADIUS
+    {
+        return Err(error::ErrorBadRequest(""));
+    }
+    let document = get_document_if_some_access_valid(
+        dynamodb_client,
+        session_user,
+        &request.doc_id,
+        &request.share_token,
+        &[
+            DocumentSharingPermission::CanView,
+            DocumentSharingPermission::CanComment,
+            DocumentSharingPermission::CanEdit,
+        ],
+    )
+    .await?;
+    if !document.encryption_key_fingerprint.is_empty() {
+        return Err(error::ErrorBadRequest(""));
+    }
+    let (change_sets, revision_number) =
+        get_change_sets(dynamodb_client, &request.doc_id, 0, request.revision_number)
+            .await
+            .map_err(|e| {
+                log_error(e.to_string());
+                error::ErrorInternalServerError("")
+            })?;
+    let value = ot::compose_iter(&change_sets)
+        .and_then(|snapshot| ot::apply_slice(&[], &snapshot))
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    let offset = request.offset as usize;
+    if offset > value.len() {
+        return Err(error::ErrorBadRequest(""));
+    }
+    let radius = request.radius as usize;
+    let mut start = offset.saturating_sub(radius);
+    let mut end = (offset + radius).min(value.len());
+    if start > 0 && is_low_surrogate(value[start]) {
+        start -= 1;
+    }
+    if end < value.len() && is_low_surrogate(value[end]) {
+        end += 1;
+    }
+    Ok(GetDocumentExcerptResponse {
+        excerpt: String::from_utf16_lossy(&value[start..end]),
+        start_offset: start as i64,
+        end_offset: end as i64,
+        revision_number,
+    })
+}
+
+fn is_low_surrogate(c: u16) -> bool {
+    (0xDC00..=0xDFFF).contains(&c)
+}
+
 /// Read the next page of revisions from the document's revision log.
 ///
 /// If the document does not exist, returns 404 Not Found.
 ///
-/// If the session user does not have permission to read the document, returns 403 Forbidden.
+/// If the session user does not have permission to read the document, returns 403 Forbidden. A
+/// request with a valid share token does not need a session user. If there is neither, returns 401
+/// Unauthorized.
 ///
 /// If an internal server error occurs, returns 500 Internal Server Error.
 ///
@@ -110,17 +302,26 @@ pub async fn get_document(
 /// number given by `request.after_revision_number`.
 ///
 /// If there are no more revisions, `end_of_revisions` is set in the response.
+///
+/// If `request.pinned_revision_number` is set, no revisions after it are returned, so that a reader
+/// paging through the log sees a stable document while edits continue. The response always says
+/// which revision the read is pinned to. Unpinned reads are pinned to the last revision at the time
+/// of the read.
 pub async fn get_document_revisions(
     dynamodb_client: &DynamoDbClient,
-    session_user: &SessionUser,
+    permission_cache: &PermissionCache,
+    session_user: Option<&SessionUser>,
     request: &GetDocumentRevisionsRequest,
 ) -> actix_web::Result<GetDocumentRevisionsResponse> {
-    get_document_if_some_permission_valid(
-        &dynamodb_client,
-        &session_user,
+    validate_some_access_cached(
+        dynamodb_client,
+        permission_cache,
+        session_user,
         &request.doc_id,
+        &request.share_token,
         &[
             DocumentSharingPermission::CanView,
+            DocumentSharingPermission::CanComment,
             DocumentSharingPermission::CanEdit,
         ],
     )
@@ -134,20 +335,44 @@ pub async fn get_document_revisions(
             request,
         );
     };
+    let pinned = request.pinned_revision_number > 0;
+    if pinned && request.pinned_revision_number <= request.after_revision_number {
+        return Ok(GetDocumentRevisionsResponse {
+            last_revision_number: 0,
+            revisions: Vec::new(),
+            end_of_revisions: true,
+            pinned_revision_number: request.pinned_revision_number,
+        });
+    }
+    let (key_condition_expression, expression_attribute_values) = if pinned {
+        (
+            "doc_id = :doc_id AND revision_number BETWEEN :first_revision_number \
+            AND :pinned_revision_number",
+            av_map(&[
+                av_s(":doc_id", &request.doc_id),
+                av_n(":first_revision_number", request.after_revision_number + 1),
+                av_n(":pinned_revision_number", request.pinned_revision_number),
+            ]),
+        )
+    } else {
+        (
+            "doc_id = :doc_id AND revision_number > :after_revision_number",
+            av_map(&[
+                av_s(":doc_id", &request.doc_id),
+                av_n(":after_revision_number", request.after_revision_number),
+            ]),
+        )
+    };
     let input = QueryInput {
         table_name: table_name("document_revisions"),
         // Need consistent read to make sure we wait for pending writes to the revision log to
         // finish. Prevents us from seeing gaps in the log.
         consistent_read: Some(true),
-        key_condition_expression: Some(String::from(
-            "doc_id = :doc_id AND revision_number > :after_revision_number",
-        )),
-        expression_attribute_values: Some(av_map(&[
-            av_s(":doc_id", &request.doc_id),
-            av_n(":after_revision_number", request.after_revision_number),
-        ])),
+        key_condition_expression: Some(String::from(key_condition_expression)),
+        expression_attribute_values: Some(expression_attribute_values),
         projection_expression: Some(String::from(
-            "author_user_id, revision_number, change_set, committed_at",
+            "author_user_id, author_display_name, revision_number, change_set, \
+            encrypted_change_set, committed_at, signing_key_id, signature, client_id, session_id",
         )),
         ..Default::default()
     };
@@ -159,46 +384,370 @@ pub async fn get_document_revisions(
         last_revision_number: 0,
         revisions: Vec::new(),
         end_of_revisions: output.last_evaluated_key.is_none(),
+        pinned_revision_number: request.pinned_revision_number,
     };
-    if output.items.is_none() {
-        return Ok(response);
+    for item in output.items.unwrap_or_default().iter() {
+        let revision = document_revision_from_item(&request.doc_id, item).map_err(|e| {
+            log_error(e);
+            error::ErrorInternalServerError("")
+        })?;
+        response.last_revision_number = revision.revision_number;
+        response.revisions.push(revision);
     }
-    let items = output.items.unwrap();
-    if items.is_empty() {
-        return Ok(response);
+
+    if !pinned {
+        // When we read to the end, the last revision we read is the last one. Otherwise, look it
+        // up, so that the reader can pin the rest of its reads to it.
+        response.pinned_revision_number = if response.end_of_revisions {
+            std::cmp::max(response.last_revision_number, request.after_revision_number)
+        } else {
+            get_last_revision_number(dynamodb_client, &request.doc_id)
+                .await
+                .map_err(|e| {
+                    log_error(e.to_string());
+                    error::ErrorInternalServerError("")
+                })?
+        };
     }
 
-    let missing_field_error = || {
-        log_error("document_revision is missing a field".to_string());
-        error::ErrorInternalServerError("")
+    Ok(response)
+}
+
+/// Returns the change sets of the document's revisions after the given revision number, in order,
+/// along with the number of the last revision. If there are none, the number is
+/// `after_revision_number`. If `pinned_revision_number` is positive, no revisions after it are
+/// returned.
+pub async fn get_change_sets(
+    dynamodb_client: &DynamoDbClient,
+    doc_id: &str,
+    after_revision_number: i64,
+    pinned_revision_number: i64,
+) -> anyhow::Result<(Vec<ChangeSet>, i64)> {
+    let pinned_revision_number = if pinned_revision_number > 0 {
+        pinned_revision_number
+    } else {
+        i64::MAX
     };
-    for item in items.into_iter() {
-        let author_user_id = av_get_s(&item, "author_user_id").ok_or_else(missing_field_error)?;
-        let revision_number = av_get_n(&item, "revision_number").ok_or_else(missing_field_error)?;
-        let change_set_binary = av_get_b(&item, "change_set").ok_or_else(missing_field_error)?;
-        let committed_at = av_get_s(&item, "committed_at").ok_or_else(missing_field_error)?;
-        let change_set = ChangeSet::decode(&change_set_binary[..]).map_err(|e| {
-            log_error(e.to_string());
-            error::ErrorInternalServerError("")
+    if pinned_revision_number <= after_revision_number {
+        return Ok((Vec::new(), after_revision_number));
+    }
+    let items = dynamodb::query_all_items(
+        dynamodb_client,
+        QueryInput {
+            table_name: table_name("document_revisions"),
+            consistent_read: Some(true),
+            key_condition_expression: Some(String::from(
+                "doc_id = :doc_id AND revision_number BETWEEN :first_revision_number \
+                AND :pinned_revision_number",
+            )),
+            expression_attribute_values: Some(av_map(&[
+                av_s(":doc_id", doc_id),
+                av_n(":first_revision_number", after_revision_number + 1),
+                av_n(":pinned_revision_number", pinned_revision_number),
+            ])),
+            ..Default::default()
+        },
+    )
+    .await?;
+    let mut change_sets = Vec::with_capacity(items.len());
+    let mut last_revision_number = after_revision_number;
+    for item in items.iter() {
+        let revision = document_revision_from_item(doc_id, item).map_err(anyhow::Error::msg)?;
+        let change_set = revision.change_set.ok_or_else(|| {
+            anyhow::anyhow!("revision {} has no change set", revision.revision_number)
         })?;
-        response.revisions.push(DocumentRevision {
+        change_sets.push(change_set);
+        last_revision_number = revision.revision_number;
+    }
+    Ok((change_sets, last_revision_number))
+}
+
+/// Returns the document's encryption key fingerprint, which is empty unless the document is
+/// end-to-end encrypted, or `None` if the document does not exist.
+///
+/// The fingerprint never changes, but `validate_some_access_cached` does not read the document, so
+/// it is read here.
+async fn get_encryption_key_fingerprint(
+    dynamodb_client: &DynamoDbClient,
+    doc_id: &str,
+) -> anyhow::Result<Option<String>> {
+    let output = dynamodb_client
+        .get_item(GetItemInput {
+            table_name: table_name("documents"),
+            key: av_map(&[av_s("id", doc_id)]),
+            projection_expression: Some(String::from("id, encryption_key_fingerprint")),
+            ..Default::default()
+        })
+        .await?;
+    Ok(output.item.map(|item| {
+        av_get_s(&item, "encryption_key_fingerprint")
+            .unwrap_or_default()
+            .to_string()
+    }))
+}
+
+/// Returns the number of the document's last revision, or 0 if it has none.
+pub async fn get_last_revision_number(
+    dynamodb_client: &DynamoDbClient,
+    doc_id: &str,
+) -> anyhow::Result<i64> {
+    let input = QueryInput {
+        table_name: table_name("document_revisions"),
+        consistent_read: Some(true),
+        key_condition_expression: Some(String::from("doc_id = :doc_id")),
+        expression_attribute_values: Some(av_map(&[av_s(":doc_id", doc_id)])),
+        projection_expression: Some(String::from("revision_number")),
+        scan_index_forward: Some(false),
+        limit: Some(1),
+        ..Default::default()
+    };
+    let output = dynamodb_client.query(input).await?;
+    let last_revision_number = output
+        .items
+        .unwrap_or_default()
+        .first()
+        .and_then(|item| av_get_n(item, "revision_number"))
+        .unwrap_or(0);
+    Ok(last_revision_number)
+}
+
+/// Reads a revision from a `document_revisions` item. Returns an error message if the item is
+/// malformed.
+pub fn document_revision_from_item(
+    doc_id: &str,
+    item: &HashMap<String, AttributeValue>,
+) -> Result<DocumentRevision, String> {
+    let missing_field_error = || "document_revision is missing a field".to_string();
+    let author_user_id = av_get_s(item, "author_user_id").ok_or_else(missing_field_error)?;
+    let author_display_name = av_get_s(item, "author_display_name").unwrap_or("");
+    let revision_number = av_get_n(item, "revision_number").ok_or_else(missing_field_error)?;
+    let committed_at = av_get_s(item, "committed_at").ok_or_else(missing_field_error)?;
+    let signature = match (
+        av_get_s(item, "signing_key_id"),
+        av_get_b(item, "signature"),
+    ) {
+        (Some(key_id), Some(signature)) => Some(RevisionSignature {
+            key_id: key_id.to_string(),
+            signature: signature.to_vec(),
+        }),
+        _ => None,
+    };
+    // Revisions of encrypted documents are passed through to the client as they are.
+    let (change_set, encrypted_change_set) = match av_get_b(item, "encrypted_change_set") {
+        Some(encrypted_change_set) => (None, encrypted_change_set.to_vec()),
+        None => {
+            let change_set_binary = av_get_b(item, "change_set").ok_or_else(missing_field_error)?;
+            let change_set =
+                ChangeSet::decode(&change_set_binary[..]).map_err(|e| e.to_string())?;
+            (Some(change_set), Vec::new())
+        }
+    };
+    Ok(DocumentRevision {
+        doc_id: doc_id.to_string(),
+        author_user_id: author_user_id.to_string(),
+        author_display_name: author_display_name.to_string(),
+        revision_number,
+        change_set,
+        encrypted_change_set,
+        committed_at: String::from(committed_at),
+        signature,
+        client_id: av_get_s(item, "client_id").unwrap_or("").to_string(),
+        session_id: av_get_s(item, "session_id").unwrap_or("").to_string(),
+    })
+}
+
+/// Lint the next page of revisions from the document's revision log with `ot::diagnose`. Used to
+/// triage malformed change sets that users report.
+///
+/// If the session user is not an org admin, returns 403 Forbidden.
+///
+/// The server cannot read the change sets of end-to-end encrypted documents. If the page includes
+/// an encrypted revision, returns 400 Bad Request.
+///
+/// Otherwise, behaves like `get_document_revisions`, except that the response only includes
+/// revisions that have at least one diagnostic.
+pub async fn diagnose_document_revisions(
+    dynamodb_client: &DynamoDbClient,
+    permission_cache: &PermissionCache,
+    session_user: &SessionUser,
+    request: &DiagnoseDocumentRevisionsRequest,
+) -> actix_web::Result<DiagnoseDocumentRevisionsResponse> {
+    if session_user.user_role != UserRole::OrgAdmin {
+        return Err(error::ErrorForbidden(""));
+    }
+    let revisions_response = get_document_revisions(
+        dynamodb_client,
+        permission_cache,
+        Some(session_user),
+        &GetDocumentRevisionsRequest {
             doc_id: request.doc_id.clone(),
-            author_user_id: author_user_id.to_string(),
-            revision_number,
-            change_set: Some(change_set),
-            committed_at: String::from(committed_at),
-        });
-        response.last_revision_number = revision_number;
+            after_revision_number: request.after_revision_number,
+            ..Default::default()
+        },
+    )
+    .await?;
+    if revisions_response
+        .revisions
+        .iter()
+        .any(|revision| !revision.encrypted_change_set.is_empty())
+    {
+        return Err(error::ErrorBadRequest(""));
     }
+    let revisions = revisions_response
+        .revisions
+        .iter()
+        .filter_map(|revision| {
+            let diagnostics = ot::diagnose(revision.change_set.as_ref()?);
+            if diagnostics.is_empty() {
+                return None;
+            }
+            Some(RevisionDiagnostics {
+                revision_number: revision.revision_number,
+                diagnostics: diagnostics.iter().map(ot::Diagnostic::to_string).collect(),
+                author_user_id: revision.author_user_id.clone(),
+                client_id: revision.client_id.clone(),
+                session_id: revision.session_id.clone(),
+            })
+        })
+        .collect();
+    Ok(DiagnoseDocumentRevisionsResponse {
+        last_revision_number: revisions_response.last_revision_number,
+        revisions,
+        end_of_revisions: revisions_response.end_of_revisions,
+    })
+}
 
+/// `debug_decode_revision` truncates inserts longer than this.
+const DEBUG_MAX_INSERT_CHARS: usize = 200;
+
+/// Render one stored revision for a person to read: its change set, one op at a time, and
+/// everything stored with it. Lets support engineers inspect a suspicious revision without pulling
+/// the binary item out of DynamoDB.
+///
+/// If the session user is not an org admin, returns 403 Forbidden.
+///
+/// If the document or the revision does not exist, returns 404 Not Found.
+///
+/// The server cannot read the change sets of end-to-end encrypted documents, so for those, only
+/// what is stored alongside the change set is rendered. A change set that does not decode is
+/// rendered as the decoding error.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn debug_decode_revision(
+    dynamodb_client: &DynamoDbClient,
+    permission_cache: &PermissionCache,
+    session_user: &SessionUser,
+    request: &DebugDecodeRevisionRequest,
+) -> actix_web::Result<DebugDecodeRevisionResponse> {
+    if session_user.user_role != UserRole::OrgAdmin {
+        return Err(error::ErrorForbidden(""));
+    }
+    validate_some_access_cached(
+        dynamodb_client,
+        permission_cache,
+        Some(session_user),
+        &request.doc_id,
+        "",
+        &[
+            DocumentSharingPermission::CanView,
+            DocumentSharingPermission::CanComment,
+            DocumentSharingPermission::CanEdit,
+        ],
+    )
+    .await?;
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [debug_decode_revision] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    let output = dynamodb_client
+        .get_item(GetItemInput {
+            table_name: table_name("document_revisions"),
+            key: av_map(&[
+                av_s("doc_id", &request.doc_id),
+                av_n("revision_number", request.revision_number),
+            ]),
+            consistent_read: Some(true),
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    let item = output.item.ok_or_else(|| error::ErrorNotFound(""))?;
+    let mut response = DebugDecodeRevisionResponse {
+        revision_number: request.revision_number,
+        author_user_id: av_get_s(&item, "author_user_id").unwrap_or("").to_string(),
+        author_display_name: av_get_s(&item, "author_display_name")
+            .unwrap_or("")
+            .to_string(),
+        committed_at: av_get_s(&item, "committed_at").unwrap_or("").to_string(),
+        client_id: av_get_s(&item, "client_id").unwrap_or("").to_string(),
+        session_id: av_get_s(&item, "session_id").unwrap_or("").to_string(),
+        signing_key_id: av_get_s(&item, "signing_key_id").unwrap_or("").to_string(),
+        ..Default::default()
+    };
+    if let Some(encrypted_change_set) = av_get_b(&item, "encrypted_change_set") {
+        response.encrypted = true;
+        response.change_set_bytes = encrypted_change_set.len() as i64;
+        return Ok(response);
+    }
+    let change_set_binary = av_get_b(&item, "change_set").ok_or_else(|| {
+        log_error("document_revision is missing a field".to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    response.change_set_bytes = change_set_binary.len() as i64;
+    match ChangeSet::decode(&change_set_binary[..]) {
+        Ok(change_set) => {
+            response.summary = change_set.to_string();
+            response.change_set = change_set.fmt_compact(DEBUG_MAX_INSERT_CHARS).to_string();
+            response.diagnostics = ot::diagnose(&change_set)
+                .iter()
+                .map(ot::Diagnostic::to_string)
+                .collect();
+        }
+        Err(e) => response.change_set = format!("Could not decode change set: {}", e),
+    }
     Ok(response)
 }
 
+/// Counts a revision that the requester is about to commit to the document against their edit
+/// budget. If they have used it up, returns how long until they may commit again.
+pub fn acquire_edit_permit(
+    edit_rate_limiter: &RateLimiter,
+    requester: &Requester,
+    doc_id: &str,
+) -> Result<(), std::time::Duration> {
+    edit_rate_limiter.acquire(&format!("{}:{}", requester.id().as_str(), doc_id))
+}
+
+/// The longest client or session id that `submit_document_change_set` accepts.
+pub const MAX_CLIENT_ID_LEN: usize = 128;
+
 /// Submit a change set to be appended to a document's revision log.
 ///
 /// If the document does not exist, returns 404 Not Found.
 ///
 /// If the session user does not have permission to write to the document, returns 403 Forbidden.
+/// A share token that grants `CanEdit` lets users from any org, and guests, submit changes. Guests
+/// must always present a share token. Otherwise, they get 401 Unauthorized.
+///
+/// Exactly one of the change set and the encrypted change set must be given, and it must be the
+/// encrypted one if and only if the document is end-to-end encrypted. Otherwise, or if the change
+/// set contains an empty op, returns 400 Bad Request. The server cannot read encrypted change sets,
+/// so they are committed without being validated.
+///
+/// A revision signature is stored with the revision without being verified. If it is malformed,
+/// returns 400 Bad Request.
+///
+/// The optional client and session ids are stored with the revision, to trace problems back to the
+/// client that caused them. If either is longer than `MAX_CLIENT_ID_LEN` characters, returns 400
+/// Bad Request.
 ///
 /// If an internal server error occurs, returns 500 Internal Server Error.
 ///
@@ -208,49 +757,206 @@ pub async fn get_document_revisions(
 /// Otherwise, if the change is based on the latest revision, it will be appended to the end of the
 /// revision log. In this case, returns status code `Ack` and a list containing the document
 /// revision that was just appended to the revision log.
+///
+/// A change set that only retains characters would not change the document, so it is not
+/// appended. In this case, returns status code `Ack` and an empty list of revisions.
+///
+/// If the change set modifies one of the document's protected ranges, and the requester is not the
+/// owner of the document, it is not appended either. In this case, returns status code
+/// `ProtectedRangeModified`. An encrypted change set cannot be checked, so it gets this status from
+/// anyone but the owner if the document has any protected ranges. See `protected_ranges`.
+///
+/// If the requester has already submitted `rate_limiter::EDIT_MAX_REVISIONS_PER_WINDOW` change sets
+/// to the document within the last window, returns status code `Throttled`, along with how long to
+/// wait before submitting again. Requests that fail with an error above do not count.
 pub async fn submit_document_change_set(
     dynamodb_client: &DynamoDbClient,
-    session_user: &SessionUser,
+    permission_cache: &PermissionCache,
+    edit_rate_limiter: &RateLimiter,
+    requester: &Requester,
     request: &SubmitDocumentChangeSetRequest,
 ) -> actix_web::Result<SubmitDocumentChangeSetResponse> {
-    get_document_if_some_permission_valid(
+    let org_id = validate_some_access_cached(
         dynamodb_client,
-        session_user,
+        permission_cache,
+        requester.session_user(),
         &request.doc_id,
+        &request.share_token,
         &[DocumentSharingPermission::CanEdit],
     )
     .await?;
     let log_error = |error_message: String| {
         log::error!(
             "Error occurred: \"{}\" [submit_document_change_set] \
-            [session_user: {:?}, request: {{org_id: {}, doc_id: {}, on_revision_number: {}}}]",
+            [requester: {:?}, request: {{doc_id: {}, on_revision_number: {}, change_set: {}}}]",
             error_message,
-            session_user,
-            session_user.org_id.as_str(),
+            requester,
             &request.doc_id,
             request.on_revision_number,
+            proto::change_set_for_log(request.change_set.as_ref()),
         );
     };
-    let change_set = request
-        .change_set
-        .as_ref()
-        .ok_or_else(|| error::ErrorBadRequest(""))?;
-    let change_set_binary = proto::encode_protobuf_message(change_set).map_err(|e| {
+    if let Some(signature) = &request.signature {
+        if !revision_signatures::is_well_formed(signature) {
+            return Err(error::ErrorBadRequest(""));
+        }
+    }
+    if request.client_id.chars().count() > MAX_CLIENT_ID_LEN
+        || request.session_id.chars().count() > MAX_CLIENT_ID_LEN
+    {
+        return Err(error::ErrorBadRequest(""));
+    }
+    // No client could apply ciphertext committed to a plaintext document, or read plaintext
+    // committed to an encrypted one.
+    let is_encrypted = !get_encryption_key_fingerprint(dynamodb_client, &request.doc_id)
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?
+        .ok_or_else(|| error::ErrorNotFound(""))?
+        .is_empty();
+    if is_encrypted != request.change_set.is_none() {
+        return Err(error::ErrorBadRequest(""));
+    }
+    let (change_set_attribute, change_set_binary) = match &request.change_set {
+        Some(_) if !request.encrypted_change_set.is_empty() => {
+            return Err(error::ErrorBadRequest(""));
+        }
+        Some(change_set) => {
+            // Every client that loads a revision with an empty op, a negative count or content
+            // that is not UTF-16 would fail to apply it, so reject it here rather than committing
+            // it.
+            if let Err(e) = ot::validate(change_set) {
+                log_error(e.to_string());
+                return Err(match e {
+                    OtError::EmptyOp { .. }
+                    | OtError::NegativeCount { .. }
+                    | OtError::LengthOverflow
+                    | OtError::InvalidInsertContent { .. } => error::ErrorBadRequest(""),
+                    _ => error::ErrorInternalServerError(""),
+                });
+            }
+            if ot::is_identity(change_set, None) {
+                // Nothing would change. Acknowledge without committing an empty revision.
+                return Ok(SubmitDocumentChangeSetResponse {
+                    response_code: ResponseCode::Ack.into(),
+                    last_revision_number: request.on_revision_number,
+                    revisions: Vec::new(),
+                    end_of_revisions: true,
+                    ..Default::default()
+                });
+            }
+            let change_set_binary = proto::encode_protobuf_message(change_set).map_err(|e| {
+                log_error(e.to_string());
+                error::ErrorBadRequest("")
+            })?;
+            ("change_set", Bytes::from(change_set_binary))
+        }
+        None if request.encrypted_change_set.is_empty() => {
+            return Err(error::ErrorBadRequest(""));
+        }
+        None => (
+            "encrypted_change_set",
+            Bytes::from(request.encrypted_change_set.clone()),
+        ),
+    };
+    // A runaway client could otherwise commit hundreds of revisions a second. Tell it to back off,
+    // rather than failing, so that it keeps its pending changes and submits them later.
+    if let Err(retry_after) = acquire_edit_permit(edit_rate_limiter, requester, &request.doc_id) {
+        return Ok(SubmitDocumentChangeSetResponse {
+            response_code: ResponseCode::Throttled.into(),
+            last_revision_number: request.on_revision_number,
+            retry_after_millis: retry_after.as_millis() as i64,
+            ..Default::default()
+        });
+    }
+    let check = match &request.change_set {
+        Some(change_set) => {
+            protected_ranges::check_change_set(
+                dynamodb_client,
+                requester,
+                &request.doc_id,
+                request.on_revision_number,
+                change_set,
+            )
+            .await
+        }
+        None => {
+            protected_ranges::check_encrypted_change_set(
+                dynamodb_client,
+                requester,
+                &request.doc_id,
+            )
+            .await
+        }
+    };
+    let protected_ranges_advance = match check.map_err(|e| {
         log_error(e.to_string());
-        error::ErrorBadRequest("")
-    })?;
-    let change_set_binary = Bytes::from(change_set_binary);
+        error::ErrorInternalServerError("")
+    })? {
+        ChangeSetCheck::Unprotected => None,
+        ChangeSetCheck::Allowed(advance) => Some(advance),
+        ChangeSetCheck::Modified => {
+            return Ok(SubmitDocumentChangeSetResponse {
+                response_code: ResponseCode::ProtectedRangeModified.into(),
+                last_revision_number: request.on_revision_number,
+                ..Default::default()
+            });
+        }
+    };
     let new_revision_number = request.on_revision_number + 1;
     let committed_at = time::date_time_iso_str(&chrono::Utc::now());
+    // Revisions record their author's name as of when they were committed, so that the history of
+    // the document reads the same after someone renames themselves. Guests have no user account,
+    // so their revisions are attributed to their guest id, along with the display name they chose
+    // when they joined.
+    let (author_user_id, author_display_name) = match requester {
+        Requester::User(session_user) => {
+            let name = users::get_user_name(dynamodb_client, &session_user.user_id)
+                .await
+                .map_err(|e| {
+                    log_error(e.to_string());
+                    error::ErrorInternalServerError("")
+                })?;
+            (session_user.user_id.as_str(), name)
+        }
+        Requester::Guest(guest_user) => (
+            guest_user.guest_id.as_str(),
+            guest_user.display_name.clone(),
+        ),
+    };
+    let mut item = av_map(&[
+        av_s("doc_id", &request.doc_id),
+        // For the org changefeed. See `org_revisions`.
+        av_s("org_id", &org_id),
+        av_s("author_user_id", author_user_id),
+        av_n("revision_number", new_revision_number),
+        av_b(change_set_attribute, change_set_binary),
+        av_s("committed_at", &committed_at),
+    ]);
+    if !author_display_name.is_empty() {
+        let (key, value) = av_s("author_display_name", &author_display_name);
+        item.insert(key, value);
+    }
+    // Like the display name, the client and session ids are only stored when they are given.
+    if !request.client_id.is_empty() {
+        let (key, value) = av_s("client_id", &request.client_id);
+        item.insert(key, value);
+    }
+    if !request.session_id.is_empty() {
+        let (key, value) = av_s("session_id", &request.session_id);
+        item.insert(key, value);
+    }
+    if let Some(signature) = &request.signature {
+        let (key, value) = av_s("signing_key_id", &signature.key_id);
+        item.insert(key, value);
+        let (key, value) = av_b("signature", Bytes::from(signature.signature.clone()));
+        item.insert(key, value);
+    }
     let input = PutItemInput {
         table_name: table_name("document_revisions"),
-        item: av_map(&[
-            av_s("doc_id", &request.doc_id),
-            av_s("author_user_id", session_user.user_id.as_str()),
-            av_n("revision_number", new_revision_number),
-            av_b("change_set", change_set_binary),
-            av_s("committed_at", &committed_at),
-        ]),
+        item,
         // Only succeed if key (doc_id, revision_number) does not already exist.
         condition_expression: Some(String::from(
             "attribute_not_exists(doc_id) AND attribute_not_exists(revision_number)",
@@ -259,35 +965,71 @@ pub async fn submit_document_change_set(
     };
     let result = dynamodb_client.put_item(input).await;
     match result {
-        Ok(_) => Ok(SubmitDocumentChangeSetResponse {
-            response_code: ResponseCode::Ack.into(),
-            last_revision_number: new_revision_number,
-            revisions: vec![DocumentRevision {
-                doc_id: request.doc_id.clone(),
-                author_user_id: session_user.user_id.as_str().to_string(),
-                revision_number: new_revision_number,
-                change_set: request.change_set.clone(),
-                committed_at,
-            }],
-            end_of_revisions: true,
-        }),
+        Ok(_) => {
+            if let (Some(advance), Some(change_set)) =
+                (protected_ranges_advance, &request.change_set)
+            {
+                // The revision is committed either way. The ranges are moved past it on the next
+                // check if this fails.
+                if let Err(e) = protected_ranges::advance(
+                    dynamodb_client,
+                    &request.doc_id,
+                    advance,
+                    change_set,
+                    new_revision_number,
+                )
+                .await
+                {
+                    log_error(e.to_string());
+                }
+            }
+            Ok(SubmitDocumentChangeSetResponse {
+                response_code: ResponseCode::Ack.into(),
+                last_revision_number: new_revision_number,
+                revisions: vec![DocumentRevision {
+                    doc_id: request.doc_id.clone(),
+                    author_user_id: author_user_id.to_string(),
+                    author_display_name,
+                    revision_number: new_revision_number,
+                    change_set: request.change_set.clone(),
+                    encrypted_change_set: request.encrypted_change_set.clone(),
+                    committed_at,
+                    signature: request.signature.clone(),
+                    client_id: request.client_id.clone(),
+                    session_id: request.session_id.clone(),
+                }],
+                end_of_revisions: true,
+                ..Default::default()
+            })
+        }
         Err(RusotoError::Service(PutItemError::ConditionalCheckFailed(_))) => {
             log::info!(
                 "Conditional check failed. Another revision was committed before ours. \
-                Getting new revisions. [request: {:?}]",
-                &request,
+                Getting new revisions. [request: {{doc_id: {}, on_revision_number: {}, \
+                change_set: {}}}]",
+                &request.doc_id,
+                request.on_revision_number,
+                proto::change_set_for_log(request.change_set.as_ref()),
             );
             let rev_request = GetDocumentRevisionsRequest {
                 doc_id: request.doc_id.clone(),
                 after_revision_number: request.on_revision_number,
+                share_token: request.share_token.clone(),
+                ..Default::default()
             };
-            let response =
-                get_document_revisions(dynamodb_client, session_user, &rev_request).await?;
+            let response = get_document_revisions(
+                dynamodb_client,
+                permission_cache,
+                requester.session_user(),
+                &rev_request,
+            )
+            .await?;
             Ok(SubmitDocumentChangeSetResponse {
                 response_code: ResponseCode::DiscoveredNewRevisions.into(),
                 last_revision_number: response.last_revision_number,
                 revisions: response.revisions,
                 end_of_revisions: response.end_of_revisions,
+                ..Default::default()
             })
         }
         Err(e) => {
@@ -297,6 +1039,54 @@ pub async fn submit_document_change_set(
     }
 }
 
+/// Keeps each document's `updated_at` at the time of its last revision, so that the document list
+/// shows recently edited documents first, along with the last revision's number and author for
+/// the list to show. Run by the revision stream processor.
+pub struct UpdatedAtStreamHandler;
+
+impl RevisionStreamHandler for UpdatedAtStreamHandler {
+    fn name(&self) -> &'static str {
+        "updated_at"
+    }
+
+    fn handle<'a>(
+        &'a self,
+        dynamodb_client: &'a DynamoDbClient,
+        revision: &'a CommittedRevision,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let input = UpdateItemInput {
+                table_name: table_name("documents"),
+                key: av_map(&[av_s("id", &revision.doc_id)]),
+                // Never move backwards, e.g. when a revision is handled again. Documents whose
+                // revisions were handled before the last revision number was recorded go by
+                // updated_at.
+                condition_expression: Some(String::from(
+                    "attribute_exists(id) AND (last_revision_number < :revision_number \
+                    OR (attribute_not_exists(last_revision_number) \
+                    AND updated_at < :committed_at))",
+                )),
+                update_expression: Some(String::from(
+                    "SET updated_at = :committed_at, last_revision_number = :revision_number, \
+                    last_edited_by_user_id = :author_user_id",
+                )),
+                expression_attribute_values: Some(av_map(&[
+                    av_s(":committed_at", &revision.committed_at),
+                    av_n(":revision_number", revision.revision_number),
+                    av_s(":author_user_id", &revision.author_user_id),
+                ])),
+                ..Default::default()
+            };
+            match dynamodb_client.update_item(input).await {
+                Ok(_) | Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => {
+                    Ok(())
+                }
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+}
+
 /// Update the title of a document.
 ///
 /// If new title is empty, we use "Untitled Document" as the new title.
@@ -308,11 +1098,13 @@ pub async fn submit_document_change_set(
 /// Upon success, returns default response.
 pub async fn update_document_title(
     dynamodb_client: &DynamoDbClient,
+    permission_cache: &PermissionCache,
     session_user: &SessionUser,
     request: &UpdateDocumentTitleRequest,
 ) -> actix_web::Result<UpdateDocumentTitleResponse> {
-    get_document_if_some_permission_valid(
+    validate_some_permission_cached(
         dynamodb_client,
+        permission_cache,
         session_user,
         &request.doc_id,
         &[DocumentSharingPermission::CanEdit],
@@ -336,10 +1128,13 @@ pub async fn update_document_title(
         table_name: table_name("documents"),
         key: av_map(&[av_s("id", &request.doc_id)]),
         condition_expression: Some(String::from("org_id = :org_id")),
-        update_expression: Some(String::from("SET title = :new_title")),
+        update_expression: Some(String::from(
+            "SET title = :new_title, title_lc = :new_title_lc",
+        )),
         expression_attribute_values: Some(av_map(&[
             av_s(":org_id", session_user.org_id.as_str()),
             av_s(":new_title", new_title),
+            av_s(":new_title_lc", &title_search_key(new_title)),
         ])),
         ..Default::default()
     };
@@ -357,6 +1152,139 @@ pub async fn update_document_title(
     }
 }
 
+/// Make another user in the document's org its owner. The owner and org admins may transfer a
+/// document. The previous owner keeps only the access that sharing gives them.
+///
+/// If the doc id or the new owner's user id is invalid, or the new owner is not in the session
+/// user's org, returns 400 Bad Request.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If the session user is neither the document's owner nor an org admin, returns 403 Forbidden.
+///
+/// If the owner changed while the transfer was in flight, returns 409 Conflict.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn transfer_ownership(
+    dynamodb_client: &DynamoDbClient,
+    permission_cache: &PermissionCache,
+    session_user: &SessionUser,
+    request: &TransferOwnershipRequest,
+) -> actix_web::Result<TransferOwnershipResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [transfer_ownership] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    match Id::parse(&request.doc_id) {
+        Some(id) if id.id_type == IdType::Document => {}
+        _ => return Err(error::ErrorBadRequest("")),
+    }
+    let new_owner_user_id = match Id::parse(&request.new_owner_user_id) {
+        Some(id) if id.id_type == IdType::User => id,
+        _ => return Err(error::ErrorBadRequest("")),
+    };
+    let document = get_document_if_some_permission_valid(
+        dynamodb_client,
+        session_user,
+        &request.doc_id,
+        &[
+            DocumentSharingPermission::CanView,
+            DocumentSharingPermission::CanComment,
+            DocumentSharingPermission::CanEdit,
+        ],
+    )
+    .await?;
+    if document.owner_user_id != session_user.user_id.as_str()
+        && session_user.user_role != UserRole::OrgAdmin
+    {
+        return Err(error::ErrorForbidden(""));
+    }
+
+    // The new owner must be in the document's org, which is the session user's org.
+    let output = dynamodb_client
+        .get_item(GetItemInput {
+            table_name: table_name("organization_users"),
+            key: av_map(&[
+                av_s("org_id", session_user.org_id.as_str()),
+                av_s("user_id", new_owner_user_id.as_str()),
+            ]),
+            projection_expression: Some(String::from("user_id")),
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    if output.item.is_none() {
+        return Err(error::ErrorBadRequest(""));
+    }
+
+    // Documents from before ownership could be transferred have no owner_user_id. Their owner is
+    // the creator.
+    let input = UpdateItemInput {
+        table_name: table_name("documents"),
+        key: av_map(&[av_s("id", &request.doc_id)]),
+        condition_expression: Some(String::from(
+            "org_id = :org_id AND (owner_user_id = :previous_owner_user_id OR \
+            (attribute_not_exists(owner_user_id) AND \
+            created_by_user_id = :previous_owner_user_id))",
+        )),
+        update_expression: Some(String::from("SET owner_user_id = :owner_user_id")),
+        expression_attribute_values: Some(av_map(&[
+            av_s(":org_id", session_user.org_id.as_str()),
+            av_s(":previous_owner_user_id", &document.owner_user_id),
+            av_s(":owner_user_id", new_owner_user_id.as_str()),
+        ])),
+        ..Default::default()
+    };
+    match dynamodb_client.update_item(input).await {
+        Ok(_) => {}
+        Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => {
+            return Err(error::ErrorConflict(""));
+        }
+        Err(e) => {
+            log_error(e.to_string());
+            return Err(error::ErrorInternalServerError(""));
+        }
+    }
+    // The previous owner's cached CanEdit no longer holds.
+    permission_cache.invalidate_document(&request.doc_id);
+    log::info!(
+        "Transferred ownership [transfer_ownership] [doc_id: {}, previous_owner_user_id: {}, \
+        owner_user_id: {}, transferred_by: {:?}]",
+        request.doc_id,
+        document.owner_user_id,
+        new_owner_user_id.as_str(),
+        session_user,
+    );
+    Ok(TransferOwnershipResponse {})
+}
+
+/// Titles are matched by their first this many characters. Keeps the search key well under
+/// DynamoDB's limit for sort keys.
+const TITLE_SEARCH_KEY_MAX_CHARS: usize = 200;
+
+/// The lowercase, truncated title stored as `title_lc`, which `search_document_titles` matches
+/// prefixes against.
+pub fn title_search_key(title: &str) -> String {
+    title
+        .to_lowercase()
+        .chars()
+        .take(TITLE_SEARCH_KEY_MAX_CHARS)
+        .collect()
+}
+
+/// Encryption key fingerprints are hex-encoded SHA-256 hashes of the key, computed by the client.
+fn is_valid_encryption_key_fingerprint(fingerprint: &str) -> bool {
+    fingerprint.len() == 64 && fingerprint.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
 /// Validates that the given session user has at least one of the given permissions.
 ///
 /// Examples of `permissions` arguments:
@@ -366,9 +1294,14 @@ pub async fn update_document_title(
 /// &[DocumentSharingPermission::CanEdit]
 /// ```
 ///
-/// Validate that the user has permission either to read or to edit a document.
+/// Validate that the user has permission to read a document, which every permission but `None`
+/// includes.
 /// ```
-/// &[DocumentSharingPermission::CanView, DocumentSharingPermission::CanEdit]
+/// &[
+///     DocumentSharingPermission::CanView,
+///     DocumentSharingPermission::CanComment,
+///     DocumentSharingPermission::CanEdit,
+/// ]
 /// ```
 ///
 /// If the document does not exist, returns 404 Not Found.
@@ -377,16 +1310,215 @@ pub async fn update_document_title(
 ///
 /// If an internal server error occurs, returns 500 Internal Server Error.
 ///
-/// If the user has at least one of the given permissions, returns `Ok(())`.
-async fn get_document_if_some_permission_valid(
+/// If the user has at least one of the given permissions, returns the document.
+pub async fn get_document_if_some_permission_valid(
     dynamodb_client: &DynamoDbClient,
     session_user: &SessionUser,
     doc_id: &str,
     permissions: &[DocumentSharingPermission],
 ) -> actix_web::Result<Document> {
+    let (document, _) =
+        get_document_and_granted_permission(dynamodb_client, session_user, doc_id, permissions)
+            .await?;
+    Ok(document)
+}
+
+/// Like `get_document_if_some_permission_valid`, but consults the permission cache first, and
+/// does not return the document.
+///
+/// Use this in hot paths like the sync loop that only need to know whether the user is allowed to
+/// proceed. Permissions granted by a DynamoDB check are added to the cache.
+async fn validate_some_permission_cached(
+    dynamodb_client: &DynamoDbClient,
+    permission_cache: &PermissionCache,
+    session_user: &SessionUser,
+    doc_id: &str,
+    permissions: &[DocumentSharingPermission],
+) -> actix_web::Result<()> {
+    if let Some(cached_permission) = permission_cache.get(session_user, doc_id) {
+        if permissions.contains(&cached_permission) {
+            return Ok(());
+        }
+    }
+    let (_, granted_permission) =
+        get_document_and_granted_permission(dynamodb_client, session_user, doc_id, permissions)
+            .await?;
+    permission_cache.insert(session_user, doc_id, granted_permission);
+    Ok(())
+}
+
+/// Validates access to a document through either a share token or the session user.
+///
+/// If a share token is given and grants one of the permissions, the document is returned no matter
+/// which org it belongs to. If the token does not grant access, we fall back to checking the
+/// session user's permissions.
+///
+/// If there is no session user, and no valid share token, returns 401 Unauthorized. Otherwise,
+/// returns the same errors as `get_document_if_some_permission_valid`.
+async fn get_document_if_some_access_valid(
+    dynamodb_client: &DynamoDbClient,
+    session_user: Option<&SessionUser>,
+    doc_id: &str,
+    share_token: &str,
+    permissions: &[DocumentSharingPermission],
+) -> actix_web::Result<Document> {
+    if !share_token.is_empty() {
+        match get_document_if_share_token_valid(dynamodb_client, share_token, doc_id, permissions)
+            .await
+        {
+            Ok(document) => return Ok(document),
+            Err(e) if session_user.is_none() || e.as_response_error().status_code() == 500 => {
+                return Err(e);
+            }
+            Err(_) => {}
+        }
+    }
+    match session_user {
+        Some(session_user) => {
+            get_document_if_some_permission_valid(
+                dynamodb_client,
+                session_user,
+                doc_id,
+                permissions,
+            )
+            .await
+        }
+        None => Err(error::ErrorUnauthorized("")),
+    }
+}
+
+/// Like `get_document_if_some_access_valid`, but checks the session user's permissions with
+/// `validate_some_permission_cached`. Share tokens are never cached, so that revoking one takes
+/// effect immediately.
+///
+/// Upon success, returns the id of the org that the document belongs to.
+pub async fn validate_some_access_cached(
+    dynamodb_client: &DynamoDbClient,
+    permission_cache: &PermissionCache,
+    session_user: Option<&SessionUser>,
+    doc_id: &str,
+    share_token: &str,
+    permissions: &[DocumentSharingPermission],
+) -> actix_web::Result<String> {
+    match session_user {
+        Some(session_user) if share_token.is_empty() => {
+            validate_some_permission_cached(
+                dynamodb_client,
+                permission_cache,
+                session_user,
+                doc_id,
+                permissions,
+            )
+            .await?;
+            // Session users can only access documents in their own org.
+            Ok(session_user.org_id.as_str().to_string())
+        }
+        _ => {
+            let document = get_document_if_some_access_valid(
+                dynamodb_client,
+                session_user,
+                doc_id,
+                share_token,
+                permissions,
+            )
+            .await?;
+            Ok(document.org_id)
+        }
+    }
+}
+
+/// Validates that the share token grants at least one of the given permissions on the document.
+///
+/// If the token is invalid, expired, revoked, or does not grant one of the permissions, returns
+/// 403 Forbidden.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+async fn get_document_if_share_token_valid(
+    dynamodb_client: &DynamoDbClient,
+    share_token: &str,
+    doc_id: &str,
+    permissions: &[DocumentSharingPermission],
+) -> actix_web::Result<Document> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [get_document_if_share_token_valid] \
+            [doc_id: {}, permissions: {:?}]",
+            error_message,
+            doc_id,
+            permissions,
+        );
+    };
+    let missing_field_error = || {
+        log_error("document is missing a field".to_string());
+        error::ErrorInternalServerError("")
+    };
+    let permission =
+        share_tokens::get_share_token_permission(dynamodb_client, share_token, doc_id).await?;
+    if !permissions.contains(&permission) {
+        return Err(error::ErrorForbidden(""));
+    }
+    let output = dynamodb_client
+        .get_item(GetItemInput {
+            table_name: table_name("documents"),
+            key: av_map(&[av_s("id", doc_id)]),
+            projection_expression: Some(String::from(
+                "org_id, title, created_by_user_id, owner_user_id, org_level_sharing_permission, \
+                created_at, updated_at, encryption_key_fingerprint, archived_at",
+            )),
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    let item = output.item.ok_or_else(|| error::ErrorNotFound(""))?;
+    Ok(Document {
+        id: doc_id.to_string(),
+        org_id: av_get_s(&item, "org_id")
+            .ok_or_else(missing_field_error)?
+            .to_string(),
+        title: av_get_s(&item, "title")
+            .ok_or_else(missing_field_error)?
+            .to_string(),
+        created_by_user_id: av_get_s(&item, "created_by_user_id")
+            .ok_or_else(missing_field_error)?
+            .to_string(),
+        owner_user_id: owner_user_id_from_item(&item)
+            .ok_or_else(missing_field_error)?
+            .to_string(),
+        org_level_sharing_permission: av_get_n(&item, "org_level_sharing_permission")
+            .ok_or_else(missing_field_error)?,
+        created_at: av_get_s(&item, "created_at")
+            .ok_or_else(missing_field_error)?
+            .to_string(),
+        updated_at: av_get_s(&item, "updated_at")
+            .ok_or_else(missing_field_error)?
+            .to_string(),
+        encryption_key_fingerprint: av_get_s(&item, "encryption_key_fingerprint")
+            .unwrap_or("")
+            .to_string(),
+        is_starred: false,
+        archived_at: av_get_s(&item, "archived_at").unwrap_or("").to_string(),
+        last_revision_number: 0,
+        word_count: 0,
+        last_edited_by_user_id: String::new(),
+    })
+}
+
+/// Does the work of `get_document_if_some_permission_valid`. Also returns the permission that
+/// satisfied the check. The owner of a document is treated as having `CanEdit`.
+async fn get_document_and_granted_permission(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    doc_id: &str,
+    permissions: &[DocumentSharingPermission],
+) -> actix_web::Result<(Document, DocumentSharingPermission)> {
     let log_error = |error_message: String| {
         log::error!(
-            "Error occurred: \"{}\" [get_document_if_some_permission_valid] \
+            "Error occurred: \"{}\" [get_document_and_granted_permission] \
             [session_user: {:?}, doc_id: {}, permissions: {:?}]",
             error_message,
             session_user,
@@ -405,7 +1537,8 @@ async fn get_document_if_some_permission_valid(
         key_condition_expression: Some(String::from("id = :doc_id")),
         filter_expression: Some(String::from("org_id = :org_id")),
         projection_expression: Some(String::from(
-            "title, created_by_user_id, org_level_sharing_permission, created_at, updated_at",
+            "title, created_by_user_id, owner_user_id, org_level_sharing_permission, created_at, \
+            updated_at, encryption_key_fingerprint, archived_at",
         )),
         expression_attribute_values: Some(av_map(&[
             av_s(":doc_id", doc_id),
@@ -432,6 +1565,9 @@ async fn get_document_if_some_permission_valid(
         created_by_user_id: av_get_s(item, "created_by_user_id")
             .ok_or_else(missing_field_error)?
             .to_string(),
+        owner_user_id: owner_user_id_from_item(item)
+            .ok_or_else(missing_field_error)?
+            .to_string(),
         org_level_sharing_permission: av_get_n(item, "org_level_sharing_permission")
             .ok_or_else(missing_field_error)?,
         created_at: av_get_s(item, "created_at")
@@ -440,12 +1576,20 @@ async fn get_document_if_some_permission_valid(
         updated_at: av_get_s(item, "updated_at")
             .ok_or_else(missing_field_error)?
             .to_string(),
+        encryption_key_fingerprint: av_get_s(item, "encryption_key_fingerprint")
+            .unwrap_or("")
+            .to_string(),
+        is_starred: false,
+        archived_at: av_get_s(item, "archived_at").unwrap_or("").to_string(),
+        last_revision_number: 0,
+        word_count: 0,
+        last_edited_by_user_id: String::new(),
     };
 
-    // - If I created this document, then I have permission.
+    // - If I own this document, then I have permission.
     // TODO(cliff): Is there anything bad about this rule? Seems pretty powerful.
-    if document.created_by_user_id == session_user.user_id.as_str() {
-        return Ok(document);
+    if document.owner_user_id == session_user.user_id.as_str() {
+        return Ok((document, DocumentSharingPermission::CanEdit));
     }
 
     // - If the document was shared with the entire org, check to see if that gave me
@@ -464,7 +1608,7 @@ async fn get_document_if_some_permission_valid(
         .iter()
         .any(|p| p == &org_level_sharing_permission);
     if found_permission_match {
-        return Ok(document);
+        return Ok((document, org_level_sharing_permission));
     }
 
     // - If the document was shared with me, check to see if that gave me permission.
@@ -503,7 +1647,7 @@ async fn get_document_if_some_permission_valid(
         })?;
     let found_permission_match = permissions.iter().any(|p| p == &sharing_permission);
     if found_permission_match {
-        Ok(document)
+        Ok((document, sharing_permission))
     } else {
         Err(error::ErrorForbidden(""))
     }
@@ -530,8 +1674,9 @@ pub async fn list_my_documents(
         key_condition_expression: Some(String::from(
             "created_by_user_id = :created_by_user_id AND updated_at < :updated_at",
         )),
+        // Archived documents are only listed by list_archived.
         filter_expression: Some(String::from(
-            "org_id = :org_id",
+            "org_id = :org_id AND attribute_not_exists(archived_at)",
         )),
         expression_attribute_values: Some(av_map(&[
             av_s(":created_by_user_id", session_user.user_id.as_str()),
@@ -539,7 +1684,9 @@ pub async fn list_my_documents(
             av_s(":updated_at", &request.updated_before_date_time),
         ])),
         projection_expression: Some(String::from(
-            "id, org_id, title, created_by_user_id, org_level_sharing_permission, created_at, updated_at"
+            "id, org_id, title, created_by_user_id, owner_user_id, org_level_sharing_permission, \
+            created_at, updated_at, encryption_key_fingerprint, archived_at, last_revision_number, \
+            last_edited_by_user_id",
         )),
         ..QueryInput::default()
     };
@@ -573,6 +1720,9 @@ pub async fn list_my_documents(
             created_by_user_id: av_get_s(&item, "created_by_user_id")
                 .ok_or_else(missing_field_error)?
                 .to_string(),
+            owner_user_id: owner_user_id_from_item(&item)
+                .ok_or_else(missing_field_error)?
+                .to_string(),
             org_level_sharing_permission: av_get_n(&item, "org_level_sharing_permission")
                 .ok_or_else(missing_field_error)?,
             created_at: av_get_s(&item, "created_at")
@@ -581,122 +1731,424 @@ pub async fn list_my_documents(
             updated_at: av_get_s(&item, "updated_at")
                 .ok_or_else(missing_field_error)?
                 .to_string(),
+            encryption_key_fingerprint: av_get_s(&item, "encryption_key_fingerprint")
+                .unwrap_or("")
+                .to_string(),
+            is_starred: false,
+            archived_at: av_get_s(&item, "archived_at").unwrap_or("").to_string(),
+            last_revision_number: av_get_n(&item, "last_revision_number").unwrap_or(0),
+            word_count: 0,
+            last_edited_by_user_id: av_get_s(&item, "last_edited_by_user_id")
+                .unwrap_or("")
+                .to_string(),
         });
     }
+    starred_documents::mark_starred(dynamodb_client, session_user, &mut response.documents)
+        .await
+        .map_err(|e| {
+            log_error(e);
+            error::ErrorInternalServerError("")
+        })?;
+    document_stats::set_word_counts(dynamodb_client, &mut response.documents)
+        .await
+        .map_err(|e| {
+            log_error(e);
+            error::ErrorInternalServerError("")
+        })?;
     if let Some(last_document) = response.documents.last().as_ref() {
         response.next_updated_before_date_time = last_document.updated_at.clone();
     }
     Ok(response)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    use std::collections::HashMap;
-    use std::ops::Sub;
+/// Upper bound on the number of doc ids accepted by `get_my_permissions`.
+const GET_MY_PERMISSIONS_MAX_DOC_IDS: usize = 1000;
 
-    use rusoto_dynamodb::AttributeValue;
+/// Look up the session user's effective permission on each of the given documents in one call.
+/// The app's document list uses this to show which documents the user can edit, which they can
+/// comment on, and which they can only view.
+///
+/// The effective permission is the strongest of: `CanEdit` if the user owns the document, the
+/// org-level sharing permission, and the permission explicitly shared with the user. Documents
+/// that do not exist or belong to another org get `None`, exactly like documents the user cannot
+/// access, so this endpoint cannot be used to probe for document ids.
+///
+/// If too many doc ids are given, or one of them is not a valid document id, returns 400 Bad
+/// Request.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+///
+/// Upon success, returns one permission per distinct doc id, in the order they were requested.
+pub async fn get_my_permissions(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &GetMyPermissionsRequest,
+) -> actix_web::Result<GetMyPermissionsResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [get_my_permissions] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    if request.doc_ids.len() > GET_MY_PERMISSIONS_MAX_DOC_IDS {
+        return Err(error::ErrorBadRequest(""));
+    }
+    let mut doc_ids: Vec<&str> = Vec::with_capacity(request.doc_ids.len());
+    let mut seen_doc_ids = HashSet::new();
+    for doc_id in request.doc_ids.iter() {
+        match Id::parse(doc_id) {
+            Some(id) if id.id_type == IdType::Document => {}
+            _ => return Err(error::ErrorBadRequest("")),
+        }
+        if seen_doc_ids.insert(doc_id.as_str()) {
+            doc_ids.push(doc_id.as_str());
+        }
+    }
+    let invalid_item_error = |error_message: &str| {
+        log_error(error_message.to_string());
+        error::ErrorInternalServerError("")
+    };
 
-    use ot::writing_proto::ChangeSet;
+    // 1. Read the documents. Documents in other orgs are treated as if they do not exist.
+    let mut permissions: HashMap<String, DocumentSharingPermission> = HashMap::new();
+    let keys = doc_ids
+        .iter()
+        .map(|doc_id| av_map(&[av_s("id", doc_id)]))
+        .collect();
+    let items = dynamodb::batch_get_all_items(
+        dynamodb_client,
+        &table_name("documents"),
+        keys,
+        "id, org_id, created_by_user_id, owner_user_id, org_level_sharing_permission",
+    )
+    .await
+    .map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    for item in items.iter() {
+        if av_get_s(item, "org_id") != Some(session_user.org_id.as_str()) {
+            continue;
+        }
+        let doc_id = av_get_s(item, "id").ok_or_else(|| invalid_item_error("missing id"))?;
+        let permission = if owner_user_id_from_item(item) == Some(session_user.user_id.as_str()) {
+            DocumentSharingPermission::CanEdit
+        } else {
+            av_get_n(item, "org_level_sharing_permission")
+                .and_then(DocumentSharingPermission::from_i32)
+                .ok_or_else(|| invalid_item_error("invalid org_level_sharing_permission"))?
+        };
+        permissions.insert(doc_id.to_string(), permission);
+    }
 
-    use crate::testing::utils::TestDynamoDb;
-    use crate::users::UserRole;
+    // 2. For documents where the user does not already have CanEdit, read the permissions that
+    //    were explicitly shared with the user.
+    add_user_sharing_permissions(dynamodb_client, session_user, &mut permissions)
+        .await
+        .map_err(|e| {
+            log_error(e);
+            error::ErrorInternalServerError("")
+        })?;
 
-    type TestResult = Result<(), Box<dyn std::error::Error>>;
+    Ok(GetMyPermissionsResponse {
+        permissions: doc_ids
+            .iter()
+            .map(|doc_id| DocumentPermission {
+                doc_id: doc_id.to_string(),
+                permission: permissions
+                    .get(*doc_id)
+                    .copied()
+                    .unwrap_or(DocumentSharingPermission::None)
+                    .into(),
+            })
+            .collect(),
+    })
+}
 
-    #[tokio::test]
-    async fn test_get_document_revisions() -> TestResult {
-        let db = TestDynamoDb::new().await;
+/// Raises each document's permission in `permissions` to the permission explicitly shared with the
+/// session user, if that is stronger. Documents that already have `CanEdit` are skipped.
+///
+/// On failure, returns a message to log.
+async fn add_user_sharing_permissions(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    permissions: &mut HashMap<String, DocumentSharingPermission>,
+) -> Result<(), String> {
+    let keys: Vec<_> = permissions
+        .iter()
+        .filter(|(_, permission)| **permission != DocumentSharingPermission::CanEdit)
+        .map(|(doc_id, _)| {
+            av_map(&[
+                av_s("doc_id", doc_id),
+                av_s("user_id", session_user.user_id.as_str()),
+            ])
+        })
+        .collect();
+    let items = dynamodb::batch_get_all_items(
+        dynamodb_client,
+        &table_name("document_user_sharing_permissions"),
+        keys,
+        "doc_id, org_id, sharing_permission",
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+    for item in items.iter() {
+        if av_get_s(item, "org_id") != Some(session_user.org_id.as_str()) {
+            continue;
+        }
+        let doc_id = av_get_s(item, "doc_id").ok_or("missing doc_id")?;
+        let sharing_permission = av_get_n(item, "sharing_permission")
+            .and_then(DocumentSharingPermission::from_i32)
+            .ok_or("invalid sharing_permission")?;
+        if let Some(permission) = permissions.get_mut(doc_id) {
+            if permission_strength(sharing_permission) > permission_strength(*permission) {
+                *permission = sharing_permission;
+            }
+        }
+    }
+    Ok(())
+}
 
-        // Create 2 different change sets.
-        let mut change_set1 = ChangeSet::new();
-        change_set1.insert("foo_bar");
-        let mut change_set2 = ChangeSet::new();
-        change_set2.retain(3);
-        change_set2.delete(4);
-        let change_set_bytes1 = Bytes::from(proto::encode_protobuf_message(&change_set1)?);
-        let change_set_bytes2 = Bytes::from(proto::encode_protobuf_message(&change_set2)?);
+/// Orders permissions from weakest to strongest. The enum's values do not, since `CanComment` was
+/// added after `CanEdit`.
+fn permission_strength(permission: DocumentSharingPermission) -> u8 {
+    match permission {
+        DocumentSharingPermission::None => 0,
+        DocumentSharingPermission::CanView => 1,
+        DocumentSharingPermission::CanComment => 2,
+        DocumentSharingPermission::CanEdit => 3,
+    }
+}
 
-        // Document 1:
-        let doc_id1 = Id::new(IdType::Document);
-        let org_id1 = Id::new(IdType::Organization);
-        let user_id1 = Id::new(IdType::User);
-        create_document(
-            &db.dynamodb_client,
-            DocParams {
-                doc_id: doc_id1.clone(),
-                org_id: org_id1.clone(),
-                created_by_user_id: user_id1.clone(),
-                org_level_sharing_permission: DocumentSharingPermission::CanEdit,
-            },
-        )
-        .await?;
-        let session_user1 = SessionUser {
-            user_id: user_id1.clone(),
-            org_id: org_id1.clone(),
-            user_role: UserRole::Default,
-        };
+const SEARCH_DOCUMENT_TITLES_DEFAULT_LIMIT: usize = 10;
+const SEARCH_DOCUMENT_TITLES_MAX_LIMIT: usize = 50;
 
-        // Document 2:
-        let doc_id2 = Id::new(IdType::Document);
-        let org_id2 = Id::new(IdType::Document);
-        let user_id2 = Id::new(IdType::User);
-        create_document(
-            &db.dynamodb_client,
-            DocParams {
-                doc_id: doc_id2.clone(),
-                org_id: org_id2.clone(),
-                created_by_user_id: user_id2.clone(),
-                org_level_sharing_permission: DocumentSharingPermission::CanEdit,
-            },
-        )
-        .await?;
+// Titles are read from the index in pages of this many. Most of a page may be documents the user
+// cannot access, so we read a few pages before giving up on filling the limit.
+const SEARCH_DOCUMENT_TITLES_PAGE_SIZE: i64 = 100;
+const SEARCH_DOCUMENT_TITLES_MAX_PAGES: usize = 5;
 
-        // Add revisions to Document 1 and 2.
-        let dt1 = chrono::Utc::now().sub(chrono::Duration::days(7));
-        let dt2 = chrono::Utc::now().sub(chrono::Duration::days(6));
-        let dt3 = chrono::Utc::now().sub(chrono::Duration::days(5));
+/// Find documents in the session user's org whose titles start with the given prefix, ignoring
+/// case. The document picker uses this for typeahead.
+///
+/// Only documents the session user can access are returned, in order of title. Archived documents
+/// are left out.
+///
+/// If the prefix is empty or longer than a title search key, returns 400 Bad Request.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+///
+/// Upon success, returns up to `request.limit` matching documents.
+pub async fn search_document_titles(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &SearchDocumentTitlesRequest,
+) -> actix_web::Result<SearchDocumentTitlesResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [search_document_titles] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    let prefix = request.prefix.to_lowercase();
+    if prefix.is_empty() || prefix.chars().count() > TITLE_SEARCH_KEY_MAX_CHARS {
+        return Err(error::ErrorBadRequest(""));
+    }
+    let limit = match request.limit {
+        limit if limit <= 0 => SEARCH_DOCUMENT_TITLES_DEFAULT_LIMIT,
+        limit => (limit as usize).min(SEARCH_DOCUMENT_TITLES_MAX_LIMIT),
+    };
 
-        let items: Vec<HashMap<String, AttributeValue>> = vec![
-            av_map(&[
-                av_s("doc_id", doc_id1.as_str()),
-                av_s("author_user_id", user_id1.as_str()),
-                av_n("revision_number", 1),
-                av_b("change_set", change_set_bytes1.clone()),
-                av_s("committed_at", &time::date_time_iso_str(&dt1)),
-            ]),
-            av_map(&[
-                av_s("doc_id", doc_id1.as_str()),
-                av_s("author_user_id", user_id2.as_str()),
-                av_n("revision_number", 2),
-                av_b("change_set", change_set_bytes2.clone()),
-                av_s("committed_at", &time::date_time_iso_str(&dt2)),
-            ]),
-            av_map(&[
-                av_s("doc_id", doc_id2.as_str()),
-                av_s("author_user_id", user_id2.as_str()),
-                av_n("revision_number", 1),
-                av_b("change_set", change_set_bytes1.clone()),
-                av_s("committed_at", &time::date_time_iso_str(&dt3)),
-            ]),
-        ];
-        for item in items.into_iter() {
-            let input = PutItemInput {
-                table_name: table_name("document_revisions"),
-                item,
-                ..Default::default()
-            };
-            db.dynamodb_client.put_item(input).await?;
+    let mut documents = Vec::new();
+    let mut exclusive_start_key = None;
+    for _ in 0..SEARCH_DOCUMENT_TITLES_MAX_PAGES {
+        let input = QueryInput {
+            table_name: table_name("documents"),
+            index_name: Some(String::from("org_id-title_lc-index")),
+            key_condition_expression: Some(String::from(
+                "org_id = :org_id AND begins_with(title_lc, :prefix)",
+            )),
+            filter_expression: Some(String::from("attribute_not_exists(archived_at)")),
+            expression_attribute_values: Some(av_map(&[
+                av_s(":org_id", session_user.org_id.as_str()),
+                av_s(":prefix", &prefix),
+            ])),
+            projection_expression: Some(String::from(
+                "id, org_id, title, created_by_user_id, owner_user_id, \
+                org_level_sharing_permission, created_at, updated_at, encryption_key_fingerprint, \
+                archived_at",
+            )),
+            limit: Some(SEARCH_DOCUMENT_TITLES_PAGE_SIZE),
+            exclusive_start_key,
+            ..QueryInput::default()
+        };
+        let output = dynamodb_client.query(input).await.map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+        let page = output
+            .items
+            .unwrap_or_default()
+            .iter()
+            .map(document_from_item)
+            .collect::<Option<Vec<Document>>>()
+            .ok_or_else(|| {
+                log_error("document is missing a field".to_string());
+                error::ErrorInternalServerError("")
+            })?;
+
+        let page = filter_accessible_documents(dynamodb_client, session_user, page)
+            .await
+            .map_err(|e| {
+                log_error(e);
+                error::ErrorInternalServerError("")
+            })?;
+        documents.extend(page);
+
+        if documents.len() >= limit || output.last_evaluated_key.is_none() {
+            break;
         }
+        exclusive_start_key = output.last_evaluated_key;
+    }
+    documents.truncate(limit);
+    starred_documents::mark_starred(dynamodb_client, session_user, &mut documents)
+        .await
+        .map_err(|e| {
+            log_error(e);
+            error::ErrorInternalServerError("")
+        })?;
+    Ok(SearchDocumentTitlesResponse { documents })
+}
+
+/// Keeps the documents the session user can access: ones they own, ones shared with the whole
+/// org, and ones shared with them. The documents must be in the session user's org.
+///
+/// On failure, returns a message to log.
+pub async fn filter_accessible_documents(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    documents: Vec<Document>,
+) -> Result<Vec<Document>, String> {
+    let mut permissions: HashMap<String, DocumentSharingPermission> = HashMap::new();
+    for document in documents.iter() {
+        let permission = if document.owner_user_id == session_user.user_id.as_str() {
+            DocumentSharingPermission::CanEdit
+        } else {
+            DocumentSharingPermission::from_i32(document.org_level_sharing_permission)
+                .unwrap_or(DocumentSharingPermission::None)
+        };
+        permissions.insert(document.id.clone(), permission);
+    }
+    add_user_sharing_permissions(dynamodb_client, session_user, &mut permissions).await?;
+    Ok(documents
+        .into_iter()
+        .filter(|document| permissions.get(&document.id) != Some(&DocumentSharingPermission::None))
+        .collect())
+}
+
+/// The owner of a `documents` item: its `owner_user_id`, or for documents from before ownership
+/// could be transferred, its `created_by_user_id`.
+pub fn owner_user_id_from_item(item: &HashMap<String, AttributeValue>) -> Option<&str> {
+    av_get_s(item, "owner_user_id").or_else(|| av_get_s(item, "created_by_user_id"))
+}
+
+/// Reads a document from a `documents` item. Returns `None` if a field is missing.
+pub fn document_from_item(item: &HashMap<String, AttributeValue>) -> Option<Document> {
+    Some(Document {
+        id: av_get_s(item, "id")?.to_string(),
+        org_id: av_get_s(item, "org_id")?.to_string(),
+        title: av_get_s(item, "title")?.to_string(),
+        created_by_user_id: av_get_s(item, "created_by_user_id")?.to_string(),
+        owner_user_id: owner_user_id_from_item(item)?.to_string(),
+        org_level_sharing_permission: av_get_n(item, "org_level_sharing_permission")?,
+        created_at: av_get_s(item, "created_at")?.to_string(),
+        updated_at: av_get_s(item, "updated_at")?.to_string(),
+        encryption_key_fingerprint: av_get_s(item, "encryption_key_fingerprint")
+            .unwrap_or("")
+            .to_string(),
+        is_starred: false,
+        archived_at: av_get_s(item, "archived_at").unwrap_or("").to_string(),
+        last_revision_number: 0,
+        word_count: 0,
+        last_edited_by_user_id: String::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ops::{Add, Sub};
+
+    use ot::writing_proto::{ChangeOp, ChangeSet, UpdateDocumentStatsRequest};
+
+    use crate::testing::fixtures::{
+        create_organization_user, create_user, DocumentFixture, RevisionFixture,
+    };
+    use crate::testing::utils::TestDynamoDb;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[tokio::test]
+    async fn test_get_document_revisions() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        // Create 2 different change sets.
+        let mut change_set1 = ChangeSet::new();
+        change_set1.insert("foo_bar");
+        let mut change_set2 = ChangeSet::new();
+        change_set2.retain(3);
+        change_set2.delete(4);
+
+        let dt1 = chrono::Utc::now().sub(chrono::Duration::days(7));
+        let dt2 = chrono::Utc::now().sub(chrono::Duration::days(6));
+        let dt3 = chrono::Utc::now().sub(chrono::Duration::days(5));
+
+        // Document 1, with 2 revisions:
+        let doc_id1 = Id::new(IdType::Document);
+        let org_id1 = Id::new(IdType::Organization);
+        let user_id1 = Id::new(IdType::User);
+        let user_id2 = Id::new(IdType::User);
+        DocumentFixture::new()
+            .with_doc_id(&doc_id1)
+            .with_org_id(&org_id1)
+            .with_created_by_user_id(&user_id1)
+            .with_org_level_sharing_permission(DocumentSharingPermission::CanEdit)
+            .with_revisions(vec![
+                RevisionFixture::new(&user_id1, &change_set1, &dt1),
+                RevisionFixture::new(&user_id2, &change_set2, &dt2),
+            ])
+            .create(&db.dynamodb_client)
+            .await;
+        let session_user1 = SessionUser {
+            user_id: user_id1.clone(),
+            org_id: org_id1.clone(),
+            user_role: UserRole::Default,
+        };
+
+        // Document 2, with 1 revision:
+        DocumentFixture::new()
+            .with_created_by_user_id(&user_id2)
+            .with_org_level_sharing_permission(DocumentSharingPermission::CanEdit)
+            .with_revisions(vec![RevisionFixture::new(&user_id2, &change_set1, &dt3)])
+            .create(&db.dynamodb_client)
+            .await;
 
         // Get revisions for Document 1
         let response = get_document_revisions(
             &db.dynamodb_client,
-            &session_user1,
+            &PermissionCache::default(),
+            Some(&session_user1),
             &GetDocumentRevisionsRequest {
                 doc_id: String::from(doc_id1.as_str()),
                 after_revision_number: 0,
+                ..Default::default()
             },
         )
         .await?;
@@ -731,62 +2183,246 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_submit_change_set_success() -> TestResult {
+    async fn test_get_document_head() -> TestResult {
         let db = TestDynamoDb::new().await;
 
-        // Create a document, and a user who can read from and write to it.
-        let doc_id = Id::new(IdType::Document);
-        let org_id = Id::new(IdType::Organization);
         let user_id = Id::new(IdType::User);
-        create_document(
-            &db.dynamodb_client,
-            DocParams {
-                doc_id: doc_id.clone(),
-                org_id: org_id.clone(),
-                created_by_user_id: user_id.clone(),
-                org_level_sharing_permission: DocumentSharingPermission::CanEdit,
-            },
-        )
-        .await?;
+        let mut change_set1 = ChangeSet::new();
+        change_set1.insert("foo");
+        let mut change_set2 = ChangeSet::new();
+        change_set2.retain(3);
+        change_set2.insert("bar");
+        let now = chrono::Utc::now();
+        let doc = DocumentFixture::new()
+            .with_created_by_user_id(&user_id)
+            .with_title("Head")
+            .with_revisions(vec![
+                RevisionFixture::new(&user_id, &change_set1, &now),
+                RevisionFixture::new(&user_id, &change_set2, &now),
+            ]);
+        doc.create(&db.dynamodb_client).await;
         let session_user = SessionUser {
             user_id: user_id.clone(),
-            org_id: org_id.clone(),
+            org_id: doc.org_id.clone(),
             user_role: UserRole::Default,
         };
+        let request = GetDocumentHeadRequest {
+            doc_id: doc.doc_id.as_str().to_string(),
+            ..Default::default()
+        };
 
-        // Add a change set to the revision log. Prepare a new change set to be submitted by the
-        // user.
+        let response =
+            get_document_head(&db.dynamodb_client, Some(&session_user), &request).await?;
+        assert_eq!(response.last_revision_number, 2);
+        assert_eq!(response.title, "Head");
+        assert!(!response.updated_at.is_empty());
+
+        // Someone in another org cannot read it.
+        let other_user = SessionUser {
+            user_id: Id::new(IdType::User),
+            org_id: Id::new(IdType::Organization),
+            user_role: UserRole::Default,
+        };
+        let result = get_document_head(&db.dynamodb_client, Some(&other_user), &request).await;
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_document_excerpt() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let user_id = Id::new(IdType::User);
+        let mut change_set1 = ChangeSet::new();
+        change_set1.insert("Hello, world!");
+        let mut change_set2 = ChangeSet::new();
+        change_set2.retain(7);
+        change_set2.delete(5);
+        change_set2.insert("😀 everyone");
+        change_set2.retain(1);
+        let now = chrono::Utc::now();
+        let doc = DocumentFixture::new()
+            .with_created_by_user_id(&user_id)
+            .with_revisions(vec![
+                RevisionFixture::new(&user_id, &change_set1, &now),
+                RevisionFixture::new(&user_id, &change_set2, &now),
+            ]);
+        doc.create(&db.dynamodb_client).await;
+        let session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: doc.org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let mut request = GetDocumentExcerptRequest {
+            doc_id: doc.doc_id.as_str().to_string(),
+            revision_number: 1,
+            offset: 7,
+            radius: 3,
+            ..Default::default()
+        };
+
+        let response =
+            get_document_excerpt(&db.dynamodb_client, Some(&session_user), &request).await?;
+        assert_eq!(response.excerpt, "o, wor");
+        assert_eq!((response.start_offset, response.end_offset), (4, 10));
+        assert_eq!(response.revision_number, 1);
+
+        // The last revision, with bounds widened so as not to split the emoji.
+        request.revision_number = 0;
+        request.offset = 5;
+        let response =
+            get_document_excerpt(&db.dynamodb_client, Some(&session_user), &request).await?;
+        assert_eq!(response.excerpt, "llo, 😀");
+        assert_eq!((response.start_offset, response.end_offset), (2, 9));
+        assert_eq!(response.revision_number, 2);
+        request.offset = 11;
+        let response =
+            get_document_excerpt(&db.dynamodb_client, Some(&session_user), &request).await?;
+        assert_eq!(response.excerpt, "😀 ever");
+        assert_eq!((response.start_offset, response.end_offset), (7, 14));
+
+        // Bounds are clamped to the document.
+        request.offset = 0;
+        request.radius = 100;
+        let response =
+            get_document_excerpt(&db.dynamodb_client, Some(&session_user), &request).await?;
+        assert_eq!(response.excerpt, "Hello, 😀 everyone!");
+        assert_eq!((response.start_offset, response.end_offset), (0, 19));
+
+        // Offsets outside the document are rejected.
+        request.offset = 20;
+        let result = get_document_excerpt(&db.dynamodb_client, Some(&session_user), &request).await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+
+        // Someone in another org cannot read it.
+        request.offset = 0;
+        let other_user = SessionUser {
+            user_id: Id::new(IdType::User),
+            org_id: Id::new(IdType::Organization),
+            user_role: UserRole::Default,
+        };
+        let result = get_document_excerpt(&db.dynamodb_client, Some(&other_user), &request).await;
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_document_revisions_pinned() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let mut change_set1 = ChangeSet::new();
+        change_set1.insert("foo");
+        let mut change_set2 = ChangeSet::new();
+        change_set2.retain(3);
+        change_set2.insert("bar");
+        let mut change_set3 = ChangeSet::new();
+        change_set3.retain(6);
+        change_set3.insert("baz");
+        let dt = chrono::Utc::now();
+        let user_id = Id::new(IdType::User);
+        let doc = DocumentFixture::new()
+            .with_created_by_user_id(&user_id)
+            .with_revisions(vec![
+                RevisionFixture::new(&user_id, &change_set1, &dt),
+                RevisionFixture::new(&user_id, &change_set2, &dt),
+                RevisionFixture::new(&user_id, &change_set3, &dt),
+            ]);
+        doc.create(&db.dynamodb_client).await;
+        let session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: doc.org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let get_revisions = |after_revision_number: i64, pinned_revision_number: i64| {
+            get_document_revisions(
+                &db.dynamodb_client,
+                &PermissionCache::default(),
+                Some(&session_user),
+                &GetDocumentRevisionsRequest {
+                    doc_id: doc.doc_id.as_str().to_string(),
+                    after_revision_number,
+                    pinned_revision_number,
+                    ..Default::default()
+                },
+            )
+        };
+
+        // Unpinned reads are pinned to the last revision.
+        let response = get_revisions(0, 0).await?;
+        assert_eq!(response.revisions.len(), 3);
+        assert_eq!(response.pinned_revision_number, 3);
+        let response = get_revisions(3, 0).await?;
+        assert!(response.revisions.is_empty());
+        assert_eq!(response.pinned_revision_number, 3);
+
+        // Pinned reads stop at the pinned revision.
+        let response = get_revisions(0, 2).await?;
+        let revision_numbers: Vec<i64> = response
+            .revisions
+            .iter()
+            .map(|r| r.revision_number)
+            .collect();
+        assert_eq!(revision_numbers, vec![1, 2]);
+        assert_eq!(response.last_revision_number, 2);
+        assert_eq!(response.pinned_revision_number, 2);
+        assert!(response.end_of_revisions);
+        let response = get_revisions(1, 2).await?;
+        assert_eq!(response.revisions.len(), 1);
+        let response = get_revisions(2, 2).await?;
+        assert!(response.revisions.is_empty());
+        assert!(response.end_of_revisions);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_submit_change_set_success() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        // Create a document with one revision, and a user who can read from and write to it.
+        // Prepare a new change set to be submitted by the user.
         let mut existing_change_set = ChangeSet::new();
         existing_change_set.insert("foo bar");
         let mut new_change_set = ChangeSet::new();
         new_change_set.retain(3);
         new_change_set.delete(3);
-        let existing_change_set_bytes =
-            Bytes::from(proto::encode_protobuf_message(&existing_change_set)?);
 
         let dt1 = chrono::Utc::now().sub(chrono::Duration::days(7));
 
+        let org_id = Id::new(IdType::Organization);
+        let user_id = create_user(&db.dynamodb_client, "writer@example.com", "Writer").await;
         let some_other_user_id = Id::new(IdType::User);
-        let input = PutItemInput {
-            table_name: table_name("document_revisions"),
-            item: av_map(&[
-                av_s("doc_id", doc_id.as_str()),
-                av_s("author_user_id", some_other_user_id.as_str()),
-                av_n("revision_number", 1),
-                av_b("change_set", existing_change_set_bytes.clone()),
-                av_s("committed_at", &time::date_time_iso_str(&dt1)),
-            ]),
-            ..Default::default()
+        let doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_created_by_user_id(&user_id)
+            .with_org_level_sharing_permission(DocumentSharingPermission::CanEdit)
+            .with_revisions(vec![RevisionFixture::new(
+                &some_other_user_id,
+                &existing_change_set,
+                &dt1,
+            )]);
+        doc.create(&db.dynamodb_client).await;
+        let doc_id = &doc.doc_id;
+        let session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: org_id.clone(),
+            user_role: UserRole::Default,
         };
-        db.dynamodb_client.put_item(input).await?;
 
         let response = submit_document_change_set(
             &db.dynamodb_client,
-            &session_user,
+            &PermissionCache::default(),
+            &RateLimiter::default(),
+            &Requester::User(session_user.clone()),
             &SubmitDocumentChangeSetRequest {
                 doc_id: String::from(doc_id.as_str()),
                 on_revision_number: 1,
                 change_set: Some(new_change_set.clone()),
+                client_id: String::from("test/0.1"),
+                session_id: String::from("session_1"),
+                ..Default::default()
             },
         )
         .await?;
@@ -803,26 +2439,501 @@ mod tests {
             &committed_revision.author_user_id,
             session_user.user_id.as_str()
         );
+        assert_eq!(committed_revision.author_display_name, "Writer");
         assert!(response.end_of_revisions);
 
         // Verify that we can read the revision that we just wrote.
         let response = get_document_revisions(
             &db.dynamodb_client,
+            &PermissionCache::default(),
+            Some(&session_user),
+            &GetDocumentRevisionsRequest {
+                doc_id: String::from(doc_id.as_str()),
+                after_revision_number: 1,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        assert_eq!(response.last_revision_number, 2);
+        assert_eq!(response.revisions.len(), 1);
+        let committed_revision = &response.revisions[0];
+        assert_eq!(
+            committed_revision.change_set.as_ref().unwrap(),
+            &new_change_set
+        );
+        assert_eq!(committed_revision.author_display_name, "Writer");
+        assert_eq!(committed_revision.client_id, "test/0.1");
+        assert_eq!(committed_revision.session_id, "session_1");
+        assert!(response.end_of_revisions);
+
+        // Client ids are limited in length.
+        let result = submit_document_change_set(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
+            &RateLimiter::default(),
+            &Requester::User(session_user.clone()),
+            &SubmitDocumentChangeSetRequest {
+                doc_id: String::from(doc_id.as_str()),
+                on_revision_number: 2,
+                change_set: Some(new_change_set.clone()),
+                client_id: "x".repeat(MAX_CLIENT_ID_LEN + 1),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_submit_identity_change_set() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let mut existing_change_set = ChangeSet::new();
+        existing_change_set.insert("foo bar");
+        let user_id = Id::new(IdType::User);
+        let doc = DocumentFixture::new()
+            .with_created_by_user_id(&user_id)
+            .with_revisions(vec![RevisionFixture::new(
+                &user_id,
+                &existing_change_set,
+                &chrono::Utc::now(),
+            )]);
+        doc.create(&db.dynamodb_client).await;
+        let session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: doc.org_id.clone(),
+            user_role: UserRole::Default,
+        };
+
+        let mut identity_change_set = ChangeSet::new();
+        identity_change_set.retain(7);
+        let response = submit_document_change_set(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
+            &RateLimiter::default(),
+            &Requester::User(session_user.clone()),
+            &SubmitDocumentChangeSetRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                on_revision_number: 1,
+                change_set: Some(identity_change_set),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(response.response_code(), ResponseCode::Ack);
+        assert_eq!(response.last_revision_number, 1);
+        assert!(response.revisions.is_empty());
+
+        // No revision was committed.
+        let response = get_document_revisions(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
+            Some(&session_user),
+            &GetDocumentRevisionsRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                after_revision_number: 0,
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(response.revisions.len(), 1);
+        assert_eq!(response.last_revision_number, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_submit_invalid_change_set() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let user_id = Id::new(IdType::User);
+        let doc = DocumentFixture::new().with_created_by_user_id(&user_id);
+        doc.create(&db.dynamodb_client).await;
+        let session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: doc.org_id.clone(),
+            user_role: UserRole::Default,
+        };
+
+        let mut empty_op = ChangeSet::new();
+        empty_op.insert("foo");
+        empty_op.ops.push(ChangeOp { op: None });
+        let mut negative_count = ChangeSet::new();
+        negative_count.insert("foo");
+        negative_count.ops.push(ChangeOp {
+            op: Some(ot::delete_op(-3)),
+        });
+        let mut length_overflow = ChangeSet::new();
+        length_overflow.retain(i64::MAX);
+        length_overflow.insert("foo");
+        let mut not_utf16 = ChangeSet::new();
+        not_utf16.insert_vec(vec![0x11_0000]);
+        for change_set in vec![empty_op, negative_count, length_overflow, not_utf16] {
+            let result = submit_document_change_set(
+                &db.dynamodb_client,
+                &PermissionCache::default(),
+                &RateLimiter::default(),
+                &Requester::User(session_user.clone()),
+                &SubmitDocumentChangeSetRequest {
+                    doc_id: doc.doc_id.as_str().to_string(),
+                    on_revision_number: 0,
+                    change_set: Some(change_set.clone()),
+                    ..Default::default()
+                },
+            )
+            .await;
+            assert_eq!(
+                result.err().unwrap().as_response_error().status_code(),
+                400,
+                "{:?}",
+                change_set
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_encrypted_document() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let session_user = SessionUser {
+            user_id: Id::new(IdType::User),
+            org_id: Id::new(IdType::Organization),
+            user_role: UserRole::Default,
+        };
+        let fingerprint = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+        let response = create_document(
+            &db.dynamodb_client,
+            &session_user,
+            &CreateDocumentRequest {
+                title: "Secret".to_string(),
+                encryption_key_fingerprint: fingerprint.to_string(),
+                ..Default::default()
+            },
+        )
+        .await?;
+        let response = get_document(
+            &db.dynamodb_client,
+            Some(&session_user),
+            &GetDocumentRequest {
+                doc_id: response.doc_id,
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(
+            response.document.unwrap().encryption_key_fingerprint,
+            fingerprint
+        );
+
+        // Fingerprints must be hex-encoded SHA-256 hashes.
+        let result = create_document(
+            &db.dynamodb_client,
+            &session_user,
+            &CreateDocumentRequest {
+                title: "Secret".to_string(),
+                encryption_key_fingerprint: "not a fingerprint".to_string(),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_submit_encrypted_change_set() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let user_id = Id::new(IdType::User);
+        let doc = DocumentFixture::new()
+            .with_created_by_user_id(&user_id)
+            .with_encryption_key_fingerprint(
+                "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08",
+            );
+        doc.create(&db.dynamodb_client).await;
+        let session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: doc.org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let permission_cache = PermissionCache::default();
+
+        // The server commits the encrypted change set without reading it, and returns it as is.
+        let encrypted_change_set = vec![0xde, 0xad, 0xbe, 0xef];
+        let response = submit_document_change_set(
+            &db.dynamodb_client,
+            &permission_cache,
+            &RateLimiter::default(),
+            &Requester::User(session_user.clone()),
+            &SubmitDocumentChangeSetRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                on_revision_number: 0,
+                encrypted_change_set: encrypted_change_set.clone(),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(response.response_code(), ResponseCode::Ack);
+        let response = get_document_revisions(
+            &db.dynamodb_client,
+            &permission_cache,
+            Some(&session_user),
+            &GetDocumentRevisionsRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(response.revisions.len(), 1);
+        assert_eq!(response.revisions[0].change_set, None);
+        assert_eq!(
+            response.revisions[0].encrypted_change_set,
+            encrypted_change_set
+        );
+
+        // Encrypted revisions cannot be diagnosed.
+        let session_admin = SessionUser {
+            user_role: UserRole::OrgAdmin,
+            ..session_user.clone()
+        };
+        let result = diagnose_document_revisions(
+            &db.dynamodb_client,
+            &permission_cache,
+            &session_admin,
+            &DiagnoseDocumentRevisionsRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                after_revision_number: 0,
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+
+        // Exactly one of the change set and the encrypted change set must be given.
+        let mut change_set = ChangeSet::new();
+        change_set.retain(4);
+        change_set.insert("foo");
+        let result = submit_document_change_set(
+            &db.dynamodb_client,
+            &permission_cache,
+            &RateLimiter::default(),
+            &Requester::User(session_user.clone()),
+            &SubmitDocumentChangeSetRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                on_revision_number: 1,
+                change_set: Some(change_set),
+                encrypted_change_set: encrypted_change_set.clone(),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+        let result = submit_document_change_set(
+            &db.dynamodb_client,
+            &permission_cache,
+            &RateLimiter::default(),
+            &Requester::User(session_user.clone()),
+            &SubmitDocumentChangeSetRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                on_revision_number: 1,
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+
+        // An encrypted document only takes encrypted change sets.
+        let mut change_set = ChangeSet::new();
+        change_set.retain(4);
+        change_set.insert("foo");
+        let result = submit_document_change_set(
+            &db.dynamodb_client,
+            &permission_cache,
+            &RateLimiter::default(),
+            &Requester::User(session_user.clone()),
+            &SubmitDocumentChangeSetRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                on_revision_number: 1,
+                change_set: Some(change_set),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+
+        // And a plaintext document only takes plaintext ones.
+        let plaintext_doc = DocumentFixture::new()
+            .with_org_id(&doc.org_id)
+            .with_created_by_user_id(&user_id);
+        plaintext_doc.create(&db.dynamodb_client).await;
+        let result = submit_document_change_set(
+            &db.dynamodb_client,
+            &permission_cache,
+            &RateLimiter::default(),
+            &Requester::User(session_user),
+            &SubmitDocumentChangeSetRequest {
+                doc_id: plaintext_doc.doc_id.as_str().to_string(),
+                on_revision_number: 0,
+                encrypted_change_set,
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_debug_decode_revision() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let mut change_set1 = ChangeSet::new();
+        change_set1.insert("foo");
+        let mut change_set2 = ChangeSet::new();
+        change_set2.retain(1);
+        change_set2.ops.push(ChangeOp { op: None });
+        change_set2.ops.push(ChangeOp {
+            op: Some(ot::retain_op(2)),
+        });
+        let user_id = Id::new(IdType::User);
+        let now = chrono::Utc::now();
+        let doc = DocumentFixture::new()
+            .with_created_by_user_id(&user_id)
+            .with_revisions(vec![
+                RevisionFixture::new(&user_id, &change_set1, &now),
+                RevisionFixture::new(&user_id, &change_set2, &now),
+            ]);
+        doc.create(&db.dynamodb_client).await;
+        let mut session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: doc.org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let mut request = DebugDecodeRevisionRequest {
+            doc_id: doc.doc_id.as_str().to_string(),
+            revision_number: 1,
+        };
+
+        // Only org admins may decode revisions.
+        let result = debug_decode_revision(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
+            &session_user,
+            &request,
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+
+        session_user.user_role = UserRole::OrgAdmin;
+        let response = debug_decode_revision(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
+            &session_user,
+            &request,
+        )
+        .await?;
+        assert_eq!(response.revision_number, 1);
+        assert_eq!(response.author_user_id, user_id.as_str());
+        assert!(!response.committed_at.is_empty());
+        assert!(!response.encrypted);
+        assert!(response.change_set_bytes > 0);
+        assert_eq!(response.change_set, "[insert \"foo\"] (0 -> 3)");
+        assert!(response.summary.starts_with("Change set with 1 ops"));
+        assert!(response.diagnostics.is_empty());
+
+        request.revision_number = 2;
+        let response = debug_decode_revision(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
+            &session_user,
+            &request,
+        )
+        .await?;
+        assert_eq!(
+            response.change_set,
+            "[retain 1, empty op, retain 2] (invalid)"
+        );
+        assert_eq!(
+            response.diagnostics,
+            vec!["empty op at index 1".to_string()]
+        );
+
+        request.revision_number = 3;
+        let result = debug_decode_revision(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
+            &session_user,
+            &request,
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 404);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_diagnose_document_revisions() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let mut change_set1 = ChangeSet::new();
+        change_set1.insert("foo");
+        let mut change_set2 = ChangeSet::new();
+        change_set2.retain(1);
+        change_set2.ops.push(ChangeOp { op: None });
+        change_set2.ops.push(ChangeOp {
+            op: Some(ot::retain_op(2)),
+        });
+        let user_id = Id::new(IdType::User);
+        let now = chrono::Utc::now();
+        let doc = DocumentFixture::new()
+            .with_created_by_user_id(&user_id)
+            .with_revisions(vec![
+                RevisionFixture::new(&user_id, &change_set1, &now),
+                RevisionFixture::new(&user_id, &change_set2, &now),
+            ]);
+        doc.create(&db.dynamodb_client).await;
+        let mut session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: doc.org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let request = DiagnoseDocumentRevisionsRequest {
+            doc_id: doc.doc_id.as_str().to_string(),
+            after_revision_number: 0,
+        };
+
+        // Only org admins may diagnose revisions.
+        let result = diagnose_document_revisions(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
+            &session_user,
+            &request,
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+
+        session_user.user_role = UserRole::OrgAdmin;
+        let response = diagnose_document_revisions(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
             &session_user,
-            &GetDocumentRevisionsRequest {
-                doc_id: String::from(doc_id.as_str()),
-                after_revision_number: 1,
-            },
+            &request,
         )
         .await?;
-
-        assert_eq!(response.last_revision_number, 2);
-        assert_eq!(response.revisions.len(), 1);
-        let committed_revision = &response.revisions[0];
         assert_eq!(
-            committed_revision.change_set.as_ref().unwrap(),
-            &new_change_set
+            response.revisions,
+            vec![RevisionDiagnostics {
+                revision_number: 2,
+                diagnostics: vec!["empty op at index 1".to_string()],
+            }]
         );
+        assert_eq!(response.last_revision_number, 2);
         assert!(response.end_of_revisions);
 
         Ok(())
@@ -839,8 +2950,6 @@ mod tests {
         change_set2.delete(4);
         let mut new_change_set = ChangeSet::new();
         new_change_set.delete(4);
-        let change_set_bytes1 = Bytes::from(proto::encode_protobuf_message(&change_set1)?);
-        let change_set_bytes2 = Bytes::from(proto::encode_protobuf_message(&change_set2)?);
 
         // Create a document, and a user who can read it, write it.
         let doc_id = Id::new(IdType::Document);
@@ -852,55 +2961,34 @@ mod tests {
             org_id: org_id.clone(),
             user_role: UserRole::Default,
         };
-        create_document(
-            &db.dynamodb_client,
-            DocParams {
-                doc_id: doc_id.clone(),
-                org_id: org_id.clone(),
-                created_by_user_id: user_id.clone(),
-                org_level_sharing_permission: DocumentSharingPermission::CanEdit,
-            },
-        )
-        .await?;
 
         // Add two revisions to the document. The latest revision number is 2.
         let dt1 = chrono::Utc::now().sub(chrono::Duration::days(1));
         let dt2 = chrono::Utc::now().sub(chrono::Duration::seconds(10));
-
-        let items: Vec<HashMap<String, AttributeValue>> = vec![
-            av_map(&[
-                av_s("doc_id", doc_id.as_str()),
-                av_s("author_user_id", user_id.as_str()),
-                av_n("revision_number", 1),
-                av_b("change_set", change_set_bytes1.clone()),
-                av_s("committed_at", &time::date_time_iso_str(&dt1)),
-            ]),
-            av_map(&[
-                av_s("doc_id", doc_id.as_str()),
-                av_s("author_user_id", some_other_user_id.as_str()),
-                av_n("revision_number", 2),
-                av_b("change_set", change_set_bytes2.clone()),
-                av_s("committed_at", &time::date_time_iso_str(&dt2)),
-            ]),
-        ];
-        for item in items.into_iter() {
-            let input = PutItemInput {
-                table_name: table_name("document_revisions"),
-                item,
-                ..Default::default()
-            };
-            db.dynamodb_client.put_item(input).await?;
-        }
+        DocumentFixture::new()
+            .with_doc_id(&doc_id)
+            .with_org_id(&org_id)
+            .with_created_by_user_id(&user_id)
+            .with_org_level_sharing_permission(DocumentSharingPermission::CanEdit)
+            .with_revisions(vec![
+                RevisionFixture::new(&user_id, &change_set1, &dt1),
+                RevisionFixture::new(&some_other_user_id, &change_set2, &dt2),
+            ])
+            .create(&db.dynamodb_client)
+            .await;
 
         // Attempt to submit a new revision on top of revision 1. This should fail because revision
         // 1 is no longer the latest revision. The function will return the new revision.
         let response = submit_document_change_set(
             &db.dynamodb_client,
-            &session_user,
+            &PermissionCache::default(),
+            &RateLimiter::default(),
+            &Requester::User(session_user.clone()),
             &SubmitDocumentChangeSetRequest {
                 doc_id: String::from(doc_id.as_str()),
                 on_revision_number: 1,
                 change_set: Some(new_change_set.clone()),
+                ..Default::default()
             },
         )
         .await?;
@@ -937,16 +3025,13 @@ mod tests {
         let org_id = Id::new(IdType::Organization);
         let created_by_user_id = Id::new(IdType::User);
 
-        create_document(
-            &db.dynamodb_client,
-            DocParams {
-                doc_id: doc_id.clone(),
-                org_id: org_id.clone(),
-                created_by_user_id: created_by_user_id.clone(),
-                org_level_sharing_permission: DocumentSharingPermission::None,
-            },
-        )
-        .await?;
+        DocumentFixture::new()
+            .with_doc_id(&doc_id)
+            .with_org_id(&org_id)
+            .with_created_by_user_id(&created_by_user_id)
+            .with_org_level_sharing_permission(DocumentSharingPermission::None)
+            .create(&db.dynamodb_client)
+            .await;
 
         // User requests permission to read a document she created. Should be accepted.
         let mut session_user = SessionUser {
@@ -994,16 +3079,13 @@ mod tests {
         let org_id = Id::new(IdType::Organization);
         let created_by_user_id = Id::new(IdType::User);
 
-        create_document(
-            &db.dynamodb_client,
-            DocParams {
-                doc_id: doc_id.clone(),
-                org_id: org_id.clone(),
-                created_by_user_id: created_by_user_id.clone(),
-                org_level_sharing_permission: DocumentSharingPermission::CanView,
-            },
-        )
-        .await?;
+        DocumentFixture::new()
+            .with_doc_id(&doc_id)
+            .with_org_id(&org_id)
+            .with_created_by_user_id(&created_by_user_id)
+            .with_org_level_sharing_permission(DocumentSharingPermission::CanView)
+            .create(&db.dynamodb_client)
+            .await;
 
         // User requested permission to read a doc created by someone in her org. The org-level
         // permission is CanView, so should be accepted.
@@ -1052,29 +3134,16 @@ mod tests {
         let created_by_user_id = Id::new(IdType::User);
         let reader_user_id = Id::new(IdType::User);
 
-        // Document created with no org-level sharing permission.
-        create_document(
-            &db.dynamodb_client,
-            DocParams {
-                doc_id: doc_id.clone(),
-                org_id: org_id.clone(),
-                created_by_user_id: created_by_user_id.clone(),
-                org_level_sharing_permission: DocumentSharingPermission::None,
-            },
-        )
-        .await?;
-
-        // User is specifically given permission to read the document.
-        create_document_user_sharing_permission(
-            &db.dynamodb_client,
-            DocumentUserSharingPermissionParams {
-                doc_id: doc_id.clone(),
-                user_id: reader_user_id.clone(),
-                org_id: org_id.clone(),
-                sharing_permission: DocumentSharingPermission::CanView,
-            },
-        )
-        .await?;
+        // Document created with no org-level sharing permission. User is specifically given
+        // permission to read the document.
+        DocumentFixture::new()
+            .with_doc_id(&doc_id)
+            .with_org_id(&org_id)
+            .with_created_by_user_id(&created_by_user_id)
+            .with_org_level_sharing_permission(DocumentSharingPermission::None)
+            .with_sharing(&reader_user_id, DocumentSharingPermission::CanView)
+            .create(&db.dynamodb_client)
+            .await;
 
         // User requested to read the document. Should be accepted.
         let session_user = SessionUser {
@@ -1113,24 +3182,390 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_permission_document_is_in_different_org() -> TestResult {
+    async fn test_permission_can_comment() -> TestResult {
         let db = TestDynamoDb::new().await;
+        let permission_cache = PermissionCache::default();
 
-        let doc_id = Id::new(IdType::Document);
-        let org_id1 = Id::new(IdType::Organization);
-        let created_by_user_id = Id::new(IdType::User);
+        let org_id = Id::new(IdType::Organization);
+        let commenter_user_id = Id::new(IdType::User);
+        let doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_sharing(&commenter_user_id, DocumentSharingPermission::CanComment);
+        doc.create(&db.dynamodb_client).await;
+        let session_user = SessionUser {
+            user_id: commenter_user_id.clone(),
+            org_id: org_id.clone(),
+            user_role: UserRole::Default,
+        };
+
+        // Commenters can read the document.
+        get_document_revisions(
+            &db.dynamodb_client,
+            &permission_cache,
+            Some(&session_user),
+            &GetDocumentRevisionsRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        // But they cannot edit it.
+        let result = submit_document_change_set(
+            &db.dynamodb_client,
+            &permission_cache,
+            &RateLimiter::default(),
+            &Requester::User(session_user.clone()),
+            &SubmitDocumentChangeSetRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                on_revision_number: 0,
+                change_set: Some(ChangeSet::new()),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+
+        // CanComment is stronger than CanView and weaker than CanEdit, whichever way each is
+        // granted.
+        let viewable_doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_org_level_sharing_permission(DocumentSharingPermission::CanView)
+            .with_sharing(&commenter_user_id, DocumentSharingPermission::CanComment);
+        let editable_doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_org_level_sharing_permission(DocumentSharingPermission::CanComment)
+            .with_sharing(&commenter_user_id, DocumentSharingPermission::CanEdit);
+        let commentable_doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_org_level_sharing_permission(DocumentSharingPermission::CanComment)
+            .with_sharing(&commenter_user_id, DocumentSharingPermission::CanView);
+        for doc in [&viewable_doc, &editable_doc, &commentable_doc].iter() {
+            doc.create(&db.dynamodb_client).await;
+        }
+        let response = get_my_permissions(
+            &db.dynamodb_client,
+            &session_user,
+            &GetMyPermissionsRequest {
+                doc_ids: vec![
+                    viewable_doc.doc_id.as_str().to_string(),
+                    editable_doc.doc_id.as_str().to_string(),
+                    commentable_doc.doc_id.as_str().to_string(),
+                ],
+            },
+        )
+        .await?;
+        let permissions: Vec<DocumentSharingPermission> = response
+            .permissions
+            .iter()
+            .map(|p| p.permission())
+            .collect();
+        assert_eq!(
+            permissions,
+            vec![
+                DocumentSharingPermission::CanComment,
+                DocumentSharingPermission::CanEdit,
+                DocumentSharingPermission::CanComment,
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_transfer_ownership() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let permission_cache = PermissionCache::default();
+
+        // A document from before ownership could be transferred, owned by its creator.
+        let org_id = Id::new(IdType::Organization);
+        let creator_user_id = Id::new(IdType::User);
+        let new_owner_user_id = Id::new(IdType::User);
+        create_organization_user(
+            &db.dynamodb_client,
+            &org_id,
+            &new_owner_user_id,
+            &chrono::Utc::now(),
+        )
+        .await;
+        let doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_created_by_user_id(&creator_user_id);
+        doc.create(&db.dynamodb_client).await;
+        let creator = SessionUser {
+            user_id: creator_user_id.clone(),
+            org_id: org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let new_owner = SessionUser {
+            user_id: new_owner_user_id.clone(),
+            org_id: org_id.clone(),
+            user_role: UserRole::Default,
+        };
+
+        // The new owner must be in the document's org.
+        let result = transfer_ownership(
+            &db.dynamodb_client,
+            &permission_cache,
+            &creator,
+            &TransferOwnershipRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                new_owner_user_id: Id::new(IdType::User).as_str().to_string(),
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+
+        transfer_ownership(
+            &db.dynamodb_client,
+            &permission_cache,
+            &creator,
+            &TransferOwnershipRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                new_owner_user_id: new_owner_user_id.as_str().to_string(),
+            },
+        )
+        .await?;
+
+        // The new owner can edit the document, and the creator, who was not shared it, cannot see
+        // it anymore.
+        let (document, permission) = get_document_and_granted_permission(
+            &db.dynamodb_client,
+            &new_owner,
+            doc.doc_id.as_str(),
+            &[DocumentSharingPermission::CanEdit],
+        )
+        .await?;
+        assert_eq!(document.owner_user_id, new_owner_user_id.as_str());
+        assert_eq!(permission, DocumentSharingPermission::CanEdit);
+        let result = get_document_if_some_permission_valid(
+            &db.dynamodb_client,
+            &creator,
+            doc.doc_id.as_str(),
+            &[DocumentSharingPermission::CanView],
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+
+        // Only the owner and org admins may transfer the document.
+        let editor_user_id = Id::new(IdType::User);
+        let shared_doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_sharing(&editor_user_id, DocumentSharingPermission::CanEdit);
+        shared_doc.create(&db.dynamodb_client).await;
+        let mut editor = SessionUser {
+            user_id: editor_user_id.clone(),
+            org_id: org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let request = TransferOwnershipRequest {
+            doc_id: shared_doc.doc_id.as_str().to_string(),
+            new_owner_user_id: new_owner_user_id.as_str().to_string(),
+        };
+        let result =
+            transfer_ownership(&db.dynamodb_client, &permission_cache, &editor, &request).await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+        editor.user_role = UserRole::OrgAdmin;
+        transfer_ownership(&db.dynamodb_client, &permission_cache, &editor, &request).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_permission_cache() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let permission_cache = PermissionCache::default();
+
+        // Document is explicitly shared with the reader.
+        let org_id = Id::new(IdType::Organization);
+        let reader_user_id = Id::new(IdType::User);
+        let doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_sharing(&reader_user_id, DocumentSharingPermission::CanView);
+        doc.create(&db.dynamodb_client).await;
+        let session_user = SessionUser {
+            user_id: reader_user_id.clone(),
+            org_id: org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let request = GetDocumentRevisionsRequest {
+            doc_id: doc.doc_id.as_str().to_string(),
+            after_revision_number: 0,
+            ..Default::default()
+        };
+
+        // First read checks DynamoDB and caches the granted permission.
+        get_document_revisions(
+            &db.dynamodb_client,
+            &permission_cache,
+            Some(&session_user),
+            &request,
+        )
+        .await?;
+        assert_eq!(
+            permission_cache.get(&session_user, doc.doc_id.as_str()),
+            Some(DocumentSharingPermission::CanView)
+        );
+
+        // Revoke the user's permission. The cached permission is still honored until the cache is
+        // invalidated.
+        db.dynamodb_client
+            .delete_item(rusoto_dynamodb::DeleteItemInput {
+                table_name: table_name("document_user_sharing_permissions"),
+                key: av_map(&[
+                    av_s("doc_id", doc.doc_id.as_str()),
+                    av_s("user_id", reader_user_id.as_str()),
+                ]),
+                ..Default::default()
+            })
+            .await?;
+        get_document_revisions(
+            &db.dynamodb_client,
+            &permission_cache,
+            Some(&session_user),
+            &request,
+        )
+        .await?;
+
+        // A cached CanView permission does not satisfy a CanEdit check, so submitting goes to
+        // DynamoDB and is rejected.
+        let result = submit_document_change_set(
+            &db.dynamodb_client,
+            &permission_cache,
+            &RateLimiter::default(),
+            &Requester::User(session_user.clone()),
+            &SubmitDocumentChangeSetRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                on_revision_number: 0,
+                change_set: Some(ChangeSet::new()),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+
+        // After invalidation, reads are rejected too.
+        permission_cache.invalidate_document(doc.doc_id.as_str());
+        let result = get_document_revisions(
+            &db.dynamodb_client,
+            &permission_cache,
+            Some(&session_user),
+            &request,
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_my_permissions() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let org_id = Id::new(IdType::Organization);
+        let user_id = Id::new(IdType::User);
+        let session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: org_id.clone(),
+            user_role: UserRole::Default,
+        };
 
-        create_document(
+        // Created by the user.
+        let created_doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_created_by_user_id(&user_id);
+        // Shared with the whole org as read-only, but explicitly shared with the user as editable.
+        let shared_doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_org_level_sharing_permission(DocumentSharingPermission::CanView)
+            .with_sharing(&user_id, DocumentSharingPermission::CanEdit);
+        // Shared with the whole org as read-only.
+        let org_doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_org_level_sharing_permission(DocumentSharingPermission::CanView);
+        // Not shared at all.
+        let private_doc = DocumentFixture::new().with_org_id(&org_id);
+        // Shared with a different org.
+        let other_org_doc = DocumentFixture::new()
+            .with_org_level_sharing_permission(DocumentSharingPermission::CanEdit);
+        let docs = [
+            &created_doc,
+            &shared_doc,
+            &org_doc,
+            &private_doc,
+            &other_org_doc,
+        ];
+        for doc in docs.iter() {
+            doc.create(&db.dynamodb_client).await;
+        }
+        let missing_doc_id = Id::new(IdType::Document);
+
+        let doc_ids = vec![
+            created_doc.doc_id.as_str(),
+            shared_doc.doc_id.as_str(),
+            org_doc.doc_id.as_str(),
+            private_doc.doc_id.as_str(),
+            other_org_doc.doc_id.as_str(),
+            missing_doc_id.as_str(),
+            created_doc.doc_id.as_str(),
+        ];
+        let response = get_my_permissions(
             &db.dynamodb_client,
-            DocParams {
-                doc_id: doc_id.clone(),
-                org_id: org_id1.clone(),
-                created_by_user_id: created_by_user_id.clone(),
-                org_level_sharing_permission: DocumentSharingPermission::CanView,
+            &session_user,
+            &GetMyPermissionsRequest {
+                doc_ids: doc_ids.iter().map(|doc_id| doc_id.to_string()).collect(),
             },
         )
         .await?;
 
+        // One entry per distinct doc id, in request order.
+        let permissions: Vec<(&str, DocumentSharingPermission)> = response
+            .permissions
+            .iter()
+            .map(|p| (p.doc_id.as_str(), p.permission()))
+            .collect();
+        assert_eq!(
+            permissions,
+            vec![
+                (doc_ids[0], DocumentSharingPermission::CanEdit),
+                (doc_ids[1], DocumentSharingPermission::CanEdit),
+                (doc_ids[2], DocumentSharingPermission::CanView),
+                (doc_ids[3], DocumentSharingPermission::None),
+                (doc_ids[4], DocumentSharingPermission::None),
+                (doc_ids[5], DocumentSharingPermission::None),
+            ]
+        );
+
+        // Ids that are not document ids are rejected.
+        let result = get_my_permissions(
+            &db.dynamodb_client,
+            &session_user,
+            &GetMyPermissionsRequest {
+                doc_ids: vec![user_id.as_str().to_string()],
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_permission_document_is_in_different_org() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let doc_id = Id::new(IdType::Document);
+        let org_id1 = Id::new(IdType::Organization);
+        let created_by_user_id = Id::new(IdType::User);
+
+        DocumentFixture::new()
+            .with_doc_id(&doc_id)
+            .with_org_id(&org_id1)
+            .with_created_by_user_id(&created_by_user_id)
+            .with_org_level_sharing_permission(DocumentSharingPermission::CanView)
+            .create(&db.dynamodb_client)
+            .await;
+
         // User requested permission to read a doc in a different org. Should get a 404 Not Found
         // error result.
         let org_id2 = Id::new(IdType::Organization);
@@ -1189,65 +3624,184 @@ mod tests {
         Ok(())
     }
 
-    struct DocParams {
-        doc_id: Id,
-        org_id: Id,
-        created_by_user_id: Id,
-        org_level_sharing_permission: DocumentSharingPermission,
-    }
+    #[tokio::test]
+    async fn test_search_document_titles() -> TestResult {
+        let db = TestDynamoDb::new().await;
 
-    async fn create_document(
-        dynamodb_client: &DynamoDbClient,
-        doc_params: DocParams,
-    ) -> TestResult {
-        let doc_created_at = chrono::Utc::now().sub(chrono::Duration::days(1));
-        let doc_created_at_str = time::date_time_iso_str(&doc_created_at);
-        let input = rusoto_dynamodb::PutItemInput {
-            table_name: table_name("documents"),
-            item: av_map(&[
-                av_s("id", doc_params.doc_id.as_str()),
-                av_s("org_id", doc_params.org_id.as_str()),
-                av_s("title", "My favorite document ever"),
-                av_s("created_by_user_id", doc_params.created_by_user_id.as_str()),
-                av_n(
-                    "org_level_sharing_permission",
-                    doc_params.org_level_sharing_permission as i32,
-                ),
-                av_s("created_at", &doc_created_at_str),
-                av_s("updated_at", &doc_created_at_str),
-            ]),
-            ..Default::default()
+        let org_id = Id::new(IdType::Organization);
+        let user_id = Id::new(IdType::User);
+        let session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let fixtures = [
+            DocumentFixture::new()
+                .with_org_id(&org_id)
+                .with_created_by_user_id(&user_id)
+                .with_title("Quarterly Plan"),
+            DocumentFixture::new()
+                .with_org_id(&org_id)
+                .with_org_level_sharing_permission(DocumentSharingPermission::CanView)
+                .with_title("quarterly review"),
+            DocumentFixture::new()
+                .with_org_id(&org_id)
+                .with_sharing(&user_id, DocumentSharingPermission::CanView)
+                .with_title("QUARTERLY budget"),
+            // Not shared with the user.
+            DocumentFixture::new()
+                .with_org_id(&org_id)
+                .with_title("Quarterly secrets"),
+            // In a different org.
+            DocumentFixture::new()
+                .with_org_level_sharing_permission(DocumentSharingPermission::CanEdit)
+                .with_title("Quarterly elsewhere"),
+            DocumentFixture::new()
+                .with_org_id(&org_id)
+                .with_created_by_user_id(&user_id)
+                .with_title("Meeting notes"),
+        ];
+        for fixture in fixtures.iter() {
+            fixture.create(&db.dynamodb_client).await;
+        }
+        let search = |prefix: &str, limit: i32| {
+            let request = SearchDocumentTitlesRequest {
+                prefix: prefix.to_string(),
+                limit,
+            };
+            let dynamodb_client = &db.dynamodb_client;
+            let session_user = &session_user;
+            async move { search_document_titles(dynamodb_client, session_user, &request).await }
+        };
+        let titles = |response: SearchDocumentTitlesResponse| -> Vec<String> {
+            response
+                .documents
+                .into_iter()
+                .map(|doc| doc.title)
+                .collect()
         };
-        dynamodb_client.put_item(input).await?;
+
+        assert_eq!(
+            titles(search("QuArTeRlY", 0).await?),
+            vec!["QUARTERLY budget", "Quarterly Plan", "quarterly review"]
+        );
+        assert_eq!(
+            titles(search("quarterly", 2).await?),
+            vec!["QUARTERLY budget", "Quarterly Plan"]
+        );
+        assert_eq!(titles(search("meet", 0).await?), vec!["Meeting notes"]);
+        assert!(search("nothing", 0).await?.documents.is_empty());
+
+        // Renaming updates the search key.
+        update_document_title(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
+            &session_user,
+            &UpdateDocumentTitleRequest {
+                doc_id: fixtures[5].doc_id.as_str().to_string(),
+                new_title: String::from("Quarterly kickoff"),
+            },
+        )
+        .await?;
+        assert!(search("meet", 0).await?.documents.is_empty());
+        assert_eq!(
+            titles(search("quarterly k", 0).await?),
+            vec!["Quarterly kickoff"]
+        );
+
+        let result = search("", 0).await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
         Ok(())
     }
 
-    struct DocumentUserSharingPermissionParams {
-        doc_id: Id,
-        user_id: Id,
-        org_id: Id,
-        sharing_permission: DocumentSharingPermission,
-    }
-
-    async fn create_document_user_sharing_permission(
-        dynamodb_client: &DynamoDbClient,
-        params: DocumentUserSharingPermissionParams,
-    ) -> TestResult {
-        let created_at = chrono::Utc::now().sub(chrono::Duration::days(1));
-        let created_at_str = time::date_time_iso_str(&created_at);
-        let input = rusoto_dynamodb::PutItemInput {
-            table_name: table_name("document_user_sharing_permissions"),
-            item: av_map(&[
-                av_s("doc_id", params.doc_id.as_str()),
-                av_s("user_id", params.user_id.as_str()),
-                av_s("org_id", params.org_id.as_str()),
-                av_n("sharing_permission", params.sharing_permission as i32),
-                av_s("created_at", &created_at_str),
-                av_s("updated_at", &created_at_str),
-            ]),
-            ..Default::default()
+    #[tokio::test]
+    async fn test_list_my_documents() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let user_id = Id::new(IdType::User);
+        let other_user_id = Id::new(IdType::User);
+        let doc = DocumentFixture::new()
+            .with_created_by_user_id(&user_id)
+            .with_org_level_sharing_permission(DocumentSharingPermission::CanEdit)
+            .with_title("Field notes");
+        doc.create(&db.dynamodb_client).await;
+        let session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: doc.org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let list = || {
+            let request = ListMyDocumentsRequest {
+                updated_before_date_time: time::date_time_iso_str(
+                    &chrono::Utc::now().add(chrono::Duration::days(1)),
+                ),
+            };
+            let dynamodb_client = &db.dynamodb_client;
+            let session_user = &session_user;
+            async move { list_my_documents(dynamodb_client, session_user, &request).await }
         };
-        dynamodb_client.put_item(input).await?;
+
+        // Nothing is known about the document's revisions yet.
+        let response = list().await?;
+        assert_eq!(response.documents.len(), 1);
+        assert_eq!(response.documents[0].last_revision_number, 0);
+        assert_eq!(response.documents[0].word_count, 0);
+        assert_eq!(response.documents[0].last_edited_by_user_id, "");
+
+        // Another user edits the document, and their client reports its stats.
+        let committed_at = chrono::Utc::now().add(chrono::Duration::seconds(1));
+        let revision = CommittedRevision {
+            doc_id: doc.doc_id.as_str().to_string(),
+            revision_number: 3,
+            author_user_id: other_user_id.as_str().to_string(),
+            committed_at: time::date_time_iso_str(&committed_at),
+        };
+        UpdatedAtStreamHandler
+            .handle(&db.dynamodb_client, &revision)
+            .await?;
+        document_stats::update_document_stats(
+            &db.dynamodb_client,
+            &SessionUser {
+                user_id: other_user_id.clone(),
+                ..session_user.clone()
+            },
+            &UpdateDocumentStatsRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                revision_number: 3,
+                word_count: 42,
+                character_count: 200,
+                paragraph_count: 4,
+            },
+        )
+        .await?;
+        let response = list().await?;
+        assert_eq!(response.documents[0].last_revision_number, 3);
+        assert_eq!(response.documents[0].word_count, 42);
+        assert_eq!(
+            response.documents[0].last_edited_by_user_id,
+            other_user_id.as_str()
+        );
+        assert_eq!(
+            response.documents[0].updated_at,
+            time::date_time_iso_str(&committed_at)
+        );
+
+        // Handling an older revision again does not move the document backwards.
+        let old_revision = CommittedRevision {
+            revision_number: 2,
+            author_user_id: user_id.as_str().to_string(),
+            ..revision.clone()
+        };
+        UpdatedAtStreamHandler
+            .handle(&db.dynamodb_client, &old_revision)
+            .await?;
+        let response = list().await?;
+        assert_eq!(response.documents[0].last_revision_number, 3);
+        assert_eq!(
+            response.documents[0].last_edited_by_user_id,
+            other_user_id.as_str()
+        );
+
         Ok(())
     }
 }
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rusoto_dynamodb::ConsumedCapacity;
+
+use crate::dynamodb::request::current_endpoint;
+
+/// How often the consumed capacity is logged and reset.
+pub const CAPACITY_METRICS_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Sums the read and write capacity units that DynamoDB reports as consumed, per endpoint and per
+/// table, so that provisioned throughput can be sized from what the tables actually use.
+///
+/// Totals are kept in memory, so they are per server.
+#[derive(Default)]
+pub struct CapacityMetrics {
+    units: Mutex<HashMap<(String, String), CapacityUnits>>,
+    // What the last `take` returned, for the admin dashboard.
+    last_interval: Mutex<Vec<CapacityUsage>>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct CapacityUnits {
+    read: f64,
+    write: f64,
+}
+
+/// The capacity units that one endpoint consumed on one table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CapacityUsage {
+    pub endpoint: String,
+    pub table_name: String,
+    pub read_capacity_units: f64,
+    pub write_capacity_units: f64,
+}
+
+impl CapacityMetrics {
+    /// Records the capacity that a read (get, query, scan or batch get) consumed.
+    pub fn record_read(&self, consumed_capacity: &ConsumedCapacity) {
+        self.record(consumed_capacity, |units, capacity_units| {
+            units.read += capacity_units
+        });
+    }
+
+    /// Records the capacity that a write (put, update, delete, batch write or transaction)
+    /// consumed.
+    pub fn record_write(&self, consumed_capacity: &ConsumedCapacity) {
+        self.record(consumed_capacity, |units, capacity_units| {
+            units.write += capacity_units
+        });
+    }
+
+    fn record(&self, consumed_capacity: &ConsumedCapacity, add: impl Fn(&mut CapacityUnits, f64)) {
+        let capacity_units = match consumed_capacity.capacity_units {
+            Some(capacity_units) if capacity_units > 0.0 => capacity_units,
+            _ => return,
+        };
+        let table_name = consumed_capacity.table_name.clone().unwrap_or_default();
+        let mut units = self.units.lock().unwrap();
+        add(
+            units.entry((current_endpoint(), table_name)).or_default(),
+            capacity_units,
+        );
+    }
+
+    /// Returns the capacity consumed since the last `take`, sorted by endpoint and table, and
+    /// resets it.
+    pub fn take(&self) -> Vec<CapacityUsage> {
+        let units = std::mem::take(&mut *self.units.lock().unwrap());
+        let mut usage: Vec<CapacityUsage> = units
+            .into_iter()
+            .map(|((endpoint, table_name), units)| CapacityUsage {
+                endpoint,
+                table_name,
+                read_capacity_units: units.read,
+                write_capacity_units: units.write,
+            })
+            .collect();
+        usage.sort_by(|a, b| (&a.endpoint, &a.table_name).cmp(&(&b.endpoint, &b.table_name)));
+        *self.last_interval.lock().unwrap() = usage.clone();
+        usage
+    }
+
+    /// The capacity as of the last `take`, which the reporter calls once per
+    /// `CAPACITY_METRICS_REPORT_INTERVAL`. Empty before the first one.
+    pub fn last_interval(&self) -> Vec<CapacityUsage> {
+        self.last_interval.lock().unwrap().clone()
+    }
+}
+
+/// Adds up the usage of every endpoint, per table, sorted by table.
+pub fn usage_per_table(usage: &[CapacityUsage]) -> Vec<(String, f64, f64)> {
+    let mut per_table: HashMap<&str, (f64, f64)> = HashMap::new();
+    for entry in usage {
+        let units = per_table.entry(entry.table_name.as_str()).or_default();
+        units.0 += entry.read_capacity_units;
+        units.1 += entry.write_capacity_units;
+    }
+    let mut per_table: Vec<(String, f64, f64)> = per_table
+        .into_iter()
+        .map(|(table_name, (read, write))| (table_name.to_string(), read, write))
+        .collect();
+    per_table.sort_by(|a, b| a.0.cmp(&b.0));
+    per_table
+}
+
+/// Logs and resets the consumed capacity every `CAPACITY_METRICS_REPORT_INTERVAL`, as average
+/// units per second so that it compares directly with provisioned throughput. Quiet intervals are
+/// not logged.
+pub async fn run_capacity_metrics_reporter(capacity_metrics: Arc<CapacityMetrics>) {
+    let seconds = CAPACITY_METRICS_REPORT_INTERVAL.as_secs_f64();
+    let mut interval = tokio::time::interval(CAPACITY_METRICS_REPORT_INTERVAL);
+    loop {
+        interval.tick().await;
+        let usage = capacity_metrics.take();
+        for (table_name, read, write) in usage_per_table(&usage) {
+            log::info!(
+                "DynamoDB capacity: {} used {:.2} RCU/s, {:.2} WCU/s",
+                table_name,
+                read / seconds,
+                write / seconds
+            );
+        }
+        for entry in usage {
+            log::info!(
+                "DynamoDB capacity: {} used {:.2} RCU/s, {:.2} WCU/s on {}",
+                entry.endpoint,
+                entry.read_capacity_units / seconds,
+                entry.write_capacity_units / seconds,
+                entry.table_name
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::dynamodb::request::{with_endpoint, BACKGROUND_ENDPOINT};
+
+    fn consumed(table_name: &str, capacity_units: f64) -> ConsumedCapacity {
+        ConsumedCapacity {
+            table_name: Some(table_name.to_string()),
+            capacity_units: Some(capacity_units),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_take() {
+        let capacity_metrics = CapacityMetrics::default();
+        assert!(capacity_metrics.take().is_empty());
+
+        capacity_metrics.record_read(&consumed("users", 0.5));
+        with_endpoint("/api/documents.get_document".to_string(), async {
+            capacity_metrics.record_read(&consumed("documents", 1.0));
+            capacity_metrics.record_read(&consumed("documents", 2.5));
+            capacity_metrics.record_write(&consumed("documents", 1.0));
+            capacity_metrics.record_read(&consumed("users", 0.5));
+        })
+        .await;
+        let usage = capacity_metrics.take();
+        assert_eq!(
+            usage,
+            vec![
+                CapacityUsage {
+                    endpoint: "/api/documents.get_document".to_string(),
+                    table_name: "documents".to_string(),
+                    read_capacity_units: 3.5,
+                    write_capacity_units: 1.0,
+                },
+                CapacityUsage {
+                    endpoint: "/api/documents.get_document".to_string(),
+                    table_name: "users".to_string(),
+                    read_capacity_units: 0.5,
+                    write_capacity_units: 0.0,
+                },
+                CapacityUsage {
+                    endpoint: BACKGROUND_ENDPOINT.to_string(),
+                    table_name: "users".to_string(),
+                    read_capacity_units: 0.5,
+                    write_capacity_units: 0.0,
+                },
+            ]
+        );
+        assert_eq!(
+            usage_per_table(&usage),
+            vec![
+                ("documents".to_string(), 3.5, 1.0),
+                ("users".to_string(), 1.0, 0.0),
+            ]
+        );
+        assert_eq!(capacity_metrics.last_interval(), usage);
+
+        // Taking resets the totals.
+        assert!(capacity_metrics.take().is_empty());
+    }
+}
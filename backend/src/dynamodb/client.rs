@@ -0,0 +1,323 @@
+use std::future::Future;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use rusoto_core::{Region, RusotoError};
+use rusoto_dynamodb::{
+    BatchGetItemError, BatchGetItemInput, BatchGetItemOutput, BatchWriteItemError,
+    BatchWriteItemInput, BatchWriteItemOutput, DeleteItemError, DeleteItemInput, DeleteItemOutput,
+    DynamoDb, GetItemError, GetItemInput, GetItemOutput, PutItemError, PutItemInput, PutItemOutput,
+    QueryError, QueryInput, QueryOutput, ScanError, ScanInput, ScanOutput, TransactWriteItemsError,
+    TransactWriteItemsInput, TransactWriteItemsOutput, UpdateItemError, UpdateItemInput,
+    UpdateItemOutput,
+};
+
+use crate::dynamodb::capacity::CapacityMetrics;
+use crate::dynamodb::request;
+use crate::dynamodb::retry::{self, CircuitBreaker, ThrottlingError};
+
+/// Asks DynamoDB to report the capacity each call consumed, in total across the table and its
+/// indexes.
+const RETURN_CONSUMED_CAPACITY: &str = "TOTAL";
+
+/// A DynamoDB client that records the capacity consumed by every item read and write in its
+/// `CapacityMetrics`, and retries the ones that DynamoDB throttles. See `call_with_retries`.
+///
+/// The item operations that the backend uses are methods of the client itself. Anything else,
+/// like describing tables, goes straight to the underlying rusoto client, and is not metered.
+/// Neither are calls that fail, including failed conditions, since DynamoDB reports no capacity
+/// for them.
+#[derive(Clone)]
+pub struct DynamoDbClient {
+    inner: rusoto_dynamodb::DynamoDbClient,
+    capacity_metrics: Arc<CapacityMetrics>,
+    circuit_breaker: Arc<CircuitBreaker>,
+}
+
+impl DynamoDbClient {
+    pub fn new(region: Region) -> Self {
+        Self::from_client(rusoto_dynamodb::DynamoDbClient::new(region))
+    }
+
+    pub fn from_client(inner: rusoto_dynamodb::DynamoDbClient) -> Self {
+        Self {
+            inner,
+            capacity_metrics: Arc::new(CapacityMetrics::default()),
+            circuit_breaker: Arc::new(CircuitBreaker::default()),
+        }
+    }
+
+    pub fn capacity_metrics(&self) -> &Arc<CapacityMetrics> {
+        &self.capacity_metrics
+    }
+
+    pub async fn get_item(
+        &self,
+        mut input: GetItemInput,
+    ) -> Result<GetItemOutput, RusotoError<GetItemError>> {
+        input
+            .return_consumed_capacity
+            .get_or_insert_with(|| RETURN_CONSUMED_CAPACITY.to_string());
+        let table_name = input.table_name.clone();
+        let output = self
+            .call_with_retries("GetItem", &table_name, input, |input| {
+                self.inner.get_item(input)
+            })
+            .await?;
+        if let Some(consumed_capacity) = &output.consumed_capacity {
+            self.capacity_metrics.record_read(consumed_capacity);
+        }
+        Ok(output)
+    }
+
+    pub async fn query(
+        &self,
+        mut input: QueryInput,
+    ) -> Result<QueryOutput, RusotoError<QueryError>> {
+        input
+            .return_consumed_capacity
+            .get_or_insert_with(|| RETURN_CONSUMED_CAPACITY.to_string());
+        let table_name = input.table_name.clone();
+        let output = self
+            .call_with_retries("Query", &table_name, input, |input| self.inner.query(input))
+            .await?;
+        if let Some(consumed_capacity) = &output.consumed_capacity {
+            self.capacity_metrics.record_read(consumed_capacity);
+        }
+        Ok(output)
+    }
+
+    pub async fn scan(&self, mut input: ScanInput) -> Result<ScanOutput, RusotoError<ScanError>> {
+        input
+            .return_consumed_capacity
+            .get_or_insert_with(|| RETURN_CONSUMED_CAPACITY.to_string());
+        let table_name = input.table_name.clone();
+        let output = self
+            .call_with_retries("Scan", &table_name, input, |input| self.inner.scan(input))
+            .await?;
+        if let Some(consumed_capacity) = &output.consumed_capacity {
+            self.capacity_metrics.record_read(consumed_capacity);
+        }
+        Ok(output)
+    }
+
+    pub async fn batch_get_item(
+        &self,
+        mut input: BatchGetItemInput,
+    ) -> Result<BatchGetItemOutput, RusotoError<BatchGetItemError>> {
+        input
+            .return_consumed_capacity
+            .get_or_insert_with(|| RETURN_CONSUMED_CAPACITY.to_string());
+        let table_name = batch_table_name(input.request_items.keys());
+        let output = self
+            .call_with_retries("BatchGetItem", &table_name, input, |input| {
+                self.inner.batch_get_item(input)
+            })
+            .await?;
+        for consumed_capacity in output.consumed_capacity.iter().flatten() {
+            self.capacity_metrics.record_read(consumed_capacity);
+        }
+        Ok(output)
+    }
+
+    pub async fn put_item(
+        &self,
+        mut input: PutItemInput,
+    ) -> Result<PutItemOutput, RusotoError<PutItemError>> {
+        input
+            .return_consumed_capacity
+            .get_or_insert_with(|| RETURN_CONSUMED_CAPACITY.to_string());
+        let table_name = input.table_name.clone();
+        let output = self
+            .call_with_retries("PutItem", &table_name, input, |input| {
+                self.inner.put_item(input)
+            })
+            .await?;
+        if let Some(consumed_capacity) = &output.consumed_capacity {
+            self.capacity_metrics.record_write(consumed_capacity);
+        }
+        Ok(output)
+    }
+
+    pub async fn update_item(
+        &self,
+        mut input: UpdateItemInput,
+    ) -> Result<UpdateItemOutput, RusotoError<UpdateItemError>> {
+        input
+            .return_consumed_capacity
+            .get_or_insert_with(|| RETURN_CONSUMED_CAPACITY.to_string());
+        let table_name = input.table_name.clone();
+        let output = self
+            .call_with_retries("UpdateItem", &table_name, input, |input| {
+                self.inner.update_item(input)
+            })
+            .await?;
+        if let Some(consumed_capacity) = &output.consumed_capacity {
+            self.capacity_metrics.record_write(consumed_capacity);
+        }
+        Ok(output)
+    }
+
+    pub async fn delete_item(
+        &self,
+        mut input: DeleteItemInput,
+    ) -> Result<DeleteItemOutput, RusotoError<DeleteItemError>> {
+        input
+            .return_consumed_capacity
+            .get_or_insert_with(|| RETURN_CONSUMED_CAPACITY.to_string());
+        let table_name = input.table_name.clone();
+        let output = self
+            .call_with_retries("DeleteItem", &table_name, input, |input| {
+                self.inner.delete_item(input)
+            })
+            .await?;
+        if let Some(consumed_capacity) = &output.consumed_capacity {
+            self.capacity_metrics.record_write(consumed_capacity);
+        }
+        Ok(output)
+    }
+
+    pub async fn batch_write_item(
+        &self,
+        mut input: BatchWriteItemInput,
+    ) -> Result<BatchWriteItemOutput, RusotoError<BatchWriteItemError>> {
+        input
+            .return_consumed_capacity
+            .get_or_insert_with(|| RETURN_CONSUMED_CAPACITY.to_string());
+        let table_name = batch_table_name(input.request_items.keys());
+        let output = self
+            .call_with_retries("BatchWriteItem", &table_name, input, |input| {
+                self.inner.batch_write_item(input)
+            })
+            .await?;
+        for consumed_capacity in output.consumed_capacity.iter().flatten() {
+            self.capacity_metrics.record_write(consumed_capacity);
+        }
+        Ok(output)
+    }
+
+    pub async fn transact_write_items(
+        &self,
+        mut input: TransactWriteItemsInput,
+    ) -> Result<TransactWriteItemsOutput, RusotoError<TransactWriteItemsError>> {
+        input
+            .return_consumed_capacity
+            .get_or_insert_with(|| RETURN_CONSUMED_CAPACITY.to_string());
+        let table_name = transact_table_name(&input);
+        let output = self
+            .call_with_retries("TransactWriteItems", &table_name, input, |input| {
+                self.inner.transact_write_items(input)
+            })
+            .await?;
+        for consumed_capacity in output.consumed_capacity.iter().flatten() {
+            self.capacity_metrics.record_write(consumed_capacity);
+        }
+        Ok(output)
+    }
+
+    /// Makes the call, and retries it with backoff while DynamoDB throttles it, up to
+    /// `MAX_ATTEMPTS` times and within the current request's retry budget. Fails straight away
+    /// while the table's circuit is open.
+    async fn call_with_retries<I, O, E, F, Fut>(
+        &self,
+        operation: &str,
+        table_name: &str,
+        input: I,
+        call: F,
+    ) -> Result<O, RusotoError<E>>
+    where
+        I: Clone,
+        E: ThrottlingError,
+        F: Fn(I) -> Fut,
+        Fut: Future<Output = Result<O, RusotoError<E>>>,
+    {
+        let mut attempt = 1;
+        loop {
+            if self.circuit_breaker.is_open(table_name) {
+                return Err(RusotoError::Service(E::circuit_open(table_name)));
+            }
+            let result = call(input.clone()).await;
+            match &result {
+                Err(e) if retry::is_throttling(e) => {}
+                _ => return result,
+            }
+            if self.circuit_breaker.record_throttle(table_name) {
+                log::error!(
+                    "DynamoDB circuit opened: table={} operation={} endpoint={} cooldown_ms={}",
+                    table_name,
+                    operation,
+                    request::current_endpoint(),
+                    retry::CIRCUIT_BREAKER_COOLDOWN.as_millis()
+                );
+                return result;
+            }
+            if attempt >= retry::MAX_ATTEMPTS {
+                log::warn!(
+                    "DynamoDB throttled, giving up: table={} operation={} endpoint={} attempts={}",
+                    table_name,
+                    operation,
+                    request::current_endpoint(),
+                    attempt
+                );
+                return result;
+            }
+            let delay = retry::backoff_delay(attempt);
+            if !request::spend_retry_budget(delay) {
+                log::warn!(
+                    "DynamoDB throttled, retry budget spent: table={} operation={} endpoint={} \
+                     attempts={}",
+                    table_name,
+                    operation,
+                    request::current_endpoint(),
+                    attempt
+                );
+                return result;
+            }
+            log::info!(
+                "DynamoDB throttled, retrying: table={} operation={} endpoint={} attempt={} \
+                 delay_ms={}",
+                table_name,
+                operation,
+                request::current_endpoint(),
+                attempt,
+                delay.as_millis()
+            );
+            tokio::time::delay_for(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+impl Deref for DynamoDbClient {
+    type Target = rusoto_dynamodb::DynamoDbClient;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+/// The table that a batch call is counted against for throttling. The backend's batches each
+/// read or write a single table.
+fn batch_table_name<'a>(mut table_names: impl Iterator<Item = &'a String>) -> String {
+    table_names.next().cloned().unwrap_or_default()
+}
+
+/// The table that a transaction is counted against for throttling: the table of its first item.
+fn transact_table_name(input: &TransactWriteItemsInput) -> String {
+    let item = match input.transact_items.first() {
+        Some(item) => item,
+        None => return String::new(),
+    };
+    let table_name = item
+        .put
+        .as_ref()
+        .map(|put| &put.table_name)
+        .or_else(|| item.update.as_ref().map(|update| &update.table_name))
+        .or_else(|| item.delete.as_ref().map(|delete| &delete.table_name))
+        .or_else(|| {
+            item.condition_check
+                .as_ref()
+                .map(|condition_check| &condition_check.table_name)
+        });
+    table_name.cloned().unwrap_or_default()
+}
@@ -0,0 +1,264 @@
+mod capacity;
+mod client;
+mod request;
+mod retry;
+
+pub use capacity::{
+    run_capacity_metrics_reporter, usage_per_table, CapacityMetrics, CapacityUsage,
+    CAPACITY_METRICS_REPORT_INTERVAL,
+};
+pub use client::DynamoDbClient;
+pub use request::with_endpoint;
+
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{
+    AttributeValue, BatchGetItemError, BatchGetItemInput, BatchWriteItemError, BatchWriteItemInput,
+    DeleteRequest, KeysAndAttributes, QueryError, QueryInput, ScanError, ScanInput, WriteRequest,
+};
+
+/// In production and staging, DynamoDB table names have a prefix, namely "staging-" and
+/// "production-".
+#[cfg(not(test))]
+pub fn table_name(base_table_name: &str) -> String {
+    format!(
+        "{}-{}",
+        &crate::config::config().dynamodb_env,
+        base_table_name
+    )
+}
+
+/// In local testing, DynamoDB table names have a prefix that depends on the current test thread,
+/// like "test4-". This allows database changes in one test thread to be isolated from database
+/// changes in another.
+#[cfg(test)]
+pub fn table_name(base_table_name: &str) -> String {
+    let shard = crate::testing::utils::current_test_thread_dynamodb_shard();
+    test_table_name(shard, base_table_name)
+}
+
+#[cfg(test)]
+pub fn test_table_name(test_shard: i32, base_table_name: &str) -> String {
+    format!("test{}-{}", test_shard, base_table_name)
+}
+
+/// Shorthand to create `AttributeValue` entry with string type `S`.
+pub fn av_s(key: &str, value: &str) -> (String, AttributeValue) {
+    (
+        String::from(key),
+        AttributeValue {
+            s: Some(String::from(value)),
+            ..Default::default()
+        },
+    )
+}
+
+/// Shorthand to create `AttributeValue` entry with number type `N`.
+pub fn av_n<T>(key: &str, number: T) -> (String, AttributeValue)
+where
+    T: std::string::ToString,
+{
+    (
+        String::from(key),
+        AttributeValue {
+            n: Some(number.to_string()),
+            ..Default::default()
+        },
+    )
+}
+
+/// Shorthand to create `AttributeValue` entry with binary type `B`.
+pub fn av_b(key: &str, binary: Bytes) -> (String, AttributeValue) {
+    (
+        String::from(key),
+        AttributeValue {
+            b: Some(binary),
+            ..Default::default()
+        },
+    )
+}
+
+/// Shorthand to create `AttributeValue` entry with string set type `SS`. DynamoDB rejects empty
+/// sets.
+pub fn av_ss(key: &str, values: &[String]) -> (String, AttributeValue) {
+    (
+        String::from(key),
+        AttributeValue {
+            ss: Some(values.to_vec()),
+            ..Default::default()
+        },
+    )
+}
+
+/// Shorthand. Turn an array of `AttributeValue` entries into a hash map.
+///
+/// eg.
+/// ```
+/// let input = GetItemInput {
+///     key: av_map(&[
+///         av_s("my_hash_key", "id123"),
+///         av_s("my_range_key", "id456"),
+///     ])
+/// }
+/// ```
+pub fn av_map(arr: &[(String, AttributeValue)]) -> HashMap<String, AttributeValue> {
+    arr.iter().cloned().collect()
+}
+
+/// Shorthand. Retrieve the `S` string value for a given key in a Dynamo item.
+pub fn av_get_s<'a>(item: &'a HashMap<String, AttributeValue>, key: &str) -> Option<&'a str> {
+    let attribute_value = item.get(key)?;
+    let s_value = attribute_value.s.as_ref()?;
+    Some(s_value.as_str())
+}
+
+/// Shorthand. Retrieve the `N` value for a given key in a Dynamo item, and parse to a number.
+pub fn av_get_n<T>(item: &HashMap<String, AttributeValue>, key: &str) -> Option<T>
+where
+    T: std::str::FromStr,
+{
+    let attribute_value = item.get(key)?;
+    let n_value = attribute_value.n.as_ref()?;
+    let number = n_value.parse::<T>().ok()?;
+    Some(number)
+}
+
+/// Shorthand. Retrieve the `B` binary value for a given ey in a Dynamo item.
+pub fn av_get_b<'a>(item: &'a HashMap<String, AttributeValue>, key: &str) -> Option<&'a Bytes> {
+    let attribute_value = item.get(key)?;
+    let b_value = attribute_value.b.as_ref()?;
+    Some(b_value)
+}
+
+/// DynamoDB allows at most this many keys in a single `BatchGetItem` request.
+pub const BATCH_GET_ITEM_MAX_KEYS: usize = 100;
+
+/// Reads all of the items with the given keys from one table, splitting the keys into as many
+/// `BatchGetItem` requests as needed and retrying any unprocessed keys.
+///
+/// Items that do not exist are simply missing from the result. Results are in no particular order.
+pub async fn batch_get_all_items(
+    dynamodb_client: &DynamoDbClient,
+    table_name: &str,
+    keys: Vec<HashMap<String, AttributeValue>>,
+    projection_expression: &str,
+) -> Result<Vec<HashMap<String, AttributeValue>>, RusotoError<BatchGetItemError>> {
+    let mut items = Vec::new();
+    let mut pending_keys = keys;
+    while !pending_keys.is_empty() {
+        let split_at = pending_keys.len().saturating_sub(BATCH_GET_ITEM_MAX_KEYS);
+        let batch_keys = pending_keys.split_off(split_at);
+        let mut request_items = HashMap::new();
+        request_items.insert(
+            table_name.to_string(),
+            KeysAndAttributes {
+                keys: batch_keys,
+                projection_expression: Some(projection_expression.to_string()),
+                ..Default::default()
+            },
+        );
+        let output = dynamodb_client
+            .batch_get_item(BatchGetItemInput {
+                request_items,
+                ..Default::default()
+            })
+            .await?;
+        if let Some(mut responses) = output.responses {
+            if let Some(table_items) = responses.remove(table_name) {
+                items.extend(table_items);
+            }
+        }
+        // DynamoDB may return early without reading every key, e.g. when throttled. Try those
+        // keys again after a short pause.
+        if let Some(mut unprocessed_keys) = output.unprocessed_keys {
+            if let Some(keys_and_attributes) = unprocessed_keys.remove(table_name) {
+                if !keys_and_attributes.keys.is_empty() {
+                    pending_keys.extend(keys_and_attributes.keys);
+                    tokio::time::delay_for(std::time::Duration::from_millis(50)).await;
+                }
+            }
+        }
+    }
+    Ok(items)
+}
+
+/// DynamoDB allows at most this many puts and deletes in a single `BatchWriteItem` request.
+pub const BATCH_WRITE_ITEM_MAX_REQUESTS: usize = 25;
+
+/// Deletes all of the items with the given keys from one table, splitting the keys into as many
+/// `BatchWriteItem` requests as needed and retrying any unprocessed deletes.
+///
+/// Deleting an item that does not exist has no effect.
+pub async fn batch_delete_all_items(
+    dynamodb_client: &DynamoDbClient,
+    table_name: &str,
+    keys: Vec<HashMap<String, AttributeValue>>,
+) -> Result<(), RusotoError<BatchWriteItemError>> {
+    let mut pending_requests: Vec<WriteRequest> = keys
+        .into_iter()
+        .map(|key| WriteRequest {
+            delete_request: Some(DeleteRequest { key }),
+            ..Default::default()
+        })
+        .collect();
+    while !pending_requests.is_empty() {
+        let split_at = pending_requests
+            .len()
+            .saturating_sub(BATCH_WRITE_ITEM_MAX_REQUESTS);
+        let batch_requests = pending_requests.split_off(split_at);
+        let mut request_items = HashMap::new();
+        request_items.insert(table_name.to_string(), batch_requests);
+        let output = dynamodb_client
+            .batch_write_item(BatchWriteItemInput {
+                request_items,
+                ..Default::default()
+            })
+            .await?;
+        // As with `batch_get_all_items`, try anything DynamoDB skipped again after a short pause.
+        if let Some(mut unprocessed_items) = output.unprocessed_items {
+            if let Some(requests) = unprocessed_items.remove(table_name) {
+                if !requests.is_empty() {
+                    pending_requests.extend(requests);
+                    tokio::time::delay_for(std::time::Duration::from_millis(50)).await;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs the query to the end, following `last_evaluated_key` through every page of results.
+pub async fn query_all_items(
+    dynamodb_client: &DynamoDbClient,
+    input: QueryInput,
+) -> Result<Vec<HashMap<String, AttributeValue>>, RusotoError<QueryError>> {
+    let mut items = Vec::new();
+    let mut input = input;
+    loop {
+        let output = dynamodb_client.query(input.clone()).await?;
+        items.extend(output.items.unwrap_or_default());
+        if output.last_evaluated_key.is_none() {
+            return Ok(items);
+        }
+        input.exclusive_start_key = output.last_evaluated_key;
+    }
+}
+
+/// Runs the scan to the end, following `last_evaluated_key` through every page of results.
+pub async fn scan_all_items(
+    dynamodb_client: &DynamoDbClient,
+    input: ScanInput,
+) -> Result<Vec<HashMap<String, AttributeValue>>, RusotoError<ScanError>> {
+    let mut items = Vec::new();
+    let mut input = input;
+    loop {
+        let output = dynamodb_client.scan(input.clone()).await?;
+        items.extend(output.items.unwrap_or_default());
+        if output.last_evaluated_key.is_none() {
+            return Ok(items);
+        }
+        input.exclusive_start_key = output.last_evaluated_key;
+    }
+}
@@ -0,0 +1,82 @@
+use std::cell::Cell;
+use std::future::Future;
+use std::time::Duration;
+
+/// DynamoDB calls made outside of an HTTP request, e.g. by job workers and the digest sender, are
+/// counted under this endpoint.
+pub const BACKGROUND_ENDPOINT: &str = "background";
+
+/// The longest that all of the DynamoDB calls of one HTTP request may spend backing off from
+/// throttling, in total. Past that, the caller is better served by an error than by a slow
+/// response. Background work has no such budget.
+pub const RETRY_BUDGET_PER_REQUEST: Duration = Duration::from_secs(1);
+
+struct RequestScope {
+    // The route pattern of the HTTP request.
+    endpoint: String,
+    retry_budget: Cell<Duration>,
+}
+
+tokio::task_local! {
+    // The HTTP request that the current task is handling.
+    static REQUEST: RequestScope;
+}
+
+/// Runs the future as the handling of an HTTP request to the given endpoint. Its DynamoDB calls
+/// are attributed to the endpoint, and share one retry budget. Tasks spawned from it are not, and
+/// count as background.
+pub async fn with_endpoint<F: Future>(endpoint: String, future: F) -> F::Output {
+    let scope = RequestScope {
+        endpoint,
+        retry_budget: Cell::new(RETRY_BUDGET_PER_REQUEST),
+    };
+    REQUEST.scope(scope, future).await
+}
+
+pub(super) fn current_endpoint() -> String {
+    REQUEST
+        .try_with(|scope| scope.endpoint.clone())
+        .unwrap_or_else(|_| BACKGROUND_ENDPOINT.to_string())
+}
+
+/// Takes the delay out of the current request's retry budget. Returns false, and takes nothing,
+/// if the budget cannot cover it.
+pub(super) fn spend_retry_budget(delay: Duration) -> bool {
+    REQUEST
+        .try_with(|scope| match scope.retry_budget.get().checked_sub(delay) {
+            Some(remaining) => {
+                scope.retry_budget.set(remaining);
+                true
+            }
+            None => false,
+        })
+        .unwrap_or(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_spend_retry_budget() {
+        // Background work is not budgeted.
+        assert!(spend_retry_budget(RETRY_BUDGET_PER_REQUEST * 10));
+        assert_eq!(current_endpoint(), BACKGROUND_ENDPOINT);
+
+        with_endpoint("/api/documents.get_document".to_string(), async {
+            assert_eq!(current_endpoint(), "/api/documents.get_document");
+            assert!(spend_retry_budget(RETRY_BUDGET_PER_REQUEST / 2));
+            assert!(!spend_retry_budget(RETRY_BUDGET_PER_REQUEST));
+            // A refused delay takes nothing, so a smaller one still fits.
+            assert!(spend_retry_budget(RETRY_BUDGET_PER_REQUEST / 2));
+            assert!(!spend_retry_budget(Duration::from_millis(1)));
+        })
+        .await;
+
+        // Each request has its own budget.
+        with_endpoint("/api/documents.get_document".to_string(), async {
+            assert!(spend_retry_budget(RETRY_BUDGET_PER_REQUEST));
+        })
+        .await;
+    }
+}
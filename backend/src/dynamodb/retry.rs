@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{
+    BatchGetItemError, BatchWriteItemError, DeleteItemError, GetItemError, PutItemError,
+    QueryError, ScanError, TransactWriteItemsError, UpdateItemError,
+};
+
+/// A throttled call is made at most this many times, counting the first.
+pub const MAX_ATTEMPTS: u32 = 4;
+
+/// The backoff before the first retry is up to this long, and doubles for each one after it.
+const BASE_BACKOFF: Duration = Duration::from_millis(25);
+const MAX_BACKOFF: Duration = Duration::from_millis(800);
+
+/// When a table is throttled this many times within a window, its circuit opens: calls to it fail
+/// straight away, without reaching DynamoDB, until the cooldown has passed. Retrying against a
+/// table that stays throttled only adds to its load, and holds requests open for nothing.
+pub const CIRCUIT_BREAKER_THRESHOLD: u32 = 50;
+pub const CIRCUIT_BREAKER_WINDOW: Duration = Duration::from_secs(10);
+pub const CIRCUIT_BREAKER_COOLDOWN: Duration = Duration::from_secs(5);
+
+/// The errors of the item operations, which DynamoDB reports throttling through.
+pub trait ThrottlingError: Sized {
+    /// True if DynamoDB turned the call away for exceeding the table's provisioned throughput or
+    /// the account's request limit. Trying again later may succeed.
+    fn is_throttling(&self) -> bool;
+
+    /// The error for a call that was not made, because the table's circuit is open.
+    fn circuit_open(table_name: &str) -> Self;
+}
+
+macro_rules! impl_throttling_error {
+    ($($error:ident),*) => {
+        $(
+            impl ThrottlingError for $error {
+                fn is_throttling(&self) -> bool {
+                    matches!(
+                        self,
+                        $error::ProvisionedThroughputExceeded(_) | $error::RequestLimitExceeded(_)
+                    )
+                }
+
+                fn circuit_open(table_name: &str) -> Self {
+                    $error::ProvisionedThroughputExceeded(format!(
+                        "Circuit open after sustained throttling of table {}",
+                        table_name
+                    ))
+                }
+            }
+        )*
+    };
+}
+
+impl_throttling_error!(
+    BatchGetItemError,
+    BatchWriteItemError,
+    DeleteItemError,
+    GetItemError,
+    PutItemError,
+    QueryError,
+    ScanError,
+    TransactWriteItemsError,
+    UpdateItemError
+);
+
+pub fn is_throttling<E: ThrottlingError>(error: &RusotoError<E>) -> bool {
+    match error {
+        RusotoError::Service(e) => e.is_throttling(),
+        _ => false,
+    }
+}
+
+/// How long to wait before the given retry, counting from 1. Exponential, with full jitter, so
+/// that callers throttled together do not all come back together.
+pub fn backoff_delay(retry: u32) -> Duration {
+    let max = BASE_BACKOFF
+        .checked_mul(1u32 << retry.saturating_sub(1).min(16))
+        .map_or(MAX_BACKOFF, |backoff| backoff.min(MAX_BACKOFF));
+    max.mul_f64(rand::random::<f64>())
+}
+
+/// Counts throttling per table, and opens a table's circuit when it is throttled too often.
+///
+/// State is kept in memory, so each server decides on its own.
+pub struct CircuitBreaker {
+    threshold: u32,
+    window: Duration,
+    cooldown: Duration,
+    tables: Mutex<HashMap<String, TableCircuit>>,
+}
+
+#[derive(Default)]
+struct TableCircuit {
+    window_started_at: Option<Instant>,
+    throttles: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, window: Duration, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            window,
+            cooldown,
+            tables: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// True if calls to the table should fail without being made.
+    pub fn is_open(&self, table_name: &str) -> bool {
+        let tables = self.tables.lock().unwrap();
+        match tables.get(table_name).and_then(|table| table.open_until) {
+            Some(open_until) => Instant::now() < open_until,
+            None => false,
+        }
+    }
+
+    /// Counts a throttled call to the table. Returns true if that opened its circuit.
+    pub fn record_throttle(&self, table_name: &str) -> bool {
+        let now = Instant::now();
+        let mut tables = self.tables.lock().unwrap();
+        let table = tables.entry(table_name.to_string()).or_default();
+        match table.window_started_at {
+            Some(started_at) if now.duration_since(started_at) < self.window => {}
+            _ => {
+                table.window_started_at = Some(now);
+                table.throttles = 0;
+            }
+        }
+        table.throttles += 1;
+        if table.throttles < self.threshold {
+            return false;
+        }
+        // Start counting afresh once the cooldown ends, so that a single throttle then does not
+        // open the circuit again.
+        table.open_until = Some(now + self.cooldown);
+        table.window_started_at = None;
+        true
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(
+            CIRCUIT_BREAKER_THRESHOLD,
+            CIRCUIT_BREAKER_WINDOW,
+            CIRCUIT_BREAKER_COOLDOWN,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_throttling() {
+        let throttled: RusotoError<GetItemError> =
+            RusotoError::Service(GetItemError::ProvisionedThroughputExceeded(String::new()));
+        assert!(is_throttling(&throttled));
+        let limited: RusotoError<QueryError> =
+            RusotoError::Service(QueryError::RequestLimitExceeded(String::new()));
+        assert!(is_throttling(&limited));
+        let not_found: RusotoError<GetItemError> =
+            RusotoError::Service(GetItemError::ResourceNotFound(String::new()));
+        assert!(!is_throttling(&not_found));
+        let circuit_open: RusotoError<PutItemError> =
+            RusotoError::Service(PutItemError::circuit_open("documents"));
+        assert!(is_throttling(&circuit_open));
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        for retry in 1..=20 {
+            let delay = backoff_delay(retry);
+            assert!(delay <= MAX_BACKOFF);
+            if retry == 1 {
+                assert!(delay <= BASE_BACKOFF);
+            }
+        }
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let circuit_breaker =
+            CircuitBreaker::new(3, Duration::from_secs(60), Duration::from_secs(60));
+        assert!(!circuit_breaker.is_open("documents"));
+        assert!(!circuit_breaker.record_throttle("documents"));
+        assert!(!circuit_breaker.record_throttle("documents"));
+        assert!(!circuit_breaker.is_open("documents"));
+        assert!(circuit_breaker.record_throttle("documents"));
+        assert!(circuit_breaker.is_open("documents"));
+        // Tables have separate circuits.
+        assert!(!circuit_breaker.is_open("users"));
+
+        // The circuit closes after the cooldown.
+        let circuit_breaker =
+            CircuitBreaker::new(1, Duration::from_secs(60), Duration::from_secs(0));
+        assert!(circuit_breaker.record_throttle("documents"));
+        assert!(!circuit_breaker.is_open("documents"));
+
+        // Throttles in different windows do not add up.
+        let circuit_breaker =
+            CircuitBreaker::new(2, Duration::from_secs(0), Duration::from_secs(60));
+        assert!(!circuit_breaker.record_throttle("documents"));
+        assert!(!circuit_breaker.record_throttle("documents"));
+        assert!(!circuit_breaker.is_open("documents"));
+    }
+}
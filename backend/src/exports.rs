@@ -0,0 +1,568 @@
+//! Org data exports.
+//!
+//! An org admin can export everything that we store about their org, for example to answer a data
+//! access request. Exports can take a while for big orgs, so they run in the background as a job
+//! in the `jobs` table, which records their progress. The finished archive is written to the
+//! export store, and admins download it from a pre-signed URL.
+//!
+//! The archive is a tar file containing:
+//!
+//! - `manifest.json`: the org id, when the export was made, and how many of each thing it holds.
+//! - `users.jsonl`: one JSON object per org member, with their profile and role.
+//! - `documents.jsonl`: one JSON object per document, with the users it is shared with and the
+//!   number of the last exported revision.
+//! - `share_tokens.jsonl`: one JSON object per share link. The tokens themselves are left out,
+//!   since anyone holding one could still use it.
+//! - `revisions/<doc_id>.pb`: the document's revision log, as length-delimited `DocumentRevision`
+//!   protobuf messages. Revisions of end-to-end encrypted documents stay encrypted.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::error;
+use futures::future::BoxFuture;
+use prost::Message;
+use rusoto_dynamodb::{AttributeValue, QueryInput, ScanInput};
+use serde_json::json;
+
+use ot::writing_proto::{
+    get_org_export_response::Status, GetOrgExportRequest, GetOrgExportResponse,
+    StartOrgExportRequest, StartOrgExportResponse,
+};
+
+use crate::blob_store::BlobStore;
+use crate::documents;
+use crate::dynamodb::{self, av_get_n, av_get_s, av_map, av_n, av_s, table_name, DynamoDbClient};
+use crate::http::SessionUser;
+use crate::jobs::{self, Job, JobHandler, JobStatus};
+use crate::users::UserRole;
+use crate::utils::time;
+
+/// The `job_type` of org export jobs in the `jobs` table.
+pub const ORG_EXPORT_JOB_TYPE: &str = "org_export";
+
+/// How long a download URL handed out by `get_org_export` stays valid.
+pub const DOWNLOAD_URL_EXPIRY: Duration = Duration::from_secs(60 * 60);
+
+/// Start exporting the session user's org. The export runs in the background as a job, handled by
+/// `OrgExportJobHandler`. Pass the returned job id to `get_org_export` to follow its progress.
+///
+/// If the session user is not an org admin, returns 403 Forbidden.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn start_org_export(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &StartOrgExportRequest,
+) -> actix_web::Result<StartOrgExportResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [start_org_export] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    if session_user.user_role != UserRole::OrgAdmin {
+        return Err(error::ErrorForbidden(""));
+    }
+    let job_id = jobs::create_job(dynamodb_client, ORG_EXPORT_JOB_TYPE, session_user, &[])
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    Ok(StartOrgExportResponse {
+        job_id: job_id.as_str().to_string(),
+    })
+}
+
+/// Get the progress of an export of the session user's org. Once the export is complete, the
+/// response includes a URL to download the archive from.
+///
+/// If the session user is not an org admin, returns 403 Forbidden.
+///
+/// If there is no export with the job id in the session user's org, returns 404 Not Found.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn get_org_export(
+    dynamodb_client: &DynamoDbClient,
+    export_store: &dyn BlobStore,
+    session_user: &SessionUser,
+    request: &GetOrgExportRequest,
+) -> actix_web::Result<GetOrgExportResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [get_org_export] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    if session_user.user_role != UserRole::OrgAdmin {
+        return Err(error::ErrorForbidden(""));
+    }
+    if request.job_id.is_empty() {
+        return Err(error::ErrorNotFound(""));
+    }
+    let job = jobs::get_job(dynamodb_client, &request.job_id)
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?
+        .ok_or_else(|| error::ErrorNotFound(""))?;
+    if job.job_type != ORG_EXPORT_JOB_TYPE || job.org_id != session_user.org_id.as_str() {
+        return Err(error::ErrorNotFound(""));
+    }
+    let mut response = GetOrgExportResponse {
+        job_id: request.job_id.clone(),
+        status: match job.job_status {
+            JobStatus::Pending => Status::Pending,
+            JobStatus::Running => Status::Running,
+            JobStatus::Complete => Status::Complete,
+            JobStatus::Failed => Status::Failed,
+        }
+        .into(),
+        documents_exported: job.progress_done,
+        documents_total: job.progress_total,
+        download_url: String::new(),
+    };
+    if job.job_status == JobStatus::Complete {
+        response.download_url = export_store
+            .download_url(&job.output_key, DOWNLOAD_URL_EXPIRY)
+            .await
+            .map_err(|e| {
+                log_error(e.to_string());
+                error::ErrorInternalServerError("")
+            })?;
+    }
+    Ok(response)
+}
+
+/// Runs the exports started by `start_org_export`, and writes their archives to the export store.
+pub struct OrgExportJobHandler {
+    pub export_store: Arc<dyn BlobStore>,
+}
+
+impl JobHandler for OrgExportJobHandler {
+    fn job_type(&self) -> &'static str {
+        ORG_EXPORT_JOB_TYPE
+    }
+
+    fn run<'a>(
+        &'a self,
+        dynamodb_client: &'a DynamoDbClient,
+        job: &'a Job,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(export_org(dynamodb_client, self.export_store.as_ref(), job))
+    }
+}
+
+async fn export_org(
+    dynamodb_client: &DynamoDbClient,
+    export_store: &dyn BlobStore,
+    job: &Job,
+) -> anyhow::Result<()> {
+    let job_id = job.job_id.as_str();
+    let org_id = job.org_id.as_str();
+
+    let exported_at = time::date_time_iso_str(&chrono::Utc::now());
+    let mut archive = tar::Builder::new(Vec::new());
+
+    let users = get_org_users(dynamodb_client, org_id).await?;
+    append_jsonl(&mut archive, "users.jsonl", &users)?;
+
+    // TODO(cliff): Scanning is fine while the tables are small. Once they are not, add indexes on
+    // org_id and query them instead.
+    let document_items = dynamodb::scan_all_items(
+        dynamodb_client,
+        ScanInput {
+            table_name: table_name("documents"),
+            filter_expression: Some(String::from("org_id = :org_id")),
+            expression_attribute_values: Some(av_map(&[av_s(":org_id", org_id)])),
+            consistent_read: Some(true),
+            ..Default::default()
+        },
+    )
+    .await?;
+    let share_token_items = dynamodb::scan_all_items(
+        dynamodb_client,
+        ScanInput {
+            table_name: table_name("share_tokens"),
+            filter_expression: Some(String::from("org_id = :org_id")),
+            expression_attribute_values: Some(av_map(&[av_s(":org_id", org_id)])),
+            ..Default::default()
+        },
+    )
+    .await?;
+    let share_tokens: Vec<serde_json::Value> = share_token_items
+        .iter()
+        .map(|item| {
+            json!({
+                "doc_id": json_s(item, "doc_id"),
+                "permission": json_n(item, "permission"),
+                "created_by_user_id": json_s(item, "created_by_user_id"),
+                "created_at": json_s(item, "created_at"),
+                "expires_at": json_s(item, "expires_at"),
+            })
+        })
+        .collect();
+    append_jsonl(&mut archive, "share_tokens.jsonl", &share_tokens)?;
+
+    jobs::update_job(
+        dynamodb_client,
+        job_id,
+        &[av_n(":progress_total", document_items.len())],
+    )
+    .await?;
+    let mut exported_documents = Vec::with_capacity(document_items.len());
+    let mut num_revisions = 0;
+    for (i, item) in document_items.iter().enumerate() {
+        let doc_id = av_get_s(item, "id").ok_or_else(|| anyhow::anyhow!("document has no id"))?;
+        let sharing_items = dynamodb::query_all_items(
+            dynamodb_client,
+            QueryInput {
+                table_name: table_name("document_user_sharing_permissions"),
+                key_condition_expression: Some(String::from("doc_id = :doc_id")),
+                expression_attribute_values: Some(av_map(&[av_s(":doc_id", doc_id)])),
+                ..Default::default()
+            },
+        )
+        .await?;
+        let sharing: Vec<serde_json::Value> = sharing_items
+            .iter()
+            .map(|item| {
+                json!({
+                    "user_id": json_s(item, "user_id"),
+                    "sharing_permission": json_n(item, "sharing_permission"),
+                    "created_at": json_s(item, "created_at"),
+                    "updated_at": json_s(item, "updated_at"),
+                })
+            })
+            .collect();
+        // Pin the revisions to the ones committed before we started reading them, so that the
+        // export holds a stable document even while edits continue.
+        let pinned_revision_number =
+            documents::get_last_revision_number(dynamodb_client, doc_id).await?;
+        exported_documents.push(json!({
+            "id": doc_id,
+            "title": json_s(item, "title"),
+            "created_by_user_id": json_s(item, "created_by_user_id"),
+            "owner_user_id": documents::owner_user_id_from_item(item),
+            "org_level_sharing_permission": json_n(item, "org_level_sharing_permission"),
+            "encryption_key_fingerprint": json_s(item, "encryption_key_fingerprint"),
+            "created_at": json_s(item, "created_at"),
+            "updated_at": json_s(item, "updated_at"),
+            "archived_at": json_s(item, "archived_at"),
+            "sharing": sharing,
+            "last_revision_number": pinned_revision_number,
+        }));
+
+        let revision_items = dynamodb::query_all_items(
+            dynamodb_client,
+            QueryInput {
+                table_name: table_name("document_revisions"),
+                consistent_read: Some(true),
+                key_condition_expression: Some(String::from(
+                    "doc_id = :doc_id AND revision_number <= :pinned_revision_number",
+                )),
+                expression_attribute_values: Some(av_map(&[
+                    av_s(":doc_id", doc_id),
+                    av_n(":pinned_revision_number", pinned_revision_number),
+                ])),
+                ..Default::default()
+            },
+        )
+        .await?;
+        let mut revisions_binary = Vec::new();
+        for revision_item in revision_items.iter() {
+            let revision = documents::document_revision_from_item(doc_id, revision_item)
+                .map_err(anyhow::Error::msg)?;
+            revision.encode_length_delimited(&mut revisions_binary)?;
+        }
+        num_revisions += revision_items.len();
+        append_file(
+            &mut archive,
+            &format!("revisions/{}.pb", doc_id),
+            &revisions_binary,
+        )?;
+
+        jobs::set_job_progress(dynamodb_client, job_id, i as i64 + 1).await?;
+    }
+    append_jsonl(&mut archive, "documents.jsonl", &exported_documents)?;
+
+    let manifest = json!({
+        "org_id": org_id,
+        "job_id": job_id,
+        "exported_at": exported_at,
+        "num_users": users.len(),
+        "num_documents": exported_documents.len(),
+        "num_revisions": num_revisions,
+        "num_share_tokens": share_tokens.len(),
+    });
+    append_file(
+        &mut archive,
+        "manifest.json",
+        manifest.to_string().as_bytes(),
+    )?;
+
+    let output_key = format!("org_exports/{}/{}.tar", org_id, job_id);
+    export_store
+        .put(&output_key, "application/x-tar", archive.into_inner()?)
+        .await?;
+    jobs::update_job(dynamodb_client, job_id, &[av_s(":output_key", &output_key)]).await
+}
+
+/// Every member of the org, with their profile from `users` and their membership from
+/// `organization_users`. Password hashes are left out.
+async fn get_org_users(
+    dynamodb_client: &DynamoDbClient,
+    org_id: &str,
+) -> anyhow::Result<Vec<serde_json::Value>> {
+    let membership_items = dynamodb::query_all_items(
+        dynamodb_client,
+        QueryInput {
+            table_name: table_name("organization_users"),
+            key_condition_expression: Some(String::from("org_id = :org_id")),
+            expression_attribute_values: Some(av_map(&[av_s(":org_id", org_id)])),
+            ..Default::default()
+        },
+    )
+    .await?;
+    let mut users = Vec::with_capacity(membership_items.len());
+    for membership_item in membership_items.iter() {
+        let user_id = av_get_s(membership_item, "user_id")
+            .ok_or_else(|| anyhow::anyhow!("organization user has no user_id"))?;
+        let output = dynamodb_client
+            .query(QueryInput {
+                table_name: table_name("users"),
+                index_name: Some(String::from("id-index")),
+                key_condition_expression: Some(String::from("id = :id")),
+                expression_attribute_values: Some(av_map(&[av_s(":id", user_id)])),
+                projection_expression: Some(String::from(
+                    "email, #name, photo_url, created_at, updated_at",
+                )),
+                // "name" is a reserved word.
+                expression_attribute_names: Some(maplit::hashmap! {
+                    String::from("#name") => String::from("name"),
+                }),
+                ..Default::default()
+            })
+            .await?;
+        let empty_item = HashMap::new();
+        let user_item = output
+            .items
+            .as_ref()
+            .and_then(|items| items.first())
+            .unwrap_or(&empty_item);
+        let user_role = av_get_n::<i32>(membership_item, "user_role")
+            .and_then(|user_role| UserRole::try_from(user_role).ok())
+            .unwrap_or(UserRole::Default);
+        users.push(json!({
+            "id": user_id,
+            "email": json_s(user_item, "email"),
+            "name": json_s(user_item, "name"),
+            "photo_url": json_s(user_item, "photo_url"),
+            "user_role": match user_role {
+                UserRole::Default => "default",
+                UserRole::OrgAdmin => "org_admin",
+            },
+            "last_login_at": json_s(membership_item, "last_login_at"),
+            "joined_at": json_s(membership_item, "created_at"),
+            "created_at": json_s(user_item, "created_at"),
+            "updated_at": json_s(user_item, "updated_at"),
+        }));
+    }
+    Ok(users)
+}
+
+/// The `S` value of the attribute as JSON, or null if the item does not have it.
+fn json_s(item: &HashMap<String, AttributeValue>, key: &str) -> serde_json::Value {
+    match av_get_s(item, key) {
+        Some(s) => json!(s),
+        None => serde_json::Value::Null,
+    }
+}
+
+/// The `N` value of the attribute as JSON, or null if the item does not have it.
+fn json_n(item: &HashMap<String, AttributeValue>, key: &str) -> serde_json::Value {
+    match av_get_n::<i64>(item, key) {
+        Some(n) => json!(n),
+        None => serde_json::Value::Null,
+    }
+}
+
+fn append_jsonl(
+    archive: &mut tar::Builder<Vec<u8>>,
+    path: &str,
+    values: &[serde_json::Value],
+) -> anyhow::Result<()> {
+    let mut contents = String::new();
+    for value in values {
+        contents.push_str(&value.to_string());
+        contents.push('\n');
+    }
+    append_file(archive, path, contents.as_bytes())
+}
+
+fn append_file(
+    archive: &mut tar::Builder<Vec<u8>>,
+    path: &str,
+    contents: &[u8],
+) -> anyhow::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(chrono::Utc::now().timestamp() as u64);
+    archive.append_data(&mut header, path, contents)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+
+    use ot::writing_proto::{ChangeSet, DocumentRevision, DocumentSharingPermission};
+
+    use crate::ids::{Id, IdType};
+    use crate::jobs::JobWorker;
+    use crate::testing::fixtures::{
+        create_organization_user, create_user, DocumentFixture, RevisionFixture,
+    };
+    use crate::testing::utils::{MemoryBlobStore, TestDynamoDb};
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    fn read_archive(archive: &[u8]) -> HashMap<String, Vec<u8>> {
+        let mut files = HashMap::new();
+        for entry in tar::Archive::new(archive).entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_str().unwrap().to_string();
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents).unwrap();
+            files.insert(path, contents);
+        }
+        files
+    }
+
+    #[tokio::test]
+    async fn test_org_export() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let export_store = Arc::new(MemoryBlobStore::default());
+        let mut worker = JobWorker::default();
+        worker.register(Arc::new(OrgExportJobHandler {
+            export_store: export_store.clone(),
+        }));
+
+        let org_id = Id::new(IdType::Organization);
+        let admin_id = create_user(&db.dynamodb_client, "admin@example.com", "Admin").await;
+        let member_id = create_user(&db.dynamodb_client, "member@example.com", "Member").await;
+        for user_id in &[&admin_id, &member_id] {
+            create_organization_user(&db.dynamodb_client, &org_id, user_id, &chrono::Utc::now())
+                .await;
+        }
+        let admin = SessionUser {
+            user_id: admin_id.clone(),
+            org_id: org_id.clone(),
+            user_role: UserRole::OrgAdmin,
+        };
+
+        let mut change_set = ChangeSet::new();
+        change_set.insert("Hello");
+        let doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_created_by_user_id(&member_id)
+            .with_revisions(vec![RevisionFixture::new(
+                &member_id,
+                &change_set,
+                &chrono::Utc::now(),
+            )])
+            .with_sharing(&admin_id, DocumentSharingPermission::CanView);
+        doc.create(&db.dynamodb_client).await;
+        // Documents of other orgs are not exported.
+        DocumentFixture::new().create(&db.dynamodb_client).await;
+
+        let job_id = start_org_export(&db.dynamodb_client, &admin, &StartOrgExportRequest {})
+            .await?
+            .job_id;
+        let request = GetOrgExportRequest {
+            job_id: job_id.clone(),
+        };
+        let response =
+            get_org_export(&db.dynamodb_client, export_store.as_ref(), &admin, &request).await?;
+        assert_eq!(response.status(), Status::Pending);
+        assert!(response.download_url.is_empty());
+
+        assert!(
+            worker
+                .run_next_job(&db.dynamodb_client, chrono::Utc::now())
+                .await?
+        );
+
+        let response =
+            get_org_export(&db.dynamodb_client, export_store.as_ref(), &admin, &request).await?;
+        assert_eq!(response.status(), Status::Complete);
+        assert_eq!(response.documents_exported, 1);
+        assert_eq!(response.documents_total, 1);
+        let output_key = format!("org_exports/{}/{}.tar", org_id.as_str(), job_id);
+        assert_eq!(response.download_url, format!("memory://{}", output_key));
+
+        let files = read_archive(&export_store.blobs.lock().unwrap()[&output_key].1);
+        let users = String::from_utf8(files["users.jsonl"].clone())?;
+        assert_eq!(users.lines().count(), 2);
+        assert!(users.contains("member@example.com"));
+        assert!(!users.contains("hashed_password"));
+        let documents: Vec<serde_json::Value> =
+            String::from_utf8(files["documents.jsonl"].clone())?
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect();
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0]["id"], doc.doc_id.as_str());
+        assert_eq!(documents[0]["sharing"][0]["user_id"], admin_id.as_str());
+        assert_eq!(documents[0]["last_revision_number"], 1);
+        let revisions_binary = &files[&format!("revisions/{}.pb", doc.doc_id.as_str())];
+        let revision = DocumentRevision::decode_length_delimited(&revisions_binary[..])?;
+        assert_eq!(revision.change_set, Some(change_set));
+        assert!(files.contains_key("manifest.json"));
+
+        // Only org admins can export, and only their own org.
+        let member = SessionUser {
+            user_id: member_id.clone(),
+            org_id: org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let result =
+            start_org_export(&db.dynamodb_client, &member, &StartOrgExportRequest {}).await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+        let result = get_org_export(
+            &db.dynamodb_client,
+            export_store.as_ref(),
+            &member,
+            &request,
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+        let other_admin = SessionUser {
+            org_id: Id::new(IdType::Organization),
+            ..admin.clone()
+        };
+        let result = get_org_export(
+            &db.dynamodb_client,
+            export_store.as_ref(),
+            &other_admin,
+            &request,
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 404);
+
+        Ok(())
+    }
+}
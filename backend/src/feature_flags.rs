@@ -0,0 +1,464 @@
+//! Feature flags, for rolling features out one org at a time.
+//!
+//! Every flag is off unless turned on. A global default in the `feature_flags` table, stored under
+//! `GLOBAL_ORG_ID`, turns it on or off for every org, and an org's own setting overrides the
+//! global default for that org. Org admins set their org's flags through the admin endpoints.
+//! Global defaults are set by running the backend with `--set_global_feature_flag`.
+//!
+//! Flags are cached per org for `FEATURE_FLAG_CACHE_TTL`, so a change may take that long to reach
+//! other servers.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::error;
+use rusoto_dynamodb::{DeleteItemInput, PutItemInput, QueryInput};
+
+use ot::writing_proto::{
+    FeatureFlagState, ListFeatureFlagsRequest, ListFeatureFlagsResponse, SetFeatureFlagRequest,
+    SetFeatureFlagResponse,
+};
+
+use crate::dynamodb::{av_get_n, av_get_s, av_map, av_n, av_s, table_name, DynamoDbClient};
+use crate::http::SessionUser;
+use crate::users::UserRole;
+use crate::utils::time;
+
+/// The org id that global defaults are stored under.
+pub const GLOBAL_ORG_ID: &str = "global";
+
+/// How long an org's flags may be reused before we go back to DynamoDB.
+pub const FEATURE_FLAG_CACHE_TTL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum FeatureFlag {
+    /// The server transforms change sets submitted on old revisions, instead of sending the
+    /// client the new revisions to rebase on.
+    ServerSideTransform,
+    /// Text attributes, like bold and italic.
+    Attributes,
+    /// Syncing over a WebSocket instead of polling.
+    WebSockets,
+}
+
+impl FeatureFlag {
+    pub const ALL: [FeatureFlag; 3] = [
+        FeatureFlag::ServerSideTransform,
+        FeatureFlag::Attributes,
+        FeatureFlag::WebSockets,
+    ];
+
+    /// The flag's name in the table, the API and the capabilities response.
+    pub fn name(self) -> &'static str {
+        match self {
+            FeatureFlag::ServerSideTransform => "server_side_transform",
+            FeatureFlag::Attributes => "attributes",
+            FeatureFlag::WebSockets => "websockets",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|flag| flag.name() == name)
+    }
+}
+
+/// The flags in effect for an org.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FeatureFlags {
+    global_defaults: HashMap<FeatureFlag, bool>,
+    org_settings: HashMap<FeatureFlag, bool>,
+}
+
+impl FeatureFlags {
+    pub fn is_enabled(&self, flag: FeatureFlag) -> bool {
+        self.org_settings
+            .get(&flag)
+            .or_else(|| self.global_defaults.get(&flag))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// The names of the flags that are on, in the order of `FeatureFlag::ALL`.
+    pub fn enabled_names(&self) -> Vec<String> {
+        FeatureFlag::ALL
+            .iter()
+            .filter(|flag| self.is_enabled(**flag))
+            .map(|flag| flag.name().to_string())
+            .collect()
+    }
+}
+
+/// Remembers each org's flags, so that handlers can check them on every request.
+pub struct FeatureFlagCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (FeatureFlags, Instant)>>,
+}
+
+impl FeatureFlagCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn get(&self, org_id: &str) -> Option<FeatureFlags> {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(org_id) {
+            Some((flags, cached_at)) if cached_at.elapsed() < self.ttl => Some(flags.clone()),
+            _ => None,
+        }
+    }
+
+    fn insert(&self, org_id: &str, flags: &FeatureFlags) {
+        let mut entries = self.entries.lock().unwrap();
+        // There are few orgs, so expired entries are swept on every insert.
+        let ttl = self.ttl;
+        entries.retain(|_, (_, cached_at)| cached_at.elapsed() < ttl);
+        entries.insert(org_id.to_string(), (flags.clone(), Instant::now()));
+    }
+
+    /// Forgets the org's flags, or every org's flags for `GLOBAL_ORG_ID`.
+    fn invalidate(&self, org_id: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        if org_id == GLOBAL_ORG_ID {
+            entries.clear();
+        } else {
+            entries.remove(org_id);
+        }
+    }
+}
+
+impl Default for FeatureFlagCache {
+    fn default() -> Self {
+        Self::new(FEATURE_FLAG_CACHE_TTL)
+    }
+}
+
+/// Returns the flags in effect for the org, from the cache if they were read recently.
+pub async fn get_feature_flags(
+    dynamodb_client: &DynamoDbClient,
+    feature_flag_cache: &FeatureFlagCache,
+    org_id: &str,
+) -> anyhow::Result<FeatureFlags> {
+    if let Some(flags) = feature_flag_cache.get(org_id) {
+        return Ok(flags);
+    }
+    let flags = FeatureFlags {
+        global_defaults: get_settings(dynamodb_client, GLOBAL_ORG_ID).await?,
+        org_settings: get_settings(dynamodb_client, org_id).await?,
+    };
+    feature_flag_cache.insert(org_id, &flags);
+    Ok(flags)
+}
+
+/// Returns whether the flag is on for the org. For handlers, which should treat an error like
+/// the flag being off.
+pub async fn is_enabled(
+    dynamodb_client: &DynamoDbClient,
+    feature_flag_cache: &FeatureFlagCache,
+    org_id: &str,
+    flag: FeatureFlag,
+) -> anyhow::Result<bool> {
+    Ok(
+        get_feature_flags(dynamodb_client, feature_flag_cache, org_id)
+            .await?
+            .is_enabled(flag),
+    )
+}
+
+/// Turns the flag on or off for the org, or for every org without a setting of its own if the org
+/// id is `GLOBAL_ORG_ID`. With no setting, removes the org's setting, so that the flag follows the
+/// global default again, or removes the global default, so that the flag is off.
+pub async fn set_feature_flag(
+    dynamodb_client: &DynamoDbClient,
+    feature_flag_cache: &FeatureFlagCache,
+    org_id: &str,
+    flag: FeatureFlag,
+    enabled: Option<bool>,
+    updated_by: &str,
+) -> anyhow::Result<()> {
+    let key = [av_s("org_id", org_id), av_s("flag", flag.name())];
+    match enabled {
+        Some(enabled) => {
+            let mut item = key.to_vec();
+            item.extend_from_slice(&[
+                av_n("enabled", enabled as i32),
+                av_s("updated_by", updated_by),
+                av_s("updated_at", &time::date_time_iso_str(&chrono::Utc::now())),
+            ]);
+            let input = PutItemInput {
+                table_name: table_name("feature_flags"),
+                item: av_map(&item),
+                ..Default::default()
+            };
+            dynamodb_client.put_item(input).await?;
+        }
+        None => {
+            let input = DeleteItemInput {
+                table_name: table_name("feature_flags"),
+                key: av_map(&key),
+                ..Default::default()
+            };
+            dynamodb_client.delete_item(input).await?;
+        }
+    }
+    feature_flag_cache.invalidate(org_id);
+    Ok(())
+}
+
+async fn get_settings(
+    dynamodb_client: &DynamoDbClient,
+    org_id: &str,
+) -> anyhow::Result<HashMap<FeatureFlag, bool>> {
+    let input = QueryInput {
+        table_name: table_name("feature_flags"),
+        key_condition_expression: Some(String::from("org_id = :org_id")),
+        expression_attribute_values: Some(av_map(&[av_s(":org_id", org_id)])),
+        ..Default::default()
+    };
+    let output = dynamodb_client.query(input).await?;
+    let mut settings = HashMap::new();
+    for item in output.items.unwrap_or_default() {
+        // Settings of flags that were since removed are ignored.
+        let flag = match av_get_s(&item, "flag").and_then(FeatureFlag::from_name) {
+            Some(flag) => flag,
+            None => continue,
+        };
+        if let Some(enabled) = av_get_n::<i32>(&item, "enabled") {
+            settings.insert(flag, enabled != 0);
+        }
+    }
+    Ok(settings)
+}
+
+/// List every feature flag, whether it is on for the session user's org, and whether the org
+/// overrides the global default.
+///
+/// If the session user is not an org admin, returns 403 Forbidden.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn list_feature_flags(
+    dynamodb_client: &DynamoDbClient,
+    feature_flag_cache: &FeatureFlagCache,
+    session_user: &SessionUser,
+    request: &ListFeatureFlagsRequest,
+) -> actix_web::Result<ListFeatureFlagsResponse> {
+    if session_user.user_role != UserRole::OrgAdmin {
+        return Err(error::ErrorForbidden(""));
+    }
+    let flags = get_feature_flags(
+        dynamodb_client,
+        feature_flag_cache,
+        session_user.org_id.as_str(),
+    )
+    .await
+    .map_err(|e| {
+        log::error!(
+            "Error occurred: \"{}\" [list_feature_flags] \
+            [session_user: {:?}, request: {:?}]",
+            e,
+            session_user,
+            request,
+        );
+        error::ErrorInternalServerError("")
+    })?;
+    let flags = FeatureFlag::ALL
+        .iter()
+        .map(|flag| FeatureFlagState {
+            name: flag.name().to_string(),
+            enabled: flags.is_enabled(*flag),
+            overridden_for_org: flags.org_settings.contains_key(flag),
+        })
+        .collect();
+    Ok(ListFeatureFlagsResponse { flags })
+}
+
+/// Turn a feature flag on or off for the session user's org, or have it follow the global default
+/// again.
+///
+/// If the flag does not exist, returns 400 Bad Request.
+///
+/// If the session user is not an org admin, returns 403 Forbidden.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn set_org_feature_flag(
+    dynamodb_client: &DynamoDbClient,
+    feature_flag_cache: &FeatureFlagCache,
+    session_user: &SessionUser,
+    request: &SetFeatureFlagRequest,
+) -> actix_web::Result<SetFeatureFlagResponse> {
+    if session_user.user_role != UserRole::OrgAdmin {
+        return Err(error::ErrorForbidden(""));
+    }
+    let flag = FeatureFlag::from_name(&request.name).ok_or_else(|| error::ErrorBadRequest(""))?;
+    let enabled = if request.use_global_default {
+        None
+    } else {
+        Some(request.enabled)
+    };
+    set_feature_flag(
+        dynamodb_client,
+        feature_flag_cache,
+        session_user.org_id.as_str(),
+        flag,
+        enabled,
+        session_user.user_id.as_str(),
+    )
+    .await
+    .map_err(|e| {
+        log::error!(
+            "Error occurred: \"{}\" [set_org_feature_flag] \
+            [session_user: {:?}, request: {:?}]",
+            e,
+            session_user,
+            request,
+        );
+        error::ErrorInternalServerError("")
+    })?;
+    Ok(SetFeatureFlagResponse {})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ids::{Id, IdType};
+    use crate::testing::utils::TestDynamoDb;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    fn flag_states(response: &ListFeatureFlagsResponse) -> Vec<(&str, bool, bool)> {
+        response
+            .flags
+            .iter()
+            .map(|flag| (flag.name.as_str(), flag.enabled, flag.overridden_for_org))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_feature_flags() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        // No caching, so that every change shows up straight away.
+        let cache = FeatureFlagCache::new(Duration::from_secs(0));
+        let mut admin = SessionUser {
+            user_id: Id::new(IdType::User),
+            org_id: Id::new(IdType::Organization),
+            user_role: UserRole::Default,
+        };
+        let other_org_id = Id::new(IdType::Organization);
+
+        // Only org admins can see and set flags.
+        let result =
+            list_feature_flags(&db.dynamodb_client, &cache, &admin, &Default::default()).await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+        admin.user_role = UserRole::OrgAdmin;
+
+        // Flags are off by default.
+        let response =
+            list_feature_flags(&db.dynamodb_client, &cache, &admin, &Default::default()).await?;
+        assert_eq!(
+            flag_states(&response),
+            vec![
+                ("server_side_transform", false, false),
+                ("attributes", false, false),
+                ("websockets", false, false),
+            ]
+        );
+
+        // A global default applies to every org.
+        set_feature_flag(
+            &db.dynamodb_client,
+            &cache,
+            GLOBAL_ORG_ID,
+            FeatureFlag::Attributes,
+            Some(true),
+            "operator",
+        )
+        .await?;
+        for org_id in &[&admin.org_id, &other_org_id] {
+            let flags = get_feature_flags(&db.dynamodb_client, &cache, org_id.as_str()).await?;
+            assert!(flags.is_enabled(FeatureFlag::Attributes));
+            assert_eq!(flags.enabled_names(), vec!["attributes"]);
+        }
+
+        // An org's settings override the global default, for that org only.
+        let mut request = SetFeatureFlagRequest {
+            name: String::from("attributes"),
+            enabled: false,
+            ..Default::default()
+        };
+        set_org_feature_flag(&db.dynamodb_client, &cache, &admin, &request).await?;
+        request.name = String::from("websockets");
+        request.enabled = true;
+        set_org_feature_flag(&db.dynamodb_client, &cache, &admin, &request).await?;
+        let response =
+            list_feature_flags(&db.dynamodb_client, &cache, &admin, &Default::default()).await?;
+        assert_eq!(
+            flag_states(&response),
+            vec![
+                ("server_side_transform", false, false),
+                ("attributes", false, true),
+                ("websockets", true, true),
+            ]
+        );
+        let flags = get_feature_flags(&db.dynamodb_client, &cache, other_org_id.as_str()).await?;
+        assert_eq!(flags.enabled_names(), vec!["attributes"]);
+
+        // Without a setting of its own, the org follows the global default again.
+        request.name = String::from("attributes");
+        request.use_global_default = true;
+        set_org_feature_flag(&db.dynamodb_client, &cache, &admin, &request).await?;
+        assert!(
+            is_enabled(
+                &db.dynamodb_client,
+                &cache,
+                admin.org_id.as_str(),
+                FeatureFlag::Attributes
+            )
+            .await?
+        );
+
+        // Unknown flags cannot be set.
+        request.name = String::from("time_travel");
+        let result = set_org_feature_flag(&db.dynamodb_client, &cache, &admin, &request).await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_feature_flag_cache() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let cache = FeatureFlagCache::default();
+        let org_id = Id::new(IdType::Organization);
+
+        assert!(
+            get_feature_flags(&db.dynamodb_client, &cache, org_id.as_str())
+                .await?
+                .enabled_names()
+                .is_empty()
+        );
+        // Setting a flag on this server invalidates its cached flags.
+        set_feature_flag(
+            &db.dynamodb_client,
+            &cache,
+            org_id.as_str(),
+            FeatureFlag::WebSockets,
+            Some(true),
+            "operator",
+        )
+        .await?;
+        assert!(
+            is_enabled(
+                &db.dynamodb_client,
+                &cache,
+                org_id.as_str(),
+                FeatureFlag::WebSockets
+            )
+            .await?
+        );
+        Ok(())
+    }
+}
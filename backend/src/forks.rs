@@ -0,0 +1,728 @@
+//! Document forks.
+//!
+//! Forking a document makes a new document that starts from a snapshot of the original, so that
+//! someone can rework it without disturbing everyone else. The fork's first revision is the
+//! snapshot, and the fork item records which revision of the original it was taken at.
+//!
+//! Merging the fork back transforms its changes over whatever was committed to the original since
+//! the fork point, and commits them to the original as one revision. Text that both replaced is
+//! reported as conflicts. A fork can only be merged once: after that, its changes are no longer
+//! relative to a revision of the original.
+
+use actix_web::error;
+use bytes::Bytes;
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{GetItemInput, PutItemError, PutItemInput, UpdateItemInput};
+
+use ot::writing_proto::{
+    ChangeSet, DocumentSharingPermission, ForkDocumentRequest, ForkDocumentResponse, MergeConflict,
+    MergeForkRequest, MergeForkResponse,
+};
+
+use crate::documents;
+use crate::dynamodb::{av_b, av_get_n, av_get_s, av_map, av_n, av_s, table_name, DynamoDbClient};
+use crate::http::{Requester, SessionUser};
+use crate::ids::{Id, IdType};
+use crate::protected_ranges::{self, ChangeSetCheck};
+use crate::rate_limiter::RateLimiter;
+use crate::users;
+use crate::utils::{proto, time};
+
+/// Fork a document. The fork belongs to the session user, and has the original's org-level
+/// sharing permission.
+///
+/// If `request.revision_number` is set, the fork starts from that revision of the document, or
+/// from its last revision if it has fewer. Otherwise, it starts from the last revision.
+///
+/// End-to-end encrypted documents cannot be forked, since the server cannot read them. Returns 400
+/// Bad Request for those.
+///
+/// If the session user does not have permission to view the document, returns 403 Forbidden.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn fork_document(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &ForkDocumentRequest,
+) -> actix_web::Result<ForkDocumentResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [fork_document] [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    let document = documents::get_document_if_some_permission_valid(
+        dynamodb_client,
+        session_user,
+        &request.doc_id,
+        &[
+            DocumentSharingPermission::CanView,
+            DocumentSharingPermission::CanComment,
+            DocumentSharingPermission::CanEdit,
+        ],
+    )
+    .await?;
+    if !document.encryption_key_fingerprint.is_empty() || request.revision_number < 0 {
+        return Err(error::ErrorBadRequest(""));
+    }
+    let (change_sets, forked_from_revision_number) =
+        documents::get_change_sets(dynamodb_client, &request.doc_id, 0, request.revision_number)
+            .await
+            .map_err(|e| {
+                log_error(e.to_string());
+                error::ErrorInternalServerError("")
+            })?;
+
+    let fork_id = Id::new(IdType::Document);
+    let now = time::date_time_iso_str(&chrono::Utc::now());
+    // Write the snapshot before the fork item, so that nobody sees the fork without it.
+    if !change_sets.is_empty() {
+        let snapshot = ot::compose_iter(&change_sets).map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+        put_revision(
+            dynamodb_client,
+            fork_id.as_str(),
+            1,
+            session_user,
+            &snapshot,
+            &now,
+        )
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    }
+    let title = if request.title.is_empty() {
+        format!("{} (fork)", document.title)
+    } else {
+        request.title.clone()
+    };
+    let input = PutItemInput {
+        table_name: table_name("documents"),
+        item: av_map(&[
+            av_s("id", fork_id.as_str()),
+            av_s("org_id", session_user.org_id.as_str()),
+            av_s("title", &title),
+            av_s("title_lc", &documents::title_search_key(&title)),
+            av_s("created_by_user_id", session_user.user_id.as_str()),
+            av_s("owner_user_id", session_user.user_id.as_str()),
+            av_n(
+                "org_level_sharing_permission",
+                document.org_level_sharing_permission,
+            ),
+            av_s("forked_from_doc_id", &request.doc_id),
+            av_n("forked_from_revision_number", forked_from_revision_number),
+            av_s("created_at", &now),
+            av_s("updated_at", &now),
+        ]),
+        ..Default::default()
+    };
+    dynamodb_client.put_item(input).await.map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    Ok(ForkDocumentResponse {
+        doc_id: fork_id.as_str().to_string(),
+        forked_from_revision_number,
+    })
+}
+
+/// Merge a fork back into the document it was forked from, committing the fork's changes as a
+/// revision of the original. Returns the places where both replaced the same text. With
+/// `dry_run`, only returns the conflicts.
+///
+/// If the document is not a fork, or the fork was already merged, returns 400 Bad Request.
+///
+/// If the session user cannot view the fork or cannot edit the original, returns 403 Forbidden.
+///
+/// If either document does not exist, returns 404 Not Found.
+///
+/// If someone commits to the original while the fork is being merged, returns 409 Conflict. Try
+/// again.
+///
+/// If the fork's changes modify one of the original's protected ranges, and the session user is
+/// not the owner of the original, returns 422 Unprocessable Entity. See `protected_ranges`.
+///
+/// Merging commits a revision to the original, so it counts against the session user's edit
+/// budget for it, like `documents::submit_document_change_set`. If they have used it up, returns
+/// 429 Too Many Requests.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn merge_fork(
+    dynamodb_client: &DynamoDbClient,
+    edit_rate_limiter: &RateLimiter,
+    session_user: &SessionUser,
+    request: &MergeForkRequest,
+) -> actix_web::Result<MergeForkResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [merge_fork] [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    documents::get_document_if_some_permission_valid(
+        dynamodb_client,
+        session_user,
+        &request.doc_id,
+        &[
+            DocumentSharingPermission::CanView,
+            DocumentSharingPermission::CanComment,
+            DocumentSharingPermission::CanEdit,
+        ],
+    )
+    .await?;
+    let output = dynamodb_client
+        .get_item(GetItemInput {
+            table_name: table_name("documents"),
+            key: av_map(&[av_s("id", &request.doc_id)]),
+            projection_expression: Some(String::from(
+                "forked_from_doc_id, forked_from_revision_number, merged_into_revision_number",
+            )),
+            consistent_read: Some(true),
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    let item = output.item.ok_or_else(|| error::ErrorNotFound(""))?;
+    let upstream_doc_id = match av_get_s(&item, "forked_from_doc_id") {
+        Some(upstream_doc_id) => upstream_doc_id,
+        None => return Err(error::ErrorBadRequest("")),
+    };
+    if item.contains_key("merged_into_revision_number") {
+        return Err(error::ErrorBadRequest(""));
+    }
+    let forked_from_revision_number: i64 = av_get_n(&item, "forked_from_revision_number")
+        .ok_or_else(|| {
+            log_error("fork is missing forked_from_revision_number".to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    documents::get_document_if_some_permission_valid(
+        dynamodb_client,
+        session_user,
+        upstream_doc_id,
+        &[DocumentSharingPermission::CanEdit],
+    )
+    .await?;
+
+    let (fork_change_sets, _) = documents::get_change_sets(dynamodb_client, &request.doc_id, 0, 0)
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    let (upstream_change_sets, last_upstream_revision_number) = documents::get_change_sets(
+        dynamodb_client,
+        upstream_doc_id,
+        forked_from_revision_number,
+        0,
+    )
+    .await
+    .map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    // The fork's first revision is the snapshot that both sides start from, unless the original
+    // was empty when it was forked.
+    let (base_len, fork_changes) = if forked_from_revision_number > 0 {
+        let snapshot = fork_change_sets.first().ok_or_else(|| {
+            log_error("fork is missing its snapshot".to_string());
+            error::ErrorInternalServerError("")
+        })?;
+        let (_, base_len) = ot::get_input_output_doc_lengths(snapshot).map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+        (base_len, &fork_change_sets[1..])
+    } else {
+        (0, &fork_change_sets[..])
+    };
+    let merge_result = ot::merge(base_len, fork_changes, &upstream_change_sets).map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    let mut response = MergeForkResponse {
+        upstream_doc_id: upstream_doc_id.to_string(),
+        revision_number: 0,
+        conflicts: merge_result
+            .conflicts
+            .iter()
+            .map(|conflict| MergeConflict {
+                merged_start: conflict.merged_range.start,
+                merged_end: conflict.merged_range.end,
+                fork_content: ot::utils::u32_slice_to_string_lossy(&conflict.local_content),
+                upstream_content: ot::utils::u32_slice_to_string_lossy(&conflict.remote_content),
+            })
+            .collect(),
+    };
+    if request.dry_run {
+        return Ok(response);
+    }
+
+    let now = time::date_time_iso_str(&chrono::Utc::now());
+    if !ot::is_identity(&merge_result.rebased_local, None) {
+        let requester = Requester::User(session_user.clone());
+        if documents::acquire_edit_permit(edit_rate_limiter, &requester, upstream_doc_id).is_err() {
+            return Err(error::ErrorTooManyRequests(""));
+        }
+        // The fork has no protected ranges of its own, so the original's are checked here, the
+        // same way as for any other change set committed to it.
+        let protected_ranges_advance = match protected_ranges::check_change_set(
+            dynamodb_client,
+            &requester,
+            upstream_doc_id,
+            last_upstream_revision_number,
+            &merge_result.rebased_local,
+        )
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })? {
+            ChangeSetCheck::Unprotected => None,
+            ChangeSetCheck::Allowed(advance) => Some(advance),
+            ChangeSetCheck::Modified => return Err(error::ErrorUnprocessableEntity("")),
+        };
+        let revision_number = last_upstream_revision_number + 1;
+        let committed = put_revision(
+            dynamodb_client,
+            upstream_doc_id,
+            revision_number,
+            session_user,
+            &merge_result.rebased_local,
+            &now,
+        )
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+        if !committed {
+            return Err(error::ErrorConflict(""));
+        }
+        if let Some(advance) = protected_ranges_advance {
+            // The revision is committed either way. The ranges are moved past it on the next check
+            // if this fails.
+            if let Err(e) = protected_ranges::advance(
+                dynamodb_client,
+                upstream_doc_id,
+                advance,
+                &merge_result.rebased_local,
+                revision_number,
+            )
+            .await
+            {
+                log_error(e.to_string());
+            }
+        }
+        response.revision_number = revision_number;
+    }
+    let input = UpdateItemInput {
+        table_name: table_name("documents"),
+        key: av_map(&[av_s("id", &request.doc_id)]),
+        update_expression: Some(String::from(
+            "SET merged_into_revision_number = :merged_into_revision_number, \
+            updated_at = :updated_at",
+        )),
+        expression_attribute_values: Some(av_map(&[
+            av_n(":merged_into_revision_number", response.revision_number),
+            av_s(":updated_at", &now),
+        ])),
+        ..Default::default()
+    };
+    dynamodb_client.update_item(input).await.map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    Ok(response)
+}
+
+/// Commits the change set as the given revision of the document. Returns false if the revision
+/// already exists.
+async fn put_revision(
+    dynamodb_client: &DynamoDbClient,
+    doc_id: &str,
+    revision_number: i64,
+    session_user: &SessionUser,
+    change_set: &ChangeSet,
+    committed_at: &str,
+) -> anyhow::Result<bool> {
+    let change_set_binary = proto::encode_protobuf_message(change_set)?;
+    let author_display_name = users::get_user_name(dynamodb_client, &session_user.user_id).await?;
+    let mut item = av_map(&[
+        av_s("doc_id", doc_id),
+        av_s("org_id", session_user.org_id.as_str()),
+        av_s("author_user_id", session_user.user_id.as_str()),
+        av_n("revision_number", revision_number),
+        av_b("change_set", Bytes::from(change_set_binary)),
+        av_s("committed_at", committed_at),
+    ]);
+    if !author_display_name.is_empty() {
+        let (key, value) = av_s("author_display_name", &author_display_name);
+        item.insert(key, value);
+    }
+    let input = PutItemInput {
+        table_name: table_name("document_revisions"),
+        item,
+        // Only succeed if key (doc_id, revision_number) does not already exist.
+        condition_expression: Some(String::from(
+            "attribute_not_exists(doc_id) AND attribute_not_exists(revision_number)",
+        )),
+        ..Default::default()
+    };
+    match dynamodb_client.put_item(input).await {
+        Ok(_) => Ok(true),
+        Err(RusotoError::Service(PutItemError::ConditionalCheckFailed(_))) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ot::writing_proto::{
+        submit_document_change_set_response::ResponseCode, ProtectedRanges, Selection,
+        SetProtectedRangesRequest, SubmitDocumentChangeSetRequest,
+    };
+
+    use crate::permission_cache::PermissionCache;
+    use crate::testing::fixtures::{self, DocumentFixture, RevisionFixture};
+    use crate::testing::utils::TestDynamoDb;
+    use crate::users::UserRole;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    async fn submit(
+        dynamodb_client: &DynamoDbClient,
+        session_user: &SessionUser,
+        doc_id: &str,
+        on_revision_number: i64,
+        document_len: i64,
+        (retain, insert, delete): (i64, &str, i64),
+    ) -> TestResult {
+        let mut change_set = ChangeSet::new();
+        change_set.retain(retain);
+        change_set.insert(insert);
+        change_set.delete(delete);
+        change_set.retain(document_len - retain - delete);
+        let response = documents::submit_document_change_set(
+            dynamodb_client,
+            &PermissionCache::default(),
+            &RateLimiter::default(),
+            &Requester::User(session_user.clone()),
+            &SubmitDocumentChangeSetRequest {
+                doc_id: doc_id.to_string(),
+                on_revision_number,
+                change_set: Some(change_set),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(response.response_code(), ResponseCode::Ack);
+        Ok(())
+    }
+
+    async fn get_text(dynamodb_client: &DynamoDbClient, doc_id: &str) -> String {
+        let (change_sets, _) = documents::get_change_sets(dynamodb_client, doc_id, 0, 0)
+            .await
+            .unwrap();
+        ot::apply("", &ot::compose_iter(&change_sets).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_fork_and_merge() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let session_user = SessionUser {
+            user_id: Id::new(IdType::User),
+            org_id: Id::new(IdType::Organization),
+            user_role: UserRole::Default,
+        };
+        let mut change_set = ChangeSet::new();
+        change_set.insert("Hello world");
+        let doc = DocumentFixture::new()
+            .with_org_id(&session_user.org_id)
+            .with_created_by_user_id(&session_user.user_id)
+            .with_title("Greeting")
+            .with_revisions(vec![RevisionFixture::new(
+                &session_user.user_id,
+                &change_set,
+                &chrono::Utc::now(),
+            )]);
+        doc.create(&db.dynamodb_client).await;
+        let doc_id = doc.doc_id.as_str();
+
+        let response = fork_document(
+            &db.dynamodb_client,
+            &session_user,
+            &ForkDocumentRequest {
+                doc_id: doc_id.to_string(),
+                title: String::new(),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(response.forked_from_revision_number, 1);
+        let fork_id = response.doc_id;
+        assert_eq!(get_text(&db.dynamodb_client, &fork_id).await, "Hello world");
+        let fork = documents::get_document_if_some_permission_valid(
+            &db.dynamodb_client,
+            &session_user,
+            &fork_id,
+            &[DocumentSharingPermission::CanEdit],
+        )
+        .await?;
+        assert_eq!(fork.title, "Greeting (fork)");
+
+        // "Hello world" -> "Hello world!" in the fork, and "Hi world" in the original.
+        submit(
+            &db.dynamodb_client,
+            &session_user,
+            &fork_id,
+            1,
+            11,
+            (11, "!", 0),
+        )
+        .await?;
+        submit(
+            &db.dynamodb_client,
+            &session_user,
+            doc_id,
+            1,
+            11,
+            (0, "Hi", 5),
+        )
+        .await?;
+
+        let request = MergeForkRequest {
+            doc_id: fork_id.clone(),
+            dry_run: true,
+        };
+        let response = merge_fork(
+            &db.dynamodb_client,
+            &RateLimiter::default(),
+            &session_user,
+            &request,
+        )
+        .await?;
+        assert_eq!(response.revision_number, 0);
+        assert!(response.conflicts.is_empty());
+        assert_eq!(get_text(&db.dynamodb_client, doc_id).await, "Hi world");
+
+        let request = MergeForkRequest {
+            doc_id: fork_id.clone(),
+            dry_run: false,
+        };
+        let response = merge_fork(
+            &db.dynamodb_client,
+            &RateLimiter::default(),
+            &session_user,
+            &request,
+        )
+        .await?;
+        assert_eq!(response.upstream_doc_id, doc_id);
+        assert_eq!(response.revision_number, 3);
+        assert_eq!(get_text(&db.dynamodb_client, doc_id).await, "Hi world!");
+
+        // A fork can only be merged once.
+        let result = merge_fork(
+            &db.dynamodb_client,
+            &RateLimiter::default(),
+            &session_user,
+            &request,
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+
+        // Documents that are not forks cannot be merged.
+        let request = MergeForkRequest {
+            doc_id: doc_id.to_string(),
+            dry_run: false,
+        };
+        let result = merge_fork(
+            &db.dynamodb_client,
+            &RateLimiter::default(),
+            &session_user,
+            &request,
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_merge_with_conflict() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let session_user = SessionUser {
+            user_id: Id::new(IdType::User),
+            org_id: Id::new(IdType::Organization),
+            user_role: UserRole::Default,
+        };
+        let mut change_set = ChangeSet::new();
+        change_set.insert("Hello world");
+        let doc = DocumentFixture::new()
+            .with_org_id(&session_user.org_id)
+            .with_created_by_user_id(&session_user.user_id)
+            .with_revisions(vec![RevisionFixture::new(
+                &session_user.user_id,
+                &change_set,
+                &chrono::Utc::now(),
+            )]);
+        doc.create(&db.dynamodb_client).await;
+        let doc_id = doc.doc_id.as_str();
+        let fork_id = fork_document(
+            &db.dynamodb_client,
+            &session_user,
+            &ForkDocumentRequest {
+                doc_id: doc_id.to_string(),
+                title: String::from("Draft"),
+                ..Default::default()
+            },
+        )
+        .await?
+        .doc_id;
+
+        submit(
+            &db.dynamodb_client,
+            &session_user,
+            &fork_id,
+            1,
+            11,
+            (6, "there", 5),
+        )
+        .await?;
+        submit(
+            &db.dynamodb_client,
+            &session_user,
+            doc_id,
+            1,
+            11,
+            (6, "everyone", 5),
+        )
+        .await?;
+
+        let request = MergeForkRequest {
+            doc_id: fork_id.clone(),
+            dry_run: false,
+        };
+        let response = merge_fork(
+            &db.dynamodb_client,
+            &RateLimiter::default(),
+            &session_user,
+            &request,
+        )
+        .await?;
+        assert_eq!(
+            response.conflicts,
+            vec![MergeConflict {
+                merged_start: 6,
+                merged_end: 19,
+                fork_content: String::from("there"),
+                upstream_content: String::from("everyone"),
+            }]
+        );
+        let text = get_text(&db.dynamodb_client, doc_id).await;
+        assert!(text == "Hello thereeveryone" || text == "Hello everyonethere");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_merge_into_protected_range() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let owner_id = fixtures::create_user(&db.dynamodb_client, "olga@example.com", "Olga").await;
+        let editor_id =
+            fixtures::create_user(&db.dynamodb_client, "edna@example.com", "Edna").await;
+        let mut change_set = ChangeSet::new();
+        change_set.insert("Header\nBody");
+        let doc = DocumentFixture::new()
+            .with_created_by_user_id(&owner_id)
+            .with_sharing(&owner_id, DocumentSharingPermission::CanEdit)
+            .with_sharing(&editor_id, DocumentSharingPermission::CanEdit)
+            // So that the owner can view the editor's fork, which inherits it.
+            .with_org_level_sharing_permission(DocumentSharingPermission::CanView)
+            .with_revisions(vec![RevisionFixture::new(
+                &owner_id,
+                &change_set,
+                &chrono::Utc::now(),
+            )]);
+        doc.create(&db.dynamodb_client).await;
+        let doc_id = doc.doc_id.as_str();
+        let owner = SessionUser {
+            user_id: owner_id.clone(),
+            org_id: doc.org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let editor = SessionUser {
+            user_id: editor_id.clone(),
+            ..owner.clone()
+        };
+        protected_ranges::set_protected_ranges(
+            &db.dynamodb_client,
+            &owner,
+            &SetProtectedRangesRequest {
+                doc_id: doc_id.to_string(),
+                protected_ranges: Some(ProtectedRanges {
+                    revision_number: 1,
+                    ranges: vec![Selection {
+                        offset: 0,
+                        count: 6,
+                    }],
+                }),
+            },
+        )
+        .await?;
+
+        // An editor cannot get around the protected header by editing it in a fork.
+        let fork_id = fork_document(
+            &db.dynamodb_client,
+            &editor,
+            &ForkDocumentRequest {
+                doc_id: doc_id.to_string(),
+                title: String::from("Draft"),
+                ..Default::default()
+            },
+        )
+        .await?
+        .doc_id;
+        submit(&db.dynamodb_client, &editor, &fork_id, 1, 11, (1, "E", 1)).await?;
+        let request = MergeForkRequest {
+            doc_id: fork_id.clone(),
+            dry_run: false,
+        };
+        let result = merge_fork(
+            &db.dynamodb_client,
+            &RateLimiter::default(),
+            &editor,
+            &request,
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 422);
+        assert_eq!(get_text(&db.dynamodb_client, doc_id).await, "Header\nBody");
+
+        // The owner can merge the same fork.
+        let response = merge_fork(
+            &db.dynamodb_client,
+            &RateLimiter::default(),
+            &owner,
+            &request,
+        )
+        .await?;
+        assert_eq!(response.revision_number, 2);
+        assert_eq!(get_text(&db.dynamodb_client, doc_id).await, "HEader\nBody");
+
+        Ok(())
+    }
+}
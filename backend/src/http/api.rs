@@ -1,105 +1,1479 @@
+pub mod admin {
+
+    use actix_web::{post, web, HttpResponse};
+
+    use ot::writing_proto::{
+        GetDocumentHealthRequest, GetDynamoDbCapacityRequest, GetSyncMetricsRequest,
+        ListFeatureFlagsRequest, ListRegionLocksRequest, SetFeatureFlagRequest,
+    };
+
+    use crate::admin;
+    use crate::feature_flags;
+    use crate::http::{self, SessionUser};
+    use crate::BackendService;
+
+    #[post("/api/admin.get_document_health")]
+    pub async fn get_document_health(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<GetDocumentHealthRequest>(&request_body)?;
+        let response =
+            admin::get_document_health(&service.dynamodb_client, &session_user, &request).await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/admin.get_dynamodb_capacity")]
+    pub async fn get_dynamodb_capacity(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<GetDynamoDbCapacityRequest>(&request_body)?;
+        let response = admin::get_dynamodb_capacity(
+            service.dynamodb_client.capacity_metrics(),
+            &session_user,
+            &request,
+        )?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/admin.get_sync_metrics")]
+    pub async fn get_sync_metrics(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<GetSyncMetricsRequest>(&request_body)?;
+        let response = admin::get_sync_metrics(&service.sync_metrics, &session_user, &request)?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/admin.list_feature_flags")]
+    pub async fn list_feature_flags(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<ListFeatureFlagsRequest>(&request_body)?;
+        let response = feature_flags::list_feature_flags(
+            &service.dynamodb_client,
+            &service.feature_flag_cache,
+            &session_user,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/admin.list_region_locks")]
+    pub async fn list_region_locks(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<ListRegionLocksRequest>(&request_body)?;
+        let response = admin::list_region_locks(&service.region_locks, &session_user, &request)?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/admin.set_feature_flag")]
+    pub async fn set_feature_flag(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<SetFeatureFlagRequest>(&request_body)?;
+        let response = feature_flags::set_org_feature_flag(
+            &service.dynamodb_client,
+            &service.feature_flag_cache,
+            &session_user,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+}
+
 pub mod documents {
 
-    use actix_session::Session;
-    use actix_web::{error, post, web, HttpResponse};
-    use prost::Message;
+    use std::time::Instant;
+
+    use actix_web::{get, post, web, HttpRequest, HttpResponse};
 
     use ot::writing_proto::{
-        CreateDocumentRequest, GetDocumentRequest, GetDocumentRevisionsRequest,
-        ListMyDocumentsRequest, SubmitDocumentChangeSetRequest, UpdateDocumentTitleRequest,
+        submit_document_change_set_response::ResponseCode, AppendToDocumentRequest,
+        ArchiveDocumentRequest, CollectAttachmentsRequest, ConfirmAttachmentUploadRequest,
+        CreateAttachmentRequest, CreateDocumentRequest, DebugDecodeRevisionRequest,
+        DiagnoseDocumentRevisionsRequest, FollowDocumentRequest, ForkDocumentRequest,
+        GetAttachmentUrlRequest, GetDocumentExcerptRequest, GetDocumentHeadRequest,
+        GetDocumentRequest, GetDocumentRevisionsRequest, GetMyPermissionsRequest,
+        GetProtectedRangesRequest, ListArchivedRequest, ListAttachmentsRequest,
+        ListMyDocumentsRequest, ListReadReceiptsRequest, ListRecentlyViewedRequest,
+        ListStarredRequest, MergeForkRequest, PurgeDocumentRequest, ReplacePatternRequest,
+        ReportReadPositionRequest, SearchDocumentTitlesRequest, SendTypingRequest,
+        SetProtectedRangesRequest, StarDocumentRequest, SubmitDocumentChangeSetRequest,
+        TransferOwnershipRequest, UnarchiveDocumentRequest, UnfollowDocumentRequest,
+        UnstarDocumentRequest, UpdateDocumentStatsRequest, UpdateDocumentTitleRequest,
+        VerifyDocumentRevisionsRequest,
     };
 
+    use crate::archived_documents;
+    use crate::attachments;
+    use crate::automation;
+    use crate::document_events::{self, DocumentEvent, DocumentEventsQuery, RevisionCommitted};
+    use crate::document_stats;
+    use crate::document_views;
     use crate::documents;
-    use crate::http;
+    use crate::forks;
+    use crate::http::{self, Requester, SessionUser};
+    use crate::notifications;
+    use crate::protected_ranges;
+    use crate::purged_documents;
+    use crate::read_receipts;
+    use crate::revision_signatures;
+    use crate::starred_documents;
     use crate::BackendService;
 
+    #[post("/api/documents.append_to_document")]
+    pub async fn append_to_document(
+        req: HttpRequest,
+        requester: Requester,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        http::check_guest_rate_limit(&req, Some(&requester), &service)?;
+        let request = http::decode_protobuf_request::<AppendToDocumentRequest>(&request_body)?;
+        let response = automation::append_to_document(
+            &service.dynamodb_client,
+            &service.permission_cache,
+            &service.edit_rate_limiter,
+            &requester,
+            &request,
+        )
+        .await?;
+        if response.revision_number > 0 {
+            service.document_events.publish(RevisionCommitted {
+                doc_id: request.doc_id.clone(),
+                revision_number: response.revision_number,
+                author_user_id: requester.id().as_str().to_string(),
+            });
+            if !service.derived_data_from_stream {
+                spawn_edit_notifications(
+                    &service,
+                    &request.doc_id,
+                    response.revision_number,
+                    &requester,
+                );
+            }
+        }
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.archive_document")]
+    pub async fn archive_document(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<ArchiveDocumentRequest>(&request_body)?;
+        let response =
+            archived_documents::archive_document(&service.dynamodb_client, &session_user, &request)
+                .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.collect_attachments")]
+    pub async fn collect_attachments(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<CollectAttachmentsRequest>(&request_body)?;
+        let response = attachments::collect_attachments(
+            &service.dynamodb_client,
+            service.attachment_store.as_ref(),
+            &session_user,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.confirm_attachment_upload")]
+    pub async fn confirm_attachment_upload(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request =
+            http::decode_protobuf_request::<ConfirmAttachmentUploadRequest>(&request_body)?;
+        let response = attachments::confirm_attachment_upload(
+            &service.dynamodb_client,
+            service.attachment_store.as_ref(),
+            &session_user,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.create_attachment")]
+    pub async fn create_attachment(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<CreateAttachmentRequest>(&request_body)?;
+        let response = attachments::create_attachment(
+            &service.dynamodb_client,
+            service.attachment_store.as_ref(),
+            &session_user,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
     #[post("/api/documents.create_document")]
     pub async fn create_document(
-        session: Session,
+        session_user: SessionUser,
         request_body: actix_web::web::Bytes,
         service: web::Data<BackendService>,
     ) -> actix_web::Result<HttpResponse> {
-        let session_user = http::get_session_user(&session, &service).await?;
-        let request = CreateDocumentRequest::decode(&request_body[..])
-            .map_err(|_| error::ErrorBadRequest(""))?;
+        let request = http::decode_protobuf_request::<CreateDocumentRequest>(&request_body)?;
         let response =
             documents::create_document(&service.dynamodb_client, &session_user, &request).await?;
         http::create_protobuf_http_response(&response)
     }
 
+    #[post("/api/documents.debug_decode_revision")]
+    pub async fn debug_decode_revision(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<DebugDecodeRevisionRequest>(&request_body)?;
+        let response = documents::debug_decode_revision(
+            &service.dynamodb_client,
+            &service.permission_cache,
+            &session_user,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.diagnose_document_revisions")]
+    pub async fn diagnose_document_revisions(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request =
+            http::decode_protobuf_request::<DiagnoseDocumentRevisionsRequest>(&request_body)?;
+        let response = documents::diagnose_document_revisions(
+            &service.dynamodb_client,
+            &service.permission_cache,
+            &session_user,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[get("/api/documents.events")]
+    pub async fn events(
+        req: HttpRequest,
+        requester: Option<Requester>,
+        query: web::Query<DocumentEventsQuery>,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        http::check_guest_rate_limit(&req, requester.as_ref(), &service)?;
+        let session_user = requester.as_ref().and_then(Requester::session_user);
+        let receiver = document_events::subscribe_to_document_events(
+            &service.dynamodb_client,
+            &service.permission_cache,
+            &service.document_events,
+            session_user,
+            &query,
+        )
+        .await?;
+        let region_locks = service
+            .region_locks
+            .active(&query.doc_id, Instant::now())
+            .into_iter()
+            .map(DocumentEvent::from)
+            .collect();
+        let dynamodb_client = service.dynamodb_client.clone();
+        let permission_cache = service.permission_cache.clone();
+        let session_user = session_user.cloned();
+        let query = query.into_inner();
+        let check_access = move || {
+            let dynamodb_client = dynamodb_client.clone();
+            let permission_cache = permission_cache.clone();
+            let session_user = session_user.clone();
+            let query = query.clone();
+            async move {
+                document_events::validate_subscriber_access(
+                    &dynamodb_client,
+                    &permission_cache,
+                    session_user.as_ref(),
+                    &query,
+                )
+                .await
+                .is_ok()
+            }
+        };
+        Ok(HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .header("Cache-Control", "no-cache")
+            .streaming(document_events::event_stream(
+                receiver,
+                requester.as_ref().map(|requester| requester.id().clone()),
+                region_locks,
+                check_access,
+            )))
+    }
+
+    #[post("/api/documents.follow_document")]
+    pub async fn follow_document(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<FollowDocumentRequest>(&request_body)?;
+        let response =
+            notifications::follow_document(&service.dynamodb_client, &session_user, &request)
+                .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.fork_document")]
+    pub async fn fork_document(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<ForkDocumentRequest>(&request_body)?;
+        let response =
+            forks::fork_document(&service.dynamodb_client, &session_user, &request).await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.get_attachment_url")]
+    pub async fn get_attachment_url(
+        req: HttpRequest,
+        requester: Requester,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        http::check_guest_rate_limit(&req, Some(&requester), &service)?;
+        let request = http::decode_protobuf_request::<GetAttachmentUrlRequest>(&request_body)?;
+        let response = attachments::get_attachment_url(
+            &service.dynamodb_client,
+            &service.permission_cache,
+            service.attachment_store.as_ref(),
+            &requester,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
     #[post("/api/documents.get_document")]
     pub async fn get_document(
-        session: Session,
+        req: HttpRequest,
+        requester: Option<Requester>,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        http::check_guest_rate_limit(&req, requester.as_ref(), &service)?;
+        let session_user = requester.as_ref().and_then(Requester::session_user);
+        let request = http::decode_protobuf_request::<GetDocumentRequest>(&request_body)?;
+        let response =
+            documents::get_document(&service.dynamodb_client, session_user, &request).await?;
+        // Record the view in the background, so that loading the document does not wait on it.
+        // Guests opening a share link have no home screen, so their views are not recorded.
+        if let Some(session_user) = session_user {
+            let dynamodb_client = service.dynamodb_client.clone();
+            let session_user = session_user.clone();
+            let doc_id = request.doc_id.clone();
+            actix_web::rt::spawn(async move {
+                let result = document_views::record_document_view(
+                    &dynamodb_client,
+                    &session_user,
+                    &doc_id,
+                    chrono::Utc::now(),
+                )
+                .await;
+                if let Err(e) = result {
+                    log::error!(
+                        "Error occurred: \"{}\" [record_document_view] \
+                        [session_user: {:?}, doc_id: {}]",
+                        e,
+                        session_user,
+                        doc_id,
+                    );
+                }
+            });
+        }
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.get_document_head")]
+    pub async fn get_document_head(
+        req: HttpRequest,
+        requester: Option<Requester>,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        http::check_guest_rate_limit(&req, requester.as_ref(), &service)?;
+        let session_user = requester.as_ref().and_then(Requester::session_user);
+        let request = http::decode_protobuf_request::<GetDocumentHeadRequest>(&request_body)?;
+        let response =
+            documents::get_document_head(&service.dynamodb_client, session_user, &request).await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.get_document_excerpt")]
+    pub async fn get_document_excerpt(
+        req: HttpRequest,
+        requester: Option<Requester>,
         request_body: actix_web::web::Bytes,
         service: web::Data<BackendService>,
     ) -> actix_web::Result<HttpResponse> {
-        let session_user = http::get_session_user(&session, &service).await?;
-        let request = GetDocumentRequest::decode(&request_body[..])
-            .map_err(|_| error::ErrorBadRequest(""))?;
+        http::check_guest_rate_limit(&req, requester.as_ref(), &service)?;
+        let session_user = requester.as_ref().and_then(Requester::session_user);
+        let request = http::decode_protobuf_request::<GetDocumentExcerptRequest>(&request_body)?;
         let response =
-            documents::get_document(&service.dynamodb_client, &session_user, &request).await?;
+            documents::get_document_excerpt(&service.dynamodb_client, session_user, &request)
+                .await?;
         http::create_protobuf_http_response(&response)
     }
 
     #[post("/api/documents.get_document_revisions")]
     pub async fn get_document_revisions(
-        session: Session,
+        req: HttpRequest,
+        requester: Option<Requester>,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        http::check_guest_rate_limit(&req, requester.as_ref(), &service)?;
+        let session_user = requester.as_ref().and_then(Requester::session_user);
+        let request = http::decode_protobuf_request::<GetDocumentRevisionsRequest>(&request_body)?;
+        let response = documents::get_document_revisions(
+            &service.dynamodb_client,
+            &service.permission_cache,
+            session_user,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.get_my_permissions")]
+    pub async fn get_my_permissions(
+        session_user: SessionUser,
         request_body: actix_web::web::Bytes,
         service: web::Data<BackendService>,
     ) -> actix_web::Result<HttpResponse> {
-        let session_user = http::get_session_user(&session, &service).await?;
-        let request = GetDocumentRevisionsRequest::decode(&request_body[..])
-            .map_err(|_| error::ErrorBadRequest(""))?;
+        let request = http::decode_protobuf_request::<GetMyPermissionsRequest>(&request_body)?;
         let response =
-            documents::get_document_revisions(&service.dynamodb_client, &session_user, &request)
+            documents::get_my_permissions(&service.dynamodb_client, &session_user, &request)
+                .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.get_protected_ranges")]
+    pub async fn get_protected_ranges(
+        req: HttpRequest,
+        requester: Requester,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        http::check_guest_rate_limit(&req, Some(&requester), &service)?;
+        let request = http::decode_protobuf_request::<GetProtectedRangesRequest>(&request_body)?;
+        let response = protected_ranges::get_protected_ranges(
+            &service.dynamodb_client,
+            &service.permission_cache,
+            &requester,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.list_archived")]
+    pub async fn list_archived(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<ListArchivedRequest>(&request_body)?;
+        let response =
+            archived_documents::list_archived(&service.dynamodb_client, &session_user, &request)
+                .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.list_attachments")]
+    pub async fn list_attachments(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<ListAttachmentsRequest>(&request_body)?;
+        let response =
+            attachments::list_attachments(&service.dynamodb_client, &session_user, &request)
                 .await?;
         http::create_protobuf_http_response(&response)
     }
 
     #[post("/api/documents.list_my_documents")]
     pub async fn list_my_documents(
-        session: Session,
+        session_user: SessionUser,
         request_body: actix_web::web::Bytes,
         service: web::Data<BackendService>,
     ) -> actix_web::Result<HttpResponse> {
-        let session_user = http::get_session_user(&session, &service).await?;
-        let request = ListMyDocumentsRequest::decode(&request_body[..])
-            .map_err(|_| error::ErrorBadRequest(""))?;
+        let request = http::decode_protobuf_request::<ListMyDocumentsRequest>(&request_body)?;
         let response =
             documents::list_my_documents(&service.dynamodb_client, &session_user, &request).await?;
         http::create_protobuf_http_response(&response)
     }
 
-    #[post("/api/documents.submit_document_change_set")]
-    pub async fn submit_document_change_set(
-        session: Session,
+    #[post("/api/documents.list_read_receipts")]
+    pub async fn list_read_receipts(
+        session_user: SessionUser,
         request_body: actix_web::web::Bytes,
         service: web::Data<BackendService>,
     ) -> actix_web::Result<HttpResponse> {
-        let session_user = http::get_session_user(&session, &service).await?;
-        let request = SubmitDocumentChangeSetRequest::decode(&request_body[..])
-            .map_err(|_| error::ErrorBadRequest(""))?;
-        let response = documents::submit_document_change_set(
+        let request = http::decode_protobuf_request::<ListReadReceiptsRequest>(&request_body)?;
+        let response =
+            read_receipts::list_read_receipts(&service.dynamodb_client, &session_user, &request)
+                .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.list_recently_viewed")]
+    pub async fn list_recently_viewed(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<ListRecentlyViewedRequest>(&request_body)?;
+        let response =
+            document_views::list_recently_viewed(&service.dynamodb_client, &session_user, &request)
+                .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.list_starred")]
+    pub async fn list_starred(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<ListStarredRequest>(&request_body)?;
+        let response =
+            starred_documents::list_starred(&service.dynamodb_client, &session_user, &request)
+                .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.merge_fork")]
+    pub async fn merge_fork(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<MergeForkRequest>(&request_body)?;
+        let response = forks::merge_fork(
             &service.dynamodb_client,
+            &service.edit_rate_limiter,
             &session_user,
             &request,
         )
         .await?;
+        if response.revision_number > 0 {
+            service.document_events.publish(RevisionCommitted {
+                doc_id: response.upstream_doc_id.clone(),
+                revision_number: response.revision_number,
+                author_user_id: session_user.user_id.as_str().to_string(),
+            });
+        }
         http::create_protobuf_http_response(&response)
     }
 
-    #[post("/api/documents.update_document_title")]
-    pub async fn update_document_title(
-        session: Session,
+    #[post("/api/documents.purge_document")]
+    pub async fn purge_document(
+        session_user: SessionUser,
         request_body: actix_web::web::Bytes,
         service: web::Data<BackendService>,
     ) -> actix_web::Result<HttpResponse> {
-        let session_user = http::get_session_user(&session, &service).await?;
-        let request = UpdateDocumentTitleRequest::decode(&request_body[..])
-            .map_err(|_| error::ErrorBadRequest(""))?;
+        let request = http::decode_protobuf_request::<PurgeDocumentRequest>(&request_body)?;
         let response =
-            documents::update_document_title(&service.dynamodb_client, &session_user, &request)
+            purged_documents::purge_document(&service.dynamodb_client, &session_user, &request)
                 .await?;
         http::create_protobuf_http_response(&response)
     }
+
+    #[post("/api/documents.replace_pattern")]
+    pub async fn replace_pattern(
+        req: HttpRequest,
+        requester: Requester,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        http::check_guest_rate_limit(&req, Some(&requester), &service)?;
+        let request = http::decode_protobuf_request::<ReplacePatternRequest>(&request_body)?;
+        let response = automation::replace_pattern(
+            &service.dynamodb_client,
+            &service.permission_cache,
+            &service.edit_rate_limiter,
+            &requester,
+            &request,
+        )
+        .await?;
+        if response.revision_number > 0 {
+            service.document_events.publish(RevisionCommitted {
+                doc_id: request.doc_id.clone(),
+                revision_number: response.revision_number,
+                author_user_id: requester.id().as_str().to_string(),
+            });
+            if !service.derived_data_from_stream {
+                spawn_edit_notifications(
+                    &service,
+                    &request.doc_id,
+                    response.revision_number,
+                    &requester,
+                );
+            }
+        }
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.report_read_position")]
+    pub async fn report_read_position(
+        req: HttpRequest,
+        requester: Requester,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        http::check_guest_rate_limit(&req, Some(&requester), &service)?;
+        let request = http::decode_protobuf_request::<ReportReadPositionRequest>(&request_body)?;
+        let response = read_receipts::report_read_position(
+            &service.dynamodb_client,
+            &service.permission_cache,
+            &requester,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.search_document_titles")]
+    pub async fn search_document_titles(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<SearchDocumentTitlesRequest>(&request_body)?;
+        let response =
+            documents::search_document_titles(&service.dynamodb_client, &session_user, &request)
+                .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.send_typing")]
+    pub async fn send_typing(
+        req: HttpRequest,
+        requester: Requester,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        http::check_guest_rate_limit(&req, Some(&requester), &service)?;
+        let request = http::decode_protobuf_request::<SendTypingRequest>(&request_body)?;
+        let response = document_events::send_typing(
+            &service.dynamodb_client,
+            &service.permission_cache,
+            &service.document_events,
+            &service.typing_rate_limiter,
+            &service.region_locks,
+            &requester,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.set_protected_ranges")]
+    pub async fn set_protected_ranges(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<SetProtectedRangesRequest>(&request_body)?;
+        let response = protected_ranges::set_protected_ranges(
+            &service.dynamodb_client,
+            &session_user,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.star_document")]
+    pub async fn star_document(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<StarDocumentRequest>(&request_body)?;
+        let response =
+            starred_documents::star_document(&service.dynamodb_client, &session_user, &request)
+                .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.submit_document_change_set")]
+    pub async fn submit_document_change_set(
+        req: HttpRequest,
+        requester: Requester,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        http::check_guest_rate_limit(&req, Some(&requester), &service)?;
+        let request =
+            http::decode_protobuf_request::<SubmitDocumentChangeSetRequest>(&request_body)?;
+        let response = documents::submit_document_change_set(
+            &service.dynamodb_client,
+            &service.permission_cache,
+            &service.edit_rate_limiter,
+            &requester,
+            &request,
+        )
+        .await
+        .map_err(|e| {
+            service.sync_metrics.record_error();
+            e
+        })?;
+        match response.response_code() {
+            ResponseCode::Ack => service.sync_metrics.record_ack(),
+            ResponseCode::DiscoveredNewRevisions => {
+                service.sync_metrics.record_discovered_new_revisions()
+            }
+            ResponseCode::Throttled => service.sync_metrics.record_throttled(),
+            _ => {}
+        }
+        if response.response_code() == ResponseCode::Ack && !response.revisions.is_empty() {
+            for revision in response.revisions.iter() {
+                if let Some(change_set) = &revision.change_set {
+                    service.region_locks.on_revision_committed(
+                        &request.doc_id,
+                        revision.revision_number,
+                        change_set,
+                    );
+                }
+                service.document_events.publish(RevisionCommitted {
+                    doc_id: request.doc_id.clone(),
+                    revision_number: revision.revision_number,
+                    author_user_id: revision.author_user_id.clone(),
+                });
+            }
+            // Notify followers in the background, so that the editor's sync loop does not wait on
+            // it. Once the revision stream processor is running, it notifies them instead.
+            if !service.derived_data_from_stream {
+                spawn_edit_notifications(
+                    &service,
+                    &request.doc_id,
+                    response.last_revision_number,
+                    &requester,
+                );
+            }
+        }
+        http::create_protobuf_http_response(&response)
+    }
+
+    /// Notifies the document's followers of an edit in the background. Errors are logged by
+    /// enqueue_edit_notifications.
+    fn spawn_edit_notifications(
+        service: &BackendService,
+        doc_id: &str,
+        revision_number: i64,
+        requester: &Requester,
+    ) {
+        let dynamodb_client = service.dynamodb_client.clone();
+        let doc_id = doc_id.to_string();
+        let editor_id = requester.id().clone();
+        actix_web::rt::spawn(async move {
+            let _ = notifications::enqueue_edit_notifications(
+                &dynamodb_client,
+                &doc_id,
+                revision_number,
+                &editor_id,
+            )
+            .await;
+        });
+    }
+
+    #[post("/api/documents.transfer_ownership")]
+    pub async fn transfer_ownership(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<TransferOwnershipRequest>(&request_body)?;
+        let response = documents::transfer_ownership(
+            &service.dynamodb_client,
+            &service.permission_cache,
+            &session_user,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.unarchive_document")]
+    pub async fn unarchive_document(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<UnarchiveDocumentRequest>(&request_body)?;
+        let response = archived_documents::unarchive_document(
+            &service.dynamodb_client,
+            &session_user,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.unfollow_document")]
+    pub async fn unfollow_document(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<UnfollowDocumentRequest>(&request_body)?;
+        let response =
+            notifications::unfollow_document(&service.dynamodb_client, &session_user, &request)
+                .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.unstar_document")]
+    pub async fn unstar_document(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<UnstarDocumentRequest>(&request_body)?;
+        let response =
+            starred_documents::unstar_document(&service.dynamodb_client, &session_user, &request)
+                .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.update_document_stats")]
+    pub async fn update_document_stats(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<UpdateDocumentStatsRequest>(&request_body)?;
+        let response = document_stats::update_document_stats(
+            &service.dynamodb_client,
+            &session_user,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.update_document_title")]
+    pub async fn update_document_title(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<UpdateDocumentTitleRequest>(&request_body)?;
+        let response = documents::update_document_title(
+            &service.dynamodb_client,
+            &service.permission_cache,
+            &session_user,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/documents.verify_document_revisions")]
+    pub async fn verify_document_revisions(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request =
+            http::decode_protobuf_request::<VerifyDocumentRevisionsRequest>(&request_body)?;
+        let response = revision_signatures::verify_document_revisions(
+            &service.dynamodb_client,
+            &service.permission_cache,
+            &session_user,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        use actix_web::http::StatusCode;
+        use actix_web::{test, App};
+        use prost::Message;
+
+        use ot::writing_proto::{
+            ChangeSet, CreateShareTokenRequest, DocumentSharingPermission,
+            SubmitDocumentChangeSetResponse,
+        };
+
+        use crate::http::sessions;
+        use crate::ids::{Id, IdType};
+        use crate::rate_limiter::{
+            EDIT_MAX_REVISIONS_PER_WINDOW, EDIT_RATE_LIMIT_WINDOW, GUEST_MAX_REQUESTS_PER_WINDOW,
+        };
+        use crate::share_tokens;
+        use crate::testing::fixtures::DocumentFixture;
+        use crate::testing::utils::{
+            default_backend_service, default_cookie_session, TestDynamoDb,
+        };
+        use crate::users::UserRole;
+
+        #[tokio::test]
+        async fn test_share_token_reads_without_session_are_rate_limited() {
+            let db = TestDynamoDb::new().await;
+            let owner = SessionUser {
+                user_id: Id::new(IdType::User),
+                org_id: Id::new(IdType::Organization),
+                user_role: UserRole::Default,
+            };
+            let doc = DocumentFixture::new()
+                .with_org_id(&owner.org_id)
+                .with_created_by_user_id(&owner.user_id);
+            doc.create(&db.dynamodb_client).await;
+            let share_token = share_tokens::create_share_token(
+                &db.dynamodb_client,
+                &owner,
+                &CreateShareTokenRequest {
+                    doc_id: doc.doc_id.as_str().to_string(),
+                    permission: DocumentSharingPermission::CanView.into(),
+                    expires_in_seconds: 3600,
+                },
+            )
+            .await
+            .unwrap()
+            .token;
+
+            let mut test_app = test::init_service(
+                App::new()
+                    .data(default_backend_service().await)
+                    .wrap(default_cookie_session())
+                    .service(get_document_revisions),
+            )
+            .await;
+            // No cookie, so there is no guest id to count the requests against, only the address.
+            let mut body = Vec::new();
+            GetDocumentRevisionsRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                share_token,
+                ..Default::default()
+            }
+            .encode(&mut body)
+            .unwrap();
+            let request = || {
+                test::TestRequest::post()
+                    .uri("/api/documents.get_document_revisions")
+                    .peer_addr("203.0.113.7:443".parse().unwrap())
+                    .set_payload(body.clone())
+                    .to_request()
+            };
+            for _ in 0..GUEST_MAX_REQUESTS_PER_WINDOW {
+                let response = test::call_service(&mut test_app, request()).await;
+                assert_eq!(response.status(), StatusCode::OK);
+            }
+            let response = test::call_service(&mut test_app, request()).await;
+            assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+
+        #[tokio::test]
+        async fn test_submit_document_change_set_throttled() {
+            let db = TestDynamoDb::new().await;
+            let owner = SessionUser {
+                user_id: Id::new(IdType::User),
+                org_id: Id::new(IdType::Organization),
+                user_role: UserRole::Default,
+            };
+            let doc = DocumentFixture::new()
+                .with_org_id(&owner.org_id)
+                .with_created_by_user_id(&owner.user_id);
+            doc.create(&db.dynamodb_client).await;
+            let share_token = share_tokens::create_share_token(
+                &db.dynamodb_client,
+                &owner,
+                &CreateShareTokenRequest {
+                    doc_id: doc.doc_id.as_str().to_string(),
+                    permission: DocumentSharingPermission::CanEdit.into(),
+                    expires_in_seconds: 3600,
+                },
+            )
+            .await
+            .unwrap()
+            .token;
+
+            // Followers are notified in tasks on the actix runtime, which tokio tests do not run.
+            let mut service = default_backend_service().await;
+            service.derived_data_from_stream = true;
+            let mut test_app = test::init_service(
+                App::new()
+                    .data(service)
+                    .wrap(default_cookie_session())
+                    .service(sessions::submit_join)
+                    .service(submit_document_change_set),
+            )
+            .await;
+            let request = test::TestRequest::post()
+                .uri(&format!("/join/{}", share_token))
+                .peer_addr("203.0.113.7:443".parse().unwrap())
+                .set_form(&maplit::hashmap! { "display_name" => "Guest" })
+                .to_request();
+            let response = test::call_service(&mut test_app, request).await;
+            assert_eq!(response.status(), StatusCode::SEE_OTHER);
+            let cookie = response.response().cookies().next().unwrap().into_owned();
+
+            // Appends a character to the document, which has `revision_number` characters so far.
+            let submit_request = |revision_number: i64, share_token: &str| {
+                let mut change_set = ChangeSet::new();
+                change_set.retain(revision_number);
+                change_set.insert("a");
+                let mut body = Vec::new();
+                SubmitDocumentChangeSetRequest {
+                    doc_id: doc.doc_id.as_str().to_string(),
+                    on_revision_number: revision_number,
+                    change_set: Some(change_set),
+                    share_token: share_token.to_string(),
+                    ..Default::default()
+                }
+                .encode(&mut body)
+                .unwrap();
+                test::TestRequest::post()
+                    .uri("/api/documents.submit_document_change_set")
+                    .peer_addr("203.0.113.7:443".parse().unwrap())
+                    .cookie(cookie.clone())
+                    .set_payload(body)
+                    .to_request()
+            };
+
+            // Requests without access do not spend the budget.
+            for _ in 0..EDIT_MAX_REVISIONS_PER_WINDOW {
+                let response = test::call_service(&mut test_app, submit_request(0, "wrong")).await;
+                assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            }
+            for revision_number in 0..EDIT_MAX_REVISIONS_PER_WINDOW as i64 {
+                let response = test::read_response(
+                    &mut test_app,
+                    submit_request(revision_number, &share_token),
+                )
+                .await;
+                let response = SubmitDocumentChangeSetResponse::decode(response).unwrap();
+                assert_eq!(response.response_code(), ResponseCode::Ack);
+            }
+
+            let last_revision_number = EDIT_MAX_REVISIONS_PER_WINDOW as i64;
+            let response = test::read_response(
+                &mut test_app,
+                submit_request(last_revision_number, &share_token),
+            )
+            .await;
+            let response = SubmitDocumentChangeSetResponse::decode(response).unwrap();
+            assert_eq!(response.response_code(), ResponseCode::Throttled);
+            assert_eq!(response.last_revision_number, last_revision_number);
+            assert!(response.retry_after_millis > 0);
+            assert!(response.retry_after_millis <= EDIT_RATE_LIMIT_WINDOW.as_millis() as i64);
+        }
+    }
+}
+
+pub mod orgs {
+
+    use actix_web::{post, web, HttpResponse};
+
+    use ot::writing_proto::{
+        GetOrgExportRequest, ListOrgRevisionsRequest, SetOrgTwoFactorRequiredRequest,
+        StartOrgExportRequest,
+    };
+
+    use crate::exports;
+    use crate::http::{self, SessionUser};
+    use crate::org_revisions;
+    use crate::two_factor;
+    use crate::BackendService;
+
+    #[post("/api/orgs.get_org_export")]
+    pub async fn get_org_export(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<GetOrgExportRequest>(&request_body)?;
+        let response = exports::get_org_export(
+            &service.dynamodb_client,
+            service.export_store.as_ref(),
+            &session_user,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/orgs.list_org_revisions")]
+    pub async fn list_org_revisions(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<ListOrgRevisionsRequest>(&request_body)?;
+        let response =
+            org_revisions::list_org_revisions(&service.dynamodb_client, &session_user, &request)
+                .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/orgs.set_two_factor_required")]
+    pub async fn set_two_factor_required(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request =
+            http::decode_protobuf_request::<SetOrgTwoFactorRequiredRequest>(&request_body)?;
+        let response = two_factor::set_org_two_factor_required(
+            &service.dynamodb_client,
+            &session_user,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/orgs.start_org_export")]
+    pub async fn start_org_export(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<StartOrgExportRequest>(&request_body)?;
+        let response =
+            exports::start_org_export(&service.dynamodb_client, &session_user, &request).await?;
+        http::create_protobuf_http_response(&response)
+    }
+}
+
+pub mod server {
+
+    use actix_web::{error, post, web, HttpRequest, HttpResponse};
+
+    use ot::protocol::PROTOCOL_VERSION;
+    use ot::writing_proto::{GetServerCapabilitiesRequest, GetServerCapabilitiesResponse};
+
+    use crate::feature_flags::{self, GLOBAL_ORG_ID};
+    use crate::http::{self, SessionUser, MIN_SUPPORTED_PROTOCOL_VERSION};
+    use crate::BackendService;
+
+    /// Lets clients check whether they are still supported, and which feature flags are on for
+    /// them. No session is needed, and clients of any protocol version may call it.
+    #[post("/api/server.get_capabilities")]
+    pub async fn get_capabilities(
+        req: HttpRequest,
+        session_user: Option<SessionUser>,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        if session_user.is_none() && !http::try_acquire_guest_permit(&req, &service) {
+            return Err(error::ErrorTooManyRequests(""));
+        }
+        http::decode_protobuf_request::<GetServerCapabilitiesRequest>(&request_body)?;
+        let org_id = session_user
+            .as_ref()
+            .map_or(GLOBAL_ORG_ID, |session_user| session_user.org_id.as_str());
+        // Clients carry on without flags rather than fail to start.
+        let enabled_feature_flags = feature_flags::get_feature_flags(
+            &service.dynamodb_client,
+            &service.feature_flag_cache,
+            org_id,
+        )
+        .await
+        .map(|flags| flags.enabled_names())
+        .unwrap_or_else(|e| {
+            log::error!(
+                "Error occurred: \"{}\" [get_capabilities] [session_user: {:?}]",
+                e,
+                session_user,
+            );
+            Vec::new()
+        });
+        http::create_protobuf_http_response(&GetServerCapabilitiesResponse {
+            protocol_version: PROTOCOL_VERSION,
+            min_supported_protocol_version: MIN_SUPPORTED_PROTOCOL_VERSION,
+            enabled_feature_flags,
+        })
+    }
+}
+
+pub mod share_tokens {
+
+    use actix_web::{post, web, HttpResponse};
+
+    use ot::writing_proto::{CreateShareTokenRequest, RevokeShareTokenRequest};
+
+    use crate::http::{self, SessionUser};
+    use crate::share_tokens;
+    use crate::BackendService;
+
+    #[post("/api/share_tokens.create_share_token")]
+    pub async fn create_share_token(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<CreateShareTokenRequest>(&request_body)?;
+        let response =
+            share_tokens::create_share_token(&service.dynamodb_client, &session_user, &request)
+                .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/share_tokens.revoke_share_token")]
+    pub async fn revoke_share_token(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<RevokeShareTokenRequest>(&request_body)?;
+        let response =
+            share_tokens::revoke_share_token(&service.dynamodb_client, &session_user, &request)
+                .await?;
+        http::create_protobuf_http_response(&response)
+    }
+}
+
+pub mod sharing {
+
+    use actix_web::{post, web, HttpResponse};
+
+    use ot::writing_proto::{ShareDocumentRequest, ShareDocumentsRequest};
+
+    use crate::http::{self, SessionUser};
+    use crate::sharing;
+    use crate::BackendService;
+
+    #[post("/api/sharing.share_document")]
+    pub async fn share_document(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<ShareDocumentRequest>(&request_body)?;
+        let response = sharing::share_document(
+            &service.dynamodb_client,
+            &service.permission_cache,
+            &session_user,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/sharing.share_documents")]
+    pub async fn share_documents(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<ShareDocumentsRequest>(&request_body)?;
+        let response = sharing::share_documents(
+            &service.dynamodb_client,
+            &service.permission_cache,
+            &session_user,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+}
+
+pub mod signing_keys {
+
+    use actix_web::{post, web, HttpResponse};
+
+    use ot::writing_proto::RegisterSigningKeyRequest;
+
+    use crate::http::{self, SessionUser};
+    use crate::revision_signatures;
+    use crate::BackendService;
+
+    #[post("/api/signing_keys.register_signing_key")]
+    pub async fn register_signing_key(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<RegisterSigningKeyRequest>(&request_body)?;
+        let response = revision_signatures::register_signing_key(
+            &service.dynamodb_client,
+            &session_user,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+}
+
+pub mod users {
+
+    use actix_session::Session;
+    use actix_web::{post, web, HttpResponse};
+
+    use ot::writing_proto::{
+        ConfirmAvatarUploadRequest, DeleteAccountRequest, DisableTwoFactorRequest,
+        EnableTwoFactorRequest, GetMyProfileRequest, ProvisionTwoFactorRequest,
+        RequestAvatarUploadRequest, UpdateMyProfileRequest,
+    };
+
+    use crate::accounts;
+    use crate::avatars;
+    use crate::http::{self, SessionUser};
+    use crate::two_factor;
+    use crate::users;
+    use crate::BackendService;
+
+    #[post("/api/users.confirm_avatar_upload")]
+    pub async fn confirm_avatar_upload(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<ConfirmAvatarUploadRequest>(&request_body)?;
+        let response = avatars::confirm_avatar_upload(
+            &service.dynamodb_client,
+            service.upload_store.as_ref(),
+            &session_user,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/users.delete_account")]
+    pub async fn delete_account(
+        session: Session,
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<DeleteAccountRequest>(&request_body)?;
+        let response =
+            accounts::delete_account(&service.dynamodb_client, &session_user, &request).await?;
+        session.purge();
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/users.disable_two_factor")]
+    pub async fn disable_two_factor(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<DisableTwoFactorRequest>(&request_body)?;
+        let response =
+            two_factor::disable_two_factor(&service.dynamodb_client, &session_user, &request)
+                .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/users.enable_two_factor")]
+    pub async fn enable_two_factor(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<EnableTwoFactorRequest>(&request_body)?;
+        let response =
+            two_factor::enable_two_factor(&service.dynamodb_client, &session_user, &request)
+                .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/users.get_my_profile")]
+    pub async fn get_my_profile(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<GetMyProfileRequest>(&request_body)?;
+        let response = users::get_my_profile(
+            &service.dynamodb_client,
+            service.photo_store.as_ref(),
+            &session_user,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/users.provision_two_factor")]
+    pub async fn provision_two_factor(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<ProvisionTwoFactorRequest>(&request_body)?;
+        let response =
+            two_factor::provision_two_factor(&service.dynamodb_client, &session_user, &request)
+                .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/users.request_avatar_upload")]
+    pub async fn request_avatar_upload(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<RequestAvatarUploadRequest>(&request_body)?;
+        let response =
+            avatars::request_avatar_upload(service.upload_store.as_ref(), &session_user, &request)
+                .await?;
+        http::create_protobuf_http_response(&response)
+    }
+
+    #[post("/api/users.update_my_profile")]
+    pub async fn update_my_profile(
+        session_user: SessionUser,
+        request_body: actix_web::web::Bytes,
+        service: web::Data<BackendService>,
+    ) -> actix_web::Result<HttpResponse> {
+        let request = http::decode_protobuf_request::<UpdateMyProfileRequest>(&request_body)?;
+        let response = users::update_my_profile(
+            &service.dynamodb_client,
+            service.photo_store.as_ref(),
+            &session_user,
+            &request,
+        )
+        .await?;
+        http::create_protobuf_http_response(&response)
+    }
 }
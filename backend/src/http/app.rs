@@ -1,17 +1,40 @@
-use actix_session::Session;
 use actix_web::http::header;
-use actix_web::{get, web, HttpResponse};
+use actix_web::{get, HttpResponse};
 
-use crate::http;
-use crate::BackendService;
+use crate::http::SessionUser;
 
 #[get("/app")]
-pub async fn home(
-    session: Session,
-    service: web::Data<BackendService>,
-) -> actix_web::Result<HttpResponse> {
-    let _user = http::get_session_user(&session, &service).await?;
+pub async fn home(_session_user: SessionUser) -> actix_web::Result<HttpResponse> {
     Ok(HttpResponse::SeeOther()
         .set_header(header::LOCATION, "http://localhost:3000/")
         .finish())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use actix_web::test::TestRequest;
+    use actix_web::App;
+
+    use crate::testing::utils::{default_backend_service, default_cookie_session};
+
+    #[tokio::test]
+    async fn test_home_without_session_user() {
+        let mut test_app = test::init_service(
+            App::new()
+                .data(default_backend_service().await)
+                .wrap(default_cookie_session())
+                .service(home),
+        )
+        .await;
+
+        // No session cookie, so the SessionUser extractor should reject the request.
+        let request = TestRequest::get().uri("/app").to_request();
+        let response = test::call_service(&mut test_app, request).await;
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
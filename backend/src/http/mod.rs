@@ -2,37 +2,354 @@ pub mod api;
 pub mod app;
 pub mod marketing;
 pub mod sessions;
+pub mod trace;
 
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 
-use actix_session::{CookieSession, Session};
-use actix_web::{error, HttpResponse};
-use rusoto_dynamodb::{DynamoDb, GetItemInput};
+use actix_session::{CookieSession, Session, UserSession};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::{header, HeaderValue};
+use actix_web::{error, web, FromRequest, HttpRequest, HttpResponse};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use rusoto_dynamodb::GetItemInput;
+
+use ot::protocol::{PROTOBUF_CONTENT_TYPE, PROTOCOL_VERSION_HEADER, TRACE_ID_HEADER};
 
 use crate::dynamodb::{av_get_n, av_map, av_s, table_name};
-use crate::ids::Id;
+use crate::ids::{Id, IdType};
 use crate::users::UserRole;
 use crate::utils::proto;
 use crate::BackendService;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct SessionUser {
     pub user_id: Id,
     pub org_id: Id,
     pub user_role: UserRole,
 }
 
+/// Lets handlers declare `session_user: SessionUser` in their signature instead of calling
+/// `get_session_user` themselves. If the session is missing or invalid, the request is rejected
+/// with 401 Unauthorized. Declare `Option<SessionUser>` to handle logged-out users instead.
+///
+/// The organization_users lookup happens at most once per request. The result is stored in the
+/// request extensions so that middleware and the handler can both extract it.
+impl FromRequest for SessionUser {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, actix_web::Result<SessionUser>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            if let Some(session_user) = req.extensions().get::<SessionUser>() {
+                return Ok(session_user.clone());
+            }
+            let service = req
+                .app_data::<web::Data<BackendService>>()
+                .ok_or_else(|| {
+                    log::error!("BackendService is missing from app data");
+                    error::ErrorInternalServerError("")
+                })?
+                .clone();
+            let session_user = get_session_user(&req.get_session(), &service).await?;
+            req.extensions_mut().insert(session_user.clone());
+            Ok(session_user)
+        })
+    }
+}
+
+/// Someone without an account who joined a document through a share link. Guest sessions store a
+/// guest id and display name in the session cookie instead of a user id and org id.
+#[derive(Clone, Debug)]
+pub struct GuestUser {
+    pub guest_id: Id,
+    pub display_name: String,
+}
+
+/// Rejects the request with 401 Unauthorized if the session is not a guest session.
+impl FromRequest for GuestUser {
+    type Error = actix_web::Error;
+    type Future = Ready<actix_web::Result<GuestUser>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(get_guest_user(&req.get_session()))
+    }
+}
+
+/// Whoever is making the request: a logged-in user, or a guest.
+#[derive(Clone, Debug)]
+pub enum Requester {
+    User(SessionUser),
+    Guest(GuestUser),
+}
+
+impl Requester {
+    pub fn session_user(&self) -> Option<&SessionUser> {
+        match self {
+            Requester::User(session_user) => Some(session_user),
+            Requester::Guest(_) => None,
+        }
+    }
+
+    /// The user id, or the guest id.
+    pub fn id(&self) -> &Id {
+        match self {
+            Requester::User(session_user) => &session_user.user_id,
+            Requester::Guest(guest_user) => &guest_user.guest_id,
+        }
+    }
+}
+
+/// Extracts a logged-in user if there is one, and a guest otherwise. If the session is neither,
+/// rejects the request with 401 Unauthorized.
+impl FromRequest for Requester {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, actix_web::Result<Requester>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let session_user = SessionUser::from_request(req, payload);
+        let req = req.clone();
+        Box::pin(async move {
+            match session_user.await {
+                Ok(session_user) => Ok(Requester::User(session_user)),
+                Err(_) => Ok(Requester::Guest(get_guest_user(&req.get_session())?)),
+            }
+        })
+    }
+}
+
+/// Guests are not accountable the way logged-in users are, so they get a tighter request budget.
+/// So do requests with no session at all, like reads with only a share token. Pass the requester,
+/// if there is one. Returns 429 Too Many Requests if the budget is used up.
+pub fn check_guest_rate_limit(
+    req: &HttpRequest,
+    requester: Option<&Requester>,
+    service: &BackendService,
+) -> actix_web::Result<()> {
+    if let Some(Requester::User(_)) = requester {
+        return Ok(());
+    }
+    if !try_acquire_guest_permit(req, service) {
+        return Err(error::ErrorTooManyRequests(""));
+    }
+    Ok(())
+}
+
+/// Counts a request from a guest, or from someone joining as one, against the guest budget.
+/// Returns false if the budget is used up.
+///
+/// The budget is kept per peer address rather than per guest id. Anyone can get a new guest id by
+/// dropping the session cookie and joining again, but not a new address as easily.
+pub fn try_acquire_guest_permit(req: &HttpRequest, service: &BackendService) -> bool {
+    let peer_ip = req
+        .peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_default();
+    service.guest_rate_limiter.try_acquire(&peer_ip)
+}
+
+/// The oldest client protocol version that the server accepts. Raise it when the server can no
+/// longer serve older clients, e.g. once documents may hold ops that they cannot apply.
+pub const MIN_SUPPORTED_PROTOCOL_VERSION: u32 = 1;
+
+/// Exempt from the protocol version check, so that outdated clients can find out that they are
+/// outdated.
+const SERVER_CAPABILITIES_PATH: &str = "/api/server.get_capabilities";
+
+/// Rejects API requests from clients whose protocol version is older than
+/// `MIN_SUPPORTED_PROTOCOL_VERSION` with 426 Upgrade Required, so that they can tell the user to
+/// reload instead of failing to decode responses. Returns 400 Bad Request if the version is not a
+/// number.
+pub fn check_client_protocol_version(req: &ServiceRequest) -> actix_web::Result<()> {
+    if !req.path().starts_with("/api/") || req.path() == SERVER_CAPABILITIES_PATH {
+        return Ok(());
+    }
+    // Event streams cannot set headers, so they pass the version in the query string instead.
+    let value = match req.headers().get(PROTOCOL_VERSION_HEADER) {
+        Some(value) => Some(value.to_str().map_err(|_| error::ErrorBadRequest(""))?),
+        None => req
+            .query_string()
+            .split('&')
+            .find_map(|param| param.strip_prefix("protocol_version=")),
+    };
+    let protocol_version = match value {
+        Some(value) => value
+            .trim()
+            .parse::<u32>()
+            .map_err(|_| error::ErrorBadRequest(""))?,
+        // Clients that predate the version speak version 1.
+        None => 1,
+    };
+    if protocol_version < MIN_SUPPORTED_PROTOCOL_VERSION {
+        return Err(error::ErrorUpgradeRequired(""));
+    }
+    Ok(())
+}
+
+/// Rejects API POST requests whose Content-Type is not `PROTOBUF_CONTENT_TYPE` with 415
+/// Unsupported Media Type. Browsers send cross-site requests with other types, like `text/plain`,
+/// without asking first, so this keeps other sites from posting to the API with the user's cookies
+/// unless CORS lets them.
+pub fn check_protobuf_content_type(req: &ServiceRequest) -> actix_web::Result<()> {
+    if !req.path().starts_with("/api/")
+        || req.method() != actix_web::http::Method::POST
+        || req.path() == SERVER_CAPABILITIES_PATH
+    {
+        return Ok(());
+    }
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(str::trim);
+    match content_type {
+        Some(content_type) if content_type.eq_ignore_ascii_case(PROTOBUF_CONTENT_TYPE) => Ok(()),
+        _ => Err(error::ErrorUnsupportedMediaType("")),
+    }
+}
+
+/// Endpoints whose requests can hold a lot of document text, like a big paste, and so get
+/// `RequestBodyLimits::document_text` instead of the default limit.
+const DOCUMENT_TEXT_PATHS: [&str; 3] = [
+    "/api/documents.append_to_document",
+    "/api/documents.replace_pattern",
+    "/api/documents.submit_document_change_set",
+];
+
+/// The largest request bodies that API endpoints accept, in bytes.
+#[derive(Clone, Copy, Debug)]
+pub struct RequestBodyLimits {
+    /// For most endpoints, whose requests hold ids and short strings.
+    pub default: usize,
+    /// For the endpoints in `DOCUMENT_TEXT_PATHS`.
+    pub document_text: usize,
+}
+
+impl RequestBodyLimits {
+    pub fn for_path(&self, path: &str) -> usize {
+        if DOCUMENT_TEXT_PATHS.contains(&path) {
+            self.document_text
+        } else {
+            self.default
+        }
+    }
+
+    /// The limit for bodies that get past `check_request_body_size` without a length, i.e. none.
+    /// Set it as the app's `PayloadConfig` limit anyway, so that nothing reads more than this.
+    pub fn max(&self) -> usize {
+        self.default.max(self.document_text)
+    }
+}
+
+/// Rejects API requests whose bodies are larger than the endpoint's limit with 413 Payload Too
+/// Large, going by their Content-Length, before the session is looked up or any of the body is
+/// read. Rejects API requests with a body but no Content-Length, i.e. chunked ones, with 411
+/// Length Required, since their size is only known once they have been read. Clients never send
+/// them.
+pub fn check_request_body_size(
+    req: &ServiceRequest,
+    limits: &RequestBodyLimits,
+) -> actix_web::Result<()> {
+    if !req.path().starts_with("/api/") {
+        return Ok(());
+    }
+    let content_length = match req.headers().get(header::CONTENT_LENGTH) {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|value| value.trim().parse::<usize>().ok())
+            .ok_or_else(|| error::ErrorBadRequest(""))?,
+        None if req.headers().contains_key(header::TRANSFER_ENCODING) => {
+            return Err(error::ErrorLengthRequired(""));
+        }
+        None => 0,
+    };
+    if content_length > limits.for_path(req.path()) {
+        return Err(error::ErrorPayloadTooLarge(""));
+    }
+    Ok(())
+}
+
+const SESSION_COOKIE_NAME: &str = "session";
 const SESSION_COOKIE_MAX_AGE: i64 = 30 * 86400; // 30 days
 
+/// Session key for whether the user asked to stay logged in. Sessions without it, e.g. guest
+/// sessions and sessions from before the choice existed, keep the cookie for
+/// `SESSION_COOKIE_MAX_AGE`.
+pub const KEEP_LOGGED_IN_SESSION_KEY: &str = "keep_logged_in";
+
+/// Creates the session cookie middleware. Every session cookie it sets lasts for
+/// `SESSION_COOKIE_MAX_AGE`. `CookieSession` cannot vary the max age per session, so sessions that
+/// should end with the browser session are handled around it: wrap the app with
+/// `record_session_cookie_lifetime` inside this middleware, and with
+/// `apply_session_cookie_lifetime` outside it.
 pub fn create_cookie_session(cookie_secret: &[u8], cookie_secure: bool) -> CookieSession {
     CookieSession::private(cookie_secret)
-        .name("session")
+        .name(SESSION_COOKIE_NAME)
         .secure(cookie_secure)
         .http_only(true)
         .same_site(cookie::SameSite::Strict)
         .max_age(SESSION_COOKIE_MAX_AGE)
 }
 
+/// Marks a request whose session cookie should end with the browser session.
+struct BrowserSessionCookie;
+
+/// Remembers whether the session cookie should end with the browser session. Must run inside
+/// `CookieSession`, which takes the session state once the handler is done with it.
+pub fn record_session_cookie_lifetime<B>(res: &ServiceResponse<B>) {
+    let keep_logged_in = res
+        .request()
+        .get_session()
+        .get::<bool>(KEEP_LOGGED_IN_SESSION_KEY)
+        .unwrap_or(None);
+    if keep_logged_in == Some(false) {
+        res.request().extensions_mut().insert(BrowserSessionCookie);
+    }
+}
+
+/// Drops the max age from the session cookie of a request marked by
+/// `record_session_cookie_lifetime`, so that the browser forgets the cookie when it closes. Must
+/// run outside `CookieSession`, which sets the cookie on the response.
+pub fn apply_session_cookie_lifetime<B>(res: &mut ServiceResponse<B>) {
+    if res
+        .request()
+        .extensions()
+        .get::<BrowserSessionCookie>()
+        .is_none()
+    {
+        return;
+    }
+    let set_cookies: Vec<HeaderValue> =
+        res.headers().get_all(header::SET_COOKIE).cloned().collect();
+    let headers = res.headers_mut();
+    headers.remove(header::SET_COOKIE);
+    for value in set_cookies {
+        let cookie = value
+            .to_str()
+            .ok()
+            .and_then(|value| cookie::Cookie::parse(value).ok())
+            .map(|cookie| cookie.into_owned());
+        let value = match cookie {
+            // Cookies that log the user out have a zero max age. Leave them alone.
+            Some(mut cookie)
+                if cookie.name() == SESSION_COOKIE_NAME
+                    && cookie.max_age().map(|max_age| max_age.whole_seconds())
+                        == Some(SESSION_COOKIE_MAX_AGE) =>
+            {
+                cookie.set_max_age(None);
+                HeaderValue::from_str(&cookie.to_string()).unwrap_or(value)
+            }
+            _ => value,
+        };
+        headers.append(header::SET_COOKIE, value);
+    }
+}
+
 pub async fn get_session_user(
     session: &Session,
     service: &BackendService,
@@ -40,7 +357,10 @@ pub async fn get_session_user(
     let org_id = extract_session_cookie_id(session, "org_id");
     let user_id = extract_session_cookie_id(session, "user_id");
     if org_id.is_none() || user_id.is_none() {
-        session.purge();
+        // Guest sessions have no user. Leave them intact.
+        if extract_session_cookie_id(session, "guest_id").is_none() {
+            session.purge();
+        }
         return Err(error::ErrorUnauthorized(""));
     }
     let org_id = org_id.unwrap();
@@ -80,6 +400,103 @@ pub async fn get_session_user(
     })
 }
 
+pub fn get_guest_user(session: &Session) -> actix_web::Result<GuestUser> {
+    let guest_id = extract_session_cookie_id(session, "guest_id")
+        .filter(|guest_id| guest_id.id_type == IdType::Guest)
+        .ok_or_else(|| error::ErrorUnauthorized(""))?;
+    let display_name = match session.get::<String>("guest_name") {
+        Ok(Some(display_name)) if !display_name.is_empty() => display_name,
+        _ => return Err(error::ErrorUnauthorized("")),
+    };
+    Ok(GuestUser {
+        guest_id,
+        display_name,
+    })
+}
+
+/// Top-level length-delimited fields longer than this are rejected if the request's message does
+/// not know them. Newer clients may send fields that this server does not know yet, but they are
+/// small.
+const MAX_UNKNOWN_FIELD_LEN: usize = 4096;
+
+/// Decodes a protobuf request body, returning 400 Bad Request if it is invalid.
+///
+/// Before decoding, walks the message's top-level fields without decoding them, so that a body
+/// whose lengths run past its end, or that is padded out with long fields the message does not
+/// know, is rejected before `decode` allocates anything for it. Prost would otherwise skip unknown
+/// fields of any size.
+pub fn decode_protobuf_request<M>(body: &[u8]) -> actix_web::Result<M>
+where
+    M: prost::Message + Default,
+{
+    check_request_fields::<M>(body).ok_or_else(|| error::ErrorBadRequest(""))?;
+    M::decode(body).map_err(|_| error::ErrorBadRequest(""))
+}
+
+fn check_request_fields<M>(mut body: &[u8]) -> Option<()>
+where
+    M: prost::Message + Default,
+{
+    while !body.is_empty() {
+        let field_start = body;
+        let key = read_varint(&mut body)?;
+        if key >> 3 == 0 || key > u64::from(u32::MAX) {
+            return None;
+        }
+        match key & 7 {
+            // Varint
+            0 => {
+                read_varint(&mut body)?;
+            }
+            // 64-bit
+            1 => body = body.get(8..)?,
+            // Length-delimited
+            2 => {
+                let key_bytes = &field_start[..field_start.len() - body.len()];
+                let len = usize::try_from(read_varint(&mut body)?).ok()?;
+                if len > body.len()
+                    || (len > MAX_UNKNOWN_FIELD_LEN && !is_known_field::<M>(key_bytes))
+                {
+                    return None;
+                }
+                body = &body[len..];
+            }
+            // 32-bit
+            5 => body = body.get(4..)?,
+            // Groups are deprecated, and no message has any.
+            _ => return None,
+        }
+    }
+    Some(())
+}
+
+/// Whether `M` has a length-delimited field with the given encoded key. Decodes the field with a
+/// one-byte zero value to find out: `M` skips a field it does not know and stays empty, but keeps
+/// a string, bytes or packed field, and fails to decode a nested message from a zero tag.
+fn is_known_field<M>(key_bytes: &[u8]) -> bool
+where
+    M: prost::Message + Default,
+{
+    let mut probe = key_bytes.to_vec();
+    probe.extend_from_slice(&[1, 0]);
+    match M::decode(&probe[..]) {
+        Ok(message) => message.encoded_len() > 0,
+        Err(_) => true,
+    }
+}
+
+fn read_varint(buf: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for (i, byte) in buf.iter().take(10).enumerate() {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *buf = &buf[i + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
+
 pub fn create_protobuf_http_response<M>(message: &M) -> actix_web::Result<HttpResponse>
 where
     M: prost::Message,
@@ -88,7 +505,7 @@ where
         proto::encode_protobuf_message(message).map_err(|_| error::ErrorInternalServerError(""))?;
 
     Ok(HttpResponse::Ok()
-        .content_type("application/protobuf")
+        .content_type(PROTOBUF_CONTENT_TYPE)
         .body(encoded))
 }
 
@@ -99,9 +516,248 @@ pub fn extract_session_cookie_id(session: &Session, key: &str) -> Option<Id> {
     }
 }
 
-pub fn configure_cors() -> actix_cors::Cors {
-    // TODO(cliff): Make this sane!!! Doing this just to unblock local development.
-    actix_cors::Cors::default()
-        .allow_any_origin()
-        .send_wildcard()
+/// How long browsers may reuse a preflight response.
+const CORS_MAX_AGE_SECONDS: usize = 3600;
+
+/// Lets pages on the given origins, e.g. `https://writing.example.com`, call the API. With
+/// `allow_credentials`, their requests carry the user's cookies. Same-origin requests need neither.
+pub fn configure_cors(allowed_origins: &[String], allow_credentials: bool) -> actix_cors::Cors {
+    let mut cors = actix_cors::Cors::default()
+        .allowed_methods(vec!["GET", "POST"])
+        .allowed_headers(vec![
+            header::CONTENT_TYPE.as_str(),
+            PROTOCOL_VERSION_HEADER,
+            TRACE_ID_HEADER,
+        ])
+        .expose_headers(vec![TRACE_ID_HEADER])
+        .max_age(CORS_MAX_AGE_SECONDS);
+    for origin in allowed_origins {
+        cors = cors.allowed_origin(origin);
+    }
+    if allow_credentials {
+        cors = cors.supports_credentials();
+    }
+    cors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_check_client_protocol_version() {
+        let check = |path: &str, protocol_version: Option<&str>| {
+            let mut request = TestRequest::post().uri(path);
+            if let Some(protocol_version) = protocol_version {
+                request = request.header(PROTOCOL_VERSION_HEADER, protocol_version);
+            }
+            check_client_protocol_version(&request.to_srv_request())
+                .map_err(|e| e.as_response_error().status_code().as_u16())
+        };
+        assert_eq!(check("/api/documents.get_document", None), Ok(()));
+        assert_eq!(check("/api/documents.get_document", Some("1")), Ok(()));
+        // Newer clients are fine, since they only add fields.
+        assert_eq!(check("/api/documents.get_document", Some("7")), Ok(()));
+        assert_eq!(check("/api/documents.get_document", Some("0")), Err(426));
+        assert_eq!(check("/api/documents.get_document", Some("one")), Err(400));
+        assert_eq!(check(SERVER_CAPABILITIES_PATH, Some("0")), Ok(()));
+        assert_eq!(check("/log_in", Some("0")), Ok(()));
+
+        let check_query = |uri: &str| {
+            check_client_protocol_version(&TestRequest::get().uri(uri).to_srv_request())
+                .map_err(|e| e.as_response_error().status_code().as_u16())
+        };
+        assert_eq!(
+            check_query("/api/documents.events?doc_id=x&protocol_version=1"),
+            Ok(())
+        );
+        assert_eq!(
+            check_query("/api/documents.events?doc_id=x&protocol_version=0"),
+            Err(426)
+        );
+    }
+
+    #[test]
+    fn test_check_protobuf_content_type() {
+        let check = |method: &str, path: &str, content_type: Option<&str>| {
+            let mut request = TestRequest::default()
+                .method(method.parse().unwrap())
+                .uri(path);
+            if let Some(content_type) = content_type {
+                request = request.header(header::CONTENT_TYPE, content_type);
+            }
+            check_protobuf_content_type(&request.to_srv_request())
+                .map_err(|e| e.as_response_error().status_code().as_u16())
+        };
+        let path = "/api/documents.get_document";
+        assert_eq!(check("POST", path, Some("application/protobuf")), Ok(()));
+        assert_eq!(
+            check("POST", path, Some("Application/Protobuf; charset=binary")),
+            Ok(())
+        );
+        assert_eq!(check("POST", path, Some("text/plain")), Err(415));
+        assert_eq!(check("POST", path, None), Err(415));
+        // Event streams are GET requests, and outdated clients must still be able to find out
+        // that they are outdated.
+        assert_eq!(check("GET", "/api/documents.events?doc_id=x", None), Ok(()));
+        assert_eq!(check("POST", SERVER_CAPABILITIES_PATH, None), Ok(()));
+        assert_eq!(
+            check("POST", "/log_in", Some("application/x-www-form-urlencoded")),
+            Ok(())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cors_preflight() {
+        use actix_web::{test, App};
+
+        let mut test_app = test::init_service(
+            App::new()
+                .wrap(configure_cors(
+                    &["https://writing.example.com".to_string()],
+                    true,
+                ))
+                .route(
+                    "/api/documents.get_document",
+                    web::post().to(|| async { HttpResponse::Ok().finish() }),
+                ),
+        )
+        .await;
+        let preflight = |origin: &str, method: &str, headers: &str| {
+            TestRequest::default()
+                .method(actix_web::http::Method::OPTIONS)
+                .uri("/api/documents.get_document")
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, method)
+                .header(header::ACCESS_CONTROL_REQUEST_HEADERS, headers)
+                .to_request()
+        };
+        let allowed_origin = |response: &ServiceResponse| {
+            response
+                .headers()
+                .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .map(|value| value.to_str().unwrap().to_string())
+        };
+
+        let response = test::call_service(
+            &mut test_app,
+            preflight(
+                "https://writing.example.com",
+                "POST",
+                "content-type, x-writing-protocol-version",
+            ),
+        )
+        .await;
+        assert!(response.status().is_success());
+        assert_eq!(
+            allowed_origin(&response).as_deref(),
+            Some("https://writing.example.com")
+        );
+        assert_eq!(
+            response
+                .headers()
+                .get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS)
+                .unwrap(),
+            "true"
+        );
+
+        // Other origins, methods and headers are refused.
+        let response = test::call_service(
+            &mut test_app,
+            preflight("https://evil.example.com", "POST", "content-type"),
+        )
+        .await;
+        assert_eq!(allowed_origin(&response), None);
+        let response = test::call_service(
+            &mut test_app,
+            preflight("https://writing.example.com", "DELETE", "content-type"),
+        )
+        .await;
+        assert_eq!(allowed_origin(&response), None);
+        let response = test::call_service(
+            &mut test_app,
+            preflight("https://writing.example.com", "POST", "x-other"),
+        )
+        .await;
+        assert_eq!(allowed_origin(&response), None);
+    }
+
+    #[test]
+    fn test_check_request_body_size() {
+        let limits = RequestBodyLimits {
+            default: 100,
+            document_text: 1000,
+        };
+        let check = |path: &str, headers: &[(header::HeaderName, &str)]| {
+            let mut request = TestRequest::post().uri(path);
+            for (name, value) in headers {
+                request = request.header(name.clone(), *value);
+            }
+            check_request_body_size(&request.to_srv_request(), &limits)
+                .map_err(|e| e.as_response_error().status_code().as_u16())
+        };
+        let get_document = "/api/documents.get_document";
+        let submit = "/api/documents.submit_document_change_set";
+        assert_eq!(check(get_document, &[]), Ok(()));
+        assert_eq!(
+            check(get_document, &[(header::CONTENT_LENGTH, "100")]),
+            Ok(())
+        );
+        assert_eq!(
+            check(get_document, &[(header::CONTENT_LENGTH, "101")]),
+            Err(413)
+        );
+        assert_eq!(check(submit, &[(header::CONTENT_LENGTH, "1000")]), Ok(()));
+        assert_eq!(check(submit, &[(header::CONTENT_LENGTH, "1001")]), Err(413));
+        assert_eq!(
+            check(get_document, &[(header::CONTENT_LENGTH, "many")]),
+            Err(400)
+        );
+        assert_eq!(
+            check(get_document, &[(header::TRANSFER_ENCODING, "chunked")]),
+            Err(411)
+        );
+        // Only API requests are limited here.
+        assert_eq!(check("/log_in", &[(header::CONTENT_LENGTH, "101")]), Ok(()));
+    }
+
+    #[test]
+    fn test_decode_protobuf_request() {
+        use ot::writing_proto::AppendToDocumentRequest;
+        use prost::Message;
+
+        let decode = |body: &[u8]| {
+            decode_protobuf_request::<AppendToDocumentRequest>(body)
+                .map_err(|e| e.as_response_error().status_code().as_u16())
+        };
+        // Field 15, which AppendToDocumentRequest does not have.
+        let unknown_field = |len: usize| {
+            let mut field = vec![15 << 3 | 2];
+            prost::encoding::encode_varint(len as u64, &mut field);
+            field.resize(field.len() + len, b'x');
+            field
+        };
+        let request = AppendToDocumentRequest {
+            doc_id: "d_1".to_string(),
+            text: "x".repeat(2 * MAX_UNKNOWN_FIELD_LEN),
+            ..Default::default()
+        };
+        let mut body = Vec::new();
+        request.encode(&mut body).unwrap();
+        assert_eq!(decode(&body), Ok(request.clone()));
+        assert_eq!(decode(&[]), Ok(AppendToDocumentRequest::default()));
+
+        // The length of the text runs past the end of the body.
+        assert_eq!(decode(&body[..body.len() - 1]), Err(400));
+
+        // Short unknown fields are skipped, but long ones are rejected.
+        let mut padded = body.clone();
+        padded.extend(unknown_field(MAX_UNKNOWN_FIELD_LEN));
+        assert_eq!(decode(&padded), Ok(request));
+        let mut padded = body;
+        padded.extend(unknown_field(MAX_UNKNOWN_FIELD_LEN + 1));
+        assert_eq!(decode(&padded), Err(400));
+    }
 }
@@ -1,17 +1,25 @@
+use std::collections::HashMap;
+
 use actix_session::Session;
+use actix_web::cookie::{Cookie, SameSite};
 use actix_web::dev::HttpResponseBuilder;
 use actix_web::http::{header, StatusCode};
-use actix_web::{error, get, post, web, HttpResponse};
+use actix_web::{error, get, post, web, HttpRequest, HttpResponse};
 use askama::Template;
 use rusoto_core::RusotoError;
 use rusoto_dynamodb::{
-    AttributeValue, DynamoDb, GetItemInput, PutItemError, PutItemInput, QueryInput, UpdateItemInput,
+    AttributeValue, DeleteItemInput, GetItemInput, Put, QueryInput, TransactWriteItem,
+    TransactWriteItemsError, TransactWriteItemsInput, UpdateItemInput,
 };
 use serde::{Deserialize, Serialize};
 
-use crate::dynamodb::{av_get_s, av_map, av_s, table_name};
-use crate::http;
+use crate::dynamodb::{av_get_n, av_get_s, av_map, av_s, table_name, DynamoDbClient};
+use crate::http::{self, SessionUser};
+use crate::identity_providers::{self, ExternalIdentity};
 use crate::ids::{Id, IdType};
+use crate::log_in_attempts;
+use crate::share_tokens;
+use crate::two_factor;
 use crate::users::UserRole;
 use crate::utils;
 use crate::utils::time;
@@ -20,11 +28,24 @@ use crate::BackendService;
 const INTERNAL_SERVER_ERROR_MESSAGE: &str = "Sorry, an error occurred. Please try again later.";
 const USER_ALREADY_EXISTS_MESSAGE: &str =
     "This user already exists. Please try logging in instead.";
+// Shown the same way whether or not the email has an account.
+const LOCKED_OUT_MESSAGE: &str = "Too many failed attempts to log in. Please try again later. If \
+    this email has an account, we sent it a link to unlock the account.";
+const INCORRECT_CODE_MESSAGE: &str = "That code is not right. Please try again.";
+const PENDING_LOG_IN_SESSION_KEY: &str = "pending_log_in";
+// Not in the session, since the session cookie is SameSite=Strict, and browsers leave it out of
+// the redirect back from an identity provider.
+const EXTERNAL_LOG_IN_STATE_COOKIE: &str = "external_log_in_state";
+// How long a user has to give a two-factor code after giving their password.
+const PENDING_LOG_IN_MINUTES: i64 = 10;
 
 #[derive(Deserialize, Serialize)]
 pub struct LoginForm {
     email: String,
     password: String,
+    // Unchecked checkboxes are left out of the form.
+    #[serde(default)]
+    keep_logged_in: bool,
 }
 
 #[derive(Template)]
@@ -32,9 +53,57 @@ pub struct LoginForm {
 struct LoginTemplate {
     email: String,
     password: String,
+    keep_logged_in: bool,
+    identity_providers: Vec<IdentityProviderLink>,
+    error_message: String,
+}
+
+/// A link on the log in page to log in with an identity provider.
+struct IdentityProviderLink {
+    name: &'static str,
+    display_name: &'static str,
+}
+
+#[derive(Template)]
+#[template(path = "redirect.html")]
+struct RedirectTemplate {
+    url: String,
+}
+
+#[derive(Deserialize)]
+pub struct ExternalLogInCallbackQuery {
+    #[serde(default)]
+    code: String,
+    #[serde(default)]
+    state: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct TwoFactorForm {
+    code: String,
+}
+
+#[derive(Default, Template)]
+#[template(path = "two_factor.html")]
+struct TwoFactorTemplate {
+    // Set while the user sets up two-factor authentication.
+    secret: String,
+    otpauth_uri: String,
+    // Set once the user has set it up.
+    recovery_codes: Vec<String>,
     error_message: String,
 }
 
+/// A user who gave the right password, but has yet to give a two-factor code.
+#[derive(Deserialize, Serialize)]
+struct PendingLogIn {
+    email: String,
+    user_id: String,
+    org_id: String,
+    keep_logged_in: bool,
+    expires_at: String,
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct SignUpForm {
     email: String,
@@ -42,6 +111,23 @@ pub struct SignUpForm {
     password_confirmation: String,
 }
 
+#[derive(Deserialize, Serialize)]
+pub struct JoinForm {
+    display_name: String,
+}
+
+#[derive(Template)]
+#[template(path = "join.html")]
+struct JoinTemplate {
+    share_token: String,
+    display_name: String,
+    error_message: String,
+}
+
+const GUEST_DISPLAY_NAME_MAX_CHARS: usize = 50;
+const INVALID_SHARE_LINK_MESSAGE: &str = "This link is invalid or has expired.";
+const TOO_MANY_JOINS_MESSAGE: &str = "Too many requests. Please try again in a minute.";
+
 #[derive(Template)]
 #[template(path = "sign_up.html")]
 struct SignUpTemplate {
@@ -53,11 +139,10 @@ struct SignUpTemplate {
 
 #[get("/log_in")]
 pub async fn get_log_in(
-    session: Session,
+    session_user: Option<SessionUser>,
     service: web::Data<BackendService>,
 ) -> actix_web::Result<HttpResponse> {
-    let user = http::get_session_user(&session, &service).await;
-    if user.is_ok() {
+    if session_user.is_some() {
         return Ok(HttpResponse::SeeOther()
             .header(header::LOCATION, "/app")
             .finish());
@@ -66,6 +151,8 @@ pub async fn get_log_in(
     let body = LoginTemplate {
         email: String::new(),
         password: String::new(),
+        keep_logged_in: false,
+        identity_providers: identity_provider_links(&service),
         error_message: String::new(),
     }
     .render()
@@ -76,12 +163,8 @@ pub async fn get_log_in(
 }
 
 #[get("/sign_up")]
-pub async fn get_sign_up(
-    session: Session,
-    service: web::Data<BackendService>,
-) -> actix_web::Result<HttpResponse> {
-    let user = http::get_session_user(&session, &service).await;
-    if user.is_ok() {
+pub async fn get_sign_up(session_user: Option<SessionUser>) -> actix_web::Result<HttpResponse> {
+    if session_user.is_some() {
         return Ok(HttpResponse::SeeOther()
             .header(header::LOCATION, "/app")
             .finish());
@@ -108,11 +191,14 @@ pub async fn submit_log_in(
     let error_response = |status_code: StatusCode| -> HttpResponse {
         let error_message = match status_code {
             StatusCode::NOT_FOUND => "User was not found, or password was incorrect.",
+            StatusCode::TOO_MANY_REQUESTS => LOCKED_OUT_MESSAGE,
             _ => INTERNAL_SERVER_ERROR_MESSAGE,
         };
         let body = LoginTemplate {
             email: form.email.clone(),
             password: form.password.clone(),
+            keep_logged_in: form.keep_logged_in,
+            identity_providers: identity_provider_links(&service),
             error_message: String::from(error_message),
         }
         .render()
@@ -131,7 +217,10 @@ pub async fn submit_log_in(
         .get_item(GetItemInput {
             table_name: table_name("users"),
             key: av_map(&[av_s("email", &form.email)]),
-            projection_expression: Some("id, hashed_password".to_string()),
+            projection_expression: Some(
+                "id, hashed_password, lockout_until, failed_log_in_attempts, totp_secret"
+                    .to_string(),
+            ),
             ..Default::default()
         })
         .await
@@ -141,12 +230,23 @@ pub async fn submit_log_in(
         })?;
 
     if output.item.is_none() {
+        // Emails without an account are locked out on the same attempt as accounts are.
+        if !service.log_in_rate_limiter.try_acquire(&form.email) {
+            return Ok(error_response(StatusCode::TOO_MANY_REQUESTS));
+        }
         return Ok(error_response(StatusCode::NOT_FOUND));
     }
     let item = output.item.unwrap();
     let user_id = av_get_s(&item, "id").ok_or_else(|| error::ErrorNotFound(""))?;
+    // Users who only log in with an identity provider have no password.
     let hashed_password =
-        av_get_s(&item, "hashed_password").ok_or_else(|| error::ErrorNotFound(""))?;
+        av_get_s(&item, "hashed_password").ok_or_else(|| error_response(StatusCode::NOT_FOUND))?;
+
+    // Don't check the password of a locked account at all, so that guessing gets nowhere.
+    let now = chrono::Utc::now();
+    if log_in_attempts::is_locked_out(&item, &now) {
+        return Ok(error_response(StatusCode::TOO_MANY_REQUESTS));
+    }
 
     // Check to see if password matches
     let password_matched = bcrypt::verify(&form.password, &hashed_password).map_err(|e| {
@@ -154,72 +254,231 @@ pub async fn submit_log_in(
         error_response(StatusCode::INTERNAL_SERVER_ERROR)
     })?;
     if !password_matched {
-        return Ok(error_response(StatusCode::NOT_FOUND));
-    }
-
-    // Find the most recent org login for this user. Use that org for login.
-    let output = service
-        .dynamodb_client
-        .query(QueryInput {
-            table_name: table_name("organization_users"),
-            // Scan the [user_id, last_login_at] index from most recent login to least. Take the
-            // first result we find.
-            index_name: Some("user_id-last_login_at-index".to_string()),
-            scan_index_forward: Some(false),
-            limit: Some(1),
-            key_condition_expression: Some("user_id = :user_id".to_string()),
-            expression_attribute_values: Some(av_map(&[av_s(":user_id", user_id)])),
-            projection_expression: Some("org_id".to_string()),
-            ..Default::default()
-        })
+        let locked = log_in_attempts::record_failed_log_in(
+            &service.dynamodb_client,
+            service.mailer.as_ref(),
+            &form.email,
+            user_id,
+            &now,
+        )
         .await
         .map_err(|e| {
             log::error!("{}", e);
             error_response(StatusCode::INTERNAL_SERVER_ERROR)
         })?;
-    if output.items.is_none() {
+        if locked {
+            return Ok(error_response(StatusCode::TOO_MANY_REQUESTS));
+        }
         return Ok(error_response(StatusCode::NOT_FOUND));
     }
-    let items = output.items.unwrap();
-    if items.len() != 1 {
-        return Ok(error_response(StatusCode::NOT_FOUND));
+    if av_get_n::<i64>(&item, "failed_log_in_attempts").unwrap_or(0) > 0 {
+        log_in_attempts::reset_failed_log_ins(&service.dynamodb_client, &form.email)
+            .await
+            .map_err(|e| {
+                log::error!("{}", e);
+                error_response(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
     }
-    let item = &items[0];
-    let org_id = av_get_s(&item, "org_id").ok_or_else(|| error_response(StatusCode::NOT_FOUND))?;
 
-    // Update last_login_at value to be "now"
-    let now = chrono::Utc::now();
-    service
-        .dynamodb_client
-        .update_item(UpdateItemInput {
-            table_name: table_name("organization_users"),
-            key: av_map(&[av_s("org_id", org_id), av_s("user_id", user_id)]),
-            update_expression: Some("SET last_login_at = :now, updated_at = :now".to_string()),
-            expression_attribute_values: Some(av_map(&[av_s(
-                ":now",
-                &utils::time::date_time_iso_str(&now),
-            )])),
-            ..Default::default()
-        })
+    let org_id = find_latest_org_id(&service.dynamodb_client, user_id)
         .await
         .map_err(|e| {
             log::error!("{}", e);
             error_response(StatusCode::INTERNAL_SERVER_ERROR)
-        })?;
-
-    // Store org_id and user_id in session cookie. A user who belongs to multiple orgs may switch
-    // the org, which will update org_id in their session.
-    session
-        .set("org_id", org_id)
-        .map_err(|_| error_response(StatusCode::INTERNAL_SERVER_ERROR))?;
-    session
-        .set("user_id", user_id)
-        .map_err(|_| error_response(StatusCode::INTERNAL_SERVER_ERROR))?;
+        })?
+        .ok_or_else(|| error_response(StatusCode::NOT_FOUND))?;
+    let destination = log_in(
+        &session,
+        &service.dynamodb_client,
+        &form.email,
+        &item,
+        &org_id,
+        form.keep_logged_in,
+        &now,
+    )
+    .await
+    .map_err(|e| {
+        log::error!("{}", e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR)
+    })?;
 
     // We use "303 See Other" redirect so that refreshing the destination page does not re-submit
     // the form via POST.
     //
     // See: https://developer.mozilla.org/en-US/docs/Web/HTTP/Redirections#temporary_redirections
+    Ok(HttpResponse::SeeOther()
+        .set_header(header::LOCATION, destination)
+        .finish())
+}
+
+/// Asks for a code from the authenticator app of a user who logged in with their password. If the
+/// user's org requires two-factor authentication and the user has not set it up, this is where
+/// they set it up.
+#[get("/log_in/two_factor")]
+pub async fn get_two_factor(
+    session: Session,
+    service: web::Data<BackendService>,
+) -> actix_web::Result<HttpResponse> {
+    let now = chrono::Utc::now();
+    let pending_log_in = match get_pending_log_in(&session, &now) {
+        Some(pending_log_in) => pending_log_in,
+        None => {
+            return Ok(HttpResponse::SeeOther()
+                .header(header::LOCATION, "/log_in")
+                .finish())
+        }
+    };
+    let user_item = get_user_item(&service.dynamodb_client, &pending_log_in.email)
+        .await
+        .map_err(|e| {
+            log::error!("{}", e);
+            error::ErrorInternalServerError("")
+        })?;
+    let mut template = TwoFactorTemplate::default();
+    if !two_factor::is_enabled(&user_item) {
+        // Keep showing the same secret if the page is loaded again, in case the user already
+        // added it to their app.
+        let secret = match av_get_s(&user_item, "pending_totp_secret") {
+            Some(secret) => secret.to_string(),
+            None => {
+                two_factor::provision_secret(&service.dynamodb_client, &pending_log_in.email)
+                    .await
+                    .map_err(|e| {
+                        log::error!("{}", e);
+                        error::ErrorInternalServerError("")
+                    })?
+                    // It was turned on since the user item was read.
+                    .ok_or_else(|| error::ErrorConflict(""))?
+                    .secret
+            }
+        };
+        template.otpauth_uri = two_factor::otpauth_uri(&pending_log_in.email, &secret);
+        template.secret = secret;
+    }
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(template.render().unwrap()))
+}
+
+/// Logs in a user who gave their password, if the code is right. A user who is setting up
+/// two-factor authentication is shown their recovery codes. Wrong codes count toward locking the
+/// account, the same as wrong passwords.
+#[post("/log_in/two_factor")]
+pub async fn submit_two_factor(
+    session: Session,
+    service: web::Data<BackendService>,
+    form: web::Form<TwoFactorForm>,
+) -> actix_web::Result<HttpResponse> {
+    let render = |status_code: StatusCode, template: TwoFactorTemplate| -> HttpResponse {
+        HttpResponseBuilder::new(status_code)
+            .content_type("text/html; charset=utf-8")
+            .body(template.render().unwrap())
+    };
+    let now = chrono::Utc::now();
+    let pending_log_in = match get_pending_log_in(&session, &now) {
+        Some(pending_log_in) => pending_log_in,
+        None => {
+            return Ok(HttpResponse::SeeOther()
+                .header(header::LOCATION, "/log_in")
+                .finish())
+        }
+    };
+    let user_item = get_user_item(&service.dynamodb_client, &pending_log_in.email)
+        .await
+        .map_err(|e| {
+            log::error!("{}", e);
+            error::ErrorInternalServerError("")
+        })?;
+    if log_in_attempts::is_locked_out(&user_item, &now) {
+        session.remove(PENDING_LOG_IN_SESSION_KEY);
+        return Ok(render(
+            StatusCode::TOO_MANY_REQUESTS,
+            TwoFactorTemplate {
+                error_message: String::from(LOCKED_OUT_MESSAGE),
+                ..Default::default()
+            },
+        ));
+    }
+
+    if !two_factor::is_enabled(&user_item) {
+        let recovery_codes =
+            two_factor::enable(&service.dynamodb_client, &user_item, &form.code, &now)
+                .await
+                .map_err(|e| {
+                    log::error!("{}", e);
+                    error::ErrorInternalServerError("")
+                })?;
+        let recovery_codes = match recovery_codes {
+            Some(recovery_codes) => recovery_codes,
+            None => {
+                let secret = av_get_s(&user_item, "pending_totp_secret").unwrap_or_default();
+                return Ok(render(
+                    StatusCode::BAD_REQUEST,
+                    TwoFactorTemplate {
+                        secret: secret.to_string(),
+                        otpauth_uri: two_factor::otpauth_uri(&pending_log_in.email, secret),
+                        error_message: String::from(INCORRECT_CODE_MESSAGE),
+                        ..Default::default()
+                    },
+                ));
+            }
+        };
+        finish_pending_log_in(&session, &service.dynamodb_client, &pending_log_in, &now).await?;
+        return Ok(render(
+            StatusCode::OK,
+            TwoFactorTemplate {
+                recovery_codes,
+                ..Default::default()
+            },
+        ));
+    }
+
+    let accepted = two_factor::check_code(&service.dynamodb_client, &user_item, &form.code, &now)
+        .await
+        .map_err(|e| {
+            log::error!("{}", e);
+            error::ErrorInternalServerError("")
+        })?;
+    if !accepted {
+        let locked = log_in_attempts::record_failed_log_in(
+            &service.dynamodb_client,
+            service.mailer.as_ref(),
+            &pending_log_in.email,
+            &pending_log_in.user_id,
+            &now,
+        )
+        .await
+        .map_err(|e| {
+            log::error!("{}", e);
+            error::ErrorInternalServerError("")
+        })?;
+        if locked {
+            session.remove(PENDING_LOG_IN_SESSION_KEY);
+            return Ok(render(
+                StatusCode::TOO_MANY_REQUESTS,
+                TwoFactorTemplate {
+                    error_message: String::from(LOCKED_OUT_MESSAGE),
+                    ..Default::default()
+                },
+            ));
+        }
+        return Ok(render(
+            StatusCode::BAD_REQUEST,
+            TwoFactorTemplate {
+                error_message: String::from(INCORRECT_CODE_MESSAGE),
+                ..Default::default()
+            },
+        ));
+    }
+    if av_get_n::<i64>(&user_item, "failed_log_in_attempts").unwrap_or(0) > 0 {
+        log_in_attempts::reset_failed_log_ins(&service.dynamodb_client, &pending_log_in.email)
+            .await
+            .map_err(|e| {
+                log::error!("{}", e);
+                error::ErrorInternalServerError("")
+            })?;
+    }
+    finish_pending_log_in(&session, &service.dynamodb_client, &pending_log_in, &now).await?;
     Ok(HttpResponse::SeeOther()
         .set_header(header::LOCATION, "/app")
         .finish())
@@ -278,10 +537,6 @@ pub async fn submit_sign_up(
         ));
     }
 
-    // Create user.
-    //
-    // TODO(cliff): Introduce a new flow to set fields of user profile.
-    let user_id = Id::new(IdType::User);
     let hashed_password = bcrypt::hash(&form.password, bcrypt::DEFAULT_COST).map_err(|e| {
         log::error!("{}", e);
         error_response(
@@ -289,96 +544,176 @@ pub async fn submit_sign_up(
             INTERNAL_SERVER_ERROR_MESSAGE,
         )
     })?;
-    let now = time::date_time_iso_str(&chrono::Utc::now());
-    service
-        .dynamodb_client
-        .put_item(PutItemInput {
-            table_name: table_name("users"),
-            // Preventing data race: Only overwrite if the user does not already exist.
-            condition_expression: Some(
-                "attribute_not_exists(id) and attribute_not_exists(email)".to_string(),
-            ),
-            item: maplit::hashmap! {
-                "id".to_string() => AttributeValue {
-                    s: Some(user_id.as_str().to_string()),
-                    ..AttributeValue::default()
-                },
-                "email".to_string() => AttributeValue {
-                    s: Some(form.email.clone()),
-                    ..AttributeValue::default()
-                },
-                "name".to_string() => AttributeValue {
-                    s: Some(form.email.clone()),
-                    ..AttributeValue::default()
-                },
-                "hashed_password".to_string() => AttributeValue {
-                    s: Some(hashed_password),
-                    ..AttributeValue::default()
-                },
-                "photo_url".to_string() => AttributeValue {
-                    null: Some(true),
-                    ..AttributeValue::default()
-                },
-                "created_at".to_string() => AttributeValue {
-                    s: Some(now.clone()),
-                    ..AttributeValue::default()
-                },
-                "updated_at".to_string() => AttributeValue {
-                    s: Some(now.clone()),
-                    ..AttributeValue::default()
-                },
-            },
-            ..PutItemInput::default()
-        })
+    let (user_id, org_id) = match create_user_and_org(
+        &service.dynamodb_client,
+        &form.email,
+        &form.email,
+        Some(hashed_password),
+    )
+    .await
+    {
+        Ok(ids) => ids,
+        Err(CreateUserError::AlreadyExists) => {
+            return Ok(error_response(
+                StatusCode::BAD_REQUEST,
+                USER_ALREADY_EXISTS_MESSAGE,
+            ));
+        }
+        Err(CreateUserError::Other(e)) => {
+            log::error!("{}", e);
+            return Ok(error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                INTERNAL_SERVER_ERROR_MESSAGE,
+            ));
+        }
+    };
+
+    session.set("org_id", org_id.as_str()).map_err(|_| {
+        error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            INTERNAL_SERVER_ERROR_MESSAGE,
+        )
+    })?;
+    session.set("user_id", user_id.as_str()).map_err(|_| {
+        error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            INTERNAL_SERVER_ERROR_MESSAGE,
+        )
+    })?;
+
+    // We use "303 See Other" redirect so that refreshing the destination page does not re-submit
+    // the form via POST.
+    //
+    // See: https://developer.mozilla.org/en-US/docs/Web/HTTP/Redirections#temporary_redirections
+    Ok(HttpResponse::SeeOther()
+        .set_header(header::LOCATION, "/app")
+        .finish())
+}
+
+/// Sends the user to log in with an identity provider, like Google. The provider sends them back
+/// to `finish_external_log_in`.
+#[get("/auth/{provider}")]
+pub async fn start_external_log_in(
+    req: HttpRequest,
+    path: web::Path<String>,
+    service: web::Data<BackendService>,
+) -> actix_web::Result<HttpResponse> {
+    let provider = service
+        .identity_providers
+        .get(&path)
+        .ok_or_else(|| error::ErrorNotFound(""))?;
+    // Ties the user who comes back from the provider to this browser, so that nobody can trick a
+    // user into logging in to the trickster's account.
+    let state = uuid::Uuid::new_v4().to_simple().to_string();
+    let state_cookie = Cookie::build(EXTERNAL_LOG_IN_STATE_COOKIE, state.clone())
+        .path("/auth")
+        .secure(req.connection_info().scheme() == "https")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .finish();
+    let authorization_url =
+        provider.authorization_url(&identity_providers::callback_url(provider.name()), &state);
+    Ok(HttpResponse::SeeOther()
+        .cookie(state_cookie)
+        .set_header(header::LOCATION, authorization_url)
+        .finish())
+}
+
+/// Where identity providers send users back to. Logs the user in with the account that they
+/// logged in to the provider with, signing them up if they have no account yet.
+#[get("/auth/{provider}/callback")]
+pub async fn finish_external_log_in(
+    req: HttpRequest,
+    session: Session,
+    path: web::Path<String>,
+    query: web::Query<ExternalLogInCallbackQuery>,
+    service: web::Data<BackendService>,
+) -> actix_web::Result<HttpResponse> {
+    let provider = service
+        .identity_providers
+        .get(&path)
+        .ok_or_else(|| error::ErrorNotFound(""))?
+        .clone();
+    let state_cookie = Cookie::build(EXTERNAL_LOG_IN_STATE_COOKIE, "")
+        .path("/auth")
+        .finish();
+    let error_response = |status_code: StatusCode, error_message: &str| -> HttpResponse {
+        let body = LoginTemplate {
+            email: String::new(),
+            password: String::new(),
+            keep_logged_in: false,
+            identity_providers: identity_provider_links(&service),
+            error_message: String::from(error_message),
+        }
+        .render()
+        .unwrap();
+        HttpResponseBuilder::new(status_code)
+            .del_cookie(&state_cookie)
+            .content_type("text/html; charset=utf-8")
+            .body(body)
+    };
+    let failed_message = format!(
+        "Could not log in with {}. Please try again.",
+        provider.display_name()
+    );
+
+    let expected_state = req.cookie(EXTERNAL_LOG_IN_STATE_COOKIE);
+    if query.state.is_empty()
+        || expected_state.map(|cookie| cookie.value().to_string()) != Some(query.state.clone())
+    {
+        return Ok(error_response(StatusCode::BAD_REQUEST, &failed_message));
+    }
+    // The user chose not to log in with the provider.
+    if query.code.is_empty() {
+        return Ok(HttpResponse::SeeOther()
+            .del_cookie(&state_cookie)
+            .set_header(header::LOCATION, "/log_in")
+            .finish());
+    }
+
+    let identity = provider
+        .fetch_identity(
+            &identity_providers::callback_url(provider.name()),
+            &query.code,
+        )
         .await
         .map_err(|e| {
             log::error!("{}", e);
-            match e {
-                RusotoError::Service(PutItemError::ConditionalCheckFailed(_)) => {
-                    error_response(StatusCode::BAD_REQUEST, USER_ALREADY_EXISTS_MESSAGE)
-                }
-                _ => error_response(
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, &failed_message)
+        })?;
+    let user_item =
+        find_or_create_external_user(&service.dynamodb_client, provider.name(), &identity)
+            .await
+            .map_err(|e| {
+                log::error!("{}", e);
+                error_response(
                     StatusCode::INTERNAL_SERVER_ERROR,
                     INTERNAL_SERVER_ERROR_MESSAGE,
+                )
+            })?;
+    let user_item = match user_item {
+        Some(user_item) => user_item,
+        None => {
+            return Ok(error_response(
+                StatusCode::FORBIDDEN,
+                &format!(
+                    "Your {} account's email address is not verified.",
+                    provider.display_name()
                 ),
-            }
-        })?;
-
-    // Create organization, and add user to the organization.
-    //
-    // TODO(cliff): Introduce a new flow to create organizations. For now, just create an arbitrary
-    // organization with a single user whenever a user signs up.
-    let org_id = Id::new(IdType::Organization);
-    let org_name = format!("Organization created by {}", &form.email);
-    service
-        .dynamodb_client
-        .put_item(PutItemInput {
-            table_name: table_name("organizations"),
-            condition_expression: Some("attribute_not_exists(id)".to_string()),
-            item: maplit::hashmap! {
-                "id".to_string() => AttributeValue {
-                    s: Some(org_id.as_str().to_string()),
-                    ..AttributeValue::default()
-                },
-                "name".to_string() => AttributeValue {
-                    s: Some(org_name.clone()),
-                    ..AttributeValue::default()
-                },
-                "logo_url".to_string() => AttributeValue {
-                    null: Some(true),
-                    ..AttributeValue::default()
-                },
-                "created_at".to_string() => AttributeValue {
-                    s: Some(now.clone()),
-                    ..AttributeValue::default()
-                },
-                "updated_at".to_string() => AttributeValue {
-                    s: Some(now.clone()),
-                    ..AttributeValue::default()
-                },
-            },
-            ..PutItemInput::default()
-        })
+            ));
+        }
+    };
+    let (user_id, email) = match (av_get_s(&user_item, "id"), av_get_s(&user_item, "email")) {
+        (Some(user_id), Some(email)) => (user_id, email),
+        _ => {
+            log::error!("user is missing a field");
+            return Ok(error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                INTERNAL_SERVER_ERROR_MESSAGE,
+            ));
+        }
+    };
+    let org_id = find_latest_org_id(&service.dynamodb_client, user_id)
         .await
         .map_err(|e| {
             log::error!("{}", e);
@@ -386,76 +721,637 @@ pub async fn submit_sign_up(
                 StatusCode::INTERNAL_SERVER_ERROR,
                 INTERNAL_SERVER_ERROR_MESSAGE,
             )
-        })?;
+        })?
+        .ok_or_else(|| error_response(StatusCode::NOT_FOUND, "User was not found."))?;
+    // Logging in with the provider again takes a click, so the session ends with the browser
+    // session.
+    let destination = log_in(
+        &session,
+        &service.dynamodb_client,
+        email,
+        &user_item,
+        &org_id,
+        false,
+        &chrono::Utc::now(),
+    )
+    .await
+    .map_err(|e| {
+        log::error!("{}", e);
+        error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            INTERNAL_SERVER_ERROR_MESSAGE,
+        )
+    })?;
+
+    // The browser leaves the new session cookie out of redirects that started at the provider's
+    // site, so move on from a page of our own instead.
+    let body = RedirectTemplate {
+        url: destination.to_string(),
+    }
+    .render()
+    .unwrap();
+    Ok(HttpResponse::Ok()
+        .del_cookie(&state_cookie)
+        .content_type("text/html; charset=utf-8")
+        .body(body))
+}
+
+/// Link from the email sent when an account is locked. Unlocks the account, and sends the user to
+/// log in.
+#[get("/unlock_account/{user_id}/{unlock_token}")]
+pub async fn unlock_account(
+    path: web::Path<(String, String)>,
+    service: web::Data<BackendService>,
+) -> actix_web::Result<HttpResponse> {
+    let (user_id, unlock_token) = path.into_inner();
+    let unlocked =
+        log_in_attempts::unlock_account(&service.dynamodb_client, &user_id, &unlock_token)
+            .await
+            .map_err(|e| {
+                log::error!("{}", e);
+                error::ErrorInternalServerError("")
+            })?;
+    if !unlocked {
+        return Err(error::ErrorNotFound(""));
+    }
+    Ok(HttpResponse::SeeOther()
+        .set_header(header::LOCATION, "/log_in")
+        .finish())
+}
+
+#[post("/log_out")]
+pub async fn submit_log_out(session: Session) -> actix_web::Result<HttpResponse> {
+    session.purge();
+    Ok(HttpResponse::SeeOther()
+        .set_header(header::LOCATION, "/log_in")
+        .finish())
+}
+
+/// Landing page for a share link. Logged-in users go straight to the document. Everyone else is
+/// asked for a display name so that they can join as a guest.
+#[get("/join/{share_token}")]
+pub async fn get_join(
+    share_token: web::Path<String>,
+    session_user: Option<SessionUser>,
+    service: web::Data<BackendService>,
+) -> actix_web::Result<HttpResponse> {
+    let share_token = share_token.into_inner();
+    let render = |status_code: StatusCode, error_message: &str| -> HttpResponse {
+        let body = JoinTemplate {
+            share_token: share_token.clone(),
+            display_name: String::new(),
+            error_message: error_message.to_string(),
+        }
+        .render()
+        .unwrap();
+        HttpResponseBuilder::new(status_code)
+            .content_type("text/html; charset=utf-8")
+            .body(body)
+    };
+    let token = match share_tokens::get_share_token(&service.dynamodb_client, &share_token).await {
+        Ok(token) => token,
+        Err(_) => return Ok(render(StatusCode::NOT_FOUND, INVALID_SHARE_LINK_MESSAGE)),
+    };
+    if session_user.is_some() {
+        return Ok(HttpResponse::SeeOther()
+            .set_header(
+                header::LOCATION,
+                shared_document_url(&token.doc_id, &share_token),
+            )
+            .finish());
+    }
+    Ok(render(StatusCode::OK, ""))
+}
+
+/// Starts a guest session for a share link. The guest's id and display name are stored in the
+/// session cookie in place of a user id and org id.
+#[post("/join/{share_token}")]
+pub async fn submit_join(
+    req: HttpRequest,
+    share_token: web::Path<String>,
+    session: Session,
+    service: web::Data<BackendService>,
+    form: web::Form<JoinForm>,
+) -> actix_web::Result<HttpResponse> {
+    let share_token = share_token.into_inner();
+    let display_name = form.display_name.trim();
+    let error_response = |status_code: StatusCode, error_message: &str| -> HttpResponse {
+        let body = JoinTemplate {
+            share_token: share_token.clone(),
+            display_name: form.display_name.clone(),
+            error_message: error_message.to_string(),
+        }
+        .render()
+        .unwrap();
+        HttpResponseBuilder::new(status_code)
+            .content_type("text/html; charset=utf-8")
+            .body(body)
+    };
+    // Joining hands out a new guest id, so it counts against the same budget as the requests that
+    // guests make afterwards.
+    if !http::try_acquire_guest_permit(&req, &service) {
+        return Ok(error_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            TOO_MANY_JOINS_MESSAGE,
+        ));
+    }
+    if display_name.is_empty() || display_name.chars().count() > GUEST_DISPLAY_NAME_MAX_CHARS {
+        return Ok(error_response(
+            StatusCode::BAD_REQUEST,
+            &format!(
+                "Name must contain between 1 and {} characters.",
+                GUEST_DISPLAY_NAME_MAX_CHARS
+            ),
+        ));
+    }
+    let token = match share_tokens::get_share_token(&service.dynamodb_client, &share_token).await {
+        Ok(token) => token,
+        Err(e) if e.as_response_error().status_code() == StatusCode::FORBIDDEN => {
+            return Ok(error_response(
+                StatusCode::NOT_FOUND,
+                INVALID_SHARE_LINK_MESSAGE,
+            ));
+        }
+        Err(_) => {
+            return Ok(error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                INTERNAL_SERVER_ERROR_MESSAGE,
+            ));
+        }
+    };
+
+    // Keep an existing guest id so that a guest who changes their name is still the same author.
+    let guest_id = match http::get_guest_user(&session) {
+        Ok(guest_user) => guest_user.guest_id,
+        Err(_) => Id::new(IdType::Guest),
+    };
+    session.set("guest_id", guest_id.as_str()).map_err(|_| {
+        error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            INTERNAL_SERVER_ERROR_MESSAGE,
+        )
+    })?;
+    session.set("guest_name", display_name).map_err(|_| {
+        error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            INTERNAL_SERVER_ERROR_MESSAGE,
+        )
+    })?;
+
+    Ok(HttpResponse::SeeOther()
+        .set_header(
+            header::LOCATION,
+            shared_document_url(&token.doc_id, &share_token),
+        )
+        .finish())
+}
+
+/// Why `create_user_and_org` did not create the user.
+enum CreateUserError {
+    AlreadyExists,
+    Other(String),
+}
+
+/// Creates a user, and a new org with the user as its admin. Users without a password can only
+/// log in with an identity provider.
+async fn create_user_and_org(
+    dynamodb_client: &DynamoDbClient,
+    email: &str,
+    name: &str,
+    hashed_password: Option<String>,
+) -> Result<(Id, Id), CreateUserError> {
+    // Create user.
+    //
+    // TODO(cliff): Introduce a new flow to set fields of user profile.
+    let user_id = Id::new(IdType::User);
+    let now = time::date_time_iso_str(&chrono::Utc::now());
+    let mut user_item = maplit::hashmap! {
+        "id".to_string() => AttributeValue {
+            s: Some(user_id.as_str().to_string()),
+            ..AttributeValue::default()
+        },
+        "email".to_string() => AttributeValue {
+            s: Some(email.to_string()),
+            ..AttributeValue::default()
+        },
+        "name".to_string() => AttributeValue {
+            s: Some(name.to_string()),
+            ..AttributeValue::default()
+        },
+        "photo_url".to_string() => AttributeValue {
+            null: Some(true),
+            ..AttributeValue::default()
+        },
+        "created_at".to_string() => AttributeValue {
+            s: Some(now.clone()),
+            ..AttributeValue::default()
+        },
+        "updated_at".to_string() => AttributeValue {
+            s: Some(now.clone()),
+            ..AttributeValue::default()
+        },
+    };
+
+    if let Some(hashed_password) = hashed_password {
+        user_item.insert(
+            "hashed_password".to_string(),
+            AttributeValue {
+                s: Some(hashed_password),
+                ..AttributeValue::default()
+            },
+        );
+    }
+
+    // Create organization, and add user to the organization.
+    //
+    // TODO(cliff): Introduce a new flow to create organizations. For now, just create an arbitrary
+    // organization with a single user whenever a user signs up.
+    let org_id = Id::new(IdType::Organization);
+    let org_name = format!("Organization created by {}", email);
+    let org_item = maplit::hashmap! {
+        "id".to_string() => AttributeValue {
+            s: Some(org_id.as_str().to_string()),
+            ..AttributeValue::default()
+        },
+        "name".to_string() => AttributeValue {
+            s: Some(org_name.clone()),
+            ..AttributeValue::default()
+        },
+        "logo_url".to_string() => AttributeValue {
+            null: Some(true),
+            ..AttributeValue::default()
+        },
+        "created_at".to_string() => AttributeValue {
+            s: Some(now.clone()),
+            ..AttributeValue::default()
+        },
+        "updated_at".to_string() => AttributeValue {
+            s: Some(now.clone()),
+            ..AttributeValue::default()
+        },
+    };
+    let organization_user_item = maplit::hashmap! {
+        "org_id".to_string() => AttributeValue {
+            s: Some(org_id.as_str().to_string()),
+            ..AttributeValue::default()
+        },
+        "user_id".to_string() => AttributeValue {
+            s: Some(user_id.as_str().to_string()),
+            ..AttributeValue::default()
+        },
+        "last_login_at".to_string() => AttributeValue {
+            s: Some(now.clone()),
+            ..AttributeValue::default()
+        },
+        "user_role".to_string() => AttributeValue {
+            n: Some((UserRole::OrgAdmin as i32).to_string()),
+            ..AttributeValue::default()
+        },
+        "created_at".to_string() => AttributeValue {
+            s: Some(now.clone()),
+            ..AttributeValue::default()
+        },
+        "updated_at".to_string() => AttributeValue {
+            s: Some(now.clone()),
+            ..AttributeValue::default()
+        },
+    };
+
+    // Write the user, the organization, and the membership together, so that a failure part way
+    // cannot leave a user who belongs to no organization.
+    let put = |table: &str, condition_expression: &str, item| TransactWriteItem {
+        put: Some(Put {
+            table_name: table_name(table),
+            // Preventing data race: Only write items that do not already exist.
+            condition_expression: Some(condition_expression.to_string()),
+            item,
+            ..Put::default()
+        }),
+        ..TransactWriteItem::default()
+    };
+    let input = TransactWriteItemsInput {
+        transact_items: vec![
+            put(
+                "users",
+                "attribute_not_exists(id) and attribute_not_exists(email)",
+                user_item,
+            ),
+            put("organizations", "attribute_not_exists(id)", org_item),
+            put(
+                "organization_users",
+                "attribute_not_exists(user_id)",
+                organization_user_item,
+            ),
+        ],
+        ..TransactWriteItemsInput::default()
+    };
+    match dynamodb_client.transact_write_items(input).await {
+        Ok(_) => Ok((user_id, org_id)),
+        Err(RusotoError::Service(TransactWriteItemsError::TransactionCanceled(message))) => {
+            // Nothing was written.
+            if first_cancellation_reason(&message) == Some("ConditionalCheckFailed") {
+                return Err(CreateUserError::AlreadyExists);
+            }
+            Err(CreateUserError::Other(message))
+        }
+        Err(e) => {
+            // The transaction may or may not have been written, e.g. if the request timed out.
+            // Undo it in case it was, so that the email address is free to sign up with again.
+            undo_sign_up(dynamodb_client, email, &user_id, &org_id).await;
+            Err(CreateUserError::Other(e.to_string()))
+        }
+    }
+}
+
+fn identity_provider_links(service: &BackendService) -> Vec<IdentityProviderLink> {
     service
-        .dynamodb_client
-        .put_item(PutItemInput {
+        .identity_providers
+        .all()
+        .into_iter()
+        .map(|provider| IdentityProviderLink {
+            name: provider.name(),
+            display_name: provider.display_name(),
+        })
+        .collect()
+}
+
+/// Finds the org that the user last logged in to. Returns None if the user belongs to no org.
+async fn find_latest_org_id(
+    dynamodb_client: &DynamoDbClient,
+    user_id: &str,
+) -> anyhow::Result<Option<String>> {
+    let output = dynamodb_client
+        .query(QueryInput {
             table_name: table_name("organization_users"),
-            item: maplit::hashmap! {
-                "org_id".to_string() => AttributeValue {
-                    s: Some(org_id.as_str().to_string()),
-                    ..AttributeValue::default()
-                },
-                "user_id".to_string() => AttributeValue {
-                    s: Some(user_id.as_str().to_string()),
-                    ..AttributeValue::default()
-                },
-                "last_login_at".to_string() => AttributeValue {
-                    s: Some(now.clone()),
-                    ..AttributeValue::default()
-                },
-                "user_role".to_string() => AttributeValue {
-                    n: Some((UserRole::OrgAdmin as i32).to_string()),
-                    ..AttributeValue::default()
-                },
-                "created_at".to_string() => AttributeValue {
-                    s: Some(now.clone()),
-                    ..AttributeValue::default()
-                },
-                "updated_at".to_string() => AttributeValue {
-                    s: Some(now.clone()),
-                    ..AttributeValue::default()
-                },
-            },
-            ..PutItemInput::default()
+            // Scan the [user_id, last_login_at] index from most recent login to least. Take the
+            // first result we find.
+            index_name: Some("user_id-last_login_at-index".to_string()),
+            scan_index_forward: Some(false),
+            limit: Some(1),
+            key_condition_expression: Some("user_id = :user_id".to_string()),
+            expression_attribute_values: Some(av_map(&[av_s(":user_id", user_id)])),
+            projection_expression: Some("org_id".to_string()),
+            ..Default::default()
+        })
+        .await?;
+    Ok(output
+        .items
+        .unwrap_or_default()
+        .first()
+        .and_then(|item| av_get_s(item, "org_id"))
+        .map(String::from))
+}
+
+/// Logs in a user who proved who they are, e.g. with their password, to the org. If the user must
+/// also give a two-factor code, only starts the log in. Returns where to send the user next. The
+/// user item must include `totp_secret`, if the user has one.
+async fn log_in(
+    session: &Session,
+    dynamodb_client: &DynamoDbClient,
+    email: &str,
+    user_item: &HashMap<String, AttributeValue>,
+    org_id: &str,
+    keep_logged_in: bool,
+    now: &chrono::DateTime<chrono::Utc>,
+) -> Result<&'static str, String> {
+    let user_id = av_get_s(user_item, "id").ok_or("user is missing an id")?;
+    // Users with two-factor authentication, and users whose org requires it, must give a code
+    // before they are logged in. Until then, the session only remembers who they are.
+    let two_factor_needed = two_factor::is_enabled(user_item) || {
+        let org_id = Id::parse(org_id).ok_or_else(|| format!("invalid org id: {}", org_id))?;
+        two_factor::is_two_factor_required(dynamodb_client, &org_id)
+            .await
+            .map_err(|e| e.to_string())?
+    };
+    if two_factor_needed {
+        let pending_log_in = PendingLogIn {
+            email: email.to_string(),
+            user_id: user_id.to_string(),
+            org_id: org_id.to_string(),
+            keep_logged_in,
+            expires_at: time::date_time_iso_str(
+                &(*now + chrono::Duration::minutes(PENDING_LOG_IN_MINUTES)),
+            ),
+        };
+        session
+            .set(PENDING_LOG_IN_SESSION_KEY, pending_log_in)
+            .map_err(|e| e.to_string())?;
+        return Ok("/log_in/two_factor");
+    }
+
+    start_session(
+        session,
+        dynamodb_client,
+        user_id,
+        org_id,
+        keep_logged_in,
+        now,
+    )
+    .await?;
+    Ok("/app")
+}
+
+/// Logs the user in to the org: records the log in, and stores the user and org in the session.
+async fn start_session(
+    session: &Session,
+    dynamodb_client: &DynamoDbClient,
+    user_id: &str,
+    org_id: &str,
+    keep_logged_in: bool,
+    now: &chrono::DateTime<chrono::Utc>,
+) -> Result<(), String> {
+    // Update last_login_at value to be "now"
+    dynamodb_client
+        .update_item(UpdateItemInput {
+            table_name: table_name("organization_users"),
+            key: av_map(&[av_s("org_id", org_id), av_s("user_id", user_id)]),
+            update_expression: Some("SET last_login_at = :now, updated_at = :now".to_string()),
+            expression_attribute_values: Some(av_map(&[av_s(
+                ":now",
+                &utils::time::date_time_iso_str(now),
+            )])),
+            ..Default::default()
         })
         .await
-        .map_err(|e| {
-            log::error!("{}", e);
-            error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                INTERNAL_SERVER_ERROR_MESSAGE,
-            )
-        })?;
+        .map_err(|e| e.to_string())?;
 
-    session.set("org_id", org_id.as_str()).map_err(|_| {
-        error_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            INTERNAL_SERVER_ERROR_MESSAGE,
-        )
-    })?;
-    session.set("user_id", user_id.as_str()).map_err(|_| {
-        error_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            INTERNAL_SERVER_ERROR_MESSAGE,
-        )
-    })?;
+    // Store org_id and user_id in session cookie. A user who belongs to multiple orgs may switch
+    // the org, which will update org_id in their session.
+    session.set("org_id", org_id).map_err(|e| e.to_string())?;
+    session.set("user_id", user_id).map_err(|e| e.to_string())?;
+    // Without "keep me logged in", the session cookie ends with the browser session.
+    session
+        .set(http::KEEP_LOGGED_IN_SESSION_KEY, keep_logged_in)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
 
-    // We use "303 See Other" redirect so that refreshing the destination page does not re-submit
-    // the form via POST.
-    //
-    // See: https://developer.mozilla.org/en-US/docs/Web/HTTP/Redirections#temporary_redirections
-    Ok(HttpResponse::SeeOther()
-        .set_header(header::LOCATION, "/app")
-        .finish())
+/// Returns the log in waiting for a two-factor code, unless it has expired.
+fn get_pending_log_in(
+    session: &Session,
+    now: &chrono::DateTime<chrono::Utc>,
+) -> Option<PendingLogIn> {
+    let pending_log_in: PendingLogIn = session.get(PENDING_LOG_IN_SESSION_KEY).ok()??;
+    if pending_log_in.expires_at <= time::date_time_iso_str(now) {
+        return None;
+    }
+    Some(pending_log_in)
 }
 
-#[post("/log_out")]
-pub async fn submit_log_out(session: Session) -> actix_web::Result<HttpResponse> {
-    session.purge();
-    Ok(HttpResponse::SeeOther()
-        .set_header(header::LOCATION, "/log_in")
-        .finish())
+async fn finish_pending_log_in(
+    session: &Session,
+    dynamodb_client: &DynamoDbClient,
+    pending_log_in: &PendingLogIn,
+    now: &chrono::DateTime<chrono::Utc>,
+) -> actix_web::Result<()> {
+    session.remove(PENDING_LOG_IN_SESSION_KEY);
+    start_session(
+        session,
+        dynamodb_client,
+        &pending_log_in.user_id,
+        &pending_log_in.org_id,
+        pending_log_in.keep_logged_in,
+        now,
+    )
+    .await
+    .map_err(|e| {
+        log::error!("{}", e);
+        error::ErrorInternalServerError("")
+    })
+}
+
+async fn get_user_item(
+    dynamodb_client: &DynamoDbClient,
+    email: &str,
+) -> anyhow::Result<HashMap<String, AttributeValue>> {
+    find_user_item(dynamodb_client, email)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("user does not exist"))
+}
+
+async fn find_user_item(
+    dynamodb_client: &DynamoDbClient,
+    email: &str,
+) -> anyhow::Result<Option<HashMap<String, AttributeValue>>> {
+    let output = dynamodb_client
+        .get_item(GetItemInput {
+            table_name: table_name("users"),
+            consistent_read: Some(true),
+            key: av_map(&[av_s("email", email)]),
+            ..Default::default()
+        })
+        .await?;
+    Ok(output.item)
+}
+
+/// Finds the user that the provider account is linked to. If it is not linked yet, links it to
+/// the user with the same email, and signs that user up if there is none. Returns None if the
+/// account is not linked, and the provider has not verified its email.
+async fn find_or_create_external_user(
+    dynamodb_client: &DynamoDbClient,
+    provider_name: &str,
+    identity: &ExternalIdentity,
+) -> anyhow::Result<Option<HashMap<String, AttributeValue>>> {
+    let link =
+        identity_providers::find_identity_link(dynamodb_client, provider_name, identity).await?;
+    if let Some(link) = link {
+        // The user may have deleted their account since, and someone else may have signed up with
+        // the email. If so, the link is stale, and the account must be linked again.
+        if let Some(user_item) = find_user_item(dynamodb_client, &link.email).await? {
+            if av_get_s(&user_item, "id") == Some(link.user_id.as_str()) {
+                return Ok(Some(user_item));
+            }
+        }
+    }
+
+    // Anyone can put any email on an account with some providers, so only a verified email proves
+    // that the user owns it.
+    if !identity.email_verified || identity.email.is_empty() {
+        return Ok(None);
+    }
+    let user_item = match find_user_item(dynamodb_client, &identity.email).await? {
+        Some(user_item) => user_item,
+        None => {
+            let name = if identity.name.is_empty() {
+                &identity.email
+            } else {
+                &identity.name
+            };
+            match create_user_and_org(dynamodb_client, &identity.email, name, None).await {
+                // The user may have just signed up some other way.
+                Ok(_) | Err(CreateUserError::AlreadyExists) => {}
+                Err(CreateUserError::Other(e)) => anyhow::bail!(e),
+            }
+            get_user_item(dynamodb_client, &identity.email).await?
+        }
+    };
+    let user_id = av_get_s(&user_item, "id")
+        .and_then(Id::parse)
+        .ok_or_else(|| anyhow::anyhow!("user has no valid id"))?;
+    identity_providers::link_identity(
+        dynamodb_client,
+        provider_name,
+        identity,
+        &user_id,
+        &identity.email,
+    )
+    .await?;
+    Ok(Some(user_item))
+}
+
+/// Returns the reason that DynamoDB gave for cancelling the first item of a transaction, from the
+/// message of a `TransactionCanceled` error, which looks like "Transaction cancelled, please refer
+/// cancellation reasons for specific reasons [ConditionalCheckFailed, None, None]".
+fn first_cancellation_reason(message: &str) -> Option<&str> {
+    let start = message.rfind('[')? + 1;
+    let end = start + message[start..].find(']')?;
+    message[start..end].split(',').next().map(str::trim)
+}
+
+/// Deletes whatever a sign up wrote, as long as it is still the sign up's. Errors are logged, since
+/// the user is already being shown an error. Anything left behind is removed by
+/// `accounts::delete_orphaned_users`.
+async fn undo_sign_up(dynamodb_client: &DynamoDbClient, email: &str, user_id: &Id, org_id: &Id) {
+    let inputs = vec![
+        DeleteItemInput {
+            table_name: table_name("organization_users"),
+            key: av_map(&[
+                av_s("org_id", org_id.as_str()),
+                av_s("user_id", user_id.as_str()),
+            ]),
+            ..DeleteItemInput::default()
+        },
+        DeleteItemInput {
+            table_name: table_name("organizations"),
+            key: av_map(&[av_s("id", org_id.as_str())]),
+            ..DeleteItemInput::default()
+        },
+        DeleteItemInput {
+            table_name: table_name("users"),
+            key: av_map(&[av_s("email", email)]),
+            // Someone else may have signed up with the email address in the meantime.
+            condition_expression: Some("id = :id".to_string()),
+            expression_attribute_values: Some(av_map(&[av_s(":id", user_id.as_str())])),
+            ..DeleteItemInput::default()
+        },
+    ];
+    for input in inputs {
+        if let Err(e) = dynamodb_client.delete_item(input).await {
+            log::error!(
+                "Error occurred: \"{}\" [undo_sign_up] [user_id: {}]",
+                e,
+                user_id.as_str()
+            );
+        }
+    }
+}
+
+fn shared_document_url(doc_id: &str, share_token: &str) -> String {
+    // TODO(cliff): Stop hard-coding the frontend's development server URL.
+    format!(
+        "http://localhost:3000/document/{}?share_token={}",
+        doc_id, share_token
+    )
 }
 
 fn validate_sign_up_form(form: &SignUpForm) -> Result<(), String> {
@@ -496,13 +1392,19 @@ mod tests {
 
     use std::collections::HashMap;
 
+    use std::sync::Arc;
+
+    use actix_web::dev::{Service, ServiceResponse};
     use actix_web::http::StatusCode;
     use actix_web::test;
     use actix_web::test::TestRequest;
     use actix_web::App;
     use chrono::Utc;
     use cookie::Cookie;
+    use futures::future::LocalBoxFuture;
+    use futures::TryFutureExt;
 
+    use crate::identity_providers::{IdentityProvider, IdentityProviders};
     use crate::ids::{Id, IdType};
     use crate::testing::utils::{
         decrypt_session_cookie_value, default_backend_service, default_cookie_session, TestDynamoDb,
@@ -548,6 +1450,7 @@ mod tests {
         let login_form = LoginForm {
             email: "jane@smith.com".to_string(),
             password: password.to_string(),
+            keep_logged_in: false,
         };
         let request = TestRequest::post()
             .uri("/log_in")
@@ -577,6 +1480,380 @@ mod tests {
         assert_eq!(org_id.as_str(), &session_org_id);
     }
 
+    #[tokio::test]
+    async fn test_login_keep_logged_in() {
+        let db = TestDynamoDb::new().await;
+
+        let org_id = Id::new(IdType::Organization);
+        let user_id = create_user(&db.dynamodb_client, "jane@smith.com", "Jane Smith").await;
+        create_organization_user(&db.dynamodb_client, &org_id, &user_id, &Utc::now()).await;
+        let password = "KDIo*kJDLJ(1j1;;asdf;1;;1testtesttest";
+        let hashed_password = bcrypt::hash(password, 4).unwrap();
+        db.dynamodb_client
+            .update_item(UpdateItemInput {
+                table_name: table_name("users"),
+                key: av_map(&[av_s("email", "jane@smith.com")]),
+                update_expression: Some("SET hashed_password = :hashed_password".to_string()),
+                expression_attribute_values: Some(av_map(&[av_s(
+                    ":hashed_password",
+                    &hashed_password,
+                )])),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let mut test_app = test::init_service(
+            App::new()
+                .data(default_backend_service().await)
+                .wrap_fn(|req, srv| {
+                    srv.call(req).map_ok(|res| {
+                        http::record_session_cookie_lifetime(&res);
+                        res
+                    })
+                })
+                .wrap(default_cookie_session())
+                .wrap_fn(|req, srv| {
+                    srv.call(req).map_ok(|mut res| {
+                        http::apply_session_cookie_lifetime(&mut res);
+                        res
+                    })
+                })
+                .service(submit_log_in),
+        )
+        .await;
+
+        for keep_logged_in in &[false, true] {
+            let login_form = LoginForm {
+                email: "jane@smith.com".to_string(),
+                password: password.to_string(),
+                keep_logged_in: *keep_logged_in,
+            };
+            let request = TestRequest::post()
+                .uri("/log_in")
+                .header("content-type", "application/x-www-form-urlencoded")
+                .set_form(&login_form)
+                .to_request();
+            let response = test::call_service(&mut test_app, request).await;
+            assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+            let cookies: Vec<Cookie> = response.response().cookies().collect();
+            assert_eq!(cookies.len(), 1);
+            // Without "keep me logged in", the browser forgets the cookie when it closes.
+            let max_age = cookies[0].max_age().map(|max_age| max_age.whole_days());
+            if *keep_logged_in {
+                assert_eq!(max_age, Some(30));
+            } else {
+                assert_eq!(max_age, None);
+            }
+            let decrypted_cookie_value =
+                decrypt_session_cookie_value(&cookies[0], "session").unwrap();
+            let session_map: HashMap<String, String> =
+                serde_json::from_str(&decrypted_cookie_value).unwrap();
+            assert_eq!(
+                session_map.get(http::KEEP_LOGGED_IN_SESSION_KEY),
+                Some(&keep_logged_in.to_string())
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_login_two_factor() {
+        let db = TestDynamoDb::new().await;
+
+        let org_id = Id::new(IdType::Organization);
+        let user_id = create_user(&db.dynamodb_client, "jane@smith.com", "Jane Smith").await;
+        create_organization_user(&db.dynamodb_client, &org_id, &user_id, &Utc::now()).await;
+        let password = "KDIo*kJDLJ(1j1;;asdf;1;;1testtesttest";
+        let hashed_password = bcrypt::hash(password, 4).unwrap();
+        // The base32 encoding of the secret from the RFC 6238 test vectors.
+        let secret = b"12345678901234567890";
+        db.dynamodb_client
+            .update_item(UpdateItemInput {
+                table_name: table_name("users"),
+                key: av_map(&[av_s("email", "jane@smith.com")]),
+                update_expression: Some(
+                    "SET hashed_password = :hashed_password, totp_secret = :totp_secret"
+                        .to_string(),
+                ),
+                expression_attribute_values: Some(av_map(&[
+                    av_s(":hashed_password", &hashed_password),
+                    av_s(":totp_secret", "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"),
+                ])),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let mut test_app = test::init_service(
+            App::new()
+                .data(default_backend_service().await)
+                .wrap(default_cookie_session())
+                .service(submit_log_in)
+                .service(submit_two_factor),
+        )
+        .await;
+
+        // The password alone does not log the user in.
+        let request = TestRequest::post()
+            .uri("/log_in")
+            .header("content-type", "application/x-www-form-urlencoded")
+            .set_form(&LoginForm {
+                email: "jane@smith.com".to_string(),
+                password: password.to_string(),
+                keep_logged_in: false,
+            })
+            .to_request();
+        let response = test::call_service(&mut test_app, request).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(
+            response.headers().get(header::LOCATION).unwrap(),
+            "/log_in/two_factor"
+        );
+        let cookie = response.response().cookies().next().unwrap().into_owned();
+        let session_map: HashMap<String, String> =
+            serde_json::from_str(&decrypt_session_cookie_value(&cookie, "session").unwrap())
+                .unwrap();
+        assert!(session_map.get("user_id").is_none());
+
+        let two_factor_request = |code: String| {
+            TestRequest::post()
+                .uri("/log_in/two_factor")
+                .header("content-type", "application/x-www-form-urlencoded")
+                .cookie(cookie.clone())
+                .set_form(&TwoFactorForm { code })
+                .to_request()
+        };
+        let response =
+            test::call_service(&mut test_app, two_factor_request("000000".to_string())).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let code = two_factor::totp_code(secret, two_factor::totp_step(&Utc::now()));
+        let response = test::call_service(&mut test_app, two_factor_request(code)).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers().get(header::LOCATION).unwrap(), "/app");
+        let cookie = response.response().cookies().next().unwrap().into_owned();
+        let session_map: HashMap<String, String> =
+            serde_json::from_str(&decrypt_session_cookie_value(&cookie, "session").unwrap())
+                .unwrap();
+        let session_user_id: String =
+            serde_json::from_str(session_map.get("user_id").unwrap()).unwrap();
+        assert_eq!(user_id.as_str(), &session_user_id);
+        assert!(session_map.get(PENDING_LOG_IN_SESSION_KEY).is_none());
+    }
+
+    /// Hands out the identity for each code, without asking anyone.
+    struct FakeIdentityProvider {
+        identities: HashMap<&'static str, ExternalIdentity>,
+    }
+
+    impl IdentityProvider for FakeIdentityProvider {
+        fn name(&self) -> &'static str {
+            "fake"
+        }
+
+        fn display_name(&self) -> &'static str {
+            "Fake"
+        }
+
+        fn authorization_url(&self, redirect_uri: &str, state: &str) -> String {
+            format!(
+                "https://fake.example.com/auth?redirect_uri={}&state={}",
+                redirect_uri, state
+            )
+        }
+
+        fn fetch_identity<'a>(
+            &'a self,
+            _redirect_uri: &'a str,
+            code: &'a str,
+        ) -> LocalBoxFuture<'a, anyhow::Result<ExternalIdentity>> {
+            let identity = self.identities.get(code).cloned();
+            Box::pin(async move { identity.ok_or_else(|| anyhow::anyhow!("unknown code")) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_external_log_in() {
+        let db = TestDynamoDb::new().await;
+
+        let org_id = Id::new(IdType::Organization);
+        let user_id = create_user(&db.dynamodb_client, "jane@smith.com", "Jane Smith").await;
+        create_organization_user(&db.dynamodb_client, &org_id, &user_id, &Utc::now()).await;
+
+        let identity = |subject: &str, email: &str, email_verified: bool| ExternalIdentity {
+            subject: subject.to_string(),
+            email: email.to_string(),
+            email_verified,
+            name: String::new(),
+        };
+        let mut identity_providers = IdentityProviders::default();
+        identity_providers.register(Arc::new(FakeIdentityProvider {
+            identities: maplit::hashmap! {
+                "jane" => identity("1", "jane@smith.com", true),
+                "new" => identity("2", "new@example.com", true),
+                "unverified" => identity("3", "jane@smith.com", false),
+                "renamed" => identity("1", "jane@example.com", false),
+            },
+        }));
+        let mut service = default_backend_service().await;
+        service.identity_providers = Arc::new(identity_providers);
+        let mut test_app = test::init_service(
+            App::new()
+                .data(service)
+                .wrap(default_cookie_session())
+                .service(start_external_log_in)
+                .service(finish_external_log_in),
+        )
+        .await;
+
+        let request = TestRequest::get().uri("/auth/fake").to_request();
+        let response = test::call_service(&mut test_app, request).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        let location = response.headers().get(header::LOCATION).unwrap();
+        let state = location.to_str().unwrap().split("state=").nth(1).unwrap();
+        let state = state.to_string();
+        let state_cookie = response.response().cookies().next().unwrap().into_owned();
+        assert_eq!(state_cookie.name(), EXTERNAL_LOG_IN_STATE_COOKIE);
+
+        let callback_request = |code: &str, state: &str| {
+            TestRequest::get()
+                .uri(&format!(
+                    "/auth/fake/callback?code={}&state={}",
+                    code, state
+                ))
+                .cookie(state_cookie.clone())
+                .to_request()
+        };
+        let session_user_id = |response: &ServiceResponse| -> Option<String> {
+            let cookie = response
+                .response()
+                .cookies()
+                .find(|cookie| cookie.name() == "session")?
+                .into_owned();
+            let session_map: HashMap<String, String> =
+                serde_json::from_str(&decrypt_session_cookie_value(&cookie, "session")?).ok()?;
+            serde_json::from_str(session_map.get("user_id")?).ok()
+        };
+
+        // The state must match the one that the log in started with.
+        let request = callback_request("jane", "wrong");
+        let response = test::call_service(&mut test_app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(session_user_id(&response), None);
+
+        // Links the account to the user with the same email.
+        let request = callback_request("jane", &state);
+        let response = test::call_service(&mut test_app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            session_user_id(&response),
+            Some(user_id.as_str().to_string())
+        );
+
+        // An email that the provider has not verified is not enough to link the account.
+        let request = callback_request("unverified", &state);
+        let response = test::call_service(&mut test_app, request).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(session_user_id(&response), None);
+
+        // Signs up users without an account, with an org of their own.
+        let request = callback_request("new", &state);
+        let response = test::call_service(&mut test_app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let new_user_id = session_user_id(&response).unwrap();
+        assert_ne!(new_user_id, user_id.as_str());
+        let new_user_org_id = find_latest_org_id(&db.dynamodb_client, &new_user_id)
+            .await
+            .unwrap();
+        assert!(new_user_org_id.is_some());
+
+        // The link is by subject, so it holds after the account's email changes.
+        let request = callback_request("renamed", &state);
+        let response = test::call_service(&mut test_app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            session_user_id(&response),
+            Some(user_id.as_str().to_string())
+        );
+
+        // Once Jane deletes her account, the link does not lead to whoever signs up with her email
+        // next.
+        db.dynamodb_client
+            .delete_item(DeleteItemInput {
+                table_name: table_name("users"),
+                key: av_map(&[av_s("email", "jane@smith.com")]),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let next_user_id = create_user(&db.dynamodb_client, "jane@smith.com", "Not Jane").await;
+        create_organization_user(&db.dynamodb_client, &org_id, &next_user_id, &Utc::now()).await;
+        let request = callback_request("renamed", &state);
+        let response = test::call_service(&mut test_app, request).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(session_user_id(&response), None);
+    }
+
+    #[tokio::test]
+    async fn test_login_locked_out() {
+        let db = TestDynamoDb::new().await;
+
+        let org_id = Id::new(IdType::Organization);
+        let user_id = create_user(&db.dynamodb_client, "jane@smith.com", "Jane Smith").await;
+        create_organization_user(&db.dynamodb_client, &org_id, &user_id, &Utc::now()).await;
+        let password = "KDIo*kJDLJ(1j1;;asdf;1;;1testtesttest";
+        db.dynamodb_client
+            .update_item(UpdateItemInput {
+                table_name: table_name("users"),
+                key: av_map(&[av_s("email", "jane@smith.com")]),
+                update_expression: Some("SET hashed_password = :hashed_password".to_string()),
+                expression_attribute_values: Some(av_map(&[av_s(
+                    ":hashed_password",
+                    &bcrypt::hash(password, 4).unwrap(),
+                )])),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let mut test_app = test::init_service(
+            App::new()
+                .data(default_backend_service().await)
+                .wrap(default_cookie_session())
+                .service(submit_log_in),
+        )
+        .await;
+        let log_in_request = |email: &str, password: &str| {
+            TestRequest::post()
+                .uri("/log_in")
+                .header("content-type", "application/x-www-form-urlencoded")
+                .set_form(&LoginForm {
+                    email: email.to_string(),
+                    password: password.to_string(),
+                    keep_logged_in: false,
+                })
+                .to_request()
+        };
+
+        // Accounts and emails without one are locked out alike.
+        for email in &["jane@smith.com", "nobody@smith.com"] {
+            for _ in 1..log_in_attempts::MAX_FAILED_LOG_IN_ATTEMPTS {
+                let request = log_in_request(email, "wrong password");
+                let response = test::call_service(&mut test_app, request).await;
+                assert_eq!(response.status(), StatusCode::NOT_FOUND);
+            }
+            let request = log_in_request(email, "wrong password");
+            let response = test::call_service(&mut test_app, request).await;
+            assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+
+        // Even the right password is turned away until the lockout ends.
+        let request = log_in_request("jane@smith.com", password);
+        let response = test::call_service(&mut test_app, request).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
     #[tokio::test]
     async fn test_login_user_not_found() {
         let db = TestDynamoDb::new().await;
@@ -597,6 +1874,7 @@ mod tests {
         let login_form = LoginForm {
             email: "some@randomemail.com".to_string(),
             password: "foobar123123!!!Foobar".to_string(),
+            keep_logged_in: false,
         };
         let request = TestRequest::post()
             .uri("/log_in")
@@ -652,6 +1930,7 @@ mod tests {
         let login_form = LoginForm {
             email: "jane@smith.com".to_string(),
             password: "foobar123123!!!Foobar".to_string(),
+            keep_logged_in: false,
         };
         let request = TestRequest::post()
             .uri("/log_in")
@@ -726,6 +2005,25 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_first_cancellation_reason() {
+        assert_eq!(
+            first_cancellation_reason(
+                "Transaction cancelled, please refer cancellation reasons for specific reasons \
+                [ConditionalCheckFailed, None, None]"
+            ),
+            Some("ConditionalCheckFailed")
+        );
+        assert_eq!(
+            first_cancellation_reason(
+                "Transaction cancelled, please refer cancellation reasons for specific reasons \
+                [None, ThrottlingError, None]"
+            ),
+            Some("None")
+        );
+        assert_eq!(first_cancellation_reason("Transaction cancelled"), None);
+    }
+
     #[tokio::test]
     async fn test_sign_up_success() {
         // NOTE: This test runs a little slowly, about 1 second, because it uses `bcrypt::hash` to
@@ -0,0 +1,141 @@
+//! Trace ids, which tie the server's logs for a request to the client's.
+//!
+//! Clients send one in the `TRACE_ID_HEADER` with each request. `with_trace_id` runs the request
+//! with it, and `TracingLogger` appends it to everything logged meanwhile. Responses from handlers
+//! echo it back, error responses included, and the access log records it.
+
+use std::future::Future;
+
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::HeaderName;
+use actix_web::http::HeaderValue;
+use log::{Log, Metadata, Record};
+
+use ot::protocol::TRACE_ID_HEADER;
+
+/// Trace ids from clients longer than this are replaced, so that they cannot bloat the logs.
+const MAX_TRACE_ID_LEN: usize = 64;
+
+/// The format of the access log: actix-web's default, with the trace id appended.
+pub const ACCESS_LOG_FORMAT: &str =
+    "%a \"%r\" %s %b \"%{Referer}i\" \"%{User-Agent}i\" %T trace_id=%{x-writing-trace-id}o";
+
+tokio::task_local! {
+    static TRACE_ID: String;
+}
+
+/// The request's trace id, from its header if the client sent a valid one, or a new one.
+pub fn request_trace_id(req: &ServiceRequest) -> String {
+    req.headers()
+        .get(TRACE_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|trace_id| is_valid_trace_id(trace_id))
+        .map(String::from)
+        .unwrap_or_else(new_trace_id)
+}
+
+fn is_valid_trace_id(trace_id: &str) -> bool {
+    !trace_id.is_empty()
+        && trace_id.len() <= MAX_TRACE_ID_LEN
+        && trace_id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+fn new_trace_id() -> String {
+    rand::random::<[u8; 8]>()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Runs the future as the handling of the request with the given trace id.
+pub async fn with_trace_id<F: Future>(trace_id: String, future: F) -> F::Output {
+    TRACE_ID.scope(trace_id, future).await
+}
+
+/// The trace id of the request that the current task is handling, if any.
+pub fn current_trace_id() -> Option<String> {
+    TRACE_ID.try_with(|trace_id| trace_id.clone()).ok()
+}
+
+pub fn set_trace_id_header<B>(res: &mut ServiceResponse<B>, trace_id: &str) {
+    if let Ok(value) = HeaderValue::from_str(trace_id) {
+        res.headers_mut()
+            .insert(HeaderName::from_static(TRACE_ID_HEADER), value);
+    }
+}
+
+/// Appends the current request's trace id to each record, and passes it on to the inner logger.
+pub struct TracingLogger<L> {
+    inner: L,
+}
+
+impl<L: Log> TracingLogger<L> {
+    pub fn new(inner: L) -> Self {
+        Self { inner }
+    }
+}
+
+impl<L: Log> Log for TracingLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        let trace_id = match current_trace_id() {
+            Some(trace_id) => trace_id,
+            None => return self.inner.log(record),
+        };
+        self.inner.log(
+            &Record::builder()
+                .args(format_args!("{} trace_id={}", record.args(), trace_id))
+                .metadata(record.metadata().clone())
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .build(),
+        );
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_request_trace_id() {
+        let req = TestRequest::default()
+            .header(TRACE_ID_HEADER, "3f2a9c0d1e4b5a67")
+            .to_srv_request();
+        assert_eq!(request_trace_id(&req), "3f2a9c0d1e4b5a67");
+
+        // Missing and invalid trace ids are replaced with new ones.
+        let req = TestRequest::default().to_srv_request();
+        let trace_id = request_trace_id(&req);
+        assert_eq!(trace_id.len(), 16);
+        assert_ne!(request_trace_id(&req), trace_id);
+        let req = TestRequest::default()
+            .header(TRACE_ID_HEADER, "not a trace id")
+            .to_srv_request();
+        assert_eq!(request_trace_id(&req).len(), 16);
+        let req = TestRequest::default()
+            .header(TRACE_ID_HEADER, "a".repeat(MAX_TRACE_ID_LEN + 1))
+            .to_srv_request();
+        assert_eq!(request_trace_id(&req).len(), 16);
+    }
+
+    #[tokio::test]
+    async fn test_with_trace_id() {
+        assert_eq!(current_trace_id(), None);
+        let trace_id =
+            with_trace_id("3f2a9c0d1e4b5a67".to_string(), async { current_trace_id() }).await;
+        assert_eq!(trace_id.as_deref(), Some("3f2a9c0d1e4b5a67"));
+    }
+}
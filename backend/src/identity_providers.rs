@@ -0,0 +1,316 @@
+//! Logging in with an account from an identity provider, like Google, instead of a password.
+//!
+//! Each provider implements `IdentityProvider`, which covers both halves of a redirect-based log
+//! in: where to send the user, and how to turn the code that they come back with into an identity
+//! that the provider vouches for. Google is an OAuth 2.0 provider with OpenID Connect. Enterprise
+//! SSO, over SAML or any OpenID Connect provider, fits the same shape, and can be added as more
+//! providers.
+//!
+//! Provider accounts are linked to users in `external_identities`, by the provider's id for the
+//! account, so that the link outlives changes to the account's email. The first log in with an
+//! account links it to the user with the same email, if the provider has verified the email.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use futures::future::LocalBoxFuture;
+use rusoto_dynamodb::{GetItemInput, PutItemInput};
+use serde::Deserialize;
+
+use crate::dynamodb::{av_get_s, av_map, av_s, table_name, DynamoDbClient};
+use crate::ids::Id;
+use crate::utils::{time, uri};
+
+/// Who the provider says the user is.
+#[derive(Clone, Debug)]
+pub struct ExternalIdentity {
+    /// The provider's id for the account. Unlike the email, it never changes.
+    pub subject: String,
+    pub email: String,
+    /// Whether the provider has checked that the account owns the email.
+    pub email_verified: bool,
+    /// May be empty.
+    pub name: String,
+}
+
+pub trait IdentityProvider: Send + Sync {
+    /// Names the provider in URLs and in `external_identities`, e.g. "google".
+    fn name(&self) -> &'static str;
+
+    /// Shown to users, e.g. "Google".
+    fn display_name(&self) -> &'static str;
+
+    /// Where to send the user to log in. The provider sends them back to `redirect_uri` with
+    /// `state`, and a code for `fetch_identity`.
+    fn authorization_url(&self, redirect_uri: &str, state: &str) -> String;
+
+    /// Exchanges the code that the user came back with for their identity.
+    fn fetch_identity<'a>(
+        &'a self,
+        redirect_uri: &'a str,
+        code: &'a str,
+    ) -> LocalBoxFuture<'a, anyhow::Result<ExternalIdentity>>;
+}
+
+/// The identity providers that users may log in with, by name.
+#[derive(Default)]
+pub struct IdentityProviders {
+    providers: HashMap<&'static str, Arc<dyn IdentityProvider>>,
+}
+
+impl IdentityProviders {
+    pub fn register(&mut self, provider: Arc<dyn IdentityProvider>) {
+        self.providers.insert(provider.name(), provider);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn IdentityProvider>> {
+        self.providers.get(name)
+    }
+
+    /// All of the providers, in order of name.
+    pub fn all(&self) -> Vec<&Arc<dyn IdentityProvider>> {
+        let mut providers: Vec<_> = self.providers.values().collect();
+        providers.sort_by_key(|provider| provider.name());
+        providers
+    }
+}
+
+/// Where a provider sends users back to after they log in. It must be registered with the
+/// provider.
+pub fn callback_url(provider_name: &str) -> String {
+    // TODO(cliff): Stop hard-coding the development server URL.
+    format!("http://localhost:8080/auth/{}/callback", provider_name)
+}
+
+/// The user that a provider account is linked to.
+#[derive(Debug, PartialEq)]
+pub struct IdentityLink {
+    pub email: String,
+    /// The user's id, u_<id>. The email may belong to a different user by now, if the linked user
+    /// deleted their account and someone signed up with the email again.
+    pub user_id: String,
+}
+
+/// Returns the user that the provider account is linked to, if it is linked.
+pub async fn find_identity_link(
+    dynamodb_client: &DynamoDbClient,
+    provider_name: &str,
+    identity: &ExternalIdentity,
+) -> anyhow::Result<Option<IdentityLink>> {
+    let output = dynamodb_client
+        .get_item(GetItemInput {
+            table_name: table_name("external_identities"),
+            key: av_map(&[
+                av_s("provider", provider_name),
+                av_s("subject", &identity.subject),
+            ]),
+            projection_expression: Some(String::from("email, user_id")),
+            ..Default::default()
+        })
+        .await?;
+    let item = match output.item {
+        Some(item) => item,
+        None => return Ok(None),
+    };
+    match (av_get_s(&item, "email"), av_get_s(&item, "user_id")) {
+        (Some(email), Some(user_id)) => Ok(Some(IdentityLink {
+            email: email.to_string(),
+            user_id: user_id.to_string(),
+        })),
+        _ => Err(anyhow::anyhow!("identity link is missing email or user_id")),
+    }
+}
+
+/// Links the provider account to the user, replacing any earlier link, e.g. to a user whose
+/// account was since deleted.
+pub async fn link_identity(
+    dynamodb_client: &DynamoDbClient,
+    provider_name: &str,
+    identity: &ExternalIdentity,
+    user_id: &Id,
+    email: &str,
+) -> anyhow::Result<()> {
+    dynamodb_client
+        .put_item(PutItemInput {
+            table_name: table_name("external_identities"),
+            item: av_map(&[
+                av_s("provider", provider_name),
+                av_s("subject", &identity.subject),
+                av_s("email", email),
+                av_s("user_id", user_id.as_str()),
+                av_s("created_at", &time::date_time_iso_str(&chrono::Utc::now())),
+            ]),
+            ..Default::default()
+        })
+        .await?;
+    Ok(())
+}
+
+const GOOGLE_AUTHORIZATION_ENDPOINT: &str = "https://accounts.google.com/o/oauth2/v2/auth";
+const GOOGLE_TOKEN_ENDPOINT: &str = "https://oauth2.googleapis.com/token";
+const GOOGLE_USERINFO_ENDPOINT: &str = "https://openidconnect.googleapis.com/v1/userinfo";
+
+/// Log in with Google, over OAuth 2.0 with OpenID Connect. The identity comes from Google's
+/// userinfo endpoint, over TLS, so there is no ID token signature to check.
+pub struct GoogleIdentityProvider {
+    client_id: String,
+    client_secret: String,
+}
+
+impl GoogleIdentityProvider {
+    pub fn new(client_id: &str, client_secret: &str) -> Self {
+        GoogleIdentityProvider {
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct GoogleTokenResponse {
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+struct GoogleUserInfo {
+    sub: String,
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    email_verified: bool,
+    #[serde(default)]
+    name: String,
+}
+
+impl IdentityProvider for GoogleIdentityProvider {
+    fn name(&self) -> &'static str {
+        "google"
+    }
+
+    fn display_name(&self) -> &'static str {
+        "Google"
+    }
+
+    fn authorization_url(&self, redirect_uri: &str, state: &str) -> String {
+        format!(
+            "{}?response_type=code&client_id={}&redirect_uri={}&scope={}&state={}",
+            GOOGLE_AUTHORIZATION_ENDPOINT,
+            uri::encode(&self.client_id),
+            uri::encode(redirect_uri),
+            uri::encode("openid email profile"),
+            uri::encode(state),
+        )
+    }
+
+    fn fetch_identity<'a>(
+        &'a self,
+        redirect_uri: &'a str,
+        code: &'a str,
+    ) -> LocalBoxFuture<'a, anyhow::Result<ExternalIdentity>> {
+        Box::pin(async move {
+            let client = actix_web::client::Client::default();
+            let mut response = client
+                .post(GOOGLE_TOKEN_ENDPOINT)
+                .send_form(&[
+                    ("code", code),
+                    ("client_id", self.client_id.as_str()),
+                    ("client_secret", self.client_secret.as_str()),
+                    ("redirect_uri", redirect_uri),
+                    ("grant_type", "authorization_code"),
+                ])
+                .await
+                .map_err(|e| anyhow::anyhow!("token request failed: {}", e))?;
+            if !response.status().is_success() {
+                anyhow::bail!("token request failed with status {}", response.status());
+            }
+            let token: GoogleTokenResponse = response
+                .json()
+                .await
+                .map_err(|e| anyhow::anyhow!("token response is invalid: {}", e))?;
+
+            let mut response = client
+                .get(GOOGLE_USERINFO_ENDPOINT)
+                .bearer_auth(&token.access_token)
+                .send()
+                .await
+                .map_err(|e| anyhow::anyhow!("userinfo request failed: {}", e))?;
+            if !response.status().is_success() {
+                anyhow::bail!("userinfo request failed with status {}", response.status());
+            }
+            let user_info: GoogleUserInfo = response
+                .json()
+                .await
+                .map_err(|e| anyhow::anyhow!("userinfo response is invalid: {}", e))?;
+            Ok(ExternalIdentity {
+                subject: user_info.sub,
+                email: user_info.email,
+                email_verified: user_info.email_verified,
+                name: user_info.name,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ids::IdType;
+    use crate::testing::utils::TestDynamoDb;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[test]
+    fn test_google_authorization_url() {
+        let provider = GoogleIdentityProvider::new("client id", "secret");
+        assert_eq!(
+            provider.authorization_url(&callback_url("google"), "state123"),
+            "https://accounts.google.com/o/oauth2/v2/auth?response_type=code\
+            &client_id=client%20id\
+            &redirect_uri=http%3A%2F%2Flocalhost%3A8080%2Fauth%2Fgoogle%2Fcallback\
+            &scope=openid%20email%20profile&state=state123"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_link_identity() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let identity = ExternalIdentity {
+            subject: String::from("1234"),
+            email: String::from("jane@smith.com"),
+            email_verified: true,
+            name: String::from("Jane Smith"),
+        };
+        assert_eq!(
+            find_identity_link(&db.dynamodb_client, "google", &identity).await?,
+            None
+        );
+
+        let user_id = Id::new(IdType::User);
+        link_identity(
+            &db.dynamodb_client,
+            "google",
+            &identity,
+            &user_id,
+            "jane@example.com",
+        )
+        .await?;
+        // The link is by subject, so it holds even after the account's email changes.
+        let renamed = ExternalIdentity {
+            email: String::from("jane.smith@smith.com"),
+            ..identity.clone()
+        };
+        assert_eq!(
+            find_identity_link(&db.dynamodb_client, "google", &renamed).await?,
+            Some(IdentityLink {
+                email: String::from("jane@example.com"),
+                user_id: user_id.as_str().to_string(),
+            })
+        );
+        assert_eq!(
+            find_identity_link(&db.dynamodb_client, "saml", &identity).await?,
+            None
+        );
+        Ok(())
+    }
+}
@@ -33,9 +33,14 @@ use crate::utils::profanity;
 /// The type of object an identifier indentifies.
 #[derive(Clone, Copy, Debug, IntoEnumIterator, PartialEq, Eq, Hash)]
 pub enum IdType {
+    Attachment,
     Document,
+    Guest,
+    Job,
     LockLease,
     Organization,
+    ShareToken,
+    SigningKey,
     User,
 }
 
@@ -112,9 +117,14 @@ impl IdType {
     /// ```
     pub fn as_str(&self) -> &'static str {
         match *self {
+            IdType::Attachment => "a",
             IdType::Document => "d",
+            IdType::Guest => "g",
+            IdType::Job => "j",
             IdType::LockLease => "ll",
             IdType::Organization => "o",
+            IdType::ShareToken => "st",
+            IdType::SigningKey => "sk",
             IdType::User => "u",
         }
     }
@@ -0,0 +1,576 @@
+//! Background jobs.
+//!
+//! Work that takes too long to do while a request waits, like exporting an org, is recorded as a
+//! job in the `jobs` table and run in the background. The job item records the job's progress, so
+//! that the user who started it can follow along.
+//!
+//! Every server runs a few job worker tasks. A worker claims a job that is ready to run by
+//! conditionally updating it to `Running`, so two workers never claim the same job. The claim is a
+//! lease: while the job runs, the worker keeps pushing the lease's expiry back, and if the worker
+//! dies, another worker claims the job once the lease expires.
+//!
+//! A job that fails is retried with exponential backoff, up to `MAX_JOB_ATTEMPTS` attempts, so
+//! handlers must be safe to run more than once.
+//!
+//! To add a new type of job, implement `JobHandler` for it and register the handler in `main`.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::{self, BoxFuture, Either};
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{
+    AttributeValue, GetItemInput, PutItemInput, QueryInput, UpdateItemError, UpdateItemInput,
+};
+
+use crate::dynamodb::{av_get_n, av_get_s, av_map, av_n, av_s, table_name, DynamoDbClient};
+use crate::http::SessionUser;
+use crate::ids::{Id, IdType};
+use crate::utils::time;
+
+/// A job is given up on after failing this many times.
+pub const MAX_JOB_ATTEMPTS: i64 = 5;
+
+/// How long to wait before retrying a job that failed once. The wait doubles after each further
+/// failure.
+pub const JOB_RETRY_BASE_DELAY_SECONDS: i64 = 30;
+
+/// How long a worker's claim on a job lasts unless the worker renews it.
+pub const JOB_LEASE_SECONDS: i64 = 60;
+
+/// How often a worker renews its claim on the job it is running.
+const JOB_LEASE_RENEW_INTERVAL: Duration = Duration::from_secs(20);
+
+/// How long an idle worker waits before looking for ready jobs again.
+pub const JOB_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How many ready jobs a worker considers at once when looking for one to claim.
+const JOB_CLAIM_CANDIDATES: i64 = 10;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum JobStatus {
+    Pending = 1,
+    Running = 2,
+    Complete = 3,
+    Failed = 4,
+}
+
+impl TryFrom<i32> for JobStatus {
+    type Error = ();
+
+    fn try_from(val: i32) -> Result<Self, Self::Error> {
+        match val {
+            1 => Ok(JobStatus::Pending),
+            2 => Ok(JobStatus::Running),
+            3 => Ok(JobStatus::Complete),
+            4 => Ok(JobStatus::Failed),
+            _ => Err(()),
+        }
+    }
+}
+
+pub struct Job {
+    pub job_id: String,
+    pub job_type: String,
+    pub org_id: String,
+    pub created_by_user_id: String,
+    pub job_status: JobStatus,
+    pub progress_done: i64,
+    pub progress_total: i64,
+    pub output_key: String,
+    /// How many times a worker has claimed the job, including the current attempt.
+    pub attempts: i64,
+    /// The whole job item, for attributes that only some job types have.
+    pub item: HashMap<String, AttributeValue>,
+}
+
+/// Runs the jobs of one type.
+pub trait JobHandler: Send + Sync {
+    fn job_type(&self) -> &'static str;
+
+    /// Runs the job. Returning an error schedules a retry. The handler may report progress with
+    /// `set_job_progress`, but leaves the job's status to the worker.
+    fn run<'a>(
+        &'a self,
+        dynamodb_client: &'a DynamoDbClient,
+        job: &'a Job,
+    ) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+/// Creates a pending job of the given type on behalf of the session user, with any attributes
+/// that the job type needs. A worker picks it up shortly. Returns the new job id.
+pub async fn create_job(
+    dynamodb_client: &DynamoDbClient,
+    job_type: &str,
+    session_user: &SessionUser,
+    attributes: &[(String, AttributeValue)],
+) -> anyhow::Result<Id> {
+    let job_id = Id::new(IdType::Job);
+    let now = time::date_time_iso_str(&chrono::Utc::now());
+    let mut item = av_map(&[
+        av_s("job_id", job_id.as_str()),
+        av_s("job_type", job_type),
+        av_s("org_id", session_user.org_id.as_str()),
+        av_s("created_by_user_id", session_user.user_id.as_str()),
+        av_n("job_status", JobStatus::Pending as i32),
+        av_s("next_run_at", &now),
+        av_n("attempts", 0),
+        av_n("progress_done", 0),
+        av_n("progress_total", 0),
+        av_s("created_at", &now),
+        av_s("updated_at", &now),
+    ]);
+    item.extend(attributes.iter().cloned());
+    let input = PutItemInput {
+        table_name: table_name("jobs"),
+        item,
+        ..Default::default()
+    };
+    dynamodb_client.put_item(input).await?;
+    Ok(job_id)
+}
+
+pub async fn get_job(
+    dynamodb_client: &DynamoDbClient,
+    job_id: &str,
+) -> anyhow::Result<Option<Job>> {
+    let input = GetItemInput {
+        table_name: table_name("jobs"),
+        key: av_map(&[av_s("job_id", job_id)]),
+        consistent_read: Some(true),
+        ..Default::default()
+    };
+    match dynamodb_client.get_item(input).await?.item {
+        Some(item) => Ok(Some(job_from_item(item)?)),
+        None => Ok(None),
+    }
+}
+
+fn job_from_item(item: HashMap<String, AttributeValue>) -> anyhow::Result<Job> {
+    let missing_field_error = || anyhow::anyhow!("job is missing a field");
+    let job_status = av_get_n::<i32>(&item, "job_status").ok_or_else(missing_field_error)?;
+    Ok(Job {
+        job_id: av_get_s(&item, "job_id")
+            .ok_or_else(missing_field_error)?
+            .to_string(),
+        job_type: av_get_s(&item, "job_type")
+            .ok_or_else(missing_field_error)?
+            .to_string(),
+        org_id: av_get_s(&item, "org_id")
+            .ok_or_else(missing_field_error)?
+            .to_string(),
+        created_by_user_id: av_get_s(&item, "created_by_user_id")
+            .ok_or_else(missing_field_error)?
+            .to_string(),
+        job_status: JobStatus::try_from(job_status)
+            .map_err(|_| anyhow::anyhow!("invalid job_status: {}", job_status))?,
+        progress_done: av_get_n(&item, "progress_done").unwrap_or(0),
+        progress_total: av_get_n(&item, "progress_total").unwrap_or(0),
+        output_key: av_get_s(&item, "output_key").unwrap_or("").to_string(),
+        attempts: av_get_n(&item, "attempts").unwrap_or(0),
+        item,
+    })
+}
+
+/// Sets the given attributes of the job, named after their placeholders without the leading ':'.
+/// For example, `av_n(":progress_done", 3)` sets `progress_done` to 3.
+pub async fn update_job(
+    dynamodb_client: &DynamoDbClient,
+    job_id: &str,
+    attributes: &[(String, AttributeValue)],
+) -> anyhow::Result<()> {
+    dynamodb_client
+        .update_item(update_job_input(job_id, attributes))
+        .await?;
+    Ok(())
+}
+
+fn update_job_input(job_id: &str, attributes: &[(String, AttributeValue)]) -> UpdateItemInput {
+    let mut update_expression = String::from("SET updated_at = :updated_at");
+    for (placeholder, _) in attributes {
+        update_expression.push_str(&format!(", {} = {}", &placeholder[1..], placeholder));
+    }
+    let mut expression_attribute_values = av_map(attributes);
+    let (key, value) = av_s(":updated_at", &time::date_time_iso_str(&chrono::Utc::now()));
+    expression_attribute_values.insert(key, value);
+    UpdateItemInput {
+        table_name: table_name("jobs"),
+        key: av_map(&[av_s("job_id", job_id)]),
+        update_expression: Some(update_expression),
+        expression_attribute_values: Some(expression_attribute_values),
+        ..Default::default()
+    }
+}
+
+pub async fn set_job_progress(
+    dynamodb_client: &DynamoDbClient,
+    job_id: &str,
+    progress_done: i64,
+) -> anyhow::Result<()> {
+    update_job(
+        dynamodb_client,
+        job_id,
+        &[av_n(":progress_done", progress_done)],
+    )
+    .await
+}
+
+/// How long to wait before the next attempt of a job that has failed `attempts` times.
+pub fn retry_delay(attempts: i64) -> chrono::Duration {
+    // Cap the exponent so that the delay cannot overflow. 30 seconds * 2^10 is over 8 hours.
+    let exponent = (attempts - 1).max(0).min(10);
+    chrono::Duration::seconds(JOB_RETRY_BASE_DELAY_SECONDS << exponent)
+}
+
+/// Claims and runs ready jobs with the handlers registered for their types.
+#[derive(Default)]
+pub struct JobWorker {
+    handlers: HashMap<&'static str, Arc<dyn JobHandler>>,
+}
+
+impl JobWorker {
+    pub fn register(&mut self, handler: Arc<dyn JobHandler>) {
+        self.handlers.insert(handler.job_type(), handler);
+    }
+
+    /// Claims one job that is ready to run at `now`, if there is one, and runs it until it
+    /// succeeds or fails. Returns false if there was no job to run.
+    pub async fn run_next_job(
+        &self,
+        dynamodb_client: &DynamoDbClient,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> anyhow::Result<bool> {
+        let (job, lease_id) = match self.claim_next_job(dynamodb_client, now).await? {
+            Some(claimed) => claimed,
+            None => return Ok(false),
+        };
+        let handler = &self.handlers[job.job_type.as_str()];
+        let run = handler.run(dynamodb_client, &job);
+        let renew_lease = async {
+            loop {
+                tokio::time::delay_for(JOB_LEASE_RENEW_INTERVAL).await;
+                let lease_expires_at =
+                    chrono::Utc::now() + chrono::Duration::seconds(JOB_LEASE_SECONDS);
+                let result = update_claimed_job(
+                    dynamodb_client,
+                    &job.job_id,
+                    &lease_id,
+                    &[av_s(
+                        ":next_run_at",
+                        &time::date_time_iso_str(&lease_expires_at),
+                    )],
+                )
+                .await;
+                if let Err(e) = result {
+                    log_job_error(&job, &e);
+                }
+            }
+        };
+        let result = match future::select(run, Box::pin(renew_lease)).await {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => unreachable!("renewing the lease never finishes"),
+        };
+        let attributes = match result {
+            Ok(()) => vec![av_n(":job_status", JobStatus::Complete as i32)],
+            Err(e) => {
+                log_job_error(&job, &e);
+                let last_error = av_s(":last_error", &e.to_string());
+                if job.attempts >= MAX_JOB_ATTEMPTS {
+                    vec![av_n(":job_status", JobStatus::Failed as i32), last_error]
+                } else {
+                    let next_run_at = chrono::Utc::now() + retry_delay(job.attempts);
+                    vec![
+                        av_n(":job_status", JobStatus::Pending as i32),
+                        av_s(":next_run_at", &time::date_time_iso_str(&next_run_at)),
+                        last_error,
+                    ]
+                }
+            }
+        };
+        update_claimed_job(dynamodb_client, &job.job_id, &lease_id, &attributes).await?;
+        Ok(true)
+    }
+
+    /// Claims a pending job whose next run is due, or a running job whose lease has expired.
+    /// Returns the job along with the id of the worker's lease on it.
+    async fn claim_next_job(
+        &self,
+        dynamodb_client: &DynamoDbClient,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> anyhow::Result<Option<(Job, String)>> {
+        if self.handlers.is_empty() {
+            return Ok(None);
+        }
+        let mut job_type_placeholders = Vec::new();
+        let mut values = vec![av_s(":now", &time::date_time_iso_str(&now))];
+        for (i, job_type) in self.handlers.keys().enumerate() {
+            let placeholder = format!(":job_type{}", i);
+            values.push(av_s(&placeholder, job_type));
+            job_type_placeholders.push(placeholder);
+        }
+        for job_status in &[JobStatus::Pending, JobStatus::Running] {
+            let mut values = av_map(&values);
+            let (key, value) = av_n(":job_status", *job_status as i32);
+            values.insert(key, value);
+            let output = dynamodb_client
+                .query(QueryInput {
+                    table_name: table_name("jobs"),
+                    index_name: Some(String::from("job_status-next_run_at-index")),
+                    key_condition_expression: Some(String::from(
+                        "job_status = :job_status AND next_run_at <= :now",
+                    )),
+                    filter_expression: Some(format!(
+                        "job_type IN ({})",
+                        job_type_placeholders.join(", ")
+                    )),
+                    expression_attribute_values: Some(values),
+                    limit: Some(JOB_CLAIM_CANDIDATES),
+                    ..Default::default()
+                })
+                .await?;
+            for item in output.items.unwrap_or_default() {
+                let job = job_from_item(item)?;
+                if let Some(lease_id) = try_claim_job(dynamodb_client, &job, now).await? {
+                    // Read the job again, since the index may hold a stale copy of it.
+                    if let Some(job) = get_job(dynamodb_client, &job.job_id).await? {
+                        return Ok(Some((job, lease_id)));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Sets the job to `Running` under a new lease, unless another worker changed the job since we
+/// read it. Returns the lease id if we claimed the job.
+async fn try_claim_job(
+    dynamodb_client: &DynamoDbClient,
+    job: &Job,
+    now: chrono::DateTime<chrono::Utc>,
+) -> anyhow::Result<Option<String>> {
+    let lease_id = Id::new(IdType::LockLease);
+    let lease_expires_at = now + chrono::Duration::seconds(JOB_LEASE_SECONDS);
+    let input = UpdateItemInput {
+        table_name: table_name("jobs"),
+        key: av_map(&[av_s("job_id", &job.job_id)]),
+        update_expression: Some(String::from(
+            "SET job_status = :running, lease_id = :lease_id, next_run_at = :lease_expires_at, \
+            updated_at = :now ADD attempts :one",
+        )),
+        condition_expression: Some(String::from(
+            "job_status = :seen_job_status AND next_run_at = :seen_next_run_at",
+        )),
+        expression_attribute_values: Some(av_map(&[
+            av_n(":running", JobStatus::Running as i32),
+            av_s(":lease_id", lease_id.as_str()),
+            av_s(
+                ":lease_expires_at",
+                &time::date_time_iso_str(&lease_expires_at),
+            ),
+            av_s(":now", &time::date_time_iso_str(&now)),
+            av_n(":one", 1),
+            av_n(":seen_job_status", job.job_status as i32),
+            av_s(
+                ":seen_next_run_at",
+                av_get_s(&job.item, "next_run_at").unwrap_or(""),
+            ),
+        ])),
+        ..Default::default()
+    };
+    match dynamodb_client.update_item(input).await {
+        Ok(_) => Ok(Some(lease_id.as_str().to_string())),
+        Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Like `update_job`, but only if the worker still holds its lease on the job.
+async fn update_claimed_job(
+    dynamodb_client: &DynamoDbClient,
+    job_id: &str,
+    lease_id: &str,
+    attributes: &[(String, AttributeValue)],
+) -> anyhow::Result<()> {
+    let mut input = update_job_input(job_id, attributes);
+    input.condition_expression = Some(String::from("lease_id = :lease_id"));
+    if let Some(values) = input.expression_attribute_values.as_mut() {
+        let (key, value) = av_s(":lease_id", lease_id);
+        values.insert(key, value);
+    }
+    match dynamodb_client.update_item(input).await {
+        Ok(_) => Ok(()),
+        Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => Err(
+            anyhow::anyhow!("lost the lease on job {} to another worker", job_id),
+        ),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn log_job_error(job: &Job, error: &anyhow::Error) {
+    log::error!(
+        "Error occurred: \"{}\" [run_next_job] [job_id: {}, job_type: {}, attempts: {}]",
+        error,
+        &job.job_id,
+        &job.job_type,
+        job.attempts,
+    );
+}
+
+/// Runs `num_tasks` workers forever. Each runs ready jobs one after another, and waits
+/// `JOB_POLL_INTERVAL` whenever there are none. Errors are logged and do not stop the workers.
+pub fn spawn_job_workers(
+    dynamodb_client: Arc<DynamoDbClient>,
+    worker: Arc<JobWorker>,
+    num_tasks: usize,
+) {
+    for _ in 0..num_tasks {
+        let dynamodb_client = dynamodb_client.clone();
+        let worker = worker.clone();
+        actix_web::rt::spawn(async move {
+            loop {
+                match worker
+                    .run_next_job(&dynamodb_client, chrono::Utc::now())
+                    .await
+                {
+                    Ok(true) => {}
+                    Ok(false) => tokio::time::delay_for(JOB_POLL_INTERVAL).await,
+                    Err(e) => {
+                        log::error!("Error occurred: \"{}\" [spawn_job_workers]", e);
+                        tokio::time::delay_for(JOB_POLL_INTERVAL).await;
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::testing::utils::TestDynamoDb;
+    use crate::users::UserRole;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    /// Fails the first time it runs each job, and succeeds after that.
+    #[derive(Default)]
+    struct FlakyJobHandler {
+        runs: AtomicUsize,
+    }
+
+    impl JobHandler for FlakyJobHandler {
+        fn job_type(&self) -> &'static str {
+            "flaky"
+        }
+
+        fn run<'a>(
+            &'a self,
+            dynamodb_client: &'a DynamoDbClient,
+            job: &'a Job,
+        ) -> BoxFuture<'a, anyhow::Result<()>> {
+            Box::pin(async move {
+                self.runs.fetch_add(1, Ordering::SeqCst);
+                set_job_progress(dynamodb_client, &job.job_id, 1).await?;
+                if job.attempts == 1 {
+                    return Err(anyhow::anyhow!("flaked"));
+                }
+                Ok(())
+            })
+        }
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), chrono::Duration::seconds(30));
+        assert_eq!(retry_delay(2), chrono::Duration::seconds(60));
+        assert_eq!(retry_delay(4), chrono::Duration::seconds(240));
+        assert_eq!(retry_delay(100), chrono::Duration::seconds(30 * 1024));
+    }
+
+    #[tokio::test]
+    async fn test_run_next_job() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let handler = Arc::new(FlakyJobHandler::default());
+        let mut worker = JobWorker::default();
+        worker.register(handler.clone());
+
+        let session_user = SessionUser {
+            user_id: Id::new(IdType::User),
+            org_id: Id::new(IdType::Organization),
+            user_role: UserRole::Default,
+        };
+        let job_id = create_job(&db.dynamodb_client, "flaky", &session_user, &[]).await?;
+        // Jobs without a registered handler are left alone.
+        let other_job_id = create_job(&db.dynamodb_client, "unknown", &session_user, &[]).await?;
+
+        // The first attempt fails, and schedules a retry.
+        let now = chrono::Utc::now();
+        assert!(worker.run_next_job(&db.dynamodb_client, now).await?);
+        let job = get_job(&db.dynamodb_client, job_id.as_str())
+            .await?
+            .unwrap();
+        assert_eq!(job.job_status, JobStatus::Pending);
+        assert_eq!(job.attempts, 1);
+        assert_eq!(av_get_s(&job.item, "last_error"), Some("flaked"));
+
+        // The retry is not due yet.
+        assert!(!worker.run_next_job(&db.dynamodb_client, now).await?);
+
+        let later = now + chrono::Duration::hours(1);
+        assert!(worker.run_next_job(&db.dynamodb_client, later).await?);
+        let job = get_job(&db.dynamodb_client, job_id.as_str())
+            .await?
+            .unwrap();
+        assert_eq!(job.job_status, JobStatus::Complete);
+        assert_eq!(job.attempts, 2);
+        assert_eq!(job.progress_done, 1);
+        assert_eq!(handler.runs.load(Ordering::SeqCst), 2);
+
+        assert!(!worker.run_next_job(&db.dynamodb_client, later).await?);
+        let other_job = get_job(&db.dynamodb_client, other_job_id.as_str())
+            .await?
+            .unwrap();
+        assert_eq!(other_job.job_status, JobStatus::Pending);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_claim_job_once() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let mut worker = JobWorker::default();
+        worker.register(Arc::new(FlakyJobHandler::default()));
+        let session_user = SessionUser {
+            user_id: Id::new(IdType::User),
+            org_id: Id::new(IdType::Organization),
+            user_role: UserRole::Default,
+        };
+        create_job(&db.dynamodb_client, "flaky", &session_user, &[]).await?;
+
+        // Once claimed, a job cannot be claimed again until its lease expires.
+        let now = chrono::Utc::now();
+        let (job, _) = worker
+            .claim_next_job(&db.dynamodb_client, now)
+            .await?
+            .unwrap();
+        assert_eq!(job.job_status, JobStatus::Running);
+        assert!(worker
+            .claim_next_job(&db.dynamodb_client, now)
+            .await?
+            .is_none());
+        let lease_expired = now + chrono::Duration::seconds(JOB_LEASE_SECONDS + 1);
+        let (job, _) = worker
+            .claim_next_job(&db.dynamodb_client, lease_expired)
+            .await?
+            .unwrap();
+        assert_eq!(job.attempts, 2);
+
+        Ok(())
+    }
+}
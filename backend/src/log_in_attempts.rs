@@ -0,0 +1,260 @@
+//! Account lockout after repeated failed log ins.
+//!
+//! Each user item counts the failed log ins since the last successful one in
+//! `failed_log_in_attempts`. After `MAX_FAILED_LOG_IN_ATTEMPTS` failures in a row, the account is
+//! locked until `lockout_until`, and we email the user a link that unlocks it right away.
+//!
+//! Log ins with emails that have no account are limited per server to the same number of attempts
+//! (see `BackendService::log_in_rate_limiter`), so that being locked out does not reveal whether an
+//! email has an account.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{AttributeValue, QueryInput, UpdateItemError, UpdateItemInput};
+
+use crate::dynamodb::{av_get_n, av_get_s, av_map, av_n, av_s, table_name, DynamoDbClient};
+use crate::mailer::{Email, Mailer};
+use crate::rate_limiter::RateLimiter;
+use crate::utils::time;
+
+/// An account is locked after this many failed log ins in a row.
+pub const MAX_FAILED_LOG_IN_ATTEMPTS: i64 = 5;
+
+/// How long a locked account stays locked, unless the user follows the unlock link.
+pub const LOCKOUT_DURATION: Duration = Duration::from_secs(15 * 60);
+
+/// The limiter for log ins with emails that have no account. An account locks on its
+/// `MAX_FAILED_LOG_IN_ATTEMPTS`th failure, which is refused, so the limiter admits one attempt
+/// fewer. Otherwise the extra attempt would give away which emails have accounts.
+pub fn unknown_email_rate_limiter() -> RateLimiter {
+    RateLimiter::new(MAX_FAILED_LOG_IN_ATTEMPTS as u32 - 1, LOCKOUT_DURATION)
+}
+
+/// Returns true if the user item is locked out at `now`.
+pub fn is_locked_out(
+    user_item: &HashMap<String, AttributeValue>,
+    now: &chrono::DateTime<chrono::Utc>,
+) -> bool {
+    match av_get_s(user_item, "lockout_until") {
+        Some(lockout_until) => lockout_until > time::date_time_iso_str(now).as_str(),
+        None => false,
+    }
+}
+
+/// Counts a failed log in for the user with the given email. The failure that reaches
+/// `MAX_FAILED_LOG_IN_ATTEMPTS` locks the account, and emails the user an unlock link.
+///
+/// Returns true if the account is locked.
+pub async fn record_failed_log_in(
+    dynamodb_client: &DynamoDbClient,
+    mailer: &dyn Mailer,
+    email: &str,
+    user_id: &str,
+    now: &chrono::DateTime<chrono::Utc>,
+) -> anyhow::Result<bool> {
+    let output = dynamodb_client
+        .update_item(UpdateItemInput {
+            table_name: table_name("users"),
+            key: av_map(&[av_s("email", email)]),
+            condition_expression: Some(String::from("attribute_exists(email)")),
+            update_expression: Some(String::from("ADD failed_log_in_attempts :one")),
+            expression_attribute_values: Some(av_map(&[av_n(":one", 1)])),
+            return_values: Some(String::from("UPDATED_NEW")),
+            ..Default::default()
+        })
+        .await?;
+    let failed_log_in_attempts: i64 = output
+        .attributes
+        .as_ref()
+        .and_then(|attributes| av_get_n(attributes, "failed_log_in_attempts"))
+        .unwrap_or(0);
+    // Every failure gets its own count, so exactly one of them locks the account, even when
+    // several land at once.
+    if failed_log_in_attempts < MAX_FAILED_LOG_IN_ATTEMPTS {
+        return Ok(false);
+    }
+    if failed_log_in_attempts > MAX_FAILED_LOG_IN_ATTEMPTS {
+        return Ok(true);
+    }
+
+    let lockout_until = *now + chrono::Duration::from_std(LOCKOUT_DURATION)?;
+    let unlock_token = uuid::Uuid::new_v4().to_simple().to_string();
+    dynamodb_client
+        .update_item(UpdateItemInput {
+            table_name: table_name("users"),
+            key: av_map(&[av_s("email", email)]),
+            // Start counting again once the lockout ends.
+            update_expression: Some(String::from(
+                "SET lockout_until = :lockout_until, unlock_token = :unlock_token, \
+                failed_log_in_attempts = :zero",
+            )),
+            expression_attribute_values: Some(av_map(&[
+                av_s(":lockout_until", &time::date_time_iso_str(&lockout_until)),
+                av_s(":unlock_token", &unlock_token),
+                av_n(":zero", 0),
+            ])),
+            ..Default::default()
+        })
+        .await?;
+    let email = Email {
+        to: email.to_string(),
+        subject: String::from("Your account was locked"),
+        body: format!(
+            "Someone tried to log in to your account {} times with the wrong password, so we \
+            locked it for {} minutes.\n\n\
+            If it was you, unlock your account now:\n{}\n\n\
+            If it was not you, your account is safe, but consider choosing a stronger password.\n",
+            MAX_FAILED_LOG_IN_ATTEMPTS,
+            LOCKOUT_DURATION.as_secs() / 60,
+            unlock_url(user_id, &unlock_token),
+        ),
+    };
+    // The account is locked either way. The lockout ends on its own if the email is lost.
+    if let Err(e) = mailer.send(&email).await {
+        log::error!(
+            "Error occurred: \"{}\" [record_failed_log_in] [user_id: {}]",
+            e,
+            user_id
+        );
+    }
+    Ok(true)
+}
+
+/// Starts counting failed log ins from zero again, after a successful log in.
+pub async fn reset_failed_log_ins(
+    dynamodb_client: &DynamoDbClient,
+    email: &str,
+) -> anyhow::Result<()> {
+    let input = UpdateItemInput {
+        table_name: table_name("users"),
+        key: av_map(&[av_s("email", email)]),
+        // Most log ins follow no failures, and need no write.
+        condition_expression: Some(String::from("failed_log_in_attempts > :zero")),
+        update_expression: Some(String::from("SET failed_log_in_attempts = :zero")),
+        expression_attribute_values: Some(av_map(&[av_n(":zero", 0)])),
+        ..Default::default()
+    };
+    match dynamodb_client.update_item(input).await {
+        Ok(_) | Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Unlocks the user's account, if `unlock_token` is the token from the user's latest lockout
+/// email. Returns false if it is not.
+pub async fn unlock_account(
+    dynamodb_client: &DynamoDbClient,
+    user_id: &str,
+    unlock_token: &str,
+) -> anyhow::Result<bool> {
+    let output = dynamodb_client
+        .query(QueryInput {
+            table_name: table_name("users"),
+            index_name: Some(String::from("id-index")),
+            key_condition_expression: Some(String::from("id = :id")),
+            expression_attribute_values: Some(av_map(&[av_s(":id", user_id)])),
+            projection_expression: Some(String::from("email")),
+            ..Default::default()
+        })
+        .await?;
+    let items = output.items.unwrap_or_default();
+    let email = match items.first().and_then(|item| av_get_s(item, "email")) {
+        Some(email) => email,
+        None => return Ok(false),
+    };
+    let input = UpdateItemInput {
+        table_name: table_name("users"),
+        key: av_map(&[av_s("email", email)]),
+        condition_expression: Some(String::from("unlock_token = :unlock_token")),
+        update_expression: Some(String::from(
+            "REMOVE lockout_until, unlock_token SET failed_log_in_attempts = :zero",
+        )),
+        expression_attribute_values: Some(av_map(&[
+            av_s(":unlock_token", unlock_token),
+            av_n(":zero", 0),
+        ])),
+        ..Default::default()
+    };
+    match dynamodb_client.update_item(input).await {
+        Ok(_) => Ok(true),
+        Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn unlock_url(user_id: &str, unlock_token: &str) -> String {
+    // TODO(cliff): Stop hard-coding the development server URL.
+    format!(
+        "http://localhost:8080/unlock_account/{}/{}",
+        user_id, unlock_token
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rusoto_dynamodb::GetItemInput;
+
+    use crate::testing::fixtures::create_user;
+    use crate::testing::utils::{RecordingMailer, TestDynamoDb};
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[tokio::test]
+    async fn test_lockout_and_unlock() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let mailer = RecordingMailer::default();
+
+        let email = "locked@example.com";
+        let user_id = create_user(&db.dynamodb_client, email, "Locked").await;
+        let now = chrono::Utc::now();
+        for _ in 1..MAX_FAILED_LOG_IN_ATTEMPTS {
+            let locked =
+                record_failed_log_in(&db.dynamodb_client, &mailer, email, user_id.as_str(), &now)
+                    .await?;
+            assert!(!locked);
+        }
+        assert!(mailer.sent.lock().unwrap().is_empty());
+        let locked =
+            record_failed_log_in(&db.dynamodb_client, &mailer, email, user_id.as_str(), &now)
+                .await?;
+        assert!(locked);
+
+        let user_item = db
+            .dynamodb_client
+            .get_item(GetItemInput {
+                table_name: table_name("users"),
+                key: av_map(&[av_s("email", email)]),
+                ..Default::default()
+            })
+            .await?
+            .item
+            .unwrap();
+        assert!(is_locked_out(&user_item, &now));
+        assert!(!is_locked_out(
+            &user_item,
+            &(now + chrono::Duration::minutes(16))
+        ));
+
+        // The user is emailed a link to unlock the account.
+        let sent = mailer.sent.lock().unwrap().clone();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, email);
+        let prefix = format!("http://localhost:8080/unlock_account/{}/", user_id.as_str());
+        let unlock_token = sent[0]
+            .body
+            .lines()
+            .find_map(|line| line.strip_prefix(prefix.as_str()))
+            .unwrap()
+            .to_string();
+        assert!(!unlock_account(&db.dynamodb_client, user_id.as_str(), "wrong").await?);
+        assert!(unlock_account(&db.dynamodb_client, user_id.as_str(), &unlock_token).await?);
+        // The link only works once.
+        assert!(!unlock_account(&db.dynamodb_client, user_id.as_str(), &unlock_token).await?);
+
+        Ok(())
+    }
+}
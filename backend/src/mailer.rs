@@ -0,0 +1,38 @@
+//! Outbound email.
+//!
+//! Code that sends email depends on the `Mailer` trait rather than on a particular email provider,
+//! so that tests can capture sent messages and local development does not send real email.
+
+use futures::future::BoxFuture;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+pub trait Mailer: Send + Sync {
+    /// Sends the email. Returns an error if the email could not be handed off for delivery.
+    fn send<'a>(&'a self, email: &'a Email) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+/// Writes emails to the log instead of sending them. Used for local development.
+///
+/// TODO(cliff): Add a mailer backed by a real email provider before we turn on digests in
+/// production.
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send<'a>(&'a self, email: &'a Email) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            log::info!(
+                "Sending email [to: {}, subject: {}]\n{}",
+                &email.to,
+                &email.subject,
+                &email.body
+            );
+            Ok(())
+        })
+    }
+}
@@ -1,58 +1,388 @@
+mod accounts;
+mod admin;
+mod archived_documents;
+mod attachments;
+mod automation;
+mod avatars;
+mod blob_store;
 mod config;
+mod document_events;
+mod document_stats;
+mod document_views;
 mod documents;
 mod dynamodb;
+mod exports;
+mod feature_flags;
+mod forks;
 mod http;
+mod identity_providers;
 mod ids;
+mod jobs;
+mod log_in_attempts;
+mod mailer;
+mod notifications;
+mod org_revisions;
+mod permission_cache;
+mod protected_ranges;
+mod purged_documents;
+mod rate_limiter;
+mod read_receipts;
+mod region_locks;
+mod revision_signatures;
+mod revision_stream;
+mod share_tokens;
+mod sharing;
+mod starred_documents;
+mod sync_metrics;
+mod two_factor;
 mod users;
 mod utils;
 
 #[cfg(test)]
 mod testing;
 
+use actix_web::dev::Service;
 use actix_web::middleware::Logger;
-use actix_web::{App, HttpServer};
-use rusoto_dynamodb::DynamoDbClient;
+use actix_web::{web, App, HttpServer};
+use futures::future::{self, Either};
+use futures::TryFutureExt;
+use rusoto_dynamodbstreams::DynamoDbStreamsClient;
 use std::sync::Arc;
 
+use accounts::AccountDeletionJobHandler;
+use avatars::AvatarResizeJobHandler;
+use blob_store::{BlobStore, S3BlobStore};
 use config::config;
+use document_events::DocumentEvents;
+use documents::UpdatedAtStreamHandler;
+use dynamodb::DynamoDbClient;
+use exports::OrgExportJobHandler;
+use feature_flags::{FeatureFlag, FeatureFlagCache};
+use identity_providers::{GoogleIdentityProvider, IdentityProviders};
+use jobs::JobWorker;
+use mailer::{LogMailer, Mailer};
+use notifications::EditNotificationStreamHandler;
+use permission_cache::PermissionCache;
+use purged_documents::DocumentPurgeJobHandler;
+use rate_limiter::RateLimiter;
+use region_locks::RegionLocks;
+use revision_stream::RevisionStreamProcessor;
+use sync_metrics::SyncMetrics;
 
 pub struct BackendService {
     pub dynamodb_client: Arc<DynamoDbClient>,
+    pub permission_cache: Arc<PermissionCache>,
+    pub guest_rate_limiter: Arc<RateLimiter>,
+    pub edit_rate_limiter: Arc<RateLimiter>,
+    pub typing_rate_limiter: Arc<RateLimiter>,
+    pub export_store: Arc<dyn BlobStore>,
+    pub upload_store: Arc<dyn BlobStore>,
+    pub photo_store: Arc<dyn BlobStore>,
+    pub attachment_store: Arc<dyn BlobStore>,
+    pub document_events: Arc<DocumentEvents>,
+    pub region_locks: Arc<RegionLocks>,
+    pub sync_metrics: Arc<SyncMetrics>,
+    pub feature_flag_cache: Arc<FeatureFlagCache>,
+    /// Whether the revision stream processor maintains data derived from revisions, so that
+    /// requests need not.
+    pub derived_data_from_stream: bool,
+    pub mailer: Arc<dyn Mailer>,
+    /// Limits log ins with emails that have no account. See `log_in_attempts`.
+    pub log_in_rate_limiter: Arc<RateLimiter>,
+    pub identity_providers: Arc<IdentityProviders>,
 }
 
 #[actix_web::main]
 async fn main() -> anyhow::Result<()> {
-    simple_logger::SimpleLogger::new()
-        .with_level(log::LevelFilter::Info)
-        .init()
-        .unwrap();
+    let logger = simple_logger::SimpleLogger::new().with_level(log::LevelFilter::Info);
+    log::set_boxed_logger(Box::new(http::trace::TracingLogger::new(logger))).unwrap();
+    log::set_max_level(log::LevelFilter::Info);
 
     let dynamodb_client = Arc::new(DynamoDbClient::new(config().dynamodb_region.clone()));
 
+    if config().clean_up_orphaned_users {
+        let created_before =
+            chrono::Utc::now() - chrono::Duration::seconds(accounts::ORPHANED_USER_MIN_AGE_SECONDS);
+        let num_deleted =
+            accounts::delete_orphaned_users(&dynamodb_client, &created_before).await?;
+        log::info!("Deleted {} orphaned users", num_deleted);
+        return Ok(());
+    }
+
+    if let Some(setting) = &config().set_global_feature_flag {
+        let (name, value) = setting.split_at(setting.find('=').unwrap_or(setting.len()));
+        let flag = FeatureFlag::from_name(name)
+            .ok_or_else(|| anyhow::anyhow!("Unknown feature flag: {}", name))?;
+        let enabled = match value {
+            "=true" => Some(true),
+            "=false" => Some(false),
+            "=default" => None,
+            _ => anyhow::bail!("Expected NAME=true, NAME=false or NAME=default"),
+        };
+        feature_flags::set_feature_flag(
+            &dynamodb_client,
+            &FeatureFlagCache::default(),
+            feature_flags::GLOBAL_ORG_ID,
+            flag,
+            enabled,
+            "operator",
+        )
+        .await?;
+        log::info!("Set the global default of {} to {:?}", name, enabled);
+        return Ok(());
+    }
+
+    actix_web::rt::spawn(dynamodb::run_capacity_metrics_reporter(
+        dynamodb_client.capacity_metrics().clone(),
+    ));
+
+    if config().stream_processor {
+        let mut processor = RevisionStreamProcessor::default();
+        processor.register(Arc::new(EditNotificationStreamHandler));
+        processor.register(Arc::new(UpdatedAtStreamHandler));
+        revision_stream::run_revision_stream_processor(
+            dynamodb_client,
+            Arc::new(DynamoDbStreamsClient::new(config().dynamodb_region.clone())),
+            Arc::new(processor),
+        )
+        .await;
+        return Ok(());
+    }
+    let permission_cache = Arc::new(PermissionCache::default());
+    let guest_rate_limiter = Arc::new(RateLimiter::default());
+    let edit_rate_limiter = Arc::new(RateLimiter::new(
+        rate_limiter::EDIT_MAX_REVISIONS_PER_WINDOW,
+        rate_limiter::EDIT_RATE_LIMIT_WINDOW,
+    ));
+    let typing_rate_limiter = Arc::new(RateLimiter::new(
+        rate_limiter::TYPING_MAX_HEARTBEATS_PER_WINDOW,
+        rate_limiter::TYPING_RATE_LIMIT_WINDOW,
+    ));
+    let document_events = Arc::new(DocumentEvents::default());
+    let region_locks = Arc::new(RegionLocks::default());
+    let sync_metrics = Arc::new(SyncMetrics::default());
+    let feature_flag_cache = Arc::new(FeatureFlagCache::default());
+    let mailer: Arc<dyn Mailer> = Arc::new(LogMailer);
+    let log_in_rate_limiter = Arc::new(log_in_attempts::unknown_email_rate_limiter());
+    let mut identity_providers = IdentityProviders::default();
+    if let (Some(client_id), Some(client_secret)) =
+        (&config().google_client_id, &config().google_client_secret)
+    {
+        identity_providers.register(Arc::new(GoogleIdentityProvider::new(
+            client_id,
+            client_secret,
+        )));
+    }
+    let identity_providers = Arc::new(identity_providers);
+    let export_store: Arc<dyn BlobStore> = Arc::new(S3BlobStore::new(
+        config().s3_region.clone(),
+        &config().export_s3_bucket,
+    )?);
+    let upload_store: Arc<dyn BlobStore> = Arc::new(S3BlobStore::new(
+        config().s3_region.clone(),
+        &config().upload_s3_bucket,
+    )?);
+    let photo_store: Arc<dyn BlobStore> = Arc::new(S3BlobStore::new(
+        config().s3_region.clone(),
+        &config().photo_s3_bucket,
+    )?);
+    let attachment_store: Arc<dyn BlobStore> = Arc::new(S3BlobStore::new(
+        config().s3_region.clone(),
+        &config().attachment_s3_bucket,
+    )?);
+
+    if config().send_notification_digests {
+        actix_web::rt::spawn(notifications::run_digest_sender(
+            dynamodb_client.clone(),
+            mailer.clone(),
+        ));
+    }
+
+    actix_web::rt::spawn(sync_metrics::run_sync_metrics_reporter(
+        sync_metrics.clone(),
+    ));
+
+    let mut job_worker = JobWorker::default();
+    job_worker.register(Arc::new(OrgExportJobHandler {
+        export_store: export_store.clone(),
+    }));
+    job_worker.register(Arc::new(AccountDeletionJobHandler {
+        upload_store: upload_store.clone(),
+        photo_store: photo_store.clone(),
+    }));
+    job_worker.register(Arc::new(AvatarResizeJobHandler {
+        upload_store: upload_store.clone(),
+        photo_store: photo_store.clone(),
+    }));
+    job_worker.register(Arc::new(DocumentPurgeJobHandler {
+        attachment_store: attachment_store.clone(),
+    }));
+    jobs::spawn_job_workers(
+        dynamodb_client.clone(),
+        Arc::new(job_worker),
+        config().job_worker_tasks,
+    );
+
+    let request_body_limits = http::RequestBodyLimits {
+        default: config().max_request_body_size,
+        document_text: config().max_document_request_body_size,
+    };
+
     HttpServer::new(move || {
         App::new()
+            .app_data(web::PayloadConfig::new(request_body_limits.max()))
             .data(BackendService {
                 dynamodb_client: dynamodb_client.clone(),
+                permission_cache: permission_cache.clone(),
+                guest_rate_limiter: guest_rate_limiter.clone(),
+                edit_rate_limiter: edit_rate_limiter.clone(),
+                typing_rate_limiter: typing_rate_limiter.clone(),
+                export_store: export_store.clone(),
+                upload_store: upload_store.clone(),
+                photo_store: photo_store.clone(),
+                attachment_store: attachment_store.clone(),
+                document_events: document_events.clone(),
+                region_locks: region_locks.clone(),
+                sync_metrics: sync_metrics.clone(),
+                feature_flag_cache: feature_flag_cache.clone(),
+                derived_data_from_stream: config().derived_data_from_stream,
+                mailer: mailer.clone(),
+                log_in_rate_limiter: log_in_rate_limiter.clone(),
+                identity_providers: identity_providers.clone(),
+            })
+            .wrap_fn(|req, srv| {
+                // Attribute DynamoDB capacity to the route rather than the path, so that ids and
+                // tokens in paths neither split the metrics nor end up in them.
+                let endpoint = req
+                    .request()
+                    .match_pattern()
+                    .unwrap_or_else(|| String::from("unmatched"));
+                dynamodb::with_endpoint(endpoint, srv.call(req))
+            })
+            .wrap_fn(|req, srv| {
+                let trace_id = http::trace::request_trace_id(&req);
+                let response = srv.call(req).map_ok({
+                    let trace_id = trace_id.clone();
+                    move |mut res| {
+                        http::trace::set_trace_id_header(&mut res, &trace_id);
+                        res
+                    }
+                });
+                http::trace::with_trace_id(trace_id, response)
+            })
+            .wrap_fn(|req, srv| match http::check_protobuf_content_type(&req) {
+                Ok(()) => Either::Left(srv.call(req)),
+                Err(e) => Either::Right(future::ready(Err(e))),
+            })
+            .wrap_fn(|req, srv| match http::check_client_protocol_version(&req) {
+                Ok(()) => Either::Left(srv.call(req)),
+                Err(e) => Either::Right(future::ready(Err(e))),
+            })
+            .wrap_fn(move |req, srv| {
+                match http::check_request_body_size(&req, &request_body_limits) {
+                    Ok(()) => Either::Left(srv.call(req)),
+                    Err(e) => Either::Right(future::ready(Err(e))),
+                }
+            })
+            .wrap(Logger::new(http::trace::ACCESS_LOG_FORMAT))
+            .wrap(http::configure_cors(
+                &config().cors_allowed_origins,
+                config().cors_allow_credentials,
+            ))
+            .wrap_fn(|req, srv| {
+                srv.call(req).map_ok(|res| {
+                    http::record_session_cookie_lifetime(&res);
+                    res
+                })
             })
-            .wrap(Logger::default())
-            .wrap(http::configure_cors())
             .wrap(http::create_cookie_session(
                 config().cookie_secret.as_bytes(),
                 config().cookie_secure,
             ))
+            .wrap_fn(|req, srv| {
+                srv.call(req).map_ok(|mut res| {
+                    http::apply_session_cookie_lifetime(&mut res);
+                    res
+                })
+            })
+            .service(http::api::admin::get_document_health)
+            .service(http::api::admin::get_dynamodb_capacity)
+            .service(http::api::admin::get_sync_metrics)
+            .service(http::api::admin::list_feature_flags)
+            .service(http::api::admin::list_region_locks)
+            .service(http::api::admin::set_feature_flag)
+            .service(http::api::documents::append_to_document)
+            .service(http::api::documents::archive_document)
+            .service(http::api::documents::collect_attachments)
+            .service(http::api::documents::confirm_attachment_upload)
+            .service(http::api::documents::create_attachment)
             .service(http::api::documents::create_document)
+            .service(http::api::documents::debug_decode_revision)
+            .service(http::api::documents::diagnose_document_revisions)
+            .service(http::api::documents::events)
+            .service(http::api::documents::follow_document)
+            .service(http::api::documents::fork_document)
+            .service(http::api::documents::get_attachment_url)
             .service(http::api::documents::get_document)
+            .service(http::api::documents::get_document_excerpt)
+            .service(http::api::documents::get_document_head)
             .service(http::api::documents::get_document_revisions)
+            .service(http::api::documents::get_my_permissions)
+            .service(http::api::documents::get_protected_ranges)
+            .service(http::api::documents::list_archived)
+            .service(http::api::documents::list_attachments)
             .service(http::api::documents::list_my_documents)
+            .service(http::api::documents::list_read_receipts)
+            .service(http::api::documents::list_recently_viewed)
+            .service(http::api::documents::list_starred)
+            .service(http::api::documents::merge_fork)
+            .service(http::api::documents::purge_document)
+            .service(http::api::documents::replace_pattern)
+            .service(http::api::documents::report_read_position)
+            .service(http::api::documents::search_document_titles)
+            .service(http::api::documents::send_typing)
+            .service(http::api::documents::set_protected_ranges)
+            .service(http::api::documents::star_document)
             .service(http::api::documents::submit_document_change_set)
+            .service(http::api::documents::transfer_ownership)
+            .service(http::api::documents::unarchive_document)
+            .service(http::api::documents::unfollow_document)
+            .service(http::api::documents::unstar_document)
+            .service(http::api::documents::update_document_stats)
             .service(http::api::documents::update_document_title)
+            .service(http::api::documents::verify_document_revisions)
+            .service(http::api::orgs::get_org_export)
+            .service(http::api::orgs::list_org_revisions)
+            .service(http::api::orgs::set_two_factor_required)
+            .service(http::api::orgs::start_org_export)
+            .service(http::api::server::get_capabilities)
+            .service(http::api::share_tokens::create_share_token)
+            .service(http::api::share_tokens::revoke_share_token)
+            .service(http::api::sharing::share_document)
+            .service(http::api::sharing::share_documents)
+            .service(http::api::signing_keys::register_signing_key)
+            .service(http::api::users::confirm_avatar_upload)
+            .service(http::api::users::delete_account)
+            .service(http::api::users::disable_two_factor)
+            .service(http::api::users::enable_two_factor)
+            .service(http::api::users::get_my_profile)
+            .service(http::api::users::provision_two_factor)
+            .service(http::api::users::request_avatar_upload)
+            .service(http::api::users::update_my_profile)
             .service(http::app::home)
             .service(http::marketing::home)
+            .service(http::sessions::finish_external_log_in)
+            .service(http::sessions::get_join)
             .service(http::sessions::get_log_in)
             .service(http::sessions::get_sign_up)
+            .service(http::sessions::get_two_factor)
+            .service(http::sessions::start_external_log_in)
+            .service(http::sessions::submit_join)
             .service(http::sessions::submit_log_in)
             .service(http::sessions::submit_log_out)
             .service(http::sessions::submit_sign_up)
+            .service(http::sessions::submit_two_factor)
+            .service(http::sessions::unlock_account)
     })
     .bind(format!("localhost:{}", &config().http_port))?
     .run()
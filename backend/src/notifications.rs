@@ -0,0 +1,771 @@
+//! Email notifications for followed documents.
+//!
+//! Users follow the documents they care about. When a followed document is edited, every follower
+//! other than the editor gets a pending notification for it. Further edits update the same pending
+//! notification, so a burst of typing adds up to one notification per document per follower.
+//!
+//! The digest sender periodically collects the pending notifications whose documents have gone
+//! quiet, and sends each follower a single email that covers all of them, with a summary of what
+//! changed in each document.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::error;
+use futures::future::BoxFuture;
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{
+    AttributeValue, DeleteItemError, DeleteItemInput, PutItemInput, QueryInput, ScanInput,
+    UpdateItemError, UpdateItemInput,
+};
+
+use ot::writing_proto::{
+    Document, DocumentSharingPermission, FollowDocumentRequest, FollowDocumentResponse,
+    UnfollowDocumentRequest, UnfollowDocumentResponse,
+};
+use ot::ChangeSummary;
+
+use crate::documents;
+use crate::dynamodb::{av_get_n, av_get_s, av_map, av_n, av_s, table_name, DynamoDbClient};
+use crate::http::SessionUser;
+use crate::ids::{Id, IdType};
+use crate::mailer::{Email, Mailer};
+use crate::revision_stream::{CommittedRevision, RevisionStreamHandler};
+use crate::users::UserRole;
+use crate::utils::time;
+
+/// A pending notification is sent once its document has gone this long without an edit, so that
+/// one digest covers a whole editing session.
+pub const DIGEST_QUIET_PERIOD_MINUTES: i64 = 15;
+
+/// A document that never goes quiet still shows up in a digest once its oldest unsent edit is this
+/// old.
+pub const DIGEST_MAX_DELAY_MINUTES: i64 = 60;
+
+/// How often the digest sender looks for pending notifications that are ready to send.
+pub const DIGEST_SEND_INTERVAL: Duration = Duration::from_secs(60);
+
+const VIEW_PERMISSIONS: [DocumentSharingPermission; 3] = [
+    DocumentSharingPermission::CanView,
+    DocumentSharingPermission::CanComment,
+    DocumentSharingPermission::CanEdit,
+];
+
+/// Follow a document, so that the session user gets digest emails when others edit it. Following
+/// a document twice has no further effect.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If the session user cannot view the document, returns 403 Forbidden.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn follow_document(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &FollowDocumentRequest,
+) -> actix_web::Result<FollowDocumentResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [follow_document] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    documents::get_document_if_some_permission_valid(
+        dynamodb_client,
+        session_user,
+        &request.doc_id,
+        &VIEW_PERMISSIONS,
+    )
+    .await?;
+    let input = PutItemInput {
+        table_name: table_name("document_followers"),
+        item: av_map(&[
+            av_s("doc_id", &request.doc_id),
+            av_s("user_id", session_user.user_id.as_str()),
+            av_s("org_id", session_user.org_id.as_str()),
+            av_s("created_at", &time::date_time_iso_str(&chrono::Utc::now())),
+        ]),
+        ..Default::default()
+    };
+    dynamodb_client.put_item(input).await.map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    Ok(FollowDocumentResponse {})
+}
+
+/// Unfollow a document, and drop any notification about it that has not been sent yet.
+/// Unfollowing a document that the session user does not follow has no effect.
+///
+/// No permission is required, so that users who have lost access to a document can still stop
+/// following it.
+///
+/// If the doc id is not a document id, returns 400 Bad Request.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn unfollow_document(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &UnfollowDocumentRequest,
+) -> actix_web::Result<UnfollowDocumentResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [unfollow_document] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    match Id::parse(&request.doc_id) {
+        Some(doc_id) if doc_id.id_type == IdType::Document => {}
+        _ => return Err(error::ErrorBadRequest("")),
+    }
+    let key = av_map(&[
+        av_s("doc_id", &request.doc_id),
+        av_s("user_id", session_user.user_id.as_str()),
+    ]);
+    for table in &["document_followers", "pending_notifications"] {
+        let input = DeleteItemInput {
+            table_name: table_name(table),
+            key: key.clone(),
+            ..Default::default()
+        };
+        dynamodb_client.delete_item(input).await.map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    }
+    Ok(UnfollowDocumentResponse {})
+}
+
+/// Records that a revision was committed to a document, adding to the pending notification of each
+/// of its followers. The editor is not notified about their own edits.
+///
+/// Called after every committed revision, so this does one write per follower and nothing else.
+/// Each pending notification remembers the last revision it counted, so recording the same
+/// revision again has no effect.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error. Notifications for some
+/// followers may have been recorded.
+pub async fn enqueue_edit_notifications(
+    dynamodb_client: &DynamoDbClient,
+    doc_id: &str,
+    revision_number: i64,
+    editor_id: &Id,
+) -> actix_web::Result<()> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [enqueue_edit_notifications] \
+            [doc_id: {}, revision_number: {}, editor_id: {:?}]",
+            error_message,
+            doc_id,
+            revision_number,
+            editor_id,
+        );
+    };
+    let now = time::date_time_iso_str(&chrono::Utc::now());
+    let mut exclusive_start_key = None;
+    loop {
+        let input = QueryInput {
+            table_name: table_name("document_followers"),
+            key_condition_expression: Some(String::from("doc_id = :doc_id")),
+            expression_attribute_values: Some(av_map(&[av_s(":doc_id", doc_id)])),
+            projection_expression: Some(String::from("user_id, org_id")),
+            exclusive_start_key,
+            ..Default::default()
+        };
+        let output = dynamodb_client.query(input).await.map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+        for item in output.items.unwrap_or_default() {
+            let (user_id, org_id) = match (av_get_s(&item, "user_id"), av_get_s(&item, "org_id")) {
+                (Some(user_id), Some(org_id)) => (user_id, org_id),
+                _ => {
+                    log_error("document follower is missing a field".to_string());
+                    continue;
+                }
+            };
+            if user_id == editor_id.as_str() {
+                continue;
+            }
+            let input = UpdateItemInput {
+                table_name: table_name("pending_notifications"),
+                key: av_map(&[av_s("user_id", user_id), av_s("doc_id", doc_id)]),
+                condition_expression: Some(String::from(
+                    "attribute_not_exists(last_revision_number) \
+                    OR last_revision_number < :revision_number",
+                )),
+                update_expression: Some(String::from(
+                    "SET org_id = :org_id, last_edited_at = :now, \
+                    first_edited_at = if_not_exists(first_edited_at, :now), \
+                    first_revision_number = \
+                    if_not_exists(first_revision_number, :revision_number), \
+                    last_revision_number = :revision_number \
+                    ADD edit_count :one",
+                )),
+                expression_attribute_values: Some(av_map(&[
+                    av_s(":org_id", org_id),
+                    av_s(":now", &now),
+                    av_n(":revision_number", revision_number),
+                    av_n(":one", 1),
+                ])),
+                ..Default::default()
+            };
+            match dynamodb_client.update_item(input).await {
+                // Already counted.
+                Ok(_) | Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => {}
+                Err(e) => {
+                    log_error(e.to_string());
+                    return Err(error::ErrorInternalServerError(""));
+                }
+            }
+        }
+        exclusive_start_key = output.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            return Ok(());
+        }
+    }
+}
+
+/// Records edit notifications for each revision in the revision stream, instead of the submit
+/// request doing it. See the `derived_data_from_stream` flag.
+pub struct EditNotificationStreamHandler;
+
+impl RevisionStreamHandler for EditNotificationStreamHandler {
+    fn name(&self) -> &'static str {
+        "edit_notifications"
+    }
+
+    fn handle<'a>(
+        &'a self,
+        dynamodb_client: &'a DynamoDbClient,
+        revision: &'a CommittedRevision,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let editor_id = match Id::parse(&revision.author_user_id) {
+                Some(editor_id) => editor_id,
+                None => {
+                    log::error!(
+                        "Error occurred: \"invalid author id\" [EditNotificationStreamHandler] \
+                        [revision: {:?}]",
+                        revision,
+                    );
+                    return Ok(());
+                }
+            };
+            enqueue_edit_notifications(
+                dynamodb_client,
+                &revision.doc_id,
+                revision.revision_number,
+                &editor_id,
+            )
+            .await
+            .map_err(|_| anyhow::anyhow!("Could not enqueue edit notifications"))
+        })
+    }
+}
+
+/// Sends a digest email to every user with pending notifications that are ready to go out, and
+/// removes the notifications that were sent. Returns the number of digests sent.
+///
+/// A notification is ready once its document has been quiet for `DIGEST_QUIET_PERIOD_MINUTES`, or
+/// once its oldest edit is `DIGEST_MAX_DELAY_MINUTES` old. Notifications about documents that the
+/// follower can no longer view are dropped without being sent.
+///
+/// A failure to send one user's digest is logged, and that user's notifications are kept for the
+/// next run. Returns 500 Internal Server Error only if pending notifications could not be read.
+pub async fn send_digests(
+    dynamodb_client: &DynamoDbClient,
+    mailer: &dyn Mailer,
+    now: chrono::DateTime<chrono::Utc>,
+) -> actix_web::Result<usize> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [send_digests] [now: {}]",
+            error_message,
+            now,
+        );
+    };
+    let quiet_since = now - chrono::Duration::minutes(DIGEST_QUIET_PERIOD_MINUTES);
+    let waiting_since = now - chrono::Duration::minutes(DIGEST_MAX_DELAY_MINUTES);
+
+    // TODO(cliff): Scanning is fine while the table is small. Once it is not, add an index on
+    // last_edited_at and query it instead.
+    let mut ready_notifications: BTreeMap<String, Vec<PendingNotification>> = BTreeMap::new();
+    let mut exclusive_start_key = None;
+    loop {
+        let input = ScanInput {
+            table_name: table_name("pending_notifications"),
+            filter_expression: Some(String::from(
+                "last_edited_at <= :quiet_since OR first_edited_at <= :waiting_since",
+            )),
+            expression_attribute_values: Some(av_map(&[
+                av_s(":quiet_since", &time::date_time_iso_str(&quiet_since)),
+                av_s(":waiting_since", &time::date_time_iso_str(&waiting_since)),
+            ])),
+            exclusive_start_key,
+            ..Default::default()
+        };
+        let output = dynamodb_client.scan(input).await.map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+        for item in output.items.unwrap_or_default() {
+            match PendingNotification::from_item(&item) {
+                Some(notification) => ready_notifications
+                    .entry(notification.user_id.clone())
+                    .or_default()
+                    .push(notification),
+                None => log_error("pending notification is missing a field".to_string()),
+            }
+        }
+        exclusive_start_key = output.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            break;
+        }
+    }
+
+    let mut num_sent = 0;
+    for (user_id, notifications) in ready_notifications.iter() {
+        match send_digest(dynamodb_client, mailer, user_id, notifications).await {
+            Ok(true) => num_sent += 1,
+            Ok(false) => {}
+            Err(e) => log_error(format!("Failed to send digest to {}: {}", user_id, e)),
+        }
+    }
+    Ok(num_sent)
+}
+
+/// Sends digests forever, every `DIGEST_SEND_INTERVAL`. Errors are logged and do not stop the
+/// loop.
+///
+/// Only one server should run this. Two senders running at once may send duplicate digests.
+pub async fn run_digest_sender(dynamodb_client: Arc<DynamoDbClient>, mailer: Arc<dyn Mailer>) {
+    let mut interval = tokio::time::interval(DIGEST_SEND_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Ok(num_sent) =
+            send_digests(&dynamodb_client, mailer.as_ref(), chrono::Utc::now()).await
+        {
+            if num_sent > 0 {
+                log::info!("Sent {} notification digests", num_sent);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PendingNotification {
+    user_id: String,
+    doc_id: String,
+    org_id: String,
+    edit_count: i64,
+    last_edited_at: String,
+    first_revision_number: i64,
+    last_revision_number: i64,
+}
+
+impl PendingNotification {
+    fn from_item(item: &HashMap<String, AttributeValue>) -> Option<Self> {
+        let last_revision_number = av_get_n(item, "last_revision_number")?;
+        Some(Self {
+            user_id: av_get_s(item, "user_id")?.to_string(),
+            doc_id: av_get_s(item, "doc_id")?.to_string(),
+            org_id: av_get_s(item, "org_id")?.to_string(),
+            edit_count: av_get_n(item, "edit_count")?,
+            last_edited_at: av_get_s(item, "last_edited_at")?.to_string(),
+            // Notifications from before first revision numbers were recorded only summarize the
+            // last revision.
+            first_revision_number: av_get_n(item, "first_revision_number")
+                .unwrap_or(last_revision_number),
+            last_revision_number,
+        })
+    }
+}
+
+/// Sends one user's digest. Returns false if there turned out to be nothing to send.
+async fn send_digest(
+    dynamodb_client: &DynamoDbClient,
+    mailer: &dyn Mailer,
+    user_id: &str,
+    notifications: &[PendingNotification],
+) -> anyhow::Result<bool> {
+    let email_address = match get_user_email(dynamodb_client, user_id).await? {
+        Some(email_address) => email_address,
+        None => {
+            // The user no longer exists. Nobody to notify.
+            for notification in notifications {
+                delete_notification(dynamodb_client, notification).await?;
+            }
+            return Ok(false);
+        }
+    };
+
+    let mut edited_documents: Vec<(&PendingNotification, Document)> = Vec::new();
+    for notification in notifications {
+        let session_user = match (Id::parse(user_id), Id::parse(&notification.org_id)) {
+            (Some(user_id), Some(org_id)) => SessionUser {
+                user_id,
+                org_id,
+                user_role: UserRole::Default,
+            },
+            _ => {
+                delete_notification(dynamodb_client, notification).await?;
+                continue;
+            }
+        };
+        let result = documents::get_document_if_some_permission_valid(
+            dynamodb_client,
+            &session_user,
+            &notification.doc_id,
+            &VIEW_PERMISSIONS,
+        )
+        .await;
+        match result {
+            Ok(document) => edited_documents.push((notification, document)),
+            Err(e) => {
+                let status_code = e.as_response_error().status_code();
+                if status_code.is_client_error() {
+                    // The document is gone, or the follower lost access to it.
+                    delete_notification(dynamodb_client, notification).await?;
+                } else {
+                    anyhow::bail!("Failed to check document permission: {}", status_code);
+                }
+            }
+        }
+    }
+    if edited_documents.is_empty() {
+        return Ok(false);
+    }
+
+    let mut summaries = Vec::with_capacity(edited_documents.len());
+    for (notification, document) in edited_documents.iter() {
+        // A summary is nice to have. The digest goes out without one if it cannot be made.
+        let summary = summarize_edits(dynamodb_client, notification, document)
+            .await
+            .unwrap_or_else(|e| {
+                log::error!(
+                    "Error occurred: \"{}\" [send_digest] [notification: {:?}]",
+                    e,
+                    notification,
+                );
+                None
+            });
+        summaries.push(summary);
+    }
+    let email = compose_digest_email(&email_address, &edited_documents, &summaries);
+    mailer.send(&email).await?;
+    for (notification, _) in edited_documents.iter() {
+        delete_notification(dynamodb_client, notification).await?;
+    }
+    Ok(true)
+}
+
+/// Summarizes what the notification's revisions changed, from the document before its first
+/// revision to the document after its last. The follower's own revisions in between are included.
+///
+/// Returns None for end-to-end encrypted documents, whose change sets the server cannot read, and
+/// if the revisions changed nothing.
+async fn summarize_edits(
+    dynamodb_client: &DynamoDbClient,
+    notification: &PendingNotification,
+    document: &Document,
+) -> anyhow::Result<Option<ChangeSummary>> {
+    if !document.encryption_key_fingerprint.is_empty() {
+        return Ok(None);
+    }
+    let (change_sets, _) = documents::get_change_sets(
+        dynamodb_client,
+        &notification.doc_id,
+        0,
+        notification.last_revision_number,
+    )
+    .await?;
+    let split = ((notification.first_revision_number - 1).max(0) as usize).min(change_sets.len());
+    let (before, edits) = change_sets.split_at(split);
+    let document_before = ot::apply_slice(&[], &ot::compose_iter(before)?)?;
+    let summary = ot::summarize(&ot::compose_iter(edits)?, &document_before)?;
+    if summary.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(summary))
+}
+
+fn compose_digest_email(
+    email_address: &str,
+    edited_documents: &[(&PendingNotification, Document)],
+    summaries: &[Option<ChangeSummary>],
+) -> Email {
+    let subject = match edited_documents {
+        [(_, document)] => format!("\"{}\" was edited", &document.title),
+        _ => format!(
+            "{} documents you follow were edited",
+            edited_documents.len()
+        ),
+    };
+    let mut body = String::from("Documents you follow were edited:\n\n");
+    for ((notification, document), summary) in edited_documents.iter().zip(summaries) {
+        let edits = if notification.edit_count == 1 {
+            "edit"
+        } else {
+            "edits"
+        };
+        body.push_str(&format!(
+            "- {} ({} {})\n",
+            &document.title, notification.edit_count, edits,
+        ));
+        if let Some(summary) = summary {
+            body.push_str(&format!("  Changes: {}\n", summary));
+        }
+        body.push_str(&format!("  {}\n", document_url(&document.id)));
+    }
+    body.push_str("\nTo stop getting these emails about a document, unfollow it.\n");
+    Email {
+        to: email_address.to_string(),
+        subject,
+        body,
+    }
+}
+
+fn document_url(doc_id: &str) -> String {
+    // TODO(cliff): Stop hard-coding the frontend's development server URL.
+    format!("http://localhost:3000/document/{}", doc_id)
+}
+
+/// Deletes the notification, unless the document was edited again after we read it. In that case
+/// the newer edits stay pending for the next digest.
+async fn delete_notification(
+    dynamodb_client: &DynamoDbClient,
+    notification: &PendingNotification,
+) -> anyhow::Result<()> {
+    let input = DeleteItemInput {
+        table_name: table_name("pending_notifications"),
+        key: av_map(&[
+            av_s("user_id", &notification.user_id),
+            av_s("doc_id", &notification.doc_id),
+        ]),
+        condition_expression: Some(String::from("last_edited_at = :last_edited_at")),
+        expression_attribute_values: Some(av_map(&[av_s(
+            ":last_edited_at",
+            &notification.last_edited_at,
+        )])),
+        ..Default::default()
+    };
+    match dynamodb_client.delete_item(input).await {
+        Ok(_) | Err(RusotoError::Service(DeleteItemError::ConditionalCheckFailed(_))) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn get_user_email(
+    dynamodb_client: &DynamoDbClient,
+    user_id: &str,
+) -> anyhow::Result<Option<String>> {
+    let input = QueryInput {
+        table_name: table_name("users"),
+        index_name: Some(String::from("id-index")),
+        key_condition_expression: Some(String::from("id = :id")),
+        expression_attribute_values: Some(av_map(&[av_s(":id", user_id)])),
+        projection_expression: Some(String::from("email")),
+        ..Default::default()
+    };
+    let output = dynamodb_client.query(input).await?;
+    Ok(output
+        .items
+        .unwrap_or_default()
+        .first()
+        .and_then(|item| av_get_s(item, "email"))
+        .map(String::from))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::fixtures::{DocumentFixture, RevisionFixture};
+    use crate::testing::utils::{RecordingMailer, TestDynamoDb};
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    async fn create_user(
+        dynamodb_client: &DynamoDbClient,
+        org_id: &Id,
+        email: &str,
+    ) -> SessionUser {
+        let user_id = Id::new(IdType::User);
+        dynamodb_client
+            .put_item(PutItemInput {
+                table_name: table_name("users"),
+                item: av_map(&[av_s("id", user_id.as_str()), av_s("email", email)]),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        SessionUser {
+            user_id,
+            org_id: org_id.clone(),
+            user_role: UserRole::Default,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_follow_and_send_digest() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let mailer = RecordingMailer::default();
+
+        let org_id = Id::new(IdType::Organization);
+        let owner = create_user(&db.dynamodb_client, &org_id, "owner@example.com").await;
+        let follower = create_user(&db.dynamodb_client, &org_id, "follower@example.com").await;
+        let outsider = create_user(
+            &db.dynamodb_client,
+            &Id::new(IdType::Organization),
+            "outsider@example.com",
+        )
+        .await;
+        let values = ["", "Plans\nTBD", "Plans\nTBD, soon", "Plans\nTBD, soon!"];
+        let committed_at = chrono::Utc::now();
+        let doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_created_by_user_id(&owner.user_id)
+            .with_title("Plans")
+            .with_org_level_sharing_permission(DocumentSharingPermission::CanView)
+            .with_revisions(values.windows(2).map(|values| {
+                RevisionFixture::new(
+                    &owner.user_id,
+                    &ot::diff(values[0], values[1]),
+                    &committed_at,
+                )
+            }));
+        doc.create(&db.dynamodb_client).await;
+        let doc_id = doc.doc_id.as_str().to_string();
+
+        // Users who cannot view the document cannot follow it.
+        let result = follow_document(
+            &db.dynamodb_client,
+            &outsider,
+            &FollowDocumentRequest {
+                doc_id: doc_id.clone(),
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 404);
+
+        for session_user in &[&owner, &follower] {
+            follow_document(
+                &db.dynamodb_client,
+                session_user,
+                &FollowDocumentRequest {
+                    doc_id: doc_id.clone(),
+                },
+            )
+            .await?;
+        }
+
+        // The owner edits the document twice more. Only the follower is notified, once. Recording
+        // the same revision again does not count it twice.
+        for revision_number in &[2, 3, 3] {
+            enqueue_edit_notifications(
+                &db.dynamodb_client,
+                &doc_id,
+                *revision_number,
+                &owner.user_id,
+            )
+            .await?;
+        }
+
+        // Nothing is sent until the document goes quiet.
+        let now = chrono::Utc::now();
+        assert_eq!(send_digests(&db.dynamodb_client, &mailer, now).await?, 0);
+
+        let later = now + chrono::Duration::minutes(DIGEST_QUIET_PERIOD_MINUTES + 1);
+        assert_eq!(send_digests(&db.dynamodb_client, &mailer, later).await?, 1);
+        {
+            let sent = mailer.sent.lock().unwrap();
+            assert_eq!(sent.len(), 1);
+            assert_eq!(sent[0].to, "follower@example.com");
+            assert_eq!(sent[0].subject, "\"Plans\" was edited");
+            assert!(sent[0].body.contains("Plans (2 edits)"));
+            assert!(sent[0]
+                .body
+                .contains("Changes: inserted 7 characters near 'TBD'"));
+            assert!(sent[0].body.contains(&doc_id));
+        }
+
+        // Sent notifications are not sent again.
+        assert_eq!(send_digests(&db.dynamodb_client, &mailer, later).await?, 0);
+
+        // Unfollowing drops pending notifications.
+        enqueue_edit_notifications(&db.dynamodb_client, &doc_id, 4, &owner.user_id).await?;
+        unfollow_document(
+            &db.dynamodb_client,
+            &follower,
+            &UnfollowDocumentRequest {
+                doc_id: doc_id.clone(),
+            },
+        )
+        .await?;
+        enqueue_edit_notifications(&db.dynamodb_client, &doc_id, 5, &owner.user_id).await?;
+        assert_eq!(send_digests(&db.dynamodb_client, &mailer, later).await?, 0);
+        assert_eq!(mailer.sent.lock().unwrap().len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_digest_drops_notifications_after_access_is_lost() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let mailer = RecordingMailer::default();
+
+        let org_id = Id::new(IdType::Organization);
+        let owner = create_user(&db.dynamodb_client, &org_id, "owner@example.com").await;
+        let follower = create_user(&db.dynamodb_client, &org_id, "follower@example.com").await;
+        let doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_created_by_user_id(&owner.user_id)
+            .with_sharing(&follower.user_id, DocumentSharingPermission::CanView);
+        doc.create(&db.dynamodb_client).await;
+        let doc_id = doc.doc_id.as_str().to_string();
+
+        follow_document(
+            &db.dynamodb_client,
+            &follower,
+            &FollowDocumentRequest {
+                doc_id: doc_id.clone(),
+            },
+        )
+        .await?;
+        enqueue_edit_notifications(&db.dynamodb_client, &doc_id, 1, &owner.user_id).await?;
+
+        // The document stops being shared with the follower before the digest goes out.
+        db.dynamodb_client
+            .delete_item(DeleteItemInput {
+                table_name: table_name("document_user_sharing_permissions"),
+                key: av_map(&[
+                    av_s("doc_id", &doc_id),
+                    av_s("user_id", follower.user_id.as_str()),
+                ]),
+                ..Default::default()
+            })
+            .await?;
+
+        let later = chrono::Utc::now() + chrono::Duration::minutes(DIGEST_QUIET_PERIOD_MINUTES + 1);
+        assert_eq!(send_digests(&db.dynamodb_client, &mailer, later).await?, 0);
+        assert!(mailer.sent.lock().unwrap().is_empty());
+        let output = db
+            .dynamodb_client
+            .scan(ScanInput {
+                table_name: table_name("pending_notifications"),
+                ..Default::default()
+            })
+            .await?;
+        assert_eq!(output.count, Some(0));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,274 @@
+//! The org changefeed: every revision committed to any document in an org, in the order they were
+//! committed, for admins and integrations that mirror or audit an org's documents.
+//!
+//! Each revision item records its document's org, and the `org_id-committed_at-index` on the
+//! `document_revisions` table lists them by commit time. The index only projects the revision
+//! metadata, so the feed does not say what changed. Read the revisions themselves with
+//! `get_document_revisions` for that. Revisions committed before revisions recorded their org are
+//! not in the feed.
+
+use std::collections::HashMap;
+
+use actix_web::error;
+use rusoto_dynamodb::{AttributeValue, QueryInput};
+
+use ot::writing_proto::{
+    ListOrgRevisionsRequest, ListOrgRevisionsResponse, OrgRevision, OrgRevisionsCursor,
+};
+
+use crate::dynamodb::{av_get_n, av_get_s, av_map, av_n, av_s, table_name, DynamoDbClient};
+use crate::http::SessionUser;
+use crate::users::UserRole;
+
+const LIST_ORG_REVISIONS_DEFAULT_LIMIT: i64 = 100;
+const LIST_ORG_REVISIONS_MAX_LIMIT: i64 = 1000;
+
+/// List the revisions committed to documents in the session user's org, oldest first.
+///
+/// If the session user is not an org admin, returns 403 Forbidden.
+///
+/// If the cursor does not name a revision, returns 400 Bad Request.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+///
+/// Upon success, returns a page of revisions, and the cursor to pass to read the next page. Once
+/// the feed is caught up, the cursor can be passed again later to read revisions committed since.
+pub async fn list_org_revisions(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &ListOrgRevisionsRequest,
+) -> actix_web::Result<ListOrgRevisionsResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [list_org_revisions] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    if session_user.user_role != UserRole::OrgAdmin {
+        return Err(error::ErrorForbidden(""));
+    }
+    let limit = match request.limit as i64 {
+        limit if limit <= 0 => LIST_ORG_REVISIONS_DEFAULT_LIMIT,
+        limit => limit.min(LIST_ORG_REVISIONS_MAX_LIMIT),
+    };
+    let org_id = session_user.org_id.as_str();
+    let mut values = vec![av_s(":org_id", org_id)];
+    // Several revisions can be committed at the same time, so a cursor resumes from the exact
+    // revision it names, rather than from its commit time.
+    let (key_condition_expression, exclusive_start_key) = match &request.cursor {
+        Some(cursor) => {
+            if cursor.committed_at.is_empty()
+                || cursor.doc_id.is_empty()
+                || cursor.revision_number <= 0
+            {
+                return Err(error::ErrorBadRequest(""));
+            }
+            ("org_id = :org_id", Some(cursor_key(org_id, cursor)))
+        }
+        None if request.since_date_time.is_empty() => ("org_id = :org_id", None),
+        None => {
+            values.push(av_s(":since", &request.since_date_time));
+            ("org_id = :org_id AND committed_at >= :since", None)
+        }
+    };
+    let input = QueryInput {
+        table_name: table_name("document_revisions"),
+        index_name: Some(String::from("org_id-committed_at-index")),
+        key_condition_expression: Some(String::from(key_condition_expression)),
+        expression_attribute_values: Some(av_map(&values)),
+        exclusive_start_key,
+        limit: Some(limit),
+        ..Default::default()
+    };
+    let output = dynamodb_client.query(input).await.map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    let revisions = output
+        .items
+        .unwrap_or_default()
+        .iter()
+        .map(org_revision_from_item)
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| {
+            log_error("revision is missing a field".to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    let next_cursor = match revisions.last() {
+        Some(revision) => Some(OrgRevisionsCursor {
+            committed_at: revision.committed_at.clone(),
+            doc_id: revision.doc_id.clone(),
+            revision_number: revision.revision_number,
+        }),
+        None => request.cursor.clone(),
+    };
+    Ok(ListOrgRevisionsResponse {
+        revisions,
+        next_cursor,
+        end_of_revisions: output.last_evaluated_key.is_none(),
+    })
+}
+
+/// The index key of the revision that the cursor names.
+fn cursor_key(org_id: &str, cursor: &OrgRevisionsCursor) -> HashMap<String, AttributeValue> {
+    av_map(&[
+        av_s("org_id", org_id),
+        av_s("committed_at", &cursor.committed_at),
+        av_s("doc_id", &cursor.doc_id),
+        av_n("revision_number", cursor.revision_number),
+    ])
+}
+
+fn org_revision_from_item(item: &HashMap<String, AttributeValue>) -> Option<OrgRevision> {
+    Some(OrgRevision {
+        doc_id: av_get_s(item, "doc_id")?.to_string(),
+        revision_number: av_get_n(item, "revision_number")?,
+        author_user_id: av_get_s(item, "author_user_id")?.to_string(),
+        author_display_name: av_get_s(item, "author_display_name")
+            .unwrap_or_default()
+            .to_string(),
+        committed_at: av_get_s(item, "committed_at")?.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::{Duration, Utc};
+
+    use ot::writing_proto::ChangeSet;
+
+    use crate::ids::{Id, IdType};
+    use crate::testing::fixtures::{DocumentFixture, RevisionFixture};
+    use crate::testing::utils::TestDynamoDb;
+    use crate::utils::time;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[tokio::test]
+    async fn test_list_org_revisions() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let org_id = Id::new(IdType::Organization);
+        let admin = SessionUser {
+            user_id: Id::new(IdType::User),
+            org_id: org_id.clone(),
+            user_role: UserRole::OrgAdmin,
+        };
+        let author = SessionUser {
+            user_id: Id::new(IdType::User),
+            org_id: org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let mut hello = ChangeSet::new();
+        hello.insert("Hello");
+        let mut world = ChangeSet::new();
+        world.retain(5);
+        world.insert(" world");
+        let start = Utc::now() - Duration::minutes(10);
+        let first = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_created_by_user_id(&author.user_id)
+            .with_revisions(vec![
+                RevisionFixture::new(&author.user_id, &hello, &start),
+                RevisionFixture::new(&author.user_id, &world, &(start + Duration::minutes(2))),
+            ]);
+        first.create(&db.dynamodb_client).await;
+        // Committed at the same time as the first document's first revision.
+        let second = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_created_by_user_id(&author.user_id)
+            .with_revisions(vec![RevisionFixture::new(&author.user_id, &hello, &start)]);
+        second.create(&db.dynamodb_client).await;
+        // Another org's revisions are not in the feed.
+        DocumentFixture::new()
+            .with_revisions(vec![RevisionFixture::new(&author.user_id, &hello, &start)])
+            .create(&db.dynamodb_client)
+            .await;
+
+        // Only admins can read the feed.
+        let result = list_org_revisions(
+            &db.dynamodb_client,
+            &author,
+            &ListOrgRevisionsRequest::default(),
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+
+        let response = list_org_revisions(
+            &db.dynamodb_client,
+            &admin,
+            &ListOrgRevisionsRequest::default(),
+        )
+        .await?;
+        assert_eq!(response.revisions.len(), 3);
+        assert!(response.end_of_revisions);
+        let last = response.revisions.last().unwrap();
+        assert_eq!(last.doc_id, first.doc_id.as_str());
+        assert_eq!(last.revision_number, 2);
+        assert_eq!(last.author_user_id, author.user_id.as_str());
+
+        // Page one revision at a time, including through revisions committed at the same time.
+        let mut cursor = None;
+        let mut paged = Vec::new();
+        loop {
+            let response = list_org_revisions(
+                &db.dynamodb_client,
+                &admin,
+                &ListOrgRevisionsRequest {
+                    limit: 1,
+                    cursor: cursor.clone(),
+                    ..Default::default()
+                },
+            )
+            .await?;
+            paged.extend(response.revisions);
+            cursor = response.next_cursor;
+            if response.end_of_revisions {
+                break;
+            }
+        }
+        assert_eq!(paged, response.revisions);
+
+        // Once caught up, the cursor is handed back until something new is committed.
+        let response = list_org_revisions(
+            &db.dynamodb_client,
+            &admin,
+            &ListOrgRevisionsRequest {
+                cursor: cursor.clone(),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert!(response.revisions.is_empty());
+        assert_eq!(response.next_cursor, cursor);
+
+        let response = list_org_revisions(
+            &db.dynamodb_client,
+            &admin,
+            &ListOrgRevisionsRequest {
+                since_date_time: time::date_time_iso_str(&(start + Duration::minutes(1))),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(response.revisions.len(), 1);
+        assert_eq!(response.revisions[0].revision_number, 2);
+
+        let result = list_org_revisions(
+            &db.dynamodb_client,
+            &admin,
+            &ListOrgRevisionsRequest {
+                cursor: Some(OrgRevisionsCursor::default()),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+        Ok(())
+    }
+}
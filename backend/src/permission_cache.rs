@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use ot::writing_proto::DocumentSharingPermission;
+
+use crate::http::SessionUser;
+
+/// How long a permission check result may be reused before we go back to DynamoDB.
+pub const PERMISSION_CACHE_TTL: Duration = Duration::from_secs(30);
+
+// When the cache grows past this many entries, expired entries are swept out on the next insert.
+const SWEEP_THRESHOLD: usize = 10_000;
+
+/// Remembers which permission a session user was granted on a document, so that the sync loop,
+/// which calls submit/get revisions every few seconds, does not repeat the same permission queries
+/// on every request.
+///
+/// Only successful checks are cached. A cached permission that does not satisfy a check falls
+/// through to DynamoDB, so denials are never served from the cache.
+///
+/// Entries expire after the TTL. Code that changes a document's sharing settings must call
+/// `invalidate_document` so that revoked permissions stop being honored immediately on this
+/// server.
+pub struct PermissionCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<CacheKey, CacheEntry>>,
+}
+
+#[derive(Eq, Hash, PartialEq)]
+struct CacheKey {
+    org_id: String,
+    user_id: String,
+    doc_id: String,
+}
+
+struct CacheEntry {
+    permission: DocumentSharingPermission,
+    cached_at: Instant,
+}
+
+impl PermissionCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the permission the session user was granted on the document, if it was cached
+    /// within the TTL.
+    pub fn get(
+        &self,
+        session_user: &SessionUser,
+        doc_id: &str,
+    ) -> Option<DocumentSharingPermission> {
+        let key = CacheKey::new(session_user, doc_id);
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(&key) {
+            Some(entry) if entry.cached_at.elapsed() < self.ttl => Some(entry.permission),
+            Some(_) => {
+                entries.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(
+        &self,
+        session_user: &SessionUser,
+        doc_id: &str,
+        permission: DocumentSharingPermission,
+    ) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= SWEEP_THRESHOLD {
+            let ttl = self.ttl;
+            entries.retain(|_, entry| entry.cached_at.elapsed() < ttl);
+        }
+        entries.insert(
+            CacheKey::new(session_user, doc_id),
+            CacheEntry {
+                permission,
+                cached_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Forgets every cached permission for the document. Call this whenever the document's
+    /// org-level or user-level sharing permissions change.
+    pub fn invalidate_document(&self, doc_id: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|key, _| key.doc_id != doc_id);
+    }
+}
+
+impl Default for PermissionCache {
+    fn default() -> Self {
+        Self::new(PERMISSION_CACHE_TTL)
+    }
+}
+
+impl CacheKey {
+    fn new(session_user: &SessionUser, doc_id: &str) -> Self {
+        Self {
+            org_id: session_user.org_id.as_str().to_string(),
+            user_id: session_user.user_id.as_str().to_string(),
+            doc_id: doc_id.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ids::{Id, IdType};
+    use crate::users::UserRole;
+
+    fn new_session_user() -> SessionUser {
+        SessionUser {
+            user_id: Id::new(IdType::User),
+            org_id: Id::new(IdType::Organization),
+            user_role: UserRole::Default,
+        }
+    }
+
+    #[test]
+    fn test_get_and_invalidate() {
+        let cache = PermissionCache::default();
+        let session_user = new_session_user();
+        let other_session_user = new_session_user();
+        let doc_id1 = Id::new(IdType::Document);
+        let doc_id2 = Id::new(IdType::Document);
+
+        assert_eq!(cache.get(&session_user, doc_id1.as_str()), None);
+
+        cache.insert(
+            &session_user,
+            doc_id1.as_str(),
+            DocumentSharingPermission::CanEdit,
+        );
+        cache.insert(
+            &session_user,
+            doc_id2.as_str(),
+            DocumentSharingPermission::CanView,
+        );
+        assert_eq!(
+            cache.get(&session_user, doc_id1.as_str()),
+            Some(DocumentSharingPermission::CanEdit)
+        );
+        assert_eq!(
+            cache.get(&session_user, doc_id2.as_str()),
+            Some(DocumentSharingPermission::CanView)
+        );
+        assert_eq!(cache.get(&other_session_user, doc_id1.as_str()), None);
+
+        cache.invalidate_document(doc_id1.as_str());
+        assert_eq!(cache.get(&session_user, doc_id1.as_str()), None);
+        assert_eq!(
+            cache.get(&session_user, doc_id2.as_str()),
+            Some(DocumentSharingPermission::CanView)
+        );
+    }
+
+    #[test]
+    fn test_expired_entries_are_ignored() {
+        let cache = PermissionCache::new(Duration::from_secs(0));
+        let session_user = new_session_user();
+        let doc_id = Id::new(IdType::Document);
+
+        cache.insert(
+            &session_user,
+            doc_id.as_str(),
+            DocumentSharingPermission::CanEdit,
+        );
+        assert_eq!(cache.get(&session_user, doc_id.as_str()), None);
+    }
+}
@@ -0,0 +1,649 @@
+//! Protected ranges: parts of a document, like a frozen header, that only its owner may edit.
+//!
+//! A document's protected ranges are kept on its `documents` item, encoded as a `ProtectedRanges`
+//! message along with the revision that they are in. They are moved through the revisions
+//! committed since whenever they are read, like text selections: text inserted at the edge of a
+//! range goes outside of it, and a range whose text is all deleted goes away.
+//!
+//! `submit_document_change_set` checks each plaintext change set from anyone but the owner
+//! against the ranges, and rejects it if it modifies one. Once a change set is committed, the
+//! stored ranges are moved past it, so that checks only need to read the revisions committed since
+//! the last one. The server cannot read the change sets of end-to-end encrypted documents, so they
+//! cannot have protected ranges. Should one have them anyway, `check_encrypted_change_set` rejects
+//! every change set from anyone but the owner, since it cannot tell which ones modify a range.
+
+use std::collections::HashMap;
+
+use actix_web::error;
+use bytes::Bytes;
+use prost::Message;
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{AttributeValue, GetItemInput, UpdateItemError, UpdateItemInput};
+
+use ot::writing_proto::{
+    ChangeSet, DocumentSharingPermission, GetProtectedRangesRequest, GetProtectedRangesResponse,
+    ProtectedRanges, Selection, SetProtectedRangesRequest, SetProtectedRangesResponse,
+};
+use ot::OtError;
+
+use crate::documents;
+use crate::dynamodb::{av_b, av_get_b, av_get_s, av_map, av_s, table_name, DynamoDbClient};
+use crate::http::{Requester, SessionUser};
+use crate::permission_cache::PermissionCache;
+use crate::utils::proto;
+
+/// A document may have at most this many protected ranges.
+pub const MAX_PROTECTED_RANGES: usize = 100;
+
+/// How a change set stands with the document's protected ranges. See `check_change_set`.
+pub enum ChangeSetCheck {
+    /// The document has no protected ranges, or the change set cannot be committed anyway.
+    Unprotected,
+    /// The change set may be committed. Once it is, pass this to `advance`.
+    Allowed(ProtectedRangesAdvance),
+    /// The change set modifies a protected range, and must not be committed.
+    Modified,
+}
+
+/// The protected ranges as of the revision that a change set is based on, to move past the change
+/// set once it is committed.
+pub struct ProtectedRangesAdvance {
+    // The stored ranges, as they were read. They are only replaced if they are still the same.
+    stored_binary: Bytes,
+    protected_ranges: ProtectedRanges,
+}
+
+/// Replace the document's protected ranges. Only the owner of the document may set them.
+///
+/// The ranges are given as of a revision of the document, and are moved up to the latest revision
+/// before they are stored. Empty ranges are dropped.
+///
+/// If the protected ranges are missing, there are more than `MAX_PROTECTED_RANGES` of them, their
+/// revision does not exist, or a range does not fit in the document at that revision, returns 400
+/// Bad Request. If the document is end-to-end encrypted, returns 400 Bad Request.
+///
+/// If the session user is not the owner of the document, returns 403 Forbidden.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn set_protected_ranges(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &SetProtectedRangesRequest,
+) -> actix_web::Result<SetProtectedRangesResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [set_protected_ranges] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    let document = documents::get_document_if_some_permission_valid(
+        dynamodb_client,
+        session_user,
+        &request.doc_id,
+        &[DocumentSharingPermission::CanEdit],
+    )
+    .await?;
+    if document.owner_user_id != session_user.user_id.as_str() {
+        return Err(error::ErrorForbidden(""));
+    }
+    if !document.encryption_key_fingerprint.is_empty() {
+        return Err(error::ErrorBadRequest(""));
+    }
+    let protected_ranges = match &request.protected_ranges {
+        Some(protected_ranges) => protected_ranges,
+        None => return Err(error::ErrorBadRequest("")),
+    };
+    if protected_ranges.ranges.len() > MAX_PROTECTED_RANGES || protected_ranges.revision_number < 0
+    {
+        return Err(error::ErrorBadRequest(""));
+    }
+
+    let (change_sets, last_revision_number) =
+        documents::get_change_sets(dynamodb_client, &request.doc_id, 0, 0)
+            .await
+            .map_err(|e| {
+                log_error(e.to_string());
+                error::ErrorInternalServerError("")
+            })?;
+    if protected_ranges.revision_number > last_revision_number {
+        return Err(error::ErrorBadRequest(""));
+    }
+    let (before, after) = change_sets.split_at(protected_ranges.revision_number as usize);
+    let (_, document_len) = ot::compose_iter(before.iter())
+        .and_then(|composed| composed.lengths())
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    if protected_ranges.ranges.iter().any(|range| {
+        range.offset < 0 || range.count < 0 || range.offset + range.count > document_len
+    }) {
+        return Err(error::ErrorBadRequest(""));
+    }
+    let ranges = transform_ranges(&protected_ranges.ranges, after).map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    let protected_ranges = ProtectedRanges {
+        revision_number: last_revision_number,
+        ranges,
+    };
+    let binary = proto::encode_protobuf_message(&protected_ranges).map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    let input = UpdateItemInput {
+        table_name: table_name("documents"),
+        key: av_map(&[av_s("id", &request.doc_id)]),
+        update_expression: Some(String::from("SET protected_ranges = :protected_ranges")),
+        expression_attribute_values: Some(av_map(&[av_b(
+            ":protected_ranges",
+            Bytes::from(binary),
+        )])),
+        ..Default::default()
+    };
+    dynamodb_client.update_item(input).await.map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    Ok(SetProtectedRangesResponse {})
+}
+
+/// Returns the document's protected ranges, as of its latest revision. Access is granted either
+/// through the requester's permissions or through a share token. The requester needs view or edit
+/// permission.
+///
+/// If there is no session user, and no valid share token, returns 401 Unauthorized.
+///
+/// If the requester cannot view the document, returns 403 Forbidden.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn get_protected_ranges(
+    dynamodb_client: &DynamoDbClient,
+    permission_cache: &PermissionCache,
+    requester: &Requester,
+    request: &GetProtectedRangesRequest,
+) -> actix_web::Result<GetProtectedRangesResponse> {
+    documents::validate_some_access_cached(
+        dynamodb_client,
+        permission_cache,
+        requester.session_user(),
+        &request.doc_id,
+        &request.share_token,
+        &[
+            DocumentSharingPermission::CanView,
+            DocumentSharingPermission::CanComment,
+            DocumentSharingPermission::CanEdit,
+        ],
+    )
+    .await?;
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [get_protected_ranges] \
+            [requester: {:?}, request: {:?}]",
+            error_message,
+            requester,
+            request,
+        );
+    };
+    let item = get_document_item(dynamodb_client, &request.doc_id)
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?
+        .ok_or_else(|| error::ErrorNotFound(""))?;
+    let stored = decode_protected_ranges(&item).map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    let protected_ranges = match stored {
+        Some((_, protected_ranges)) => {
+            let (change_sets, last_revision_number) = documents::get_change_sets(
+                dynamodb_client,
+                &request.doc_id,
+                protected_ranges.revision_number,
+                0,
+            )
+            .await
+            .map_err(|e| {
+                log_error(e.to_string());
+                error::ErrorInternalServerError("")
+            })?;
+            let ranges = transform_ranges(&protected_ranges.ranges, &change_sets).map_err(|e| {
+                log_error(e.to_string());
+                error::ErrorInternalServerError("")
+            })?;
+            ProtectedRanges {
+                revision_number: last_revision_number,
+                ranges,
+            }
+        }
+        None => ProtectedRanges {
+            revision_number: documents::get_last_revision_number(dynamodb_client, &request.doc_id)
+                .await
+                .map_err(|e| {
+                    log_error(e.to_string());
+                    error::ErrorInternalServerError("")
+                })?,
+            ranges: Vec::new(),
+        },
+    };
+    Ok(GetProtectedRangesResponse {
+        protected_ranges: Some(protected_ranges),
+    })
+}
+
+/// Checks a plaintext change set that the requester submits on top of revision
+/// `on_revision_number` against the document's protected ranges.
+///
+/// The owner of the document may modify its protected ranges. If the change set is based on an
+/// older revision than the stored ranges are in, it cannot be committed, so it is not checked.
+pub async fn check_change_set(
+    dynamodb_client: &DynamoDbClient,
+    requester: &Requester,
+    doc_id: &str,
+    on_revision_number: i64,
+    change_set: &ChangeSet,
+) -> anyhow::Result<ChangeSetCheck> {
+    let item = match get_document_item(dynamodb_client, doc_id).await? {
+        Some(item) => item,
+        None => return Ok(ChangeSetCheck::Unprotected),
+    };
+    let (stored_binary, protected_ranges) = match decode_protected_ranges(&item)? {
+        Some(stored) => stored,
+        None => return Ok(ChangeSetCheck::Unprotected),
+    };
+    if on_revision_number < protected_ranges.revision_number {
+        return Ok(ChangeSetCheck::Unprotected);
+    }
+    let (change_sets, _) = documents::get_change_sets(
+        dynamodb_client,
+        doc_id,
+        protected_ranges.revision_number,
+        on_revision_number,
+    )
+    .await?;
+    let ranges = transform_ranges(&protected_ranges.ranges, &change_sets)?;
+    if !is_owner(&item, requester) {
+        for range in ranges.iter() {
+            if change_set.modifies_range(range.offset..range.offset + range.count) {
+                return Ok(ChangeSetCheck::Modified);
+            }
+        }
+    }
+    Ok(ChangeSetCheck::Allowed(ProtectedRangesAdvance {
+        stored_binary,
+        protected_ranges: ProtectedRanges {
+            revision_number: on_revision_number,
+            ranges,
+        },
+    }))
+}
+
+/// Checks an end-to-end encrypted change set that the requester submits against the document's
+/// protected ranges. Its ops cannot be read, so if the document has any protected ranges, it is
+/// only allowed from the owner. There is nothing to advance afterwards either way.
+pub async fn check_encrypted_change_set(
+    dynamodb_client: &DynamoDbClient,
+    requester: &Requester,
+    doc_id: &str,
+) -> anyhow::Result<ChangeSetCheck> {
+    let item = match get_document_item(dynamodb_client, doc_id).await? {
+        Some(item) => item,
+        None => return Ok(ChangeSetCheck::Unprotected),
+    };
+    if decode_protected_ranges(&item)?.is_none() || is_owner(&item, requester) {
+        return Ok(ChangeSetCheck::Unprotected);
+    }
+    Ok(ChangeSetCheck::Modified)
+}
+
+/// Moves the stored protected ranges past a change set that was just committed as revision
+/// `revision_number`, unless they were replaced in the meantime.
+pub async fn advance(
+    dynamodb_client: &DynamoDbClient,
+    doc_id: &str,
+    advance: ProtectedRangesAdvance,
+    change_set: &ChangeSet,
+    revision_number: i64,
+) -> anyhow::Result<()> {
+    let protected_ranges = ProtectedRanges {
+        revision_number,
+        ranges: transform_ranges(
+            &advance.protected_ranges.ranges,
+            std::slice::from_ref(change_set),
+        )?,
+    };
+    let binary = proto::encode_protobuf_message(&protected_ranges)?;
+    let input = UpdateItemInput {
+        table_name: table_name("documents"),
+        key: av_map(&[av_s("id", doc_id)]),
+        condition_expression: Some(String::from("protected_ranges = :stored_protected_ranges")),
+        update_expression: Some(String::from("SET protected_ranges = :protected_ranges")),
+        expression_attribute_values: Some(av_map(&[
+            av_b(":stored_protected_ranges", advance.stored_binary),
+            av_b(":protected_ranges", Bytes::from(binary)),
+        ])),
+        ..Default::default()
+    };
+    match dynamodb_client.update_item(input).await {
+        Ok(_) | Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn get_document_item(
+    dynamodb_client: &DynamoDbClient,
+    doc_id: &str,
+) -> anyhow::Result<Option<HashMap<String, AttributeValue>>> {
+    let output = dynamodb_client
+        .get_item(GetItemInput {
+            table_name: table_name("documents"),
+            key: av_map(&[av_s("id", doc_id)]),
+            consistent_read: Some(true),
+            projection_expression: Some(String::from(
+                "created_by_user_id, owner_user_id, protected_ranges",
+            )),
+            ..Default::default()
+        })
+        .await?;
+    Ok(output.item)
+}
+
+fn is_owner(item: &HashMap<String, AttributeValue>, requester: &Requester) -> bool {
+    match requester.session_user() {
+        Some(session_user) => {
+            documents::owner_user_id_from_item(item) == Some(session_user.user_id.as_str())
+        }
+        None => false,
+    }
+}
+
+fn decode_protected_ranges(
+    item: &HashMap<String, AttributeValue>,
+) -> anyhow::Result<Option<(Bytes, ProtectedRanges)>> {
+    match av_get_b(item, "protected_ranges") {
+        Some(binary) => {
+            let protected_ranges = ProtectedRanges::decode(&binary[..])?;
+            if protected_ranges.ranges.is_empty() {
+                return Ok(None);
+            }
+            Ok(Some((binary.clone(), protected_ranges)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Moves the ranges through the change sets, in order, dropping the ones whose text is all
+/// deleted.
+fn transform_ranges(
+    ranges: &[Selection],
+    change_sets: &[ChangeSet],
+) -> Result<Vec<Selection>, OtError> {
+    let mut ranges: Vec<Selection> = ranges
+        .iter()
+        .filter(|range| range.count > 0)
+        .cloned()
+        .collect();
+    for change_set in change_sets {
+        if ranges.is_empty() {
+            break;
+        }
+        ranges = ot::transform_selections(&ranges, change_set)?;
+        ranges.retain(|range| range.count > 0);
+    }
+    Ok(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ot::writing_proto::{
+        submit_document_change_set_response::ResponseCode, SubmitDocumentChangeSetRequest,
+        SubmitDocumentChangeSetResponse,
+    };
+
+    use crate::rate_limiter::RateLimiter;
+    use crate::testing::fixtures::{self, DocumentFixture, RevisionFixture};
+    use crate::testing::utils::TestDynamoDb;
+    use crate::users::UserRole;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    fn range(offset: i64, count: i64) -> Selection {
+        Selection { offset, count }
+    }
+
+    async fn submit(
+        dynamodb_client: &DynamoDbClient,
+        session_user: &SessionUser,
+        doc_id: &str,
+        on_revision_number: i64,
+        change_set: &ChangeSet,
+    ) -> actix_web::Result<SubmitDocumentChangeSetResponse> {
+        let request = SubmitDocumentChangeSetRequest {
+            doc_id: doc_id.to_string(),
+            on_revision_number,
+            change_set: Some(change_set.clone()),
+            ..Default::default()
+        };
+        documents::submit_document_change_set(
+            dynamodb_client,
+            &PermissionCache::default(),
+            &RateLimiter::default(),
+            &Requester::User(session_user.clone()),
+            &request,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_protected_ranges() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let permission_cache = PermissionCache::default();
+
+        let owner_id = fixtures::create_user(&db.dynamodb_client, "olga@example.com", "Olga").await;
+        let editor_id =
+            fixtures::create_user(&db.dynamodb_client, "edna@example.com", "Edna").await;
+        let mut change_set = ChangeSet::new();
+        change_set.insert("Header\nBody");
+        let doc = DocumentFixture::new()
+            .with_created_by_user_id(&owner_id)
+            .with_sharing(&owner_id, DocumentSharingPermission::CanEdit)
+            .with_sharing(&editor_id, DocumentSharingPermission::CanEdit)
+            .with_revisions(vec![RevisionFixture::new(
+                &owner_id,
+                &change_set,
+                &chrono::Utc::now(),
+            )]);
+        doc.create(&db.dynamodb_client).await;
+        let doc_id = doc.doc_id.as_str();
+        let owner = SessionUser {
+            user_id: owner_id.clone(),
+            org_id: doc.org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let editor = SessionUser {
+            user_id: editor_id.clone(),
+            ..owner.clone()
+        };
+
+        // Only the owner can protect ranges, and only ranges that fit in the document.
+        let set = |revision_number, ranges| SetProtectedRangesRequest {
+            doc_id: doc_id.to_string(),
+            protected_ranges: Some(ProtectedRanges {
+                revision_number,
+                ranges,
+            }),
+        };
+        for (session_user, request, status_code) in vec![
+            (&editor, set(1, vec![range(0, 6)]), 403),
+            (&owner, set(1, vec![range(8, 4)]), 400),
+            (&owner, set(2, vec![range(0, 6)]), 400),
+        ] {
+            let result = set_protected_ranges(&db.dynamodb_client, session_user, &request).await;
+            assert_eq!(
+                result.err().unwrap().as_response_error().status_code(),
+                status_code
+            );
+        }
+        set_protected_ranges(&db.dynamodb_client, &owner, &set(1, vec![range(0, 6)])).await?;
+
+        // Editing the header is rejected, but typing right before it is not.
+        let mut edit_header = ChangeSet::new();
+        edit_header.retain(2);
+        edit_header.delete(1);
+        edit_header.retain(8);
+        let response = submit(&db.dynamodb_client, &editor, doc_id, 1, &edit_header).await?;
+        assert_eq!(
+            response.response_code(),
+            ResponseCode::ProtectedRangeModified
+        );
+        assert_eq!(response.last_revision_number, 1);
+        let mut insert_before = ChangeSet::new();
+        insert_before.insert("# ");
+        insert_before.retain(11);
+        let response = submit(&db.dynamodb_client, &editor, doc_id, 1, &insert_before).await?;
+        assert_eq!(response.response_code(), ResponseCode::Ack);
+
+        // The header moved past the inserted text.
+        let requester = Requester::User(editor.clone());
+        let get_request = GetProtectedRangesRequest {
+            doc_id: doc_id.to_string(),
+            share_token: String::new(),
+        };
+        let response = get_protected_ranges(
+            &db.dynamodb_client,
+            &permission_cache,
+            &requester,
+            &get_request,
+        )
+        .await?;
+        assert_eq!(
+            response.protected_ranges,
+            Some(ProtectedRanges {
+                revision_number: 2,
+                ranges: vec![range(2, 6)],
+            })
+        );
+
+        // The owner may still edit the header, which grows to take in their typing.
+        let mut owner_edit = ChangeSet::new();
+        owner_edit.retain(3);
+        owner_edit.insert("x");
+        owner_edit.retain(10);
+        let response = submit(&db.dynamodb_client, &owner, doc_id, 2, &owner_edit).await?;
+        assert_eq!(response.response_code(), ResponseCode::Ack);
+        let response = get_protected_ranges(
+            &db.dynamodb_client,
+            &permission_cache,
+            &requester,
+            &get_request,
+        )
+        .await?;
+        assert_eq!(
+            response.protected_ranges,
+            Some(ProtectedRanges {
+                revision_number: 3,
+                ranges: vec![range(2, 7)],
+            })
+        );
+
+        // Unprotecting the document lets everyone edit the header.
+        set_protected_ranges(&db.dynamodb_client, &owner, &set(3, Vec::new())).await?;
+        let mut edit_header = ChangeSet::new();
+        edit_header.retain(3);
+        edit_header.delete(1);
+        edit_header.retain(10);
+        let response = submit(&db.dynamodb_client, &editor, doc_id, 3, &edit_header).await?;
+        assert_eq!(response.response_code(), ResponseCode::Ack);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_document_protected_ranges() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let owner_id = fixtures::create_user(&db.dynamodb_client, "olga@example.com", "Olga").await;
+        let editor_id =
+            fixtures::create_user(&db.dynamodb_client, "edna@example.com", "Edna").await;
+        let doc = DocumentFixture::new()
+            .with_created_by_user_id(&owner_id)
+            .with_sharing(&owner_id, DocumentSharingPermission::CanEdit)
+            .with_sharing(&editor_id, DocumentSharingPermission::CanEdit)
+            .with_encryption_key_fingerprint(
+                "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08",
+            );
+        doc.create(&db.dynamodb_client).await;
+        let doc_id = doc.doc_id.as_str();
+        let owner = SessionUser {
+            user_id: owner_id.clone(),
+            org_id: doc.org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let editor = SessionUser {
+            user_id: editor_id.clone(),
+            ..owner.clone()
+        };
+
+        // Ranges cannot be protected when the server cannot read the change sets.
+        let request = SetProtectedRangesRequest {
+            doc_id: doc_id.to_string(),
+            protected_ranges: Some(ProtectedRanges {
+                revision_number: 0,
+                ranges: vec![range(0, 0)],
+            }),
+        };
+        let result = set_protected_ranges(&db.dynamodb_client, &owner, &request).await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+
+        // Should the document have protected ranges anyway, only its owner may edit it.
+        let protected_ranges = ProtectedRanges {
+            revision_number: 0,
+            ranges: vec![range(0, 6)],
+        };
+        db.dynamodb_client
+            .update_item(UpdateItemInput {
+                table_name: table_name("documents"),
+                key: av_map(&[av_s("id", doc_id)]),
+                update_expression: Some(String::from("SET protected_ranges = :protected_ranges")),
+                expression_attribute_values: Some(av_map(&[av_b(
+                    ":protected_ranges",
+                    Bytes::from(proto::encode_protobuf_message(&protected_ranges)?),
+                )])),
+                ..Default::default()
+            })
+            .await?;
+        for (session_user, response_code, last_revision_number) in vec![
+            (&editor, ResponseCode::ProtectedRangeModified, 0),
+            (&owner, ResponseCode::Ack, 1),
+        ] {
+            let request = SubmitDocumentChangeSetRequest {
+                doc_id: doc_id.to_string(),
+                on_revision_number: 0,
+                encrypted_change_set: vec![0xde, 0xad, 0xbe, 0xef],
+                ..Default::default()
+            };
+            let response = documents::submit_document_change_set(
+                &db.dynamodb_client,
+                &PermissionCache::default(),
+                &RateLimiter::default(),
+                &Requester::User(session_user.clone()),
+                &request,
+            )
+            .await?;
+            assert_eq!(response.response_code(), response_code);
+            assert_eq!(response.last_revision_number, last_revision_number);
+        }
+        Ok(())
+    }
+}
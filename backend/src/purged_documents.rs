@@ -0,0 +1,569 @@
+//! Purging documents, which deletes an archived document for good.
+//!
+//! Purging happens in two parts, like account deletion. While the request waits, we delete the
+//! document item, so the document is gone everywhere at once. The rest runs in the background as a
+//! job, since it grows with the document's history: we delete everything else stored by the
+//! document's id, like its revisions, sharing permissions, followers, stars, share links, and
+//! attachments.
+//!
+//! The job records how many of its steps are done, and picks up from there when it is retried, so
+//! a purge that fails part way does not start over.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use actix_web::error;
+use futures::future::BoxFuture;
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{
+    AttributeValue, DeleteItemInput, GetItemInput, QueryInput, ScanInput, UpdateItemError,
+    UpdateItemInput,
+};
+
+use ot::writing_proto::{DocumentSharingPermission, PurgeDocumentRequest, PurgeDocumentResponse};
+
+use crate::attachments;
+use crate::blob_store::BlobStore;
+use crate::documents;
+use crate::dynamodb::{self, av_get_s, av_map, av_n, av_s, table_name, DynamoDbClient};
+use crate::http::SessionUser;
+use crate::jobs::{self, Job, JobHandler};
+use crate::users::UserRole;
+use crate::utils::time;
+
+/// The `job_type` of document purge jobs in the `jobs` table.
+pub const PURGE_DOCUMENT_JOB_TYPE: &str = "purge_document";
+
+/// How many revisions the purge job reads and deletes at a time.
+const PURGE_REVISIONS_PAGE_SIZE: i64 = 500;
+
+/// Purge an archived document. The document is gone as soon as this returns. The rest of its data
+/// is removed in the background by a job, handled by `DocumentPurgeJobHandler`.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If the session user cannot edit the document, or is neither its owner nor an org admin,
+/// returns 403 Forbidden.
+///
+/// If the document is not archived, returns 400 Bad Request.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn purge_document(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &PurgeDocumentRequest,
+) -> actix_web::Result<PurgeDocumentResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [purge_document] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    let document = documents::get_document_if_some_permission_valid(
+        dynamodb_client,
+        session_user,
+        &request.doc_id,
+        &[DocumentSharingPermission::CanEdit],
+    )
+    .await?;
+    if document.owner_user_id != session_user.user_id.as_str()
+        && session_user.user_role != UserRole::OrgAdmin
+    {
+        return Err(error::ErrorForbidden(""));
+    }
+
+    // Mark the document first, so that the job never removes the data of a document that is not
+    // being purged. This also fails if the document was unarchived in the meantime.
+    let input = UpdateItemInput {
+        table_name: table_name("documents"),
+        key: av_map(&[av_s("id", &request.doc_id)]),
+        condition_expression: Some(String::from(
+            "org_id = :org_id AND attribute_exists(archived_at)",
+        )),
+        update_expression: Some(String::from("SET purged_at = :purged_at")),
+        expression_attribute_values: Some(av_map(&[
+            av_s(":org_id", session_user.org_id.as_str()),
+            av_s(":purged_at", &time::date_time_iso_str(&chrono::Utc::now())),
+        ])),
+        ..Default::default()
+    };
+    match dynamodb_client.update_item(input).await {
+        Ok(_) => {}
+        Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => {
+            return Err(error::ErrorBadRequest(""));
+        }
+        Err(e) => {
+            log_error(e.to_string());
+            return Err(error::ErrorInternalServerError(""));
+        }
+    }
+
+    let job_id = jobs::create_job(
+        dynamodb_client,
+        PURGE_DOCUMENT_JOB_TYPE,
+        session_user,
+        &[av_s("doc_id", &request.doc_id)],
+    )
+    .await
+    .map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+
+    // The job deletes the document item too, so if this fails, the document is still gone soon.
+    dynamodb_client
+        .delete_item(DeleteItemInput {
+            table_name: table_name("documents"),
+            key: av_map(&[av_s("id", &request.doc_id)]),
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+
+    Ok(PurgeDocumentResponse {
+        job_id: job_id.as_str().to_string(),
+    })
+}
+
+/// Removes the rest of a purged document's data, as started by `purge_document`. Every step can
+/// safely be run again.
+pub struct DocumentPurgeJobHandler {
+    pub attachment_store: Arc<dyn BlobStore>,
+}
+
+impl JobHandler for DocumentPurgeJobHandler {
+    fn job_type(&self) -> &'static str {
+        PURGE_DOCUMENT_JOB_TYPE
+    }
+
+    fn run<'a>(
+        &'a self,
+        dynamodb_client: &'a DynamoDbClient,
+        job: &'a Job,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(remove_document_data(
+            dynamodb_client,
+            self.attachment_store.as_ref(),
+            job,
+        ))
+    }
+}
+
+/// The number of steps in `remove_document_data`, for the job's progress.
+const NUM_PURGE_STEPS: i64 = 11;
+
+async fn remove_document_data(
+    dynamodb_client: &DynamoDbClient,
+    attachment_store: &dyn BlobStore,
+    job: &Job,
+) -> anyhow::Result<()> {
+    let job_id = job.job_id.as_str();
+    let doc_id =
+        av_get_s(&job.item, "doc_id").ok_or_else(|| anyhow::anyhow!("job has no doc_id"))?;
+    jobs::update_job(
+        dynamodb_client,
+        job_id,
+        &[av_n(":progress_total", NUM_PURGE_STEPS)],
+    )
+    .await?;
+    // Steps that an earlier attempt finished are skipped.
+    let done = job.progress_done;
+
+    let doc_id_values = av_map(&[av_s(":doc_id", doc_id)]);
+    let query_by_doc_id = |table: &str, projection: &str| QueryInput {
+        table_name: table_name(table),
+        key_condition_expression: Some(String::from("doc_id = :doc_id")),
+        expression_attribute_values: Some(doc_id_values.clone()),
+        projection_expression: Some(String::from(projection)),
+        ..Default::default()
+    };
+    // TODO(cliff): These tables have no index on the doc id, so we scan them, as account deletion
+    // does for user ids.
+    let scan_for_doc_id = |table: &str, projection: &str| ScanInput {
+        table_name: table_name(table),
+        filter_expression: Some(String::from("doc_id = :doc_id")),
+        expression_attribute_values: Some(doc_id_values.clone()),
+        projection_expression: Some(String::from(projection)),
+        ..Default::default()
+    };
+
+    if done < 1 {
+        let output = dynamodb_client
+            .get_item(GetItemInput {
+                table_name: table_name("documents"),
+                key: av_map(&[av_s("id", doc_id)]),
+                consistent_read: Some(true),
+                projection_expression: Some(String::from("id, purged_at")),
+                ..Default::default()
+            })
+            .await?;
+        if let Some(item) = output.item {
+            if av_get_s(&item, "purged_at").is_none() {
+                return Err(anyhow::anyhow!("document is not being purged"));
+            }
+            dynamodb_client
+                .delete_item(DeleteItemInput {
+                    table_name: table_name("documents"),
+                    key: av_map(&[av_s("id", doc_id)]),
+                    ..Default::default()
+                })
+                .await?;
+        }
+        jobs::set_job_progress(dynamodb_client, job_id, 1).await?;
+    }
+
+    if done < 2 {
+        let items = dynamodb::query_all_items(
+            dynamodb_client,
+            query_by_doc_id("document_user_sharing_permissions", "doc_id, user_id"),
+        )
+        .await?;
+        delete_items(
+            dynamodb_client,
+            "document_user_sharing_permissions",
+            &["doc_id", "user_id"],
+            &items,
+        )
+        .await?;
+        jobs::set_job_progress(dynamodb_client, job_id, 2).await?;
+    }
+
+    if done < 3 {
+        // Revisions can be many, so delete them a page at a time. Each page starts from whatever
+        // the previous pages left.
+        loop {
+            let output = dynamodb_client
+                .query(QueryInput {
+                    limit: Some(PURGE_REVISIONS_PAGE_SIZE),
+                    ..query_by_doc_id("document_revisions", "doc_id, revision_number")
+                })
+                .await?;
+            let items = output.items.unwrap_or_default();
+            if items.is_empty() {
+                break;
+            }
+            delete_items(
+                dynamodb_client,
+                "document_revisions",
+                &["doc_id", "revision_number"],
+                &items,
+            )
+            .await?;
+        }
+        jobs::set_job_progress(dynamodb_client, job_id, 3).await?;
+    }
+
+    if done < 4 {
+        let items = dynamodb::query_all_items(
+            dynamodb_client,
+            query_by_doc_id("document_followers", "doc_id, user_id"),
+        )
+        .await?;
+        delete_items(
+            dynamodb_client,
+            "document_followers",
+            &["doc_id", "user_id"],
+            &items,
+        )
+        .await?;
+        jobs::set_job_progress(dynamodb_client, job_id, 4).await?;
+    }
+
+    if done < 5 {
+        let items = dynamodb::query_all_items(
+            dynamodb_client,
+            query_by_doc_id("read_markers", "doc_id, user_id"),
+        )
+        .await?;
+        delete_items(
+            dynamodb_client,
+            "read_markers",
+            &["doc_id", "user_id"],
+            &items,
+        )
+        .await?;
+        jobs::set_job_progress(dynamodb_client, job_id, 5).await?;
+    }
+
+    if done < 6 {
+        dynamodb_client
+            .delete_item(DeleteItemInput {
+                table_name: table_name("document_stats"),
+                key: av_map(&[av_s("doc_id", doc_id)]),
+                ..Default::default()
+            })
+            .await?;
+        jobs::set_job_progress(dynamodb_client, job_id, 6).await?;
+    }
+
+    if done < 7 {
+        let items = dynamodb::scan_all_items(
+            dynamodb_client,
+            ScanInput {
+                // "token" is a reserved word.
+                expression_attribute_names: Some(maplit::hashmap! {
+                    String::from("#token") => String::from("token"),
+                }),
+                ..scan_for_doc_id("share_tokens", "#token")
+            },
+        )
+        .await?;
+        delete_items(dynamodb_client, "share_tokens", &["token"], &items).await?;
+        jobs::set_job_progress(dynamodb_client, job_id, 7).await?;
+    }
+
+    if done < 8 {
+        let items = dynamodb::scan_all_items(
+            dynamodb_client,
+            scan_for_doc_id("starred_documents", "user_id, doc_id"),
+        )
+        .await?;
+        delete_items(
+            dynamodb_client,
+            "starred_documents",
+            &["user_id", "doc_id"],
+            &items,
+        )
+        .await?;
+        jobs::set_job_progress(dynamodb_client, job_id, 8).await?;
+    }
+
+    if done < 9 {
+        let items = dynamodb::scan_all_items(
+            dynamodb_client,
+            scan_for_doc_id("document_views", "user_id, doc_id"),
+        )
+        .await?;
+        delete_items(
+            dynamodb_client,
+            "document_views",
+            &["user_id", "doc_id"],
+            &items,
+        )
+        .await?;
+        jobs::set_job_progress(dynamodb_client, job_id, 9).await?;
+    }
+
+    if done < 10 {
+        let items = dynamodb::scan_all_items(
+            dynamodb_client,
+            scan_for_doc_id("pending_notifications", "user_id, doc_id"),
+        )
+        .await?;
+        delete_items(
+            dynamodb_client,
+            "pending_notifications",
+            &["user_id", "doc_id"],
+            &items,
+        )
+        .await?;
+        jobs::set_job_progress(dynamodb_client, job_id, 10).await?;
+    }
+
+    attachments::delete_document_attachments(dynamodb_client, attachment_store, doc_id).await?;
+    jobs::set_job_progress(dynamodb_client, job_id, 11).await
+}
+
+/// Deletes the items, in batches, from a table whose key has the given attributes.
+async fn delete_items(
+    dynamodb_client: &DynamoDbClient,
+    table: &str,
+    key_names: &[&str],
+    items: &[HashMap<String, AttributeValue>],
+) -> anyhow::Result<()> {
+    let keys = items
+        .iter()
+        .map(|item| {
+            key_names
+                .iter()
+                .map(|key_name| match item.get(*key_name) {
+                    Some(value) => Ok((key_name.to_string(), value.clone())),
+                    None => Err(anyhow::anyhow!("item is missing key {}", key_name)),
+                })
+                .collect()
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    dynamodb::batch_delete_all_items(dynamodb_client, &table_name(table), keys).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ot::writing_proto::{
+        ArchiveDocumentRequest, ChangeSet, GetDocumentRequest, StarDocumentRequest,
+    };
+
+    use crate::archived_documents;
+    use crate::ids::{Id, IdType};
+    use crate::jobs::{JobStatus, JobWorker};
+    use crate::starred_documents;
+    use crate::testing::fixtures::{DocumentFixture, RevisionFixture};
+    use crate::testing::utils::{MemoryBlobStore, TestDynamoDb};
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[tokio::test]
+    async fn test_purge_document() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let org_id = Id::new(IdType::Organization);
+        let user_id = Id::new(IdType::User);
+        let editor_id = Id::new(IdType::User);
+        let session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let editor = SessionUser {
+            user_id: editor_id.clone(),
+            ..session_user.clone()
+        };
+        let mut change_set = ChangeSet::new();
+        change_set.insert("Hello");
+        let now = chrono::Utc::now();
+        let revisions: Vec<_> = (0..3)
+            .map(|_| RevisionFixture::new(&user_id, &change_set, &now))
+            .collect();
+        let doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_created_by_user_id(&user_id)
+            .with_revisions(revisions)
+            .with_sharing(&editor_id, DocumentSharingPermission::CanEdit);
+        doc.create(&db.dynamodb_client).await;
+        let doc_id = doc.doc_id.as_str().to_string();
+        let request = PurgeDocumentRequest {
+            doc_id: doc_id.clone(),
+        };
+        starred_documents::star_document(
+            &db.dynamodb_client,
+            &editor,
+            &StarDocumentRequest {
+                doc_id: doc_id.clone(),
+            },
+        )
+        .await?;
+
+        // Only archived documents can be purged.
+        let result = purge_document(&db.dynamodb_client, &session_user, &request).await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+        archived_documents::archive_document(
+            &db.dynamodb_client,
+            &session_user,
+            &ArchiveDocumentRequest {
+                doc_id: doc_id.clone(),
+            },
+        )
+        .await?;
+
+        // Editors other than the creator cannot purge.
+        let result = purge_document(&db.dynamodb_client, &editor, &request).await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+
+        let job_id = purge_document(&db.dynamodb_client, &session_user, &request)
+            .await?
+            .job_id;
+
+        // The document is gone right away.
+        let result = documents::get_document(
+            &db.dynamodb_client,
+            Some(&session_user),
+            &GetDocumentRequest {
+                doc_id: doc_id.clone(),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 404);
+
+        let mut worker = JobWorker::default();
+        worker.register(Arc::new(DocumentPurgeJobHandler {
+            attachment_store: Arc::new(MemoryBlobStore::default()),
+        }));
+        assert!(
+            worker
+                .run_next_job(&db.dynamodb_client, chrono::Utc::now())
+                .await?
+        );
+        let job = jobs::get_job(&db.dynamodb_client, &job_id).await?.unwrap();
+        assert_eq!(job.job_status, JobStatus::Complete);
+        assert_eq!(job.progress_done, NUM_PURGE_STEPS);
+
+        let count_items = |table: &str| {
+            let input = QueryInput {
+                table_name: table_name(table),
+                key_condition_expression: Some(String::from("doc_id = :doc_id")),
+                expression_attribute_values: Some(av_map(&[av_s(":doc_id", &doc_id)])),
+                ..Default::default()
+            };
+            let dynamodb_client = &db.dynamodb_client;
+            async move { dynamodb::query_all_items(dynamodb_client, input).await }
+        };
+        assert!(count_items("document_revisions").await?.is_empty());
+        assert!(count_items("document_user_sharing_permissions")
+            .await?
+            .is_empty());
+        let output = db
+            .dynamodb_client
+            .get_item(GetItemInput {
+                table_name: table_name("starred_documents"),
+                key: av_map(&[av_s("user_id", editor_id.as_str()), av_s("doc_id", &doc_id)]),
+                ..Default::default()
+            })
+            .await?;
+        assert!(output.item.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_purge_job_skips_documents_not_being_purged() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let user_id = Id::new(IdType::User);
+        let doc = DocumentFixture::new().with_created_by_user_id(&user_id);
+        doc.create(&db.dynamodb_client).await;
+        let session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: doc.org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let job_id = jobs::create_job(
+            &db.dynamodb_client,
+            PURGE_DOCUMENT_JOB_TYPE,
+            &session_user,
+            &[av_s("doc_id", doc.doc_id.as_str())],
+        )
+        .await?;
+
+        let mut worker = JobWorker::default();
+        worker.register(Arc::new(DocumentPurgeJobHandler {
+            attachment_store: Arc::new(MemoryBlobStore::default()),
+        }));
+        worker
+            .run_next_job(&db.dynamodb_client, chrono::Utc::now())
+            .await?;
+        let job = jobs::get_job(&db.dynamodb_client, job_id.as_str())
+            .await?
+            .unwrap();
+        assert_eq!(job.progress_done, 0);
+        let output = db
+            .dynamodb_client
+            .get_item(GetItemInput {
+                table_name: table_name("documents"),
+                key: av_map(&[av_s("id", doc.doc_id.as_str())]),
+                ..Default::default()
+            })
+            .await?;
+        assert!(output.item.is_some());
+
+        Ok(())
+    }
+}
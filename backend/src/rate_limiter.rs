@@ -0,0 +1,126 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Guests may make this many API requests per window, from each address. The editor's sync loop
+/// makes a request or two every couple of seconds, so this leaves headroom for normal editing,
+/// even for a couple of guests behind the same address.
+pub const GUEST_MAX_REQUESTS_PER_WINDOW: u32 = 120;
+pub const GUEST_RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Each requester may commit this many revisions to a document per window. The editor compresses
+/// its pending changes into one change set per sync round, so only a runaway client comes close.
+pub const EDIT_MAX_REVISIONS_PER_WINDOW: u32 = 30;
+pub const EDIT_RATE_LIMIT_WINDOW: Duration = Duration::from_secs(10);
+
+/// Each requester's typing heartbeats for a document are passed on at most this often. The editor
+/// sends one every couple of seconds while someone types.
+pub const TYPING_MAX_HEARTBEATS_PER_WINDOW: u32 = 1;
+pub const TYPING_RATE_LIMIT_WINDOW: Duration = Duration::from_secs(1);
+
+// When the limiter tracks more than this many keys, stale keys are swept out on the next call.
+const SWEEP_THRESHOLD: usize = 10_000;
+
+/// A sliding-window rate limiter. Each key may acquire up to `max_requests` permits in any span of
+/// one window. Unlike fixed windows, which reset all at once, this does not let a key spend two
+/// windows' budgets in a burst around the moment that one window ends and the next begins.
+///
+/// It keeps the time of each permit that is still in the window, so it is meant for small budgets.
+/// State is kept in memory, so limits apply per server rather than globally.
+pub struct RateLimiter {
+    max_requests: u32,
+    window: Duration,
+    acquired_at: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl RateLimiter {
+    pub fn new(max_requests: u32, window: Duration) -> Self {
+        Self {
+            max_requests,
+            window,
+            acquired_at: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns true if the key is within its budget for the last window, and counts this request
+    /// against it. Returns false if the key has used up its budget.
+    pub fn try_acquire(&self, key: &str) -> bool {
+        self.acquire(key).is_ok()
+    }
+
+    /// Like `try_acquire`, but if the key has used up its budget, returns how long until its
+    /// oldest permit leaves the window, and it may acquire another.
+    pub fn acquire(&self, key: &str) -> Result<(), Duration> {
+        let mut acquired_at = self.acquired_at.lock().unwrap();
+        let now = Instant::now();
+        let window = self.window;
+        if acquired_at.len() >= SWEEP_THRESHOLD {
+            acquired_at.retain(|_, times| match times.back() {
+                Some(last) => now.duration_since(*last) < window,
+                None => false,
+            });
+        }
+        let times = acquired_at.entry(key.to_string()).or_default();
+        while let Some(first) = times.front() {
+            if now.duration_since(*first) < window {
+                break;
+            }
+            times.pop_front();
+        }
+        if times.len() >= self.max_requests as usize {
+            let first = times.front().copied().unwrap_or(now);
+            return Err(window - now.duration_since(first));
+        }
+        times.push_back(now);
+        Ok(())
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(GUEST_MAX_REQUESTS_PER_WINDOW, GUEST_RATE_LIMIT_WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_acquire() {
+        let rate_limiter = RateLimiter::new(2, Duration::from_secs(60));
+        assert!(rate_limiter.try_acquire("a"));
+        assert!(rate_limiter.try_acquire("a"));
+        assert!(!rate_limiter.try_acquire("a"));
+        // Keys have separate budgets.
+        assert!(rate_limiter.try_acquire("b"));
+    }
+
+    #[test]
+    fn test_acquire_retry_after() {
+        let rate_limiter = RateLimiter::new(1, Duration::from_secs(60));
+        assert!(rate_limiter.acquire("a").is_ok());
+        let retry_after = rate_limiter.acquire("a").unwrap_err();
+        assert!(retry_after > Duration::from_secs(59));
+        assert!(retry_after <= Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_window_slides() {
+        let rate_limiter = RateLimiter::new(2, Duration::from_millis(200));
+        assert!(rate_limiter.try_acquire("a"));
+        std::thread::sleep(Duration::from_millis(120));
+        assert!(rate_limiter.try_acquire("a"));
+        // The first permit leaves the window before the second, so only one more fits in.
+        std::thread::sleep(Duration::from_millis(120));
+        assert!(rate_limiter.try_acquire("a"));
+        assert!(!rate_limiter.try_acquire("a"));
+    }
+
+    #[test]
+    fn test_window_resets() {
+        let rate_limiter = RateLimiter::new(1, Duration::from_secs(0));
+        assert!(rate_limiter.try_acquire("a"));
+        assert!(rate_limiter.try_acquire("a"));
+    }
+}
@@ -0,0 +1,327 @@
+//! Read receipts, so that the owner of a shared document can see who has seen its latest version.
+//!
+//! As the editor shows newer revisions of a document, it reports the latest one it has shown. We
+//! keep one read marker per reader and document, which only moves forward. Guests' markers keep the
+//! display name they chose, since they have no user item to look their name up in.
+
+use std::collections::HashMap;
+
+use actix_web::error;
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{AttributeValue, QueryInput, UpdateItemError, UpdateItemInput};
+
+use ot::writing_proto::{
+    DocumentSharingPermission, ListReadReceiptsRequest, ListReadReceiptsResponse, ReadReceipt,
+    ReportReadPositionRequest, ReportReadPositionResponse,
+};
+
+use crate::documents;
+use crate::dynamodb::{self, av_get_n, av_get_s, av_map, av_n, av_s, table_name, DynamoDbClient};
+use crate::http::{Requester, SessionUser};
+use crate::ids::Id;
+use crate::permission_cache::PermissionCache;
+use crate::users;
+use crate::utils::time;
+
+/// Record that the requester has seen the document up to the given revision. Reports of a revision
+/// older than the recorded one are ignored. The requester needs view or edit permission, either as
+/// the session user or through the share token.
+///
+/// If the revision number is negative, returns 400 Bad Request.
+///
+/// If there is no session user, and no valid share token, returns 401 Unauthorized.
+///
+/// If the user does not have permission to view the document, returns 403 Forbidden.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn report_read_position(
+    dynamodb_client: &DynamoDbClient,
+    permission_cache: &PermissionCache,
+    requester: &Requester,
+    request: &ReportReadPositionRequest,
+) -> actix_web::Result<ReportReadPositionResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [report_read_position] \
+            [requester: {:?}, request: {:?}]",
+            error_message,
+            requester,
+            request,
+        );
+    };
+    if request.revision_number < 0 {
+        return Err(error::ErrorBadRequest(""));
+    }
+    documents::validate_some_access_cached(
+        dynamodb_client,
+        permission_cache,
+        requester.session_user(),
+        &request.doc_id,
+        &request.share_token,
+        &[
+            DocumentSharingPermission::CanView,
+            DocumentSharingPermission::CanComment,
+            DocumentSharingPermission::CanEdit,
+        ],
+    )
+    .await?;
+    let mut values = vec![
+        av_n(":revision_number", request.revision_number),
+        av_s(":read_at", &time::date_time_iso_str(&chrono::Utc::now())),
+    ];
+    let update_expression = match requester {
+        Requester::User(_) => "SET revision_number = :revision_number, read_at = :read_at",
+        Requester::Guest(guest_user) => {
+            values.push(av_s(":display_name", &guest_user.display_name));
+            "SET revision_number = :revision_number, read_at = :read_at, \
+            display_name = :display_name"
+        }
+    };
+    let input = UpdateItemInput {
+        table_name: table_name("read_markers"),
+        key: av_map(&[
+            av_s("doc_id", &request.doc_id),
+            av_s("user_id", requester.id().as_str()),
+        ]),
+        condition_expression: Some(String::from(
+            "attribute_not_exists(revision_number) OR revision_number < :revision_number",
+        )),
+        update_expression: Some(String::from(update_expression)),
+        expression_attribute_values: Some(av_map(&values)),
+        ..Default::default()
+    };
+    match dynamodb_client.update_item(input).await {
+        Ok(_) | Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => {
+            Ok(ReportReadPositionResponse {})
+        }
+        Err(e) => {
+            log_error(e.to_string());
+            Err(error::ErrorInternalServerError(""))
+        }
+    }
+}
+
+/// List who has read the document, and how far. Only the document's owner can list its read
+/// receipts.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If the session user is not the document's owner, returns 403 Forbidden.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+///
+/// Upon success, returns the document's last revision number, and a receipt for each reader, with
+/// the readers of the last revision first.
+pub async fn list_read_receipts(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &ListReadReceiptsRequest,
+) -> actix_web::Result<ListReadReceiptsResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [list_read_receipts] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    let document = documents::get_document_if_some_permission_valid(
+        dynamodb_client,
+        session_user,
+        &request.doc_id,
+        &[
+            DocumentSharingPermission::CanView,
+            DocumentSharingPermission::CanComment,
+            DocumentSharingPermission::CanEdit,
+        ],
+    )
+    .await?;
+    if document.owner_user_id != session_user.user_id.as_str() {
+        return Err(error::ErrorForbidden(""));
+    }
+    let last_revision_number =
+        documents::get_last_revision_number(dynamodb_client, &request.doc_id)
+            .await
+            .map_err(|e| {
+                log_error(e.to_string());
+                error::ErrorInternalServerError("")
+            })?;
+    let items = dynamodb::query_all_items(
+        dynamodb_client,
+        QueryInput {
+            table_name: table_name("read_markers"),
+            key_condition_expression: Some(String::from("doc_id = :doc_id")),
+            expression_attribute_values: Some(av_map(&[av_s(":doc_id", &request.doc_id)])),
+            ..Default::default()
+        },
+    )
+    .await
+    .map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    let mut read_receipts = items
+        .iter()
+        .map(read_receipt_from_item)
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| {
+            log_error("read marker is missing a field".to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    // Guests' markers have their display names. Look up users' names.
+    let name_lookups = read_receipts
+        .iter()
+        .filter(|read_receipt| read_receipt.display_name.is_empty())
+        .filter_map(|read_receipt| Id::parse(&read_receipt.user_id))
+        .map(|user_id| async move {
+            let name = users::get_user_name(dynamodb_client, &user_id).await?;
+            Ok::<_, anyhow::Error>((user_id.as_str().to_string(), name))
+        });
+    let names: HashMap<String, String> = futures::future::try_join_all(name_lookups)
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?
+        .into_iter()
+        .collect();
+    for read_receipt in read_receipts.iter_mut() {
+        if let Some(name) = names.get(&read_receipt.user_id) {
+            read_receipt.display_name = name.clone();
+        }
+    }
+    read_receipts
+        .sort_by(|a, b| (b.revision_number, &b.read_at).cmp(&(a.revision_number, &a.read_at)));
+    Ok(ListReadReceiptsResponse {
+        last_revision_number,
+        read_receipts,
+    })
+}
+
+fn read_receipt_from_item(item: &HashMap<String, AttributeValue>) -> Option<ReadReceipt> {
+    Some(ReadReceipt {
+        user_id: av_get_s(item, "user_id")?.to_string(),
+        display_name: av_get_s(item, "display_name")
+            .unwrap_or_default()
+            .to_string(),
+        revision_number: av_get_n(item, "revision_number")?,
+        read_at: av_get_s(item, "read_at")?.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ot::writing_proto::ChangeSet;
+
+    use crate::ids::IdType;
+    use crate::testing::fixtures::{self, DocumentFixture, RevisionFixture};
+    use crate::testing::utils::TestDynamoDb;
+    use crate::users::UserRole;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[tokio::test]
+    async fn test_read_receipts() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let permission_cache = PermissionCache::default();
+
+        let owner_id =
+            fixtures::create_user(&db.dynamodb_client, "olive@example.com", "Olive").await;
+        let reader_id =
+            fixtures::create_user(&db.dynamodb_client, "rita@example.com", "Rita").await;
+        let mut hello = ChangeSet::new();
+        hello.insert("Hello");
+        let mut world = ChangeSet::new();
+        world.retain(5);
+        world.insert(" world");
+        let now = chrono::Utc::now();
+        let doc = DocumentFixture::new()
+            .with_created_by_user_id(&owner_id)
+            .with_org_level_sharing_permission(DocumentSharingPermission::CanView)
+            .with_revisions(vec![
+                RevisionFixture::new(&owner_id, &hello, &now),
+                RevisionFixture::new(&owner_id, &world, &now),
+            ]);
+        doc.create(&db.dynamodb_client).await;
+        let owner = SessionUser {
+            user_id: owner_id.clone(),
+            org_id: doc.org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let reader = Requester::User(SessionUser {
+            user_id: reader_id.clone(),
+            ..owner.clone()
+        });
+        let report = |revision_number| ReportReadPositionRequest {
+            doc_id: doc.doc_id.as_str().to_string(),
+            share_token: String::new(),
+            revision_number,
+        };
+        let list_request = ListReadReceiptsRequest {
+            doc_id: doc.doc_id.as_str().to_string(),
+        };
+
+        report_read_position(&db.dynamodb_client, &permission_cache, &reader, &report(2)).await?;
+        // Reports of older revisions are ignored.
+        report_read_position(&db.dynamodb_client, &permission_cache, &reader, &report(1)).await?;
+        let owner_requester = Requester::User(owner.clone());
+        report_read_position(
+            &db.dynamodb_client,
+            &permission_cache,
+            &owner_requester,
+            &report(1),
+        )
+        .await?;
+
+        let response = list_read_receipts(&db.dynamodb_client, &owner, &list_request).await?;
+        assert_eq!(response.last_revision_number, 2);
+        let receipts = response
+            .read_receipts
+            .iter()
+            .map(|r| {
+                (
+                    r.user_id.as_str(),
+                    r.display_name.as_str(),
+                    r.revision_number,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            receipts,
+            vec![
+                (reader_id.as_str(), "Rita", 2),
+                (owner_id.as_str(), "Olive", 1),
+            ]
+        );
+
+        // Only the owner can list read receipts.
+        let result = list_read_receipts(
+            &db.dynamodb_client,
+            reader.session_user().unwrap(),
+            &list_request,
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+
+        // Users in other orgs cannot report reading the document.
+        let outsider = Requester::User(SessionUser {
+            user_id: Id::new(IdType::User),
+            org_id: Id::new(IdType::Organization),
+            user_role: UserRole::Default,
+        });
+        let result = report_read_position(
+            &db.dynamodb_client,
+            &permission_cache,
+            &outsider,
+            &report(2),
+        )
+        .await;
+        assert!(result.is_err());
+        Ok(())
+    }
+}
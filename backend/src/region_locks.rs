@@ -0,0 +1,285 @@
+//! Region locks: hints about which part of a document someone is editing.
+//!
+//! A typing heartbeat (see `document_events`) may also name the region that its sender is editing,
+//! usually the paragraph around their cursor, as of the last revision they have. The other
+//! subscribers to the document get a `region_lock` event, and can warn their user before they edit
+//! the same region, to avoid conflicting edits. Nothing stops them from editing it anyway.
+//!
+//! This server remembers each document's region locks until they expire, so that subscribers who
+//! connect later hear about them too. It moves them through the revisions committed through it, so
+//! that they keep covering the same text. A lock that is announced at an older revision is moved
+//! up to date through the recent revisions, if the server has seen all of them.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use ot::writing_proto::{ChangeSet, Selection};
+
+/// A region lock lasts this long after the heartbeat that announced it. Heartbeats come every
+/// couple of seconds while someone types, so a lock expires soon after they stop.
+pub const REGION_LOCK_EXPIRY: Duration = Duration::from_secs(5);
+
+// How many of each locked document's latest change sets to keep, for moving locks announced at an
+// older revision up to date.
+const MAX_RECENT_CHANGE_SETS: usize = 32;
+
+// When this many documents have region locks, documents whose locks have all expired are swept out
+// on the next registration.
+const SWEEP_THRESHOLD: usize = 10_000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegionLock {
+    pub doc_id: String,
+    /// The org that the document belongs to.
+    pub org_id: String,
+    /// The user id, or the guest id for guests.
+    pub user_id: String,
+    pub display_name: String,
+    /// The revision of the document that the region is in.
+    pub revision_number: i64,
+    pub region: Selection,
+}
+
+#[derive(Default)]
+struct DocumentRegionLocks {
+    // By user id, with when each lock expires.
+    locks: HashMap<String, (RegionLock, Instant)>,
+    // The revision numbers and change sets of the latest revisions committed through this server,
+    // oldest first.
+    recent_change_sets: VecDeque<(i64, ChangeSet)>,
+}
+
+impl DocumentRegionLocks {
+    fn has_unexpired_locks(&self, now: Instant) -> bool {
+        self.locks.values().any(|(_, expires_at)| *expires_at > now)
+    }
+}
+
+/// The region locks of each document, on this server.
+#[derive(Default)]
+pub struct RegionLocks {
+    documents: Mutex<HashMap<String, DocumentRegionLocks>>,
+}
+
+impl RegionLocks {
+    /// Registers the lock, in place of any earlier lock by the same user on the document, until
+    /// `REGION_LOCK_EXPIRY` after `now`.
+    ///
+    /// Returns the lock as registered, moved up to the latest revision that it can be, or `None`
+    /// if the region has been deleted since.
+    pub fn register(&self, lock: RegionLock, now: Instant) -> Option<RegionLock> {
+        let mut documents = self.documents.lock().unwrap();
+        if documents.len() >= SWEEP_THRESHOLD {
+            documents.retain(|_, document| document.has_unexpired_locks(now));
+        }
+        let document = documents.entry(lock.doc_id.clone()).or_default();
+        document.locks.remove(&lock.user_id);
+        let lock = bring_up_to_date(&document.recent_change_sets, lock)?;
+        document.locks.insert(
+            lock.user_id.clone(),
+            (lock.clone(), now + REGION_LOCK_EXPIRY),
+        );
+        Some(lock)
+    }
+
+    /// Moves the document's locks through a revision that was just committed. Locks whose regions
+    /// it deletes are dropped.
+    pub fn on_revision_committed(
+        &self,
+        doc_id: &str,
+        revision_number: i64,
+        change_set: &ChangeSet,
+    ) {
+        let mut documents = self.documents.lock().unwrap();
+        let document = match documents.get_mut(doc_id) {
+            Some(document) => document,
+            // Nobody has locked a region of the document.
+            None => return,
+        };
+        document
+            .recent_change_sets
+            .push_back((revision_number, change_set.clone()));
+        if document.recent_change_sets.len() > MAX_RECENT_CHANGE_SETS {
+            document.recent_change_sets.pop_front();
+        }
+        let locks = std::mem::take(&mut document.locks);
+        for (user_id, (lock, expires_at)) in locks {
+            if let Some(lock) = bring_up_to_date(&document.recent_change_sets, lock) {
+                document.locks.insert(user_id, (lock, expires_at));
+            }
+        }
+    }
+
+    /// The document's unexpired locks, sorted by user id. Forgets the rest, and forgets the
+    /// document once it has no locks left.
+    pub fn active(&self, doc_id: &str, now: Instant) -> Vec<RegionLock> {
+        let mut documents = self.documents.lock().unwrap();
+        let document = match documents.get_mut(doc_id) {
+            Some(document) => document,
+            None => return Vec::new(),
+        };
+        document
+            .locks
+            .retain(|_, (_, expires_at)| *expires_at > now);
+        if document.locks.is_empty() {
+            documents.remove(doc_id);
+            return Vec::new();
+        }
+        let mut locks = document
+            .locks
+            .values()
+            .map(|(lock, _)| lock.clone())
+            .collect::<Vec<_>>();
+        locks.sort_by(|a, b| a.user_id.cmp(&b.user_id));
+        locks
+    }
+
+    /// The unexpired locks in the org's documents, sorted by document and user id, for admins.
+    pub fn active_in_org(&self, org_id: &str, now: Instant) -> Vec<RegionLock> {
+        let documents = self.documents.lock().unwrap();
+        let mut locks = documents
+            .values()
+            .flat_map(|document| document.locks.values())
+            .filter(|(lock, expires_at)| lock.org_id == org_id && *expires_at > now)
+            .map(|(lock, _)| lock.clone())
+            .collect::<Vec<_>>();
+        locks.sort_by(|a, b| (&a.doc_id, &a.user_id).cmp(&(&b.doc_id, &b.user_id)));
+        locks
+    }
+}
+
+/// Moves the lock through the recent change sets that follow its revision, stopping early at a
+/// revision that this server did not see. Returns `None` if the lock's region is deleted, or if a
+/// change set does not fit the region, which means the lock named the wrong revision.
+fn bring_up_to_date(
+    recent_change_sets: &VecDeque<(i64, ChangeSet)>,
+    mut lock: RegionLock,
+) -> Option<RegionLock> {
+    for (revision_number, change_set) in recent_change_sets {
+        if *revision_number <= lock.revision_number {
+            continue;
+        }
+        if *revision_number != lock.revision_number + 1 {
+            break;
+        }
+        lock.region = ot::transform_region(&lock.region, change_set).ok()??;
+        lock.revision_number = *revision_number;
+    }
+    Some(lock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region_lock(user_id: &str, revision_number: i64, offset: i64, count: i64) -> RegionLock {
+        RegionLock {
+            doc_id: String::from("d_1"),
+            org_id: String::from("o_1"),
+            user_id: user_id.to_string(),
+            display_name: user_id.to_uppercase(),
+            revision_number,
+            region: Selection { offset, count },
+        }
+    }
+
+    #[test]
+    fn test_locks_expire() {
+        let region_locks = RegionLocks::default();
+        let now = Instant::now();
+        region_locks.register(region_lock("u_2", 1, 0, 5), now);
+        region_locks.register(region_lock("u_1", 1, 6, 5), now + Duration::from_secs(2));
+        // A later lock by the same user replaces the earlier one.
+        region_locks.register(region_lock("u_1", 1, 12, 5), now + Duration::from_secs(3));
+        assert_eq!(
+            region_locks.active("d_1", now + Duration::from_secs(4)),
+            vec![region_lock("u_1", 1, 12, 5), region_lock("u_2", 1, 0, 5)]
+        );
+        assert_eq!(
+            region_locks.active("d_1", now + Duration::from_secs(6)),
+            vec![region_lock("u_1", 1, 12, 5)]
+        );
+        assert!(region_locks
+            .active("d_1", now + Duration::from_secs(9))
+            .is_empty());
+        assert!(region_locks.documents.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_active_in_org() {
+        let region_locks = RegionLocks::default();
+        let now = Instant::now();
+        let other_doc_lock = RegionLock {
+            doc_id: String::from("d_2"),
+            ..region_lock("u_1", 1, 0, 5)
+        };
+        let other_org_lock = RegionLock {
+            doc_id: String::from("d_3"),
+            org_id: String::from("o_2"),
+            ..region_lock("u_1", 1, 0, 5)
+        };
+        region_locks.register(other_doc_lock.clone(), now);
+        region_locks.register(other_org_lock.clone(), now);
+        region_locks.register(region_lock("u_2", 1, 0, 5), now);
+        region_locks.register(region_lock("u_1", 1, 6, 5), now + Duration::from_secs(2));
+        assert_eq!(
+            region_locks.active_in_org("o_1", now + Duration::from_secs(1)),
+            vec![
+                region_lock("u_1", 1, 6, 5),
+                region_lock("u_2", 1, 0, 5),
+                other_doc_lock,
+            ]
+        );
+        assert_eq!(
+            region_locks.active_in_org("o_1", now + Duration::from_secs(6)),
+            vec![region_lock("u_1", 1, 6, 5)]
+        );
+        assert_eq!(region_locks.active_in_org("o_2", now), vec![other_org_lock]);
+    }
+
+    #[test]
+    fn test_locks_move_with_revisions() {
+        let region_locks = RegionLocks::default();
+        let now = Instant::now();
+        // "First paragraph.\nSecond paragraph."
+        region_locks.register(region_lock("u_1", 1, 17, 17), now);
+        region_locks.register(region_lock("u_2", 1, 0, 16), now);
+
+        // Revision 2 deletes the first paragraph.
+        let mut change_set = ChangeSet::new();
+        change_set.delete(17);
+        change_set.retain(17);
+        region_locks.on_revision_committed("d_1", 2, &change_set);
+        assert_eq!(
+            region_locks.active("d_1", now),
+            vec![region_lock("u_1", 2, 0, 17)]
+        );
+
+        // Revision 3 appends to the second paragraph.
+        let mut change_set = ChangeSet::new();
+        change_set.retain(17);
+        change_set.insert(" More.");
+        region_locks.on_revision_committed("d_1", 3, &change_set);
+
+        // A lock announced before revision 2 is moved up to date. One announced before a revision
+        // that this server did not see stays where it is.
+        let lock = region_locks.register(region_lock("u_3", 1, 17, 0), now);
+        assert_eq!(lock, Some(region_lock("u_3", 3, 0, 0)));
+        let lock = region_locks.register(region_lock("u_4", 7, 3, 2), now);
+        assert_eq!(lock, Some(region_lock("u_4", 7, 3, 2)));
+        // A lock on a region that has been deleted since is not registered.
+        assert_eq!(
+            region_locks.register(region_lock("u_5", 1, 0, 16), now),
+            None
+        );
+        assert_eq!(
+            region_locks.active("d_1", now),
+            vec![
+                region_lock("u_1", 3, 0, 23),
+                region_lock("u_3", 3, 0, 0),
+                region_lock("u_4", 7, 3, 2),
+            ]
+        );
+    }
+}
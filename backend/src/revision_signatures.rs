@@ -0,0 +1,359 @@
+//! Signed revisions.
+//!
+//! Authors may sign their revisions with Ed25519 keys whose public halves they register with us.
+//! We store each signature with its revision as submitted, without checking it. Auditors check
+//! signatures later with `verify_document_revisions`, which rebuilds what the author signed from
+//! the revision log as it is now, so a revision that was changed after it was committed no longer
+//! verifies.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use actix_web::error;
+use bytes::Bytes;
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+use rusoto_dynamodb::{GetItemInput, PutItemInput};
+use sha2::{Digest, Sha256};
+
+use ot::writing_proto::{
+    revision_verification::Status, DocumentRevision, GetDocumentRevisionsRequest,
+    RegisterSigningKeyRequest, RegisterSigningKeyResponse, RevisionSignature, RevisionVerification,
+    VerifyDocumentRevisionsRequest, VerifyDocumentRevisionsResponse,
+};
+
+use crate::documents;
+use crate::dynamodb::{av_b, av_get_b, av_get_s, av_map, av_s, table_name, DynamoDbClient};
+use crate::http::SessionUser;
+use crate::ids::{Id, IdType};
+use crate::permission_cache::PermissionCache;
+use crate::users::UserRole;
+use crate::utils::{proto, time};
+
+/// Register a public key that the session user will sign their revisions with.
+///
+/// If the public key is not a valid Ed25519 public key, returns 400 Bad Request.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+///
+/// Upon success, returns the id to send with signatures made with the key.
+pub async fn register_signing_key(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &RegisterSigningKeyRequest,
+) -> actix_web::Result<RegisterSigningKeyResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [register_signing_key] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    PublicKey::from_bytes(&request.public_key).map_err(|_| error::ErrorBadRequest(""))?;
+    let key_id = Id::new(IdType::SigningKey);
+    let input = PutItemInput {
+        table_name: table_name("signing_keys"),
+        item: av_map(&[
+            av_s("key_id", key_id.as_str()),
+            av_s("user_id", session_user.user_id.as_str()),
+            av_s("org_id", session_user.org_id.as_str()),
+            av_b("public_key", Bytes::from(request.public_key.clone())),
+            av_s("created_at", &time::date_time_iso_str(&chrono::Utc::now())),
+        ]),
+        ..Default::default()
+    };
+    dynamodb_client.put_item(input).await.map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    Ok(RegisterSigningKeyResponse {
+        key_id: key_id.as_str().to_string(),
+    })
+}
+
+/// Check the signatures of the next page of revisions from the document's revision log.
+///
+/// If the session user is not an org admin, returns 403 Forbidden.
+///
+/// Otherwise, returns the same errors as `get_document_revisions`, and upon success, returns one
+/// verification for each revision in the page, signed or not.
+pub async fn verify_document_revisions(
+    dynamodb_client: &DynamoDbClient,
+    permission_cache: &PermissionCache,
+    session_user: &SessionUser,
+    request: &VerifyDocumentRevisionsRequest,
+) -> actix_web::Result<VerifyDocumentRevisionsResponse> {
+    if session_user.user_role != UserRole::OrgAdmin {
+        return Err(error::ErrorForbidden(""));
+    }
+    let revisions_response = documents::get_document_revisions(
+        dynamodb_client,
+        permission_cache,
+        Some(session_user),
+        &GetDocumentRevisionsRequest {
+            doc_id: request.doc_id.clone(),
+            after_revision_number: request.after_revision_number,
+            ..Default::default()
+        },
+    )
+    .await?;
+    // Authors usually sign all of their revisions with the same key, so look each key up once.
+    let mut signing_keys: HashMap<String, Option<SigningKey>> = HashMap::new();
+    let mut revisions = Vec::with_capacity(revisions_response.revisions.len());
+    for revision in revisions_response.revisions.iter() {
+        let signature = match &revision.signature {
+            Some(signature) => signature,
+            None => {
+                revisions.push(verification(revision, Status::Unsigned, ""));
+                continue;
+            }
+        };
+        if !signing_keys.contains_key(&signature.key_id) {
+            let signing_key = get_signing_key(dynamodb_client, &signature.key_id).await?;
+            signing_keys.insert(signature.key_id.clone(), signing_key);
+        }
+        let status = match &signing_keys[&signature.key_id] {
+            None => Status::UnknownKey,
+            Some(signing_key) if signing_key.user_id != revision.author_user_id => {
+                Status::KeyNotAuthors
+            }
+            Some(signing_key) if is_signature_valid(&signing_key.public_key, revision)? => {
+                Status::Valid
+            }
+            Some(_) => Status::InvalidSignature,
+        };
+        revisions.push(verification(revision, status, &signature.key_id));
+    }
+    Ok(VerifyDocumentRevisionsResponse {
+        last_revision_number: revisions_response.last_revision_number,
+        revisions,
+        end_of_revisions: revisions_response.end_of_revisions,
+    })
+}
+
+/// Returns false if the signature cannot be a valid Ed25519 signature, without looking up its key.
+pub fn is_well_formed(signature: &RevisionSignature) -> bool {
+    !signature.key_id.is_empty() && Signature::try_from(&signature.signature[..]).is_ok()
+}
+
+struct SigningKey {
+    user_id: String,
+    public_key: PublicKey,
+}
+
+async fn get_signing_key(
+    dynamodb_client: &DynamoDbClient,
+    key_id: &str,
+) -> actix_web::Result<Option<SigningKey>> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [get_signing_key] [key_id: {}]",
+            error_message,
+            key_id,
+        );
+    };
+    if key_id.is_empty() {
+        return Ok(None);
+    }
+    let output = dynamodb_client
+        .get_item(GetItemInput {
+            table_name: table_name("signing_keys"),
+            key: av_map(&[av_s("key_id", key_id)]),
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    let item = match output.item {
+        Some(item) => item,
+        None => return Ok(None),
+    };
+    let user_id = av_get_s(&item, "user_id");
+    let public_key = av_get_b(&item, "public_key").and_then(|b| PublicKey::from_bytes(b).ok());
+    match (user_id, public_key) {
+        (Some(user_id), Some(public_key)) => Ok(Some(SigningKey {
+            user_id: user_id.to_string(),
+            public_key,
+        })),
+        _ => {
+            log_error("signing key is missing a field".to_string());
+            Err(error::ErrorInternalServerError(""))
+        }
+    }
+}
+
+fn is_signature_valid(
+    public_key: &PublicKey,
+    revision: &DocumentRevision,
+) -> actix_web::Result<bool> {
+    let signature = match revision
+        .signature
+        .as_ref()
+        .and_then(|s| Signature::try_from(&s.signature[..]).ok())
+    {
+        Some(signature) => signature,
+        None => return Ok(false),
+    };
+    // Clients sign the change set as they submitted it. Encoding is deterministic, so re-encoding
+    // the stored change set gives back the same bytes.
+    let change_set_hash = if !revision.encrypted_change_set.is_empty() {
+        Sha256::digest(&revision.encrypted_change_set)
+    } else {
+        let change_set = revision.change_set.clone().unwrap_or_default();
+        let change_set_binary = proto::encode_protobuf_message(&change_set).map_err(|e| {
+            log::error!(
+                "Error occurred: \"{}\" [is_signature_valid] [doc_id: {}, revision_number: {}]",
+                e,
+                revision.doc_id,
+                revision.revision_number,
+            );
+            error::ErrorInternalServerError("")
+        })?;
+        Sha256::digest(&change_set_binary)
+    };
+    let message =
+        ot::revision_signing_message(&revision.doc_id, revision.revision_number, &change_set_hash);
+    Ok(public_key.verify(&message, &signature).is_ok())
+}
+
+fn verification(revision: &DocumentRevision, status: Status, key_id: &str) -> RevisionVerification {
+    RevisionVerification {
+        revision_number: revision.revision_number,
+        author_user_id: revision.author_user_id.clone(),
+        status: status.into(),
+        key_id: key_id.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ed25519_dalek::{Keypair, SecretKey, Signer};
+
+    use ot::writing_proto::{ChangeSet, SubmitDocumentChangeSetRequest};
+
+    use crate::http::Requester;
+    use crate::rate_limiter::RateLimiter;
+    use crate::testing::fixtures::DocumentFixture;
+    use crate::testing::utils::TestDynamoDb;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    fn sign(
+        keypair: &Keypair,
+        doc_id: &str,
+        revision_number: i64,
+        change_set: &ChangeSet,
+    ) -> Vec<u8> {
+        let change_set_binary = proto::encode_protobuf_message(change_set).unwrap();
+        let message = ot::revision_signing_message(
+            doc_id,
+            revision_number,
+            &Sha256::digest(&change_set_binary),
+        );
+        keypair.sign(&message).to_bytes().to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_verify_document_revisions() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let user_id = Id::new(IdType::User);
+        let doc = DocumentFixture::new().with_created_by_user_id(&user_id);
+        doc.create(&db.dynamodb_client).await;
+        let doc_id = doc.doc_id.as_str();
+        let session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: doc.org_id.clone(),
+            user_role: UserRole::OrgAdmin,
+        };
+        let permission_cache = PermissionCache::default();
+
+        let secret = SecretKey::from_bytes(&[1u8; 32])?;
+        let public = PublicKey::from(&secret);
+        let keypair = Keypair { secret, public };
+        let key_id = register_signing_key(
+            &db.dynamodb_client,
+            &session_user,
+            &RegisterSigningKeyRequest {
+                public_key: public.to_bytes().to_vec(),
+            },
+        )
+        .await?
+        .key_id;
+
+        // Revision 1 is signed correctly. Revision 2 is signed as if it were revision 1. Revision
+        // 3 is not signed. Revision 4 is signed with a key that was never registered.
+        let signatures = vec![
+            Some((key_id.clone(), 1)),
+            Some((key_id.clone(), 1)),
+            None,
+            Some(("sk_unknown".to_string(), 4)),
+        ];
+        for (i, signature) in signatures.into_iter().enumerate() {
+            let mut change_set = ChangeSet::new();
+            change_set.retain(i as i64);
+            change_set.insert("a");
+            let signature = signature.map(|(key_id, revision_number)| RevisionSignature {
+                key_id,
+                signature: sign(&keypair, doc_id, revision_number, &change_set),
+            });
+            documents::submit_document_change_set(
+                &db.dynamodb_client,
+                &permission_cache,
+                &RateLimiter::default(),
+                &Requester::User(session_user.clone()),
+                &SubmitDocumentChangeSetRequest {
+                    doc_id: doc_id.to_string(),
+                    on_revision_number: i as i64,
+                    change_set: Some(change_set),
+                    signature,
+                    ..Default::default()
+                },
+            )
+            .await?;
+        }
+
+        let request = VerifyDocumentRevisionsRequest {
+            doc_id: doc_id.to_string(),
+            after_revision_number: 0,
+        };
+        let response = verify_document_revisions(
+            &db.dynamodb_client,
+            &permission_cache,
+            &session_user,
+            &request,
+        )
+        .await?;
+        let statuses: Vec<Status> = response
+            .revisions
+            .iter()
+            .map(|revision| revision.status())
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                Status::Valid,
+                Status::InvalidSignature,
+                Status::Unsigned,
+                Status::UnknownKey
+            ]
+        );
+
+        // Only org admins can verify revisions.
+        let non_admin = SessionUser {
+            user_role: UserRole::Default,
+            ..session_user.clone()
+        };
+        let result =
+            verify_document_revisions(&db.dynamodb_client, &permission_cache, &non_admin, &request)
+                .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,470 @@
+//! The revision stream processor.
+//!
+//! Data derived from revisions, like followers' pending notifications and when each document was
+//! last edited, is maintained by reading the `document_revisions` table's DynamoDB stream, instead
+//! of being written while the submit request waits. Exactly one process runs the processor: see the
+//! `stream_processor` flag.
+//!
+//! The processor polls every shard of the stream, and passes each committed revision to every
+//! registered handler. After each batch of records, it records how far it got in the
+//! `stream_checkpoints` table. If a handler fails, the processor stops reading that shard, and
+//! starts again from the last checkpoint on the next pass, so handlers may see a revision more than
+//! once and must be idempotent. A shard is not read until its parent shard has been read to the
+//! end, so each document's revisions are handled in order.
+//!
+//! To maintain a new kind of derived data, implement `RevisionStreamHandler` for it and register
+//! the handler in `main`.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{DescribeTableInput, DynamoDb, QueryInput, UpdateItemInput};
+use rusoto_dynamodbstreams::{
+    AttributeValue as StreamAttributeValue, DescribeStreamInput, DynamoDbStreams,
+    DynamoDbStreamsClient, GetRecordsInput, GetShardIteratorError, GetShardIteratorInput, Record,
+    Shard,
+};
+
+use crate::dynamodb::{av_get_s, av_map, av_s, query_all_items, table_name, DynamoDbClient};
+use crate::utils::time;
+
+/// How often the processor looks for new records once it has caught up.
+pub const STREAM_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The most records to read from a shard at once.
+const GET_RECORDS_LIMIT: i64 = 100;
+
+/// A revision, as read from the stream. Only the revision's metadata is included, not its change
+/// set.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommittedRevision {
+    pub doc_id: String,
+    pub revision_number: i64,
+    pub author_user_id: String,
+    pub committed_at: String,
+}
+
+/// Maintains one kind of data derived from revisions.
+pub trait RevisionStreamHandler: Send + Sync {
+    /// A short name for log messages.
+    fn name(&self) -> &'static str;
+
+    /// Handles a committed revision. Returning an error stops the processor from reading further,
+    /// and the revision is handled again on a later pass.
+    fn handle<'a>(
+        &'a self,
+        dynamodb_client: &'a DynamoDbClient,
+        revision: &'a CommittedRevision,
+    ) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+/// Passes the revisions in the stream to the registered handlers.
+#[derive(Default)]
+pub struct RevisionStreamProcessor {
+    handlers: Vec<Arc<dyn RevisionStreamHandler>>,
+}
+
+impl RevisionStreamProcessor {
+    pub fn register(&mut self, handler: Arc<dyn RevisionStreamHandler>) {
+        self.handlers.push(handler);
+    }
+
+    /// Passes a revision to every handler, stopping at the first handler that fails.
+    pub async fn handle_revision(
+        &self,
+        dynamodb_client: &DynamoDbClient,
+        revision: &CommittedRevision,
+    ) -> anyhow::Result<()> {
+        for handler in self.handlers.iter() {
+            handler
+                .handle(dynamodb_client, revision)
+                .await
+                .map_err(|e| anyhow::anyhow!("{} handler failed: {}", handler.name(), e))?;
+        }
+        Ok(())
+    }
+
+    /// Reads every shard of the stream up to its latest record. Shards whose parents are not yet
+    /// read to the end are left for a later pass.
+    pub async fn process_stream(
+        &self,
+        dynamodb_client: &DynamoDbClient,
+        streams_client: &dyn DynamoDbStreams,
+        stream_arn: &str,
+    ) -> anyhow::Result<()> {
+        let shards = describe_shards(streams_client, stream_arn).await?;
+        let shard_ids = shards
+            .iter()
+            .filter_map(|shard| shard.shard_id.clone())
+            .collect::<HashSet<_>>();
+        let mut checkpoints = get_checkpoints(dynamodb_client, stream_arn).await?;
+        for shard in shards.iter() {
+            let shard_id = match &shard.shard_id {
+                Some(shard_id) => shard_id,
+                None => continue,
+            };
+            // Parents that are no longer listed have been trimmed from the stream, so there is
+            // nothing left in them to wait for.
+            if let Some(parent_shard_id) = &shard.parent_shard_id {
+                let parent_finished = matches!(
+                    checkpoints.get(parent_shard_id),
+                    Some(checkpoint) if checkpoint.finished
+                );
+                if shard_ids.contains(parent_shard_id) && !parent_finished {
+                    continue;
+                }
+            }
+            let checkpoint = checkpoints.entry(shard_id.clone()).or_default();
+            if checkpoint.finished {
+                continue;
+            }
+            self.process_shard(
+                dynamodb_client,
+                streams_client,
+                stream_arn,
+                shard_id,
+                checkpoint,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Reads the shard from its checkpoint up to its latest record, updating the checkpoint as it
+    /// goes.
+    async fn process_shard(
+        &self,
+        dynamodb_client: &DynamoDbClient,
+        streams_client: &dyn DynamoDbStreams,
+        stream_arn: &str,
+        shard_id: &str,
+        checkpoint: &mut Checkpoint,
+    ) -> anyhow::Result<()> {
+        let mut shard_iterator = get_shard_iterator(
+            streams_client,
+            stream_arn,
+            shard_id,
+            checkpoint.sequence_number.as_deref(),
+        )
+        .await?;
+        loop {
+            let iterator = match shard_iterator {
+                Some(iterator) => iterator,
+                None => {
+                    // The shard is closed, and every record in it has been handled.
+                    put_checkpoint(dynamodb_client, stream_arn, shard_id, None, true).await?;
+                    checkpoint.finished = true;
+                    return Ok(());
+                }
+            };
+            let output = streams_client
+                .get_records(GetRecordsInput {
+                    shard_iterator: iterator,
+                    limit: Some(GET_RECORDS_LIMIT),
+                })
+                .await?;
+            let records = output.records.unwrap_or_default();
+            let mut result = Ok(());
+            let mut last_sequence_number = None;
+            for record in records.iter() {
+                if let Some(revision) = revision_from_record(record) {
+                    result = self.handle_revision(dynamodb_client, &revision).await;
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                last_sequence_number = record_sequence_number(record);
+            }
+            // Save progress even if a handler failed, so the records before it are not handled
+            // again.
+            if let Some(sequence_number) = last_sequence_number {
+                put_checkpoint(
+                    dynamodb_client,
+                    stream_arn,
+                    shard_id,
+                    Some(sequence_number),
+                    false,
+                )
+                .await?;
+                checkpoint.sequence_number = Some(sequence_number.to_string());
+            }
+            result?;
+            // An open shard with no new records has been caught up with.
+            if records.is_empty() && output.next_shard_iterator.is_some() {
+                return Ok(());
+            }
+            shard_iterator = output.next_shard_iterator;
+        }
+    }
+}
+
+/// Runs the processor forever, making a pass over the stream every `STREAM_POLL_INTERVAL`.
+pub async fn run_revision_stream_processor(
+    dynamodb_client: Arc<DynamoDbClient>,
+    streams_client: Arc<DynamoDbStreamsClient>,
+    processor: Arc<RevisionStreamProcessor>,
+) {
+    let mut interval = tokio::time::interval(STREAM_POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        // The stream changes if it is ever disabled and enabled again, so look it up every time.
+        let result = match latest_stream_arn(&dynamodb_client).await {
+            Ok(stream_arn) => {
+                processor
+                    .process_stream(&dynamodb_client, streams_client.as_ref(), &stream_arn)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            log::error!("Error occurred: \"{}\" [run_revision_stream_processor]", e);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Checkpoint {
+    sequence_number: Option<String>,
+    finished: bool,
+}
+
+/// Reads the revision from a record of a new revision. Returns `None` for records that are not
+/// new revisions. Revisions are never updated in place, so those are only deletions.
+fn revision_from_record(record: &Record) -> Option<CommittedRevision> {
+    if record.event_name.as_deref() != Some("INSERT") {
+        return None;
+    }
+    let image = record.dynamodb.as_ref()?.new_image.as_ref();
+    let revision = image.and_then(revision_from_image);
+    if revision.is_none() {
+        log::error!(
+            "Error occurred: \"revision is missing a field\" [revision_from_record] \
+            [event_id: {:?}]",
+            record.event_id,
+        );
+    }
+    revision
+}
+
+fn revision_from_image(image: &HashMap<String, StreamAttributeValue>) -> Option<CommittedRevision> {
+    let get_s = |key: &str| image.get(key)?.s.clone();
+    Some(CommittedRevision {
+        doc_id: get_s("doc_id")?,
+        revision_number: image.get("revision_number")?.n.as_ref()?.parse().ok()?,
+        author_user_id: get_s("author_user_id")?,
+        committed_at: get_s("committed_at")?,
+    })
+}
+
+fn record_sequence_number(record: &Record) -> Option<&str> {
+    record.dynamodb.as_ref()?.sequence_number.as_deref()
+}
+
+async fn latest_stream_arn(dynamodb_client: &DynamoDbClient) -> anyhow::Result<String> {
+    let output = dynamodb_client
+        .describe_table(DescribeTableInput {
+            table_name: table_name("document_revisions"),
+        })
+        .await?;
+    output
+        .table
+        .and_then(|table| table.latest_stream_arn)
+        .ok_or_else(|| anyhow::anyhow!("document_revisions has no stream"))
+}
+
+async fn describe_shards(
+    streams_client: &dyn DynamoDbStreams,
+    stream_arn: &str,
+) -> anyhow::Result<Vec<Shard>> {
+    let mut shards = Vec::new();
+    let mut exclusive_start_shard_id = None;
+    loop {
+        let output = streams_client
+            .describe_stream(DescribeStreamInput {
+                stream_arn: stream_arn.to_string(),
+                exclusive_start_shard_id,
+                ..Default::default()
+            })
+            .await?;
+        let description = output
+            .stream_description
+            .ok_or_else(|| anyhow::anyhow!("Stream has no description"))?;
+        shards.extend(description.shards.unwrap_or_default());
+        exclusive_start_shard_id = description.last_evaluated_shard_id;
+        if exclusive_start_shard_id.is_none() {
+            return Ok(shards);
+        }
+    }
+}
+
+/// Returns an iterator that starts after the checkpoint, or at the oldest record in the shard if
+/// there is no checkpoint.
+async fn get_shard_iterator(
+    streams_client: &dyn DynamoDbStreams,
+    stream_arn: &str,
+    shard_id: &str,
+    sequence_number: Option<&str>,
+) -> anyhow::Result<Option<String>> {
+    let input = GetShardIteratorInput {
+        stream_arn: stream_arn.to_string(),
+        shard_id: shard_id.to_string(),
+        shard_iterator_type: String::from("TRIM_HORIZON"),
+        sequence_number: None,
+    };
+    let sequence_number = match sequence_number {
+        Some(sequence_number) => sequence_number,
+        None => {
+            return Ok(streams_client
+                .get_shard_iterator(input)
+                .await?
+                .shard_iterator)
+        }
+    };
+    let after_checkpoint = GetShardIteratorInput {
+        shard_iterator_type: String::from("AFTER_SEQUENCE_NUMBER"),
+        sequence_number: Some(sequence_number.to_string()),
+        ..input.clone()
+    };
+    match streams_client.get_shard_iterator(after_checkpoint).await {
+        Ok(output) => Ok(output.shard_iterator),
+        // The processor fell so far behind that the stream dropped records it had not read yet.
+        // Those records are lost. Carry on from the oldest one left.
+        Err(RusotoError::Service(GetShardIteratorError::TrimmedDataAccess(e))) => {
+            log::error!(
+                "Error occurred: \"{}\" [get_shard_iterator] \
+                [shard_id: {}, sequence_number: {}]",
+                e,
+                shard_id,
+                sequence_number,
+            );
+            Ok(streams_client
+                .get_shard_iterator(input)
+                .await?
+                .shard_iterator)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn get_checkpoints(
+    dynamodb_client: &DynamoDbClient,
+    stream_arn: &str,
+) -> anyhow::Result<HashMap<String, Checkpoint>> {
+    let items = query_all_items(
+        dynamodb_client,
+        QueryInput {
+            table_name: table_name("stream_checkpoints"),
+            key_condition_expression: Some(String::from("stream_arn = :stream_arn")),
+            expression_attribute_values: Some(av_map(&[av_s(":stream_arn", stream_arn)])),
+            ..Default::default()
+        },
+    )
+    .await?;
+    Ok(items
+        .iter()
+        .filter_map(|item| {
+            let checkpoint = Checkpoint {
+                sequence_number: av_get_s(item, "sequence_number").map(String::from),
+                finished: item.contains_key("finished_at"),
+            };
+            Some((av_get_s(item, "shard_id")?.to_string(), checkpoint))
+        })
+        .collect())
+}
+
+async fn put_checkpoint(
+    dynamodb_client: &DynamoDbClient,
+    stream_arn: &str,
+    shard_id: &str,
+    sequence_number: Option<&str>,
+    finished: bool,
+) -> anyhow::Result<()> {
+    let now = time::date_time_iso_str(&chrono::Utc::now());
+    let mut update_expression = String::from("SET updated_at = :now");
+    let mut values = vec![av_s(":now", &now)];
+    if let Some(sequence_number) = sequence_number {
+        update_expression.push_str(", sequence_number = :sequence_number");
+        values.push(av_s(":sequence_number", sequence_number));
+    }
+    if finished {
+        update_expression.push_str(", finished_at = :now");
+    }
+    dynamodb_client
+        .update_item(UpdateItemInput {
+            table_name: table_name("stream_checkpoints"),
+            key: av_map(&[av_s("stream_arn", stream_arn), av_s("shard_id", shard_id)]),
+            update_expression: Some(update_expression),
+            expression_attribute_values: Some(av_map(&values)),
+            ..Default::default()
+        })
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rusoto_dynamodbstreams::StreamRecord;
+
+    fn stream_s(value: &str) -> StreamAttributeValue {
+        StreamAttributeValue {
+            s: Some(value.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn insert_record(image: HashMap<String, StreamAttributeValue>) -> Record {
+        Record {
+            event_name: Some(String::from("INSERT")),
+            dynamodb: Some(StreamRecord {
+                new_image: Some(image),
+                sequence_number: Some(String::from("100")),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_revision_from_record() {
+        let mut image = HashMap::new();
+        image.insert(String::from("doc_id"), stream_s("d_1"));
+        image.insert(String::from("author_user_id"), stream_s("u_1"));
+        image.insert(
+            String::from("committed_at"),
+            stream_s("2021-01-01T00:00:00.000Z"),
+        );
+        image.insert(
+            String::from("revision_number"),
+            StreamAttributeValue {
+                n: Some(String::from("7")),
+                ..Default::default()
+            },
+        );
+        let record = insert_record(image.clone());
+        assert_eq!(record_sequence_number(&record), Some("100"));
+        assert_eq!(
+            revision_from_record(&record),
+            Some(CommittedRevision {
+                doc_id: String::from("d_1"),
+                revision_number: 7,
+                author_user_id: String::from("u_1"),
+                committed_at: String::from("2021-01-01T00:00:00.000Z"),
+            })
+        );
+
+        // Deletions are not revisions.
+        let record = Record {
+            event_name: Some(String::from("REMOVE")),
+            ..insert_record(image.clone())
+        };
+        assert_eq!(revision_from_record(&record), None);
+
+        image.remove("author_user_id");
+        assert_eq!(revision_from_record(&insert_record(image)), None);
+    }
+}
@@ -0,0 +1,502 @@
+//! Public link sharing.
+//!
+//! A share token is an unguessable identifier that grants a permission on one document to anyone
+//! who has it: "anyone with the link can view" or "anyone with the link can edit". Tokens may have
+//! an expiry, and can be revoked by anyone who can edit the document.
+
+use actix_web::error;
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{DeleteItemInput, GetItemInput, PutItemError, PutItemInput};
+
+use ot::writing_proto::{
+    CreateShareTokenRequest, CreateShareTokenResponse, DocumentSharingPermission,
+    RevokeShareTokenRequest, RevokeShareTokenResponse,
+};
+
+use crate::documents;
+use crate::dynamodb::{av_get_n, av_get_s, av_map, av_n, av_s, table_name, DynamoDbClient};
+use crate::http::SessionUser;
+use crate::ids::{Id, IdType};
+use crate::utils::time;
+
+/// Create a share token for a document.
+///
+/// If the permission is not `CanView`, `CanComment` or `CanEdit`, or the expiry is negative,
+/// returns 400 Bad Request.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If the session user does not have permission to edit the document, returns 403 Forbidden.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+///
+/// Upon success, returns the new token and its expiry.
+pub async fn create_share_token(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &CreateShareTokenRequest,
+) -> actix_web::Result<CreateShareTokenResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [create_share_token] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    let permission = match DocumentSharingPermission::from_i32(request.permission) {
+        Some(DocumentSharingPermission::CanView) => DocumentSharingPermission::CanView,
+        Some(DocumentSharingPermission::CanComment) => DocumentSharingPermission::CanComment,
+        Some(DocumentSharingPermission::CanEdit) => DocumentSharingPermission::CanEdit,
+        _ => return Err(error::ErrorBadRequest("")),
+    };
+    if request.expires_in_seconds < 0 {
+        return Err(error::ErrorBadRequest(""));
+    }
+    documents::get_document_if_some_permission_valid(
+        dynamodb_client,
+        session_user,
+        &request.doc_id,
+        &[DocumentSharingPermission::CanEdit],
+    )
+    .await?;
+
+    let token = Id::new(IdType::ShareToken);
+    let now = chrono::Utc::now();
+    let expires_at = if request.expires_in_seconds > 0 {
+        time::date_time_iso_str(&(now + chrono::Duration::seconds(request.expires_in_seconds)))
+    } else {
+        String::new()
+    };
+    let mut item = av_map(&[
+        av_s("token", token.as_str()),
+        av_s("doc_id", &request.doc_id),
+        av_s("org_id", session_user.org_id.as_str()),
+        av_n("permission", permission as i32),
+        av_s("created_by_user_id", session_user.user_id.as_str()),
+        av_s("created_at", &time::date_time_iso_str(&now)),
+    ]);
+    if !expires_at.is_empty() {
+        let (key, value) = av_s("expires_at", &expires_at);
+        item.insert(key, value);
+    }
+    let input = PutItemInput {
+        table_name: table_name("share_tokens"),
+        item,
+        // Guard against the astronomically unlikely token collision.
+        condition_expression: Some(String::from("attribute_not_exists(token)")),
+        ..Default::default()
+    };
+    match dynamodb_client.put_item(input).await {
+        Ok(_) => Ok(CreateShareTokenResponse {
+            token: token.as_str().to_string(),
+            expires_at,
+        }),
+        Err(RusotoError::Service(PutItemError::ConditionalCheckFailed(_))) => {
+            log_error("Share token collision".to_string());
+            Err(error::ErrorInternalServerError(""))
+        }
+        Err(e) => {
+            log_error(e.to_string());
+            Err(error::ErrorInternalServerError(""))
+        }
+    }
+}
+
+/// Revoke a share token. Anyone holding the token immediately loses the access it granted.
+///
+/// If the token does not exist, or belongs to a document in another org, returns 404 Not Found.
+///
+/// If the session user does not have permission to edit the token's document, returns 403
+/// Forbidden.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn revoke_share_token(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &RevokeShareTokenRequest,
+) -> actix_web::Result<RevokeShareTokenResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [revoke_share_token] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    if request.token.is_empty() {
+        return Err(error::ErrorBadRequest(""));
+    }
+    let output = dynamodb_client
+        .get_item(GetItemInput {
+            table_name: table_name("share_tokens"),
+            key: av_map(&[av_s("token", &request.token)]),
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    let item = output.item.ok_or_else(|| error::ErrorNotFound(""))?;
+    if av_get_s(&item, "org_id") != Some(session_user.org_id.as_str()) {
+        return Err(error::ErrorNotFound(""));
+    }
+    let doc_id = av_get_s(&item, "doc_id").ok_or_else(|| {
+        log_error("share token is missing doc_id".to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    documents::get_document_if_some_permission_valid(
+        dynamodb_client,
+        session_user,
+        doc_id,
+        &[DocumentSharingPermission::CanEdit],
+    )
+    .await?;
+
+    dynamodb_client
+        .delete_item(DeleteItemInput {
+            table_name: table_name("share_tokens"),
+            key: av_map(&[av_s("token", &request.token)]),
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    Ok(RevokeShareTokenResponse {})
+}
+
+/// A valid, unexpired share token.
+#[derive(Debug)]
+pub struct ShareToken {
+    pub doc_id: String,
+    pub permission: DocumentSharingPermission,
+}
+
+/// Look up a share token.
+///
+/// If the token does not exist or has expired, returns 403 Forbidden.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn get_share_token(
+    dynamodb_client: &DynamoDbClient,
+    share_token: &str,
+) -> actix_web::Result<ShareToken> {
+    let log_error = |error_message: String| {
+        log::error!("Error occurred: \"{}\" [get_share_token]", error_message,);
+    };
+    if share_token.is_empty() {
+        return Err(error::ErrorForbidden(""));
+    }
+    let output = dynamodb_client
+        .get_item(GetItemInput {
+            table_name: table_name("share_tokens"),
+            key: av_map(&[av_s("token", share_token)]),
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    let item = output.item.ok_or_else(|| error::ErrorForbidden(""))?;
+    // Date-times are all stored in the same ISO 8601 format, so they compare correctly as strings.
+    if let Some(expires_at) = av_get_s(&item, "expires_at") {
+        if expires_at <= time::date_time_iso_str(&chrono::Utc::now()).as_str() {
+            return Err(error::ErrorForbidden(""));
+        }
+    }
+    let doc_id = av_get_s(&item, "doc_id").ok_or_else(|| {
+        log_error("share token is missing doc_id".to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    let permission_val: i32 = av_get_n(&item, "permission").ok_or_else(|| {
+        log_error("share token is missing permission".to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    let permission = DocumentSharingPermission::from_i32(permission_val).ok_or_else(|| {
+        log_error(format!(
+            "Detected invalid share token permission in DB! Value: {}",
+            permission_val
+        ));
+        error::ErrorInternalServerError("")
+    })?;
+    Ok(ShareToken {
+        doc_id: doc_id.to_string(),
+        permission,
+    })
+}
+
+/// Look up the permission that a share token grants on the given document.
+///
+/// If the token does not exist, has expired, or was created for a different document, returns 403
+/// Forbidden.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn get_share_token_permission(
+    dynamodb_client: &DynamoDbClient,
+    share_token: &str,
+    doc_id: &str,
+) -> actix_web::Result<DocumentSharingPermission> {
+    let share_token = get_share_token(dynamodb_client, share_token).await?;
+    if share_token.doc_id != doc_id {
+        return Err(error::ErrorForbidden(""));
+    }
+    Ok(share_token.permission)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ot::writing_proto::{
+        ChangeSet, GetDocumentRevisionsRequest, SubmitDocumentChangeSetRequest,
+    };
+
+    use crate::http::{GuestUser, Requester};
+    use crate::permission_cache::PermissionCache;
+    use crate::rate_limiter::RateLimiter;
+    use crate::testing::fixtures::DocumentFixture;
+    use crate::testing::utils::TestDynamoDb;
+    use crate::users::UserRole;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    fn new_session_user(org_id: &Id) -> SessionUser {
+        SessionUser {
+            user_id: Id::new(IdType::User),
+            org_id: org_id.clone(),
+            user_role: UserRole::Default,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_share_token_lifecycle() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let org_id = Id::new(IdType::Organization);
+        let owner = new_session_user(&org_id);
+        let doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_created_by_user_id(&owner.user_id);
+        doc.create(&db.dynamodb_client).await;
+
+        // Only editors may create tokens.
+        let result = create_share_token(
+            &db.dynamodb_client,
+            &new_session_user(&org_id),
+            &CreateShareTokenRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                permission: DocumentSharingPermission::CanView.into(),
+                expires_in_seconds: 0,
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+
+        let response = create_share_token(
+            &db.dynamodb_client,
+            &owner,
+            &CreateShareTokenRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                permission: DocumentSharingPermission::CanView.into(),
+                expires_in_seconds: 3600,
+            },
+        )
+        .await?;
+        assert!(response.token.starts_with("st_"));
+        assert!(!response.expires_at.is_empty());
+        let token = response.token;
+
+        // A logged-out viewer can read the document with the token...
+        let revisions_request = GetDocumentRevisionsRequest {
+            doc_id: doc.doc_id.as_str().to_string(),
+            after_revision_number: 0,
+            share_token: token.clone(),
+            ..Default::default()
+        };
+        documents::get_document_revisions(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
+            None,
+            &revisions_request,
+        )
+        .await?;
+
+        // ...but a user in another org cannot edit it with a view-only token.
+        let other_org_user = new_session_user(&Id::new(IdType::Organization));
+        let result = documents::submit_document_change_set(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
+            &RateLimiter::default(),
+            &Requester::User(other_org_user),
+            &SubmitDocumentChangeSetRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                on_revision_number: 0,
+                change_set: Some(ChangeSet::new()),
+                share_token: token.clone(),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+
+        // Once revoked, the token no longer grants access.
+        revoke_share_token(
+            &db.dynamodb_client,
+            &owner,
+            &RevokeShareTokenRequest {
+                token: token.clone(),
+            },
+        )
+        .await?;
+        let result = documents::get_document_revisions(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
+            None,
+            &revisions_request,
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_guest_edits_with_share_token() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let org_id = Id::new(IdType::Organization);
+        let owner = new_session_user(&org_id);
+        let doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_created_by_user_id(&owner.user_id);
+        doc.create(&db.dynamodb_client).await;
+        let token = create_share_token(
+            &db.dynamodb_client,
+            &owner,
+            &CreateShareTokenRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                permission: DocumentSharingPermission::CanEdit.into(),
+                expires_in_seconds: 0,
+            },
+        )
+        .await?
+        .token;
+
+        let guest = GuestUser {
+            guest_id: Id::new(IdType::Guest),
+            display_name: String::from("Ada"),
+        };
+        let mut change_set = ChangeSet::new();
+        change_set.insert("hello");
+        let response = documents::submit_document_change_set(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
+            &RateLimiter::default(),
+            &Requester::Guest(guest.clone()),
+            &SubmitDocumentChangeSetRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                on_revision_number: 0,
+                change_set: Some(change_set),
+                share_token: token.clone(),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(response.revisions.len(), 1);
+
+        // The revision is attributed to the guest, by id and by display name.
+        let response = documents::get_document_revisions(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
+            None,
+            &GetDocumentRevisionsRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                after_revision_number: 0,
+                share_token: token.clone(),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(response.revisions.len(), 1);
+        assert_eq!(
+            response.revisions[0].author_user_id,
+            guest.guest_id.as_str()
+        );
+        assert_eq!(response.revisions[0].author_display_name, "Ada");
+
+        // Without the token, the guest has no access.
+        let result = documents::submit_document_change_set(
+            &db.dynamodb_client,
+            &PermissionCache::default(),
+            &RateLimiter::default(),
+            &Requester::Guest(guest),
+            &SubmitDocumentChangeSetRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                on_revision_number: 1,
+                change_set: Some(ChangeSet::new()),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_share_token_permission() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let doc_id = Id::new(IdType::Document);
+        let other_doc_id = Id::new(IdType::Document);
+        let now = chrono::Utc::now();
+        let put_token = |token: &Id, expires_at: chrono::DateTime<chrono::Utc>| PutItemInput {
+            table_name: table_name("share_tokens"),
+            item: av_map(&[
+                av_s("token", token.as_str()),
+                av_s("doc_id", doc_id.as_str()),
+                av_s("org_id", Id::new(IdType::Organization).as_str()),
+                av_n("permission", DocumentSharingPermission::CanEdit as i32),
+                av_s("created_by_user_id", Id::new(IdType::User).as_str()),
+                av_s("created_at", &time::date_time_iso_str(&now)),
+                av_s("expires_at", &time::date_time_iso_str(&expires_at)),
+            ]),
+            ..Default::default()
+        };
+        let valid_token = Id::new(IdType::ShareToken);
+        let expired_token = Id::new(IdType::ShareToken);
+        db.dynamodb_client
+            .put_item(put_token(&valid_token, now + chrono::Duration::days(1)))
+            .await?;
+        db.dynamodb_client
+            .put_item(put_token(
+                &expired_token,
+                now - chrono::Duration::seconds(1),
+            ))
+            .await?;
+
+        let permission =
+            get_share_token_permission(&db.dynamodb_client, valid_token.as_str(), doc_id.as_str())
+                .await?;
+        assert_eq!(permission, DocumentSharingPermission::CanEdit);
+
+        // Expired token, token for a different document, and unknown token.
+        let cases = [
+            (expired_token.as_str(), doc_id.as_str()),
+            (valid_token.as_str(), other_doc_id.as_str()),
+            ("st_doesnotexist", doc_id.as_str()),
+        ];
+        for (token, doc_id) in cases.iter() {
+            let result = get_share_token_permission(&db.dynamodb_client, token, doc_id).await;
+            assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,572 @@
+//! Sharing documents with users, many at a time.
+//!
+//! Explicit sharing is stored in `document_user_sharing_permissions`, one item per document and
+//! user. These endpoints write many of those items with `BatchWriteItem`, which may skip some of
+//! them when throttled. Whatever is still unwritten after a few attempts is reported back with the
+//! other failures, instead of failing the whole request, so that the client can retry only that.
+
+use std::collections::{HashMap, HashSet};
+
+use actix_web::error;
+use rusoto_dynamodb::{BatchWriteItemInput, DeleteRequest, PutRequest, WriteRequest};
+
+use ot::writing_proto::{
+    sharing_failure::Reason, DocumentSharingPermission, GetMyPermissionsRequest,
+    ShareDocumentRequest, ShareDocumentResponse, ShareDocumentsRequest, ShareDocumentsResponse,
+    SharingFailure,
+};
+
+use crate::documents;
+use crate::dynamodb::{
+    self, av_get_s, av_map, av_n, av_s, table_name, DynamoDbClient, BATCH_WRITE_ITEM_MAX_REQUESTS,
+};
+use crate::http::SessionUser;
+use crate::ids::{Id, IdType};
+use crate::permission_cache::PermissionCache;
+use crate::users::UserRole;
+use crate::utils::time;
+
+/// At most this many users, or documents, may be shared in one request.
+const MAX_SHARES_PER_REQUEST: usize = 100;
+
+/// Writes that DynamoDB skips are attempted this many times in all before they are reported as
+/// failed.
+const MAX_WRITE_ATTEMPTS: usize = 3;
+
+/// Share one document with many users. A permission of `None` stops sharing it with them.
+///
+/// If there are no user ids, or more than 100, or the permission is invalid, returns 400 Bad
+/// Request.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+///
+/// Upon success, returns a failure for the document, or for each user it was not shared with.
+pub async fn share_document(
+    dynamodb_client: &DynamoDbClient,
+    permission_cache: &PermissionCache,
+    session_user: &SessionUser,
+    request: &ShareDocumentRequest,
+) -> actix_web::Result<ShareDocumentResponse> {
+    if request.user_ids.is_empty() || request.user_ids.len() > MAX_SHARES_PER_REQUEST {
+        return Err(error::ErrorBadRequest(""));
+    }
+    let permission = parse_permission(request.permission)?;
+    let user_ids: Vec<&str> = request.user_ids.iter().map(String::as_str).collect();
+    let failures = share(
+        dynamodb_client,
+        permission_cache,
+        session_user,
+        &[request.doc_id.as_str()],
+        &user_ids,
+        permission,
+    )
+    .await?;
+    Ok(ShareDocumentResponse { failures })
+}
+
+/// Share many documents with one user. A permission of `None` stops sharing them with the user.
+///
+/// If there are no doc ids, or more than 100, or the permission is invalid, returns 400 Bad
+/// Request.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+///
+/// Upon success, returns a failure for the user, or for each document not shared with them.
+pub async fn share_documents(
+    dynamodb_client: &DynamoDbClient,
+    permission_cache: &PermissionCache,
+    session_user: &SessionUser,
+    request: &ShareDocumentsRequest,
+) -> actix_web::Result<ShareDocumentsResponse> {
+    if request.doc_ids.is_empty() || request.doc_ids.len() > MAX_SHARES_PER_REQUEST {
+        return Err(error::ErrorBadRequest(""));
+    }
+    let permission = parse_permission(request.permission)?;
+    let doc_ids: Vec<&str> = request.doc_ids.iter().map(String::as_str).collect();
+    let failures = share(
+        dynamodb_client,
+        permission_cache,
+        session_user,
+        &doc_ids,
+        &[request.user_id.as_str()],
+        permission,
+    )
+    .await?;
+    Ok(ShareDocumentsResponse { failures })
+}
+
+fn parse_permission(permission: i32) -> actix_web::Result<DocumentSharingPermission> {
+    DocumentSharingPermission::from_i32(permission).ok_or_else(|| error::ErrorBadRequest(""))
+}
+
+/// Shares each document with each user. Documents must be in the session user's org, and the
+/// session user must be able to edit them or be an org admin. Users must be in the org too.
+///
+/// Returns the failures, with whole documents and whole users reported once rather than once per
+/// pair.
+async fn share(
+    dynamodb_client: &DynamoDbClient,
+    permission_cache: &PermissionCache,
+    session_user: &SessionUser,
+    doc_ids: &[&str],
+    user_ids: &[&str],
+    permission: DocumentSharingPermission,
+) -> actix_web::Result<Vec<SharingFailure>> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [share] \
+            [session_user: {:?}, doc_ids: {:?}, user_ids: {:?}, permission: {:?}]",
+            error_message,
+            session_user,
+            doc_ids,
+            user_ids,
+            permission,
+        );
+    };
+    let mut failures = Vec::new();
+    let doc_ids = parse_ids(doc_ids, IdType::Document, |doc_id| {
+        failures.push(new_failure(doc_id, "", Reason::InvalidId));
+    });
+    let user_ids = parse_ids(user_ids, IdType::User, |user_id| {
+        failures.push(new_failure("", user_id, Reason::InvalidId));
+    });
+
+    // 1. Find the documents in the session user's org. Documents in other orgs are treated as if
+    //    they do not exist.
+    let items = dynamodb::batch_get_all_items(
+        dynamodb_client,
+        &table_name("documents"),
+        doc_ids
+            .iter()
+            .map(|doc_id| av_map(&[av_s("id", doc_id)]))
+            .collect(),
+        "id, org_id",
+    )
+    .await
+    .map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    let found_doc_ids: HashSet<&str> = items
+        .iter()
+        .filter(|item| av_get_s(item, "org_id") == Some(session_user.org_id.as_str()))
+        .filter_map(|item| av_get_s(item, "id"))
+        .collect();
+    let mut shareable_doc_ids = Vec::with_capacity(found_doc_ids.len());
+    for doc_id in doc_ids.iter() {
+        if found_doc_ids.contains(doc_id) {
+            shareable_doc_ids.push(*doc_id);
+        } else {
+            failures.push(new_failure(doc_id, "", Reason::DocumentNotFound));
+        }
+    }
+
+    // 2. Org admins may share any document in the org. Everyone else must be able to edit it.
+    if session_user.user_role != UserRole::OrgAdmin && !shareable_doc_ids.is_empty() {
+        let response = documents::get_my_permissions(
+            dynamodb_client,
+            session_user,
+            &GetMyPermissionsRequest {
+                doc_ids: shareable_doc_ids.iter().map(|s| s.to_string()).collect(),
+            },
+        )
+        .await?;
+        let editable_doc_ids: HashSet<&str> = response
+            .permissions
+            .iter()
+            .filter(|p| p.permission() == DocumentSharingPermission::CanEdit)
+            .map(|p| p.doc_id.as_str())
+            .collect();
+        shareable_doc_ids.retain(|doc_id| {
+            if editable_doc_ids.contains(doc_id) {
+                return true;
+            }
+            failures.push(new_failure(doc_id, "", Reason::Forbidden));
+            false
+        });
+    }
+
+    // 3. Find the users in the org.
+    let items = dynamodb::batch_get_all_items(
+        dynamodb_client,
+        &table_name("organization_users"),
+        user_ids
+            .iter()
+            .map(|user_id| {
+                av_map(&[
+                    av_s("org_id", session_user.org_id.as_str()),
+                    av_s("user_id", user_id),
+                ])
+            })
+            .collect(),
+        "user_id",
+    )
+    .await
+    .map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    let org_user_ids: HashSet<&str> = items
+        .iter()
+        .filter_map(|item| av_get_s(item, "user_id"))
+        .collect();
+    let mut shareable_user_ids = Vec::with_capacity(org_user_ids.len());
+    for user_id in user_ids.iter() {
+        if org_user_ids.contains(user_id) {
+            shareable_user_ids.push(*user_id);
+        } else {
+            failures.push(new_failure("", user_id, Reason::UserNotInOrg));
+        }
+    }
+
+    // 4. Write the sharing. `BatchWriteItem` cannot update items, so sharing a document again with
+    //    a user replaces the item, and its created_at, entirely.
+    let now = time::date_time_iso_str(&chrono::Utc::now());
+    let mut requests = Vec::with_capacity(shareable_doc_ids.len() * shareable_user_ids.len());
+    for doc_id in shareable_doc_ids.iter() {
+        for user_id in shareable_user_ids.iter() {
+            let key = av_map(&[av_s("doc_id", doc_id), av_s("user_id", user_id)]);
+            requests.push(if permission == DocumentSharingPermission::None {
+                WriteRequest {
+                    delete_request: Some(DeleteRequest { key }),
+                    ..Default::default()
+                }
+            } else {
+                let mut item = key;
+                item.extend(av_map(&[
+                    av_s("org_id", session_user.org_id.as_str()),
+                    av_n("sharing_permission", permission as i32),
+                    av_s("created_at", &now),
+                    av_s("updated_at", &now),
+                ]));
+                WriteRequest {
+                    put_request: Some(PutRequest { item }),
+                    ..Default::default()
+                }
+            });
+        }
+    }
+    let unwritten = batch_write_items(
+        dynamodb_client,
+        &table_name("document_user_sharing_permissions"),
+        requests,
+        &log_error,
+    )
+    .await;
+    for request in unwritten.iter() {
+        let key = request
+            .put_request
+            .as_ref()
+            .map(|put_request| &put_request.item)
+            .or_else(|| request.delete_request.as_ref().map(|d| &d.key));
+        if let Some(key) = key {
+            failures.push(new_failure(
+                av_get_s(key, "doc_id").unwrap_or_default(),
+                av_get_s(key, "user_id").unwrap_or_default(),
+                Reason::WriteFailed,
+            ));
+        }
+    }
+
+    // Permissions cached before the change must not be honored anymore.
+    for doc_id in shareable_doc_ids.iter() {
+        permission_cache.invalidate_document(doc_id);
+    }
+    Ok(failures)
+}
+
+/// Returns the distinct valid ids of the given type, in order, and calls `on_invalid` with each
+/// invalid id.
+fn parse_ids<'a>(
+    ids: &[&'a str],
+    id_type: IdType,
+    mut on_invalid: impl FnMut(&str),
+) -> Vec<&'a str> {
+    let mut seen_ids = HashSet::new();
+    let mut valid_ids = Vec::with_capacity(ids.len());
+    for id in ids.iter() {
+        match Id::parse(id) {
+            Some(parsed) if parsed.id_type == id_type => {
+                if seen_ids.insert(*id) {
+                    valid_ids.push(*id);
+                }
+            }
+            _ => on_invalid(id),
+        }
+    }
+    valid_ids
+}
+
+fn new_failure(doc_id: &str, user_id: &str, reason: Reason) -> SharingFailure {
+    SharingFailure {
+        doc_id: doc_id.to_string(),
+        user_id: user_id.to_string(),
+        reason: reason.into(),
+    }
+}
+
+/// Writes the requests to one table in batches, attempting whatever DynamoDB skips again a few
+/// times. Returns the requests that were not written.
+async fn batch_write_items(
+    dynamodb_client: &DynamoDbClient,
+    table_name: &str,
+    requests: Vec<WriteRequest>,
+    log_error: impl Fn(String),
+) -> Vec<WriteRequest> {
+    let mut unwritten = Vec::new();
+    for batch in requests.chunks(BATCH_WRITE_ITEM_MAX_REQUESTS) {
+        let mut pending = batch.to_vec();
+        for attempt in 0..MAX_WRITE_ATTEMPTS {
+            if attempt > 0 {
+                tokio::time::delay_for(std::time::Duration::from_millis(50)).await;
+            }
+            let mut request_items: HashMap<String, Vec<WriteRequest>> = HashMap::new();
+            request_items.insert(table_name.to_string(), pending.clone());
+            match dynamodb_client
+                .batch_write_item(BatchWriteItemInput {
+                    request_items,
+                    ..Default::default()
+                })
+                .await
+            {
+                Ok(output) => {
+                    pending = output
+                        .unprocessed_items
+                        .and_then(|mut unprocessed_items| unprocessed_items.remove(table_name))
+                        .unwrap_or_default();
+                }
+                // The client already retried anything worth retrying.
+                Err(e) => {
+                    log_error(e.to_string());
+                    break;
+                }
+            }
+            if pending.is_empty() {
+                break;
+            }
+        }
+        unwritten.extend(pending);
+    }
+    unwritten
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::fixtures::{create_organization_user, DocumentFixture};
+    use crate::testing::utils::TestDynamoDb;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    async fn get_permission(
+        db: &TestDynamoDb,
+        session_user: &SessionUser,
+        doc_id: &Id,
+    ) -> DocumentSharingPermission {
+        let response = documents::get_my_permissions(
+            &db.dynamodb_client,
+            session_user,
+            &GetMyPermissionsRequest {
+                doc_ids: vec![doc_id.as_str().to_string()],
+            },
+        )
+        .await
+        .unwrap();
+        response.permissions[0].permission()
+    }
+
+    #[tokio::test]
+    async fn test_share_document() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let permission_cache = PermissionCache::default();
+
+        let org_id = Id::new(IdType::Organization);
+        let owner_user_id = Id::new(IdType::User);
+        let doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_created_by_user_id(&owner_user_id);
+        doc.create(&db.dynamodb_client).await;
+        let owner = SessionUser {
+            user_id: owner_user_id.clone(),
+            org_id: org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let mut teammates = Vec::new();
+        for _ in 0..30 {
+            let user_id = Id::new(IdType::User);
+            create_organization_user(&db.dynamodb_client, &org_id, &user_id, &chrono::Utc::now())
+                .await;
+            teammates.push(SessionUser {
+                user_id,
+                org_id: org_id.clone(),
+                user_role: UserRole::Default,
+            });
+        }
+        let stranger_user_id = Id::new(IdType::User);
+
+        // Every teammate is shared the document, across more than one batch. The user from
+        // outside the org and the invalid id are reported.
+        let mut user_ids: Vec<String> = teammates
+            .iter()
+            .map(|teammate| teammate.user_id.as_str().to_string())
+            .collect();
+        user_ids.push(stranger_user_id.as_str().to_string());
+        user_ids.push("not a user id".to_string());
+        let response = share_document(
+            &db.dynamodb_client,
+            &permission_cache,
+            &owner,
+            &ShareDocumentRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                user_ids: user_ids.clone(),
+                permission: DocumentSharingPermission::CanComment.into(),
+            },
+        )
+        .await?;
+        assert_eq!(
+            response.failures,
+            vec![
+                new_failure("", "not a user id", Reason::InvalidId),
+                new_failure("", stranger_user_id.as_str(), Reason::UserNotInOrg),
+            ]
+        );
+        for teammate in teammates.iter() {
+            assert_eq!(
+                get_permission(&db, teammate, &doc.doc_id).await,
+                DocumentSharingPermission::CanComment
+            );
+        }
+
+        // Teammates cannot share the document further, since they cannot edit it.
+        let response = share_document(
+            &db.dynamodb_client,
+            &permission_cache,
+            &teammates[0],
+            &ShareDocumentRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                user_ids: vec![teammates[1].user_id.as_str().to_string()],
+                permission: DocumentSharingPermission::CanEdit.into(),
+            },
+        )
+        .await?;
+        assert_eq!(
+            response.failures,
+            vec![new_failure(doc.doc_id.as_str(), "", Reason::Forbidden)]
+        );
+
+        // Sharing with no permission stops sharing.
+        share_document(
+            &db.dynamodb_client,
+            &permission_cache,
+            &owner,
+            &ShareDocumentRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                user_ids,
+                permission: DocumentSharingPermission::None.into(),
+            },
+        )
+        .await?;
+        assert_eq!(
+            get_permission(&db, &teammates[0], &doc.doc_id).await,
+            DocumentSharingPermission::None
+        );
+
+        // Too many users.
+        let result = share_document(
+            &db.dynamodb_client,
+            &permission_cache,
+            &owner,
+            &ShareDocumentRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+                user_ids: vec![stranger_user_id.as_str().to_string(); MAX_SHARES_PER_REQUEST + 1],
+                permission: DocumentSharingPermission::CanView.into(),
+            },
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_share_documents() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let permission_cache = PermissionCache::default();
+
+        let org_id = Id::new(IdType::Organization);
+        let new_user_id = Id::new(IdType::User);
+        create_organization_user(
+            &db.dynamodb_client,
+            &org_id,
+            &new_user_id,
+            &chrono::Utc::now(),
+        )
+        .await;
+        let new_user = SessionUser {
+            user_id: new_user_id.clone(),
+            org_id: org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let mut admin = SessionUser {
+            user_id: Id::new(IdType::User),
+            org_id: org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let own_doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_created_by_user_id(&admin.user_id);
+        let other_doc = DocumentFixture::new().with_org_id(&org_id);
+        let other_org_doc = DocumentFixture::new();
+        for doc in [&own_doc, &other_doc, &other_org_doc].iter() {
+            doc.create(&db.dynamodb_client).await;
+        }
+        let request = ShareDocumentsRequest {
+            doc_ids: vec![
+                own_doc.doc_id.as_str().to_string(),
+                other_doc.doc_id.as_str().to_string(),
+                other_org_doc.doc_id.as_str().to_string(),
+            ],
+            user_id: new_user_id.as_str().to_string(),
+            permission: DocumentSharingPermission::CanEdit.into(),
+        };
+
+        // Before they are an admin, the user may share only the documents they can edit.
+        let response =
+            share_documents(&db.dynamodb_client, &permission_cache, &admin, &request).await?;
+        assert_eq!(
+            response.failures,
+            vec![
+                new_failure(other_org_doc.doc_id.as_str(), "", Reason::DocumentNotFound),
+                new_failure(other_doc.doc_id.as_str(), "", Reason::Forbidden),
+            ]
+        );
+        assert_eq!(
+            get_permission(&db, &new_user, &own_doc.doc_id).await,
+            DocumentSharingPermission::CanEdit
+        );
+        assert_eq!(
+            get_permission(&db, &new_user, &other_doc.doc_id).await,
+            DocumentSharingPermission::None
+        );
+
+        // Admins may share any document in their org.
+        admin.user_role = UserRole::OrgAdmin;
+        let response =
+            share_documents(&db.dynamodb_client, &permission_cache, &admin, &request).await?;
+        assert_eq!(
+            response.failures,
+            vec![new_failure(
+                other_org_doc.doc_id.as_str(),
+                "",
+                Reason::DocumentNotFound
+            )]
+        );
+        assert_eq!(
+            get_permission(&db, &new_user, &other_doc.doc_id).await,
+            DocumentSharingPermission::CanEdit
+        );
+
+        Ok(())
+    }
+}
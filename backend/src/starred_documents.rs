@@ -0,0 +1,348 @@
+//! Documents that users starred, so that they can find their favorites quickly.
+
+use std::collections::HashSet;
+
+use actix_web::error;
+use rusoto_dynamodb::{DeleteItemInput, PutItemInput, QueryInput};
+
+use ot::writing_proto::{
+    Document, DocumentSharingPermission, ListStarredRequest, ListStarredResponse,
+    StarDocumentRequest, StarDocumentResponse, UnstarDocumentRequest, UnstarDocumentResponse,
+};
+
+use crate::documents;
+use crate::dynamodb::{self, av_get_s, av_map, av_s, table_name, DynamoDbClient};
+use crate::http::SessionUser;
+use crate::ids::{Id, IdType};
+use crate::utils::time;
+
+const LIST_STARRED_DEFAULT_LIMIT: i64 = 20;
+const LIST_STARRED_MAX_LIMIT: i64 = 100;
+
+const VIEW_PERMISSIONS: [DocumentSharingPermission; 3] = [
+    DocumentSharingPermission::CanView,
+    DocumentSharingPermission::CanComment,
+    DocumentSharingPermission::CanEdit,
+];
+
+/// Star a document for the session user. Starring a document again moves it to the top of the
+/// starred list.
+///
+/// If the document does not exist, returns 404 Not Found.
+///
+/// If the session user cannot view the document, returns 403 Forbidden.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn star_document(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &StarDocumentRequest,
+) -> actix_web::Result<StarDocumentResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [star_document] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    documents::get_document_if_some_permission_valid(
+        dynamodb_client,
+        session_user,
+        &request.doc_id,
+        &VIEW_PERMISSIONS,
+    )
+    .await?;
+    let input = PutItemInput {
+        table_name: table_name("starred_documents"),
+        item: av_map(&[
+            av_s("user_id", session_user.user_id.as_str()),
+            av_s("doc_id", &request.doc_id),
+            av_s("org_id", session_user.org_id.as_str()),
+            av_s("starred_at", &time::date_time_iso_str(&chrono::Utc::now())),
+        ]),
+        ..Default::default()
+    };
+    dynamodb_client.put_item(input).await.map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    Ok(StarDocumentResponse {})
+}
+
+/// Unstar a document for the session user. Unstarring a document that is not starred has no
+/// effect.
+///
+/// No permission is required, so that users who have lost access to a document can still unstar
+/// it.
+///
+/// If the doc id is not a document id, returns 400 Bad Request.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn unstar_document(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &UnstarDocumentRequest,
+) -> actix_web::Result<UnstarDocumentResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [unstar_document] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    match Id::parse(&request.doc_id) {
+        Some(doc_id) if doc_id.id_type == IdType::Document => {}
+        _ => return Err(error::ErrorBadRequest("")),
+    }
+    let input = DeleteItemInput {
+        table_name: table_name("starred_documents"),
+        key: av_map(&[
+            av_s("user_id", session_user.user_id.as_str()),
+            av_s("doc_id", &request.doc_id),
+        ]),
+        ..Default::default()
+    };
+    dynamodb_client.delete_item(input).await.map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    Ok(UnstarDocumentResponse {})
+}
+
+/// List the documents the session user starred, most recently starred first.
+///
+/// Documents that were deleted, or that the session user can no longer view, are left out, so a
+/// page may hold fewer documents than the limit even when there are more to read.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+///
+/// Upon success, returns a page of documents, and the time to pass as `starred_before_date_time` to
+/// read the next page.
+pub async fn list_starred(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &ListStarredRequest,
+) -> actix_web::Result<ListStarredResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [list_starred] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    let limit = match request.limit as i64 {
+        limit if limit <= 0 => LIST_STARRED_DEFAULT_LIMIT,
+        limit => limit.min(LIST_STARRED_MAX_LIMIT),
+    };
+    let mut values = vec![av_s(":user_id", session_user.user_id.as_str())];
+    let key_condition_expression = if request.starred_before_date_time.is_empty() {
+        "user_id = :user_id"
+    } else {
+        values.push(av_s(":starred_before", &request.starred_before_date_time));
+        "user_id = :user_id AND starred_at < :starred_before"
+    };
+    let input = QueryInput {
+        table_name: table_name("starred_documents"),
+        index_name: Some(String::from("user_id-starred_at-index")),
+        scan_index_forward: Some(false),
+        key_condition_expression: Some(String::from(key_condition_expression)),
+        expression_attribute_values: Some(av_map(&values)),
+        projection_expression: Some(String::from("doc_id, org_id, starred_at")),
+        limit: Some(limit),
+        ..Default::default()
+    };
+    let output = dynamodb_client.query(input).await.map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    let items = output.items.unwrap_or_default();
+    let mut response = ListStarredResponse::default();
+    if output.last_evaluated_key.is_some() {
+        if let Some(starred_at) = items.last().and_then(|item| av_get_s(item, "starred_at")) {
+            response.next_starred_before_date_time = starred_at.to_string();
+        }
+    }
+
+    // Stars from another org the user belonged to are not shown in this one.
+    let doc_ids: Vec<&str> = items
+        .iter()
+        .filter(|item| av_get_s(item, "org_id") == Some(session_user.org_id.as_str()))
+        .filter_map(|item| av_get_s(item, "doc_id"))
+        .collect();
+    let keys = doc_ids
+        .iter()
+        .map(|doc_id| av_map(&[av_s("id", doc_id)]))
+        .collect();
+    let document_items = dynamodb::batch_get_all_items(
+        dynamodb_client,
+        &table_name("documents"),
+        keys,
+        "id, org_id, title, created_by_user_id, owner_user_id, org_level_sharing_permission, \
+        created_at, updated_at, encryption_key_fingerprint, archived_at",
+    )
+    .await
+    .map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    let documents = document_items
+        .iter()
+        .filter(|item| av_get_s(item, "org_id") == Some(session_user.org_id.as_str()))
+        .map(documents::document_from_item)
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| {
+            log_error("document is missing a field".to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    let mut documents =
+        documents::filter_accessible_documents(dynamodb_client, session_user, documents)
+            .await
+            .map_err(|e| {
+                log_error(e);
+                error::ErrorInternalServerError("")
+            })?;
+
+    // Batch reads come back in no particular order.
+    for doc_id in doc_ids.into_iter() {
+        if let Some(i) = documents.iter().position(|document| document.id == doc_id) {
+            let mut document = documents.swap_remove(i);
+            document.is_starred = true;
+            response.documents.push(document);
+        }
+    }
+    Ok(response)
+}
+
+/// Sets `is_starred` on each of the documents that the session user starred.
+///
+/// On failure, returns a message to log.
+pub async fn mark_starred(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    documents: &mut [Document],
+) -> Result<(), String> {
+    let keys = documents
+        .iter()
+        .map(|document| {
+            av_map(&[
+                av_s("user_id", session_user.user_id.as_str()),
+                av_s("doc_id", &document.id),
+            ])
+        })
+        .collect();
+    let items = dynamodb::batch_get_all_items(
+        dynamodb_client,
+        &table_name("starred_documents"),
+        keys,
+        "doc_id",
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+    let starred: HashSet<&str> = items
+        .iter()
+        .filter_map(|item| av_get_s(item, "doc_id"))
+        .collect();
+    for document in documents.iter_mut() {
+        document.is_starred = starred.contains(document.id.as_str());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::fixtures::DocumentFixture;
+    use crate::testing::utils::TestDynamoDb;
+    use crate::users::UserRole;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[tokio::test]
+    async fn test_star_and_unstar() -> TestResult {
+        let db = TestDynamoDb::new().await;
+
+        let org_id = Id::new(IdType::Organization);
+        let user_id = Id::new(IdType::User);
+        let session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: org_id.clone(),
+            user_role: UserRole::Default,
+        };
+        let own_doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_created_by_user_id(&user_id);
+        let shared_doc = DocumentFixture::new()
+            .with_org_id(&org_id)
+            .with_sharing(&user_id, DocumentSharingPermission::CanView);
+        let private_doc = DocumentFixture::new().with_org_id(&org_id);
+        for doc in [&own_doc, &shared_doc, &private_doc].iter() {
+            doc.create(&db.dynamodb_client).await;
+        }
+        let star = |doc: &DocumentFixture| {
+            let request = StarDocumentRequest {
+                doc_id: doc.doc_id.as_str().to_string(),
+            };
+            let dynamodb_client = &db.dynamodb_client;
+            let session_user = &session_user;
+            async move { star_document(dynamodb_client, session_user, &request).await }
+        };
+
+        star(&own_doc).await?;
+        // Make sure the stars are ordered.
+        tokio::time::delay_for(std::time::Duration::from_millis(10)).await;
+        star(&shared_doc).await?;
+        let result = star(&private_doc).await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 403);
+
+        let response = list_starred(
+            &db.dynamodb_client,
+            &session_user,
+            &ListStarredRequest::default(),
+        )
+        .await?;
+        let doc_ids: Vec<&str> = response
+            .documents
+            .iter()
+            .map(|document| document.id.as_str())
+            .collect();
+        assert_eq!(
+            doc_ids,
+            vec![shared_doc.doc_id.as_str(), own_doc.doc_id.as_str()]
+        );
+        assert!(response
+            .documents
+            .iter()
+            .all(|document| document.is_starred));
+
+        unstar_document(
+            &db.dynamodb_client,
+            &session_user,
+            &UnstarDocumentRequest {
+                doc_id: shared_doc.doc_id.as_str().to_string(),
+            },
+        )
+        .await?;
+        let mut documents = vec![
+            Document {
+                id: own_doc.doc_id.as_str().to_string(),
+                ..Default::default()
+            },
+            Document {
+                id: shared_doc.doc_id.as_str().to_string(),
+                is_starred: true,
+                ..Default::default()
+            },
+        ];
+        mark_starred(&db.dynamodb_client, &session_user, &mut documents).await?;
+        assert!(documents[0].is_starred);
+        assert!(!documents[1].is_starred);
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often the counters are logged and reset.
+pub const SYNC_METRICS_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Counts how change set submissions turn out, as the server-side counterpart of the editor's
+/// telemetry. A high share of `DiscoveredNewRevisions` responses means editors sync too rarely, or
+/// compose typing into revisions for too long, for the documents they share.
+///
+/// Counters are kept in memory, so they are per server.
+#[derive(Default)]
+pub struct SyncMetrics {
+    acks: AtomicU64,
+    discovered_new_revisions: AtomicU64,
+    throttled: AtomicU64,
+    errors: AtomicU64,
+    // What the last `take` returned, for the admin dashboard.
+    last_interval: Mutex<SyncMetricsSnapshot>,
+}
+
+/// The counters at one point in time.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SyncMetricsSnapshot {
+    pub acks: u64,
+    pub discovered_new_revisions: u64,
+    pub throttled: u64,
+    pub errors: u64,
+}
+
+impl SyncMetrics {
+    pub fn record_ack(&self) {
+        self.acks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_discovered_new_revisions(&self) {
+        self.discovered_new_revisions
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_throttled(&self) {
+        self.throttled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the counters, and resets them to zero.
+    pub fn take(&self) -> SyncMetricsSnapshot {
+        let snapshot = SyncMetricsSnapshot {
+            acks: self.acks.swap(0, Ordering::Relaxed),
+            discovered_new_revisions: self.discovered_new_revisions.swap(0, Ordering::Relaxed),
+            throttled: self.throttled.swap(0, Ordering::Relaxed),
+            errors: self.errors.swap(0, Ordering::Relaxed),
+        };
+        *self.last_interval.lock().unwrap() = snapshot;
+        snapshot
+    }
+
+    /// The counters as of the last `take`, which the reporter calls once per
+    /// `SYNC_METRICS_REPORT_INTERVAL`. All zero before the first one.
+    pub fn last_interval(&self) -> SyncMetricsSnapshot {
+        *self.last_interval.lock().unwrap()
+    }
+}
+
+impl SyncMetricsSnapshot {
+    pub fn submissions(&self) -> u64 {
+        self.acks + self.discovered_new_revisions + self.throttled + self.errors
+    }
+
+    /// The share of submissions that were rejected because someone else committed first, or 0 if
+    /// there were none.
+    pub fn conflict_rate(&self) -> f64 {
+        match self.submissions() {
+            0 => 0.0,
+            submissions => self.discovered_new_revisions as f64 / submissions as f64,
+        }
+    }
+}
+
+/// Logs and resets the counters every `SYNC_METRICS_REPORT_INTERVAL`. Quiet intervals are not
+/// logged.
+pub async fn run_sync_metrics_reporter(sync_metrics: Arc<SyncMetrics>) {
+    let mut interval = tokio::time::interval(SYNC_METRICS_REPORT_INTERVAL);
+    loop {
+        interval.tick().await;
+        let snapshot = sync_metrics.take();
+        if snapshot.submissions() > 0 {
+            log::info!(
+                "Sync metrics: {} submissions, {} acks, {} discovered new revisions ({:.1}%), {} \
+                 throttled, {} errors",
+                snapshot.submissions(),
+                snapshot.acks,
+                snapshot.discovered_new_revisions,
+                snapshot.conflict_rate() * 100.0,
+                snapshot.throttled,
+                snapshot.errors
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take() {
+        let sync_metrics = SyncMetrics::default();
+        assert_eq!(sync_metrics.take().conflict_rate(), 0.0);
+
+        for _ in 0..3 {
+            sync_metrics.record_ack();
+        }
+        sync_metrics.record_discovered_new_revisions();
+        sync_metrics.record_throttled();
+        sync_metrics.record_error();
+        let snapshot = sync_metrics.take();
+        assert_eq!(
+            snapshot,
+            SyncMetricsSnapshot {
+                acks: 3,
+                discovered_new_revisions: 1,
+                throttled: 1,
+                errors: 1,
+            }
+        );
+        assert_eq!(snapshot.submissions(), 6);
+        assert!((snapshot.conflict_rate() - 1.0 / 6.0).abs() < 1e-9);
+        assert_eq!(sync_metrics.last_interval(), snapshot);
+
+        // Taking resets the counters.
+        assert_eq!(sync_metrics.take(), SyncMetricsSnapshot::default());
+    }
+}
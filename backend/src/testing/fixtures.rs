@@ -1,12 +1,15 @@
+use bytes::Bytes;
 #[cfg(test)]
 use chrono::{DateTime, Utc};
 
-use crate::dynamodb::{av_map, av_n, av_s, table_name};
+use crate::documents;
+use crate::dynamodb::{av_b, av_map, av_n, av_s, table_name, DynamoDbClient};
 use crate::ids::{Id, IdType};
 use crate::utils;
-use rusoto_dynamodb::{DynamoDb, PutItemInput};
+use ot::writing_proto::{ChangeSet, DocumentSharingPermission};
+use rusoto_dynamodb::PutItemInput;
 
-pub async fn create_user(dynamodb_client: &dyn DynamoDb, email: &str, name: &str) -> Id {
+pub async fn create_user(dynamodb_client: &DynamoDbClient, email: &str, name: &str) -> Id {
     let user_id = Id::new(IdType::User);
     let now_str = utils::time::date_time_iso_str(&Utc::now());
 
@@ -30,7 +33,7 @@ pub async fn create_user(dynamodb_client: &dyn DynamoDb, email: &str, name: &str
 }
 
 pub async fn create_organization_user(
-    dynamodb_client: &dyn DynamoDb,
+    dynamodb_client: &DynamoDbClient,
     org_id: &Id,
     user_id: &Id,
     last_login_at: &DateTime<Utc>,
@@ -55,3 +58,191 @@ pub async fn create_organization_user(
         .await
         .unwrap();
 }
+
+/// A revision to add to a `DocumentFixture`'s revision log. Revision numbers are assigned in
+/// order, starting at 1.
+pub struct RevisionFixture {
+    pub author_user_id: Id,
+    pub change_set: ChangeSet,
+    pub committed_at: DateTime<Utc>,
+}
+
+impl RevisionFixture {
+    pub fn new(author_user_id: &Id, change_set: &ChangeSet, committed_at: &DateTime<Utc>) -> Self {
+        Self {
+            author_user_id: author_user_id.clone(),
+            change_set: change_set.clone(),
+            committed_at: *committed_at,
+        }
+    }
+}
+
+/// Builds a document, its revision log, and its user sharing permissions. Example:
+/// ```
+/// let doc = DocumentFixture::new()
+///     .with_org_level_sharing_permission(DocumentSharingPermission::CanView)
+///     .with_revisions(vec![RevisionFixture::new(&user_id, &change_set, &committed_at)])
+///     .with_sharing(&reader_user_id, DocumentSharingPermission::CanEdit);
+/// doc.create(&db.dynamodb_client).await;
+/// ```
+///
+/// Ids that are not given explicitly are generated.
+pub struct DocumentFixture {
+    pub doc_id: Id,
+    pub org_id: Id,
+    pub created_by_user_id: Id,
+    /// If `None`, the document has no `owner_user_id`, like documents from before ownership could
+    /// be transferred, and is owned by its creator.
+    pub owner_user_id: Option<Id>,
+    pub title: String,
+    pub org_level_sharing_permission: DocumentSharingPermission,
+    pub created_at: DateTime<Utc>,
+    pub revisions: Vec<RevisionFixture>,
+    pub sharing: Vec<(Id, DocumentSharingPermission)>,
+    /// If `None`, the document is not end-to-end encrypted.
+    pub encryption_key_fingerprint: Option<String>,
+}
+
+impl DocumentFixture {
+    pub fn new() -> Self {
+        Self {
+            doc_id: Id::new(IdType::Document),
+            org_id: Id::new(IdType::Organization),
+            created_by_user_id: Id::new(IdType::User),
+            owner_user_id: None,
+            title: String::from("My favorite document ever"),
+            org_level_sharing_permission: DocumentSharingPermission::None,
+            created_at: Utc::now() - chrono::Duration::days(1),
+            revisions: Vec::new(),
+            sharing: Vec::new(),
+            encryption_key_fingerprint: None,
+        }
+    }
+
+    pub fn with_doc_id(mut self, doc_id: &Id) -> Self {
+        self.doc_id = doc_id.clone();
+        self
+    }
+
+    pub fn with_org_id(mut self, org_id: &Id) -> Self {
+        self.org_id = org_id.clone();
+        self
+    }
+
+    pub fn with_created_by_user_id(mut self, created_by_user_id: &Id) -> Self {
+        self.created_by_user_id = created_by_user_id.clone();
+        self
+    }
+
+    pub fn with_owner_user_id(mut self, owner_user_id: &Id) -> Self {
+        self.owner_user_id = Some(owner_user_id.clone());
+        self
+    }
+
+    pub fn with_title(mut self, title: &str) -> Self {
+        self.title = title.to_string();
+        self
+    }
+
+    pub fn with_org_level_sharing_permission(
+        mut self,
+        permission: DocumentSharingPermission,
+    ) -> Self {
+        self.org_level_sharing_permission = permission;
+        self
+    }
+
+    pub fn with_revisions<I>(mut self, revisions: I) -> Self
+    where
+        I: IntoIterator<Item = RevisionFixture>,
+    {
+        self.revisions.extend(revisions);
+        self
+    }
+
+    /// Explicitly shares the document with the given user.
+    pub fn with_sharing(mut self, user_id: &Id, permission: DocumentSharingPermission) -> Self {
+        self.sharing.push((user_id.clone(), permission));
+        self
+    }
+
+    pub fn with_encryption_key_fingerprint(mut self, fingerprint: &str) -> Self {
+        self.encryption_key_fingerprint = Some(fingerprint.to_string());
+        self
+    }
+
+    /// Writes the document, its revisions, and its sharing permissions to DynamoDB.
+    pub async fn create(&self, dynamodb_client: &DynamoDbClient) {
+        let created_at_str = utils::time::date_time_iso_str(&self.created_at);
+        let mut item = av_map(&[
+            av_s("id", self.doc_id.as_str()),
+            av_s("org_id", self.org_id.as_str()),
+            av_s("title", &self.title),
+            av_s("title_lc", &documents::title_search_key(&self.title)),
+            av_s("created_by_user_id", self.created_by_user_id.as_str()),
+            av_n(
+                "org_level_sharing_permission",
+                self.org_level_sharing_permission as i32,
+            ),
+            av_s("created_at", &created_at_str),
+            av_s("updated_at", &created_at_str),
+        ]);
+        if let Some(owner_user_id) = &self.owner_user_id {
+            let (key, value) = av_s("owner_user_id", owner_user_id.as_str());
+            item.insert(key, value);
+        }
+        if let Some(fingerprint) = &self.encryption_key_fingerprint {
+            let (key, value) = av_s("encryption_key_fingerprint", fingerprint);
+            item.insert(key, value);
+        }
+        dynamodb_client
+            .put_item(PutItemInput {
+                table_name: table_name("documents"),
+                item,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        for (i, revision) in self.revisions.iter().enumerate() {
+            let change_set_bytes =
+                Bytes::from(utils::proto::encode_protobuf_message(&revision.change_set).unwrap());
+            dynamodb_client
+                .put_item(PutItemInput {
+                    table_name: table_name("document_revisions"),
+                    item: av_map(&[
+                        av_s("doc_id", self.doc_id.as_str()),
+                        av_s("org_id", self.org_id.as_str()),
+                        av_s("author_user_id", revision.author_user_id.as_str()),
+                        av_n("revision_number", i + 1),
+                        av_b("change_set", change_set_bytes),
+                        av_s(
+                            "committed_at",
+                            &utils::time::date_time_iso_str(&revision.committed_at),
+                        ),
+                    ]),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+
+        for (user_id, permission) in self.sharing.iter() {
+            dynamodb_client
+                .put_item(PutItemInput {
+                    table_name: table_name("document_user_sharing_permissions"),
+                    item: av_map(&[
+                        av_s("doc_id", self.doc_id.as_str()),
+                        av_s("user_id", user_id.as_str()),
+                        av_s("org_id", self.org_id.as_str()),
+                        av_n("sharing_permission", *permission as i32),
+                        av_s("created_at", &created_at_str),
+                        av_s("updated_at", &created_at_str),
+                    ]),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+    }
+}
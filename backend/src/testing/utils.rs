@@ -1,16 +1,28 @@
 use std::cell::RefCell;
-use std::collections::{HashSet, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
 
 use actix_session::CookieSession;
 use actix_web::dev::{Body, ResponseBody, ServiceResponse};
 use actix_web::web;
+use futures::future::BoxFuture;
 use lazy_static::lazy_static;
-use rusoto_dynamodb::{DeleteTableInput, DynamoDb, DynamoDbClient};
+use rusoto_dynamodb::{DeleteTableInput, DynamoDb};
 use uuid::Uuid;
 
-use crate::dynamodb::test_table_name;
+use crate::blob_store::{BlobMetadata, BlobStore};
+use crate::document_events::DocumentEvents;
+use crate::dynamodb::{test_table_name, DynamoDbClient};
+use crate::feature_flags::FeatureFlagCache;
 use crate::http;
+use crate::identity_providers::IdentityProviders;
+use crate::log_in_attempts;
+use crate::mailer::{Email, Mailer};
+use crate::permission_cache::PermissionCache;
+use crate::rate_limiter::{self, RateLimiter};
+use crate::region_locks::RegionLocks;
+use crate::sync_metrics::SyncMetrics;
 use crate::BackendService;
 
 const NUM_TEST_DYNAMODB_SHARDS: i32 = 8;
@@ -88,7 +100,11 @@ fn create_test_dynamodb_client() -> DynamoDbClient {
         name: "testing".to_string(),
         endpoint: "http://127.0.0.1:8000".to_string(),
     };
-    DynamoDbClient::new_with(request_dispatcher, credentials_provider, region)
+    DynamoDbClient::from_client(rusoto_dynamodb::DynamoDbClient::new_with(
+        request_dispatcher,
+        credentials_provider,
+        region,
+    ))
 }
 
 impl Drop for TestDynamoDb {
@@ -104,7 +120,7 @@ impl Drop for TestDynamoDb {
     }
 }
 
-async fn create_test_tables(dynamodb_shard: i32, dynamodb_client: &dyn DynamoDb) {
+async fn create_test_tables(dynamodb_shard: i32, dynamodb_client: &DynamoDbClient) {
     for table_def in dynamodb_schema::TABLE_DEFINITIONS.iter() {
         // Local DynamoDB sometimes experiences ephemeral errors when creating tables. Retry a few
         // times until we succeed. Sleep briefly between attempts.
@@ -135,7 +151,7 @@ async fn create_test_tables(dynamodb_shard: i32, dynamodb_client: &dyn DynamoDb)
     }
 }
 
-async fn delete_test_tables(dynamodb_shard: i32, dynamodb_client: &dyn DynamoDb) {
+async fn delete_test_tables(dynamodb_shard: i32, dynamodb_client: &DynamoDbClient) {
     for table_def in dynamodb_schema::TABLE_DEFINITIONS.iter() {
         let table_name = test_table_name(dynamodb_shard, &table_def.table_name);
         let _result = dynamodb_client
@@ -149,6 +165,129 @@ async fn delete_test_tables(dynamodb_shard: i32, dynamodb_client: &dyn DynamoDb)
 pub async fn default_backend_service() -> BackendService {
     BackendService {
         dynamodb_client: Arc::new(create_test_dynamodb_client()),
+        permission_cache: Arc::new(PermissionCache::default()),
+        guest_rate_limiter: Arc::new(RateLimiter::default()),
+        edit_rate_limiter: Arc::new(RateLimiter::new(
+            rate_limiter::EDIT_MAX_REVISIONS_PER_WINDOW,
+            rate_limiter::EDIT_RATE_LIMIT_WINDOW,
+        )),
+        typing_rate_limiter: Arc::new(RateLimiter::new(
+            rate_limiter::TYPING_MAX_HEARTBEATS_PER_WINDOW,
+            rate_limiter::TYPING_RATE_LIMIT_WINDOW,
+        )),
+        export_store: Arc::new(MemoryBlobStore::default()),
+        upload_store: Arc::new(MemoryBlobStore::default()),
+        photo_store: Arc::new(MemoryBlobStore::default()),
+        attachment_store: Arc::new(MemoryBlobStore::default()),
+        document_events: Arc::new(DocumentEvents::default()),
+        region_locks: Arc::new(RegionLocks::default()),
+        sync_metrics: Arc::new(SyncMetrics::default()),
+        feature_flag_cache: Arc::new(FeatureFlagCache::default()),
+        derived_data_from_stream: false,
+        mailer: Arc::new(RecordingMailer::default()),
+        log_in_rate_limiter: Arc::new(log_in_attempts::unknown_email_rate_limiter()),
+        identity_providers: Arc::new(IdentityProviders::default()),
+    }
+}
+
+/// Keeps sent emails, so that tests can check them.
+#[derive(Default)]
+pub struct RecordingMailer {
+    pub sent: Mutex<Vec<Email>>,
+}
+
+impl Mailer for RecordingMailer {
+    fn send<'a>(&'a self, email: &'a Email) -> BoxFuture<'a, anyhow::Result<()>> {
+        self.sent.lock().unwrap().push(email.clone());
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Keeps blobs in memory, with their content types, and hands out fake URLs. Tests upload to an
+/// upload URL by calling `put` themselves.
+#[derive(Default)]
+pub struct MemoryBlobStore {
+    pub blobs: Mutex<HashMap<String, (String, Vec<u8>)>>,
+}
+
+impl BlobStore for MemoryBlobStore {
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        content_type: &'a str,
+        body: Vec<u8>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        self.blobs
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), (content_type.to_string(), body));
+        Box::pin(async { Ok(()) })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<Option<Vec<u8>>>> {
+        let body = self
+            .blobs
+            .lock()
+            .unwrap()
+            .get(key)
+            .map(|(_, body)| body.clone());
+        Box::pin(async move { Ok(body) })
+    }
+
+    fn head<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<Option<BlobMetadata>>> {
+        let metadata = self
+            .blobs
+            .lock()
+            .unwrap()
+            .get(key)
+            .map(|(content_type, body)| BlobMetadata {
+                content_type: content_type.clone(),
+                content_length: body.len() as i64,
+            });
+        Box::pin(async move { Ok(metadata) })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        self.blobs.lock().unwrap().remove(key);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, anyhow::Result<Vec<String>>> {
+        let keys = self
+            .blobs
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect();
+        Box::pin(async move { Ok(keys) })
+    }
+
+    fn upload_url<'a>(
+        &'a self,
+        key: &'a str,
+        content_type: &'a str,
+        _expires_in: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<String>> {
+        Box::pin(async move {
+            Ok(format!(
+                "memory://upload/{}?content_type={}",
+                key, content_type
+            ))
+        })
+    }
+
+    fn download_url<'a>(
+        &'a self,
+        key: &'a str,
+        _expires_in: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<String>> {
+        Box::pin(async move { Ok(format!("memory://{}", key)) })
+    }
+
+    fn public_url(&self, key: &str) -> String {
+        format!("memory://public/{}", key)
     }
 }
 
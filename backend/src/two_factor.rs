@@ -0,0 +1,583 @@
+//! Two-factor authentication with time-based one-time passwords (TOTP, RFC 6238).
+//!
+//! A user sets it up in two steps. `provision_two_factor` stores a new secret on the user item as
+//! `pending_totp_secret`, for the user to add to an authenticator app. `enable_two_factor` checks a
+//! code from the app against it, then makes it the user's `totp_secret` and hands out recovery
+//! codes. We only keep SHA-256 hashes of the recovery codes, in `recovery_code_hashes`. Neither
+//! step replaces a `totp_secret` that is already there: that takes `disable_two_factor` first,
+//! which asks for the password.
+//!
+//! Once it is on, log ins ask for a code after the password (see `http::sessions`). Each code works
+//! once: `totp_last_step` is the time step of the last code that was used, and older codes are
+//! turned down.
+//!
+//! Org admins may require two-factor authentication for their org. Users of the org who have not
+//! set it up must do so the next time they log in.
+
+use std::collections::HashMap;
+
+use actix_web::error;
+use hmac::{Hmac, Mac, NewMac};
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{AttributeValue, GetItemInput, UpdateItemError, UpdateItemInput};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use ot::writing_proto::{
+    DisableTwoFactorRequest, DisableTwoFactorResponse, EnableTwoFactorRequest,
+    EnableTwoFactorResponse, ProvisionTwoFactorRequest, ProvisionTwoFactorResponse,
+    SetOrgTwoFactorRequiredRequest, SetOrgTwoFactorRequiredResponse,
+};
+
+use crate::dynamodb::{av_get_s, av_map, av_n, av_s, av_ss, table_name, DynamoDbClient};
+use crate::http::SessionUser;
+use crate::ids::Id;
+use crate::users::{get_user_item, UserRole};
+use crate::utils::{time, uri};
+
+/// Shown in authenticator apps next to the user's email.
+const TOTP_ISSUER: &str = "Writing";
+
+/// Codes change every 30 seconds, and have 6 digits. Authenticator apps assume these when the
+/// otpauth URI leaves them out, but we spell them out anyway.
+const TOTP_STEP_SECONDS: i64 = 30;
+const TOTP_DIGITS: u32 = 6;
+
+/// Codes from one step before or after the current one are accepted too, to allow for clock drift
+/// and slow typing.
+const TOTP_ALLOWED_STEP_DRIFT: i64 = 1;
+
+/// 160 bits, as RFC 4226 recommends.
+const TOTP_SECRET_BYTES: usize = 20;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+const BASE32_ALPHABET: base32::Alphabet = base32::Alphabet::RFC4648 { padding: false };
+
+/// Stores a new TOTP secret for the session user to add to their authenticator app. Two-factor
+/// authentication is not on until the user confirms a code with `enable_two_factor`. Provisioning
+/// again replaces a secret that was never confirmed.
+///
+/// If two-factor authentication is already on, returns 409 Conflict. Replacing the second factor
+/// takes turning it off first, which requires the password, so that a stolen session cannot take
+/// it over.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn provision_two_factor(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &ProvisionTwoFactorRequest,
+) -> actix_web::Result<ProvisionTwoFactorResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [provision_two_factor] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    let user_item = get_user_item(dynamodb_client, &session_user.user_id)
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    let email = av_get_s(&user_item, "email").ok_or_else(|| {
+        log_error("user is missing an email".to_string());
+        error::ErrorInternalServerError("")
+    })?;
+    provision_secret(dynamodb_client, email)
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?
+        .ok_or_else(|| error::ErrorConflict(""))
+}
+
+/// Turns on two-factor authentication for the session user, if the code matches the secret from
+/// `provision_two_factor`. Returns the user's recovery codes, which are never shown again.
+///
+/// If the code does not match, no secret was provisioned, or two-factor authentication is already
+/// on, returns 400 Bad Request.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn enable_two_factor(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &EnableTwoFactorRequest,
+) -> actix_web::Result<EnableTwoFactorResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [enable_two_factor] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    let user_item = get_user_item(dynamodb_client, &session_user.user_id)
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    let recovery_codes = enable(
+        dynamodb_client,
+        &user_item,
+        &request.code,
+        &chrono::Utc::now(),
+    )
+    .await
+    .map_err(|e| {
+        log_error(e.to_string());
+        error::ErrorInternalServerError("")
+    })?
+    .ok_or_else(|| error::ErrorBadRequest(""))?;
+    Ok(EnableTwoFactorResponse { recovery_codes })
+}
+
+/// Turns off two-factor authentication for the session user.
+///
+/// If the password is not the user's password, returns 403 Forbidden. If the user's org requires
+/// two-factor authentication, also returns 403 Forbidden.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn disable_two_factor(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &DisableTwoFactorRequest,
+) -> actix_web::Result<DisableTwoFactorResponse> {
+    let log_error = |error_message: String| {
+        // Leave the request out of the log, since it holds the user's password.
+        log::error!(
+            "Error occurred: \"{}\" [disable_two_factor] [session_user: {:?}]",
+            error_message,
+            session_user,
+        );
+    };
+    let user_item = get_user_item(dynamodb_client, &session_user.user_id)
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    let (email, hashed_password) = match (
+        av_get_s(&user_item, "email"),
+        av_get_s(&user_item, "hashed_password"),
+    ) {
+        (Some(email), Some(hashed_password)) => (email, hashed_password),
+        _ => {
+            log_error("user is missing a field".to_string());
+            return Err(error::ErrorInternalServerError(""));
+        }
+    };
+    let password_matched = bcrypt::verify(&request.password, hashed_password).unwrap_or(false);
+    if !password_matched {
+        return Err(error::ErrorForbidden(""));
+    }
+    let required = is_two_factor_required(dynamodb_client, &session_user.org_id)
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    if required {
+        return Err(error::ErrorForbidden(""));
+    }
+    dynamodb_client
+        .update_item(UpdateItemInput {
+            table_name: table_name("users"),
+            key: av_map(&[av_s("email", email)]),
+            update_expression: Some(String::from(
+                "REMOVE totp_secret, pending_totp_secret, totp_last_step, recovery_code_hashes",
+            )),
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    Ok(DisableTwoFactorResponse {})
+}
+
+/// Sets whether the session user's org requires two-factor authentication. Sessions that are
+/// already logged in are not affected until they log in again.
+///
+/// If the session user is not an org admin, returns 403 Forbidden.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn set_org_two_factor_required(
+    dynamodb_client: &DynamoDbClient,
+    session_user: &SessionUser,
+    request: &SetOrgTwoFactorRequiredRequest,
+) -> actix_web::Result<SetOrgTwoFactorRequiredResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [set_org_two_factor_required] \
+            [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    if session_user.user_role != UserRole::OrgAdmin {
+        return Err(error::ErrorForbidden(""));
+    }
+    let now = time::date_time_iso_str(&chrono::Utc::now());
+    let update_expression = if request.two_factor_required {
+        "SET two_factor_required_at = :now, updated_at = :now"
+    } else {
+        "REMOVE two_factor_required_at SET updated_at = :now"
+    };
+    dynamodb_client
+        .update_item(UpdateItemInput {
+            table_name: table_name("organizations"),
+            key: av_map(&[av_s("id", session_user.org_id.as_str())]),
+            condition_expression: Some(String::from("attribute_exists(id)")),
+            update_expression: Some(String::from(update_expression)),
+            expression_attribute_values: Some(av_map(&[av_s(":now", &now)])),
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    Ok(SetOrgTwoFactorRequiredResponse {})
+}
+
+/// Returns true if the user item has two-factor authentication turned on.
+pub fn is_enabled(user_item: &HashMap<String, AttributeValue>) -> bool {
+    av_get_s(user_item, "totp_secret").is_some()
+}
+
+/// Returns true if the org requires its users to use two-factor authentication.
+pub async fn is_two_factor_required(
+    dynamodb_client: &DynamoDbClient,
+    org_id: &Id,
+) -> anyhow::Result<bool> {
+    let output = dynamodb_client
+        .get_item(GetItemInput {
+            table_name: table_name("organizations"),
+            key: av_map(&[av_s("id", org_id.as_str())]),
+            projection_expression: Some(String::from("two_factor_required_at")),
+            ..Default::default()
+        })
+        .await?;
+    Ok(output
+        .item
+        .map_or(false, |item| item.contains_key("two_factor_required_at")))
+}
+
+/// Stores a new pending TOTP secret for the user with the given email. Returns None if two-factor
+/// authentication is already on. See `provision_two_factor`.
+pub async fn provision_secret(
+    dynamodb_client: &DynamoDbClient,
+    email: &str,
+) -> anyhow::Result<Option<ProvisionTwoFactorResponse>> {
+    let secret = base32::encode(BASE32_ALPHABET, &rand::random::<[u8; TOTP_SECRET_BYTES]>());
+    let input = UpdateItemInput {
+        table_name: table_name("users"),
+        key: av_map(&[av_s("email", email)]),
+        condition_expression: Some(String::from(
+            "attribute_exists(email) AND attribute_not_exists(totp_secret)",
+        )),
+        update_expression: Some(String::from("SET pending_totp_secret = :secret")),
+        expression_attribute_values: Some(av_map(&[av_s(":secret", &secret)])),
+        ..Default::default()
+    };
+    match dynamodb_client.update_item(input).await {
+        Ok(_) => Ok(Some(ProvisionTwoFactorResponse {
+            otpauth_uri: otpauth_uri(email, &secret),
+            secret,
+        })),
+        Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Turns on two-factor authentication for the user item, if the code matches its pending secret.
+/// Returns the new recovery codes, or None if the code does not match, or if two-factor
+/// authentication is already on. See `enable_two_factor`.
+pub async fn enable(
+    dynamodb_client: &DynamoDbClient,
+    user_item: &HashMap<String, AttributeValue>,
+    code: &str,
+    now: &chrono::DateTime<chrono::Utc>,
+) -> anyhow::Result<Option<Vec<String>>> {
+    let (email, secret) = match (
+        av_get_s(user_item, "email"),
+        av_get_s(user_item, "pending_totp_secret"),
+    ) {
+        (Some(email), Some(secret)) => (email, secret),
+        _ => return Ok(None),
+    };
+    let step = match matching_totp_step(secret, code, now) {
+        Some(step) => step,
+        None => return Ok(None),
+    };
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| new_recovery_code())
+        .collect();
+    let recovery_code_hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|recovery_code| hash_recovery_code(recovery_code))
+        .collect();
+    let input = UpdateItemInput {
+        table_name: table_name("users"),
+        key: av_map(&[av_s("email", email)]),
+        // The secret may have been provisioned again since the user item was read. An existing
+        // second factor is never replaced.
+        condition_expression: Some(String::from(
+            "pending_totp_secret = :secret AND attribute_not_exists(totp_secret)",
+        )),
+        update_expression: Some(String::from(
+            "SET totp_secret = :secret, totp_last_step = :step, \
+            recovery_code_hashes = :recovery_code_hashes \
+            REMOVE pending_totp_secret",
+        )),
+        expression_attribute_values: Some(av_map(&[
+            av_s(":secret", secret),
+            av_n(":step", step),
+            av_ss(":recovery_code_hashes", &recovery_code_hashes),
+        ])),
+        ..Default::default()
+    };
+    match dynamodb_client.update_item(input).await {
+        Ok(_) => Ok(Some(recovery_codes)),
+        Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Checks a code that the user gave at log in: either a code from their authenticator app, or one
+/// of their recovery codes. Either is used up once it is accepted. Returns false if the code is
+/// not accepted.
+pub async fn check_code(
+    dynamodb_client: &DynamoDbClient,
+    user_item: &HashMap<String, AttributeValue>,
+    code: &str,
+    now: &chrono::DateTime<chrono::Utc>,
+) -> anyhow::Result<bool> {
+    let (email, secret) = match (
+        av_get_s(user_item, "email"),
+        av_get_s(user_item, "totp_secret"),
+    ) {
+        (Some(email), Some(secret)) => (email, secret),
+        _ => return Ok(false),
+    };
+    let input = match matching_totp_step(secret, code, now) {
+        // Turn down codes from the step that was last used, or an earlier one, so that a code
+        // someone saw over the user's shoulder is no good.
+        Some(step) => UpdateItemInput {
+            table_name: table_name("users"),
+            key: av_map(&[av_s("email", email)]),
+            condition_expression: Some(String::from(
+                "attribute_not_exists(totp_last_step) OR totp_last_step < :step",
+            )),
+            update_expression: Some(String::from("SET totp_last_step = :step")),
+            expression_attribute_values: Some(av_map(&[av_n(":step", step)])),
+            ..Default::default()
+        },
+        None => {
+            let recovery_code_hash = hash_recovery_code(code);
+            UpdateItemInput {
+                table_name: table_name("users"),
+                key: av_map(&[av_s("email", email)]),
+                condition_expression: Some(String::from(
+                    "contains(recovery_code_hashes, :recovery_code_hash)",
+                )),
+                update_expression: Some(String::from(
+                    "DELETE recovery_code_hashes :recovery_code_hashes",
+                )),
+                expression_attribute_values: Some(av_map(&[
+                    av_s(":recovery_code_hash", &recovery_code_hash),
+                    av_ss(":recovery_code_hashes", &[recovery_code_hash.clone()]),
+                ])),
+                ..Default::default()
+            }
+        }
+    };
+    match dynamodb_client.update_item(input).await {
+        Ok(_) => Ok(true),
+        Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The TOTP code for the given time step.
+pub fn totp_code(secret: &[u8], step: i64) -> String {
+    let mut mac = Hmac::<Sha1>::new_varkey(secret).expect("HMAC takes keys of any size");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    // Dynamic truncation, from RFC 4226.
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(TOTP_DIGITS),
+        width = TOTP_DIGITS as usize
+    )
+}
+
+/// The TOTP time step that `now` falls in.
+pub fn totp_step(now: &chrono::DateTime<chrono::Utc>) -> i64 {
+    now.timestamp() / TOTP_STEP_SECONDS
+}
+
+/// Returns the time step of the code, if it is the code for a step near `now`.
+fn matching_totp_step(
+    secret: &str,
+    code: &str,
+    now: &chrono::DateTime<chrono::Utc>,
+) -> Option<i64> {
+    let secret = base32::decode(BASE32_ALPHABET, secret)?;
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    let step = totp_step(now);
+    (step - TOTP_ALLOWED_STEP_DRIFT..=step + TOTP_ALLOWED_STEP_DRIFT)
+        .find(|&step| totp_code(&secret, step) == code)
+}
+
+/// The URI that authenticator apps read from a QR code, to add the account with the secret.
+pub fn otpauth_uri(email: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{email}?secret={secret}&issuer={issuer}\
+        &algorithm=SHA1&digits={digits}&period={period}",
+        issuer = TOTP_ISSUER,
+        email = uri::encode(email),
+        secret = secret,
+        digits = TOTP_DIGITS,
+        period = TOTP_STEP_SECONDS,
+    )
+}
+
+/// Recovery codes look like "abcd-efgh", which is easier to copy down than a single run of
+/// letters.
+fn new_recovery_code() -> String {
+    let code = base32::encode(BASE32_ALPHABET, &rand::random::<[u8; 5]>()).to_lowercase();
+    format!("{}-{}", &code[..4], &code[4..])
+}
+
+/// Recovery codes are hashed without their dash, and without regard to case.
+fn hash_recovery_code(recovery_code: &str) -> String {
+    let normalized: String = recovery_code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    Sha256::digest(normalized.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ids::IdType;
+    use crate::testing::fixtures::create_user;
+    use crate::testing::utils::TestDynamoDb;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[test]
+    fn test_totp_code() {
+        // Test vectors from RFC 6238, which have 8 digits. The last 6 of them are the 6-digit
+        // codes.
+        let secret = b"12345678901234567890";
+        assert_eq!(totp_code(secret, 59 / 30), "287082");
+        assert_eq!(totp_code(secret, 1111111109 / 30), "081804");
+        assert_eq!(totp_code(secret, 1234567890 / 30), "005924");
+        assert_eq!(totp_code(secret, 2000000000 / 30), "279037");
+    }
+
+    #[tokio::test]
+    async fn test_enable_and_check_code() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let email = "two.factor@example.com";
+        let user_id = create_user(&db.dynamodb_client, email, "Two Factor").await;
+        let session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: Id::new(IdType::Organization),
+            user_role: UserRole::Default,
+        };
+        let now = chrono::Utc::now();
+
+        let provisioned = provision_two_factor(
+            &db.dynamodb_client,
+            &session_user,
+            &ProvisionTwoFactorRequest {},
+        )
+        .await?;
+        assert!(provisioned.otpauth_uri.starts_with(&format!(
+            "otpauth://totp/Writing:two.factor%40example.com?secret={}&",
+            provisioned.secret
+        )));
+        let secret = base32::decode(BASE32_ALPHABET, &provisioned.secret).unwrap();
+
+        // Nothing is on until a code is confirmed.
+        let user_item = get_user_item(&db.dynamodb_client, &user_id).await?;
+        assert!(!is_enabled(&user_item));
+        assert!(enable(&db.dynamodb_client, &user_item, "000000", &now)
+            .await?
+            .is_none());
+        let code = totp_code(&secret, totp_step(&now));
+        let recovery_codes = enable(&db.dynamodb_client, &user_item, &code, &now)
+            .await?
+            .unwrap();
+        assert_eq!(recovery_codes.len(), RECOVERY_CODE_COUNT);
+
+        let user_item = get_user_item(&db.dynamodb_client, &user_id).await?;
+        assert!(is_enabled(&user_item));
+        // The code that turned it on is used up, but the next one works, once.
+        assert!(!check_code(&db.dynamodb_client, &user_item, &code, &now).await?);
+        let later = now + chrono::Duration::seconds(TOTP_STEP_SECONDS);
+        let next_code = totp_code(&secret, totp_step(&later));
+        assert!(check_code(&db.dynamodb_client, &user_item, &next_code, &later).await?);
+        assert!(!check_code(&db.dynamodb_client, &user_item, &next_code, &later).await?);
+
+        // Recovery codes work once each, and are not picky about case.
+        let recovery_code = recovery_codes[0].to_uppercase();
+        assert!(check_code(&db.dynamodb_client, &user_item, &recovery_code, &now).await?);
+        assert!(!check_code(&db.dynamodb_client, &user_item, &recovery_code, &now).await?);
+        assert!(check_code(&db.dynamodb_client, &user_item, &recovery_codes[1], &now).await?);
+
+        // Once it is on, a session alone cannot replace the secret or the recovery codes.
+        let result = provision_two_factor(
+            &db.dynamodb_client,
+            &session_user,
+            &ProvisionTwoFactorRequest {},
+        )
+        .await;
+        assert_eq!(result.err().unwrap().as_response_error().status_code(), 409);
+        // Nor can a pending secret left over from before.
+        db.dynamodb_client
+            .update_item(UpdateItemInput {
+                table_name: table_name("users"),
+                key: av_map(&[av_s("email", email)]),
+                update_expression: Some(String::from("SET pending_totp_secret = :secret")),
+                expression_attribute_values: Some(av_map(&[av_s(":secret", &provisioned.secret)])),
+                ..Default::default()
+            })
+            .await?;
+        let user_item = get_user_item(&db.dynamodb_client, &user_id).await?;
+        let later = now + chrono::Duration::seconds(2 * TOTP_STEP_SECONDS);
+        let code = totp_code(&secret, totp_step(&later));
+        assert!(enable(&db.dynamodb_client, &user_item, &code, &later)
+            .await?
+            .is_none());
+        let user_item = get_user_item(&db.dynamodb_client, &user_id).await?;
+        assert!(check_code(&db.dynamodb_client, &user_item, &recovery_codes[2], &now).await?);
+
+        Ok(())
+    }
+}
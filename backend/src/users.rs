@@ -1,5 +1,26 @@
+//! Users, and their profiles.
+
+use std::collections::HashMap;
 use std::convert::TryFrom;
 
+use actix_web::error;
+use rusoto_dynamodb::{AttributeValue, QueryInput, UpdateItemInput};
+
+use ot::writing_proto::{
+    GetMyProfileRequest, GetMyProfileResponse, UpdateMyProfileRequest, UpdateMyProfileResponse,
+    UserProfile,
+};
+
+use crate::avatars;
+use crate::blob_store::BlobStore;
+use crate::dynamodb::{av_get_s, av_map, av_s, table_name, DynamoDbClient};
+use crate::http::SessionUser;
+use crate::ids::Id;
+use crate::utils::time;
+
+/// Names are trimmed, and may be at most this long.
+pub const MAX_NAME_CHARS: usize = 100;
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum UserRole {
     Default = 0,
@@ -17,3 +38,266 @@ impl TryFrom<i32> for UserRole {
         }
     }
 }
+
+/// Returns the user's name, or an empty string if the user has none.
+pub async fn get_user_name(
+    dynamodb_client: &DynamoDbClient,
+    user_id: &Id,
+) -> anyhow::Result<String> {
+    let output = dynamodb_client
+        .query(QueryInput {
+            table_name: table_name("users"),
+            index_name: Some(String::from("id-index")),
+            key_condition_expression: Some(String::from("id = :id")),
+            expression_attribute_values: Some(av_map(&[av_s(":id", user_id.as_str())])),
+            // "name" is a reserved word.
+            projection_expression: Some(String::from("#name")),
+            expression_attribute_names: Some(maplit::hashmap! {
+                String::from("#name") => String::from("name"),
+            }),
+            ..Default::default()
+        })
+        .await?;
+    Ok(output
+        .items
+        .unwrap_or_default()
+        .first()
+        .and_then(|item| av_get_s(item, "name"))
+        .unwrap_or_default()
+        .to_string())
+}
+
+/// Returns the session user's profile.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn get_my_profile(
+    dynamodb_client: &DynamoDbClient,
+    photo_store: &dyn BlobStore,
+    session_user: &SessionUser,
+    request: &GetMyProfileRequest,
+) -> actix_web::Result<GetMyProfileResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [get_my_profile] [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    let user_item = get_user_item(dynamodb_client, &session_user.user_id)
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    Ok(GetMyProfileResponse {
+        profile: Some(user_profile_from_item(photo_store, &user_item)),
+    })
+}
+
+/// Updates the session user's name, removes their photo, or both. New photos are uploaded with
+/// `avatars::request_avatar_upload` instead.
+///
+/// If the name is blank or longer than `MAX_NAME_CHARS`, returns 400 Bad Request.
+///
+/// If an internal server error occurs, returns 500 Internal Server Error.
+pub async fn update_my_profile(
+    dynamodb_client: &DynamoDbClient,
+    photo_store: &dyn BlobStore,
+    session_user: &SessionUser,
+    request: &UpdateMyProfileRequest,
+) -> actix_web::Result<UpdateMyProfileResponse> {
+    let log_error = |error_message: String| {
+        log::error!(
+            "Error occurred: \"{}\" [update_my_profile] [session_user: {:?}, request: {:?}]",
+            error_message,
+            session_user,
+            request,
+        );
+    };
+    let name = request.name.trim();
+    if !request.name.is_empty() && (name.is_empty() || name.chars().count() > MAX_NAME_CHARS) {
+        return Err(error::ErrorBadRequest(""));
+    }
+
+    let user_item = get_user_item(dynamodb_client, &session_user.user_id)
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    let email = av_get_s(&user_item, "email").ok_or_else(|| {
+        log_error("user is missing an email".to_string());
+        error::ErrorInternalServerError("")
+    })?;
+
+    let mut set_expressions = vec!["updated_at = :updated_at"];
+    let mut values = vec![
+        av_s(":id", session_user.user_id.as_str()),
+        av_s(":updated_at", &time::date_time_iso_str(&chrono::Utc::now())),
+    ];
+    if !name.is_empty() {
+        set_expressions.push("#name = :name");
+        values.push(av_s(":name", name));
+    }
+    let mut update_expression = format!("SET {}", set_expressions.join(", "));
+    // TODO(cliff): Delete removed photos from the photo store.
+    if request.remove_photo {
+        update_expression.push_str(" REMOVE avatar_key, photo_url");
+    }
+    let output = dynamodb_client
+        .update_item(UpdateItemInput {
+            table_name: table_name("users"),
+            key: av_map(&[av_s("email", email)]),
+            // The account may have been deleted since.
+            condition_expression: Some(String::from("id = :id")),
+            update_expression: Some(update_expression),
+            // "name" is a reserved word.
+            expression_attribute_names: if name.is_empty() {
+                None
+            } else {
+                Some(maplit::hashmap! {
+                    String::from("#name") => String::from("name"),
+                })
+            },
+            expression_attribute_values: Some(av_map(&values)),
+            return_values: Some(String::from("ALL_NEW")),
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| {
+            log_error(e.to_string());
+            error::ErrorInternalServerError("")
+        })?;
+    let user_item = output.attributes.unwrap_or_default();
+    Ok(UpdateMyProfileResponse {
+        profile: Some(user_profile_from_item(photo_store, &user_item)),
+    })
+}
+
+fn user_profile_from_item(
+    photo_store: &dyn BlobStore,
+    item: &HashMap<String, AttributeValue>,
+) -> UserProfile {
+    UserProfile {
+        user_id: av_get_s(item, "id").unwrap_or_default().to_string(),
+        email: av_get_s(item, "email").unwrap_or_default().to_string(),
+        name: av_get_s(item, "name").unwrap_or_default().to_string(),
+        // Null until the user uploads a photo.
+        photo_url: av_get_s(item, "photo_url").unwrap_or_default().to_string(),
+        photos: avatars::profile_photos(photo_store, item),
+    }
+}
+
+/// Reads the whole user item by id, since the email is its key, not the user id.
+pub async fn get_user_item(
+    dynamodb_client: &DynamoDbClient,
+    user_id: &Id,
+) -> anyhow::Result<HashMap<String, AttributeValue>> {
+    let output = dynamodb_client
+        .query(QueryInput {
+            table_name: table_name("users"),
+            index_name: Some(String::from("id-index")),
+            key_condition_expression: Some(String::from("id = :id")),
+            expression_attribute_values: Some(av_map(&[av_s(":id", user_id.as_str())])),
+            ..Default::default()
+        })
+        .await?;
+    output
+        .items
+        .unwrap_or_default()
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("user does not exist"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ids::IdType;
+    use crate::testing::fixtures::create_user;
+    use crate::testing::utils::{MemoryBlobStore, TestDynamoDb};
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[tokio::test]
+    async fn test_get_and_update_my_profile() -> TestResult {
+        let db = TestDynamoDb::new().await;
+        let photo_store = MemoryBlobStore::default();
+        let user_id = create_user(&db.dynamodb_client, "jane@smith.com", "Jane Smith").await;
+        let session_user = SessionUser {
+            user_id: user_id.clone(),
+            org_id: Id::new(IdType::Organization),
+            user_role: UserRole::Default,
+        };
+
+        let response = get_my_profile(
+            &db.dynamodb_client,
+            &photo_store,
+            &session_user,
+            &GetMyProfileRequest {},
+        )
+        .await?;
+        assert_eq!(
+            response.profile,
+            Some(UserProfile {
+                user_id: user_id.as_str().to_string(),
+                email: String::from("jane@smith.com"),
+                name: String::from("Jane Smith"),
+                photo_url: String::new(),
+                photos: Vec::new(),
+            })
+        );
+
+        // Blank names, and names that are too long, are turned down.
+        for request in vec![
+            UpdateMyProfileRequest {
+                name: String::from("   "),
+                ..Default::default()
+            },
+            UpdateMyProfileRequest {
+                name: "x".repeat(MAX_NAME_CHARS + 1),
+                ..Default::default()
+            },
+        ] {
+            let result =
+                update_my_profile(&db.dynamodb_client, &photo_store, &session_user, &request).await;
+            assert_eq!(result.err().unwrap().as_response_error().status_code(), 400);
+        }
+
+        let response = update_my_profile(
+            &db.dynamodb_client,
+            &photo_store,
+            &session_user,
+            &UpdateMyProfileRequest {
+                name: String::from(" Jane Doe "),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(response.profile.unwrap().name, "Jane Doe");
+        assert_eq!(
+            get_user_name(&db.dynamodb_client, &user_id).await?,
+            "Jane Doe"
+        );
+
+        // Leaves the name alone when none is given.
+        let response = update_my_profile(
+            &db.dynamodb_client,
+            &photo_store,
+            &session_user,
+            &UpdateMyProfileRequest {
+                remove_photo: true,
+                ..Default::default()
+            },
+        )
+        .await?;
+        let profile = response.profile.unwrap();
+        assert_eq!(profile.name, "Jane Doe");
+        assert_eq!(profile.photo_url, "");
+        assert!(profile.photos.is_empty());
+
+        Ok(())
+    }
+}
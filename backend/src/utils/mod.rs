@@ -1,3 +1,4 @@
 pub mod profanity;
 pub mod proto;
 pub mod time;
+pub mod uri;
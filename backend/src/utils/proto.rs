@@ -1,3 +1,8 @@
+use ot::writing_proto::ChangeSet;
+
+// Change sets in logs show at most this many characters of each insert.
+const LOG_MAX_INSERT_CHARS: usize = 40;
+
 pub fn encode_protobuf_message<M>(message: &M) -> Result<Vec<u8>, prost::EncodeError>
 where
     M: prost::Message,
@@ -8,3 +13,12 @@ where
         Err(e) => Err(e),
     }
 }
+
+/// Renders a change set for logs on one line, truncating long inserts so that a big paste does not
+/// flood them.
+pub fn change_set_for_log(change_set: Option<&ChangeSet>) -> String {
+    match change_set {
+        Some(change_set) => change_set.fmt_compact(LOG_MAX_INSERT_CHARS).to_string(),
+        None => String::from("None"),
+    }
+}
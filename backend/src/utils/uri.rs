@@ -0,0 +1,13 @@
+/// Percent-encodes everything but the characters that are never special in a URI, so that the
+/// value can go anywhere in a URI, e.g. in a query parameter.
+pub fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
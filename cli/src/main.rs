@@ -0,0 +1,336 @@
+//! `writing-cli` inspects and edits documents from the command line, using the headless client.
+//!
+//! Examples:
+//! ```text
+//! writing-cli --email jane@smith.com --password ... doc cat d_123
+//! writing-cli doc history d_123 --share_token abc
+//! writing-cli doc append d_123 --text "One more line"
+//! writing-cli doc export d_123 --format md > document.md
+//! writing-cli changeset decode change_set.pb
+//! writing-cli changeset decode --revisions revisions/d_123.pb
+//! ```
+
+use std::fmt::Write;
+
+use anyhow::{anyhow, bail};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use prost::Message;
+
+use ot::writing_proto::{
+    change_op::Op, ChangeSet, DocumentRevision, GetDocumentRequest, GetDocumentRevisionsRequest,
+};
+use writing_client::{Client, Document};
+
+#[tokio::main]
+async fn main() {
+    let matches = App::new("writing-cli")
+        .version("0.1")
+        .about("Inspect and edit documents")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("url")
+                .long("url")
+                .help("Where the backend is served from")
+                .takes_value(true)
+                .env("WRITING_URL")
+                .default_value("http://localhost:8080")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("email")
+                .long("email")
+                .help("Log in as this user. Not needed with a share token.")
+                .takes_value(true)
+                .env("WRITING_EMAIL")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("password")
+                .long("password")
+                .help("The password to log in with")
+                .takes_value(true)
+                .env("WRITING_PASSWORD")
+                .hide_env_values(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("share_token")
+                .long("share_token")
+                .help("Open documents through this share link token instead of logging in")
+                .takes_value(true)
+                .global(true),
+        )
+        .subcommand(
+            SubCommand::with_name("doc")
+                .about("Read and edit documents")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("cat")
+                        .about("Print the document")
+                        .arg(doc_id_arg()),
+                )
+                .subcommand(
+                    SubCommand::with_name("history")
+                        .about("List the document's revisions")
+                        .arg(doc_id_arg()),
+                )
+                .subcommand(
+                    SubCommand::with_name("append")
+                        .about("Append text to the end of the document")
+                        .arg(doc_id_arg())
+                        .arg(
+                            Arg::with_name("text")
+                                .long("text")
+                                .help("The text to append")
+                                .takes_value(true)
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("export")
+                        .about("Print the document with its title")
+                        .arg(doc_id_arg())
+                        .arg(
+                            Arg::with_name("format")
+                                .long("format")
+                                .takes_value(true)
+                                .possible_values(&["md", "txt"])
+                                .default_value("txt"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("changeset")
+                .about("Work with protobuf change sets")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("decode")
+                        .about("Pretty-print a protobuf-encoded change set")
+                        .arg(
+                            Arg::with_name("file")
+                                .help("The file to decode")
+                                .required(true),
+                        )
+                        .arg(Arg::with_name("revisions").long("revisions").help(
+                            "The file holds length-delimited revisions, like the revision logs \
+                            in an org export, rather than one change set",
+                        )),
+                ),
+        )
+        .get_matches();
+
+    if let Err(e) = run(&matches).await {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn doc_id_arg() -> Arg<'static, 'static> {
+    Arg::with_name("doc_id")
+        .help("The document id")
+        .required(true)
+}
+
+async fn run(matches: &ArgMatches<'_>) -> anyhow::Result<()> {
+    match matches.subcommand() {
+        ("doc", Some(doc_matches)) => {
+            let (command, command_matches) = doc_matches.subcommand();
+            let command_matches =
+                command_matches.ok_or_else(|| anyhow!("Missing doc subcommand"))?;
+            let client = connect(command_matches).await?;
+            let doc_id = command_matches.value_of("doc_id").unwrap();
+            let share_token = command_matches.value_of("share_token").unwrap_or("");
+            match command {
+                "cat" => {
+                    let document = load_document(&client, doc_id, share_token).await?;
+                    print!("{}", document.value());
+                }
+                "history" => {
+                    for revision in get_revisions(&client, doc_id, share_token).await? {
+                        println!("{}", format_revision(&revision));
+                    }
+                }
+                "append" => {
+                    let text = command_matches.value_of("text").unwrap();
+                    let mut document = load_document(&client, doc_id, share_token).await?;
+                    let len = document.value().encode_utf16().count();
+                    document.splice(len..len, text)?;
+                    document.sync(&client).await?;
+                    println!("Committed revision {}", document.last_revision_number());
+                }
+                "export" => {
+                    let response = client
+                        .get_document(&GetDocumentRequest {
+                            doc_id: doc_id.to_string(),
+                            share_token: share_token.to_string(),
+                        })
+                        .await?;
+                    let title = response.document.map(|d| d.title).unwrap_or_default();
+                    let document = load_document(&client, doc_id, share_token).await?;
+                    let format = command_matches.value_of("format").unwrap();
+                    print!("{}", export(&title, &document.value(), format));
+                }
+                _ => bail!("Unknown doc subcommand: {}", command),
+            }
+        }
+        ("changeset", Some(changeset_matches)) => match changeset_matches.subcommand() {
+            ("decode", Some(decode_matches)) => {
+                let bytes = std::fs::read(decode_matches.value_of("file").unwrap())?;
+                if decode_matches.is_present("revisions") {
+                    let mut buf = &bytes[..];
+                    while !buf.is_empty() {
+                        let revision = DocumentRevision::decode_length_delimited(&mut buf)?;
+                        println!("{}", format_revision(&revision));
+                    }
+                } else {
+                    let change_set = ChangeSet::decode(&bytes[..])?;
+                    print!("{}", format_change_set(&change_set));
+                }
+            }
+            (command, _) => bail!("Unknown changeset subcommand: {}", command),
+        },
+        (command, _) => bail!("Unknown command: {}", command),
+    }
+    Ok(())
+}
+
+/// Makes a client, logged in if an email was given.
+async fn connect(matches: &ArgMatches<'_>) -> anyhow::Result<Client> {
+    let client = Client::new(matches.value_of("url").unwrap())?;
+    if let Some(email) = matches.value_of("email") {
+        let password = matches
+            .value_of("password")
+            .ok_or_else(|| anyhow!("--password is required with --email"))?;
+        client.log_in(email, password).await?;
+    }
+    Ok(client)
+}
+
+async fn load_document(
+    client: &Client,
+    doc_id: &str,
+    share_token: &str,
+) -> anyhow::Result<Document> {
+    let mut document = Document::new(doc_id).with_share_token(share_token);
+    document.load_new_revisions(client).await?;
+    Ok(document)
+}
+
+async fn get_revisions(
+    client: &Client,
+    doc_id: &str,
+    share_token: &str,
+) -> anyhow::Result<Vec<DocumentRevision>> {
+    let mut request = GetDocumentRevisionsRequest {
+        doc_id: doc_id.to_string(),
+        share_token: share_token.to_string(),
+        ..Default::default()
+    };
+    let mut revisions = Vec::new();
+    loop {
+        let mut response = client.get_document_revisions(&request).await?;
+        if response.revisions.is_empty() {
+            break;
+        }
+        revisions.append(&mut response.revisions);
+        if response.end_of_revisions {
+            break;
+        }
+        request.after_revision_number = response.last_revision_number;
+    }
+    Ok(revisions)
+}
+
+/// One line per revision: its number, when it was committed, who by and from which client, and
+/// what it changed.
+fn format_revision(revision: &DocumentRevision) -> String {
+    let author = if revision.author_display_name.is_empty() {
+        revision.author_user_id.clone()
+    } else {
+        format!(
+            "{} ({})",
+            revision.author_display_name, revision.author_user_id
+        )
+    };
+    let changes = match &revision.change_set {
+        Some(change_set) => editor_core::get_change_set_description(change_set),
+        None if !revision.encrypted_change_set.is_empty() => {
+            format!("<{} encrypted bytes>", revision.encrypted_change_set.len())
+        }
+        None => String::from("<no change set>"),
+    };
+    let client = if revision.client_id.is_empty() {
+        String::new()
+    } else {
+        format!(" [{} {}]", revision.client_id, revision.session_id)
+    };
+    format!(
+        "#{} {} {}{}: {}",
+        revision.revision_number, revision.committed_at, author, client, changes
+    )
+}
+
+/// One line per op, followed by the change set's lengths and any problems with it.
+fn format_change_set(change_set: &ChangeSet) -> String {
+    let mut ret = String::new();
+    for (i, change_op) in change_set.ops.iter().enumerate() {
+        match &change_op.op {
+            Some(Op::Retain(retain)) => writeln!(&mut ret, "{:>4}  retain {}", i, retain.count),
+            Some(Op::Delete(delete)) => writeln!(&mut ret, "{:>4}  delete {}", i, delete.count),
+            Some(Op::Insert(insert)) => writeln!(
+                &mut ret,
+                "{:>4}  insert {:?}",
+                i,
+                ot::utils::u32_slice_to_string_lossy(&insert.content)
+            ),
+            None => writeln!(&mut ret, "{:>4}  <empty op>", i),
+        }
+        .unwrap();
+    }
+    match ot::get_input_output_doc_lengths(change_set) {
+        Ok((input_len, output_len)) => {
+            writeln!(&mut ret, "input length: {}", input_len).unwrap();
+            writeln!(&mut ret, "output length: {}", output_len).unwrap();
+        }
+        Err(e) => writeln!(&mut ret, "invalid: {}", e).unwrap(),
+    }
+    for diagnostic in ot::diagnose(change_set) {
+        writeln!(&mut ret, "warning: {}", diagnostic).unwrap();
+    }
+    ret
+}
+
+fn export(title: &str, value: &str, format: &str) -> String {
+    let mut ret = match format {
+        "md" => format!("# {}\n\n{}", title, value),
+        _ => value.to_string(),
+    };
+    if !ret.ends_with('\n') {
+        ret.push('\n');
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_change_set() {
+        let mut change_set = ChangeSet::new();
+        change_set.retain(3);
+        change_set.insert("a\nb");
+        change_set.delete(2);
+        assert_eq!(
+            format_change_set(&change_set),
+            "   0  retain 3\n   1  insert \"a\\nb\"\n   2  delete 2\n\
+            input length: 5\noutput length: 6\n"
+        );
+    }
+
+    #[test]
+    fn test_export() {
+        assert_eq!(export("Notes", "Hello\n", "md"), "# Notes\n\nHello\n");
+        assert_eq!(export("Notes", "Hello", "txt"), "Hello\n");
+    }
+}
@@ -0,0 +1,151 @@
+use prost::Message;
+use thiserror::Error;
+
+use editor_core::committed_log::CommittedLogError;
+use ot::protocol::{PROTOBUF_CONTENT_TYPE, PROTOCOL_VERSION, PROTOCOL_VERSION_HEADER};
+use ot::writing_proto::{
+    CreateDocumentRequest, CreateDocumentResponse, GetDocumentRequest, GetDocumentResponse,
+    GetDocumentRevisionsRequest, GetDocumentRevisionsResponse, SubmitDocumentChangeSetRequest,
+    SubmitDocumentChangeSetResponse,
+};
+use ot::OtError;
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("HTTP Error: {0}")]
+    HttpError(#[from] reqwest::Error),
+    #[error("{path} failed with status {status}")]
+    StatusError {
+        path: String,
+        status: reqwest::StatusCode,
+    },
+    #[error("Encode Error: {0}")]
+    EncodeError(#[from] prost::EncodeError),
+    #[error("Decode Error: {0}")]
+    DecodeError(#[from] prost::DecodeError),
+    #[error("Ot Error: {0}")]
+    OtError(#[from] OtError),
+    #[error("Committed Log Error: {0}")]
+    CommittedLogError(#[from] CommittedLogError),
+    #[error("Invalid Response Error: {0}")]
+    InvalidResponseError(String),
+    #[error("Gave up after {0} attempts")]
+    TooManyAttemptsError(usize),
+    #[error("The server no longer supports this client's protocol version; update the client")]
+    UpdateRequiredError,
+    #[error("The change modifies a range of the document that only its owner may edit")]
+    ProtectedRangeModifiedError,
+}
+
+impl ClientError {
+    /// Returns true if the request might succeed if it were sent again: the connection failed, or
+    /// the server had an internal error.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ClientError::HttpError(e) => e.is_timeout() || e.is_connect() || e.is_request(),
+            ClientError::StatusError { status, .. } => status.is_server_error(),
+            _ => false,
+        }
+    }
+}
+
+/// An HTTP client for the document API. Logging in stores the session cookie in the client, and
+/// every clone of the client shares it.
+#[derive(Clone)]
+pub struct Client {
+    http_client: reqwest::Client,
+    base_url: String,
+}
+
+impl Client {
+    /// `base_url` is where the backend is served from, like `http://localhost:8000`.
+    pub fn new(base_url: &str) -> Result<Self, ClientError> {
+        let http_client = reqwest::Client::builder()
+            .cookie_store(true)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        Ok(Self {
+            http_client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    /// Logs in with the same form that the log in page posts.
+    pub async fn log_in(&self, email: &str, password: &str) -> Result<(), ClientError> {
+        let path = "/log_in";
+        let response = self
+            .http_client
+            .post(&format!("{}{}", &self.base_url, path))
+            .form(&[("email", email), ("password", password)])
+            .send()
+            .await?;
+        // A successful log in redirects to the app.
+        if !response.status().is_redirection() {
+            return Err(ClientError::StatusError {
+                path: path.to_string(),
+                status: response.status(),
+            });
+        }
+        Ok(())
+    }
+
+    pub async fn create_document(
+        &self,
+        request: &CreateDocumentRequest,
+    ) -> Result<CreateDocumentResponse, ClientError> {
+        self.execute("/api/documents.create_document", request)
+            .await
+    }
+
+    pub async fn get_document(
+        &self,
+        request: &GetDocumentRequest,
+    ) -> Result<GetDocumentResponse, ClientError> {
+        self.execute("/api/documents.get_document", request).await
+    }
+
+    pub async fn get_document_revisions(
+        &self,
+        request: &GetDocumentRevisionsRequest,
+    ) -> Result<GetDocumentRevisionsResponse, ClientError> {
+        self.execute("/api/documents.get_document_revisions", request)
+            .await
+    }
+
+    pub async fn submit_document_change_set(
+        &self,
+        request: &SubmitDocumentChangeSetRequest,
+    ) -> Result<SubmitDocumentChangeSetResponse, ClientError> {
+        self.execute("/api/documents.submit_document_change_set", request)
+            .await
+    }
+
+    /// Posts the request to the API endpoint at `path`, and decodes the response.
+    pub async fn execute<Req, Res>(&self, path: &str, request: &Req) -> Result<Res, ClientError>
+    where
+        Req: Message,
+        Res: Message + Default,
+    {
+        let mut body = Vec::with_capacity(request.encoded_len());
+        request.encode(&mut body)?;
+        let response = self
+            .http_client
+            .post(&format!("{}{}", &self.base_url, path))
+            .header("content-type", PROTOBUF_CONTENT_TYPE)
+            .header(PROTOCOL_VERSION_HEADER, PROTOCOL_VERSION.to_string())
+            .body(body)
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::UPGRADE_REQUIRED {
+            return Err(ClientError::UpdateRequiredError);
+        }
+        if !response.status().is_success() {
+            return Err(ClientError::StatusError {
+                path: path.to_string(),
+                status: response.status(),
+            });
+        }
+        let bytes = response.bytes().await?;
+        Ok(Res::decode(&bytes[..])?)
+    }
+}
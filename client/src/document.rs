@@ -0,0 +1,350 @@
+use std::ops::Range;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use editor_core::committed_log::CommittedLog;
+use editor_core::pending_log::PendingLog;
+use ot::writing_proto::submit_document_change_set_response::ResponseCode;
+use ot::writing_proto::{
+    ChangeSet, DocumentRevision, GetDocumentRevisionsRequest, SubmitDocumentChangeSetRequest,
+};
+
+use crate::api::ClientError;
+use crate::transport::Transport;
+
+/// How many times to try submitting a change set, counting both retries after transient errors
+/// and retries after rebasing onto revisions that someone else committed first.
+const MAX_SUBMIT_ATTEMPTS: usize = 10;
+
+// Transient errors are retried after this delay, doubled for each failed attempt.
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(100);
+
+/// Sent with every change set, so that revisions can be traced back to the build that made them.
+const CLIENT_ID: &str = concat!("writing-client/", env!("CARGO_PKG_VERSION"));
+
+/// A document being edited, kept in sync with the server.
+///
+/// Local edits apply to the document right away, and queue up as pending change sets. `sync`
+/// submits them one at a time, on top of the last revision we know about. If someone else
+/// committed first, we load their revisions, rebase the pending change sets onto them with
+/// `ot::transform`, and submit again.
+pub struct Document {
+    doc_id: String,
+    // Sent with every request when the document was opened through a share link. Empty otherwise.
+    share_token: String,
+    committed_log: CommittedLog,
+    // The document as of the last committed revision.
+    committed_value: Vec<u16>,
+    pending_log: PendingLog,
+    // The document with the pending change sets applied.
+    value: Vec<u16>,
+    // Sent with every change set, so that revisions made through this `Document` can be grouped
+    // together.
+    session_id: String,
+}
+
+impl Document {
+    pub fn new(doc_id: &str) -> Self {
+        Self {
+            doc_id: doc_id.to_string(),
+            share_token: String::new(),
+            committed_log: CommittedLog::new(),
+            committed_value: Vec::new(),
+            pending_log: PendingLog::new(),
+            value: Vec::new(),
+            session_id: new_session_id(),
+        }
+    }
+
+    /// Opens the document through a share link, for clients that are not logged in.
+    pub fn with_share_token(mut self, share_token: &str) -> Self {
+        self.share_token = share_token.to_string();
+        self
+    }
+
+    pub fn doc_id(&self) -> &str {
+        &self.doc_id
+    }
+
+    /// The document, including local edits that have not been committed yet.
+    pub fn value(&self) -> String {
+        String::from_utf16_lossy(&self.value)
+    }
+
+    /// The document as of the last committed revision.
+    pub fn committed_value(&self) -> String {
+        String::from_utf16_lossy(&self.committed_value)
+    }
+
+    /// The number of the last committed revision that we know about, or 0 if there are none.
+    pub fn last_revision_number(&self) -> i64 {
+        self.committed_log.last_revision_number()
+    }
+
+    pub fn has_pending_changes(&self) -> bool {
+        !self.pending_log.is_empty()
+    }
+
+    /// Applies a local edit. The change set must be based on the document's current value. It is
+    /// committed on the next `sync`.
+    pub fn edit(&mut self, change_set: &ChangeSet) -> Result<(), ClientError> {
+        self.value = ot::apply_slice(&self.value, change_set)?;
+        self.pending_log.push_back(change_set);
+        Ok(())
+    }
+
+    /// Replaces `range` of the document, in UTF-16 code units, with `content`.
+    pub fn splice(&mut self, range: Range<usize>, content: &str) -> Result<(), ClientError> {
+        let content = ot::utils::str_to_u32_vec(content);
+        let mut builder = ot::SpliceBuilder::new(self.value.len() as i64);
+        builder.splice((range.start as i64)..(range.end as i64), &content)?;
+        self.edit(&builder.finish())
+    }
+
+    /// Loads every revision committed since the last sync, then commits the pending change sets,
+    /// in order. Returns once there is nothing left to commit.
+    ///
+    /// Transient errors are retried with backoff. Gives up on a change set after
+    /// `MAX_SUBMIT_ATTEMPTS` attempts, leaving it and the rest of the pending change sets to the
+    /// next sync.
+    ///
+    /// The server never commits a change set that modifies a range that the document's owner
+    /// protected. Such a change set is dropped from the document, and the sync stops with
+    /// `ProtectedRangeModifiedError`, leaving the rest of the pending change sets to the next sync.
+    pub async fn sync<T: Transport + ?Sized>(&mut self, transport: &T) -> Result<(), ClientError> {
+        self.load_new_revisions(transport).await?;
+        // Edits that cancel each other out never need to be committed.
+        self.pending_log.compress()?;
+        while let Some(change_set) = self.pending_log.front().cloned() {
+            self.submit_with_retry(transport, &change_set).await?;
+        }
+        Ok(())
+    }
+
+    /// Loads the revisions that were committed since the last one we know about, and rebases the
+    /// pending change sets onto them.
+    pub async fn load_new_revisions<T: Transport + ?Sized>(
+        &mut self,
+        transport: &T,
+    ) -> Result<(), ClientError> {
+        let mut request = GetDocumentRevisionsRequest {
+            doc_id: self.doc_id.clone(),
+            share_token: self.share_token.clone(),
+            after_revision_number: self.last_revision_number(),
+            ..Default::default()
+        };
+        let mut revisions = Vec::new();
+        loop {
+            let mut response = transport.get_document_revisions(&request).await?;
+            if response.revisions.is_empty() {
+                break;
+            }
+            revisions.append(&mut response.revisions);
+            if response.end_of_revisions {
+                break;
+            }
+            request.after_revision_number = response.last_revision_number;
+        }
+        self.integrate_remote_revisions(revisions)
+    }
+
+    async fn submit_with_retry<T: Transport + ?Sized>(
+        &mut self,
+        transport: &T,
+        change_set: &ChangeSet,
+    ) -> Result<(), ClientError> {
+        // The change set that we submit changes each time we rebase it.
+        let mut change_set = change_set.clone();
+        let mut retry_delay = RETRY_BASE_DELAY;
+        for _ in 0..MAX_SUBMIT_ATTEMPTS {
+            let request = SubmitDocumentChangeSetRequest {
+                doc_id: self.doc_id.clone(),
+                share_token: self.share_token.clone(),
+                on_revision_number: self.last_revision_number(),
+                change_set: Some(change_set.clone()),
+                client_id: CLIENT_ID.to_string(),
+                session_id: self.session_id.clone(),
+                ..Default::default()
+            };
+            let mut response = match transport.submit_document_change_set(&request).await {
+                Ok(response) => response,
+                Err(e) if e.is_transient() => {
+                    tokio::time::delay_for(retry_delay).await;
+                    retry_delay *= 2;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            match response.response_code() {
+                ResponseCode::Ack => {
+                    // If the change set was an identity, the server acknowledges it without
+                    // committing a revision.
+                    self.acknowledge(response.revisions.pop())?;
+                    return Ok(());
+                }
+                ResponseCode::DiscoveredNewRevisions => {
+                    let end_of_revisions = response.end_of_revisions;
+                    self.integrate_remote_revisions(response.revisions)?;
+                    if !end_of_revisions {
+                        self.load_new_revisions(transport).await?;
+                    }
+                    change_set = match self.pending_log.front() {
+                        Some(change_set) => change_set.clone(),
+                        None => return Ok(()),
+                    };
+                }
+                ResponseCode::Throttled => {
+                    // Hold off as long as the server asks, then commit everything pending as one
+                    // change set.
+                    let retry_after = Duration::from_millis(response.retry_after_millis as u64);
+                    tokio::time::delay_for(retry_after).await;
+                    self.pending_log.compress()?;
+                    change_set = match self.pending_log.front() {
+                        Some(change_set) => change_set.clone(),
+                        None => return Ok(()),
+                    };
+                }
+                ResponseCode::ProtectedRangeModified => {
+                    self.discard_first_pending()?;
+                    return Err(ClientError::ProtectedRangeModifiedError);
+                }
+                code => {
+                    return Err(ClientError::InvalidResponseError(format!(
+                        "Unexpected response code: {:?}",
+                        code
+                    )))
+                }
+            }
+        }
+        Err(ClientError::TooManyAttemptsError(MAX_SUBMIT_ATTEMPTS))
+    }
+
+    /// Adds remote revisions to the committed log, and rebases the pending change sets onto them.
+    fn integrate_remote_revisions(
+        &mut self,
+        revisions: Vec<DocumentRevision>,
+    ) -> Result<(), ClientError> {
+        let composed = match self.committed_log.append_remote_revisions(revisions)? {
+            Some(composed) => composed.composed_change_sets,
+            None => return Ok(()),
+        };
+        self.committed_value = ot::apply_slice(&self.committed_value, &composed)?;
+        let transformed_remote = self.pending_log.transform(&composed)?;
+        self.value = ot::apply_slice(&self.value, &transformed_remote)?;
+        Ok(())
+    }
+
+    /// Commits the first pending change set, which the server acknowledged as `revision`. Nothing
+    /// changes unless the revision is the one that we expect next.
+    fn acknowledge(&mut self, revision: Option<DocumentRevision>) -> Result<(), ClientError> {
+        let change_set = self.pending_log.front().cloned().ok_or_else(|| {
+            ClientError::InvalidResponseError(String::from("Acknowledged with nothing pending"))
+        })?;
+        match revision {
+            Some(revision) => {
+                let committed_value = ot::apply_slice(&self.committed_value, &change_set)?;
+                self.committed_log.push_local_revision(revision)?;
+                self.committed_value = committed_value;
+            }
+            // Only identities are acknowledged without a revision. Anything else would leave the
+            // change in the document, but not on the server.
+            None if !ot::is_identity(&change_set, None) => {
+                return Err(ClientError::InvalidResponseError(String::from(
+                    "Acknowledged a change set that changes the document without a revision",
+                )));
+            }
+            None => {}
+        }
+        self.pending_log.pop_front();
+        Ok(())
+    }
+
+    /// Drops the first pending change set, which the server will never commit, from the document.
+    fn discard_first_pending(&mut self) -> Result<(), ClientError> {
+        if let Some(undo) = self.pending_log.discard_front(&self.committed_value)? {
+            self.value = ot::apply_slice(&self.value, &undo)?;
+        }
+        Ok(())
+    }
+}
+
+/// Unique enough to tell sessions apart in the revision history, without a source of randomness.
+fn new_session_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    format!("{:x}-{:x}", std::process::id(), nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn revision(revision_number: i64, change_set: ChangeSet) -> DocumentRevision {
+        DocumentRevision {
+            revision_number,
+            change_set: Some(change_set),
+            ..Default::default()
+        }
+    }
+
+    fn splice(document_len: i64, range: Range<i64>, content: &str) -> ChangeSet {
+        let content = ot::utils::str_to_u32_vec(content);
+        let mut builder = ot::SpliceBuilder::new(document_len);
+        builder.splice(range, &content).unwrap();
+        builder.finish()
+    }
+
+    #[test]
+    fn test_rebase_pending_changes() {
+        let mut document = Document::new("d_1");
+        document
+            .integrate_remote_revisions(vec![revision(1, splice(0, 0..0, "Hello world"))])
+            .unwrap();
+        assert_eq!(document.value(), "Hello world");
+        assert_eq!(document.last_revision_number(), 1);
+
+        document.splice(11..11, "!").unwrap();
+        assert_eq!(document.value(), "Hello world!");
+        assert!(document.has_pending_changes());
+
+        // Someone else commits first. Our pending change is rebased onto theirs.
+        document
+            .integrate_remote_revisions(vec![revision(2, splice(11, 0..5, "Hi"))])
+            .unwrap();
+        assert_eq!(document.committed_value(), "Hi world");
+        assert_eq!(document.value(), "Hi world!");
+        assert_eq!(document.pending_log.front(), Some(&splice(8, 8..8, "!")));
+
+        let pending = document.pending_log.front().cloned().unwrap();
+        document.acknowledge(Some(revision(3, pending))).unwrap();
+        assert_eq!(document.committed_value(), "Hi world!");
+        assert_eq!(document.last_revision_number(), 3);
+        assert!(!document.has_pending_changes());
+    }
+
+    #[test]
+    fn test_discard_first_pending() {
+        let mut document = Document::new("d_1");
+        document
+            .integrate_remote_revisions(vec![revision(1, splice(0, 0..0, "Header\nBody"))])
+            .unwrap();
+        document.splice(0..6, "Title").unwrap();
+        document.splice(10..10, "!").unwrap();
+        assert_eq!(document.value(), "Title\nBody!");
+
+        // The server rejects the first change. The second one stays, rebased to do without it.
+        document.discard_first_pending().unwrap();
+        assert_eq!(document.value(), "Header\nBody!");
+        assert_eq!(document.pending_log.front(), Some(&splice(11, 11..11, "!")));
+        assert_eq!(document.committed_value(), "Header\nBody");
+    }
+
+    #[test]
+    fn test_out_of_order_revisions() {
+        let mut document = Document::new("d_1");
+        let result = document.integrate_remote_revisions(vec![revision(2, splice(0, 0..0, "a"))]);
+        assert!(matches!(result, Err(ClientError::CommittedLogError(_))));
+        assert_eq!(document.value(), "");
+    }
+}
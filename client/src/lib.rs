@@ -0,0 +1,27 @@
+//! A headless client for the collaborative editing protocol.
+//!
+//! This is the same protocol that the browser speaks, without a browser: CLI tools, bots and load
+//! tests can open a document, edit it, and sync their edits with everyone else's.
+//!
+//! Example:
+//! ```ignore
+//! let client = Client::new("http://localhost:8000")?;
+//! client.log_in("jane@smith.com", "AJLK:jasd;lj123123").await?;
+//! let mut document = Document::new(&doc_id);
+//! document.sync(&client).await?;
+//! document.splice(0..0, "Hello, world!\n")?;
+//! document.sync(&client).await?;
+//! ```
+//!
+//! End-to-end encrypted documents are not supported, since their revisions can only be read with
+//! the document key.
+
+mod api;
+mod document;
+#[cfg(test)]
+mod simulation;
+mod transport;
+
+pub use api::{Client, ClientError};
+pub use document::Document;
+pub use transport::{ResponseFuture, Transport};
@@ -0,0 +1,449 @@
+//! A deterministic harness for the sync loop. Documents sync with an in-memory server through a
+//! transport that loses, repeats, holds up and mixes up responses. Faults are picked by a seeded
+//! random number generator, and time is paused, so any failure replays exactly from its seed.
+//!
+//! The browser's `DocumentEditorModel` runs the same protocol, but only in wasm. `Document` is the
+//! client that these tests can drive natively.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::Duration;
+
+use reqwest::StatusCode;
+
+use ot::writing_proto::submit_document_change_set_response::ResponseCode;
+use ot::writing_proto::{
+    DocumentRevision, GetDocumentRevisionsRequest, GetDocumentRevisionsResponse,
+    SubmitDocumentChangeSetRequest, SubmitDocumentChangeSetResponse,
+};
+
+use crate::api::ClientError;
+use crate::document::Document;
+use crate::transport::{ResponseFuture, Transport};
+
+const DOC_ID: &str = "d_1";
+
+const GET_DOCUMENT_REVISIONS_PATH: &str = "/api/documents.get_document_revisions";
+const SUBMIT_DOCUMENT_CHANGE_SET_PATH: &str = "/api/documents.submit_document_change_set";
+
+/// The server returns at most this many revisions per read, so that catching up takes several.
+const PAGE_SIZE: usize = 3;
+
+/// Requests and responses that are held up wait up to this long.
+const MAX_DELAY_MILLIS: u64 = 50;
+
+/// A xorshift generator. Plenty for picking faults and edits, and the same everywhere.
+struct Rng(Cell<u64>);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(Cell::new(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1))
+    }
+
+    fn next(&self) -> u64 {
+        let mut x = self.0.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0.set(x);
+        x
+    }
+
+    fn below(&self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn chance(&self, percent: u64) -> bool {
+        self.below(100) < percent
+    }
+}
+
+/// The backend's side of the protocol: a change set is committed only on top of the last revision.
+/// Otherwise, the response has the revisions that the client is missing.
+#[derive(Default)]
+struct Server {
+    revisions: Vec<DocumentRevision>,
+    value: Vec<u16>,
+}
+
+impl Server {
+    fn last_revision_number(&self) -> i64 {
+        self.revisions.len() as i64
+    }
+
+    fn value(&self) -> String {
+        String::from_utf16_lossy(&self.value)
+    }
+
+    fn revisions_after(&self, after_revision_number: i64) -> Vec<DocumentRevision> {
+        let skip = after_revision_number.max(0) as usize;
+        self.revisions.iter().skip(skip).cloned().collect()
+    }
+
+    fn get_document_revisions(
+        &self,
+        request: &GetDocumentRevisionsRequest,
+    ) -> GetDocumentRevisionsResponse {
+        let mut revisions = self.revisions_after(request.after_revision_number);
+        revisions.truncate(PAGE_SIZE);
+        let last_revision_number = revisions
+            .last()
+            .map_or(request.after_revision_number, |r| r.revision_number);
+        GetDocumentRevisionsResponse {
+            last_revision_number,
+            revisions,
+            end_of_revisions: last_revision_number >= self.last_revision_number(),
+            ..Default::default()
+        }
+    }
+
+    fn submit_document_change_set(
+        &mut self,
+        request: &SubmitDocumentChangeSetRequest,
+    ) -> Result<SubmitDocumentChangeSetResponse, ClientError> {
+        let last_revision_number = self.last_revision_number();
+        if request.on_revision_number < last_revision_number {
+            return Ok(SubmitDocumentChangeSetResponse {
+                response_code: ResponseCode::DiscoveredNewRevisions.into(),
+                last_revision_number,
+                revisions: self.revisions_after(request.on_revision_number),
+                end_of_revisions: true,
+                ..Default::default()
+            });
+        }
+        let bad_request = || status_error(SUBMIT_DOCUMENT_CHANGE_SET_PATH, StatusCode::BAD_REQUEST);
+        let change_set = match &request.change_set {
+            Some(change_set) if request.on_revision_number == last_revision_number => change_set,
+            _ => return Err(bad_request()),
+        };
+        let value = ot::apply_slice(&self.value, change_set).map_err(|_| bad_request())?;
+        let mut response = SubmitDocumentChangeSetResponse {
+            response_code: ResponseCode::Ack.into(),
+            last_revision_number,
+            end_of_revisions: true,
+            ..Default::default()
+        };
+        if ot::is_identity(change_set, None) {
+            return Ok(response);
+        }
+        let revision = DocumentRevision {
+            doc_id: request.doc_id.clone(),
+            revision_number: last_revision_number + 1,
+            change_set: Some(change_set.clone()),
+            client_id: request.client_id.clone(),
+            session_id: request.session_id.clone(),
+            ..Default::default()
+        };
+        self.revisions.push(revision.clone());
+        self.value = value;
+        response.last_revision_number = revision.revision_number;
+        response.revisions.push(revision);
+        Ok(response)
+    }
+}
+
+fn status_error(path: &str, status: StatusCode) -> ClientError {
+    ClientError::StatusError {
+        path: path.to_string(),
+        status,
+    }
+}
+
+/// The chances, out of 100, of each fault hitting a request.
+#[derive(Clone, Copy, Default)]
+struct Faults {
+    /// The server handles the request, but the response is lost. The client sees a gateway
+    /// timeout, and cannot tell whether the request was handled.
+    drop_response: u64,
+    /// The request reaches the server a second time right after the first, like a retry by a
+    /// proxy. The client sees the first response.
+    duplicate_request: u64,
+    /// The request is held up on its way to the server, and the response on its way back, so that
+    /// other clients' requests overtake it.
+    delay: u64,
+    /// The client sees the response to its previous request of the same kind instead, as if the
+    /// responses were delivered out of order.
+    stale_response: u64,
+}
+
+impl Faults {
+    const ALWAYS: u64 = 100;
+}
+
+/// Sends a document's requests to the shared `Server`, with faults.
+struct FaultyTransport {
+    server: Rc<RefCell<Server>>,
+    faults: Cell<Faults>,
+    // Faults for the next requests, in order, in place of `faults`.
+    scripted_faults: RefCell<VecDeque<Faults>>,
+    rng: Rng,
+    // The last responses, for `stale_response` to deliver in place of the next ones.
+    last_revisions_response: RefCell<Option<GetDocumentRevisionsResponse>>,
+    last_submit_response: RefCell<Option<SubmitDocumentChangeSetResponse>>,
+}
+
+impl FaultyTransport {
+    fn new(server: Rc<RefCell<Server>>, faults: Faults, seed: u64) -> Self {
+        Self {
+            server,
+            faults: Cell::new(faults),
+            scripted_faults: RefCell::new(VecDeque::new()),
+            rng: Rng::new(seed),
+            last_revisions_response: RefCell::new(None),
+            last_submit_response: RefCell::new(None),
+        }
+    }
+
+    /// The next requests meet these faults, one per request, and then `faults` again.
+    fn script(&self, faults: &[Faults]) {
+        self.scripted_faults
+            .borrow_mut()
+            .extend(faults.iter().copied());
+    }
+
+    async fn deliver<Res, F>(
+        &self,
+        path: &str,
+        last_response: &RefCell<Option<Res>>,
+        mut handle: F,
+    ) -> Result<Res, ClientError>
+    where
+        Res: Clone,
+        F: FnMut(&mut Server) -> Result<Res, ClientError>,
+    {
+        let faults = self
+            .scripted_faults
+            .borrow_mut()
+            .pop_front()
+            .unwrap_or_else(|| self.faults.get());
+        self.hold_up(faults).await;
+        let response = handle(&mut self.server.borrow_mut());
+        if self.rng.chance(faults.duplicate_request) {
+            let _ = handle(&mut self.server.borrow_mut());
+        }
+        self.hold_up(faults).await;
+        if self.rng.chance(faults.drop_response) {
+            return Err(status_error(path, StatusCode::GATEWAY_TIMEOUT));
+        }
+        let response = response?;
+        let previous = last_response.replace(Some(response.clone()));
+        match previous {
+            Some(previous) if self.rng.chance(faults.stale_response) => Ok(previous),
+            _ => Ok(response),
+        }
+    }
+
+    async fn hold_up(&self, faults: Faults) {
+        if self.rng.chance(faults.delay) {
+            let millis = 1 + self.rng.below(MAX_DELAY_MILLIS);
+            tokio::time::delay_for(Duration::from_millis(millis)).await;
+        }
+    }
+}
+
+impl Transport for FaultyTransport {
+    fn get_document_revisions<'a>(
+        &'a self,
+        request: &'a GetDocumentRevisionsRequest,
+    ) -> ResponseFuture<'a, GetDocumentRevisionsResponse> {
+        Box::pin(self.deliver(
+            GET_DOCUMENT_REVISIONS_PATH,
+            &self.last_revisions_response,
+            move |server| Ok(server.get_document_revisions(request)),
+        ))
+    }
+
+    fn submit_document_change_set<'a>(
+        &'a self,
+        request: &'a SubmitDocumentChangeSetRequest,
+    ) -> ResponseFuture<'a, SubmitDocumentChangeSetResponse> {
+        Box::pin(self.deliver(
+            SUBMIT_DOCUMENT_CHANGE_SET_PATH,
+            &self.last_submit_response,
+            move |server| server.submit_document_change_set(request),
+        ))
+    }
+}
+
+/// Inserts a few copies of `letter`, or deletes a few characters.
+fn random_edit(document: &mut Document, rng: &Rng, letter: char) {
+    let len = document.value().len();
+    if len > 0 && rng.chance(30) {
+        let start = rng.below(len as u64) as usize;
+        let end = (start + 1 + rng.below(3) as usize).min(len);
+        document.splice(start..end, "").unwrap();
+    } else {
+        let start = rng.below(len as u64 + 1) as usize;
+        let content = letter.to_string().repeat(1 + rng.below(3) as usize);
+        document.splice(start..start, &content).unwrap();
+    }
+}
+
+/// Edits and syncs the document a number of times.
+async fn run_client(document: &mut Document, transport: &FaultyTransport, rng: Rng, letter: char) {
+    for _ in 0..10 {
+        for _ in 0..=rng.below(2) {
+            random_edit(document, &rng, letter);
+        }
+        // A sync fails when the faults are more than it can handle, and leaves what it did not
+        // commit to the next one.
+        let _ = document.sync(transport).await;
+    }
+}
+
+/// Asserts that every document has caught up with the server, with nothing left to commit.
+fn assert_converged(server: &Server, documents: &[Document]) {
+    for document in documents {
+        assert_eq!(document.value(), server.value());
+        assert_eq!(document.committed_value(), server.value());
+        assert_eq!(
+            document.last_revision_number(),
+            server.last_revision_number()
+        );
+        assert!(!document.has_pending_changes());
+    }
+}
+
+#[tokio::test]
+async fn test_converges_despite_faults() {
+    tokio::time::pause();
+    let faults = Faults {
+        drop_response: 10,
+        duplicate_request: 10,
+        delay: 30,
+        stale_response: 5,
+    };
+    for seed in 0..50 {
+        let server = Rc::new(RefCell::new(Server::default()));
+        let transports: Vec<FaultyTransport> = (0..3)
+            .map(|i| FaultyTransport::new(server.clone(), faults, seed * 3 + i))
+            .collect();
+        let mut documents = [
+            Document::new(DOC_ID),
+            Document::new(DOC_ID),
+            Document::new(DOC_ID),
+        ];
+        let [a, b, c] = &mut documents;
+        tokio::join!(
+            run_client(a, &transports[0], Rng::new(seed + 100), 'a'),
+            run_client(b, &transports[1], Rng::new(seed + 200), 'b'),
+            run_client(c, &transports[2], Rng::new(seed + 300), 'c'),
+        );
+
+        // Once the faults stop, everyone commits what they have left, then catches up with
+        // everyone else.
+        for transport in transports.iter() {
+            transport.faults.set(Faults::default());
+        }
+        for _ in 0..2 {
+            for (document, transport) in documents.iter_mut().zip(transports.iter()) {
+                document.sync(transport).await.unwrap();
+            }
+        }
+        assert_converged(&server.borrow(), &documents);
+    }
+}
+
+#[tokio::test]
+async fn test_lost_ack_commits_twice() {
+    tokio::time::pause();
+    let server = Rc::new(RefCell::new(Server::default()));
+    let transport = FaultyTransport::new(server.clone(), Faults::default(), 0);
+    let mut document = Document::new(DOC_ID);
+    document.splice(0..0, "Hello").unwrap();
+    document.sync(&transport).await.unwrap();
+
+    // The sync loads revisions, then submits. The server commits the change set, but the ack is
+    // lost, so the client submits it again. The server answers with the client's own revision,
+    // which the client takes for someone else's, and rebases its change set onto it.
+    document.splice(5..5, "!").unwrap();
+    transport.script(&[
+        Faults::default(),
+        Faults {
+            drop_response: Faults::ALWAYS,
+            ..Default::default()
+        },
+    ]);
+    document.sync(&transport).await.unwrap();
+
+    // The document converges, with the edit in it twice. Telling a retry from a new change set
+    // would take an idempotency key on each submit.
+    assert_converged(&server.borrow(), std::slice::from_ref(&document));
+    assert_eq!(server.borrow().value(), "Hello!!");
+    assert_eq!(server.borrow().last_revision_number(), 3);
+}
+
+#[tokio::test]
+async fn test_duplicate_submit_commits_once() {
+    tokio::time::pause();
+    let server = Rc::new(RefCell::new(Server::default()));
+    let transport = FaultyTransport::new(server.clone(), Faults::default(), 0);
+    let mut document = Document::new(DOC_ID);
+    document.splice(0..0, "Hello").unwrap();
+    transport.script(&[
+        Faults::default(),
+        Faults {
+            duplicate_request: Faults::ALWAYS,
+            ..Default::default()
+        },
+    ]);
+    document.sync(&transport).await.unwrap();
+
+    // The duplicate is submitted on the revision that the first one committed, so the server
+    // turns it away.
+    assert_converged(&server.borrow(), std::slice::from_ref(&document));
+    assert_eq!(server.borrow().value(), "Hello");
+    assert_eq!(server.borrow().last_revision_number(), 1);
+}
+
+#[tokio::test]
+async fn test_stale_responses_are_rejected() {
+    tokio::time::pause();
+    let server = Rc::new(RefCell::new(Server::default()));
+    let other_transport = FaultyTransport::new(server.clone(), Faults::default(), 0);
+    let mut other = Document::new(DOC_ID);
+    let transport = FaultyTransport::new(server.clone(), Faults::default(), 1);
+    let mut document = Document::new(DOC_ID);
+    let stale = Faults {
+        stale_response: Faults::ALWAYS,
+        ..Default::default()
+    };
+
+    other.splice(0..0, "Hello").unwrap();
+    other.sync(&other_transport).await.unwrap();
+    document.sync(&transport).await.unwrap();
+    other.splice(5..5, " world").unwrap();
+    other.sync(&other_transport).await.unwrap();
+
+    // The read of revision 2 gets revision 1 again, which the document turns away.
+    transport.script(&[stale]);
+    let result = document.sync(&transport).await;
+    assert!(matches!(result, Err(ClientError::CommittedLogError(_))));
+    assert_eq!(document.value(), "Hello");
+    assert_eq!(document.last_revision_number(), 1);
+    document.sync(&transport).await.unwrap();
+    assert_eq!(document.value(), "Hello world");
+
+    document.splice(5..5, ",").unwrap();
+    document.sync(&transport).await.unwrap();
+    assert_eq!(document.last_revision_number(), 3);
+
+    // The sync loads revisions, then submits. The ack of revision 3 arrives for the submit of
+    // revision 4, and the document is left as it was, with the change set still pending.
+    document.splice(12..12, "!").unwrap();
+    transport.script(&[Faults::default(), stale]);
+    let result = document.sync(&transport).await;
+    assert!(matches!(result, Err(ClientError::CommittedLogError(_))));
+    assert_eq!(document.value(), "Hello, world!");
+    assert_eq!(document.committed_value(), "Hello, world");
+    assert_eq!(document.last_revision_number(), 3);
+    assert!(document.has_pending_changes());
+
+    // The next sync catches up. The server committed the change set that the stale ack stood in
+    // for, so, as with a lost ack, the document ends up with it twice.
+    document.sync(&transport).await.unwrap();
+    other.sync(&other_transport).await.unwrap();
+    assert_converged(&server.borrow(), &[document, other]);
+    assert_eq!(server.borrow().value(), "Hello, world!!");
+}
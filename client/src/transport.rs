@@ -0,0 +1,41 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use ot::writing_proto::{
+    GetDocumentRevisionsRequest, GetDocumentRevisionsResponse, SubmitDocumentChangeSetRequest,
+    SubmitDocumentChangeSetResponse,
+};
+
+use crate::api::{Client, ClientError};
+
+pub type ResponseFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, ClientError>> + 'a>>;
+
+/// The requests that a `Document` needs to stay in sync. `Client` sends them to the backend. Tests
+/// send them to an in-memory server instead, and can lose, repeat and hold up requests on the way.
+pub trait Transport {
+    fn get_document_revisions<'a>(
+        &'a self,
+        request: &'a GetDocumentRevisionsRequest,
+    ) -> ResponseFuture<'a, GetDocumentRevisionsResponse>;
+
+    fn submit_document_change_set<'a>(
+        &'a self,
+        request: &'a SubmitDocumentChangeSetRequest,
+    ) -> ResponseFuture<'a, SubmitDocumentChangeSetResponse>;
+}
+
+impl Transport for Client {
+    fn get_document_revisions<'a>(
+        &'a self,
+        request: &'a GetDocumentRevisionsRequest,
+    ) -> ResponseFuture<'a, GetDocumentRevisionsResponse> {
+        Box::pin(Client::get_document_revisions(self, request))
+    }
+
+    fn submit_document_change_set<'a>(
+        &'a self,
+        request: &'a SubmitDocumentChangeSetRequest,
+    ) -> ResponseFuture<'a, SubmitDocumentChangeSetResponse> {
+        Box::pin(Client::submit_document_change_set(self, request))
+    }
+}
@@ -10,7 +10,7 @@
 use lazy_static::lazy_static;
 use rusoto_dynamodb::{
     AttributeDefinition, CreateTableInput, GlobalSecondaryIndex, KeySchemaElement, Projection,
-    ProvisionedThroughput,
+    ProvisionedThroughput, StreamSpecification,
 };
 
 lazy_static! {
@@ -25,6 +25,10 @@ lazy_static! {
              *   name: string
              *   hashed_password: string
              *   photo_url: string
+             *   totp_secret: string, base32, set once two-factor auth is on
+             *   pending_totp_secret: string, base32, until the user confirms it
+             *   totp_last_step: int, time step of the last TOTP code used
+             *   recovery_code_hashes: string set, hex-encoded SHA-256 hashes
              *   created_at: string, iso 8601 date time
              *   updated_at: string, iso 8601 date time
              *
@@ -63,6 +67,8 @@ lazy_static! {
              *   id: string, o_<id>
              *   name: string
              *   logo_url: string
+             *   two_factor_required_at: string, iso 8601 date time, if the org requires
+             *     two-factor auth
              *   created_at: string, iso 8601 date time
              *   updated_at: string, iso 8601 date time
              *
@@ -130,36 +136,86 @@ lazy_static! {
              *   id: string, d_<id>
              *   org_id: string, o_<id>
              *   title: string
+             *   title_lc: string, the title in lowercase, truncated. For title search.
              *   created_by_user_id: string, u_<id>
+             *   owner_user_id: string, u_<id>, optional. Set when the document is created, and
+             *     changed by transfer_ownership. Documents from before ownership could be
+             *     transferred lack it, and are owned by created_by_user_id.
              *   org_level_sharing_permission: int, enum
              *   created_at: string, iso 8601 date time
              *   updated_at: string, iso 8601 date time
+             *   encryption_key_fingerprint: string, optional. Only set for end-to-end encrypted
+             *     documents.
+             *   forked_from_doc_id: string, d_<id>, optional. Only set for forks.
+             *   forked_from_revision_number: integer, optional. The revision of the original
+             *     document that the fork starts from.
+             *   merged_into_revision_number: integer, optional. Set once the fork is merged back,
+             *     to the revision of the original that holds its changes, or 0 if it had none.
+             *   archived_at: string, iso 8601 date time, optional. Only set for archived
+             *     documents.
              *
              * primary key:
              *
              *   [id]
              *
+             * global secondary indexes:
+             *
+             *   [created_by_user_id, updated_at]
+             *   [org_id, title_lc]
+             *   [org_id, archived_at], sparse: only archived documents are in it.
+             *
              */
             table_name: "documents".to_string(),
             attribute_definitions: vec![
                 attr_def("id", "S"),
                 attr_def("created_by_user_id", "S"),
                 attr_def("updated_at", "S"),
+                attr_def("org_id", "S"),
+                attr_def("title_lc", "S"),
+                attr_def("archived_at", "S"),
             ],
             key_schema: vec![key_schema_elem("id", "HASH"),],
-            global_secondary_indexes: Some(vec![GlobalSecondaryIndex {
-                index_name: "created_by_user_id-updated_at-index".to_string(),
-                key_schema: vec![
-                    key_schema_elem("created_by_user_id", "HASH"),
-                    key_schema_elem("updated_at", "RANGE"),
-                ],
-                projection: Projection {
-                    projection_type: Some("ALL".to_string()),
+            global_secondary_indexes: Some(vec![
+                GlobalSecondaryIndex {
+                    index_name: "created_by_user_id-updated_at-index".to_string(),
+                    key_schema: vec![
+                        key_schema_elem("created_by_user_id", "HASH"),
+                        key_schema_elem("updated_at", "RANGE"),
+                    ],
+                    projection: Projection {
+                        projection_type: Some("ALL".to_string()),
+                        ..Default::default()
+                    },
+                    provisioned_throughput: default_provisioned_throughput(),
                     ..Default::default()
                 },
-                provisioned_throughput: default_provisioned_throughput(),
-                ..Default::default()
-            }]),
+                GlobalSecondaryIndex {
+                    index_name: "org_id-title_lc-index".to_string(),
+                    key_schema: vec![
+                        key_schema_elem("org_id", "HASH"),
+                        key_schema_elem("title_lc", "RANGE"),
+                    ],
+                    projection: Projection {
+                        projection_type: Some("ALL".to_string()),
+                        ..Default::default()
+                    },
+                    provisioned_throughput: default_provisioned_throughput(),
+                    ..Default::default()
+                },
+                GlobalSecondaryIndex {
+                    index_name: "org_id-archived_at-index".to_string(),
+                    key_schema: vec![
+                        key_schema_elem("org_id", "HASH"),
+                        key_schema_elem("archived_at", "RANGE"),
+                    ],
+                    projection: Projection {
+                        projection_type: Some("ALL".to_string()),
+                        ..Default::default()
+                    },
+                    provisioned_throughput: default_provisioned_throughput(),
+                    ..Default::default()
+                },
+            ]),
             provisioned_throughput: default_provisioned_throughput(),
             ..Default::default()
         },
@@ -190,30 +246,467 @@ lazy_static! {
             provisioned_throughput: default_provisioned_throughput(),
             ..Default::default()
         },
+        CreateTableInput {
+            /*
+             * share_tokens
+             *
+             *   token: string, st_<id>
+             *   doc_id: string, d_<id>
+             *   org_id: string, o_<id>
+             *   permission: int, enum
+             *   created_by_user_id: string, u_<id>
+             *   created_at: string, iso 8601 date time
+             *   expires_at: string, iso 8601 date time, optional. Absent if token never expires.
+             *
+             * primary key:
+             *
+             *   [token]
+             */
+            table_name: "share_tokens".to_string(),
+            attribute_definitions: vec![attr_def("token", "S")],
+            key_schema: vec![key_schema_elem("token", "HASH")],
+            provisioned_throughput: default_provisioned_throughput(),
+            ..Default::default()
+        },
+        CreateTableInput {
+            /*
+             * document_followers
+             *
+             *   doc_id: string, d_<id>
+             *   user_id: string, u_<id>
+             *   org_id: string, o_<id>
+             *   created_at: string, iso 8601 date time
+             *
+             * primary key:
+             *
+             *   [doc_id, user_id]
+             */
+            table_name: "document_followers".to_string(),
+            attribute_definitions: vec![
+                attr_def("doc_id", "S"),
+                attr_def("user_id", "S"),
+            ],
+            key_schema: vec![
+                key_schema_elem("doc_id", "HASH"),
+                key_schema_elem("user_id", "RANGE"),
+            ],
+            provisioned_throughput: default_provisioned_throughput(),
+            ..Default::default()
+        },
+        CreateTableInput {
+            /*
+             * feature_flags
+             *
+             * Each org's feature flag settings, and the global defaults under the org id "global".
+             * Flags without an item follow the global default, or are off.
+             *
+             *   org_id: string, o_<id> or "global"
+             *   flag: string, the flag's name, like "websockets"
+             *   enabled: integer, 1 if the flag is on, 0 if it is off
+             *   updated_by: string, the id of the user who last set it, or "operator"
+             *   updated_at: string, iso 8601 date time
+             *
+             * primary key:
+             *
+             *   [org_id, flag]
+             */
+            table_name: "feature_flags".to_string(),
+            attribute_definitions: vec![attr_def("org_id", "S"), attr_def("flag", "S")],
+            key_schema: vec![
+                key_schema_elem("org_id", "HASH"),
+                key_schema_elem("flag", "RANGE"),
+            ],
+            provisioned_throughput: default_provisioned_throughput(),
+            ..Default::default()
+        },
+        CreateTableInput {
+            /*
+             * pending_notifications
+             *
+             * One item per follower per edited document, waiting to go out in the follower's next
+             * digest email. Further edits update the existing item rather than adding new ones.
+             *
+             *   user_id: string, u_<id>
+             *   doc_id: string, d_<id>
+             *   org_id: string, o_<id>
+             *   edit_count: integer
+             *   first_edited_at: string, iso 8601 date time
+             *   last_edited_at: string, iso 8601 date time
+             *   first_revision_number: integer, the first revision counted in edit_count. Not set
+             *     for notifications recorded before it was added.
+             *   last_revision_number: integer, the last revision counted in edit_count
+             *
+             * primary key:
+             *
+             *   [user_id, doc_id]
+             */
+            table_name: "pending_notifications".to_string(),
+            attribute_definitions: vec![
+                attr_def("user_id", "S"),
+                attr_def("doc_id", "S"),
+            ],
+            key_schema: vec![
+                key_schema_elem("user_id", "HASH"),
+                key_schema_elem("doc_id", "RANGE"),
+            ],
+            provisioned_throughput: default_provisioned_throughput(),
+            ..Default::default()
+        },
         CreateTableInput {
             /*
              * document_revisions
              *
              *   doc_id: string, d_<id>
-             *   author_user_id: string, u_<id>
+             *   org_id: string, o_<id>. Not set for revisions committed before the org
+             *     changefeed was added.
+             *   author_user_id: string, u_<id> or g_<id> for guests
+             *   author_display_name: string, optional. Only set for guests.
              *   revision_number: integer
-             *   change_set: binary, protobuf message
+             *   change_set: binary, protobuf message. Not set for end-to-end encrypted documents.
+             *   encrypted_change_set: binary, optional. Only set for end-to-end encrypted
+             *     documents.
              *   committed_at: string, iso 8601 date time
+             *   signing_key_id: string, optional. The key the author signed the revision with.
+             *   signature: binary, optional. The author's Ed25519 signature over the revision.
              *
              * primary key:
              *
              *   [doc_id, revision]
+             *
+             * global secondary indexes:
+             *
+             *   [org_id, committed_at], projecting only the revision metadata
+             *
+             * stream:
+             *
+             *   new images, for the revision stream processor
              */
 
             table_name: "document_revisions".to_string(),
             attribute_definitions: vec![
                 attr_def("doc_id", "S"),
                 attr_def("revision_number", "N"),
+                attr_def("org_id", "S"),
+                attr_def("committed_at", "S"),
             ],
             key_schema: vec![
                 key_schema_elem("doc_id", "HASH"),
                 key_schema_elem("revision_number", "RANGE"),
             ],
+            global_secondary_indexes: Some(vec![GlobalSecondaryIndex {
+                index_name: "org_id-committed_at-index".to_string(),
+                key_schema: vec![
+                    key_schema_elem("org_id", "HASH"),
+                    key_schema_elem("committed_at", "RANGE"),
+                ],
+                projection: Projection {
+                    projection_type: Some("INCLUDE".to_string()),
+                    non_key_attributes: Some(vec![
+                        "author_user_id".to_string(),
+                        "author_display_name".to_string(),
+                    ]),
+                },
+                provisioned_throughput: default_provisioned_throughput(),
+                ..Default::default()
+            }]),
+            provisioned_throughput: default_provisioned_throughput(),
+            stream_specification: Some(StreamSpecification {
+                stream_enabled: true,
+                stream_view_type: Some("NEW_IMAGE".to_string()),
+            }),
+            ..Default::default()
+        },
+        CreateTableInput {
+            /*
+             * document_stats
+             *
+             * Word counts and other stats for the document list, as counted by the last editor
+             * that reported them.
+             *
+             *   doc_id: string, d_<id>
+             *   revision_number: integer, the revision the stats were counted at
+             *   word_count: integer
+             *   character_count: integer
+             *   paragraph_count: integer
+             *   updated_at: string, iso 8601 date time
+             *
+             * primary key:
+             *
+             *   [doc_id]
+             */
+            table_name: "document_stats".to_string(),
+            attribute_definitions: vec![attr_def("doc_id", "S")],
+            key_schema: vec![key_schema_elem("doc_id", "HASH")],
+            provisioned_throughput: default_provisioned_throughput(),
+            ..Default::default()
+        },
+        CreateTableInput {
+            /*
+             * document_views
+             *
+             * When each user last opened each document, for the "Recent" list on the home screen.
+             *
+             *   user_id: string, u_<id>
+             *   doc_id: string, d_<id>
+             *   org_id: string, o_<id>
+             *   last_viewed_at: string, iso 8601 date time
+             *
+             * primary key:
+             *
+             *   [user_id, doc_id]
+             *
+             * global secondary indexes:
+             *
+             *   [user_id, last_viewed_at]
+             */
+            table_name: "document_views".to_string(),
+            attribute_definitions: vec![
+                attr_def("user_id", "S"),
+                attr_def("doc_id", "S"),
+                attr_def("last_viewed_at", "S"),
+            ],
+            key_schema: vec![
+                key_schema_elem("user_id", "HASH"),
+                key_schema_elem("doc_id", "RANGE"),
+            ],
+            global_secondary_indexes: Some(vec![GlobalSecondaryIndex {
+                index_name: "user_id-last_viewed_at-index".to_string(),
+                key_schema: vec![
+                    key_schema_elem("user_id", "HASH"),
+                    key_schema_elem("last_viewed_at", "RANGE"),
+                ],
+                projection: Projection {
+                    projection_type: Some("ALL".to_string()),
+                    ..Default::default()
+                },
+                provisioned_throughput: default_provisioned_throughput(),
+                ..Default::default()
+            }]),
+            provisioned_throughput: default_provisioned_throughput(),
+            ..Default::default()
+        },
+        CreateTableInput {
+            /*
+             * read_markers
+             *
+             * The latest revision of each document that each reader has seen, for read receipts.
+             *
+             *   doc_id: string, d_<id>
+             *   user_id: string, u_<id>, or g_<id> for guests
+             *   revision_number: number
+             *   read_at: string, iso 8601 date time
+             *   display_name: string, only set for guests
+             *
+             * primary key:
+             *
+             *   [doc_id, user_id]
+             */
+            table_name: "read_markers".to_string(),
+            attribute_definitions: vec![attr_def("doc_id", "S"), attr_def("user_id", "S")],
+            key_schema: vec![
+                key_schema_elem("doc_id", "HASH"),
+                key_schema_elem("user_id", "RANGE"),
+            ],
+            provisioned_throughput: default_provisioned_throughput(),
+            ..Default::default()
+        },
+        CreateTableInput {
+            /*
+             * document_attachments
+             *
+             * Images embedded in documents. The images themselves are in the attachment store.
+             *
+             *   doc_id: string, d_<id>
+             *   attachment_id: string, a_<id>
+             *   content_type: string
+             *   content_length: number
+             *   created_by_user_id: string, u_<id>
+             *   created_at: string, iso 8601 date time
+             *
+             * primary key:
+             *
+             *   [doc_id, attachment_id]
+             */
+            table_name: "document_attachments".to_string(),
+            attribute_definitions: vec![attr_def("doc_id", "S"), attr_def("attachment_id", "S")],
+            key_schema: vec![
+                key_schema_elem("doc_id", "HASH"),
+                key_schema_elem("attachment_id", "RANGE"),
+            ],
+            provisioned_throughput: default_provisioned_throughput(),
+            ..Default::default()
+        },
+        CreateTableInput {
+            /*
+             * starred_documents
+             *
+             *   user_id: string, u_<id>
+             *   doc_id: string, d_<id>
+             *   org_id: string, o_<id>
+             *   starred_at: string, iso 8601 date time
+             *
+             * primary key:
+             *
+             *   [user_id, doc_id]
+             *
+             * global secondary indexes:
+             *
+             *   [user_id, starred_at]
+             */
+            table_name: "starred_documents".to_string(),
+            attribute_definitions: vec![
+                attr_def("user_id", "S"),
+                attr_def("doc_id", "S"),
+                attr_def("starred_at", "S"),
+            ],
+            key_schema: vec![
+                key_schema_elem("user_id", "HASH"),
+                key_schema_elem("doc_id", "RANGE"),
+            ],
+            global_secondary_indexes: Some(vec![GlobalSecondaryIndex {
+                index_name: "user_id-starred_at-index".to_string(),
+                key_schema: vec![
+                    key_schema_elem("user_id", "HASH"),
+                    key_schema_elem("starred_at", "RANGE"),
+                ],
+                projection: Projection {
+                    projection_type: Some("ALL".to_string()),
+                    ..Default::default()
+                },
+                provisioned_throughput: default_provisioned_throughput(),
+                ..Default::default()
+            }]),
+            provisioned_throughput: default_provisioned_throughput(),
+            ..Default::default()
+        },
+        CreateTableInput {
+            /*
+             * signing_keys
+             *
+             * Public keys that users sign their revisions with.
+             *
+             *   key_id: string, sk_<id>
+             *   user_id: string, u_<id>
+             *   org_id: string, o_<id>
+             *   public_key: binary, Ed25519 public key
+             *   created_at: string, iso 8601 date time
+             *
+             * primary key:
+             *
+             *   [key_id]
+             */
+            table_name: "signing_keys".to_string(),
+            attribute_definitions: vec![attr_def("key_id", "S")],
+            key_schema: vec![key_schema_elem("key_id", "HASH")],
+            provisioned_throughput: default_provisioned_throughput(),
+            ..Default::default()
+        },
+        CreateTableInput {
+            /*
+             * external_identities
+             *
+             * Accounts with identity providers, like Google, that users log in with.
+             *
+             *   provider: string, e.g. "google"
+             *   subject: string, the provider's id for the account
+             *   email: string, the email of the user that the account is linked to
+             *   user_id: string, u_<id>
+             *   created_at: string, iso 8601 date time
+             *
+             * primary key:
+             *
+             *   [provider, subject]
+             */
+            table_name: "external_identities".to_string(),
+            attribute_definitions: vec![
+                attr_def("provider", "S"),
+                attr_def("subject", "S"),
+            ],
+            key_schema: vec![
+                key_schema_elem("provider", "HASH"),
+                key_schema_elem("subject", "RANGE"),
+            ],
+            provisioned_throughput: default_provisioned_throughput(),
+            ..Default::default()
+        },
+        CreateTableInput {
+            /*
+             * jobs
+             *
+             * Work that runs in the background, like org exports and account deletions.
+             *
+             *   job_id: string, j_<id>
+             *   job_type: string, e.g. "org_export"
+             *   org_id: string, o_<id>
+             *   created_by_user_id: string, u_<id>
+             *   job_status: int, enum
+             *   next_run_at: string, iso 8601 date time. When a pending job is due to run, or
+             *     when a running job's lease expires.
+             *   attempts: integer, how many times a worker has claimed the job
+             *   lease_id: string, ll_<id>, optional. The lease of the worker that last claimed
+             *     the job.
+             *   last_error: string, optional. Why the job's last attempt failed.
+             *   progress_done: integer
+             *   progress_total: integer, zero until known
+             *   output_key: string, optional. Where the job wrote its output in the export store.
+             *   tombstone_user_id: string, u_<id>, optional. For account deletions, the id that
+             *     replaces the deleted user's id.
+             *   created_at: string, iso 8601 date time
+             *   updated_at: string, iso 8601 date time
+             *
+             * primary key:
+             *
+             *   [job_id]
+             *
+             * global secondary indexes:
+             *
+             *   [job_status, next_run_at]
+             */
+            table_name: "jobs".to_string(),
+            attribute_definitions: vec![
+                attr_def("job_id", "S"),
+                attr_def("job_status", "N"),
+                attr_def("next_run_at", "S"),
+            ],
+            key_schema: vec![key_schema_elem("job_id", "HASH")],
+            global_secondary_indexes: Some(vec![GlobalSecondaryIndex {
+                index_name: "job_status-next_run_at-index".to_string(),
+                key_schema: vec![
+                    key_schema_elem("job_status", "HASH"),
+                    key_schema_elem("next_run_at", "RANGE"),
+                ],
+                projection: Projection {
+                    projection_type: Some("ALL".to_string()),
+                    ..Default::default()
+                },
+                provisioned_throughput: default_provisioned_throughput(),
+            }]),
+            provisioned_throughput: default_provisioned_throughput(),
+            ..Default::default()
+        },
+        CreateTableInput {
+            /*
+             * stream_checkpoints
+             *
+             * How far the revision stream processor has read each shard of a table's stream.
+             *
+             *   stream_arn: string
+             *   shard_id: string
+             *   sequence_number: string, optional. The last record that was processed.
+             *   finished_at: string, optional, iso 8601 date time. Set once the shard is closed
+             *     and every record in it was processed.
+             *   updated_at: string, iso 8601 date time
+             *
+             * primary key:
+             *
+             *   [stream_arn, shard_id]
+             */
+            table_name: "stream_checkpoints".to_string(),
+            attribute_definitions: vec![attr_def("stream_arn", "S"), attr_def("shard_id", "S")],
+            key_schema: vec![
+                key_schema_elem("stream_arn", "HASH"),
+                key_schema_elem("shard_id", "RANGE"),
+            ],
             provisioned_throughput: default_provisioned_throughput(),
             ..Default::default()
         },
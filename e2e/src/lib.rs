@@ -0,0 +1,284 @@
+//! End-to-end test harness.
+//!
+//! Starts DynamoDB Local in a Docker container, creates the tables from `TABLE_DEFINITIONS`, and
+//! boots the backend server on a random port pointed at the container. Tests then talk to the
+//! backend over HTTP exactly like a browser would: form posts for sign up, and Protobuf requests
+//! for the document API.
+//!
+//! Example:
+//! ```ignore
+//! let docker = testcontainers::clients::Cli::default();
+//! let harness = Harness::start(&docker).await?;
+//! let client = harness.sign_up("jane@smith.com", "AJLK:jasd;lj123123").await?;
+//! ```
+
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail};
+use prost::Message;
+use rusoto_dynamodb::{DynamoDb, DynamoDbClient};
+use testcontainers::images::generic::{GenericImage, WaitFor};
+use testcontainers::{clients, Container, Docker};
+
+use ot::writing_proto::{
+    submit_document_change_set_response::ResponseCode, ChangeSet, CreateDocumentRequest,
+    CreateDocumentResponse, DocumentSharingPermission, GetDocumentRevisionsRequest,
+    GetDocumentRevisionsResponse, SubmitDocumentChangeSetRequest, SubmitDocumentChangeSetResponse,
+};
+
+const DYNAMODB_LOCAL_IMAGE: &str = "amazon/dynamodb-local";
+const DYNAMODB_LOCAL_PORT: u16 = 8000;
+const DYNAMODB_ENV: &str = "e2e";
+const COOKIE_SECRET: &str = "e2e-cookie-secret-e2e-cookie-secret-e2e-cookie-secret";
+
+// The first run has to compile the backend before it can start listening.
+const BACKEND_STARTUP_TIMEOUT: Duration = Duration::from_secs(600);
+
+pub struct Harness<'d> {
+    _dynamodb: Container<'d, clients::Cli, GenericImage>,
+    backend: Child,
+    pub base_url: String,
+}
+
+impl<'d> Harness<'d> {
+    /// Starts DynamoDB Local and the backend server. Both are torn down when the harness is
+    /// dropped.
+    pub async fn start(docker: &'d clients::Cli) -> anyhow::Result<Harness<'d>> {
+        let image = GenericImage::new(DYNAMODB_LOCAL_IMAGE)
+            .with_wait_for(WaitFor::message_on_stdout("Initializing DynamoDB Local"));
+        let dynamodb = docker.run(image);
+        let dynamodb_port = dynamodb
+            .get_host_port(DYNAMODB_LOCAL_PORT)
+            .ok_or_else(|| anyhow!("DynamoDB Local container did not expose a port"))?;
+        let dynamodb_endpoint = format!("http://127.0.0.1:{}", dynamodb_port);
+        create_tables(&dynamodb_endpoint).await?;
+
+        let http_port = pick_unused_port()?;
+        let backend = spawn_backend(&dynamodb_endpoint, http_port)?;
+        let mut harness = Harness {
+            _dynamodb: dynamodb,
+            backend,
+            base_url: format!("http://localhost:{}", http_port),
+        };
+        harness.wait_for_backend().await?;
+        Ok(harness)
+    }
+
+    /// Signs up a brand new user. The returned client carries the user's session cookie.
+    pub async fn sign_up(&self, email: &str, password: &str) -> anyhow::Result<ApiClient> {
+        let http_client = reqwest::Client::builder()
+            .cookie_store(true)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        let response = http_client
+            .post(&format!("{}/sign_up", &self.base_url))
+            .form(&[
+                ("email", email),
+                ("password", password),
+                ("password_confirmation", password),
+            ])
+            .send()
+            .await?;
+        if response.status() != reqwest::StatusCode::SEE_OTHER {
+            bail!("Sign up failed with status {}", response.status());
+        }
+        Ok(ApiClient {
+            http_client,
+            base_url: self.base_url.clone(),
+        })
+    }
+
+    async fn wait_for_backend(&mut self) -> anyhow::Result<()> {
+        let started_at = Instant::now();
+        let url = format!("{}/", &self.base_url);
+        while started_at.elapsed() < BACKEND_STARTUP_TIMEOUT {
+            if let Some(status) = self.backend.try_wait()? {
+                bail!("Backend exited before it started listening: {}", status);
+            }
+            if let Ok(response) = reqwest::get(&url).await {
+                if response.status().is_success() {
+                    return Ok(());
+                }
+            }
+            tokio::time::delay_for(Duration::from_millis(250)).await;
+        }
+        bail!("Timed out waiting for the backend to start")
+    }
+}
+
+impl<'d> Drop for Harness<'d> {
+    fn drop(&mut self) {
+        let _ = self.backend.kill();
+        let _ = self.backend.wait();
+    }
+}
+
+/// A logged-in HTTP client for the document API.
+#[derive(Clone)]
+pub struct ApiClient {
+    http_client: reqwest::Client,
+    base_url: String,
+}
+
+impl ApiClient {
+    pub async fn create_document(&self, title: &str) -> anyhow::Result<CreateDocumentResponse> {
+        let request = CreateDocumentRequest {
+            title: title.to_string(),
+            org_level_sharing_permission: DocumentSharingPermission::None.into(),
+            ..Default::default()
+        };
+        self.execute("/api/documents.create_document", &request)
+            .await
+    }
+
+    pub async fn get_document_revisions(
+        &self,
+        request: &GetDocumentRevisionsRequest,
+    ) -> anyhow::Result<GetDocumentRevisionsResponse> {
+        self.execute("/api/documents.get_document_revisions", request)
+            .await
+    }
+
+    pub async fn submit_document_change_set(
+        &self,
+        request: &SubmitDocumentChangeSetRequest,
+    ) -> anyhow::Result<SubmitDocumentChangeSetResponse> {
+        self.execute("/api/documents.submit_document_change_set", request)
+            .await
+    }
+
+    /// Submits the change set, which is based on `on_revision_number`. If other revisions were
+    /// committed first, transforms the change set against them and tries again.
+    ///
+    /// Returns the revision number at which the change set was committed.
+    pub async fn submit_until_acked(
+        &self,
+        doc_id: &str,
+        on_revision_number: i64,
+        change_set: &ChangeSet,
+    ) -> anyhow::Result<i64> {
+        let mut on_revision_number = on_revision_number;
+        let mut change_set = change_set.clone();
+        loop {
+            let response = self
+                .submit_document_change_set(&SubmitDocumentChangeSetRequest {
+                    doc_id: doc_id.to_string(),
+                    on_revision_number,
+                    change_set: Some(change_set.clone()),
+                    ..Default::default()
+                })
+                .await?;
+            match response.response_code() {
+                ResponseCode::Ack => return Ok(response.last_revision_number),
+                ResponseCode::DiscoveredNewRevisions => {
+                    for revision in response.revisions.iter() {
+                        let remote = revision
+                            .change_set
+                            .as_ref()
+                            .ok_or_else(|| anyhow!("Revision is missing its change set"))?;
+                        let (transformed, _) = ot::transform(&change_set, remote)?;
+                        change_set = transformed;
+                        on_revision_number = revision.revision_number;
+                    }
+                }
+                code => bail!("Unexpected response code: {:?}", code),
+            }
+        }
+    }
+
+    /// Reads the entire revision log and replays it from the beginning.
+    pub async fn read_document(&self, doc_id: &str) -> anyhow::Result<(i64, String)> {
+        let mut document = String::new();
+        let mut last_revision_number = 0;
+        loop {
+            let response = self
+                .get_document_revisions(&GetDocumentRevisionsRequest {
+                    doc_id: doc_id.to_string(),
+                    after_revision_number: last_revision_number,
+                    ..Default::default()
+                })
+                .await?;
+            for revision in response.revisions.iter() {
+                let change_set = revision
+                    .change_set
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("Revision is missing its change set"))?;
+                document = ot::apply(&document, change_set)?;
+                last_revision_number = revision.revision_number;
+            }
+            if response.end_of_revisions || response.revisions.is_empty() {
+                break;
+            }
+        }
+        Ok((last_revision_number, document))
+    }
+
+    async fn execute<Req, Res>(&self, path: &str, request: &Req) -> anyhow::Result<Res>
+    where
+        Req: prost::Message,
+        Res: prost::Message + Default,
+    {
+        let mut body = Vec::new();
+        request.encode(&mut body)?;
+        let response = self
+            .http_client
+            .post(&format!("{}{}", &self.base_url, path))
+            .header("content-type", "application/protobuf")
+            .body(body)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            bail!("{} failed with status {}", path, response.status());
+        }
+        let bytes = response.bytes().await?;
+        Ok(Res::decode(&bytes[..])?)
+    }
+}
+
+async fn create_tables(dynamodb_endpoint: &str) -> anyhow::Result<()> {
+    let request_dispatcher = rusoto_core::request::HttpClient::new()?;
+    let credentials_provider =
+        rusoto_credential::StaticProvider::new_minimal("e2e".to_string(), "e2e".to_string());
+    let region = rusoto_core::Region::Custom {
+        name: "local".to_string(),
+        endpoint: dynamodb_endpoint.to_string(),
+    };
+    let dynamodb_client = DynamoDbClient::new_with(request_dispatcher, credentials_provider, region);
+    for table_def in dynamodb_schema::TABLE_DEFINITIONS.iter() {
+        let mut table_def = table_def.clone();
+        table_def.table_name = format!("{}-{}", DYNAMODB_ENV, &table_def.table_name);
+        dynamodb_client.create_table(table_def).await?;
+    }
+    Ok(())
+}
+
+fn spawn_backend(dynamodb_endpoint: &str, http_port: u16) -> anyhow::Result<Child> {
+    let manifest_path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "..", "backend", "Cargo.toml"]
+        .iter()
+        .collect();
+    let child = Command::new(env!("CARGO"))
+        .arg("run")
+        .arg("--quiet")
+        .arg("--manifest-path")
+        .arg(manifest_path)
+        .arg("--")
+        .arg("local")
+        .arg(DYNAMODB_ENV)
+        .arg(http_port.to_string())
+        .arg(dynamodb_endpoint)
+        .env("COOKIE_SECRET", COOKIE_SECRET)
+        .env("COOKIE_SECURE", "false")
+        .env("AWS_ACCESS_KEY_ID", "e2e")
+        .env("AWS_SECRET_ACCESS_KEY", "e2e")
+        .stdout(Stdio::null())
+        .spawn()?;
+    Ok(child)
+}
+
+fn pick_unused_port() -> anyhow::Result<u16> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    Ok(listener.local_addr()?.port())
+}
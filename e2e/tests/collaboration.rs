@@ -0,0 +1,58 @@
+use e2e::Harness;
+
+use ot::writing_proto::ChangeSet;
+
+const PASSWORD: &str = "AJLK:jasd;lj123123";
+
+#[tokio::test]
+async fn test_concurrent_submits_converge() -> anyhow::Result<()> {
+    let docker = testcontainers::clients::Cli::default();
+    let harness = Harness::start(&docker).await?;
+
+    // Sign up, create a document, and commit its first revision.
+    let client = harness.sign_up("jane@smith.com", PASSWORD).await?;
+    let doc_id = client.create_document("E2E document").await?.doc_id;
+    let mut change_set = ChangeSet::new();
+    change_set.insert("Hello, world!");
+    let base_revision_number = client.submit_until_acked(&doc_id, 0, &change_set).await?;
+    assert_eq!(base_revision_number, 1);
+
+    // Several editors race to append their edits on top of the same base revision. All but one
+    // of them will discover new revisions and need to transform and retry.
+    let words = vec!["alpha", "bravo", "charlie", "delta", "echo"];
+    let base_len = "Hello, world!".encode_utf16().count() as i64;
+    let submits: Vec<_> = words
+        .iter()
+        .map(|word| {
+            let client = client.clone();
+            let doc_id = doc_id.clone();
+            let mut change_set = ChangeSet::new();
+            change_set.retain(base_len);
+            change_set.insert(&format!(" {}", word));
+            tokio::spawn(async move {
+                client
+                    .submit_until_acked(&doc_id, base_revision_number, &change_set)
+                    .await
+            })
+        })
+        .collect();
+    let mut committed_revision_numbers = Vec::new();
+    for submit in submits.into_iter() {
+        committed_revision_numbers.push(submit.await??);
+    }
+    committed_revision_numbers.sort_unstable();
+    let expected: Vec<i64> = (2..=(1 + words.len() as i64)).collect();
+    assert_eq!(committed_revision_numbers, expected);
+
+    // Every reader replaying the log must see the same document, containing every edit once.
+    let (last_revision_number, document) = client.read_document(&doc_id).await?;
+    assert_eq!(last_revision_number, 1 + words.len() as i64);
+    assert!(document.starts_with("Hello, world!"));
+    for word in words.iter() {
+        assert_eq!(document.matches(word).count(), 1, "document: {}", &document);
+    }
+    let (_, document_again) = client.read_document(&doc_id).await?;
+    assert_eq!(document, document_again);
+
+    Ok(())
+}
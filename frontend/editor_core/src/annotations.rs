@@ -0,0 +1,119 @@
+use serde::Serialize;
+
+use ot::writing_proto::{ChangeSet, Selection};
+use ot::OtError;
+
+/// Ranges of the document that the host has marked with ids, like spelling errors or search
+/// highlights. Every change applied to the document is applied to the ranges too, so they keep
+/// covering the same text.
+///
+/// A range whose text is deleted entirely is removed. Empty ranges that the host added stay until
+/// the host removes them.
+#[derive(Clone, Debug, Default)]
+pub struct Annotations {
+    annotations: Vec<Annotation>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Annotation {
+    pub id: String,
+    pub start: i64,
+    pub end: i64,
+}
+
+impl Annotations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an annotation, or moves the annotation with the same id.
+    pub fn set(&mut self, id: &str, start: i64, end: i64) {
+        let annotation = Annotation {
+            id: id.to_string(),
+            start,
+            end,
+        };
+        match self.annotations.iter_mut().find(|a| a.id == id) {
+            Some(existing) => *existing = annotation,
+            None => self.annotations.push(annotation),
+        }
+    }
+
+    /// Removes the annotation with the given id. Returns false if there was none.
+    pub fn remove(&mut self, id: &str) -> bool {
+        let len = self.annotations.len();
+        self.annotations.retain(|a| a.id != id);
+        self.annotations.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.annotations.clear();
+    }
+
+    pub fn get_all(&self) -> &[Annotation] {
+        &self.annotations
+    }
+
+    /// Moves every annotation through the change set, which must apply to the document that the
+    /// annotations currently describe.
+    pub fn transform(&mut self, change_set: &ChangeSet) -> Result<(), OtError> {
+        if self.annotations.is_empty() {
+            return Ok(());
+        }
+        let selections: Vec<Selection> = self
+            .annotations
+            .iter()
+            .map(|a| Selection {
+                offset: a.start,
+                count: a.end - a.start,
+            })
+            .collect();
+        let new_selections = ot::transform_selections(&selections, change_set)?;
+        let old_annotations = std::mem::take(&mut self.annotations);
+        for (annotation, selection) in old_annotations.into_iter().zip(new_selections) {
+            if annotation.start < annotation.end && selection.count == 0 {
+                continue;
+            }
+            self.annotations.push(Annotation {
+                start: selection.offset,
+                end: selection.offset + selection.count,
+                ..annotation
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transform() {
+        let mut annotations = Annotations::new();
+        annotations.set("misspelled", 6, 11);
+        annotations.set("deleted", 0, 5);
+        annotations.set("cursor", 12, 12);
+
+        // "Hello wrold!" -> "Hi there, wrold!"
+        let mut change_set = ChangeSet::new();
+        change_set.delete(5);
+        change_set.insert("Hi there,");
+        change_set.retain(7);
+        annotations.transform(&change_set).unwrap();
+
+        let expected_annotations = vec![
+            Annotation {
+                id: "misspelled".to_string(),
+                start: 10,
+                end: 15,
+            },
+            Annotation {
+                id: "cursor".to_string(),
+                start: 16,
+                end: 16,
+            },
+        ];
+        assert_eq!(annotations.get_all(), &expected_annotations[..]);
+    }
+}
@@ -0,0 +1,369 @@
+use std::ops::Range;
+
+use thiserror::Error;
+
+use ot::writing_proto::{ChangeSet, DocumentRevision};
+use ot::OtError;
+
+use crate::get_change_set_description;
+
+#[derive(Debug, Error)]
+pub enum CommittedLogError {
+    #[error("Ot Error: {0}")]
+    OtError(OtError),
+    #[error("Invalid Response Error: {0}")]
+    InvalidResponseError(String),
+    #[error("Invalid State Error: {0}")]
+    InvalidStateError(String),
+    /// A revision arrived out of sequence: after a gap, or when the log already had it. Nothing
+    /// was appended, but the log can no longer be trusted to match the server's, so the document
+    /// must be loaded again from scratch rather than synced.
+    #[error(
+        "Revision Sequence Error: Received revision number {received}, but expected {expected}"
+    )]
+    RevisionSequenceError { expected: i64, received: i64 },
+}
+
+impl CommittedLogError {
+    /// True if the log may have diverged from the server's, and syncing cannot recover.
+    pub fn requires_resync(&self) -> bool {
+        matches!(self, CommittedLogError::RevisionSequenceError { .. })
+    }
+}
+
+/// Consecutive remote revisions always compose, so if they do not, the server sent us a bad
+/// response rather than us hitting a bug in the `ot` crate.
+fn remote_revisions_error(error: OtError) -> CommittedLogError {
+    match error {
+        OtError::IncompatibleChangeSets { .. } | OtError::EmptyOp { .. } => {
+            CommittedLogError::InvalidResponseError(format!(
+                "Received remote revisions that do not compose: {}",
+                error
+            ))
+        }
+        _ => CommittedLogError::OtError(error),
+    }
+}
+
+/// The revisions of the document that the server has committed, in order.
+///
+/// The log only holds revisions. Fetching them from the server, and decrypting them, is up to the
+/// host.
+#[derive(Default)]
+pub struct CommittedLog {
+    revisions: Vec<DocumentRevision>,
+}
+
+pub struct ComposedRemoteRevisions {
+    pub composed_change_sets: ChangeSet,
+    pub revision_range: (i64, i64),
+}
+
+impl CommittedLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.revisions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.revisions.is_empty()
+    }
+
+    pub fn compose_range(&self, range: Range<usize>) -> Result<Option<ChangeSet>, OtError> {
+        if range.start >= self.revisions.len() {
+            return Ok(None);
+        }
+        if range.end <= range.start {
+            return Ok(None);
+        }
+        let iter = self.revisions[range.start..range.end]
+            .iter()
+            .map(|rev| rev.change_set.as_ref().unwrap());
+        Ok(Some(ot::compose_iter(iter)?))
+    }
+
+    /// The document as of the given revision, replayed from the start of the log. Empty as of
+    /// revision 0.
+    pub fn value_at(&self, revision_number: i64) -> Result<Vec<u16>, OtError> {
+        if revision_number > self.last_revision_number() {
+            return Err(OtError::InvalidInput(format!(
+                "Revision {} has not been loaded",
+                revision_number
+            )));
+        }
+        match self.compose_range(0..revision_number.max(0) as usize)? {
+            Some(composed) => ot::apply_slice(&[], &composed),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Appends the revision that the server committed for our local change set.
+    pub fn push_local_revision(
+        &mut self,
+        revision: DocumentRevision,
+    ) -> Result<(), CommittedLogError> {
+        self.check_revision_number(revision.revision_number, 0)?;
+        self.revisions.push(revision);
+        Ok(())
+    }
+
+    /// Appends new remote revisions to the log. Their revision numbers must be consecutive
+    /// integers, starting right after the last revision in the log. Otherwise, returns
+    /// `RevisionSequenceError`, since composing them would corrupt the document's history.
+    ///
+    /// Composes the new remote revisions into a single change set and returns them. We can use the
+    /// composed remote revisions to transform our local revisions. If there are none, returns
+    /// `None`.
+    ///
+    /// Nothing is appended unless every revision is valid.
+    pub fn append_remote_revisions(
+        &mut self,
+        revisions: Vec<DocumentRevision>,
+    ) -> Result<Option<ComposedRemoteRevisions>, CommittedLogError> {
+        if revisions.is_empty() {
+            return Ok(None);
+        }
+        self.check_remote_revisions(&revisions)?;
+        let composed_change_sets =
+            ot::compose_iter(revisions.iter().map(|rev| rev.change_set.as_ref().unwrap()))
+                .map_err(remote_revisions_error)?;
+        self.append_composed_remote_revisions(revisions, composed_change_sets)
+    }
+
+    /// Like `append_remote_revisions`, for revisions that the host composed itself, e.g. in a web
+    /// worker, after `check_remote_revisions` passed. They are checked again, in case the log
+    /// changed meanwhile.
+    pub fn append_composed_remote_revisions(
+        &mut self,
+        revisions: Vec<DocumentRevision>,
+        composed_change_sets: ChangeSet,
+    ) -> Result<Option<ComposedRemoteRevisions>, CommittedLogError> {
+        self.check_remote_revisions(&revisions)?;
+        let (first_revision_number, last_revision_number) =
+            match (revisions.first(), revisions.last()) {
+                (Some(first), Some(last)) => (first.revision_number, last.revision_number),
+                _ => return Ok(None),
+            };
+        self.revisions.extend(revisions);
+        Ok(Some(ComposedRemoteRevisions {
+            composed_change_sets,
+            revision_range: (first_revision_number, last_revision_number),
+        }))
+    }
+
+    /// Checks that new remote revisions could be appended to the log: that they come next, in
+    /// sequence, and that each has a change set.
+    pub fn check_remote_revisions(
+        &self,
+        revisions: &[DocumentRevision],
+    ) -> Result<(), CommittedLogError> {
+        for (i, revision) in revisions.iter().enumerate() {
+            self.check_revision_number(revision.revision_number, i)?;
+            if revision.change_set.is_none() {
+                return Err(CommittedLogError::InvalidResponseError(format!(
+                    "Received remote revision {} without a change set",
+                    revision.revision_number
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the revision `offset` places after the end of the log has the number that
+    /// comes next. Revision numbers start at 1, and have no gaps.
+    fn check_revision_number(
+        &self,
+        revision_number: i64,
+        offset: usize,
+    ) -> Result<(), CommittedLogError> {
+        let expected = self.last_revision_number() + 1 + offset as i64;
+        if revision_number != expected {
+            return Err(CommittedLogError::RevisionSequenceError {
+                expected,
+                received: revision_number,
+            });
+        }
+        Ok(())
+    }
+
+    /// The revision number of the last committed revision, or 0 if there are none.
+    pub fn last_revision_number(&self) -> i64 {
+        self.revisions
+            .last()
+            .map(|r| r.revision_number)
+            .unwrap_or(0)
+    }
+
+    pub fn get_debug_lines(&self) -> Vec<String> {
+        let mut ret = Vec::new();
+        for revision in self.revisions.iter() {
+            let change_set = revision.change_set.as_ref().unwrap();
+            ret.push(format!(
+                "remote revision: {}",
+                get_change_set_description(change_set)
+            ));
+            for diagnostic in ot::diagnose(change_set) {
+                ret.push(format!("  warning: {}", diagnostic));
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn revision(revision_number: i64, change_set: ChangeSet) -> DocumentRevision {
+        DocumentRevision {
+            revision_number,
+            change_set: Some(change_set),
+            ..Default::default()
+        }
+    }
+
+    fn insert(retain: i64, content: &str) -> ChangeSet {
+        let mut change_set = ChangeSet::new();
+        change_set.retain(retain);
+        change_set.insert(content);
+        change_set
+    }
+
+    #[test]
+    fn test_append_remote_revisions() {
+        let mut log = CommittedLog::new();
+        assert!(log.append_remote_revisions(Vec::new()).unwrap().is_none());
+
+        let composed = log
+            .append_remote_revisions(vec![
+                revision(1, insert(0, "foo")),
+                revision(2, insert(3, "bar")),
+            ])
+            .unwrap()
+            .unwrap();
+        assert_eq!(composed.revision_range, (1, 2));
+        assert_eq!(
+            ot::apply("", &composed.composed_change_sets).unwrap(),
+            "foobar"
+        );
+        assert_eq!(log.len(), 2);
+        assert_eq!(log.last_revision_number(), 2);
+
+        log.push_local_revision(revision(3, insert(6, "!")))
+            .unwrap();
+        let composed = log.compose_range(0..3).unwrap().unwrap();
+        assert_eq!(ot::apply("", &composed).unwrap(), "foobar!");
+
+        assert_eq!(log.value_at(0).unwrap(), Vec::<u16>::new());
+        assert_eq!(log.value_at(1).unwrap(), ot::utils::str_to_u16_vec("foo"));
+        assert_eq!(
+            log.value_at(3).unwrap(),
+            ot::utils::str_to_u16_vec("foobar!")
+        );
+        assert!(log.value_at(4).is_err());
+    }
+
+    #[test]
+    fn test_append_composed_remote_revisions() {
+        let mut log = CommittedLog::new();
+        let revisions = vec![revision(1, insert(0, "foo")), revision(2, insert(3, "bar"))];
+        log.check_remote_revisions(&revisions).unwrap();
+        let composed_change_sets = ot::diff("", "foobar");
+
+        // Our own revision was committed while the remote revisions were being composed, so they
+        // no longer come next.
+        let mut changed_log = CommittedLog::new();
+        changed_log
+            .push_local_revision(revision(1, insert(0, "baz")))
+            .unwrap();
+        let result = changed_log
+            .append_composed_remote_revisions(revisions.clone(), composed_change_sets.clone());
+        assert!(matches!(
+            result,
+            Err(CommittedLogError::RevisionSequenceError {
+                expected: 2,
+                received: 1
+            })
+        ));
+
+        let composed = log
+            .append_composed_remote_revisions(revisions, composed_change_sets)
+            .unwrap()
+            .unwrap();
+        assert_eq!(composed.revision_range, (1, 2));
+        assert_eq!(log.len(), 2);
+    }
+
+    #[test]
+    fn test_append_remote_revisions_out_of_order() {
+        let mut log = CommittedLog::new();
+        let result = log.append_remote_revisions(vec![
+            revision(1, insert(0, "foo")),
+            revision(3, insert(3, "bar")),
+        ]);
+        assert!(matches!(
+            result,
+            Err(CommittedLogError::RevisionSequenceError {
+                expected: 2,
+                received: 3
+            })
+        ));
+        assert!(result.err().unwrap().requires_resync());
+        assert!(log.is_empty());
+
+        // Revisions that do not compose are the server's fault.
+        let result = log.append_remote_revisions(vec![
+            revision(1, insert(0, "foo")),
+            revision(2, insert(5, "bar")),
+        ]);
+        assert!(matches!(
+            result,
+            Err(CommittedLogError::InvalidResponseError(_))
+        ));
+        assert!(!result.err().unwrap().requires_resync());
+        assert!(log.is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_revisions() {
+        let mut log = CommittedLog::new();
+        log.append_remote_revisions(vec![revision(1, insert(0, "foo"))])
+            .unwrap();
+
+        // A revision that the log already has, like from a response that was delivered twice.
+        let result = log.append_remote_revisions(vec![
+            revision(1, insert(0, "foo")),
+            revision(2, insert(3, "bar")),
+        ]);
+        assert!(matches!(
+            result,
+            Err(CommittedLogError::RevisionSequenceError {
+                expected: 2,
+                received: 1
+            })
+        ));
+        let result = log.append_remote_revisions(vec![
+            revision(2, insert(3, "bar")),
+            revision(2, insert(6, "bar")),
+        ]);
+        assert!(matches!(
+            result,
+            Err(CommittedLogError::RevisionSequenceError {
+                expected: 3,
+                received: 2
+            })
+        ));
+        let result = log.push_local_revision(revision(1, insert(3, "!")));
+        assert!(matches!(
+            result,
+            Err(CommittedLogError::RevisionSequenceError {
+                expected: 2,
+                received: 1
+            })
+        ));
+        assert_eq!(log.len(), 1);
+    }
+}
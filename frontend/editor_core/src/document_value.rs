@@ -0,0 +1,782 @@
+use std::cmp::Ordering;
+use std::ops::Range;
+
+use serde::Serialize;
+
+use ot::writing_proto::change_op::Op;
+use ot::writing_proto::ChangeSet;
+use ot::OtError;
+
+use crate::search::SearchPattern;
+
+#[derive(Clone, Debug, Default)]
+pub struct DocumentValue {
+    pub chunks: Vec<DocumentValueChunk>,
+    chunk_id_counter: DocumentValueChunkId,
+    stats: DocumentStats,
+}
+
+pub type DocumentValueChunkId = usize;
+pub type DocumentValueChunkVersion = usize;
+
+#[derive(Clone, Debug)]
+pub struct DocumentValueChunk {
+    pub id: DocumentValueChunkId,
+    pub version: DocumentValueChunkVersion,
+    pub offset: usize,
+    pub value: Vec<u16>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DocumentValueChunkMeta {
+    pub id: DocumentValueChunkId,
+    pub version: DocumentValueChunkVersion,
+    pub offset: usize,
+}
+
+/// Word, character and paragraph counts for a document value.
+///
+/// Every count is a sum over lines: words never span a line break, a character count excludes line
+/// breaks, and a paragraph is a line that is not blank. So after a change, we only need to recount
+/// the lines that the change touched.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct DocumentStats {
+    pub word_count: usize,
+    pub character_count: usize,
+    pub paragraph_count: usize,
+}
+
+impl DocumentStats {
+    /// Counts the stats of `value`, which must start at the start of a line and end at the end of
+    /// a line.
+    fn of_lines(value: &[u16]) -> Self {
+        let mut stats = DocumentStats::default();
+        let mut in_word = false;
+        let mut line_is_blank = true;
+        for ch in std::char::decode_utf16(value.iter().copied()) {
+            let ch = ch.unwrap_or(std::char::REPLACEMENT_CHARACTER);
+            if ch == '\n' {
+                if !line_is_blank {
+                    stats.paragraph_count += 1;
+                }
+                line_is_blank = true;
+                in_word = false;
+                continue;
+            }
+            stats.character_count += 1;
+            if ch.is_whitespace() {
+                in_word = false;
+            } else {
+                if !in_word {
+                    stats.word_count += 1;
+                }
+                in_word = true;
+                line_is_blank = false;
+            }
+        }
+        if !line_is_blank {
+            stats.paragraph_count += 1;
+        }
+        stats
+    }
+
+    fn add(&mut self, other: &DocumentStats) {
+        self.word_count += other.word_count;
+        self.character_count += other.character_count;
+        self.paragraph_count += other.paragraph_count;
+    }
+
+    fn subtract(&mut self, other: &DocumentStats) {
+        self.word_count -= other.word_count;
+        self.character_count -= other.character_count;
+        self.paragraph_count -= other.paragraph_count;
+    }
+}
+
+/// The lines that a run of inserts and deletes touches, before and after the change.
+struct TouchedLines {
+    input: Range<usize>,
+    output: Range<usize>,
+}
+
+impl DocumentValue {
+    pub fn new() -> Self {
+        Self {
+            chunks: Vec::new(),
+            chunk_id_counter: 0,
+            stats: DocumentStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> DocumentStats {
+        self.stats
+    }
+
+    pub fn value_len(&self) -> usize {
+        match self.chunks.last() {
+            None => 0,
+            Some(chunk) => chunk.offset + chunk.value.len(),
+        }
+    }
+
+    pub fn apply(&mut self, change_set: &ChangeSet) -> Result<(), OtError> {
+        let (input_len, output_len) = ot::get_input_output_doc_lengths(change_set)?;
+        let value_len = self.value_len();
+        if input_len != value_len as i64 {
+            return Err(OtError::LengthMismatch {
+                expected: input_len,
+                actual: value_len as i64,
+            });
+        }
+
+        // Set aside the stats of the lines that the change set touches, so that we can recount
+        // just those lines afterwards.
+        let touched_lines = self.get_touched_lines(change_set)?;
+        let mut touched_stats_before = DocumentStats::default();
+        for lines in touched_lines.iter() {
+            let value = self.get_value_in_range(lines.input.clone())?;
+            touched_stats_before.add(&DocumentStats::of_lines(&value));
+        }
+
+        let original_chunks_len = self.chunks.len();
+        let mut chunks_iter = std::mem::take(&mut self.chunks).into_iter();
+        self.chunks.reserve(original_chunks_len);
+        let mut ops_iter = change_set.ops.iter();
+
+        let mut temp_op: Option<Op>;
+
+        let mut maybe_chunk = chunks_iter.next();
+        let mut maybe_op = ot::next_op(&mut ops_iter)?;
+
+        loop {
+            match (maybe_chunk, maybe_op) {
+                (None, None) => break,
+                (chunk, Some(Op::Insert(insert))) => {
+                    self.append_content(ot::utils::u32_slice_to_u16_vec(&insert.content)?);
+                    maybe_chunk = chunk;
+                    maybe_op = ot::next_op(&mut ops_iter)?;
+                }
+                (Some(mut chunk), Some(Op::Retain(retain))) => {
+                    let chunk_len = chunk.value.len() as i64;
+                    let retain_count = retain.count;
+                    match chunk_len.cmp(&retain_count) {
+                        Ordering::Less => {
+                            self.append_chunk(chunk);
+                            temp_op = Some(ot::retain_op(retain_count - chunk_len));
+                            maybe_chunk = chunks_iter.next();
+                            maybe_op = temp_op.as_ref();
+                        }
+                        Ordering::Greater => {
+                            let next_chunk_value = chunk.value.split_off(retain_count as usize);
+                            self.append_chunk(chunk);
+                            maybe_chunk = Some(DocumentValueChunk {
+                                id: self.next_chunk_id(),
+                                value: next_chunk_value,
+                                version: 0,
+                                offset: 0,
+                            });
+                            maybe_op = ot::next_op(&mut ops_iter)?;
+                        }
+                        Ordering::Equal => {
+                            self.append_chunk(chunk);
+                            maybe_chunk = chunks_iter.next();
+                            maybe_op = ot::next_op(&mut ops_iter)?;
+                        }
+                    }
+                }
+                (Some(mut chunk), Some(Op::Delete(delete))) => {
+                    let chunk_len = chunk.value.len() as i64;
+                    let delete_count = delete.count;
+                    match chunk_len.cmp(&delete_count) {
+                        Ordering::Less => {
+                            temp_op = Some(ot::delete_op(delete_count - chunk_len));
+                            maybe_chunk = chunks_iter.next();
+                            maybe_op = temp_op.as_ref();
+                        }
+                        Ordering::Greater => {
+                            chunk.value.drain(0..delete_count as usize);
+                            chunk.version += 1;
+                            maybe_chunk = Some(chunk);
+                            maybe_op = ot::next_op(&mut ops_iter)?;
+                        }
+                        Ordering::Equal => {
+                            maybe_chunk = chunks_iter.next();
+                            maybe_op = ot::next_op(&mut ops_iter)?;
+                        }
+                    }
+                }
+                (None, _) | (_, None) => {
+                    return Err(OtError::InvalidInput(String::from(
+                        "Mismatched document value and change set ops",
+                    )));
+                }
+            }
+        }
+        let new_value_len = self.value_len();
+        if output_len != new_value_len as i64 {
+            return Err(OtError::PostConditionFailed(format!(
+                "After applying changes, the document should have length {}, but it had length {}",
+                output_len, new_value_len,
+            )));
+        }
+
+        self.stats.subtract(&touched_stats_before);
+        for lines in touched_lines.iter() {
+            let value = self.get_value_in_range(lines.output.clone())?;
+            self.stats.add(&DocumentStats::of_lines(&value));
+        }
+        Ok(())
+    }
+
+    /// Returns the lines touched by each run of inserts and deletes in the change set, in order.
+    /// Runs that touch the same lines are merged, so no line is counted twice.
+    fn get_touched_lines(&self, change_set: &ChangeSet) -> Result<Vec<TouchedLines>, OtError> {
+        let mut touched_lines: Vec<TouchedLines> = Vec::new();
+        let mut input_offset = 0;
+        let mut output_offset = 0;
+        // The input and output offsets where the current run of inserts and deletes started.
+        let mut run_start: Option<(usize, usize)> = None;
+        let mut end_run = |run_start: (usize, usize), input_end: usize, output_end: usize| {
+            // The text between the run and the ends of its lines is retained, so it has the same
+            // length before and after the change. At the start, that only holds if no earlier run
+            // touched the same line, in which case we merge with the earlier run instead.
+            let line_start = self.get_line_start(run_start.0);
+            let line_end = self.get_line_end(input_end);
+            let output_line_end = output_end + (line_end - input_end);
+            match touched_lines.last_mut() {
+                Some(last) if line_start < last.input.end => {
+                    last.input.end = line_end;
+                    last.output.end = output_line_end;
+                }
+                _ => touched_lines.push(TouchedLines {
+                    input: line_start..line_end,
+                    output: (run_start.1 - (run_start.0 - line_start))..output_line_end,
+                }),
+            }
+        };
+        ot::for_each_op(change_set, |op| {
+            match op {
+                Op::Retain(retain) => {
+                    if let Some(start) = run_start.take() {
+                        end_run(start, input_offset, output_offset);
+                    }
+                    input_offset += retain.count as usize;
+                    output_offset += retain.count as usize;
+                }
+                Op::Delete(delete) => {
+                    run_start.get_or_insert((input_offset, output_offset));
+                    input_offset += delete.count as usize;
+                }
+                Op::Insert(insert) => {
+                    run_start.get_or_insert((input_offset, output_offset));
+                    output_offset += insert.content.len();
+                }
+            }
+            Ok(())
+        })?;
+        if let Some(start) = run_start {
+            end_run(start, input_offset, output_offset);
+        }
+        Ok(touched_lines)
+    }
+
+    /// Returns the offset of the start of the line containing `offset`. Each chunk is one line.
+    fn get_line_start(&self, offset: usize) -> usize {
+        if offset < self.value_len() {
+            return self.chunks[self.get_chunk_index(offset)].offset;
+        }
+        match self.chunks.last() {
+            // Appending to a last line that has no line break continues that line.
+            Some(chunk) if !self.can_append_to_last_chunk() => chunk.offset + chunk.value.len(),
+            Some(chunk) => chunk.offset,
+            None => 0,
+        }
+    }
+
+    /// Returns the offset of the end of the line containing `offset`, including its line break.
+    fn get_line_end(&self, offset: usize) -> usize {
+        if offset < self.value_len() {
+            let chunk = &self.chunks[self.get_chunk_index(offset)];
+            chunk.offset + chunk.value.len()
+        } else {
+            self.value_len()
+        }
+    }
+
+    /// Returns the index of the chunk containing `offset`, which must be less than the value length.
+    fn get_chunk_index(&self, offset: usize) -> usize {
+        match self
+            .chunks
+            .binary_search_by(|chunk| chunk.offset.cmp(&offset))
+        {
+            Ok(index) => index,
+            Err(index) => index - 1,
+        }
+    }
+
+    pub fn invert(&self, change_set: &ChangeSet) -> Result<ChangeSet, OtError> {
+        let (input_len, _output_len) = ot::get_input_output_doc_lengths(change_set)?;
+        let value_len = self.value_len();
+        if input_len != value_len as i64 {
+            return Err(OtError::LengthMismatch {
+                expected: input_len,
+                actual: value_len as i64,
+            });
+        }
+        let mut inverted = ChangeSet::new();
+        let mut i = 0;
+        ot::for_each_op(change_set, |op| {
+            match op {
+                Op::Retain(retain) => {
+                    inverted.retain(retain.count);
+                    i += retain.count as usize;
+                }
+                Op::Insert(insert) => {
+                    inverted.delete(insert.content.len() as i64);
+                }
+                Op::Delete(delete) => {
+                    let content = self.get_value_in_range(i..(i + delete.count as usize))?;
+                    inverted.insert_vec_u16(content);
+                    i += delete.count as usize;
+                }
+            }
+            Ok(())
+        })?;
+        Ok(inverted)
+    }
+
+    /// Returns true if applying the change set would leave the value unchanged.
+    pub fn is_identity(&self, change_set: &ChangeSet) -> Result<bool, OtError> {
+        if ot::is_identity(change_set, None) {
+            return Ok(true);
+        }
+        // Only a change set that keeps the length the same can be an identity. Check that before
+        // copying the value.
+        let (input_len, output_len) = ot::get_input_output_doc_lengths(change_set)?;
+        if input_len != output_len {
+            return Ok(false);
+        }
+        let value = self.get_value_in_range(0..self.value_len())?;
+        Ok(ot::is_identity(change_set, Some(&value)))
+    }
+
+    pub fn get_value_in_range(&self, range: Range<usize>) -> Result<Vec<u16>, OtError> {
+        let mut start = range.start;
+        let end = range.end;
+        if start > end {
+            return Err(OtError::InvalidInput(format!(
+                "Invalid range: {:?}",
+                (start..end)
+            )));
+        }
+        if start == end {
+            return Ok(Vec::new());
+        }
+        if self.chunks.is_empty() {
+            return Err(OtError::InvalidInput(String::from(
+                "Cannot get value range from empty DocumentValue",
+            )));
+        }
+        if end > self.value_len() {
+            return Err(OtError::InvalidInput(format!(
+                "Invalid range: {:?}. End is greater than DocumentValue length {}",
+                (start..end),
+                self.value_len()
+            )));
+        }
+        let first = match self
+            .chunks
+            .binary_search_by(|chunk| chunk.offset.cmp(&start))
+        {
+            Ok(index) => index,
+            Err(index) => index - 1,
+        };
+        let last = match self
+            .chunks
+            .binary_search_by(|chunk| (chunk.offset + chunk.value.len()).cmp(&end))
+        {
+            Ok(index) => index,
+            Err(index) => index,
+        };
+        let mut value_in_range: Vec<u16> = Vec::with_capacity(range.end - range.start);
+        for i in first..=last {
+            let chunk = &self.chunks[i];
+            let slice_start = start - chunk.offset;
+            let slice_end = std::cmp::min(end - chunk.offset, chunk.value.len());
+            value_in_range.extend_from_slice(&chunk.value[slice_start..slice_end]);
+            start = chunk.offset + chunk.value.len();
+        }
+        Ok(value_in_range)
+    }
+
+    /// Returns the range of the paragraphs that `range` touches, without the last one's line break.
+    /// Each chunk is one paragraph.
+    pub fn get_paragraphs_range(&self, range: Range<usize>) -> Range<usize> {
+        let start = self.get_line_start(range.start);
+        let end = self.get_line_end(std::cmp::max(range.start, range.end));
+        let ends_with_line_break = end > start
+            && self.chunks[self.get_chunk_index(end - 1)].value.last() == Some(&('\n' as u16));
+        if ends_with_line_break {
+            start..(end - 1)
+        } else {
+            start..end
+        }
+    }
+
+    /// Returns the ranges of the value that match the pattern, in order and without overlapping.
+    ///
+    /// A pattern without a line break cannot match across lines, so we search one chunk at a time
+    /// and never copy the value. Only a pattern with a line break searches a copy of the whole
+    /// value.
+    pub fn find_all(&self, pattern: &SearchPattern) -> Result<Vec<Range<usize>>, OtError> {
+        if pattern.contains_newline() {
+            let value = self.get_value_in_range(0..self.value_len())?;
+            return Ok(pattern.find_all(&value));
+        }
+        let mut matches = Vec::new();
+        for chunk in self.chunks.iter() {
+            let offset = chunk.offset;
+            let chunk_matches = pattern.find_all(&chunk.value);
+            matches.extend(
+                chunk_matches
+                    .into_iter()
+                    .map(|range| (range.start + offset)..(range.end + offset)),
+            );
+        }
+        Ok(matches)
+    }
+
+    /// Returns the first match that starts at or after `from`. If there is none, wraps around and
+    /// returns the first match in the value, if any.
+    pub fn find_next(
+        &self,
+        pattern: &SearchPattern,
+        from: usize,
+    ) -> Result<Option<Range<usize>>, OtError> {
+        if pattern.contains_newline() {
+            let value = self.get_value_in_range(0..self.value_len())?;
+            return Ok(pattern
+                .find_from(&value, std::cmp::min(from, value.len()))
+                .or_else(|| pattern.find_from(&value, 0)));
+        }
+        let first_chunk_index = if from < self.value_len() {
+            self.get_chunk_index(from)
+        } else {
+            self.chunks.len()
+        };
+        // Search from `from` to the end of the value, then from the start of the value up to the
+        // chunk we started in.
+        let after = (first_chunk_index..self.chunks.len()).map(|index| {
+            let chunk = &self.chunks[index];
+            let start = if index == first_chunk_index {
+                from - chunk.offset
+            } else {
+                0
+            };
+            (chunk, start)
+        });
+        let wrapped = (0..std::cmp::min(first_chunk_index + 1, self.chunks.len()))
+            .map(|index| (&self.chunks[index], 0));
+        Ok(after.chain(wrapped).find_map(|(chunk, start)| {
+            pattern
+                .find_from(&chunk.value, start)
+                .map(|range| (range.start + chunk.offset)..(range.end + chunk.offset))
+        }))
+    }
+
+    pub fn get_chunk_ids(&self) -> Vec<DocumentValueChunkId> {
+        self.chunks.iter().map(|chunk| chunk.id).collect()
+    }
+
+    pub fn get_chunk_versions(&self) -> Vec<DocumentValueChunkVersion> {
+        self.chunks.iter().map(|chunk| chunk.version).collect()
+    }
+
+    pub fn get_chunk(&self, id: DocumentValueChunkId) -> Option<&DocumentValueChunk> {
+        for i in 0..self.chunks.len() {
+            if self.chunks[i].id == id {
+                return Some(&self.chunks[i]);
+            }
+        }
+        None
+    }
+
+    fn append_chunk(&mut self, mut chunk: DocumentValueChunk) {
+        if self.can_append_to_last_chunk() {
+            self.append_content(chunk.value);
+        } else {
+            chunk.offset = match self.chunks.last() {
+                None => 0,
+                Some(last_chunk) => last_chunk.offset + last_chunk.value.len(),
+            };
+            self.chunks.push(chunk);
+        }
+    }
+
+    fn can_append_to_last_chunk(&self) -> bool {
+        match self.chunks.last() {
+            None => false,
+            Some(chunk) => match &chunk.value[..] {
+                [.., last_char] if *last_char == '\n' as u16 => false,
+                _ => true,
+            },
+        }
+    }
+
+    fn append_content(&mut self, content: Vec<u16>) {
+        // If the content is one line, append it directly without any new allocations.
+        let newline_index = content.iter().position(|&ch| ch == '\n' as u16);
+        let is_one_line = newline_index.is_none() || newline_index.unwrap() == content.len() - 1;
+        if is_one_line {
+            self.append_content_line(content);
+        } else {
+            // Otherwise, split into lines, copying each line into a newly allocated Vec.
+            let content_lines = content.split_inclusive(|&ch| ch == '\n' as u16);
+            for content_line in content_lines {
+                self.append_content_line(content_line.to_vec());
+            }
+        }
+    }
+
+    fn append_content_line(&mut self, mut content_line: Vec<u16>) {
+        if self.can_append_to_last_chunk() {
+            let mut last_chunk = self.chunks.last_mut().unwrap();
+            last_chunk.value.append(&mut content_line);
+            last_chunk.version += 1;
+        } else {
+            self.push_new_chunk(content_line);
+        }
+    }
+
+    fn push_new_chunk(&mut self, content: Vec<u16>) {
+        let id = self.next_chunk_id();
+        let offset = match self.chunks.last() {
+            None => 0,
+            Some(last_chunk) => last_chunk.offset + last_chunk.value.len(),
+        };
+        self.chunks.push(DocumentValueChunk {
+            id,
+            offset,
+            version: 0,
+            value: content,
+        });
+    }
+
+    fn next_chunk_id(&mut self) -> DocumentValueChunkId {
+        self.chunk_id_counter += 1;
+        self.chunk_id_counter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ot::writing_proto::{change_op::Op, ChangeOp, Delete, Insert, Retain};
+
+    use crate::search::SearchOptions;
+
+    fn create_change_set(ops: &[&str]) -> ChangeSet {
+        let change_ops: Vec<ChangeOp> = ops
+            .iter()
+            .map(|op| {
+                let op = if let Some(rest) = op.strip_prefix("I:") {
+                    ot::insert_op(&ot::utils::str_to_u32_vec(rest))
+                } else if let Some(rest) = op.strip_prefix("R:") {
+                    ot::retain_op(rest.parse::<i64>().unwrap())
+                } else if let Some(rest) = op.strip_prefix("D:") {
+                    ot::delete_op(rest.parse::<i64>().unwrap())
+                } else {
+                    unreachable!()
+                };
+                ChangeOp { op: Some(op) }
+            })
+            .collect();
+        ChangeSet { ops: change_ops }
+    }
+
+    #[allow(dead_code)]
+    fn print_chunks(document_value: &DocumentValue) {
+        for chunk in document_value.chunks.iter() {
+            let mut value = String::new();
+            for ch in String::from_utf16(&chunk.value).unwrap().chars() {
+                if ch == '\n' {
+                    value.push_str("\\n");
+                } else {
+                    value.push(ch);
+                }
+            }
+            println!(
+                "DocumentValueChunk(id: {}, version: {}, value: {}",
+                chunk.id, chunk.version, value
+            );
+        }
+    }
+
+    #[test]
+    fn test_apply_to_empty_document() {
+        let mut document_value = DocumentValue::new();
+        let change_set = create_change_set(&["I:Hello, world!"]);
+        document_value.apply(&change_set).unwrap();
+        assert_eq!(document_value.value_len(), 13);
+        assert_eq!(document_value.chunks.len(), 1);
+        let chunk = &document_value.chunks[0];
+        assert_eq!(chunk.id, 1);
+        assert_eq!(chunk.version, 0);
+        assert_eq!(chunk.offset, 0);
+        assert_eq!(String::from_utf16(&chunk.value).unwrap(), "Hello, world!");
+    }
+
+    #[test]
+    fn test_apply_insert_newline() {
+        let mut document_value = DocumentValue::new();
+        document_value
+            .apply(&create_change_set(&["I:Hello, world!"]))
+            .unwrap();
+
+        // Change "Hello, world!" into "Hello,\n world!"
+        let change_set = create_change_set(&["R:6", "I:\n", "R:7"]);
+        document_value.apply(&change_set).unwrap();
+        assert_eq!(document_value.value_len(), 14);
+        assert_eq!(document_value.chunks.len(), 2);
+        let first_chunk = &document_value.chunks[0];
+        let second_chunk = &document_value.chunks[1];
+        // Verify that first chunk's id stayed the same, but its version changed. Implies that we
+        // did not make a new allocation for the first chunk.
+        assert_eq!(first_chunk.id, 1);
+        assert_eq!(first_chunk.version, 1);
+        assert_eq!(first_chunk.offset, 0);
+        assert_eq!(String::from_utf16(&first_chunk.value).unwrap(), "Hello,\n");
+        // Verify that second chunk has a new id and starts with a fresh version.
+        assert_eq!(second_chunk.id, 2);
+        assert_eq!(second_chunk.version, 0);
+        assert_eq!(second_chunk.offset, 7);
+        assert_eq!(String::from_utf16(&second_chunk.value).unwrap(), " world!");
+    }
+
+    #[test]
+    fn test_invert() {
+        // Simple: invert a deletion from one chunk.
+        let mut document_value = DocumentValue::new();
+        document_value
+            .apply(&create_change_set(&["I:Hello, there friend!"]))
+            .unwrap();
+        assert_eq!(document_value.chunks.len(), 1);
+        let change_set = create_change_set(&["R:7", "D:5", "I:my", "R:8"]);
+        let result = document_value.invert(&change_set);
+        assert!(result.is_ok());
+        let inverted = result.unwrap();
+        assert_eq!(
+            inverted,
+            create_change_set(&["R:7", "I:there", "D:2", "R:8"])
+        );
+
+        // Complex: invert a deletion from multiple chunks.
+        let mut document_value = DocumentValue::new();
+        document_value
+            .apply(&create_change_set(&[
+                "I:Hello\nthere my\ngood and delightful\nfriend!",
+            ]))
+            .unwrap();
+        assert_eq!(document_value.chunks.len(), 4);
+        let change_set = create_change_set(&["R:12", "D:12", "R:18"]);
+        let result = document_value.invert(&change_set);
+        let inverted = result.unwrap();
+        assert_eq!(
+            inverted,
+            create_change_set(&["R:12", "I:my\ngood and ", "R:18"])
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut document_value = DocumentValue::new();
+        let change_sets = vec![
+            create_change_set(&["I:Hello there,\nmy good friend!\n"]),
+            // Append after the trailing line break.
+            create_change_set(&["R:29", "I:\nHow are you?"]),
+            // Delete a line break, joining two lines.
+            create_change_set(&["R:12", "D:1", "I: ", "R:29"]),
+            // Two changes in the same line, and one in another line.
+            create_change_set(&[
+                "R:6", "D:5", "I:again", "R:5", "I:x", "R:13", "I:  \n", "R:13",
+            ]),
+            // Delete everything but the last word.
+            create_change_set(&["D:42", "R:4"]),
+            create_change_set(&["D:4"]),
+        ];
+        for change_set in change_sets.iter() {
+            document_value.apply(change_set).unwrap();
+            let value = document_value
+                .get_value_in_range(0..document_value.value_len())
+                .unwrap();
+            assert_eq!(document_value.stats(), DocumentStats::of_lines(&value));
+        }
+    }
+
+    #[test]
+    fn test_find() {
+        let mut document_value = DocumentValue::new();
+        document_value
+            .apply(&create_change_set(&["I:The cat\nsat on the mat.\nCat"]))
+            .unwrap();
+        assert_eq!(document_value.chunks.len(), 3);
+        let pattern = |pattern: &str, whole_word: bool| {
+            let pattern: Vec<u16> = pattern.encode_utf16().collect();
+            SearchPattern::new(&pattern, &SearchOptions::new(false, whole_word))
+        };
+
+        let cat = pattern("cat", false);
+        assert_eq!(document_value.find_all(&cat).unwrap(), vec![4..7, 24..27]);
+        assert_eq!(document_value.find_next(&cat, 0).unwrap(), Some(4..7));
+        assert_eq!(document_value.find_next(&cat, 5).unwrap(), Some(24..27));
+        // Wraps around to the start.
+        assert_eq!(document_value.find_next(&cat, 25).unwrap(), Some(4..7));
+        assert_eq!(document_value.find_next(&cat, 27).unwrap(), Some(4..7));
+
+        // Matches across lines.
+        let across_lines = pattern("at\nsa", false);
+        assert_eq!(document_value.find_all(&across_lines).unwrap(), vec![5..10]);
+        assert_eq!(
+            document_value.find_next(&across_lines, 6).unwrap(),
+            Some(5..10)
+        );
+
+        let whole_word = pattern("at", true);
+        assert_eq!(document_value.find_all(&whole_word).unwrap(), vec![]);
+        assert_eq!(document_value.find_next(&whole_word, 0).unwrap(), None);
+    }
+
+    #[test]
+    fn test_get_paragraphs_range() {
+        let mut document_value = DocumentValue::new();
+        assert_eq!(document_value.get_paragraphs_range(0..0), 0..0);
+        document_value
+            .apply(&create_change_set(&["I:First.\nSecond one.\n\nLast."]))
+            .unwrap();
+        assert_eq!(document_value.get_paragraphs_range(0..0), 0..6);
+        assert_eq!(document_value.get_paragraphs_range(6..6), 0..6);
+        assert_eq!(document_value.get_paragraphs_range(9..9), 7..18);
+        assert_eq!(document_value.get_paragraphs_range(3..9), 0..18);
+        assert_eq!(document_value.get_paragraphs_range(19..19), 19..19);
+        assert_eq!(document_value.get_paragraphs_range(25..25), 20..25);
+
+        document_value
+            .apply(&create_change_set(&["R:25", "I:\n"]))
+            .unwrap();
+        assert_eq!(document_value.get_paragraphs_range(26..26), 26..26);
+    }
+
+    #[test]
+    fn test_stats_of_lines() {
+        let value: Vec<u16> = "One two\n\n  three 🙂\n".encode_utf16().collect();
+        assert_eq!(
+            DocumentStats::of_lines(&value),
+            DocumentStats {
+                word_count: 4,
+                character_count: 16,
+                paragraph_count: 2,
+            }
+        );
+    }
+}
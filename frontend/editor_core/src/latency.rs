@@ -0,0 +1,153 @@
+use std::collections::VecDeque;
+
+use serde::Serialize;
+
+/// Percentiles are computed over at most this many of each stage's latest samples, so that the
+/// report reflects how the editor is doing now, and memory stays bounded.
+pub const MAX_SAMPLES_PER_STAGE: usize = 1000;
+
+/// A stage of the editor's work that can make typing feel janky.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Stage {
+    /// Applying a change set to the document value.
+    Apply,
+    /// Transforming the pending changes over remote revisions.
+    Transform,
+    /// Turning an input event, or the difference between the textarea and the document, into a
+    /// change.
+    Diff,
+    /// Encoding requests to the backend and decoding its responses.
+    Serialization,
+}
+
+/// Records how long each stage takes, and summarizes the latest durations as percentiles.
+#[derive(Debug, Default)]
+pub struct LatencyRecorder {
+    stages: [StageSamples; 4],
+}
+
+#[derive(Debug, Default)]
+struct StageSamples {
+    count: u64,
+    millis: VecDeque<f64>,
+}
+
+/// The latency of every stage, in milliseconds. Serialized for the host as
+/// `{apply: {count, p50_millis, ...}, transform: ..., diff: ..., serialization: ...}`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct LatencyReport {
+    pub apply: StageReport,
+    pub transform: StageReport,
+    pub diff: StageReport,
+    pub serialization: StageReport,
+}
+
+/// The latency of one stage. `count` is every sample ever recorded, and the percentiles and the
+/// maximum are over the latest `MAX_SAMPLES_PER_STAGE` of them. All are 0 if there are none.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct StageReport {
+    pub count: u64,
+    pub p50_millis: f64,
+    pub p90_millis: f64,
+    pub p99_millis: f64,
+    pub max_millis: f64,
+}
+
+impl LatencyRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, stage: Stage, millis: f64) {
+        let samples = &mut self.stages[stage as usize];
+        if samples.millis.len() == MAX_SAMPLES_PER_STAGE {
+            samples.millis.pop_front();
+        }
+        samples.millis.push_back(millis);
+        samples.count += 1;
+    }
+
+    pub fn report(&self) -> LatencyReport {
+        LatencyReport {
+            apply: self.stage_report(Stage::Apply),
+            transform: self.stage_report(Stage::Transform),
+            diff: self.stage_report(Stage::Diff),
+            serialization: self.stage_report(Stage::Serialization),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    fn stage_report(&self, stage: Stage) -> StageReport {
+        let samples = &self.stages[stage as usize];
+        let mut millis: Vec<f64> = samples.millis.iter().copied().collect();
+        millis.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        StageReport {
+            count: samples.count,
+            p50_millis: percentile(&millis, 50.0),
+            p90_millis: percentile(&millis, 90.0),
+            p99_millis: percentile(&millis, 99.0),
+            max_millis: millis.last().copied().unwrap_or_default(),
+        }
+    }
+}
+
+/// The nearest-rank percentile of sorted samples.
+fn percentile(sorted_millis: &[f64], percent: f64) -> f64 {
+    if sorted_millis.is_empty() {
+        return 0.0;
+    }
+    let rank = (percent / 100.0 * sorted_millis.len() as f64).ceil() as usize;
+    sorted_millis[rank.max(1) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut recorder = LatencyRecorder::new();
+        assert_eq!(recorder.report(), LatencyReport::default());
+
+        // Recorded out of order.
+        for millis in (1..=100).rev() {
+            recorder.record(Stage::Apply, millis as f64);
+        }
+        recorder.record(Stage::Diff, 0.25);
+        let report = recorder.report();
+        assert_eq!(
+            report.apply,
+            StageReport {
+                count: 100,
+                p50_millis: 50.0,
+                p90_millis: 90.0,
+                p99_millis: 99.0,
+                max_millis: 100.0,
+            }
+        );
+        assert_eq!(report.diff.p50_millis, 0.25);
+        assert_eq!(report.diff.p99_millis, 0.25);
+        assert_eq!(report.transform, StageReport::default());
+
+        recorder.clear();
+        assert_eq!(recorder.report(), LatencyReport::default());
+    }
+
+    #[test]
+    fn test_report_latest_samples() {
+        // A slow start is forgotten once enough newer samples are recorded, but still counted.
+        let mut recorder = LatencyRecorder::new();
+        for _ in 0..10 {
+            recorder.record(Stage::Transform, 500.0);
+        }
+        for _ in 0..MAX_SAMPLES_PER_STAGE {
+            recorder.record(Stage::Transform, 1.0);
+        }
+        let report = recorder.report();
+        assert_eq!(report.transform.count, MAX_SAMPLES_PER_STAGE as u64 + 10);
+        assert_eq!(report.transform.max_millis, 1.0);
+    }
+}
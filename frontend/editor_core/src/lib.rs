@@ -0,0 +1,70 @@
+//! The editor's document model, without any browser dependencies.
+//!
+//! This holds the state of a document being edited: the revisions committed on the server, the
+//! local revisions that are not committed yet, the undo and redo stacks, and the document's value.
+//! The `wasm` crate wraps it in a `wasm-bindgen` API for the browser, and talks to the backend.
+//! Keeping it free of `js_sys` and `web_sys` means it can be unit tested natively and reused
+//! outside the browser.
+
+pub mod annotations;
+pub mod committed_log;
+pub mod document_value;
+pub mod latency;
+pub mod pending_log;
+pub mod progressive_insert;
+pub mod region_locks;
+pub mod scroll_anchor;
+pub mod search;
+pub mod sync_schedule;
+pub mod textarea_update;
+pub mod title_sync;
+pub mod typing;
+pub mod undo_manager;
+
+use std::fmt::Write;
+
+use ot::writing_proto::{change_op::Op, ChangeSet};
+
+pub fn get_change_set_description(change_set: &ChangeSet) -> String {
+    let mut ret = String::new();
+    let mut is_first = true;
+    for change_op in &change_set.ops {
+        if change_op.op.is_none() {
+            continue;
+        }
+        if is_first {
+            is_first = false;
+        } else {
+            ret.push_str(", ");
+        }
+        let op = change_op.op.as_ref().unwrap();
+        match op {
+            Op::Retain(retain) => {
+                write!(&mut ret, "Retain({})", retain.count).unwrap();
+            }
+            Op::Delete(delete) => {
+                write!(&mut ret, "Delete({})", delete.count).unwrap();
+            }
+            Op::Insert(insert) => {
+                let mut content_u16: Vec<u16> = Vec::new();
+                for ch in &insert.content {
+                    let ch = *ch as u16;
+                    if ch == '\n' as u16 {
+                        content_u16.push('\\' as u16);
+                        content_u16.push('n' as u16);
+                    } else {
+                        content_u16.push(ch);
+                    }
+                }
+                let content_str: String =
+                    String::from_utf16(&content_u16).unwrap_or_else(|_| "".to_string());
+                if content_str == "\\n" {
+                    write!(&mut ret, "Insert('\\n')").unwrap();
+                } else {
+                    write!(&mut ret, "Insert(\"{}\")", &content_str).unwrap();
+                }
+            }
+        }
+    }
+    ret
+}
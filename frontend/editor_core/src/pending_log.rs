@@ -0,0 +1,160 @@
+use std::collections::VecDeque;
+use std::ops::Range;
+
+use ot::writing_proto::ChangeSet;
+use ot::OtError;
+
+use crate::get_change_set_description;
+
+#[derive(Default)]
+pub struct PendingLog {
+    change_sets: VecDeque<ChangeSet>,
+}
+
+impl PendingLog {
+    pub fn new() -> Self {
+        Self {
+            change_sets: VecDeque::new(),
+        }
+    }
+
+    pub fn front(&self) -> Option<&ChangeSet> {
+        self.change_sets.front()
+    }
+
+    pub fn push_back(&mut self, change_set: &ChangeSet) {
+        self.change_sets.push_back(change_set.clone());
+    }
+
+    pub fn pop_front(&mut self) -> Option<ChangeSet> {
+        self.change_sets.pop_front()
+    }
+
+    pub fn back_mut(&mut self) -> Option<&mut ChangeSet> {
+        self.change_sets.back_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.change_sets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn compress(&mut self) -> Result<(), OtError> {
+        if self.change_sets.is_empty() {
+            return Ok(());
+        }
+        let change_set = ot::compose_iter(self.change_sets.iter())?;
+        self.change_sets.clear();
+        // Pending edits may cancel out, like typing a character and then deleting it. There is no
+        // need to commit a revision that changes nothing.
+        if !ot::is_identity(&change_set, None) {
+            self.change_sets.push_back(change_set);
+        }
+        Ok(())
+    }
+
+    pub fn compose_range(&self, range: Range<usize>) -> Result<Option<ChangeSet>, OtError> {
+        if range.start >= self.change_sets.len() {
+            return Ok(None);
+        }
+        if range.end <= range.start {
+            return Ok(None);
+        }
+        let iter = self.change_sets.range(range);
+        Ok(Some(ot::compose_iter(iter)?))
+    }
+
+    /// Transforms the local pending changes against the remote change.
+    ///
+    /// Returns the remote change transformed against the local pending changes.
+    ///
+    /// In particular, if we have local changes `L1, L2, ..., LN`, and remote change `R`, then this
+    /// function transforms the local changes and remote change into `L1', L2', ..., LN'` and `R'`
+    /// respectively such that `R * L1' * L2' * ... * LN' == L1 * L2 * ... LN * R'`.
+    ///
+    /// The function updates the local changes in the pending log to `L1', L2', ..., LN'` and
+    /// returns `R'`.
+    pub fn transform(&mut self, remote: &ChangeSet) -> Result<ChangeSet, OtError> {
+        let mut remote = remote.clone();
+        for change_set in self.change_sets.iter_mut() {
+            let (transformed_change_set, transformed_remote) = ot::transform(&change_set, &remote)?;
+            *change_set = transformed_change_set;
+            remote = transformed_remote;
+        }
+        Ok(remote)
+    }
+
+    /// Drops the first pending change set, as if it had never been made, for when the server will
+    /// never commit it. `committed_value` is the document that it applies to.
+    ///
+    /// Returns the change set that takes the local document, with every pending change applied,
+    /// to the local document without the dropped change. The rest of the pending changes are
+    /// rebased to do without it.
+    pub fn discard_front(&mut self, committed_value: &[u16]) -> Result<Option<ChangeSet>, OtError> {
+        let change_set = match self.change_sets.pop_front() {
+            Some(change_set) => change_set,
+            None => return Ok(None),
+        };
+        // The inverse applies to the document right after the dropped change, like the rest of
+        // the pending changes, so they transform against each other like concurrent changes.
+        let inverse = ot::invert_slice(committed_value, &change_set)?;
+        Ok(Some(self.transform(&inverse)?))
+    }
+
+    /// Drops every pending change set, as if none had been made, for when the server will never
+    /// commit any of them. `committed_value` is the document that the first one applies to.
+    ///
+    /// Returns the change set that takes the local document, with every pending change applied,
+    /// back to `committed_value`.
+    pub fn discard_all(&mut self, committed_value: &[u16]) -> Result<Option<ChangeSet>, OtError> {
+        let composed = match self.compose_range(0..self.change_sets.len())? {
+            Some(composed) => composed,
+            None => return Ok(None),
+        };
+        let inverse = ot::invert_slice(committed_value, &composed)?;
+        self.change_sets.clear();
+        Ok(Some(inverse))
+    }
+
+    pub fn get_debug_lines(&self) -> Vec<String> {
+        let mut ret = Vec::new();
+        for change_set in self.change_sets.iter() {
+            ret.push(format!(
+                "local_revision: {}",
+                get_change_set_description(change_set)
+            ));
+            for diagnostic in ot::diagnose(change_set) {
+                ret.push(format!("  warning: {}", diagnostic));
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ot::utils::str_to_u16_vec;
+
+    #[test]
+    fn test_discard_all() {
+        let mut pending_log = PendingLog::new();
+        assert_eq!(pending_log.discard_all(&[]).unwrap(), None);
+
+        let committed = "Hello, world";
+        let first = ot::diff(committed, "Hello there, world");
+        let second = ot::diff("Hello there, world", "Hello there");
+        pending_log.push_back(&first);
+        pending_log.push_back(&second);
+        let rollback = pending_log
+            .discard_all(&str_to_u16_vec(committed))
+            .unwrap()
+            .unwrap();
+        assert!(pending_log.is_empty());
+        assert_eq!(ot::apply("Hello there", &rollback).unwrap(), committed);
+    }
+}
@@ -0,0 +1,218 @@
+use std::ops::Range;
+
+use ot::writing_proto::{change_op::Op, ChangeSet, Selection};
+use ot::OtError;
+
+/// The most text, in UTF-16 code units, that one step of a progressive insert adds.
+pub const PROGRESSIVE_INSERT_CHUNK_LEN: usize = 16 * 1024;
+
+/// A large insert, like pasting hundreds of kilobytes, split into change sets that each insert a
+/// bounded amount of text. Applying one giant change set stalls the page, while applying the
+/// pieces one at a time lets other work run in between.
+///
+/// The first change set also deletes the text that the insert replaces. Every later change set
+/// inserts right after the previous one, so the pieces compose into the original insert. If other
+/// changes land in between, `transform` moves the insert position through them.
+#[derive(Clone, Debug)]
+pub struct ProgressiveInsert {
+    offset: i64,
+    delete_count: i64,
+    text: Vec<u16>,
+    chunks: Vec<Range<usize>>,
+    next_chunk: usize,
+}
+
+impl ProgressiveInsert {
+    /// Replaces `delete_count` code units at `offset` with `text`, inserting at most
+    /// `max_chunk_len` code units per change set.
+    pub fn new(offset: i64, delete_count: i64, text: Vec<u16>, max_chunk_len: usize) -> Self {
+        let chunks = split_text(&text, max_chunk_len);
+        Self {
+            offset,
+            delete_count,
+            text,
+            chunks,
+            next_chunk: 0,
+        }
+    }
+
+    /// Splits a change set that replaces one range of the document, like a paste, if it inserts
+    /// more than `max_chunk_len` code units. Returns `None` for any other change set.
+    pub fn from_change_set(change_set: &ChangeSet, max_chunk_len: usize) -> Option<Self> {
+        let mut offset = 0;
+        let mut delete_count = 0;
+        let mut text: Option<Vec<u16>> = None;
+        for change_op in &change_set.ops {
+            match (change_op.op.as_ref()?, &text) {
+                (Op::Retain(retain), None) if delete_count == 0 => offset += retain.count,
+                (Op::Delete(delete), None) => delete_count += delete.count,
+                (Op::Insert(insert), None) => {
+                    text = Some(insert.content.iter().map(|&c| c as u16).collect());
+                }
+                // The rest of the document, after the insert.
+                (Op::Retain(_), Some(_)) => {}
+                _ => return None,
+            }
+        }
+        let text = text?;
+        if text.len() <= max_chunk_len {
+            return None;
+        }
+        Some(Self::new(offset, delete_count, text, max_chunk_len))
+    }
+
+    /// Where the next piece goes, which is right after the text inserted so far.
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+
+    /// Returns the change set for the next piece of the insert, which applies to a document of
+    /// length `value_len`, or `None` if the whole text was inserted.
+    pub fn next_change_set(&mut self, value_len: i64) -> Option<ChangeSet> {
+        let chunk = self.chunks.get(self.next_chunk)?.clone();
+        let chunk_len = (chunk.end - chunk.start) as i64;
+        let mut change_set = ChangeSet::new();
+        change_set.retain(self.offset);
+        change_set.delete(self.delete_count);
+        change_set.insert_vec_u16(self.text[chunk].to_vec());
+        change_set.retain(value_len - self.offset - self.delete_count);
+        self.offset += chunk_len;
+        self.delete_count = 0;
+        self.next_chunk += 1;
+        Some(change_set)
+    }
+
+    /// Moves the insert position through a change set that applies to the document after the
+    /// pieces inserted so far.
+    pub fn transform(&mut self, change_set: &ChangeSet) -> Result<(), OtError> {
+        let selection = Selection {
+            offset: self.offset,
+            count: self.delete_count,
+        };
+        let selection = ot::transform_selection(&selection, change_set)?;
+        self.offset = selection.offset;
+        self.delete_count = selection.count;
+        Ok(())
+    }
+
+    /// The number of code units inserted so far.
+    pub fn inserted_len(&self) -> usize {
+        self.chunks[..self.next_chunk]
+            .last()
+            .map_or(0, |chunk| chunk.end)
+    }
+
+    pub fn total_len(&self) -> usize {
+        self.text.len()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.next_chunk >= self.chunks.len()
+    }
+}
+
+/// Splits `text` into ranges of at most `max_len` code units, without splitting surrogate pairs.
+/// Empty text is one empty range, so that an insert of nothing still deletes what it replaces.
+fn split_text(text: &[u16], max_len: usize) -> Vec<Range<usize>> {
+    let max_len = std::cmp::max(max_len, 2);
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < text.len() {
+        let mut end = std::cmp::min(start + max_len, text.len());
+        if end < text.len() && (0xD800..=0xDBFF).contains(&text[end - 1]) {
+            end -= 1;
+        }
+        chunks.push(start..end);
+        start = end;
+    }
+    if chunks.is_empty() {
+        chunks.push(0..0);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    #[test]
+    fn test_split_text() {
+        assert_eq!(split_text(&utf16(""), 4), vec![0..0]);
+        assert_eq!(split_text(&utf16("abcdefghij"), 4), vec![0..4, 4..8, 8..10]);
+        // The emoji is two code units, and is not split between chunks.
+        assert_eq!(split_text(&utf16("abc\u{1F600}d"), 4), vec![0..3, 3..6]);
+    }
+
+    #[test]
+    fn test_from_change_set() {
+        let mut paste = ChangeSet::new();
+        paste.retain(2);
+        paste.delete(3);
+        paste.insert("abcdefghij");
+        paste.retain(6);
+        let insert = ProgressiveInsert::from_change_set(&paste, 4).unwrap();
+        assert_eq!(insert.offset(), 2);
+        assert_eq!(insert.delete_count, 3);
+        assert_eq!(insert.chunks, vec![0..4, 4..8, 8..10]);
+
+        // Small enough to apply at once.
+        assert!(ProgressiveInsert::from_change_set(&paste, 10).is_none());
+
+        // Not a single replacement.
+        let mut two_inserts = ChangeSet::new();
+        two_inserts.insert("abcdefghij");
+        two_inserts.retain(2);
+        two_inserts.insert("abcdefghij");
+        assert!(ProgressiveInsert::from_change_set(&two_inserts, 4).is_none());
+    }
+
+    #[test]
+    fn test_next_change_set() -> Result<(), OtError> {
+        // Replace "big" in "a big paste" with the alphabet, four letters at a time.
+        let document = "a big paste";
+        let alphabet = "abcdefghijklmnopqrstuvwxyz";
+        let mut insert = ProgressiveInsert::new(2, 3, utf16(alphabet), 4);
+        let mut value = document.to_string();
+        let mut pieces = Vec::new();
+        while let Some(change_set) = insert.next_change_set(value.encode_utf16().count() as i64) {
+            value = ot::apply(&value, &change_set)?;
+            pieces.push(change_set);
+        }
+        assert!(insert.is_done());
+        assert_eq!(pieces.len(), 7);
+        assert_eq!(insert.inserted_len(), insert.total_len());
+        assert_eq!(value, format!("a {} paste", alphabet));
+
+        // The pieces compose into one change set that does the whole replacement.
+        let composed = ot::compose_iter(pieces.iter())?;
+        assert_eq!(ot::apply(document, &composed)?, value);
+        Ok(())
+    }
+
+    #[test]
+    fn test_transform() -> Result<(), OtError> {
+        let mut insert = ProgressiveInsert::new(5, 0, utf16("abcdef"), 3);
+        let mut value = String::from("0123456789");
+
+        let change_set = insert.next_change_set(10).unwrap();
+        value = ot::apply(&value, &change_set)?;
+        assert_eq!(value, "01234abc56789");
+
+        // Someone else inserts text at the start of the document, before the next piece lands.
+        let mut remote = ChangeSet::new();
+        remote.insert(">>");
+        remote.retain(13);
+        value = ot::apply(&value, &remote)?;
+        insert.transform(&remote)?;
+
+        let change_set = insert.next_change_set(15).unwrap();
+        value = ot::apply(&value, &change_set)?;
+        assert_eq!(value, ">>01234abcdef56789");
+        assert!(insert.next_change_set(18).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use ot::writing_proto::{ChangeSet, Selection};
+use ot::OtError;
+
+use crate::typing::TYPING_EXPIRY;
+
+/// Forget someone's region lock after this long without a heartbeat from them, in milliseconds.
+/// Locks are announced with typing heartbeats, so they last as long as typing does.
+pub const REGION_LOCK_EXPIRY: f64 = TYPING_EXPIRY;
+
+/// A region of the local document that someone else is editing.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ActiveRegionLock {
+    pub user_id: String,
+    pub display_name: String,
+    pub start: i64,
+    pub end: i64,
+}
+
+struct RegionLock {
+    display_name: String,
+    // The revision that `region` is in.
+    revision_number: i64,
+    region: Selection,
+    // In milliseconds.
+    last_seen_at: f64,
+}
+
+/// Tracks the regions of the document that other people are editing, so that the host can warn
+/// the user before they edit the same region. The locks are only hints: editing a locked region is
+/// allowed, and may just lead to a conflict.
+///
+/// Each lock is in some revision of the document. As remote revisions are loaded, locks are moved
+/// through them, and they are moved through the pending local changes when shown. A lock in a
+/// revision that we do not have yet is shown as is, which is close enough until the next
+/// heartbeat.
+#[derive(Default)]
+pub struct RegionLockTracker {
+    // By user id.
+    locks: HashMap<String, RegionLock>,
+}
+
+impl RegionLockTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records someone else's region lock, in place of their earlier one.
+    pub fn on_region_lock(
+        &mut self,
+        user_id: &str,
+        display_name: &str,
+        revision_number: i64,
+        region: Selection,
+        now: f64,
+    ) {
+        self.locks.insert(
+            user_id.to_string(),
+            RegionLock {
+                display_name: display_name.to_string(),
+                revision_number,
+                region,
+                last_seen_at: now,
+            },
+        );
+    }
+
+    /// Moves the locks in revision `first_revision_number - 1` through the remote revisions
+    /// `first_revision_number..=last_revision_number`, composed into one change set. Forgets the
+    /// locks whose regions they delete.
+    pub fn on_remote_revisions(
+        &mut self,
+        first_revision_number: i64,
+        last_revision_number: i64,
+        composed_change_set: &ChangeSet,
+    ) -> Result<(), OtError> {
+        let mut deleted = Vec::new();
+        for (user_id, lock) in self.locks.iter_mut() {
+            if lock.revision_number != first_revision_number - 1 {
+                continue;
+            }
+            match ot::transform_region(&lock.region, composed_change_set)? {
+                Some(region) => {
+                    lock.region = region;
+                    lock.revision_number = last_revision_number;
+                }
+                None => deleted.push(user_id.clone()),
+            }
+        }
+        for user_id in deleted {
+            self.locks.remove(&user_id);
+        }
+        Ok(())
+    }
+
+    /// The locks announced within the last `REGION_LOCK_EXPIRY` milliseconds, in the local
+    /// document, which is the last loaded revision with the `pending` local changes applied.
+    /// Sorted by where they start. Forgets the expired locks.
+    pub fn active_region_locks(
+        &mut self,
+        now: f64,
+        pending: Option<&ChangeSet>,
+    ) -> Result<Vec<ActiveRegionLock>, OtError> {
+        self.locks
+            .retain(|_, lock| now - lock.last_seen_at < REGION_LOCK_EXPIRY);
+        let mut active_region_locks = Vec::new();
+        for (user_id, lock) in self.locks.iter() {
+            let region = match pending {
+                Some(pending) => match ot::transform_region(&lock.region, pending)? {
+                    Some(region) => region,
+                    None => continue,
+                },
+                None => lock.region.clone(),
+            };
+            active_region_locks.push(ActiveRegionLock {
+                user_id: user_id.clone(),
+                display_name: lock.display_name.clone(),
+                start: region.offset,
+                end: region.offset + region.count,
+            });
+        }
+        active_region_locks.sort_by(|a, b| (a.start, &a.user_id).cmp(&(b.start, &b.user_id)));
+        Ok(active_region_locks)
+    }
+}
+
+/// Maps a region of the local document back to the last loaded revision, by undoing the `pending`
+/// local changes, so that it can be announced as a region of that revision. Returns `None` if the
+/// pending changes inserted all of the region.
+pub fn region_before_pending(
+    region: &Selection,
+    pending: Option<&ChangeSet>,
+) -> Result<Option<Selection>, OtError> {
+    match pending {
+        // Where the pending changes deleted text, the inverse inserts placeholders of the same
+        // length, which is all that transforming a region needs.
+        Some(pending) => ot::transform_region(region, &ot::create_empty_inverse(pending)),
+        None => Ok(Some(region.clone())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(offset: i64, count: i64) -> Selection {
+        Selection { offset, count }
+    }
+
+    #[test]
+    fn test_locks_move_and_expire() {
+        let mut tracker = RegionLockTracker::new();
+        // "First paragraph.\nSecond paragraph."
+        tracker.on_region_lock("u_1", "Al", 4, region(17, 17), 0.0);
+        tracker.on_region_lock("u_2", "Bea", 4, region(0, 16), 1000.0);
+        // Cy announced a lock in a revision that we have not loaded yet.
+        tracker.on_region_lock("u_3", "Cy", 6, region(0, 3), 1000.0);
+
+        // Remote revisions 5 and 6 delete the first paragraph.
+        let mut remote = ChangeSet::new();
+        remote.delete(17);
+        remote.retain(17);
+        tracker.on_remote_revisions(5, 6, &remote).unwrap();
+
+        // Our pending changes append to what is now the first paragraph.
+        let mut pending = ChangeSet::new();
+        pending.retain(17);
+        pending.insert(" More.");
+        let locks = tracker
+            .active_region_locks(2000.0, Some(&pending))
+            .unwrap()
+            .into_iter()
+            .map(|lock| (lock.display_name, lock.start, lock.end))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            locks,
+            vec![(String::from("Al"), 0, 23), (String::from("Cy"), 0, 3)]
+        );
+
+        let locks = tracker.active_region_locks(5500.0, None).unwrap();
+        assert_eq!(locks.len(), 1);
+        assert_eq!(locks[0].display_name, "Cy");
+    }
+
+    #[test]
+    fn test_region_before_pending() {
+        // "Hello.\nWorld." -> "Hi.\nWorld!!"
+        let mut pending = ChangeSet::new();
+        pending.retain(1);
+        pending.delete(4);
+        pending.insert("i");
+        pending.retain(7);
+        pending.delete(1);
+        pending.insert("!!");
+        assert_eq!(
+            region_before_pending(&region(4, 7), Some(&pending)).unwrap(),
+            Some(region(7, 6))
+        );
+        assert_eq!(
+            region_before_pending(&region(0, 3), Some(&pending)).unwrap(),
+            Some(region(0, 6))
+        );
+        assert_eq!(
+            region_before_pending(&region(4, 7), None).unwrap(),
+            Some(region(4, 7))
+        );
+    }
+}
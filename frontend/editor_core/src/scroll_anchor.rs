@@ -0,0 +1,170 @@
+use ot::writing_proto::{change_op::Op, ChangeSet};
+use ot::OtError;
+
+use crate::document_value::DocumentValue;
+
+const NEWLINE: u16 = '\n' as u16;
+
+/// The first visible character of the host's viewport, moved through the changes that others make
+/// to the document. When collaborators add or remove lines above the viewport, the host scrolls by
+/// `lines_delta` lines to keep showing the same text.
+///
+/// `lines_delta` only counts line breaks, so it is a hint: lines that wrap, or edits inside the
+/// first visible line, can still move the text by a few pixels.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScrollAnchor {
+    pub offset: i64,
+    pub lines_delta: i64,
+}
+
+impl ScrollAnchor {
+    pub fn new(offset: i64) -> Self {
+        Self {
+            offset,
+            lines_delta: 0,
+        }
+    }
+
+    /// Moves the anchor through the change set, which must apply to `value`, and adds the line
+    /// breaks that it inserts or deletes before the anchor to `lines_delta`.
+    pub fn transform(
+        &mut self,
+        value: &DocumentValue,
+        change_set: &ChangeSet,
+    ) -> Result<(), OtError> {
+        let mut old_offset = 0;
+        for change_op in &change_set.ops {
+            if old_offset >= self.offset {
+                break;
+            }
+            match change_op.op.as_ref() {
+                Some(Op::Retain(retain)) => {
+                    old_offset += retain.count;
+                }
+                Some(Op::Delete(delete)) => {
+                    let end = std::cmp::min(old_offset + delete.count, self.offset);
+                    let deleted = value.get_value_in_range(old_offset as usize..end as usize)?;
+                    self.lines_delta -= deleted.iter().filter(|&&c| c == NEWLINE).count() as i64;
+                    old_offset += delete.count;
+                }
+                Some(Op::Insert(insert)) => {
+                    self.lines_delta += insert
+                        .content
+                        .iter()
+                        .filter(|&&c| c == NEWLINE as u32)
+                        .count() as i64;
+                }
+                None => {
+                    return Err(OtError::InvalidInput(String::from("Change op is empty")));
+                }
+            }
+        }
+        self.offset = ot::transform_position(self.offset, change_set)?;
+        Ok(())
+    }
+
+    /// Returns the lines that the anchor moved by since the last call, and starts counting again.
+    pub fn take_lines_delta(&mut self) -> i64 {
+        std::mem::take(&mut self.lines_delta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document_value(text: &str) -> DocumentValue {
+        let mut value = DocumentValue::new();
+        let mut change_set = ChangeSet::new();
+        change_set.insert(text);
+        value.apply(&change_set).unwrap();
+        value
+    }
+
+    #[test]
+    fn test_transform() {
+        // The anchor is at the start of "three".
+        let value = document_value("one\ntwo\nthree\nfour");
+        let anchor_offset = 8;
+
+        let mut insert_above = ChangeSet::new();
+        insert_above.retain(4);
+        insert_above.insert("new\nlines\n");
+        insert_above.retain(14);
+
+        let mut delete_above = ChangeSet::new();
+        delete_above.delete(8);
+        delete_above.retain(10);
+
+        let mut insert_at_anchor = ChangeSet::new();
+        insert_at_anchor.retain(8);
+        insert_at_anchor.insert("inserted\n");
+        insert_at_anchor.retain(10);
+
+        let mut delete_across_anchor = ChangeSet::new();
+        delete_across_anchor.retain(2);
+        delete_across_anchor.delete(10);
+        delete_across_anchor.retain(6);
+
+        let mut edit_below = ChangeSet::new();
+        edit_below.retain(14);
+        edit_below.delete(4);
+        edit_below.insert("\n\n");
+
+        let cases = vec![
+            (
+                insert_above,
+                ScrollAnchor {
+                    offset: 18,
+                    lines_delta: 2,
+                },
+            ),
+            (
+                delete_above,
+                ScrollAnchor {
+                    offset: 0,
+                    lines_delta: -2,
+                },
+            ),
+            (
+                insert_at_anchor,
+                ScrollAnchor {
+                    offset: 8,
+                    lines_delta: 0,
+                },
+            ),
+            (
+                delete_across_anchor,
+                ScrollAnchor {
+                    offset: 2,
+                    lines_delta: -2,
+                },
+            ),
+            (
+                edit_below,
+                ScrollAnchor {
+                    offset: 8,
+                    lines_delta: 0,
+                },
+            ),
+        ];
+        for (change_set, expected) in cases {
+            let mut anchor = ScrollAnchor::new(anchor_offset);
+            anchor.transform(&value, &change_set).unwrap();
+            assert_eq!(anchor, expected, "{:?}", change_set);
+        }
+    }
+
+    #[test]
+    fn test_take_lines_delta() {
+        let value = document_value("one\ntwo");
+        let mut anchor = ScrollAnchor::new(4);
+        let mut change_set = ChangeSet::new();
+        change_set.insert("zero\n");
+        change_set.retain(7);
+        anchor.transform(&value, &change_set).unwrap();
+        assert_eq!(anchor.take_lines_delta(), 1);
+        assert_eq!(anchor.take_lines_delta(), 0);
+        assert_eq!(anchor.offset, 9);
+    }
+}
@@ -0,0 +1,146 @@
+use std::ops::Range;
+
+/// Options for finding text in a document.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SearchOptions {
+    /// If false, letters match regardless of case.
+    pub match_case: bool,
+    /// If true, only matches that are not part of a longer word count.
+    pub whole_word: bool,
+}
+
+impl SearchOptions {
+    pub fn new(match_case: bool, whole_word: bool) -> Self {
+        Self {
+            match_case,
+            whole_word,
+        }
+    }
+}
+
+/// Text to search for, prepared once so that it can be matched against many pieces of text.
+#[derive(Clone, Debug)]
+pub struct SearchPattern {
+    // UTF-16 code units, case folded unless the search matches case.
+    folded: Vec<u16>,
+    options: SearchOptions,
+}
+
+impl SearchPattern {
+    pub fn new(pattern: &[u16], options: &SearchOptions) -> Self {
+        Self {
+            folded: pattern
+                .iter()
+                .map(|ch| fold_case_unit(*ch, options))
+                .collect(),
+            options: *options,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.folded.is_empty()
+    }
+
+    /// Returns true if a match could span more than one line.
+    pub fn contains_newline(&self) -> bool {
+        self.folded.contains(&('\n' as u16))
+    }
+
+    /// Returns the ranges of `value` that match, in order and without overlapping. An empty pattern
+    /// matches nothing.
+    pub fn find_all(&self, value: &[u16]) -> Vec<Range<usize>> {
+        let mut matches = Vec::new();
+        let mut start = 0;
+        while let Some(range) = self.find_from(value, start) {
+            start = range.end;
+            matches.push(range);
+        }
+        matches
+    }
+
+    /// Returns the first match in `value` that starts at or after `start`. Text before `start` still
+    /// counts when deciding whether a match is a whole word.
+    pub fn find_from(&self, value: &[u16], start: usize) -> Option<Range<usize>> {
+        if self.is_empty() || self.folded.len() > value.len() {
+            return None;
+        }
+        (start..=(value.len() - self.folded.len()))
+            .map(|match_start| match_start..(match_start + self.folded.len()))
+            .find(|range| {
+                value[range.clone()]
+                    .iter()
+                    .zip(self.folded.iter())
+                    .all(|(ch, pattern_ch)| fold_case_unit(*ch, &self.options) == *pattern_ch)
+                    && (!self.options.whole_word || is_whole_word(value, range.clone()))
+            })
+    }
+}
+
+/// Lower-cases a UTF-16 code unit, if it is a character whose lower case is a single code unit.
+/// Keeping one code unit per code unit means offsets into the folded text are offsets into the
+/// original text too.
+fn fold_case_unit(ch: u16, options: &SearchOptions) -> u16 {
+    if options.match_case {
+        return ch;
+    }
+    let c = match std::char::from_u32(ch as u32) {
+        Some(c) => c,
+        // A surrogate, which is never a letter on its own.
+        None => return ch,
+    };
+    let mut lower = c.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(lower), None) if (lower as u32) <= 0xFFFF => lower as u16,
+        _ => ch,
+    }
+}
+
+fn is_whole_word(value: &[u16], range: Range<usize>) -> bool {
+    let is_word_unit = |ch: u16| match std::char::from_u32(ch as u32) {
+        Some(c) => c.is_alphanumeric() || c == '_',
+        // Surrogates are part of characters outside the BMP. Treat them as word characters.
+        None => true,
+    };
+    let starts_word = range.start == 0 || !is_word_unit(value[range.start - 1]);
+    let ends_word = range.end == value.len() || !is_word_unit(value[range.end]);
+    starts_word && ends_word
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_u16(value: &str) -> Vec<u16> {
+        value.encode_utf16().collect()
+    }
+
+    #[test]
+    fn test_find_all() {
+        let value = to_u16("The cat sat on the Cathedral's CAT. the");
+        let find = |pattern: &str, match_case: bool, whole_word: bool| {
+            SearchPattern::new(
+                &to_u16(pattern),
+                &SearchOptions::new(match_case, whole_word),
+            )
+            .find_all(&value)
+        };
+        assert_eq!(find("cat", false, false), vec![4..7, 19..22, 31..34]);
+        assert_eq!(find("cat", true, false), vec![4..7]);
+        assert_eq!(find("cat", false, true), vec![4..7, 31..34]);
+        assert_eq!(find("the", false, true), vec![0..3, 15..18, 36..39]);
+        assert_eq!(find("", false, false), vec![]);
+        // Matches do not overlap.
+        let pattern = SearchPattern::new(&to_u16("aa"), &SearchOptions::default());
+        assert_eq!(pattern.find_all(&to_u16("aaaa")), vec![0..2, 2..4]);
+    }
+
+    #[test]
+    fn test_find_from() {
+        let value = to_u16("cat concatenate cat");
+        let pattern = SearchPattern::new(&to_u16("cat"), &SearchOptions::new(false, true));
+        assert_eq!(pattern.find_from(&value, 0), Some(0..3));
+        // The "cat" in "concatenate" is not a whole word, even though the search starts inside it.
+        assert_eq!(pattern.find_from(&value, 7), Some(16..19));
+        assert_eq!(pattern.find_from(&value, 17), None);
+    }
+}
@@ -0,0 +1,149 @@
+/// Sync as often as this while the document is being edited, in milliseconds.
+pub const MIN_SYNC_INTERVAL: f64 = 1000.0;
+
+/// Back off to syncing this rarely while nothing is happening, in milliseconds.
+pub const MAX_SYNC_INTERVAL: f64 = 30000.0;
+
+/// Decides how long to wait before the next sync.
+///
+/// Local edits are synced right away. While the document is idle, with no local edits and no new
+/// remote revisions, the interval doubles after each sync, up to `MAX_SYNC_INTERVAL`. Any activity,
+/// or the page becoming visible or focused again, snaps it back to `MIN_SYNC_INTERVAL`. While the
+/// browser is offline, there is no point in trying, so syncs wait for it to come back online.
+///
+/// The schedule only computes delays. The host owns the timers.
+pub struct SyncSchedule {
+    interval: f64,
+    visible: bool,
+    online: bool,
+}
+
+impl Default for SyncSchedule {
+    fn default() -> Self {
+        Self {
+            interval: MIN_SYNC_INTERVAL,
+            visible: true,
+            online: true,
+        }
+    }
+}
+
+impl SyncSchedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The delay before the next sync, given that the user just edited the document.
+    pub fn on_local_edit(&mut self) -> f64 {
+        self.interval = MIN_SYNC_INTERVAL;
+        if self.online {
+            0.0
+        } else {
+            MAX_SYNC_INTERVAL
+        }
+    }
+
+    /// The delay before the next sync, given that a sync just finished. `had_activity` is true if
+    /// it committed local changes or loaded remote revisions.
+    pub fn on_sync_finished(&mut self, had_activity: bool) -> f64 {
+        if had_activity {
+            self.interval = MIN_SYNC_INTERVAL;
+        } else {
+            self.interval = (self.interval * 2.0).min(MAX_SYNC_INTERVAL);
+        }
+        if !self.online {
+            MAX_SYNC_INTERVAL
+        } else if self.visible {
+            self.interval
+        } else {
+            // Nobody is looking, so there is no hurry to show remote changes.
+            MAX_SYNC_INTERVAL
+        }
+    }
+
+    /// Whether the page was visible, as of the last visibility change.
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// The delay before the next sync, given that the page became visible or hidden.
+    pub fn on_visibility_change(&mut self, visible: bool) -> f64 {
+        self.visible = visible;
+        if visible {
+            self.on_focus()
+        } else {
+            MAX_SYNC_INTERVAL
+        }
+    }
+
+    /// Whether the browser was online, as of the last connectivity change.
+    pub fn is_online(&self) -> bool {
+        self.online
+    }
+
+    /// The delay before the next sync, given that the browser went online or offline. Once back
+    /// online, sync right away, to push the changes made while offline.
+    pub fn on_connectivity_change(&mut self, online: bool) -> f64 {
+        self.online = online;
+        if online {
+            self.on_focus()
+        } else {
+            MAX_SYNC_INTERVAL
+        }
+    }
+
+    /// The delay before the next sync, given that the page regained focus. The user is likely
+    /// about to look at the document, so catch up right away.
+    pub fn on_focus(&mut self) -> f64 {
+        self.interval = MIN_SYNC_INTERVAL;
+        if self.online {
+            0.0
+        } else {
+            MAX_SYNC_INTERVAL
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backs_off_when_idle() {
+        let mut schedule = SyncSchedule::new();
+        assert_eq!(schedule.on_sync_finished(false), 2000.0);
+        assert_eq!(schedule.on_sync_finished(false), 4000.0);
+        for _ in 0..10 {
+            schedule.on_sync_finished(false);
+        }
+        assert_eq!(schedule.on_sync_finished(false), MAX_SYNC_INTERVAL);
+
+        // Remote revisions snap it back.
+        assert_eq!(schedule.on_sync_finished(true), MIN_SYNC_INTERVAL);
+
+        schedule.on_sync_finished(false);
+        assert_eq!(schedule.on_local_edit(), 0.0);
+        assert_eq!(schedule.on_sync_finished(false), 2000.0);
+    }
+
+    #[test]
+    fn test_visibility() {
+        let mut schedule = SyncSchedule::new();
+        assert_eq!(schedule.on_visibility_change(false), MAX_SYNC_INTERVAL);
+        assert_eq!(schedule.on_sync_finished(true), MAX_SYNC_INTERVAL);
+        assert_eq!(schedule.on_visibility_change(true), 0.0);
+        assert_eq!(schedule.on_sync_finished(false), 2000.0);
+    }
+
+    #[test]
+    fn test_connectivity() {
+        let mut schedule = SyncSchedule::new();
+        assert_eq!(schedule.on_connectivity_change(false), MAX_SYNC_INTERVAL);
+        assert!(!schedule.is_online());
+        assert_eq!(schedule.on_local_edit(), MAX_SYNC_INTERVAL);
+        assert_eq!(schedule.on_sync_finished(false), MAX_SYNC_INTERVAL);
+        assert_eq!(schedule.on_focus(), MAX_SYNC_INTERVAL);
+        assert_eq!(schedule.on_connectivity_change(true), 0.0);
+        assert_eq!(schedule.on_sync_finished(true), MIN_SYNC_INTERVAL);
+    }
+}
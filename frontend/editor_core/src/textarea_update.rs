@@ -0,0 +1,168 @@
+use ot::writing_proto::Selection;
+
+/// Replace `start..end` of the textarea's value with `text`, like `setRangeText`. Offsets are in
+/// UTF-16 code units, like the textarea's.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextSplice {
+    pub start: usize,
+    pub end: usize,
+    pub text: Vec<u16>,
+}
+
+/// How to bring a textarea up to date with the document, without replacing its whole value.
+/// Replacing the value resets the caret and the scroll position, while splicing in only what
+/// changed keeps both.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextareaUpdate {
+    /// `None` if the value is already up to date.
+    pub splice: Option<TextSplice>,
+    /// The selection to set after the splice, or `None` if the textarea already has it.
+    pub selection: Option<Selection>,
+}
+
+/// Computes the update from the textarea's value and selection to the document's value and
+/// selection, where `selection` is the textarea's selection transformed through the changes that
+/// the document's value has and the textarea's does not, like remote revisions.
+///
+/// The splice covers everything between the longest common prefix and suffix of the two values.
+/// Where that is ambiguous, like text inserted into a run of the same character, the browser may
+/// move the caret differently than the transform did, so the selection is always set after a
+/// splice.
+pub fn compute_textarea_update(
+    old_value: &[u16],
+    new_value: &[u16],
+    previous_selection: &Selection,
+    selection: &Selection,
+) -> TextareaUpdate {
+    let splice = compute_splice(old_value, new_value);
+    let selection = if splice.is_some() || selection != previous_selection {
+        Some(selection.clone())
+    } else {
+        None
+    };
+    TextareaUpdate { splice, selection }
+}
+
+fn compute_splice(old_value: &[u16], new_value: &[u16]) -> Option<TextSplice> {
+    if old_value == new_value {
+        return None;
+    }
+    let mut prefix = old_value
+        .iter()
+        .zip(new_value.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    // Never split a surrogate pair.
+    if prefix > 0 && is_high_surrogate(old_value[prefix - 1]) {
+        prefix -= 1;
+    }
+    let max_suffix = old_value.len().min(new_value.len()) - prefix;
+    let mut suffix = old_value
+        .iter()
+        .rev()
+        .zip(new_value.iter().rev())
+        .take(max_suffix)
+        .take_while(|(a, b)| a == b)
+        .count();
+    if suffix > 0 && is_low_surrogate(old_value[old_value.len() - suffix]) {
+        suffix -= 1;
+    }
+    Some(TextSplice {
+        start: prefix,
+        end: old_value.len() - suffix,
+        text: new_value[prefix..new_value.len() - suffix].to_vec(),
+    })
+}
+
+fn is_high_surrogate(code_unit: u16) -> bool {
+    (0xD800..=0xDBFF).contains(&code_unit)
+}
+
+fn is_low_surrogate(code_unit: u16) -> bool {
+    (0xDC00..=0xDFFF).contains(&code_unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    fn selection(offset: i64, count: i64) -> Selection {
+        Selection { offset, count }
+    }
+
+    fn splice(start: usize, end: usize, text: &str) -> Option<TextSplice> {
+        Some(TextSplice {
+            start,
+            end,
+            text: utf16(text),
+        })
+    }
+
+    #[test]
+    fn test_compute_splice() {
+        let cases = vec![
+            ("Hello world", "Hello world", None),
+            ("Hello world", "Hello, world", splice(5, 5, ",")),
+            ("Hello world", "Hello", splice(5, 11, "")),
+            ("Hello world", "Jello world", splice(0, 1, "J")),
+            ("Hello world", "Hello there", splice(6, 11, "there")),
+            ("aaa", "aaaa", splice(3, 3, "a")),
+            ("", "Hi", splice(0, 0, "Hi")),
+            // The emoji differ only in their low surrogates, and are replaced whole.
+            ("a\u{1F600}b", "a\u{1F601}b", splice(1, 3, "\u{1F601}")),
+            // The emoji differ only in their high surrogates, and are replaced whole.
+            ("a\u{1F600}b", "a\u{10600}b", splice(1, 3, "\u{10600}")),
+        ];
+        for (old_value, new_value, expected) in cases {
+            assert_eq!(
+                compute_splice(&utf16(old_value), &utf16(new_value)),
+                expected,
+                "{:?} -> {:?}",
+                old_value,
+                new_value
+            );
+        }
+    }
+
+    #[test]
+    fn test_compute_textarea_update() {
+        // A remote revision inserted text before the caret.
+        let update = compute_textarea_update(
+            &utf16("world"),
+            &utf16("Hello world"),
+            &selection(5, 0),
+            &selection(11, 0),
+        );
+        assert_eq!(update.splice, splice(0, 0, "Hello "));
+        assert_eq!(update.selection, Some(selection(11, 0)));
+
+        // Only the selection changed.
+        let update = compute_textarea_update(
+            &utf16("Hello"),
+            &utf16("Hello"),
+            &selection(5, 0),
+            &selection(0, 5),
+        );
+        assert_eq!(update.splice, None);
+        assert_eq!(update.selection, Some(selection(0, 5)));
+
+        // Already up to date.
+        let update = compute_textarea_update(
+            &utf16("Hello"),
+            &utf16("Hello"),
+            &selection(5, 0),
+            &selection(5, 0),
+        );
+        assert_eq!(
+            update,
+            TextareaUpdate {
+                splice: None,
+                selection: None,
+            }
+        );
+    }
+}
@@ -0,0 +1,116 @@
+/// How long the title must go unchanged before a local edit is saved, in milliseconds. Saving on
+/// every keystroke would send a request per character typed.
+pub const TITLE_SAVE_DEBOUNCE: f64 = 1000.0;
+
+/// The document's title as the editor shows it, kept in sync with the title on the server.
+///
+/// Local edits are saved once the title stops changing for `TITLE_SAVE_DEBOUNCE`. Titles that
+/// others set are picked up during sync, unless there is a local edit that is not saved yet, which
+/// wins.
+#[derive(Clone, Debug, Default)]
+pub struct TitleSync {
+    // `None` until the title is first loaded or set.
+    title: Option<String>,
+    // The last title that the server is known to have.
+    saved_title: Option<String>,
+    // When the title was last edited locally, in milliseconds since the epoch. `None` if there is
+    // no unsaved edit.
+    edited_at: Option<f64>,
+}
+
+impl TitleSync {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// Records a local edit to the title.
+    pub fn set_title(&mut self, title: &str, now: f64) {
+        self.title = Some(title.to_string());
+        self.edited_at = if self.saved_title.as_deref() == Some(title) {
+            None
+        } else {
+            Some(now)
+        };
+    }
+
+    /// Returns the title to save, if there is an unsaved edit and the title has not changed for
+    /// `TITLE_SAVE_DEBOUNCE`.
+    pub fn title_to_save(&self, now: f64) -> Option<&str> {
+        match self.edited_at {
+            Some(edited_at) if now - edited_at >= TITLE_SAVE_DEBOUNCE => self.title.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Records that the server saved `title`. If the title was edited again while it was being
+    /// saved, that edit is still unsaved.
+    pub fn on_saved(&mut self, title: &str) {
+        self.saved_title = Some(title.to_string());
+        if self.title.as_deref() == Some(title) {
+            self.edited_at = None;
+        }
+    }
+
+    /// Records the title that the server has. Returns true if the title shown changed, which it
+    /// does unless there is an unsaved local edit.
+    pub fn on_remote_title(&mut self, title: &str) -> bool {
+        self.saved_title = Some(title.to_string());
+        if self.edited_at.is_some() || self.title.as_deref() == Some(title) {
+            return false;
+        }
+        self.title = Some(title.to_string());
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debounce() {
+        let mut title_sync = TitleSync::new();
+        assert!(title_sync.on_remote_title("Draft"));
+        assert_eq!(title_sync.title(), Some("Draft"));
+        assert_eq!(title_sync.title_to_save(0.0), None);
+
+        title_sync.set_title("Final", 0.0);
+        title_sync.set_title("Final draft", 500.0);
+        assert_eq!(title_sync.title_to_save(1000.0), None);
+        assert_eq!(title_sync.title_to_save(1500.0), Some("Final draft"));
+
+        title_sync.on_saved("Final draft");
+        assert_eq!(title_sync.title_to_save(5000.0), None);
+
+        // Setting the title back to the saved one leaves nothing to save.
+        title_sync.set_title("Final draf", 6000.0);
+        title_sync.set_title("Final draft", 6100.0);
+        assert_eq!(title_sync.title_to_save(8000.0), None);
+    }
+
+    #[test]
+    fn test_remote_title() {
+        let mut title_sync = TitleSync::new();
+        title_sync.on_remote_title("Draft");
+
+        // Someone else renamed the document.
+        assert!(title_sync.on_remote_title("Plan"));
+        assert_eq!(title_sync.title(), Some("Plan"));
+        assert!(!title_sync.on_remote_title("Plan"));
+
+        // An unsaved local edit wins over the remote title.
+        title_sync.set_title("Our plan", 0.0);
+        assert!(!title_sync.on_remote_title("Their plan"));
+        assert_eq!(title_sync.title(), Some("Our plan"));
+        assert_eq!(title_sync.title_to_save(1000.0), Some("Our plan"));
+
+        // Edits made while a save is in flight stay unsaved.
+        title_sync.set_title("Our plan!", 1100.0);
+        title_sync.on_saved("Our plan");
+        assert_eq!(title_sync.title_to_save(2100.0), Some("Our plan!"));
+    }
+}
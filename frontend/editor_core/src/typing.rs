@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+/// While the user types, send a typing heartbeat at most this often, in milliseconds.
+pub const TYPING_HEARTBEAT_INTERVAL: f64 = 2000.0;
+
+/// Forget someone who is typing after this long without a heartbeat from them, in milliseconds.
+/// Long enough to ride out a late heartbeat or two.
+pub const TYPING_EXPIRY: f64 = 5000.0;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Typer {
+    pub user_id: String,
+    pub display_name: String,
+}
+
+/// Tracks who else is typing in the document, and when to tell them that we are.
+///
+/// Like `SyncSchedule`, it only computes. The host passes in the time, and sends the heartbeats.
+#[derive(Default)]
+pub struct TypingIndicator {
+    last_heartbeat_sent_at: Option<f64>,
+    // By user id. The time of each typer's last heartbeat, in milliseconds.
+    typers: HashMap<String, (Typer, f64)>,
+}
+
+impl TypingIndicator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true if the user's edit at `now` should be followed by a heartbeat, and if so,
+    /// counts the heartbeat as sent.
+    pub fn on_local_edit(&mut self, now: f64) -> bool {
+        match self.last_heartbeat_sent_at {
+            Some(sent_at) if now - sent_at < TYPING_HEARTBEAT_INTERVAL => false,
+            _ => {
+                self.last_heartbeat_sent_at = Some(now);
+                true
+            }
+        }
+    }
+
+    /// Records a heartbeat from someone else.
+    pub fn on_typing(&mut self, user_id: &str, display_name: &str, now: f64) {
+        let typer = Typer {
+            user_id: user_id.to_string(),
+            display_name: display_name.to_string(),
+        };
+        self.typers.insert(user_id.to_string(), (typer, now));
+    }
+
+    /// The people who have sent a heartbeat within the last `TYPING_EXPIRY` milliseconds, sorted
+    /// by name. Forgets the rest.
+    pub fn active_typers(&mut self, now: f64) -> Vec<Typer> {
+        self.typers
+            .retain(|_, (_, last_seen_at)| now - *last_seen_at < TYPING_EXPIRY);
+        let mut typers = self
+            .typers
+            .values()
+            .map(|(typer, _)| typer.clone())
+            .collect::<Vec<_>>();
+        typers.sort_by(|a, b| (&a.display_name, &a.user_id).cmp(&(&b.display_name, &b.user_id)));
+        typers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heartbeats_are_throttled() {
+        let mut typing_indicator = TypingIndicator::new();
+        assert!(typing_indicator.on_local_edit(1000.0));
+        assert!(!typing_indicator.on_local_edit(1500.0));
+        assert!(!typing_indicator.on_local_edit(2999.0));
+        assert!(typing_indicator.on_local_edit(3000.0));
+    }
+
+    #[test]
+    fn test_typers_expire() {
+        let mut typing_indicator = TypingIndicator::new();
+        typing_indicator.on_typing("u_2", "Bea", 0.0);
+        typing_indicator.on_typing("u_1", "Al", 1000.0);
+        let names = |typers: Vec<Typer>| -> Vec<String> {
+            typers.into_iter().map(|t| t.display_name).collect()
+        };
+        assert_eq!(
+            names(typing_indicator.active_typers(2000.0)),
+            vec!["Al", "Bea"]
+        );
+
+        // Bea stops typing. Al keeps going.
+        typing_indicator.on_typing("u_1", "Al", 4000.0);
+        assert_eq!(names(typing_indicator.active_typers(5000.0)), vec!["Al"]);
+        assert_eq!(
+            names(typing_indicator.active_typers(9000.0)),
+            Vec::<String>::new()
+        );
+    }
+}
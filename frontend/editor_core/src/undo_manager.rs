@@ -0,0 +1,284 @@
+use std::collections::VecDeque;
+
+use ot::writing_proto::{ChangeSet, Selection};
+use ot::OtError;
+
+use crate::get_change_set_description;
+
+const MAX_UNDO_HISTORY_LENGTH: usize = 10_000;
+
+#[derive(Default)]
+pub struct UndoManager {
+    undo_stack: VecDeque<UndoItem>,
+    redo_stack: VecDeque<UndoItem>,
+    policy: UndoPolicy,
+}
+
+/// How undo items that remote changes have emptied are treated. Once a collaborator deletes all
+/// the text that an undo item would remove or put back, the item transforms into an identity, and
+/// undoing it only moves the selection. By default such items stay on the stacks, so that every
+/// undo step the user took is still there to step through.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct UndoPolicy {
+    /// Undo and redo pop past items with nothing left to undo, so that each one changes the
+    /// document, if anything on the stack still does.
+    pub skip_empty_undos: bool,
+    /// Items are dropped from the stacks as soon as a remote change leaves them with nothing to
+    /// undo.
+    pub collapse_identity_items: bool,
+}
+
+pub struct UndoItem {
+    pub change_set: ChangeSet,
+    pub selection_after: Selection,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UndoType {
+    Undo,
+    Redo,
+}
+
+impl UndoManager {
+    pub fn new() -> Self {
+        Self::with_policy(UndoPolicy::default())
+    }
+
+    pub fn with_policy(policy: UndoPolicy) -> Self {
+        Self {
+            undo_stack: VecDeque::new(),
+            redo_stack: VecDeque::new(),
+            policy,
+        }
+    }
+
+    pub fn policy(&self) -> UndoPolicy {
+        self.policy
+    }
+
+    /// Applies to items already on the stacks too, the next time a remote change transforms them.
+    pub fn set_policy(&mut self, policy: UndoPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn push(&mut self, undo_type: UndoType, undo_item: UndoItem) {
+        let stack = match undo_type {
+            UndoType::Undo => &mut self.undo_stack,
+            UndoType::Redo => &mut self.redo_stack,
+        };
+        stack.push_back(undo_item);
+        if undo_type == UndoType::Undo && stack.len() > MAX_UNDO_HISTORY_LENGTH {
+            stack.pop_front();
+        }
+    }
+
+    pub fn pop(&mut self, undo_type: UndoType) -> Option<UndoItem> {
+        match undo_type {
+            UndoType::Undo => self.undo_stack.pop_back(),
+            UndoType::Redo => self.redo_stack.pop_back(),
+        }
+    }
+
+    /// Pops the item that an undo or redo command applies. Unlike `pop`, which gets the top item
+    /// to extend it, this drops the items with nothing left to undo on the way under
+    /// `skip_empty_undos`.
+    pub fn pop_for_command(&mut self, undo_type: UndoType) -> Option<UndoItem> {
+        loop {
+            let undo_item = self.pop(undo_type)?;
+            if !self.policy.skip_empty_undos || !is_empty_undo_item(&undo_item) {
+                return Some(undo_item);
+            }
+        }
+    }
+
+    /// The number of items on the stack.
+    pub fn len(&self, undo_type: UndoType) -> usize {
+        match undo_type {
+            UndoType::Undo => self.undo_stack.len(),
+            UndoType::Redo => self.redo_stack.len(),
+        }
+    }
+
+    pub fn transform(&mut self, remote: &ChangeSet) -> Result<(), OtError> {
+        Self::transform_stack(&mut self.undo_stack, remote)?;
+        Self::transform_stack(&mut self.redo_stack, remote)?;
+        if self.policy.collapse_identity_items {
+            self.undo_stack
+                .retain(|undo_item| !is_empty_undo_item(undo_item));
+            self.redo_stack
+                .retain(|undo_item| !is_empty_undo_item(undo_item));
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self, undo_type: UndoType) {
+        match undo_type {
+            UndoType::Undo => self.undo_stack.clear(),
+            UndoType::Redo => self.redo_stack.clear(),
+        }
+    }
+
+    fn transform_stack(stack: &mut VecDeque<UndoItem>, remote: &ChangeSet) -> Result<(), OtError> {
+        let mut remote = remote.clone();
+        for undo_item in stack.iter_mut().rev() {
+            let (transformed_undo, transformed_remote) =
+                ot::transform(&undo_item.change_set, &remote)?;
+            let transformed_selection_after =
+                ot::transform_selection(&undo_item.selection_after, &remote)?;
+            undo_item.change_set = transformed_undo;
+            undo_item.selection_after = transformed_selection_after;
+            remote = transformed_remote;
+        }
+        Ok(())
+    }
+
+    pub fn get_debug_lines(&self) -> Vec<String> {
+        let mut ret = vec!["Undo Stack (top->bottom)".to_string()];
+        for undo_item in self.undo_stack.iter().rev() {
+            ret.push(format!(
+                "{}, {:?}",
+                &get_change_set_description(&undo_item.change_set),
+                &undo_item.selection_after,
+            ));
+        }
+        ret.push("Redo Stack (top->bottom)".to_string());
+        for undo_item in self.redo_stack.iter().rev() {
+            ret.push(format!(
+                "{}, {:?}",
+                &get_change_set_description(&undo_item.change_set),
+                &undo_item.selection_after,
+            ));
+        }
+        ret
+    }
+}
+
+fn is_empty_undo_item(undo_item: &UndoItem) -> bool {
+    ot::is_identity(&undo_item.change_set, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn undo_item(document: &str, change_set: ChangeSet) -> UndoItem {
+        UndoItem {
+            change_set: ot::invert(document, &change_set).unwrap(),
+            selection_after: Selection {
+                offset: 0,
+                count: 0,
+            },
+        }
+    }
+
+    fn insert_at(document_len: i64, offset: i64, text: &str) -> ChangeSet {
+        let mut change_set = ChangeSet::new();
+        change_set.retain(offset);
+        change_set.insert(text);
+        change_set.retain(document_len - offset);
+        change_set
+    }
+
+    fn delete_range(document_len: i64, offset: i64, count: i64) -> ChangeSet {
+        let mut change_set = ChangeSet::new();
+        change_set.retain(offset);
+        change_set.delete(count);
+        change_set.retain(document_len - offset - count);
+        change_set
+    }
+
+    fn undo(undo_manager: &mut UndoManager, document: &str) -> Option<String> {
+        let undo_item = undo_manager.pop_for_command(UndoType::Undo)?;
+        Some(ot::apply(document, &undo_item.change_set).unwrap())
+    }
+
+    #[test]
+    fn test_remote_delete_under_undo() {
+        // Undoing an insert that a collaborator has since partly deleted removes what is left of
+        // it.
+        let mut undo_manager = UndoManager::new();
+        undo_manager.push(UndoType::Undo, undo_item("Hi!", insert_at(3, 2, " there")));
+        let remote = delete_range(9, 2, 4);
+        undo_manager.transform(&remote).unwrap();
+        assert_eq!(undo(&mut undo_manager, "Hire!").unwrap(), "Hi!");
+
+        // Undoing a delete whose surroundings a collaborator has since deleted puts the deleted
+        // text back, where the surroundings were.
+        let mut undo_manager = UndoManager::new();
+        undo_manager.push(
+            UndoType::Undo,
+            undo_item("Hi there!", delete_range(9, 3, 2)),
+        );
+        let remote = delete_range(7, 2, 4);
+        undo_manager.transform(&remote).unwrap();
+        assert_eq!(undo(&mut undo_manager, "Hi!").unwrap(), "Hith!");
+    }
+
+    #[test]
+    fn test_default_policy_keeps_empty_undo_items() {
+        let mut undo_manager = UndoManager::new();
+        undo_manager.push(UndoType::Undo, undo_item("Hi", insert_at(2, 2, " there")));
+        undo_manager.push(UndoType::Undo, undo_item("Hi there", insert_at(8, 8, "!")));
+        // A collaborator deletes the exclamation mark.
+        undo_manager.transform(&delete_range(9, 8, 1)).unwrap();
+        assert_eq!(undo_manager.len(UndoType::Undo), 2);
+
+        // Undoing it changes nothing. The next undo removes " there".
+        assert_eq!(undo(&mut undo_manager, "Hi there").unwrap(), "Hi there");
+        assert_eq!(undo(&mut undo_manager, "Hi there").unwrap(), "Hi");
+    }
+
+    #[test]
+    fn test_skip_empty_undos() {
+        let mut undo_manager = UndoManager::with_policy(UndoPolicy {
+            skip_empty_undos: true,
+            collapse_identity_items: false,
+        });
+        undo_manager.push(UndoType::Undo, undo_item("Hi", insert_at(2, 2, " there")));
+        undo_manager.push(UndoType::Undo, undo_item("Hi there", insert_at(8, 8, "!")));
+        undo_manager.transform(&delete_range(9, 8, 1)).unwrap();
+        // The emptied item stays on the stack until an undo reaches it.
+        assert_eq!(undo_manager.len(UndoType::Undo), 2);
+        assert_eq!(undo(&mut undo_manager, "Hi there").unwrap(), "Hi");
+        assert_eq!(undo_manager.len(UndoType::Undo), 0);
+
+        // An undo that only finds emptied items finds nothing.
+        undo_manager.push(UndoType::Undo, undo_item("Hi", insert_at(2, 2, "!")));
+        undo_manager.transform(&delete_range(3, 2, 1)).unwrap();
+        assert!(undo(&mut undo_manager, "Hi").is_none());
+
+        // Popping the top item to extend it does not skip anything.
+        undo_manager.push(UndoType::Undo, undo_item("Hi", insert_at(2, 2, "!")));
+        undo_manager.transform(&delete_range(3, 2, 1)).unwrap();
+        assert!(undo_manager.pop(UndoType::Undo).is_some());
+    }
+
+    #[test]
+    fn test_collapse_identity_items() {
+        let mut undo_manager = UndoManager::with_policy(UndoPolicy {
+            skip_empty_undos: false,
+            collapse_identity_items: true,
+        });
+        undo_manager.push(UndoType::Undo, undo_item("Hi", insert_at(2, 2, " there")));
+        undo_manager.push(UndoType::Undo, undo_item("Hi there", insert_at(8, 8, "!")));
+        undo_manager.push(
+            UndoType::Redo,
+            undo_item("Hi there!?", delete_range(10, 9, 1)),
+        );
+        // A collaborator deletes " there!". Both undo items are emptied. The redo item, which
+        // puts back a "?", is not.
+        undo_manager.transform(&delete_range(9, 2, 7)).unwrap();
+        assert_eq!(undo_manager.len(UndoType::Undo), 0);
+        assert_eq!(undo_manager.len(UndoType::Redo), 1);
+
+        // Items that are only partly undone by the remote change stay.
+        let mut undo_manager = UndoManager::with_policy(UndoPolicy {
+            skip_empty_undos: false,
+            collapse_identity_items: true,
+        });
+        undo_manager.push(UndoType::Undo, undo_item("Hi", insert_at(2, 2, " there")));
+        undo_manager.transform(&delete_range(8, 2, 1)).unwrap();
+        assert_eq!(undo_manager.len(UndoType::Undo), 1);
+        assert_eq!(undo(&mut undo_manager, "Hithere").unwrap(), "Hi");
+    }
+}
@@ -0,0 +1,21 @@
+// Composes large batches of remote revisions off the main thread for the document editor. See
+// wasm/src/ot_worker.rs for the messages it answers.
+//
+// Workers cannot load the bundled wasm package, so this loads its own copy of the module, built
+// for workers into public/ot-worker by `npm run wasm-worker`.
+
+importScripts('ot-worker/index.js');
+
+const ready = wasm_bindgen('ot-worker/index_bg.wasm');
+
+self.addEventListener('message', async (event) => {
+  try {
+    await ready;
+  } catch (e) {
+    // The editor composes the change sets itself instead.
+    self.postMessage({ id: event.data.id, error: `Error loading wasm: ${e}` });
+    return;
+  }
+  const response = wasm_bindgen.OtWorkerTasks.handleMessage(event.data);
+  self.postMessage(response, response.changeSet ? [response.changeSet.buffer] : []);
+});
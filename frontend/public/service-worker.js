@@ -0,0 +1,57 @@
+// Keeps the app shell available offline, and wakes open editors up to push the changes they
+// queued while offline once connectivity returns.
+//
+// The shell is cached as it is fetched, network first, so a deploy is picked up on the next load
+// while online. API requests are never cached: editors queue their changes instead.
+
+const SHELL_CACHE = 'writing-shell-v1';
+const SYNC_TAG = 'sync-queued-changes';
+
+self.addEventListener('install', () => {
+  self.skipWaiting();
+});
+
+self.addEventListener('activate', (event) => {
+  event.waitUntil((async () => {
+    const names = await caches.keys();
+    await Promise.all(
+      names.filter((name) => name !== SHELL_CACHE).map((name) => caches.delete(name)));
+    await self.clients.claim();
+  })());
+});
+
+self.addEventListener('fetch', (event) => {
+  const url = new URL(event.request.url);
+  if (event.request.method !== 'GET' ||
+      url.origin !== self.location.origin ||
+      url.pathname.startsWith('/api/')) {
+    return;
+  }
+  // Every route of the single page app is served by index.html, so all navigations share one
+  // cache entry.
+  const cacheKey = event.request.mode === 'navigate' ? '/' : event.request;
+  event.respondWith((async () => {
+    const cache = await caches.open(SHELL_CACHE);
+    try {
+      const response = await fetch(event.request);
+      if (response.ok) {
+        cache.put(cacheKey, response.clone());
+      }
+      return response;
+    } catch (e) {
+      const cached = await cache.match(cacheKey);
+      if (cached) return cached;
+      throw e;
+    }
+  })());
+});
+
+// Registered by editors that went offline with queued changes. Fires once the browser thinks it
+// is back online, possibly before the page has heard about it.
+self.addEventListener('sync', (event) => {
+  if (event.tag !== SYNC_TAG) return;
+  event.waitUntil((async () => {
+    const clients = await self.clients.matchAll({ type: 'window' });
+    clients.forEach((client) => client.postMessage({ type: SYNC_TAG }));
+  })());
+});
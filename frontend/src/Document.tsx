@@ -1,14 +1,16 @@
 import React from 'react';
-import { useParams } from 'react-router-dom';
+import { useLocation, useParams } from 'react-router-dom';
 import DocumentEditor from './DocumentEditor';
 import './Document.css';
 
 function Document() {
   let { id } = useParams() as any;
+  // Present when the document was opened through a share link.
+  const shareToken = new URLSearchParams(useLocation().search).get('share_token') || undefined;
 
   return (
     <div className="Document">
-      <DocumentEditor docId={id} />
+      <DocumentEditor docId={id} shareToken={shareToken} />
     </div>
   );
 }
@@ -6,51 +6,179 @@ import React, {
 import './DocumentEditor.css';
 import DocumentValueChunk from './DocumentValueChunk';
 import { importWasm } from './importWasm';
+import { loadQueuedChanges, saveQueuedChanges } from './utils/offlineQueue';
 import { logPerformance } from './utils/performance';
 
 const DEBUG_LOGGING = false;
+const TELEMETRY_LOGGING = false;
 const Z_KEY_CODE = 90;
+// How often to check who else is typing, and where. People drop off the list a few seconds after
+// they stop.
+const TYPERS_POLL_INTERVAL_MILLIS = 1000;
+// How often the owner's view of who has read the document is refreshed.
+const READ_RECEIPTS_POLL_INTERVAL_MILLIS = 30000;
+// The background sync tag that public/service-worker.js listens for.
+const SYNC_QUEUED_CHANGES_TAG = 'sync-queued-changes';
 
 function DocumentEditor(props: any) {
   const { InputEventParams, DocumentEditorModel, JsBackendApi, JsSelection } = importWasm();
 
   const textAreaElem: any = useRef(null);
+  // Set while a large paste is applied piece by piece. The textarea already shows all of it, so it
+  // is not updated from the model until the paste is done.
+  const pasting = useRef(false);
   const [title, setTitle] = useState('Untitled Document');
   const [loaded, setLoaded] = useState(false);
+  const [updateRequired, setUpdateRequired] = useState(false);
+  const [protectedRangeRejected, setProtectedRangeRejected] = useState(false);
+  const [rejectedChangesStatus, setRejectedChangesStatus] = useState<number | null>(null);
+  const [authRequired, setAuthRequired] = useState(false);
   const [documentEditorModel, _] = useState(() => {
-    return DocumentEditorModel.new(props.docId);
+    return DocumentEditorModel.new(props.docId, props.shareToken);
   });
   const [chunkMetas, setChunkMetas] = useState<Array<any>>([]);
   const [debugSelection, setDebugSelection] = useState(JsSelection.new(0, 0));
   const [debugLines, setDebugLines] = useState(new Array<string>());
+  const [typers, setTypers] = useState<Array<any>>([]);
+  const [regionLocks, setRegionLocks] = useState<Array<any>>([]);
+  const [readReceipts, setReadReceipts] = useState<any>(null);
+  const [pasteProgress, setPasteProgress] = useState<any>(null);
+  const [offlineStatus, setOfflineStatus] = useState<any>({ offline: false, queued_changes: 0 });
+  // Changes made offline, and saved before the page closed, must be handed to the model before it
+  // first syncs. Every sync waits for this.
+  const [queuedChangesRestored] = useState(() => {
+    return loadQueuedChanges(props.docId)
+      .then((queuedChanges) => {
+        if (queuedChanges) documentEditorModel.restoreQueuedChanges(queuedChanges);
+      })
+      .catch((e) => console.error('Error loading queued changes:', e));
+  });
 
   // Load the document metadata, sync contents.
   useEffect(() => {
-    if (loaded) return;
+    if (loaded || updateRequired) return;
     async function loadDocument() {
       try {
-        const getDocumentPromise = JsBackendApi.getDocument(props.docId);
-        const syncPromise = documentEditorModel.sync();
+        const getDocumentPromise = JsBackendApi.getDocument(props.docId, props.shareToken);
+        const syncPromise = queuedChangesRestored.then(() => documentEditorModel.sync());
         const [getDocumentResponse, _] = await Promise.all([getDocumentPromise, syncPromise]);
-        setTitle(getDocumentResponse.document.title);
+        setTitle(documentEditorModel.getTitle() ?? getDocumentResponse.document.title);
         setLoaded(true);
         syncModelToView();
       } catch (e: any) {
         console.error(e);
+        setUpdateRequired(JsBackendApi.isUpdateRequired());
       }
     }
     loadDocument();
   });
 
-  // Periodically run sync.
+  // Let the model decide when to sync. It syncs right after local edits, and backs off while
+  // nothing changes.
+  useEffect(() => {
+    let timeoutId: any = null;
+    documentEditorModel.setSyncScheduler((delayMillis: number) => {
+      clearTimeout(timeoutId);
+      timeoutId = setTimeout(sync, delayMillis);
+    });
+    function onVisibilityChange() {
+      documentEditorModel.onVisibilityChange(document.visibilityState === 'visible');
+    }
+    function onFocus() {
+      documentEditorModel.onFocus();
+    }
+    // The session expired. Syncing pauses, keeping local changes, until the user signs in again.
+    documentEditorModel.setAuthRequiredHandler(() => setAuthRequired(true));
+    document.addEventListener('visibilitychange', onVisibilityChange);
+    window.addEventListener('focus', onFocus);
+    return function () {
+      documentEditorModel.setSyncScheduler(() => {});
+      documentEditorModel.setAuthRequiredHandler(() => {});
+      clearTimeout(timeoutId);
+      document.removeEventListener('visibilitychange', onVisibilityChange);
+      window.removeEventListener('focus', onFocus);
+    };
+  }, [documentEditorModel]);
+
+  // Stop syncing while offline, and let the service worker wake us up when connectivity returns,
+  // in case the browser tells it first.
+  useEffect(() => {
+    function onConnectivityChange() {
+      documentEditorModel.onConnectivityChange(navigator.onLine);
+      if (!navigator.onLine) registerBackgroundSync();
+    }
+    function onServiceWorkerMessage(event: any) {
+      if (event.data && event.data.type === SYNC_QUEUED_CHANGES_TAG) {
+        documentEditorModel.onConnectivityChange(true);
+      }
+    }
+    onConnectivityChange();
+    window.addEventListener('online', onConnectivityChange);
+    window.addEventListener('offline', onConnectivityChange);
+    navigator.serviceWorker?.addEventListener('message', onServiceWorkerMessage);
+    return function () {
+      window.removeEventListener('online', onConnectivityChange);
+      window.removeEventListener('offline', onConnectivityChange);
+      navigator.serviceWorker?.removeEventListener('message', onServiceWorkerMessage);
+    };
+  }, [documentEditorModel]);
+
+  // Hear about others' revisions and typing as they happen.
   useEffect(() => {
-    let intervalId = setInterval(() => {
-      sync();
-    }, 1000);
+    documentEditorModel.subscribeToEvents();
+    const intervalId = setInterval(() => {
+      setTypers(documentEditorModel.activeTypers());
+      setRegionLocks(documentEditorModel.activeRegionLocks());
+      setOfflineStatus(documentEditorModel.offlineStatus());
+    }, TYPERS_POLL_INTERVAL_MILLIS);
     return function () {
       clearInterval(intervalId);
+      documentEditorModel.unsubscribeFromEvents();
     };
-  });
+  }, [documentEditorModel]);
+
+  // Show the owner who has read the latest version. Only the owner can list read receipts, so the
+  // request fails for everyone else, and they see nothing.
+  useEffect(() => {
+    if (props.shareToken) return;
+    let stopped = false;
+    async function loadReadReceipts() {
+      try {
+        const response = await JsBackendApi.listReadReceipts(props.docId);
+        if (!stopped) setReadReceipts(response);
+      } catch (e: any) {
+        stopped = true;
+        clearInterval(intervalId);
+      }
+    }
+    loadReadReceipts();
+    const intervalId = setInterval(loadReadReceipts, READ_RECEIPTS_POLL_INTERVAL_MILLIS);
+    return function () {
+      stopped = true;
+      clearInterval(intervalId);
+    };
+  }, [props.docId, props.shareToken]);
+
+  // Compose large batches of others' revisions off the main thread, so that catching up does not
+  // freeze typing. See public/ot-worker.js.
+  useEffect(() => {
+    if (typeof Worker === 'undefined') return;
+    const worker = new Worker(`${process.env.PUBLIC_URL}/ot-worker.js`);
+    documentEditorModel.setOtWorker(worker);
+    return function () {
+      worker.terminate();
+    };
+  }, [documentEditorModel]);
+
+  // Sync and conflict statistics, for tuning how long typing composes into one revision and how
+  // often we sync.
+  useEffect(() => {
+    documentEditorModel.setTelemetryCallback((event: any) => {
+      if (TELEMETRY_LOGGING) {
+        console.log('[telemetry]', event);
+      }
+    });
+  }, [documentEditorModel]);
 
   function captureSelection(event: any) {
     const newSelection = JsSelection.new(
@@ -61,6 +189,14 @@ function DocumentEditor(props: any) {
     setDebugSelection(documentEditorModel.getSelection());
   }
 
+  // Tells the model which text is at the top of the viewport, so that it can keep it there when
+  // collaborators edit above it.
+  function onScroll(event: any) {
+    const textArea = event.target;
+    const firstVisibleLine = Math.floor(textArea.scrollTop / lineHeight(textArea));
+    documentEditorModel.setScrollAnchor(lineStartOffset(textArea.value, firstVisibleLine));
+  }
+
   function onKeyDown(event: any) {
     captureSelection(event);
 
@@ -99,7 +235,28 @@ function DocumentEditor(props: any) {
       JsSelection.new(event.target.selectionStart, event.target.selectionEnd)
     );
 
-    updateFromInputEvent(inputEventParams);
+    if (inputType === 'insertFromPaste' || inputType === 'insertFromDrop') {
+      pasteProgressively(inputEventParams);
+    } else {
+      updateFromInputEvent(inputEventParams);
+    }
+  }
+
+  // Large pastes are applied to the model in pieces, so that the page keeps responding. Input
+  // must wait until the paste is done.
+  function pasteProgressively(inputEventParams: any) {
+    pasting.current = true;
+    textAreaElem.current.readOnly = true;
+    const onProgress = (inserted: number, total: number) => setPasteProgress({ inserted, total });
+    documentEditorModel.updateFromInputEventProgressively(inputEventParams, onProgress)
+      .catch((e: any) => console.error('Error pasting:', e))
+      .finally(() => {
+        pasting.current = false;
+        if (textAreaElem.current) textAreaElem.current.readOnly = false;
+        setPasteProgress(null);
+        syncModelToView();
+        saveQueue();
+      });
   }
 
   function updateFromInputEvent(inputEventParams: any) {
@@ -107,20 +264,39 @@ function DocumentEditor(props: any) {
       documentEditorModel.updateFromInputEvent(inputEventParams);
     });
     syncModelToView();
+    saveQueue();
+  }
+
+  // Saves the changes that have not been committed yet, so that they survive closing the page
+  // while offline.
+  function saveQueue() {
+    queuedChangesRestored
+      .then(() => saveQueuedChanges(props.docId, documentEditorModel.exportQueuedChanges()))
+      .catch((e) => console.error('Error saving queued changes:', e));
   }
 
   function syncModelToView() {
-    if (!textAreaElem.current) return;
-    const value = logPerformance('getValue', () => documentEditorModel.getValue());
-    if (textAreaElem.current.value !== value) {
-      textAreaElem.current.value = value;
+    if (!textAreaElem.current || pasting.current) return;
+    // Splice in only what changed, so that the caret and scroll position stay put when remote
+    // changes arrive.
+    const textArea = textAreaElem.current;
+    const update = logPerformance('computeTextareaUpdate', () =>
+      documentEditorModel.computeTextareaUpdate(
+        textArea.value,
+        JsSelection.new(textArea.selectionStart, textArea.selectionEnd)
+      ));
+    if (update.splice) {
+      textArea.setRangeText(update.splice.text, update.splice.start, update.splice.end, 'preserve');
     }
-    const selection = documentEditorModel.getSelection();
-    if (textAreaElem.current.selectionStart !== selection.start ||
-        textAreaElem.current.selectionEnd !== selection.end) {
-      textAreaElem.current.selectionStart = selection.start;
-      textAreaElem.current.selectionEnd = selection.end;
+    // Scroll past the lines that remote changes added or removed above the viewport.
+    const scrollAnchor = documentEditorModel.takeScrollAnchor(lineHeight(textArea));
+    if (scrollAnchor && scrollAnchor.pixel_delta !== 0) {
+      textArea.scrollTop += scrollAnchor.pixel_delta;
+    }
+    if (update.selection) {
+      textArea.setSelectionRange(update.selection.start, update.selection.end);
     }
+    const selection = documentEditorModel.getSelection();
     setDebugSelection(selection);
     if (DEBUG_LOGGING) {
       setDebugLines(documentEditorModel.getDebugLines());
@@ -136,24 +312,92 @@ function DocumentEditor(props: any) {
     }
   }
 
+  // The model saves the title, debounced, during sync.
+  function onTitleChange(event: any) {
+    setTitle(event.target.value);
+    documentEditorModel.setTitle(event.target.value);
+  }
+
+  function onResumeAfterReauthentication() {
+    setAuthRequired(false);
+    documentEditorModel.resumeAfterReauthentication();
+  }
+
   async function sync() {
     try {
+      await queuedChangesRestored;
       await documentEditorModel.sync();
+      // The server rejected edits to text that only the owner may change, and the model undid
+      // them.
+      if (documentEditorModel.takeProtectedRangeRejections() > 0) {
+        setProtectedRangeRejected(true);
+      }
       syncModelToView();
+      // Someone else may have renamed the document.
+      const modelTitle = documentEditorModel.getTitle();
+      if (modelTitle != null) setTitle(modelTitle);
       if (DEBUG_LOGGING) {
         setDebugLines(documentEditorModel.getDebugLines());
       }
     } catch (e) {
       console.error("Error syncing with server:", e);
+      setUpdateRequired(JsBackendApi.isUpdateRequired());
+      // The server will never accept the local changes, and the model rolled them back.
+      const status = documentEditorModel.takeRejectedChangesStatus();
+      if (status != null) {
+        setRejectedChangesStatus(status);
+        syncModelToView();
+      }
     }
+    saveQueue();
   }
 
   return (
     <div className="DocumentEditor">
+      {updateRequired &&
+        <div className="DocumentEditor-updateRequired">
+          A new version of the editor is available. Reload the page to keep editing.
+        </div>
+      }
+      {protectedRangeRejected &&
+        <div className="DocumentEditor-protectedRangeRejected">
+          Some of your changes were undone, because only the owner of this document can edit that
+          part of it.
+          <button onClick={() => setProtectedRangeRejected(false)}>Dismiss</button>
+        </div>
+      }
+      {authRequired &&
+        <div className="DocumentEditor-authRequired">
+          Your session expired. Sign in again in another tab, then resume. Your changes are kept
+          until then.
+          <button onClick={onResumeAfterReauthentication}>Resume</button>
+        </div>
+      }
+      {rejectedChangesStatus != null &&
+        <div className="DocumentEditor-changesRejected">
+          {rejectedChangesMessage(rejectedChangesStatus)}
+          <button onClick={() => setRejectedChangesStatus(null)}>Dismiss</button>
+        </div>
+      }
+      {offlineStatus.offline &&
+        <div className="DocumentEditor-offline">
+          {offlineMessage(offlineStatus)}
+        </div>
+      }
+      {pasteProgress &&
+        <div className="DocumentEditor-pasteProgress">
+          Pasting... {Math.floor(100 * pasteProgress.inserted / pasteProgress.total)}%
+        </div>
+      }
       {!loaded ?
         <div>Loading...</div> :
         <div className="DocumentEditor-controls">
-          <h1>{title}</h1>
+          <input
+            className="DocumentEditor-title"
+            value={title}
+            onChange={onTitleChange}
+            readOnly={!!props.shareToken}
+          />
           <textarea
             ref={textAreaElem}
             className="DocumentEditor-text"
@@ -161,7 +405,17 @@ function DocumentEditor(props: any) {
             onSelect={captureSelection}
             onKeyDown={onKeyDown}
             onInput={onInput}
+            onScroll={onScroll}
           ></textarea>
+          <div className="DocumentEditor-typers">
+            {typingMessage(typers)}
+          </div>
+          <div className="DocumentEditor-regionLockWarning">
+            {regionLockWarning(regionLocks, debugSelection)}
+          </div>
+          <div className="DocumentEditor-readReceipts">
+            {readReceiptsMessage(readReceipts)}
+          </div>
           <div className="DocumentEditor-selection">
             { debugSelection.toString() }
           </div>
@@ -195,4 +449,81 @@ function DocumentEditor(props: any) {
   );
 }
 
+function offlineMessage(offlineStatus: any): string {
+  const count = offlineStatus.queued_changes;
+  if (count === 0) return 'Offline';
+  return `Offline. ${count} ${count === 1 ? 'change' : 'changes'} will sync when you reconnect.`;
+}
+
+function rejectedChangesMessage(status: number): string {
+  switch (status) {
+    case 403:
+      return 'Your recent changes were undone, because you can no longer edit this document.';
+    case 404:
+      return 'Your recent changes were undone, because this document was deleted.';
+    case 413:
+      return 'Your recent changes were undone, because they were too large to save at once.';
+    case 415:
+      return 'Your recent changes were undone, because this editor is out of date. Please reload.';
+    default:
+      return 'Your recent changes were undone, because the server could not accept them.';
+  }
+}
+
+// Asks the service worker to wake us up once the browser is back online. Not every browser
+// supports background sync. The `online` event covers the rest.
+function registerBackgroundSync() {
+  if (!('serviceWorker' in navigator)) return;
+  navigator.serviceWorker.ready
+    .then((registration: any) => registration.sync?.register(SYNC_QUEUED_CHANGES_TAG))
+    .catch((e) => console.error('Error registering background sync:', e));
+}
+
+function lineHeight(textArea: HTMLTextAreaElement): number {
+  const style = window.getComputedStyle(textArea);
+  // `line-height: normal` does not parse. Browsers render it at about 1.2 times the font size.
+  return parseFloat(style.lineHeight) || 1.2 * parseFloat(style.fontSize);
+}
+
+// The offset where the given line starts. Lines that wrap are not counted, so this is an estimate.
+function lineStartOffset(value: string, line: number): number {
+  let offset = 0;
+  for (let i = 0; i < line; i++) {
+    const next = value.indexOf('\n', offset);
+    if (next === -1) break;
+    offset = next + 1;
+  }
+  return offset;
+}
+
+function typingMessage(typers: Array<any>): string {
+  const names = typers.map((typer) => typer.display_name || 'Someone');
+  if (names.length === 0) return '';
+  if (names.length === 1) return `${names[0]} is typing...`;
+  if (names.length === 2) return `${names[0]} and ${names[1]} are typing...`;
+  return 'Several people are typing...';
+}
+
+// Warns, without stopping anyone, when the selection is in a paragraph that someone else is
+// editing, since editing it too is likely to conflict.
+function regionLockWarning(regionLocks: Array<any>, selection: any): string {
+  const lock = regionLocks.find((lock) =>
+    lock.start <= selection.end && selection.start <= lock.end);
+  if (!lock) return '';
+  return `${lock.display_name || 'Someone'} is editing this paragraph.`;
+}
+
+function readReceiptsMessage(readReceipts: any): string {
+  if (!readReceipts) return '';
+  const lastRevisionNumber = readReceipts.last_revision_number;
+  const names = readReceipts.read_receipts
+    .filter((receipt: any) => receipt.revision_number >= lastRevisionNumber)
+    .map((receipt: any) => receipt.display_name || 'Someone');
+  const behind = readReceipts.read_receipts.length - names.length;
+  const parts = [];
+  if (names.length > 0) parts.push(`Seen by ${names.join(', ')}`);
+  if (behind > 0) parts.push(`${behind} more saw an earlier version`);
+  return parts.join('. ');
+}
+
 export default DocumentEditor;
@@ -69,12 +69,23 @@ function NewDocumentControls(props: any) {
   }
 }
 
+// Matches the DocumentSharingPermission enum in document.proto.
+const CAN_EDIT = 2;
+const CAN_COMMENT = 3;
+
 function DocumentListItem(props: any) {
-  const { doc } = props;
+  const { doc, permission } = props;
   return (
     <div>
       <Link to={`/document/${doc.id}`}>{doc.title}</Link>
+      {permission === CAN_COMMENT && <span> (can comment)</span>}
+      {permission !== undefined && permission !== CAN_EDIT && permission !== CAN_COMMENT &&
+        <span> (view only)</span>}
       <span>- Last updated at {doc.updated_at}</span>
+      {doc.last_revision_number > 0 &&
+        <span className="DocumentList-itemStats">
+          {doc.word_count} words, revision {doc.last_revision_number}
+        </span>}
     </div>
   );
 }
@@ -84,6 +95,7 @@ function DocumentList() {
 
   const [loaded, setLoaded] = useState(false);
   const [documents, setDocuments] = useState([]);
+  const [permissions, setPermissions] = useState<{ [docId: string]: number }>({});
   const [nextUpdatedBefore, setNextUpdatedBefore] = useState<Date | null>(new Date());
 
   useEffect(() => {
@@ -96,6 +108,7 @@ function DocumentList() {
       let response = await JsBackendApi.listMyDocuments(nextUpdatedBefore);
       setLoaded(true);
       setDocuments(response.documents);
+      loadPermissions(response.documents);
       let nextDate = null;
       let responseDateStr = response.nextUpdatedBeforeDateTime
       if (responseDateStr && responseDateStr.length > 0) {
@@ -112,6 +125,19 @@ function DocumentList() {
     }
   }
 
+  async function loadPermissions(docs: any[]) {
+    try {
+      let response = await JsBackendApi.getMyPermissions(docs.map((doc: any) => doc.id));
+      let newPermissions: { [docId: string]: number } = {};
+      for (let p of response.permissions) {
+        newPermissions[p.doc_id] = p.permission;
+      }
+      setPermissions(newPermissions);
+    } catch (e: any) {
+      console.error('Error loading permissions:', e);
+    }
+  }
+
   return (
     <div className="DocumentList">
       <h1 className="DocumentList-header">Document List</h1>
@@ -120,7 +146,8 @@ function DocumentList() {
         <div className="DocumentList-list">
           <NewDocumentControls />
           {
-            documents.map((doc: any) => <DocumentListItem key={doc.id} doc={doc} />)
+            documents.map((doc: any) =>
+              <DocumentListItem key={doc.id} doc={doc} permission={permissions[doc.id]} />)
           }
           {
             nextUpdatedBefore &&
@@ -13,4 +13,14 @@ function main() {
   );
 };
 
+// Serves the app shell while offline. See public/service-worker.js.
+function registerServiceWorker() {
+  if (process.env.NODE_ENV !== 'production' || !('serviceWorker' in navigator)) return;
+  window.addEventListener('load', () => {
+    navigator.serviceWorker.register(`${process.env.PUBLIC_URL}/service-worker.js`)
+      .catch((e) => console.error('Error registering service worker:', e));
+  });
+}
+
+registerServiceWorker();
 initializeWasm().then(main);
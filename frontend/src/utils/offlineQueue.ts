@@ -0,0 +1,54 @@
+// Saves each document's queued changes, from `exportQueuedChanges`, in IndexedDB, so that changes
+// made offline survive closing the page.
+
+const DB_NAME = 'writing';
+const DB_VERSION = 1;
+const STORE_NAME = 'queuedChanges';
+
+let dbPromise: Promise<IDBDatabase> | null = null;
+
+function openDb(): Promise<IDBDatabase> {
+  if (!dbPromise) {
+    dbPromise = new Promise((resolve, reject) => {
+      const request = indexedDB.open(DB_NAME, DB_VERSION);
+      request.onupgradeneeded = () => {
+        request.result.createObjectStore(STORE_NAME);
+      };
+      request.onsuccess = () => resolve(request.result);
+      request.onerror = () => {
+        dbPromise = null;
+        reject(request.error);
+      };
+    });
+  }
+  return dbPromise;
+}
+
+function requestToPromise<T>(request: IDBRequest<T>): Promise<T> {
+  return new Promise((resolve, reject) => {
+    request.onsuccess = () => resolve(request.result);
+    request.onerror = () => reject(request.error);
+  });
+}
+
+async function loadQueuedChanges(docId: string): Promise<any> {
+  const db = await openDb();
+  const store = db.transaction(STORE_NAME, 'readonly').objectStore(STORE_NAME);
+  return (await requestToPromise(store.get(docId))) || null;
+}
+
+// Saves the queued changes, or forgets them if `queuedChanges` is null.
+async function saveQueuedChanges(docId: string, queuedChanges: any): Promise<void> {
+  const db = await openDb();
+  const store = db.transaction(STORE_NAME, 'readwrite').objectStore(STORE_NAME);
+  if (queuedChanges) {
+    await requestToPromise(store.put(queuedChanges, docId));
+  } else {
+    await requestToPromise(store.delete(docId));
+  }
+}
+
+export {
+  loadQueuedChanges,
+  saveQueuedChanges,
+}
@@ -1,3 +1,4 @@
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 
 use js_sys::{ArrayBuffer, Date, Promise, Uint8Array};
@@ -7,13 +8,24 @@ use wasm_bindgen::JsCast;
 use wasm_bindgen_futures::{future_to_promise, JsFuture};
 use web_sys::{Request, RequestInit, RequestMode, Response};
 
+use editor_core::latency::Stage;
+use ot::protocol::{
+    PROTOBUF_CONTENT_TYPE, PROTOCOL_VERSION, PROTOCOL_VERSION_HEADER, TRACE_ID_HEADER,
+};
 use ot::writing_proto::{
-    CreateDocumentRequest, CreateDocumentResponse, DocumentSharingPermission, GetDocumentRequest,
-    GetDocumentResponse, GetDocumentRevisionsRequest, GetDocumentRevisionsResponse,
-    ListMyDocumentsRequest, ListMyDocumentsResponse, SubmitDocumentChangeSetRequest,
-    SubmitDocumentChangeSetResponse,
+    CreateDocumentRequest, CreateDocumentResponse, DocumentSharingPermission,
+    GetDocumentHeadRequest, GetDocumentHeadResponse, GetDocumentRequest, GetDocumentResponse,
+    GetDocumentRevisionsRequest, GetDocumentRevisionsResponse, GetMyPermissionsRequest,
+    GetMyPermissionsResponse, ListMyDocumentsRequest, ListMyDocumentsResponse,
+    ListReadReceiptsRequest, ListReadReceiptsResponse, RegisterSigningKeyRequest,
+    RegisterSigningKeyResponse, ReportReadPositionRequest, ReportReadPositionResponse,
+    SendTypingRequest, SendTypingResponse, SubmitDocumentChangeSetRequest,
+    SubmitDocumentChangeSetResponse, UpdateDocumentStatsRequest, UpdateDocumentStatsResponse,
+    UpdateDocumentTitleRequest, UpdateDocumentTitleResponse,
 };
 
+use crate::performance;
+
 #[derive(Debug, Error)]
 pub enum BackendApiError {
     #[error("Invalid Input: {0}")]
@@ -22,6 +34,65 @@ pub enum BackendApiError {
     ServerError(String),
     #[error("Invalid Response: {0}")]
     InvalidResponse(String),
+    #[error("Update Required: The server no longer supports this version of the editor")]
+    UpdateRequired,
+    #[error("Unauthorized: The session expired, and the user must sign in again")]
+    Unauthorized,
+    /// The server will never accept the request: it is invalid (400), the user may not make it
+    /// (403), what it is about no longer exists (404), its body is too large (413), or it is not
+    /// encoded as a protobuf (415). Retrying fails the same way.
+    #[error("Rejected: Status: {status}, trace id: {trace_id}")]
+    Rejected { status: u16, trace_id: String },
+}
+
+thread_local! {
+    // Set once the server rejects a request because our protocol version is too old. Stays set
+    // until the page is reloaded with a newer build.
+    static UPDATE_REQUIRED: Cell<bool> = Cell::new(false);
+}
+
+/// Returns true if the server has told us that this build is too old to talk to it.
+pub fn is_update_required() -> bool {
+    UPDATE_REQUIRED.with(|update_required| update_required.get())
+}
+
+thread_local! {
+    // Set once the server rejects a request because the session cookie expired. Every editor on
+    // the page shares the cookie, so they all pause syncing until the host signs the user in again
+    // and calls `clear_auth_required`.
+    static AUTH_REQUIRED: Cell<bool> = Cell::new(false);
+}
+
+/// Returns true if the server has told us that the user must sign in again.
+pub fn is_auth_required() -> bool {
+    AUTH_REQUIRED.with(|auth_required| auth_required.get())
+}
+
+pub fn clear_auth_required() {
+    AUTH_REQUIRED.with(|auth_required| auth_required.set(false));
+}
+
+thread_local! {
+    // Sent with every request until the next `start_trace`, so that the server's logs for them can
+    // be found from ours. Empty until the first trace starts.
+    static TRACE_ID: RefCell<String> = RefCell::new(String::new());
+}
+
+/// Starts a new trace, e.g. for a sync round, and returns its id. Requests made from now on carry
+/// it, including those of any other work that runs meanwhile.
+pub fn start_trace() -> String {
+    let mut bytes = [0u8; 8];
+    let trace_id: String = match getrandom::getrandom(&mut bytes) {
+        Ok(()) => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+        Err(_) => String::new(),
+    };
+    TRACE_ID.with(|current| current.replace(trace_id.clone()));
+    trace_id
+}
+
+/// The id of the current trace. Empty if none has started.
+pub fn current_trace_id() -> String {
+    TRACE_ID.with(|current| current.borrow().clone())
 }
 
 pub struct BackendApi {}
@@ -41,6 +112,13 @@ impl BackendApi {
         Self::execute_backend_api_request(&url, request).await
     }
 
+    pub async fn get_document_head(
+        request: &GetDocumentHeadRequest,
+    ) -> Result<GetDocumentHeadResponse, BackendApiError> {
+        let url = "/api/documents.get_document_head";
+        Self::execute_backend_api_request(&url, request).await
+    }
+
     pub async fn get_document_revisions(
         request: &GetDocumentRevisionsRequest,
     ) -> Result<GetDocumentRevisionsResponse, BackendApiError> {
@@ -48,6 +126,13 @@ impl BackendApi {
         Self::execute_backend_api_request(&url, request).await
     }
 
+    pub async fn get_my_permissions(
+        request: &GetMyPermissionsRequest,
+    ) -> Result<GetMyPermissionsResponse, BackendApiError> {
+        let url = "/api/documents.get_my_permissions";
+        Self::execute_backend_api_request(&url, request).await
+    }
+
     pub async fn list_my_documents(
         request: &ListMyDocumentsRequest,
     ) -> Result<ListMyDocumentsResponse, BackendApiError> {
@@ -55,6 +140,34 @@ impl BackendApi {
         Self::execute_backend_api_request(&url, request).await
     }
 
+    pub async fn list_read_receipts(
+        request: &ListReadReceiptsRequest,
+    ) -> Result<ListReadReceiptsResponse, BackendApiError> {
+        let url = "/api/documents.list_read_receipts";
+        Self::execute_backend_api_request(&url, request).await
+    }
+
+    pub async fn register_signing_key(
+        request: &RegisterSigningKeyRequest,
+    ) -> Result<RegisterSigningKeyResponse, BackendApiError> {
+        let url = "/api/signing_keys.register_signing_key";
+        Self::execute_backend_api_request(&url, request).await
+    }
+
+    pub async fn report_read_position(
+        request: &ReportReadPositionRequest,
+    ) -> Result<ReportReadPositionResponse, BackendApiError> {
+        let url = "/api/documents.report_read_position";
+        Self::execute_backend_api_request(&url, request).await
+    }
+
+    pub async fn send_typing(
+        request: &SendTypingRequest,
+    ) -> Result<SendTypingResponse, BackendApiError> {
+        let url = "/api/documents.send_typing";
+        Self::execute_backend_api_request(&url, request).await
+    }
+
     pub async fn submit_document_change_set(
         request: &SubmitDocumentChangeSetRequest,
     ) -> Result<SubmitDocumentChangeSetResponse, BackendApiError> {
@@ -62,6 +175,36 @@ impl BackendApi {
         Self::execute_backend_api_request(&url, request).await
     }
 
+    pub async fn update_document_stats(
+        request: &UpdateDocumentStatsRequest,
+    ) -> Result<UpdateDocumentStatsResponse, BackendApiError> {
+        let url = "/api/documents.update_document_stats";
+        Self::execute_backend_api_request(&url, request).await
+    }
+
+    pub async fn update_document_title(
+        request: &UpdateDocumentTitleRequest,
+    ) -> Result<UpdateDocumentTitleResponse, BackendApiError> {
+        let url = "/api/documents.update_document_title";
+        Self::execute_backend_api_request(&url, request).await
+    }
+
+    /// The URL of the server-sent event stream for the document.
+    pub fn document_events_url(doc_id: &str, share_token: &str) -> String {
+        let mut url = format!(
+            "/api/documents.events?doc_id={}&protocol_version={}",
+            String::from(js_sys::encode_uri_component(doc_id)),
+            PROTOCOL_VERSION
+        );
+        if !share_token.is_empty() {
+            url.push_str(&format!(
+                "&share_token={}",
+                String::from(js_sys::encode_uri_component(share_token))
+            ));
+        }
+        url
+    }
+
     async fn execute_backend_api_request<Req, Res>(
         url: &str,
         request: &Req,
@@ -72,9 +215,10 @@ impl BackendApi {
     {
         // 1. Create JS Request from protobuf request.
         let mut encoded_request = Vec::new();
-        request
-            .encode(&mut encoded_request)
-            .map_err(|e| BackendApiError::InvalidInput(e.to_string()))?;
+        performance::time(Stage::Serialization, || {
+            request.encode(&mut encoded_request)
+        })
+        .map_err(|e| BackendApiError::InvalidInput(e.to_string()))?;
         let array = Uint8Array::new_with_length(encoded_request.len() as u32);
         array.copy_from(&encoded_request);
         let mut request_opts = RequestInit::new();
@@ -84,19 +228,64 @@ impl BackendApi {
         let js_request = Request::new_with_str_and_init(url, &request_opts).map_err(|e| {
             BackendApiError::InvalidInput(format!("Error creating Request: {:?}", e))
         })?;
+        js_request
+            .headers()
+            .set("content-type", PROTOBUF_CONTENT_TYPE)
+            .map_err(|e| BackendApiError::InvalidInput(format!("Error setting header: {:?}", e)))?;
+        js_request
+            .headers()
+            .set(PROTOCOL_VERSION_HEADER, &PROTOCOL_VERSION.to_string())
+            .map_err(|e| BackendApiError::InvalidInput(format!("Error setting header: {:?}", e)))?;
+        let trace_id = current_trace_id();
+        if !trace_id.is_empty() {
+            js_request
+                .headers()
+                .set(TRACE_ID_HEADER, &trace_id)
+                .map_err(|e| {
+                    BackendApiError::InvalidInput(format!("Error setting header: {:?}", e))
+                })?;
+        }
         let window = web_sys::window()
             .ok_or_else(|| BackendApiError::InvalidInput("window not available".to_string()))?;
         let js_response: Response = JsFuture::from(window.fetch_with_request(&js_request))
             .await
-            .map_err(|e| BackendApiError::ServerError(format!("Error executing fetch: {:?}", e)))?
+            .map_err(|e| {
+                BackendApiError::ServerError(format!(
+                    "Error executing fetch: {:?}, trace id: {}",
+                    e, trace_id
+                ))
+            })?
             .dyn_into()
             .map_err(|e| {
                 BackendApiError::InvalidResponse(format!("Error converting response: {:?}", e))
             })?;
+        if js_response.status() == 426 {
+            UPDATE_REQUIRED.with(|update_required| update_required.set(true));
+            return Err(BackendApiError::UpdateRequired);
+        }
+        if js_response.status() == 401 {
+            AUTH_REQUIRED.with(|auth_required| auth_required.set(true));
+            return Err(BackendApiError::Unauthorized);
+        }
         if !js_response.ok() {
-            return Err(BackendApiError::ServerError(
-                "Error: Did not receive OK response status".to_string(),
-            ));
+            // The server makes up a trace id if we sent none, so prefer the one it echoes back.
+            let trace_id = js_response
+                .headers()
+                .get(TRACE_ID_HEADER)
+                .ok()
+                .flatten()
+                .unwrap_or(trace_id);
+            if let 400 | 403 | 404 | 413 | 415 = js_response.status() {
+                return Err(BackendApiError::Rejected {
+                    status: js_response.status(),
+                    trace_id,
+                });
+            }
+            return Err(BackendApiError::ServerError(format!(
+                "Error: Did not receive OK response status. Status: {}, trace id: {}",
+                js_response.status(),
+                trace_id
+            )));
         }
         let body_promise = js_response.array_buffer().map_err(|e| {
             BackendApiError::InvalidResponse(format!("Error getting array buffer: {:?}", e))
@@ -114,9 +303,10 @@ impl BackendApi {
         // TODO(cliff): Use unsafe function to get view of bytes instead of copying them?
         let mut body_bytes: Vec<u8> = vec![0; body_uint8_array.length() as usize];
         body_uint8_array.copy_to(&mut body_bytes);
-        let response = Res::decode(&body_bytes[..]).map_err(|e| {
-            BackendApiError::InvalidResponse(format!("Error decoding response: {:?}", e))
-        })?;
+        let response = performance::time(Stage::Serialization, || Res::decode(&body_bytes[..]))
+            .map_err(|e| {
+                BackendApiError::InvalidResponse(format!("Error decoding response: {:?}", e))
+            })?;
         Ok(response)
     }
 }
@@ -126,11 +316,28 @@ pub struct JsBackendApi {}
 
 #[wasm_bindgen]
 impl JsBackendApi {
+    /// Returns true once the server has rejected a request because this build of the editor is
+    /// too old. Requests will keep failing until the page is reloaded.
+    #[wasm_bindgen(js_name = isUpdateRequired)]
+    pub fn is_update_required() -> bool {
+        is_update_required()
+    }
+
+    /// Returns true once the server has rejected a request because the session expired. Requests
+    /// will keep failing until the user signs in again.
+    #[wasm_bindgen(js_name = isAuthRequired)]
+    pub fn is_auth_required() -> bool {
+        is_auth_required()
+    }
+
+    /// Creates a document. Pass the fingerprint from `encryptionKeyFingerprint` to create an
+    /// end-to-end encrypted document.
     #[wasm_bindgen(js_name = createDocument)]
-    pub fn create_document(title: String) -> Promise {
+    pub fn create_document(title: String, encryption_key_fingerprint: Option<String>) -> Promise {
         let request = CreateDocumentRequest {
             title,
             org_level_sharing_permission: DocumentSharingPermission::None.into(),
+            encryption_key_fingerprint: encryption_key_fingerprint.unwrap_or_default(),
         };
         let future = async move {
             match BackendApi::create_document(&request).await {
@@ -147,8 +354,11 @@ impl JsBackendApi {
     }
 
     #[wasm_bindgen(js_name = getDocument)]
-    pub fn get_document(doc_id: String) -> Promise {
-        let request = GetDocumentRequest { doc_id };
+    pub fn get_document(doc_id: String, share_token: Option<String>) -> Promise {
+        let request = GetDocumentRequest {
+            doc_id,
+            share_token: share_token.unwrap_or_default(),
+        };
         let future = async move {
             match BackendApi::get_document(&request).await {
                 Ok(response) => Ok(JsValue::from_serde(&response).unwrap()),
@@ -163,6 +373,28 @@ impl JsBackendApi {
         future_to_promise(future)
     }
 
+    #[wasm_bindgen(js_name = getMyPermissions)]
+    pub fn get_my_permissions(doc_ids: Box<[JsValue]>) -> Promise {
+        let request = GetMyPermissionsRequest {
+            doc_ids: doc_ids
+                .iter()
+                .filter_map(|doc_id| doc_id.as_string())
+                .collect(),
+        };
+        let future = async move {
+            match BackendApi::get_my_permissions(&request).await {
+                Ok(response) => Ok(JsValue::from_serde(&response).unwrap()),
+                Err(e) => {
+                    let error_message = format!("Error: {:?}", e);
+                    let mut map = HashMap::new();
+                    map.insert("error".to_string(), error_message);
+                    Err(JsValue::from_serde(&map).unwrap())
+                }
+            }
+        };
+        future_to_promise(future)
+    }
+
     #[wasm_bindgen(js_name = listMyDocuments)]
     pub fn list_my_documents(updated_before_date_time: Date) -> Promise {
         let request = ListMyDocumentsRequest {
@@ -181,4 +413,41 @@ impl JsBackendApi {
         };
         future_to_promise(future)
     }
+
+    /// Lists who has read the document, and up to which revision. Only the document's owner can
+    /// list them. Resolves to `{last_revision_number, read_receipts}`.
+    #[wasm_bindgen(js_name = listReadReceipts)]
+    pub fn list_read_receipts(doc_id: String) -> Promise {
+        let request = ListReadReceiptsRequest { doc_id };
+        let future = async move {
+            match BackendApi::list_read_receipts(&request).await {
+                Ok(response) => Ok(JsValue::from_serde(&response).unwrap()),
+                Err(e) => {
+                    let error_message = format!("Error: {:?}", e);
+                    let mut map = HashMap::new();
+                    map.insert("error".to_string(), error_message);
+                    Err(JsValue::from_serde(&map).unwrap())
+                }
+            }
+        };
+        future_to_promise(future)
+    }
+
+    /// Registers the public key of a signing key from `JsSigning`. Resolves to `{key_id}`.
+    #[wasm_bindgen(js_name = registerSigningKey)]
+    pub fn register_signing_key(public_key: Vec<u8>) -> Promise {
+        let request = RegisterSigningKeyRequest { public_key };
+        let future = async move {
+            match BackendApi::register_signing_key(&request).await {
+                Ok(response) => Ok(JsValue::from_serde(&response).unwrap()),
+                Err(e) => {
+                    let error_message = format!("Error: {:?}", e);
+                    let mut map = HashMap::new();
+                    map.insert("error".to_string(), error_message);
+                    Err(JsValue::from_serde(&map).unwrap())
+                }
+            }
+        };
+        future_to_promise(future)
+    }
 }
@@ -1,27 +1,46 @@
-mod committed_log;
-mod document_value;
-mod pending_log;
-mod undo_manager;
+mod offline_queue;
+mod revision_sync;
+mod search;
 
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::fmt::Write;
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 
-use js_sys::{Date, JsString, Promise};
+use js_sys::{Date, Function, JsString, Promise};
 use thiserror::Error;
 use wasm_bindgen::prelude::*;
-use wasm_bindgen_futures::future_to_promise;
+use wasm_bindgen_futures::{future_to_promise, spawn_local, JsFuture};
 
+use editor_core::annotations::Annotations;
+use editor_core::committed_log::CommittedLog;
+use editor_core::document_value::{DocumentValue, DocumentValueChunkId, DocumentValueChunkVersion};
+use editor_core::latency::Stage;
+use editor_core::pending_log::PendingLog;
+use editor_core::progressive_insert::{ProgressiveInsert, PROGRESSIVE_INSERT_CHUNK_LEN};
+use editor_core::region_locks::{self, RegionLockTracker};
+use editor_core::scroll_anchor::ScrollAnchor;
+use editor_core::search::SearchPattern;
+use editor_core::sync_schedule::SyncSchedule;
+use editor_core::textarea_update;
+use editor_core::title_sync::{TitleSync, TITLE_SAVE_DEBOUNCE};
+use editor_core::typing::TypingIndicator;
+use editor_core::undo_manager::{UndoItem, UndoManager, UndoPolicy, UndoType};
 use ot::writing_proto::submit_document_change_set_response::ResponseCode;
-use ot::writing_proto::{change_op::Op, ChangeSet, Selection};
-
-use crate::document_editor::committed_log::CommittedLog;
-use crate::document_editor::document_value::{
-    DocumentValue, DocumentValueChunkId, DocumentValueChunkVersion,
+use ot::writing_proto::{
+    ChangeSet, ReportReadPositionRequest, Selection, SendTypingRequest, UpdateDocumentStatsRequest,
+    UpdateDocumentTitleRequest,
 };
-use crate::document_editor::pending_log::PendingLog;
-use crate::document_editor::undo_manager::{UndoItem, UndoManager, UndoType};
+
+use crate::backend_api::{self, BackendApi};
+use crate::document_editor::offline_queue::{OfflineStatus, QueuedChanges};
+use crate::document_editor::revision_sync::{RevisionSync, RevisionSyncError};
+use crate::document_editor::search::SearchOptions;
+use crate::document_events::{DocumentEvent, DocumentEventSource};
+use crate::encryption::DocumentCipher;
+use crate::ot_worker::OtWorker;
+use crate::performance;
+use crate::signing::RevisionSigner;
+use crate::telemetry::{self, JsTelemetry, Telemetry, TelemetryEvent};
 
 // When a user is typing, their keystrokes will edit the most recent revision. Once the revision is
 // a few seconds old, it will be committed to the revision log, and a corresponding undo item will
@@ -30,6 +49,14 @@ use crate::document_editor::undo_manager::{UndoItem, UndoManager, UndoType};
 // Reason: If the user is typing a lot, we don't want each keystroke to create a new revision.
 const MAX_COMPOSABLE_TIME: f64 = 2000.0;
 
+// Document stats are reported to the backend for the document list at most this often, in
+// milliseconds.
+const STATS_REPORT_INTERVAL: f64 = 60000.0;
+
+// The latest revision that the user has seen is reported to the backend for read receipts at most
+// this often, in milliseconds.
+const READ_POSITION_REPORT_INTERVAL: f64 = 10000.0;
+
 #[derive(Debug, Error)]
 enum DocumentEditorError {
     #[error("Invalid Input Error: {0}")]
@@ -46,32 +73,142 @@ pub struct DocumentEditorModel {
 
 struct DocumentEditorModelInner {
     doc_id: String,
-    committed_log: CommittedLog,
+    share_token: String,
+    revision_sync: RevisionSync,
     pending_log: PendingLog,
     undo_manager: UndoManager,
     current_selection: Selection,
     current_value: DocumentValue,
+    annotations: Annotations,
+    title_sync: TitleSync,
+    // Set while `updateFromInputEventProgressively` applies a large insert piece by piece.
+    progressive_insert: Option<ProgressiveInsert>,
+    // Set by `setScrollAnchor`, and moved through remote revisions.
+    scroll_anchor: Option<ScrollAnchor>,
     sync_running: bool,
+    // Set when a sync finds that the committed log may have diverged from the server's. Syncing
+    // stops until `resync`.
+    resync_required: bool,
+    // Set when an event asks for a sync while one is running, so that another round runs after it.
+    sync_requested: bool,
+    event_source: Option<DocumentEventSource>,
+    typing_indicator: TypingIndicator,
+    region_lock_tracker: RegionLockTracker,
+    sync_schedule: SyncSchedule,
+    // Set by `restoreQueuedChanges` until the next sync applies them.
+    queued_changes_to_restore: Option<QueuedChanges>,
+    // Supplied by the host. Called with a delay in milliseconds, after which the host should call
+    // `sync`.
+    sync_scheduler: Option<Function>,
+    // Supplied by the host. Called when syncing pauses because the session expired.
+    auth_required_handler: Option<Function>,
+    // Supplied by the host, to collect statistics about syncing and conflicts.
+    telemetry: Option<Box<dyn Telemetry>>,
+    // Counted during each sync, for the `SyncFinished` telemetry event.
+    sync_rebases: u32,
+    sync_discovered_new_revisions: u32,
+    // Counted until `takeProtectedRangeRejections` takes them.
+    protected_range_rejections: u32,
+    // The HTTP status of the last rejection that rolled back the pending changes, until
+    // `takeRejectedChangesStatus` takes it.
+    rejected_changes_status: Option<u16>,
     last_pending_composable_until: f64,
+    last_reported_stats_revision_number: i64,
+    last_stats_reported_at: f64,
+    last_reported_read_revision_number: i64,
+    last_read_position_reported_at: f64,
 }
 
 #[wasm_bindgen]
 impl DocumentEditorModel {
-    pub fn new(doc_id: String) -> Self {
+    pub fn new(doc_id: String, share_token: Option<String>) -> Self {
+        let share_token = share_token.unwrap_or_default();
         Self {
             inner: Rc::new(RefCell::new(DocumentEditorModelInner {
                 doc_id: doc_id.clone(),
-                committed_log: CommittedLog::new(&doc_id),
+                share_token: share_token.clone(),
+                revision_sync: RevisionSync::new(&doc_id, &share_token),
                 pending_log: PendingLog::new(),
                 undo_manager: UndoManager::new(),
                 current_selection: Selection::default(),
                 current_value: DocumentValue::new(),
+                annotations: Annotations::new(),
+                title_sync: TitleSync::new(),
+                progressive_insert: None,
+                scroll_anchor: None,
                 sync_running: false,
+                resync_required: false,
+                sync_requested: false,
+                event_source: None,
+                typing_indicator: TypingIndicator::new(),
+                region_lock_tracker: RegionLockTracker::new(),
+                sync_schedule: SyncSchedule::new(),
+                queued_changes_to_restore: None,
+                sync_scheduler: None,
+                auth_required_handler: None,
+                telemetry: None,
+                sync_rebases: 0,
+                sync_discovered_new_revisions: 0,
+                protected_range_rejections: 0,
+                rejected_changes_status: None,
                 last_pending_composable_until: 0.0,
+                last_reported_stats_revision_number: 0,
+                last_stats_reported_at: 0.0,
+                last_reported_read_revision_number: 0,
+                last_read_position_reported_at: 0.0,
             })),
         }
     }
 
+    /// Opens an end-to-end encrypted document with its key. `fingerprint` is the document's
+    /// encryption key fingerprint. Returns false if the key does not match it. Must be called
+    /// before the first sync.
+    #[wasm_bindgen(js_name = setEncryptionKey)]
+    pub fn set_encryption_key(&self, key: &[u8], fingerprint: String) -> bool {
+        let self_ = self.inner.borrow();
+        match DocumentCipher::new(&self_.doc_id, key) {
+            Ok(cipher) if cipher.fingerprint() == fingerprint => {
+                self_.revision_sync.set_cipher(cipher);
+                true
+            }
+            Ok(_) => {
+                web_sys::console::error_1(&"Encryption key does not match the document".into());
+                false
+            }
+            Err(e) => {
+                web_sys::console::error_1(&format!("Error setting encryption key: {}", e).into());
+                false
+            }
+        }
+    }
+
+    /// Signs every revision committed from now on with the given secret key. `key_id` is the id
+    /// the server gave back when the key's public key was registered.
+    #[wasm_bindgen(js_name = setSigningKey)]
+    pub fn set_signing_key(&self, key_id: String, secret_key: &[u8]) -> bool {
+        match RevisionSigner::new(&key_id, secret_key) {
+            Ok(signer) => {
+                self.inner.borrow().revision_sync.set_signer(signer);
+                true
+            }
+            Err(e) => {
+                web_sys::console::error_1(&format!("Error setting signing key: {}", e).into());
+                false
+            }
+        }
+    }
+
+    /// Composes large batches of remote revisions in the given web worker, so that catching up does
+    /// not block typing. The worker loads this module too, and answers each message with
+    /// `OtWorkerTasks.handleMessage`. Without one, they are composed on the main thread.
+    #[wasm_bindgen(js_name = setOtWorker)]
+    pub fn set_ot_worker(&self, worker: web_sys::Worker) {
+        self.inner
+            .borrow()
+            .revision_sync
+            .set_ot_worker(OtWorker::new(worker));
+    }
+
     #[wasm_bindgen(js_name = getDocId)]
     pub fn get_doc_id(&self) -> String {
         self.inner.borrow().doc_id.clone()
@@ -88,6 +225,18 @@ impl DocumentEditorModel {
         self_.current_selection = selection.into();
     }
 
+    /// Sets how undo treats steps that collaborators' changes have emptied, like typing that a
+    /// collaborator has since deleted. With `skip_empty_undos`, undo and redo pass over them. With
+    /// `collapse_identity_items`, they are dropped as soon as they are emptied. By default they
+    /// stay, and undoing one only restores the selection.
+    #[wasm_bindgen(js_name = setUndoPolicy)]
+    pub fn set_undo_policy(&self, skip_empty_undos: bool, collapse_identity_items: bool) {
+        self.inner.borrow_mut().undo_manager.set_policy(UndoPolicy {
+            skip_empty_undos,
+            collapse_identity_items,
+        });
+    }
+
     #[wasm_bindgen(js_name = getValue)]
     pub fn get_value(&self) -> JsString {
         let self_ = self.inner.borrow();
@@ -98,6 +247,106 @@ impl DocumentEditorModel {
         slice_to_js_string(&current_value)
     }
 
+    /// The document's title, or null until the first sync loads it. Syncs pick up titles that
+    /// others set, so hosts should read it again after each sync.
+    #[wasm_bindgen(js_name = getTitle)]
+    pub fn get_title(&self) -> Option<String> {
+        self.inner.borrow().title_sync.title().map(String::from)
+    }
+
+    /// Sets the document's title. It is saved by the first sync after it stops changing for a
+    /// moment, so that typing a title does not send a request per keystroke.
+    #[wasm_bindgen(js_name = setTitle)]
+    pub fn set_title(&self, title: String) {
+        self.inner
+            .borrow_mut()
+            .title_sync
+            .set_title(&title, Date::now());
+        self.schedule_sync(TITLE_SAVE_DEBOUNCE);
+    }
+
+    /// Computes how to bring a textarea up to date with the document without replacing its value,
+    /// which would reset the caret and the scroll position. Pass the textarea's value and
+    /// selection. Returns `{splice, selection}`, where `splice` is `{start, end, text}` to pass to
+    /// `setRangeText`, and `selection` is `{start, end}` to pass to `setSelectionRange` after it.
+    /// Either is null if there is nothing to do.
+    #[wasm_bindgen(js_name = computeTextareaUpdate)]
+    pub fn compute_textarea_update(
+        &self,
+        textarea_value: JsString,
+        textarea_selection: &JsSelection,
+    ) -> JsValue {
+        let self_ = self.inner.borrow();
+        let old_value: Vec<u16> = textarea_value.iter().collect();
+        let new_value = self_
+            .current_value
+            .get_value_in_range(0..self_.current_value.value_len())
+            .unwrap();
+        let update = performance::time(Stage::Diff, || {
+            textarea_update::compute_textarea_update(
+                &old_value,
+                &new_value,
+                &(*textarea_selection).into(),
+                &self_.current_selection,
+            )
+        });
+        let result = js_sys::Object::new();
+        let splice = match update.splice {
+            Some(splice) => {
+                let object = js_sys::Object::new();
+                set_property(&object, "start", &JsValue::from(splice.start as u32));
+                set_property(&object, "end", &JsValue::from(splice.end as u32));
+                set_property(&object, "text", &slice_to_js_string(&splice.text));
+                object.into()
+            }
+            None => JsValue::NULL,
+        };
+        set_property(&result, "splice", &splice);
+        let selection = match update.selection {
+            Some(selection) => {
+                let selection: JsSelection = selection.into();
+                let object = js_sys::Object::new();
+                set_property(&object, "start", &JsValue::from(selection.start));
+                set_property(&object, "end", &JsValue::from(selection.end));
+                object.into()
+            }
+            None => JsValue::NULL,
+        };
+        set_property(&result, "selection", &selection);
+        result.into()
+    }
+
+    /// Sets the offset of the first character visible in the host's viewport. Remote revisions move
+    /// it, and count the lines they add or remove above it, until `takeScrollAnchor` reports them.
+    #[wasm_bindgen(js_name = setScrollAnchor)]
+    pub fn set_scroll_anchor(&self, offset: u32) {
+        let mut self_ = self.inner.borrow_mut();
+        let offset = std::cmp::min(offset as usize, self_.current_value.value_len());
+        self_.scroll_anchor = Some(ScrollAnchor::new(offset as i64));
+    }
+
+    /// Returns `{offset, pixel_delta}`, where `offset` is where the scroll anchor moved to, and
+    /// `pixel_delta` is how far to scroll, given the line height in pixels, to keep it in the same
+    /// place in the viewport. The delta only counts line breaks, so lines that wrap can still move
+    /// a little. Returns null if no anchor is set.
+    #[wasm_bindgen(js_name = takeScrollAnchor)]
+    pub fn take_scroll_anchor(&self, line_height: f64) -> JsValue {
+        let mut self_ = self.inner.borrow_mut();
+        let anchor = match self_.scroll_anchor.as_mut() {
+            Some(anchor) => anchor,
+            None => return JsValue::NULL,
+        };
+        let lines_delta = anchor.take_lines_delta();
+        let result = js_sys::Object::new();
+        set_property(&result, "offset", &JsValue::from(anchor.offset as u32));
+        set_property(
+            &result,
+            "pixel_delta",
+            &JsValue::from(lines_delta as f64 * line_height),
+        );
+        result.into()
+    }
+
     #[wasm_bindgen(js_name = getChunkIds)]
     pub fn get_chunk_ids(&self) -> Vec<DocumentValueChunkId> {
         let self_ = self.inner.borrow();
@@ -124,15 +373,172 @@ impl DocumentEditorModel {
         self.inner.borrow().sync_running
     }
 
+    /// True once a sync has found revisions out of sequence, like a gap or a revision loaded
+    /// twice. The model stops syncing rather than build on a history that may not match the
+    /// server's, until the host calls `resync`.
+    #[wasm_bindgen(js_name = isResyncRequired)]
+    pub fn is_resync_required(&self) -> bool {
+        self.inner.borrow().resync_required
+    }
+
     fn set_sync_running(&self, sync_running: bool) {
         let mut self_ = self.inner.borrow_mut();
         self_.sync_running = sync_running;
     }
 
+    /// Lets the model decide when to sync, instead of syncing on a fixed timer. After local edits,
+    /// after each sync, and on `onVisibilityChange` and `onFocus`, the model calls `scheduler` with
+    /// a delay in milliseconds. The host should call `sync` once that much time has passed,
+    /// replacing any call it had scheduled before.
+    ///
+    /// Syncs right after local edits, and backs off exponentially while nothing changes locally or
+    /// remotely.
+    #[wasm_bindgen(js_name = setSyncScheduler)]
+    pub fn set_sync_scheduler(&self, scheduler: Function) {
+        self.inner.borrow_mut().sync_scheduler = Some(scheduler);
+        self.schedule_sync(0.0);
+    }
+
+    /// Calls `handler` with no arguments when a sync finds that the session expired. Syncing then
+    /// pauses, and local edits keep piling up as pending changes, until the host signs the user in
+    /// again and calls `resumeAfterReauthentication`.
+    #[wasm_bindgen(js_name = setAuthRequiredHandler)]
+    pub fn set_auth_required_handler(&self, handler: Function) {
+        self.inner.borrow_mut().auth_required_handler = Some(handler);
+    }
+
+    /// Resumes syncing after the host signed the user in again, committing every change made while
+    /// it was paused. The session is shared by the whole page, so this resumes the other editors on
+    /// it too, at their next local edit or scheduled sync.
+    #[wasm_bindgen(js_name = resumeAfterReauthentication)]
+    pub fn resume_after_reauthentication(&self) {
+        backend_api::clear_auth_required();
+        self.schedule_sync(0.0);
+    }
+
+    /// Reports statistics about syncing and conflicts by calling `callback` with an event object.
+    /// Each event has a `type` field: `rebase`, `discovered_new_revisions`, or `sync_finished`.
+    #[wasm_bindgen(js_name = setTelemetryCallback)]
+    pub fn set_telemetry_callback(&self, callback: Function) {
+        self.inner.borrow_mut().telemetry = Some(Box::new(JsTelemetry::new(callback)));
+    }
+
+    /// Returns how long the editor's stages have taken lately, to find the one that makes typing
+    /// janky: `{apply, transform, diff, serialization}`, each `{count, p50_millis, p90_millis,
+    /// p99_millis, max_millis}`. Covers every editor on the page.
+    #[wasm_bindgen(js_name = getPerformanceReport)]
+    pub fn get_performance_report(&self) -> JsValue {
+        JsValue::from_serde(&performance::report()).unwrap()
+    }
+
+    /// Forgets the timings so far, e.g. before measuring a scripted typing session.
+    #[wasm_bindgen(js_name = resetPerformanceReport)]
+    pub fn reset_performance_report(&self) {
+        performance::clear();
+    }
+
+    /// Call when the page becomes visible or hidden. Syncs rarely while hidden, and catches up
+    /// right away when visible again.
+    #[wasm_bindgen(js_name = onVisibilityChange)]
+    pub fn on_visibility_change(&self, visible: bool) {
+        let delay = self
+            .inner
+            .borrow_mut()
+            .sync_schedule
+            .on_visibility_change(visible);
+        self.schedule_sync(delay);
+    }
+
+    /// Call when the page regains focus. Catches up right away.
+    #[wasm_bindgen(js_name = onFocus)]
+    pub fn on_focus(&self) {
+        let delay = self.inner.borrow_mut().sync_schedule.on_focus();
+        self.schedule_sync(delay);
+    }
+
+    /// Call when the browser goes online or offline, and once at the start with
+    /// `navigator.onLine`. While offline, the model does not sync, and local changes queue up in
+    /// the pending log. Syncs right away once back online.
+    #[wasm_bindgen(js_name = onConnectivityChange)]
+    pub fn on_connectivity_change(&self, online: bool) {
+        let delay = self
+            .inner
+            .borrow_mut()
+            .sync_schedule
+            .on_connectivity_change(online);
+        self.schedule_sync(delay);
+    }
+
+    /// Returns `{offline, queued_changes}`, where `queued_changes` is how many local changes are
+    /// waiting to be committed.
+    #[wasm_bindgen(js_name = offlineStatus)]
+    pub fn offline_status(&self) -> JsValue {
+        let inner = self.inner.borrow();
+        let status = OfflineStatus {
+            offline: !inner.sync_schedule.is_online(),
+            queued_changes: inner.pending_log.len()
+                + inner.queued_changes_to_restore.iter().count(),
+        };
+        JsValue::from_serde(&status).unwrap()
+    }
+
+    /// Returns the local changes that have not been committed yet, as
+    /// `{base_revision_number, change_set}`, or null if there are none. The host should save them
+    /// after edits and syncs, and pass them to `restoreQueuedChanges` when the document is opened
+    /// again, so that changes made offline are not lost when the page closes.
+    ///
+    /// Returns null for end-to-end encrypted documents, whose changes must not be stored
+    /// unencrypted. A change that was being committed when the page closed may have been
+    /// committed after all, and is then applied twice when restored.
+    #[wasm_bindgen(js_name = exportQueuedChanges)]
+    pub fn export_queued_changes(&self) -> JsValue {
+        let inner = self.inner.borrow();
+        // Not restored yet, so they are still the changes to keep.
+        if let Some(queued_changes) = &inner.queued_changes_to_restore {
+            return JsValue::from_serde(queued_changes).unwrap();
+        }
+        if inner.revision_sync.is_encrypted() {
+            return JsValue::NULL;
+        }
+        match inner.pending_log.compose_range(0..inner.pending_log.len()) {
+            Ok(Some(change_set)) => {
+                let base_revision_number = inner.revision_sync.last_revision_number();
+                JsValue::from_serde(&QueuedChanges::new(base_revision_number, &change_set)).unwrap()
+            }
+            Ok(None) => JsValue::NULL,
+            Err(e) => {
+                web_sys::console::error_1(&format!("Error exporting queued changes: {}", e).into());
+                JsValue::NULL
+            }
+        }
+    }
+
+    /// Hands over changes from `exportQueuedChanges` that were saved before the page closed. The
+    /// next sync loads the document up to the revision they were made on, applies them as local
+    /// edits, and then commits them like any other. Must be called before the first sync. Returns
+    /// false if it was called too late, or if `queued_changes` is malformed.
+    #[wasm_bindgen(js_name = restoreQueuedChanges)]
+    pub fn restore_queued_changes(&self, queued_changes: JsValue) -> bool {
+        let queued_changes: QueuedChanges = match queued_changes.into_serde() {
+            Ok(queued_changes) => queued_changes,
+            Err(_) => return false,
+        };
+        let mut inner = self.inner.borrow_mut();
+        if inner.revision_sync.last_revision_number() > 0 || !inner.pending_log.is_empty() {
+            return false;
+        }
+        inner.queued_changes_to_restore = Some(queued_changes);
+        true
+    }
+
     #[wasm_bindgen(js_name = updateFromInputEvent)]
     pub fn update_from_input_event(&self, input_event: InputEventParams) {
         match self.update_from_input_event_impl(input_event) {
-            Ok(_) => {}
+            Ok(_) => {
+                let delay = self.inner.borrow_mut().sync_schedule.on_local_edit();
+                self.schedule_sync(delay);
+                self.send_typing_heartbeat();
+            }
             Err(e) => {
                 web_sys::console::error_1(
                     &format!("Error occurred updating from input event: {}", e).into(),
@@ -141,6 +547,42 @@ impl DocumentEditorModel {
         }
     }
 
+    /// Like `updateFromInputEvent`, but applies a large paste or drop in pieces, yielding to other
+    /// queued work between them so that the page does not stall. Calls `on_progress(inserted,
+    /// total)`, in UTF-16 code units, after each piece. The pieces make one undo step, and syncs
+    /// hold off committing them until the last one is applied, so they commit as one revision.
+    ///
+    /// Other input events are applied at once. Keep the textarea read-only until the returned
+    /// promise resolves, since input events must not interleave with the pieces.
+    #[wasm_bindgen(js_name = updateFromInputEventProgressively)]
+    pub fn update_from_input_event_progressively(
+        &self,
+        input_event: InputEventParams,
+        on_progress: Function,
+    ) -> Promise {
+        let self_ = self.clone();
+        let future = async move {
+            match self_
+                .update_from_input_event_progressively_impl(input_event, &on_progress)
+                .await
+            {
+                Ok(_) => {
+                    let delay = self_.inner.borrow_mut().sync_schedule.on_local_edit();
+                    self_.schedule_sync(delay);
+                    self_.send_typing_heartbeat();
+                    Ok(JsValue::UNDEFINED)
+                }
+                Err(e) => {
+                    let error_message = format!("Error occurred updating from input event: {}", e);
+                    let mut map = HashMap::new();
+                    map.insert("error".to_string(), error_message);
+                    Err(JsValue::from_serde(&map).unwrap())
+                }
+            }
+        };
+        future_to_promise(future)
+    }
+
     #[wasm_bindgen(js_name = sync)]
     pub fn sync(&self) -> Promise {
         let self_ = self.clone();
@@ -151,6 +593,14 @@ impl DocumentEditorModel {
                     let error_message = format!("Document Editor sync error: {:?}", e);
                     let mut map = HashMap::new();
                     map.insert("error".to_string(), error_message);
+                    // Look for this in the server's logs to see how the failed round went there.
+                    map.insert("trace_id".to_string(), backend_api::current_trace_id());
+                    if self_.is_resync_required() {
+                        map.insert("resync_required".to_string(), "true".to_string());
+                    }
+                    if backend_api::is_auth_required() {
+                        map.insert("auth_required".to_string(), "true".to_string());
+                    }
                     Err(JsValue::from_serde(&map).unwrap())
                 }
             }
@@ -158,32 +608,550 @@ impl DocumentEditorModel {
         future_to_promise(future)
     }
 
+    /// Loads the document again from the server's full revision log, in place of the revisions
+    /// loaded so far, and rebases the local changes that were not committed yet onto it. The
+    /// selection is kept. Meant for when `isResyncRequired` returns true, instead of reloading the
+    /// page.
+    ///
+    /// Nothing changes until the whole log has loaded, so if the promise is rejected, the model is
+    /// as it was, and `resync` can be called again.
+    #[wasm_bindgen(js_name = resync)]
+    pub fn resync(&self) -> Promise {
+        let self_ = self.clone();
+        let future = async move {
+            match self_.resync_impl().await {
+                Ok(_) => Ok(JsValue::UNDEFINED),
+                Err(e) => {
+                    let error_message = format!("Document Editor resync error: {:?}", e);
+                    let mut map = HashMap::new();
+                    map.insert("error".to_string(), error_message);
+                    map.insert("trace_id".to_string(), backend_api::current_trace_id());
+                    Err(JsValue::from_serde(&map).unwrap())
+                }
+            }
+        };
+        future_to_promise(future)
+    }
+
+    /// Listens for revisions committed by others, and syncs as soon as one arrives rather than at
+    /// the next periodic sync. Keep syncing periodically anyway, since events only cover revisions
+    /// committed through the server we are connected to. Returns false if the event stream could
+    /// not be opened.
+    #[wasm_bindgen(js_name = subscribeToEvents)]
+    pub fn subscribe_to_events(&self) -> bool {
+        // The event source lives in the model, so it must not keep the model alive.
+        let inner = Rc::downgrade(&self.inner);
+        let on_event = move |event: DocumentEvent| {
+            if let Some(model) = DocumentEditorModel::upgrade(&inner) {
+                model.on_document_event(event);
+            }
+        };
+        let mut self_ = self.inner.borrow_mut();
+        match DocumentEventSource::open(&self_.doc_id, &self_.share_token, on_event) {
+            Ok(event_source) => {
+                self_.event_source = Some(event_source);
+                true
+            }
+            Err(e) => {
+                web_sys::console::error_1(&format!("Error opening event stream: {:?}", e).into());
+                false
+            }
+        }
+    }
+
+    #[wasm_bindgen(js_name = unsubscribeFromEvents)]
+    pub fn unsubscribe_from_events(&self) {
+        self.inner.borrow_mut().event_source = None;
+    }
+
+    /// Returns who else is typing in the document, as a list of `{user_id, display_name}` sorted
+    /// by name. People drop off the list a few seconds after they stop typing, so the host should
+    /// call this every second or so. Only works while subscribed to events.
+    #[wasm_bindgen(js_name = activeTypers)]
+    pub fn active_typers(&self) -> JsValue {
+        let now = Date::now();
+        let typers = self.inner.borrow_mut().typing_indicator.active_typers(now);
+        JsValue::from_serde(&typers).unwrap()
+    }
+
+    /// Returns the regions of the document that others are editing, as a list of
+    /// `{user_id, display_name, start, end}` sorted by `start`. The host can warn the user before
+    /// they edit one of them, since that is likely to conflict. Like `activeTypers`, locks drop off
+    /// a few seconds after their editors stop typing, so the host should call this every second or
+    /// so. Only works while subscribed to events.
+    #[wasm_bindgen(js_name = activeRegionLocks)]
+    pub fn active_region_locks(&self) -> JsValue {
+        let now = Date::now();
+        let mut self_ = self.inner.borrow_mut();
+        let result = self_
+            .pending_log
+            .compose_range(0..self_.pending_log.len())
+            .and_then(|pending| {
+                self_
+                    .region_lock_tracker
+                    .active_region_locks(now, pending.as_ref())
+            });
+        match result {
+            Ok(region_locks) => JsValue::from_serde(&region_locks).unwrap(),
+            Err(e) => {
+                web_sys::console::error_1(&format!("Error getting region locks: {}", e).into());
+                js_sys::Array::new().into()
+            }
+        }
+    }
+
+    /// Returns how many of the user's changes the server rejected since the last call, because
+    /// they modified a range of the document that only its owner may edit. The rejected changes
+    /// are undone, so the host should tell the user why their text changed back. Changes are
+    /// combined before they are submitted, so everything typed since the previous sync is undone
+    /// along with the change to the protected range.
+    #[wasm_bindgen(js_name = takeProtectedRangeRejections)]
+    pub fn take_protected_range_rejections(&self) -> u32 {
+        std::mem::take(&mut self.inner.borrow_mut().protected_range_rejections)
+    }
+
+    /// Returns the HTTP status with which the server rejected the user's changes for good since the
+    /// last call, or undefined if it did not. The changes were rolled back, so the host should tell
+    /// the user why their text changed back: 403 means they may no longer edit the document, 404
+    /// that it was deleted, 413 that the changes were too large to submit at once, and 400 or 415
+    /// that the changes were invalid.
+    #[wasm_bindgen(js_name = takeRejectedChangesStatus)]
+    pub fn take_rejected_changes_status(&self) -> Option<u16> {
+        self.inner.borrow_mut().rejected_changes_status.take()
+    }
+
+    /// Returns every match of `pattern` in the document, in order, as `{start, end}` objects.
+    #[wasm_bindgen(js_name = findAll)]
+    pub fn find_all(&self, pattern: JsString, options: &SearchOptions) -> JsValue {
+        let pattern: Vec<u16> = pattern.iter().collect();
+        let pattern = SearchPattern::new(&pattern, &(*options).into());
+        match self.inner.borrow().current_value.find_all(&pattern) {
+            Ok(matches) => JsValue::from_serde(&matches).unwrap(),
+            Err(e) => {
+                web_sys::console::error_1(&format!("Error occurred finding all: {}", e).into());
+                js_sys::Array::new().into()
+            }
+        }
+    }
+
+    /// Returns the first match of `pattern` that starts at or after `from`, wrapping around to the
+    /// start of the document. Returns `undefined` if there is no match.
+    #[wasm_bindgen(js_name = findNext)]
+    pub fn find_next(
+        &self,
+        pattern: JsString,
+        from: u32,
+        options: &SearchOptions,
+    ) -> Option<JsSelection> {
+        let pattern: Vec<u16> = pattern.iter().collect();
+        let pattern = SearchPattern::new(&pattern, &(*options).into());
+        match self
+            .inner
+            .borrow()
+            .current_value
+            .find_next(&pattern, from as usize)
+        {
+            Ok(found) => found.map(|range| JsSelection::new(range.start as u32, range.end as u32)),
+            Err(e) => {
+                web_sys::console::error_1(&format!("Error occurred finding next: {}", e).into());
+                None
+            }
+        }
+    }
+
+    /// Replaces every match of `pattern` in the document with `replacement`, as one revision that a
+    /// single undo reverts. Returns the number of matches replaced.
+    #[wasm_bindgen(js_name = replaceAll)]
+    pub fn replace_all(
+        &self,
+        pattern: JsString,
+        replacement: JsString,
+        options: &SearchOptions,
+    ) -> u32 {
+        match self.replace_all_impl(&pattern, &replacement, options) {
+            Ok(count) => count,
+            Err(e) => {
+                web_sys::console::error_1(&format!("Error occurred replacing all: {}", e).into());
+                0
+            }
+        }
+    }
+
+    /// Marks the range `start..end` of the document with the given id, replacing any range already
+    /// marked with that id. The range moves with the text it covers as the document changes, and
+    /// is removed if that text is deleted.
+    #[wasm_bindgen(js_name = setAnnotation)]
+    pub fn set_annotation(&self, id: String, start: u32, end: u32) {
+        self.inner
+            .borrow_mut()
+            .annotations
+            .set(&id, start as i64, end as i64);
+    }
+
+    /// Removes the range marked with the given id. Returns false if there was none.
+    #[wasm_bindgen(js_name = removeAnnotation)]
+    pub fn remove_annotation(&self, id: String) -> bool {
+        self.inner.borrow_mut().annotations.remove(&id)
+    }
+
+    #[wasm_bindgen(js_name = clearAnnotations)]
+    pub fn clear_annotations(&self) {
+        self.inner.borrow_mut().annotations.clear();
+    }
+
+    /// Returns the marked ranges, as of the current document value, as a list of
+    /// `{id, start, end}`.
+    #[wasm_bindgen(js_name = getAnnotations)]
+    pub fn get_annotations(&self) -> JsValue {
+        JsValue::from_serde(self.inner.borrow().annotations.get_all()).unwrap()
+    }
+
+    /// Returns the word, character and paragraph counts of the document as
+    /// `{word_count, character_count, paragraph_count}`.
+    #[wasm_bindgen(js_name = getStats)]
+    pub fn get_stats(&self) -> JsValue {
+        JsValue::from_serde(&self.inner.borrow().current_value.stats()).unwrap()
+    }
+
     #[wasm_bindgen(js_name = getDebugLines)]
     pub fn get_debug_lines(&self) -> JsValue {
         let self_ = self.inner.borrow();
         let mut ret: Vec<String> = Vec::new();
-        ret.append(&mut self_.committed_log.get_debug_lines());
+        ret.append(&mut self_.revision_sync.get_debug_lines());
         ret.append(&mut self_.pending_log.get_debug_lines());
         ret.append(&mut self_.undo_manager.get_debug_lines());
         JsValue::from_serde(&ret).unwrap()
     }
 
-    async fn sync_impl(&self) -> anyhow::Result<()> {
+    fn upgrade(inner: &Weak<RefCell<DocumentEditorModelInner>>) -> Option<Self> {
+        inner.upgrade().map(|inner| Self { inner })
+    }
+
+    fn on_document_event(&self, event: DocumentEvent) {
+        if let DocumentEvent::Typing {
+            user_id,
+            display_name,
+        } = &event
+        {
+            self.inner
+                .borrow_mut()
+                .typing_indicator
+                .on_typing(user_id, display_name, Date::now());
+            return;
+        }
+        if let DocumentEvent::RegionLock {
+            user_id,
+            display_name,
+            revision_number,
+            region,
+        } = event
+        {
+            self.inner.borrow_mut().region_lock_tracker.on_region_lock(
+                &user_id,
+                &display_name,
+                revision_number,
+                region,
+                Date::now(),
+            );
+            return;
+        }
+        if let DocumentEvent::RevisionCommitted { revision_number } = event {
+            if revision_number <= self.inner.borrow().revision_sync.last_revision_number() {
+                // We already have it, most likely because we committed it ourselves.
+                return;
+            }
+        }
         if self.is_sync_running() {
+            self.inner.borrow_mut().sync_requested = true;
+            return;
+        }
+        let self_ = self.clone();
+        spawn_local(async move {
+            if let Err(e) = self_.sync_impl().await {
+                web_sys::console::error_1(&format!("Document Editor sync error: {:?}", e).into());
+            }
+        });
+    }
+
+    /// Tells the other editors of the document that we are typing, and which paragraphs we are
+    /// editing, at most once per `TYPING_HEARTBEAT_INTERVAL`. Heartbeats are best effort, so errors
+    /// are only logged.
+    fn send_typing_heartbeat(&self) {
+        if backend_api::is_update_required() {
+            return;
+        }
+        let request = {
+            let mut self_ = self.inner.borrow_mut();
+            if !self_.typing_indicator.on_local_edit(Date::now()) {
+                return;
+            }
+            let editing_region = match get_editing_region(&self_) {
+                Ok(editing_region) => editing_region,
+                Err(e) => {
+                    web_sys::console::error_1(
+                        &format!("Error getting editing region: {}", e).into(),
+                    );
+                    None
+                }
+            };
+            SendTypingRequest {
+                doc_id: self_.doc_id.clone(),
+                share_token: self_.share_token.clone(),
+                editing_region,
+                revision_number: self_.revision_sync.last_revision_number(),
+            }
+        };
+        spawn_local(async move {
+            if let Err(e) = BackendApi::send_typing(&request).await {
+                web_sys::console::error_1(&format!("Error sending typing heartbeat: {}", e).into());
+            }
+        });
+    }
+
+    async fn sync_impl(&self) -> anyhow::Result<()> {
+        // Changes queue up while offline, and `onConnectivityChange` syncs once back online. They
+        // queue up the same way while the session is expired, until `resumeAfterReauthentication`.
+        if self.is_sync_running()
+            || !self.inner.borrow().sync_schedule.is_online()
+            || self.is_resync_required()
+            || backend_api::is_auth_required()
+        {
             return Ok(());
         }
         self.compress_pending_log()?;
         self.set_sync_running(true);
+        let started_at = telemetry::now_millis();
+        {
+            let mut inner = self.inner.borrow_mut();
+            inner.sync_rebases = 0;
+            inner.sync_discovered_new_revisions = 0;
+        }
         let self_ = self.clone();
-        let result = self_.run_sync_round().await;
+        let (revision_number_before, had_pending_changes) = {
+            let inner = self_.inner.borrow();
+            (
+                inner.revision_sync.last_revision_number(),
+                !inner.pending_log.is_empty(),
+            )
+        };
+        let mut result = self_.apply_queued_changes_to_restore().await;
+        if result.is_ok() {
+            result = self_.run_sync_round().await;
+        }
+        while result.is_ok() && std::mem::take(&mut self_.inner.borrow_mut().sync_requested) {
+            result = self_.run_sync_round().await;
+        }
+        if result.is_ok() {
+            self_.sync_title().await;
+            self_.report_stats().await;
+            self_.report_read_position().await;
+        }
         self_.set_sync_running(false);
+        self_.report_sync_finished(result.is_ok(), telemetry::now_millis() - started_at);
+        // Failed syncs count as idle, so that we back off while the server is unreachable.
+        let had_activity = result.is_ok()
+            && (had_pending_changes
+                || self_.inner.borrow().revision_sync.last_revision_number()
+                    != revision_number_before);
+        // Syncing again cannot succeed until the page is reloaded with a newer build, or until
+        // the document is loaded again after its revisions came out of sequence.
+        if let Err(e) = &result {
+            if let Some(e) = e.downcast_ref::<RevisionSyncError>() {
+                self_.inner.borrow_mut().resync_required |= e.requires_resync();
+            }
+        }
+        if backend_api::is_update_required() || self_.is_resync_required() {
+            return result;
+        }
+        if backend_api::is_auth_required() {
+            self_.notify_auth_required();
+            return result;
+        }
+        let delay = self_
+            .inner
+            .borrow_mut()
+            .sync_schedule
+            .on_sync_finished(had_activity);
+        self_.schedule_sync(delay);
         result
     }
 
+    async fn resync_impl(&self) -> anyhow::Result<()> {
+        if self.is_sync_running() || self.inner.borrow().progressive_insert.is_some() {
+            return Err(DocumentEditorError::InvalidStateError(
+                "Cannot resync while syncing, or while inserting progressively".to_string(),
+            )
+            .into());
+        }
+        backend_api::start_trace();
+        let revision_sync = self.inner.borrow().revision_sync.clone();
+        // Edits made while the log loads are pending like any others, and rebased with them.
+        self.set_sync_running(true);
+        let result = revision_sync.load_all_revisions().await;
+        self.set_sync_running(false);
+        let committed_log = result?;
+        {
+            let mut inner = self.inner.borrow_mut();
+            rebase_onto_committed_log(&mut inner, committed_log)?;
+            inner.resync_required = false;
+        }
+        self.schedule_sync(0.0);
+        Ok(())
+    }
+
+    fn report_telemetry(&self, event: TelemetryEvent) {
+        if let Some(telemetry) = &self.inner.borrow().telemetry {
+            telemetry.report(&event);
+        }
+    }
+
+    fn report_sync_finished(&self, ok: bool, latency_millis: f64) {
+        let event = {
+            let inner = self.inner.borrow();
+            TelemetryEvent::SyncFinished {
+                ok,
+                trace_id: backend_api::current_trace_id(),
+                latency_millis,
+                rebases: inner.sync_rebases,
+                discovered_new_revisions: inner.sync_discovered_new_revisions,
+                undo_stack_depth: inner.undo_manager.len(UndoType::Undo),
+                redo_stack_depth: inner.undo_manager.len(UndoType::Redo),
+            }
+        };
+        self.report_telemetry(event);
+    }
+
+    /// Tells the host that the user must sign in again, if it supplied a handler.
+    fn notify_auth_required(&self) {
+        let handler = match &self.inner.borrow().auth_required_handler {
+            Some(handler) => handler.clone(),
+            None => return,
+        };
+        if let Err(e) = handler.call0(&JsValue::NULL) {
+            web_sys::console::error_1(&format!("Error handling expired session: {:?}", e).into());
+        }
+    }
+
+    /// Asks the host to sync after `delay` milliseconds, if it supplied a scheduler.
+    fn schedule_sync(&self, delay: f64) {
+        let scheduler = match &self.inner.borrow().sync_scheduler {
+            Some(scheduler) => scheduler.clone(),
+            None => return,
+        };
+        if let Err(e) = scheduler.call1(&JsValue::NULL, &JsValue::from_f64(delay)) {
+            web_sys::console::error_1(&format!("Error scheduling sync: {:?}", e).into());
+        }
+    }
+
+    /// Reports the document stats to the backend, so that the document list can show them. Skips
+    /// the report if nothing was committed since the last one, if the last one was too recent, or
+    /// if there are local changes that have not been committed yet, since the stats would not
+    /// match any revision.
+    ///
+    /// The stats are only for display, so errors are logged rather than failing the sync round.
+    /// They also say something about what a document contains, so they are never reported for
+    /// encrypted documents.
+    async fn report_stats(&self) {
+        let request = {
+            let mut self_ = self.inner.borrow_mut();
+            let revision_number = self_.revision_sync.last_revision_number();
+            let now = Date::now();
+            if self_.revision_sync.is_encrypted()
+                || !self_.pending_log.is_empty()
+                || revision_number <= self_.last_reported_stats_revision_number
+                || now < self_.last_stats_reported_at + STATS_REPORT_INTERVAL
+            {
+                return;
+            }
+            self_.last_reported_stats_revision_number = revision_number;
+            self_.last_stats_reported_at = now;
+            let stats = self_.current_value.stats();
+            UpdateDocumentStatsRequest {
+                doc_id: self_.doc_id.clone(),
+                revision_number,
+                word_count: stats.word_count as i64,
+                character_count: stats.character_count as i64,
+                paragraph_count: stats.paragraph_count as i64,
+            }
+        };
+        if let Err(e) = BackendApi::update_document_stats(&request).await {
+            web_sys::console::error_1(&format!("Error reporting document stats: {}", e).into());
+        }
+    }
+
+    /// Picks up the title that the sync round's head check loaded, then saves the local title if
+    /// it was edited and has settled. The remote title comes first, since it was loaded before the
+    /// save.
+    ///
+    /// Errors are logged rather than failing the sync round. The edit stays unsaved, and the next
+    /// sync tries again.
+    async fn sync_title(&self) {
+        let request = {
+            let mut self_ = self.inner.borrow_mut();
+            if let Some(title) = self_.revision_sync.take_head_title() {
+                self_.title_sync.on_remote_title(&title);
+            }
+            match self_.title_sync.title_to_save(Date::now()) {
+                Some(title) => UpdateDocumentTitleRequest {
+                    doc_id: self_.doc_id.clone(),
+                    new_title: title.to_string(),
+                },
+                None => return,
+            }
+        };
+        match BackendApi::update_document_title(&request).await {
+            Ok(_) => self
+                .inner
+                .borrow_mut()
+                .title_sync
+                .on_saved(&request.new_title),
+            Err(e) => {
+                web_sys::console::error_1(&format!("Error saving title: {}", e).into());
+            }
+        }
+    }
+
+    /// Reports the latest revision that the user has seen to the backend, for read receipts. Only
+    /// reports while the page is visible, since a hidden page has not been seen. Skips the report
+    /// if no revision was loaded since the last one, or if the last one was too recent.
+    ///
+    /// Read receipts are only for display, so errors are logged rather than failing the sync
+    /// round.
+    async fn report_read_position(&self) {
+        let request = {
+            let mut self_ = self.inner.borrow_mut();
+            let revision_number = self_.revision_sync.last_revision_number();
+            let now = Date::now();
+            if !self_.sync_schedule.is_visible()
+                || revision_number <= self_.last_reported_read_revision_number
+                || now < self_.last_read_position_reported_at + READ_POSITION_REPORT_INTERVAL
+            {
+                return;
+            }
+            self_.last_reported_read_revision_number = revision_number;
+            self_.last_read_position_reported_at = now;
+            ReportReadPositionRequest {
+                doc_id: self_.doc_id.clone(),
+                share_token: self_.share_token.clone(),
+                revision_number,
+            }
+        };
+        if let Err(e) = BackendApi::report_read_position(&request).await {
+            web_sys::console::error_1(&format!("Error reporting read position: {}", e).into());
+        }
+    }
+
     async fn run_sync_round(&self) -> anyhow::Result<()> {
+        backend_api::start_trace();
         let self_ = self.clone();
         let pending_log_len = self_.inner.borrow().pending_log.len();
-        if pending_log_len == 0 {
+        // While throttled, pending changes keep piling up, and sync_impl compresses them into one
+        // change set each round, to commit once the server lets us.
+        let is_throttled = self_.inner.borrow().revision_sync.is_throttled();
+        // A large insert being applied piece by piece commits once all of its pieces are in, as
+        // one revision.
+        let is_inserting = self_.inner.borrow().progressive_insert.is_some();
+        if pending_log_len == 0 || is_throttled || is_inserting {
             self_.load_new_remote_revisions().await?;
             return Ok(());
         }
@@ -198,6 +1166,10 @@ impl DocumentEditorModel {
                     self_.try_commit_next_pending_revision().await?;
                     loaded_remote = true;
                 }
+                ResponseCode::Throttled => {
+                    self_.compress_pending_log()?;
+                    break;
+                }
                 _ => continue,
             }
         }
@@ -213,13 +1185,39 @@ impl DocumentEditorModel {
         }
         let change_set = self.inner.borrow().pending_log.front().unwrap().clone();
         let self_ = self.clone();
-        let committed_log = self_.inner.borrow().committed_log.clone();
-        match committed_log.commit_local_change_set(&change_set).await? {
+        let revision_sync = self_.inner.borrow().revision_sync.clone();
+        let response_code = match revision_sync.commit_local_change_set(&change_set).await {
+            Ok(response_code) => response_code,
+            Err(e) => {
+                // Submitting them again would fail the same way, so they would never be
+                // committed, and the document would stay diverged from everyone else's.
+                if let Some(status) = e.rejected_status() {
+                    self_.roll_back_pending_changes(status)?;
+                }
+                return Err(e.into());
+            }
+        };
+        match response_code {
             ResponseCode::Ack => {
                 self_.inner.borrow_mut().pending_log.pop_front();
                 Ok(ResponseCode::Ack)
             }
-            ResponseCode::DiscoveredNewRevisions => Ok(ResponseCode::DiscoveredNewRevisions),
+            ResponseCode::DiscoveredNewRevisions => {
+                let pending_change_sets = {
+                    let mut inner = self_.inner.borrow_mut();
+                    inner.sync_discovered_new_revisions += 1;
+                    inner.pending_log.len()
+                };
+                self_.report_telemetry(TelemetryEvent::DiscoveredNewRevisions {
+                    pending_change_sets,
+                });
+                Ok(ResponseCode::DiscoveredNewRevisions)
+            }
+            ResponseCode::Throttled => Ok(ResponseCode::Throttled),
+            ResponseCode::ProtectedRangeModified => {
+                self_.discard_rejected_change_set()?;
+                Ok(ResponseCode::ProtectedRangeModified)
+            }
             _ => Err(DocumentEditorError::InvalidStateError(
                 "Received unknown response code.".to_string(),
             )
@@ -227,27 +1225,116 @@ impl DocumentEditorModel {
         }
     }
 
+    /// Undoes the first pending change set, which the server will never commit because it modifies
+    /// a protected range, and rebases the rest of the pending changes to do without it.
+    fn discard_rejected_change_set(&self) -> anyhow::Result<()> {
+        let mut inner = self.inner.borrow_mut();
+        // The rejected change set applies to the last loaded revision.
+        let committed_value = inner.revision_sync.committed_value()?;
+        if let Some(undo) = inner.pending_log.discard_front(&committed_value)? {
+            apply_rebased_change_set(&mut inner, &undo)?;
+        }
+        inner.protected_range_rejections += 1;
+        Ok(())
+    }
+
+    /// Undoes every pending change set, which the server will never commit, and restores the
+    /// document to the last loaded revision. The rest are dropped along with the first, since they
+    /// were made on top of it, and a rejection because the user may no longer edit the document
+    /// applies to them all.
+    ///
+    /// The undo and redo stacks are cleared too. Typing extends undo items across syncs, so the
+    /// items for the rolled back changes may also hold committed ones, and cannot be told apart.
+    fn roll_back_pending_changes(&self, status: u16) -> anyhow::Result<()> {
+        let mut inner = self.inner.borrow_mut();
+        let committed_value = inner.revision_sync.committed_value()?;
+        if let Some(rollback) = inner.pending_log.discard_all(&committed_value)? {
+            apply_rebased_change_set(&mut inner, &rollback)?;
+        }
+        inner.undo_manager = UndoManager::with_policy(inner.undo_manager.policy());
+        inner.last_pending_composable_until = 0.0;
+        inner.rejected_changes_status = Some(status);
+        Ok(())
+    }
+
+    /// Applies the changes handed over by `restoreQueuedChanges` as local edits, on top of the
+    /// revision they were made on. Later sync rounds rebase them onto the revisions committed
+    /// since, like any other pending changes.
+    async fn apply_queued_changes_to_restore(&self) -> anyhow::Result<()> {
+        let base_revision_number = match &self.inner.borrow().queued_changes_to_restore {
+            Some(queued_changes) => queued_changes.base_revision_number,
+            None => return Ok(()),
+        };
+        // If loading fails, the changes are kept for the next sync. Changes made on an empty
+        // document need no revisions loaded first.
+        if base_revision_number > 0 {
+            self.load_remote_revisions(base_revision_number).await?;
+        }
+        let mut inner = self.inner.borrow_mut();
+        let queued_changes = inner.queued_changes_to_restore.take().unwrap();
+        if inner.revision_sync.last_revision_number() != base_revision_number {
+            return Err(DocumentEditorError::InvalidInputError(format!(
+                "Queued changes were made on revision {}, which the document does not have",
+                base_revision_number
+            ))
+            .into());
+        }
+        let change_set = queued_changes.decode_change_set()?;
+        let undo_item = UndoItem {
+            change_set: inner.current_value.invert(&change_set)?,
+            selection_after: inner.current_selection.clone(),
+        };
+        inner.pending_log.push_back(&change_set);
+        inner.undo_manager.push(UndoType::Undo, undo_item);
+        inner.last_pending_composable_until = 0.0;
+        performance::time(Stage::Apply, || inner.current_value.apply(&change_set))?;
+        inner.annotations.transform(&change_set)?;
+        inner.current_selection = ot::transform_selection(&inner.current_selection, &change_set)?;
+        Ok(())
+    }
+
     async fn load_new_remote_revisions(&self) -> anyhow::Result<()> {
+        self.load_remote_revisions(0).await
+    }
+
+    /// Loads the remote revisions after the last loaded one, up to `pinned_revision_number` unless
+    /// it is 0, and rebases the pending changes onto them.
+    async fn load_remote_revisions(&self, pinned_revision_number: i64) -> anyhow::Result<()> {
         let self_ = self.clone();
-        let committed_log = self.inner.borrow().committed_log.clone();
-        match committed_log.load_new_remote_revisions().await? {
+        let revision_sync = self.inner.borrow().revision_sync.clone();
+        match revision_sync
+            .load_new_remote_revisions(pinned_revision_number)
+            .await?
+        {
             None => Ok(()),
             Some(composed_remote_revisions) => {
-                let mut self_ = self_.inner.borrow_mut();
-                // Transform pending log.
-                let transformed_remote = self_
-                    .pending_log
-                    .transform(&composed_remote_revisions.composed_change_sets)?;
+                let started_at = telemetry::now_millis();
+                let mut inner = self_.inner.borrow_mut();
+                let pending_change_sets = inner.pending_log.len();
+                // Move the region locks that others announced as of our last loaded revision.
+                let (first, last) = composed_remote_revisions.revision_range;
+                inner.region_lock_tracker.on_remote_revisions(
+                    first,
+                    last,
+                    &composed_remote_revisions.composed_change_sets,
+                )?;
 
-                // Apply transformed remote change set to current value.
-                self_.current_value.apply(&transformed_remote)?;
+                // Transform pending log.
+                let transformed_remote = performance::time(Stage::Transform, || {
+                    inner
+                        .pending_log
+                        .transform(&composed_remote_revisions.composed_change_sets)
+                })?;
 
-                // Transform undo/redo stacks.
-                self_.undo_manager.transform(&transformed_remote)?;
+                apply_rebased_change_set(&mut inner, &transformed_remote)?;
+                inner.sync_rebases += 1;
+                drop(inner);
 
-                // Transform current change and selection.
-                self_.current_selection =
-                    ot::transform_selection(&self_.current_selection, &transformed_remote)?;
+                self_.report_telemetry(TelemetryEvent::Rebase {
+                    remote_revisions: last - first + 1,
+                    pending_change_sets,
+                    transform_millis: telemetry::now_millis() - started_at,
+                });
                 Ok(())
             }
         }
@@ -275,10 +1362,114 @@ impl DocumentEditorModel {
         }
     }
 
+    async fn update_from_input_event_progressively_impl(
+        &self,
+        input_event: InputEventParams,
+        on_progress: &Function,
+    ) -> anyhow::Result<()> {
+        let change_set = match &input_event.input_type[..] {
+            "insertFromPaste" | "insertFromDrop" => {
+                let inner = self.inner.borrow();
+                compute_change_set_from_input_event(
+                    &inner.current_selection,
+                    inner.current_value.value_len() as u32,
+                    &input_event,
+                )?
+                .0
+            }
+            _ => return self.update_from_input_event_impl(input_event),
+        };
+        let insert =
+            match ProgressiveInsert::from_change_set(&change_set, PROGRESSIVE_INSERT_CHUNK_LEN) {
+                Some(insert) => insert,
+                None => return self.update_from_input_event_impl(input_event),
+            };
+        {
+            let mut inner = self.inner.borrow_mut();
+            inner.undo_manager.clear(UndoType::Redo);
+            inner.progressive_insert = Some(insert);
+        }
+        let result = self.apply_progressive_insert(on_progress).await;
+        let mut inner = self.inner.borrow_mut();
+        let insert = inner.progressive_insert.take();
+        result?;
+        // The caret goes after the pasted text, wherever other people's edits moved it.
+        if let Some(insert) = insert {
+            inner.current_selection = Selection {
+                offset: insert.offset(),
+                count: 0,
+            };
+        }
+        // Typing after the paste is a revision and an undo step of its own.
+        inner.last_pending_composable_until = 0.0;
+        Ok(())
+    }
+
+    async fn apply_progressive_insert(&self, on_progress: &Function) -> anyhow::Result<()> {
+        let mut is_first_piece = true;
+        loop {
+            let (inserted_len, total_len) = {
+                let mut inner = self.inner.borrow_mut();
+                let inner = &mut *inner;
+                let insert = inner.progressive_insert.as_mut().ok_or_else(|| {
+                    DocumentEditorError::InvalidStateError(String::from(
+                        "Unexpected missing progressive insert",
+                    ))
+                })?;
+                let change_set =
+                    match insert.next_change_set(inner.current_value.value_len() as i64) {
+                        Some(change_set) => change_set,
+                        None => return Ok(()),
+                    };
+                let progress = (insert.inserted_len(), insert.total_len());
+
+                // Each piece is a pending change set of its own, since a sync may be committing
+                // the last one. The next sync compresses them into one. The undo item grows with
+                // each piece, like it does while typing.
+                let inverted_change_set = inner.current_value.invert(&change_set)?;
+                inner.pending_log.push_back(&change_set);
+                if is_first_piece {
+                    let selection_after = inner.current_selection.clone();
+                    inner.undo_manager.push(
+                        UndoType::Undo,
+                        UndoItem {
+                            change_set: inverted_change_set,
+                            selection_after,
+                        },
+                    );
+                } else {
+                    let mut undo_item =
+                        inner.undo_manager.pop(UndoType::Undo).ok_or_else(|| {
+                            DocumentEditorError::InvalidStateError(String::from(
+                                "Unexpected empty undo stack",
+                            ))
+                        })?;
+                    undo_item.change_set =
+                        ot::compose(&inverted_change_set, &undo_item.change_set)?;
+                    inner.undo_manager.push(UndoType::Undo, undo_item);
+                }
+                performance::time(Stage::Apply, || inner.current_value.apply(&change_set))?;
+                inner.annotations.transform(&change_set)?;
+                inner.current_selection =
+                    ot::transform_selection(&inner.current_selection, &change_set)?;
+                progress
+            };
+            is_first_piece = false;
+            if let Err(e) = on_progress.call2(
+                &JsValue::NULL,
+                &JsValue::from(inserted_len as u32),
+                &JsValue::from(total_len as u32),
+            ) {
+                web_sys::console::error_1(&format!("Error reporting progress: {:?}", e).into());
+            }
+            yield_to_queued_work().await;
+        }
+    }
+
     fn process_undo_command(&self, undo_type: UndoType) -> anyhow::Result<()> {
         let mut self_ = self.inner.borrow_mut();
 
-        let undo_item = match self_.undo_manager.pop(undo_type) {
+        let undo_item = match self_.undo_manager.pop_for_command(undo_type) {
             Some(undo_item) => undo_item,
             None => {
                 return Ok(());
@@ -294,7 +1485,10 @@ impl DocumentEditorModel {
             UndoType::Undo => self_.undo_manager.push(UndoType::Redo, new_undo_item),
             UndoType::Redo => self_.undo_manager.push(UndoType::Undo, new_undo_item),
         }
-        self_.current_value.apply(&undo_item.change_set)?;
+        performance::time(Stage::Apply, || {
+            self_.current_value.apply(&undo_item.change_set)
+        })?;
+        self_.annotations.transform(&undo_item.change_set)?;
         self_.current_selection = undo_item.selection_after;
 
         Ok(())
@@ -302,11 +1496,19 @@ impl DocumentEditorModel {
 
     fn process_edit_command(&self, input_event: &InputEventParams) -> anyhow::Result<()> {
         let mut self_ = self.inner.borrow_mut();
-        let (change_set, should_start_new_revision) = compute_change_set_from_input_event(
-            &self_.current_selection,
-            self_.current_value.value_len() as u32,
-            input_event,
-        )?;
+        let (change_set, should_start_new_revision) = performance::time(Stage::Diff, || {
+            compute_change_set_from_input_event(
+                &self_.current_selection,
+                self_.current_value.value_len() as u32,
+                input_event,
+            )
+        })?;
+        // Edits that leave the document unchanged, like typing a character over the same selected
+        // character, do not need a revision or an undo step.
+        if self_.current_value.is_identity(&change_set)? {
+            self_.current_selection = input_event.selection.into();
+            return Ok(());
+        }
         let should_start_new_revision = should_start_new_revision
             || self_.pending_log.is_empty()
             || Date::now() > self_.last_pending_composable_until;
@@ -333,11 +1535,51 @@ impl DocumentEditorModel {
             undo_item.change_set = ot::compose(&inverted_change_set, &undo_item.change_set)?;
             self_.undo_manager.push(UndoType::Undo, undo_item);
         }
-        self_.current_value.apply(&change_set)?;
+        performance::time(Stage::Apply, || self_.current_value.apply(&change_set))?;
+        self_.annotations.transform(&change_set)?;
         self_.current_selection = input_event.selection.into();
         Ok(())
     }
 
+    fn replace_all_impl(
+        &self,
+        pattern: &JsString,
+        replacement: &JsString,
+        options: &SearchOptions,
+    ) -> anyhow::Result<u32> {
+        let mut self_ = self.inner.borrow_mut();
+        let value_len = self_.current_value.value_len();
+        let pattern: Vec<u16> = pattern.iter().collect();
+        let replacement = js_string_to_vec_u32(replacement);
+        let matches = self_
+            .current_value
+            .find_all(&SearchPattern::new(&pattern, &(*options).into()))?;
+        let mut builder = ot::SpliceBuilder::new(value_len as i64);
+        for range in matches.iter() {
+            builder.splice((range.start as i64)..(range.end as i64), &replacement)?;
+        }
+        let change_set = builder.finish();
+        if self_.current_value.is_identity(&change_set)? {
+            return Ok(matches.len() as u32);
+        }
+
+        // Replacing is a revision and an undo step of its own. It never composes with typing
+        // before or after it.
+        let undo_item = UndoItem {
+            change_set: self_.current_value.invert(&change_set)?,
+            selection_after: self_.current_selection.clone(),
+        };
+        self_.pending_log.push_back(&change_set);
+        self_.undo_manager.clear(UndoType::Redo);
+        self_.undo_manager.push(UndoType::Undo, undo_item);
+        self_.last_pending_composable_until = 0.0;
+
+        performance::time(Stage::Apply, || self_.current_value.apply(&change_set))?;
+        self_.annotations.transform(&change_set)?;
+        self_.current_selection = ot::transform_selection(&self_.current_selection, &change_set)?;
+        Ok(matches.len() as u32)
+    }
+
     fn compress_pending_log(&self) -> anyhow::Result<()> {
         let mut self_ = self.inner.borrow_mut();
         self_.pending_log.compress()?;
@@ -416,54 +1658,131 @@ impl JsSelection {
     }
 }
 
-fn js_string_to_vec_u32(js_string: &JsString) -> Vec<u32> {
-    let mut ret = Vec::new();
-    for ch in js_string.iter() {
-        ret.push(ch as u32);
+/// The paragraphs around the selection, as a region of the last loaded revision, to announce as
+/// our region lock. `None` if our pending changes wrote all of them, since nobody else can be
+/// editing them yet.
+fn get_editing_region(inner: &DocumentEditorModelInner) -> anyhow::Result<Option<Selection>> {
+    let selection_start = inner.current_selection.offset as usize;
+    let selection_end = selection_start + inner.current_selection.count as usize;
+    let paragraphs = inner
+        .current_value
+        .get_paragraphs_range(selection_start..selection_end);
+    let region = Selection {
+        offset: paragraphs.start as i64,
+        count: (paragraphs.end - paragraphs.start) as i64,
+    };
+    let pending = inner
+        .pending_log
+        .compose_range(0..inner.pending_log.len())?;
+    Ok(region_locks::region_before_pending(
+        &region,
+        pending.as_ref(),
+    )?)
+}
+
+/// Applies a change set that the pending changes were just rebased onto, like transformed remote
+/// revisions, to the current value and everything that points into it.
+fn apply_rebased_change_set(
+    inner: &mut DocumentEditorModelInner,
+    change_set: &ChangeSet,
+) -> anyhow::Result<()> {
+    // Move the scroll anchor, which needs the text that the change set deletes.
+    let DocumentEditorModelInner {
+        scroll_anchor,
+        current_value,
+        ..
+    } = &mut *inner;
+    if let Some(anchor) = scroll_anchor.as_mut() {
+        anchor.transform(current_value, change_set)?;
     }
-    ret
+    if let Some(insert) = inner.progressive_insert.as_mut() {
+        insert.transform(change_set)?;
+    }
+
+    // Apply the change set to current value.
+    performance::time(Stage::Apply, || inner.current_value.apply(change_set))?;
+    inner.annotations.transform(change_set)?;
+
+    // Transform undo/redo stacks. If that collapsed undo items, the one that typing would extend
+    // may be gone, so typing starts a new one.
+    let undo_stack_len = inner.undo_manager.len(UndoType::Undo);
+    inner.undo_manager.transform(change_set)?;
+    if inner.undo_manager.len(UndoType::Undo) < undo_stack_len {
+        inner.last_pending_composable_until = 0.0;
+    }
+
+    // Transform current change and selection.
+    inner.current_selection = ot::transform_selection(&inner.current_selection, change_set)?;
+    Ok(())
 }
 
-pub fn get_change_set_description(change_set: &ChangeSet) -> String {
-    let mut ret = String::new();
-    let mut is_first = true;
-    for change_op in &change_set.ops {
-        if change_op.op.is_none() {
-            continue;
+/// Replaces the committed log with one loaded from scratch, and rebases the pending changes onto its
+/// revisions after the last one that was loaded before.
+///
+/// If the new log disagrees with the old one about the document as of that revision, the old one
+/// had diverged, and the current value, undo stacks and annotations were built on it. The pending
+/// changes are then kept as one change set, applied to the new log's version of the document, and
+/// the rest is let go. Fails, changing nothing, if the pending changes do not apply to it.
+fn rebase_onto_committed_log(
+    inner: &mut DocumentEditorModelInner,
+    committed_log: CommittedLog,
+) -> anyhow::Result<()> {
+    let base_revision_number = inner.revision_sync.last_revision_number();
+    if committed_log.last_revision_number() < base_revision_number {
+        return Err(DocumentEditorError::InvalidStateError(format!(
+            "The server has {} revisions, but {} were loaded before",
+            committed_log.last_revision_number(),
+            base_revision_number
+        ))
+        .into());
+    }
+    let base_value = committed_log.value_at(base_revision_number)?;
+    if base_value != inner.revision_sync.committed_value()? {
+        let pending = inner
+            .pending_log
+            .compose_range(0..inner.pending_log.len())?;
+        let mut value = ChangeSet::new();
+        value.insert_slice(&ot::utils::u16_slice_to_u32_vec(&base_value));
+        if let Some(pending) = &pending {
+            value = ot::compose(&value, pending).map_err(|e| {
+                DocumentEditorError::InvalidStateError(format!(
+                    "Local changes do not apply to the document as loaded again: {}",
+                    e
+                ))
+            })?;
         }
-        if is_first {
-            is_first = false;
-        } else {
-            ret.push_str(", ");
-        }
-        let op = change_op.op.as_ref().unwrap();
-        match op {
-            Op::Retain(retain) => {
-                write!(&mut ret, "Retain({})", retain.count).unwrap();
-            }
-            Op::Delete(delete) => {
-                write!(&mut ret, "Delete({})", delete.count).unwrap();
-            }
-            Op::Insert(insert) => {
-                let mut content_u16: Vec<u16> = Vec::new();
-                for ch in &insert.content {
-                    let ch = *ch as u16;
-                    if ch == '\n' as u16 {
-                        content_u16.push('\\' as u16);
-                        content_u16.push('n' as u16);
-                    } else {
-                        content_u16.push(ch);
-                    }
-                }
-                let content_str: String =
-                    String::from_utf16(&content_u16).unwrap_or_else(|_| "".to_string());
-                if content_str == "\\n" {
-                    write!(&mut ret, "Insert('\\n')").unwrap();
-                } else {
-                    write!(&mut ret, "Insert(\"{}\")", &content_str).unwrap();
-                }
-            }
+        let mut current_value = DocumentValue::new();
+        current_value.apply(&value)?;
+        let value_len = current_value.value_len() as i64;
+        inner.current_value = current_value;
+        inner.pending_log = PendingLog::new();
+        if let Some(pending) = &pending {
+            inner.pending_log.push_back(pending);
         }
+        inner.undo_manager = UndoManager::with_policy(inner.undo_manager.policy());
+        inner.annotations = Annotations::new();
+        inner.scroll_anchor = None;
+        let offset = inner.current_selection.offset.min(value_len);
+        inner.current_selection = Selection {
+            offset,
+            count: inner.current_selection.count.min(value_len - offset),
+        };
+    }
+    let remote = committed_log.compose_range(base_revision_number as usize..committed_log.len())?;
+    inner.revision_sync.replace_committed_log(committed_log);
+    inner.region_lock_tracker = RegionLockTracker::new();
+    if let Some(remote) = remote {
+        let transformed_remote =
+            performance::time(Stage::Transform, || inner.pending_log.transform(&remote))?;
+        apply_rebased_change_set(inner, &transformed_remote)?;
+    }
+    Ok(())
+}
+
+fn js_string_to_vec_u32(js_string: &JsString) -> Vec<u32> {
+    let mut ret = Vec::new();
+    for ch in js_string.iter() {
+        ret.push(ch as u32);
     }
     ret
 }
@@ -527,7 +1846,19 @@ fn compute_change_set_from_input_event(
     Ok((change_set, should_start_new_revision))
 }
 
-fn slice_to_js_string(value: &[u16]) -> JsString {
+/// Lets the promise callbacks that are already queued, like the responses to a sync's requests,
+/// run before continuing.
+async fn yield_to_queued_work() {
+    // A resolved promise never rejects.
+    let _ = JsFuture::from(Promise::resolve(&JsValue::UNDEFINED)).await;
+}
+
+fn set_property(object: &js_sys::Object, key: &str, value: &JsValue) {
+    // Only fails for frozen objects and proxies, and these are neither.
+    js_sys::Reflect::set(object, &JsValue::from(key), value).unwrap();
+}
+
+pub fn slice_to_js_string(value: &[u16]) -> JsString {
     if value.len() < (1usize << 16) {
         JsString::from_char_code(value)
     } else {
@@ -0,0 +1,38 @@
+use prost::Message;
+use serde::{Deserialize, Serialize};
+
+use ot::writing_proto::ChangeSet;
+
+/// Whether the editor is offline, and how many local changes are waiting to be committed, for the
+/// host to show.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct OfflineStatus {
+    pub offline: bool,
+    pub queued_changes: usize,
+}
+
+/// The local changes that have not been committed yet, composed into one change set on top of the
+/// last loaded revision. The host saves them, for example in IndexedDB, so that changes made
+/// offline survive closing the page, and hands them back when the document is opened again.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QueuedChanges {
+    pub base_revision_number: i64,
+    // The encoded change set.
+    pub change_set: Vec<u8>,
+}
+
+impl QueuedChanges {
+    pub fn new(base_revision_number: i64, change_set: &ChangeSet) -> Self {
+        let mut change_set_binary = Vec::with_capacity(change_set.encoded_len());
+        // Encoding into a Vec only fails when it runs out of room, and a Vec grows as needed.
+        change_set.encode(&mut change_set_binary).unwrap();
+        Self {
+            base_revision_number,
+            change_set: change_set_binary,
+        }
+    }
+
+    pub fn decode_change_set(&self) -> Result<ChangeSet, prost::DecodeError> {
+        ChangeSet::decode(&self.change_set[..])
+    }
+}
@@ -0,0 +1,405 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use js_sys::Date;
+use prost::Message;
+use thiserror::Error;
+
+use editor_core::committed_log::{CommittedLog, CommittedLogError, ComposedRemoteRevisions};
+use ot::writing_proto::submit_document_change_set_response;
+use ot::writing_proto::{
+    ChangeSet, DocumentRevision, GetDocumentHeadRequest, GetDocumentRevisionsRequest,
+    SubmitDocumentChangeSetRequest,
+};
+use ot::OtError;
+
+use crate::backend_api::{BackendApi, BackendApiError};
+use crate::encryption::{self, DocumentCipher, EncryptionError};
+use crate::ot_worker::{OtWorker, OT_WORKER_MIN_BYTES};
+use crate::signing::RevisionSigner;
+
+/// Sent with every change set, so that revisions can be traced back to the build that made them.
+const CLIENT_ID: &str = concat!("wasm/", env!("CARGO_PKG_VERSION"));
+
+#[derive(Debug, Error)]
+pub enum RevisionSyncError {
+    #[error("Backend API Error: {0}")]
+    BackendApiError(BackendApiError),
+    #[error("Committed Log Error: {0}")]
+    CommittedLogError(CommittedLogError),
+    #[error("Encryption Error: {0}")]
+    EncryptionError(EncryptionError),
+    #[error("Invalid Response Error: {0}")]
+    InvalidResponseError(String),
+    #[error("Invalid State Error: {0}")]
+    InvalidStateError(String),
+}
+
+impl RevisionSyncError {
+    /// True if the committed log may have diverged from the server's revision log, so that syncing
+    /// again would fail the same way. See `CommittedLogError::requires_resync`.
+    pub fn requires_resync(&self) -> bool {
+        match self {
+            RevisionSyncError::CommittedLogError(e) => e.requires_resync(),
+            _ => false,
+        }
+    }
+
+    /// The HTTP status, if the server rejected the request for good. See
+    /// `BackendApiError::Rejected`.
+    pub fn rejected_status(&self) -> Option<u16> {
+        match self {
+            RevisionSyncError::BackendApiError(BackendApiError::Rejected { status, .. }) => {
+                Some(*status)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Keeps the committed log in sync with the document's revision log on the server.
+#[derive(Clone)]
+pub struct RevisionSync {
+    inner: Rc<RefCell<RevisionSyncInner>>,
+}
+
+struct RevisionSyncInner {
+    doc_id: String,
+    // Sent with every request when the document was opened through a share link. Empty otherwise.
+    share_token: String,
+    // Set for end-to-end encrypted documents. Change sets are encrypted before they are sent, and
+    // revisions are decrypted as soon as they are received.
+    cipher: Option<DocumentCipher>,
+    // Set when the author signs their revisions. Each change set is signed as it is sent.
+    signer: Option<RevisionSigner>,
+    // Set when the host gave us a web worker. Large batches of remote revisions are composed there.
+    ot_worker: Option<OtWorker>,
+    // Random, and sent with every change set, so that revisions from this editing session can be
+    // grouped together. Empty if no randomness was available.
+    session_id: String,
+    // When the server throttles our commits, we hold off until this time, in milliseconds since
+    // the epoch.
+    throttled_until: f64,
+    committed_log: CommittedLog,
+    // The title from the last head check, until `take_head_title` takes it.
+    head_title: Option<String>,
+}
+
+impl RevisionSync {
+    pub fn new(doc_id: &str, share_token: &str) -> Self {
+        Self {
+            inner: Rc::new(RefCell::new(RevisionSyncInner {
+                doc_id: doc_id.to_string(),
+                share_token: share_token.to_string(),
+                cipher: None,
+                signer: None,
+                ot_worker: None,
+                session_id: new_session_id(),
+                throttled_until: 0.0,
+                committed_log: CommittedLog::new(),
+                head_title: None,
+            })),
+        }
+    }
+
+    pub fn set_cipher(&self, cipher: DocumentCipher) {
+        self.inner.borrow_mut().cipher = Some(cipher);
+    }
+
+    pub fn set_signer(&self, signer: RevisionSigner) {
+        self.inner.borrow_mut().signer = Some(signer);
+    }
+
+    pub fn set_ot_worker(&self, ot_worker: OtWorker) {
+        self.inner.borrow_mut().ot_worker = Some(ot_worker);
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.inner.borrow().cipher.is_some()
+    }
+
+    /// Commits the given local change set, sending it to the server to add to the document
+    /// revisions log.
+    ///
+    /// Returns one of these response codes:
+    ///
+    /// - Ack: The local revision was successfully committed to the document revisions log on the
+    /// server. It has also been appended to the committed log on the client.
+    ///
+    /// - DiscoveredNewRevisions: We discovered new remote revisions on the server that the client
+    /// does not yet know about. The given local revision was not committed.
+    ///
+    /// - Throttled: We committed too many revisions recently. The given local revision was not
+    /// committed. `is_throttled` returns true until the server says we may try again.
+    ///
+    /// - ProtectedRangeModified: The local revision modifies a range that only the document's owner
+    /// may edit. It was not committed, and never will be.
+    pub async fn commit_local_change_set(
+        &self,
+        change_set: &ChangeSet,
+    ) -> Result<submit_document_change_set_response::ResponseCode, RevisionSyncError> {
+        use submit_document_change_set_response::ResponseCode;
+        let mut request = SubmitDocumentChangeSetRequest::default();
+        {
+            let self_ = self.inner.borrow();
+            match &self_.cipher {
+                Some(cipher) => {
+                    request.encrypted_change_set = cipher
+                        .encrypt_change_set(change_set)
+                        .map_err(RevisionSyncError::EncryptionError)?;
+                }
+                None => request.change_set = Some(change_set.clone()),
+            }
+            request.doc_id = self_.doc_id.clone();
+            request.share_token = self_.share_token.clone();
+            request.on_revision_number = self_.committed_log.last_revision_number();
+            request.client_id = CLIENT_ID.to_string();
+            request.session_id = self_.session_id.clone();
+            if let Some(signer) = &self_.signer {
+                let change_set_binary = match &request.change_set {
+                    Some(change_set) => {
+                        let mut change_set_binary = Vec::with_capacity(change_set.encoded_len());
+                        change_set.encode(&mut change_set_binary).map_err(|e| {
+                            RevisionSyncError::InvalidStateError(format!(
+                                "Could not encode change set to sign: {}",
+                                e
+                            ))
+                        })?;
+                        change_set_binary
+                    }
+                    None => request.encrypted_change_set.clone(),
+                };
+                request.signature = Some(signer.sign(
+                    &request.doc_id,
+                    request.on_revision_number + 1,
+                    &change_set_binary,
+                ));
+            }
+        }
+        let self_ = self.inner.clone();
+        let mut response = BackendApi::submit_document_change_set(&request)
+            .await
+            .map_err(RevisionSyncError::BackendApiError)?;
+        match response.response_code() {
+            ResponseCode::DiscoveredNewRevisions => {
+                // New remote revisions were discovered. Could not commit this local revision.
+                Ok(ResponseCode::DiscoveredNewRevisions)
+            }
+            ResponseCode::Throttled => {
+                self_.borrow_mut().throttled_until =
+                    Date::now() + response.retry_after_millis as f64;
+                Ok(ResponseCode::Throttled)
+            }
+            ResponseCode::ProtectedRangeModified => Ok(ResponseCode::ProtectedRangeModified),
+            ResponseCode::Ack => {
+                // Successfully committed this local revision. If the change set was an identity,
+                // the server acknowledges it without committing a revision.
+                if response.revisions.len() > 1 {
+                    return Err(RevisionSyncError::InvalidResponseError(format!(
+                        "Expected response to contain at most 1 document revision. Contained {}.",
+                        response.revisions.len()
+                    )));
+                }
+                let mut self_ = self_.borrow_mut();
+                encryption::decrypt_revisions(self_.cipher.as_ref(), &mut response.revisions)
+                    .map_err(RevisionSyncError::EncryptionError)?;
+                if let Some(revision) = response.revisions.pop() {
+                    self_
+                        .committed_log
+                        .push_local_revision(revision)
+                        .map_err(RevisionSyncError::CommittedLogError)?;
+                }
+                Ok(ResponseCode::Ack)
+            }
+            _ => Err(RevisionSyncError::InvalidResponseError(String::from(
+                "Response status code was not Ack, DiscoveredNewRevisions, Throttled, or \
+                ProtectedRangeModified",
+            ))),
+        }
+    }
+
+    /// Loads new remote revisions from the server.
+    ///
+    /// If there are new remote revisions, loads them all from the server and adds them to the
+    /// committed log. Composes the new remote revisions into a single change set and returns them.
+    /// We can use the composed remote revisions to transform our local revisions.
+    ///
+    /// If there are no new remote revisions, returns `None`.
+    ///
+    /// If `pinned_revision_number` is positive, no revisions after it are loaded.
+    pub async fn load_new_remote_revisions(
+        &self,
+        pinned_revision_number: i64,
+    ) -> Result<Option<ComposedRemoteRevisions>, RevisionSyncError> {
+        // Most syncs find nothing new. Checking the head first skips reading the revision log in
+        // that case, and picks up the title too. Pinned reads always want the revisions up to the
+        // pin.
+        let (head_request, last_revision_number) = {
+            let self_ = self.inner.borrow();
+            let head_request = GetDocumentHeadRequest {
+                doc_id: self_.doc_id.clone(),
+                share_token: self_.share_token.clone(),
+            };
+            (head_request, self_.committed_log.last_revision_number())
+        };
+        if pinned_revision_number == 0 {
+            let head = BackendApi::get_document_head(&head_request)
+                .await
+                .map_err(RevisionSyncError::BackendApiError)?;
+            self.inner.borrow_mut().head_title = Some(head.title);
+            if head.last_revision_number == last_revision_number {
+                return Ok(None);
+            }
+        }
+
+        // Query for new remote revisions that have revision_number greater than the last revision
+        // number in our log. They are only added to the committed log once we have all of them, so
+        // that a failed batch leaves the log as it was.
+        let revisions = self
+            .fetch_revisions(last_revision_number, pinned_revision_number)
+            .await?;
+        if let Some(composed_change_sets) = self.compose_in_ot_worker(&revisions).await? {
+            return self
+                .inner
+                .borrow_mut()
+                .committed_log
+                .append_composed_remote_revisions(revisions, composed_change_sets)
+                .map_err(RevisionSyncError::CommittedLogError);
+        }
+        self.inner
+            .borrow_mut()
+            .committed_log
+            .append_remote_revisions(revisions)
+            .map_err(RevisionSyncError::CommittedLogError)
+    }
+
+    /// Composes the revisions' change sets in the web worker, if there is one and they are large
+    /// enough to be worth it. Returns `None` if they should be composed here instead, including
+    /// when the worker fails.
+    async fn compose_in_ot_worker(
+        &self,
+        revisions: &[DocumentRevision],
+    ) -> Result<Option<ChangeSet>, RevisionSyncError> {
+        let ot_worker = match &self.inner.borrow().ot_worker {
+            Some(ot_worker) => ot_worker.clone(),
+            None => return Ok(None),
+        };
+        let encoded_len: usize = revisions
+            .iter()
+            .filter_map(|revision| revision.change_set.as_ref())
+            .map(|change_set| change_set.encoded_len())
+            .sum();
+        if encoded_len < OT_WORKER_MIN_BYTES {
+            return Ok(None);
+        }
+        // Invalid revisions would fail the same way once composed, so fail before posting them.
+        self.inner
+            .borrow()
+            .committed_log
+            .check_remote_revisions(revisions)
+            .map_err(RevisionSyncError::CommittedLogError)?;
+        let change_sets: Vec<&ChangeSet> = revisions
+            .iter()
+            .filter_map(|revision| revision.change_set.as_ref())
+            .collect();
+        match ot_worker.compose(&change_sets).await {
+            Ok(composed_change_sets) => Ok(Some(composed_change_sets)),
+            Err(e) => {
+                web_sys::console::warn_1(
+                    &format!("Composing in the ot worker failed, composing here: {}", e).into(),
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// Loads every revision of the document into a new committed log, leaving this one's as it
+    /// was. For starting over when this one can no longer be trusted. See `replace_committed_log`.
+    pub async fn load_all_revisions(&self) -> Result<CommittedLog, RevisionSyncError> {
+        let revisions = self.fetch_revisions(0, 0).await?;
+        let mut committed_log = CommittedLog::new();
+        committed_log
+            .append_remote_revisions(revisions)
+            .map_err(RevisionSyncError::CommittedLogError)?;
+        Ok(committed_log)
+    }
+
+    /// Swaps in a committed log from `load_all_revisions`, and returns the one it replaces.
+    pub fn replace_committed_log(&self, committed_log: CommittedLog) -> CommittedLog {
+        std::mem::replace(&mut self.inner.borrow_mut().committed_log, committed_log)
+    }
+
+    /// Reads the revisions after `after_revision_number` from the backend API, in batches, and
+    /// decrypts them.
+    async fn fetch_revisions(
+        &self,
+        after_revision_number: i64,
+        pinned_revision_number: i64,
+    ) -> Result<Vec<DocumentRevision>, RevisionSyncError> {
+        let mut request = {
+            let self_ = self.inner.borrow();
+            GetDocumentRevisionsRequest {
+                doc_id: self_.doc_id.clone(),
+                share_token: self_.share_token.clone(),
+                after_revision_number,
+                pinned_revision_number,
+            }
+        };
+        let mut revisions: Vec<DocumentRevision> = Vec::new();
+        loop {
+            let mut response = BackendApi::get_document_revisions(&request)
+                .await
+                .map_err(RevisionSyncError::BackendApiError)?;
+            if response.revisions.is_empty() {
+                break;
+            }
+            encryption::decrypt_revisions(
+                self.inner.borrow().cipher.as_ref(),
+                &mut response.revisions,
+            )
+            .map_err(RevisionSyncError::EncryptionError)?;
+            revisions.append(&mut response.revisions);
+            if response.end_of_revisions {
+                break;
+            }
+            request.after_revision_number = response.last_revision_number;
+        }
+        Ok(revisions)
+    }
+
+    /// Returns the document's title as of the last head check, once. `None` if there was no head
+    /// check since the last call.
+    pub fn take_head_title(&self) -> Option<String> {
+        self.inner.borrow_mut().head_title.take()
+    }
+
+    /// True if the server asked us to stop committing revisions for a while.
+    pub fn is_throttled(&self) -> bool {
+        Date::now() < self.inner.borrow().throttled_until
+    }
+
+    /// The revision number of the last committed revision, or 0 if there are none.
+    pub fn last_revision_number(&self) -> i64 {
+        self.inner.borrow().committed_log.last_revision_number()
+    }
+
+    /// The document as of the last committed revision, replayed from the whole committed log.
+    pub fn committed_value(&self) -> Result<Vec<u16>, OtError> {
+        let self_ = self.inner.borrow();
+        self_
+            .committed_log
+            .value_at(self_.committed_log.last_revision_number())
+    }
+
+    pub fn get_debug_lines(&self) -> Vec<String> {
+        self.inner.borrow().committed_log.get_debug_lines()
+    }
+}
+
+fn new_session_id() -> String {
+    let mut bytes = [0u8; 8];
+    match getrandom::getrandom(&mut bytes) {
+        Ok(()) => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+        Err(_) => String::new(),
+    }
+}
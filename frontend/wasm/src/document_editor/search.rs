@@ -0,0 +1,27 @@
+use wasm_bindgen::prelude::*;
+
+/// Options for finding text in a document.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SearchOptions {
+    /// If false, letters match regardless of case.
+    pub match_case: bool,
+    /// If true, only matches that are not part of a longer word count.
+    pub whole_word: bool,
+}
+
+#[wasm_bindgen]
+impl SearchOptions {
+    pub fn new(match_case: bool, whole_word: bool) -> Self {
+        Self {
+            match_case,
+            whole_word,
+        }
+    }
+}
+
+impl From<SearchOptions> for editor_core::search::SearchOptions {
+    fn from(options: SearchOptions) -> editor_core::search::SearchOptions {
+        editor_core::search::SearchOptions::new(options.match_case, options.whole_word)
+    }
+}
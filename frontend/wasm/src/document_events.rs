@@ -0,0 +1,167 @@
+//! Live document events, for syncing as soon as someone else commits a revision, and for showing
+//! who else is typing, and where.
+//!
+//! Events are streamed from the backend as server-sent events, which work where WebSockets do not.
+//! The browser reconnects on its own if the stream drops.
+
+use std::rc::Rc;
+
+use serde::Deserialize;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{EventSource, MessageEvent};
+
+use ot::writing_proto::Selection;
+
+use crate::backend_api::BackendApi;
+
+pub enum DocumentEvent {
+    /// Someone committed the revision with this number.
+    RevisionCommitted { revision_number: i64 },
+    /// Someone else is typing. Sent every couple of seconds while they type.
+    Typing {
+        user_id: String,
+        display_name: String,
+    },
+    /// Someone else is editing this region of the document, as of revision `revision_number`.
+    /// Sent with their typing heartbeats, and when we subscribe.
+    RegionLock {
+        user_id: String,
+        display_name: String,
+        revision_number: i64,
+        region: Selection,
+    },
+    /// We fell too far behind, and missed some events.
+    Lagged,
+}
+
+#[derive(Deserialize)]
+struct RevisionCommittedData {
+    revision_number: i64,
+}
+
+#[derive(Deserialize)]
+struct TypingData {
+    user_id: String,
+    display_name: String,
+}
+
+#[derive(Deserialize)]
+struct RegionLockData {
+    user_id: String,
+    display_name: String,
+    revision_number: i64,
+    offset: i64,
+    count: i64,
+}
+
+/// An open event stream for one document. The stream is closed when this is dropped.
+pub struct DocumentEventSource {
+    event_source: EventSource,
+    _on_revision_committed: Closure<dyn FnMut(MessageEvent)>,
+    _on_typing: Closure<dyn FnMut(MessageEvent)>,
+    _on_region_lock: Closure<dyn FnMut(MessageEvent)>,
+    _on_lagged: Closure<dyn FnMut(MessageEvent)>,
+}
+
+impl DocumentEventSource {
+    pub fn open(
+        doc_id: &str,
+        share_token: &str,
+        on_event: impl Fn(DocumentEvent) + 'static,
+    ) -> Result<Self, JsValue> {
+        let event_source = EventSource::new(&BackendApi::document_events_url(doc_id, share_token))?;
+        let on_event = Rc::new(on_event);
+
+        let on_revision_committed = {
+            let on_event = on_event.clone();
+            Closure::wrap(Box::new(move |event: MessageEvent| {
+                let data = event.data().as_string().unwrap_or_default();
+                let data = js_sys::JSON::parse(&data)
+                    .ok()
+                    .and_then(|data| data.into_serde::<RevisionCommittedData>().ok());
+                match data {
+                    Some(data) => on_event(DocumentEvent::RevisionCommitted {
+                        revision_number: data.revision_number,
+                    }),
+                    None => web_sys::console::error_1(
+                        &format!("Invalid revision_committed event: {:?}", event.data()).into(),
+                    ),
+                }
+            }) as Box<dyn FnMut(MessageEvent)>)
+        };
+        event_source.add_event_listener_with_callback(
+            "revision_committed",
+            on_revision_committed.as_ref().unchecked_ref(),
+        )?;
+
+        let on_typing = {
+            let on_event = on_event.clone();
+            Closure::wrap(Box::new(move |event: MessageEvent| {
+                let data = event.data().as_string().unwrap_or_default();
+                let data = js_sys::JSON::parse(&data)
+                    .ok()
+                    .and_then(|data| data.into_serde::<TypingData>().ok());
+                match data {
+                    Some(data) => on_event(DocumentEvent::Typing {
+                        user_id: data.user_id,
+                        display_name: data.display_name,
+                    }),
+                    None => web_sys::console::error_1(
+                        &format!("Invalid typing event: {:?}", event.data()).into(),
+                    ),
+                }
+            }) as Box<dyn FnMut(MessageEvent)>)
+        };
+        event_source
+            .add_event_listener_with_callback("typing", on_typing.as_ref().unchecked_ref())?;
+
+        let on_region_lock = {
+            let on_event = on_event.clone();
+            Closure::wrap(Box::new(move |event: MessageEvent| {
+                let data = event.data().as_string().unwrap_or_default();
+                let data = js_sys::JSON::parse(&data)
+                    .ok()
+                    .and_then(|data| data.into_serde::<RegionLockData>().ok());
+                match data {
+                    Some(data) => on_event(DocumentEvent::RegionLock {
+                        user_id: data.user_id,
+                        display_name: data.display_name,
+                        revision_number: data.revision_number,
+                        region: Selection {
+                            offset: data.offset,
+                            count: data.count,
+                        },
+                    }),
+                    None => web_sys::console::error_1(
+                        &format!("Invalid region_lock event: {:?}", event.data()).into(),
+                    ),
+                }
+            }) as Box<dyn FnMut(MessageEvent)>)
+        };
+        event_source.add_event_listener_with_callback(
+            "region_lock",
+            on_region_lock.as_ref().unchecked_ref(),
+        )?;
+
+        let on_lagged = Closure::wrap(Box::new(move |_: MessageEvent| {
+            on_event(DocumentEvent::Lagged);
+        }) as Box<dyn FnMut(MessageEvent)>);
+        event_source
+            .add_event_listener_with_callback("lagged", on_lagged.as_ref().unchecked_ref())?;
+
+        Ok(Self {
+            event_source,
+            _on_revision_committed: on_revision_committed,
+            _on_typing: on_typing,
+            _on_region_lock: on_region_lock,
+            _on_lagged: on_lagged,
+        })
+    }
+}
+
+impl Drop for DocumentEventSource {
+    fn drop(&mut self) {
+        self.event_source.close();
+    }
+}
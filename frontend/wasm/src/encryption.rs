@@ -0,0 +1,214 @@
+//! End-to-end encryption of document change sets.
+//!
+//! An encrypted document's change sets are encrypted with AES-256-GCM under a key that only
+//! clients hold. The server stores and returns them without being able to read them, and knows the
+//! key only by its fingerprint, the hex-encoded SHA-256 hash of the key, so that clients can check
+//! that they were given the right key.
+//!
+//! An encrypted change set is a random 96-bit nonce followed by the ciphertext of the encoded
+//! change set. The document id is authenticated along with it, so a revision copied into another
+//! document fails to decrypt.
+
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::Aes256Gcm;
+use prost::Message;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use wasm_bindgen::prelude::*;
+
+use ot::writing_proto::{ChangeSet, DocumentRevision};
+
+pub const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, Error)]
+pub enum EncryptionError {
+    #[error("Invalid Key Error: expected 32 bytes, got {0}")]
+    InvalidKeyError(usize),
+    #[error("Random Error: {0}")]
+    RandomError(getrandom::Error),
+    #[error("Encrypt Error")]
+    EncryptError,
+    #[error("Decrypt Error: revision {0} could not be decrypted with this key")]
+    DecryptError(i64),
+    #[error("Missing Key Error: revision {0} is encrypted, but no key was given")]
+    MissingKeyError(i64),
+    #[error("Unencrypted Revision Error: revision {0} of an encrypted document is not encrypted")]
+    UnencryptedRevisionError(i64),
+}
+
+/// Encrypts and decrypts the change sets of one document.
+pub struct DocumentCipher {
+    doc_id: String,
+    cipher: Aes256Gcm,
+    fingerprint: String,
+}
+
+impl DocumentCipher {
+    pub fn new(doc_id: &str, key: &[u8]) -> Result<Self, EncryptionError> {
+        if key.len() != KEY_LEN {
+            return Err(EncryptionError::InvalidKeyError(key.len()));
+        }
+        Ok(Self {
+            doc_id: doc_id.to_string(),
+            cipher: Aes256Gcm::new(GenericArray::from_slice(key)),
+            fingerprint: key_fingerprint(key),
+        })
+    }
+
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+
+    pub fn encrypt_change_set(&self, change_set: &ChangeSet) -> Result<Vec<u8>, EncryptionError> {
+        let mut change_set_binary = Vec::with_capacity(change_set.encoded_len());
+        change_set
+            .encode(&mut change_set_binary)
+            .map_err(|_| EncryptionError::EncryptError)?;
+        let mut nonce = [0u8; NONCE_LEN];
+        getrandom::getrandom(&mut nonce).map_err(EncryptionError::RandomError)?;
+        let payload = Payload {
+            msg: &change_set_binary,
+            aad: self.doc_id.as_bytes(),
+        };
+        let ciphertext = self
+            .cipher
+            .encrypt(GenericArray::from_slice(&nonce), payload)
+            .map_err(|_| EncryptionError::EncryptError)?;
+        let mut encrypted = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        encrypted.extend_from_slice(&nonce);
+        encrypted.extend_from_slice(&ciphertext);
+        Ok(encrypted)
+    }
+
+    /// Decrypts the encrypted change set of the given revision.
+    pub fn decrypt_change_set(
+        &self,
+        revision_number: i64,
+        encrypted: &[u8],
+    ) -> Result<ChangeSet, EncryptionError> {
+        if encrypted.len() < NONCE_LEN {
+            return Err(EncryptionError::DecryptError(revision_number));
+        }
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: self.doc_id.as_bytes(),
+        };
+        let change_set_binary = self
+            .cipher
+            .decrypt(GenericArray::from_slice(nonce), payload)
+            .map_err(|_| EncryptionError::DecryptError(revision_number))?;
+        ChangeSet::decode(&change_set_binary[..])
+            .map_err(|_| EncryptionError::DecryptError(revision_number))
+    }
+}
+
+/// Decrypts the change set of every revision in place, so that the rest of the client can treat
+/// encrypted and unencrypted documents alike.
+///
+/// Pass `None` for unencrypted documents. Fails if an unencrypted document has an encrypted
+/// revision, or the other way around, rather than showing a document that is missing revisions.
+pub fn decrypt_revisions(
+    cipher: Option<&DocumentCipher>,
+    revisions: &mut [DocumentRevision],
+) -> Result<(), EncryptionError> {
+    for revision in revisions.iter_mut() {
+        let is_encrypted = !revision.encrypted_change_set.is_empty();
+        match cipher {
+            None if is_encrypted => {
+                return Err(EncryptionError::MissingKeyError(revision.revision_number));
+            }
+            None => {}
+            Some(_) if !is_encrypted => {
+                return Err(EncryptionError::UnencryptedRevisionError(
+                    revision.revision_number,
+                ));
+            }
+            Some(cipher) => {
+                let encrypted = std::mem::take(&mut revision.encrypted_change_set);
+                let change_set = cipher.decrypt_change_set(revision.revision_number, &encrypted)?;
+                revision.change_set = Some(change_set);
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn key_fingerprint(key: &[u8]) -> String {
+    Sha256::digest(key)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[wasm_bindgen]
+pub struct JsEncryption {}
+
+#[wasm_bindgen]
+impl JsEncryption {
+    /// Generates a new random key for an encrypted document. The host must keep the key, since the
+    /// server never sees it. Returns `undefined` if no randomness is available.
+    #[wasm_bindgen(js_name = generateKey)]
+    pub fn generate_key() -> Option<Vec<u8>> {
+        let mut key = vec![0u8; KEY_LEN];
+        match getrandom::getrandom(&mut key) {
+            Ok(_) => Some(key),
+            Err(e) => {
+                web_sys::console::error_1(&format!("Error generating key: {}", e).into());
+                None
+            }
+        }
+    }
+
+    /// The fingerprint to create an encrypted document with, for the given key.
+    #[wasm_bindgen(js_name = keyFingerprint)]
+    pub fn key_fingerprint(key: &[u8]) -> String {
+        key_fingerprint(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_change_set() {
+        let key = [7u8; KEY_LEN];
+        let cipher = DocumentCipher::new("doc1", &key).unwrap();
+        let mut change_set = ChangeSet::new();
+        change_set.retain(3);
+        change_set.insert("secret");
+        let encrypted = cipher.encrypt_change_set(&change_set).unwrap();
+        assert_eq!(
+            cipher.decrypt_change_set(1, &encrypted).unwrap(),
+            change_set
+        );
+
+        // The ciphertext is bound to the key and to the document.
+        let other_key_cipher = DocumentCipher::new("doc1", &[8u8; KEY_LEN]).unwrap();
+        assert!(other_key_cipher.decrypt_change_set(1, &encrypted).is_err());
+        let other_doc_cipher = DocumentCipher::new("doc2", &key).unwrap();
+        assert!(other_doc_cipher.decrypt_change_set(1, &encrypted).is_err());
+
+        let mut revisions = vec![DocumentRevision {
+            revision_number: 1,
+            encrypted_change_set: encrypted,
+            ..Default::default()
+        }];
+        assert!(decrypt_revisions(None, &mut revisions).is_err());
+        decrypt_revisions(Some(&cipher), &mut revisions).unwrap();
+        assert_eq!(revisions[0].change_set, Some(change_set));
+        // Now that the revision looks unencrypted, the cipher refuses it.
+        assert!(decrypt_revisions(Some(&cipher), &mut revisions).is_err());
+    }
+
+    #[test]
+    fn test_key_fingerprint() {
+        assert_eq!(
+            key_fingerprint(b"test"),
+            "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+        );
+    }
+}
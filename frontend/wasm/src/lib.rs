@@ -1,5 +1,12 @@
 mod backend_api;
 mod document_editor;
+mod document_events;
+mod encryption;
+mod ot_worker;
+mod performance;
+mod revision_player;
+mod signing;
+mod telemetry;
 
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
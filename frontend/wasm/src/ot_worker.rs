@@ -0,0 +1,211 @@
+//! Composing remote revisions in a web worker, so that catching up on a big batch of them does not
+//! freeze typing.
+//!
+//! The worker, public/ot-worker.js, runs its own instance of this wasm module, and answers each
+//! message with `OtWorkerTasks.handleMessage`. Change sets are passed both ways encoded as
+//! protobufs, and their buffers are transferred rather than copied. A request is
+//! `{id, changeSets}`, with `changeSets` an array of `Uint8Array`s, and its response is
+//! `{id, changeSet}`, or `{id, error}` if the change sets do not compose.
+//!
+//! Only composing moves off the main thread. The pending changes and the document keep changing
+//! while the user types, so transforming the pending changes and applying the result stay on the
+//! main thread, once the composed change set comes back. The committed log checks the revisions
+//! again before it appends them, so a result that arrives after the log changed is not applied out
+//! of order.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use js_sys::{Array, Function, Object, Promise, Reflect, Uint8Array};
+use prost::Message;
+use thiserror::Error;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{MessageEvent, Worker};
+
+use ot::writing_proto::ChangeSet;
+
+/// Remote revisions whose change sets are smaller than this many bytes in all, encoded, are
+/// composed on the main thread. Posting them to the worker would cost more than it saves.
+pub const OT_WORKER_MIN_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Error)]
+pub enum OtWorkerError {
+    #[error("Ot Worker Error: {0}")]
+    WorkerError(String),
+    #[error("Invalid Response: {0}")]
+    InvalidResponse(String),
+}
+
+/// Composes change sets in a web worker on behalf of the main thread. The host creates the worker,
+/// and keeps it for as long as it likes. This only stops listening to it when dropped.
+#[derive(Clone)]
+pub struct OtWorker {
+    inner: Rc<OtWorkerInner>,
+}
+
+struct OtWorkerInner {
+    worker: Worker,
+    next_request_id: Cell<u32>,
+    // The `resolve` and `reject` functions of the promise for each request that is waiting for a
+    // response, by request id.
+    pending_requests: Rc<RefCell<HashMap<u32, (Function, Function)>>>,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_error: Closure<dyn FnMut(JsValue)>,
+}
+
+impl OtWorker {
+    pub fn new(worker: Worker) -> Self {
+        let pending_requests: Rc<RefCell<HashMap<u32, (Function, Function)>>> = Rc::default();
+
+        let on_message = {
+            let pending_requests = pending_requests.clone();
+            Closure::wrap(Box::new(move |event: MessageEvent| {
+                let response = event.data();
+                let id = Reflect::get(&response, &"id".into())
+                    .ok()
+                    .and_then(|id| id.as_f64());
+                let request = id.and_then(|id| pending_requests.borrow_mut().remove(&(id as u32)));
+                let (resolve, reject) = match request {
+                    Some(request) => request,
+                    None => {
+                        web_sys::console::error_1(
+                            &format!("Unexpected ot worker response: {:?}", response).into(),
+                        );
+                        return;
+                    }
+                };
+                let result = match Reflect::get(&response, &"error".into()) {
+                    Ok(error) if !error.is_undefined() => reject.call1(&JsValue::NULL, &error),
+                    _ => resolve.call1(&JsValue::NULL, &response),
+                };
+                if let Err(e) = result {
+                    web_sys::console::error_1(&e);
+                }
+            }) as Box<dyn FnMut(MessageEvent)>)
+        };
+        worker.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+        // The worker failed to load, or threw. Fail every waiting request, so that its change sets
+        // are composed on the main thread instead.
+        let on_error = {
+            let pending_requests = pending_requests.clone();
+            Closure::wrap(Box::new(move |error: JsValue| {
+                for (_, (_, reject)) in pending_requests.borrow_mut().drain() {
+                    let _ = reject.call1(&JsValue::NULL, &error);
+                }
+            }) as Box<dyn FnMut(JsValue)>)
+        };
+        worker.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+
+        Self {
+            inner: Rc::new(OtWorkerInner {
+                worker,
+                next_request_id: Cell::new(0),
+                pending_requests,
+                _on_message: on_message,
+                _on_error: on_error,
+            }),
+        }
+    }
+
+    /// Composes the change sets in the worker. Requests are answered in the order they were made.
+    pub async fn compose(&self, change_sets: &[&ChangeSet]) -> Result<ChangeSet, OtWorkerError> {
+        let id = self.inner.next_request_id.get();
+        self.inner.next_request_id.set(id.wrapping_add(1));
+        let encoded_change_sets = Array::new();
+        let transfer = Array::new();
+        for change_set in change_sets {
+            let array = encode_change_set(change_set);
+            transfer.push(&array.buffer());
+            encoded_change_sets.push(&array);
+        }
+        let request = Object::new();
+        set_property(&request, "id", &JsValue::from(id));
+        set_property(&request, "changeSets", &encoded_change_sets);
+
+        let pending_requests = self.inner.pending_requests.clone();
+        let promise = Promise::new(&mut |resolve, reject| {
+            pending_requests.borrow_mut().insert(id, (resolve, reject));
+        });
+        if let Err(e) = self
+            .inner
+            .worker
+            .post_message_with_transfer(&request, &transfer)
+        {
+            self.inner.pending_requests.borrow_mut().remove(&id);
+            return Err(OtWorkerError::WorkerError(format!("{:?}", e)));
+        }
+        let response = JsFuture::from(promise)
+            .await
+            .map_err(|e| OtWorkerError::WorkerError(format!("{:?}", e)))?;
+        let array: Uint8Array = Reflect::get(&response, &"changeSet".into())
+            .ok()
+            .and_then(|array| array.dyn_into().ok())
+            .ok_or_else(|| OtWorkerError::InvalidResponse("Missing changeSet".to_string()))?;
+        ChangeSet::decode(&array.to_vec()[..])
+            .map_err(|e| OtWorkerError::InvalidResponse(format!("Invalid changeSet: {}", e)))
+    }
+}
+
+impl Drop for OtWorkerInner {
+    fn drop(&mut self) {
+        self.worker.set_onmessage(None);
+        self.worker.set_onerror(None);
+    }
+}
+
+/// The tasks that the worker's instance of this module runs.
+#[wasm_bindgen]
+pub struct OtWorkerTasks {}
+
+#[wasm_bindgen]
+impl OtWorkerTasks {
+    /// Handles a request posted to the worker, and returns the response to post back. Post the
+    /// response's `changeSet.buffer`, if any, as a transferable.
+    #[wasm_bindgen(js_name = handleMessage)]
+    pub fn handle_message(request: &JsValue) -> JsValue {
+        let id = Reflect::get(request, &"id".into()).unwrap_or(JsValue::NULL);
+        let response = Object::new();
+        set_property(&response, "id", &id);
+        match compose_encoded(request) {
+            Ok(change_set) => {
+                set_property(&response, "changeSet", &encode_change_set(&change_set));
+            }
+            Err(e) => set_property(&response, "error", &e.into()),
+        }
+        response.into()
+    }
+}
+
+fn compose_encoded(request: &JsValue) -> Result<ChangeSet, String> {
+    let encoded_change_sets: Array = Reflect::get(request, &"changeSets".into())
+        .ok()
+        .and_then(|change_sets| change_sets.dyn_into().ok())
+        .ok_or_else(|| "Missing changeSets".to_string())?;
+    let mut change_sets = Vec::with_capacity(encoded_change_sets.length() as usize);
+    for array in encoded_change_sets.iter() {
+        let array: Uint8Array = array
+            .dyn_into()
+            .map_err(|_| "Invalid changeSets".to_string())?;
+        change_sets.push(
+            ChangeSet::decode(&array.to_vec()[..])
+                .map_err(|e| format!("Invalid change set: {}", e))?,
+        );
+    }
+    ot::compose_iter(&change_sets).map_err(|e| e.to_string())
+}
+
+fn encode_change_set(change_set: &ChangeSet) -> Uint8Array {
+    let mut bytes = Vec::with_capacity(change_set.encoded_len());
+    // Encoding only fails when the buffer is too small, and it has room.
+    change_set.encode(&mut bytes).unwrap();
+    Uint8Array::from(&bytes[..])
+}
+
+fn set_property(object: &Object, key: &str, value: &JsValue) {
+    // Only fails for frozen objects and proxies, and these are neither.
+    Reflect::set(object, &JsValue::from(key), value).unwrap();
+}
@@ -0,0 +1,28 @@
+use std::cell::RefCell;
+
+use editor_core::latency::{LatencyRecorder, LatencyReport, Stage};
+
+use crate::telemetry;
+
+thread_local! {
+    // Collected for the whole page, since requests to the backend are not made on behalf of any one
+    // editor. Pages rarely have more than one.
+    static LATENCY_RECORDER: RefCell<LatencyRecorder> = RefCell::new(LatencyRecorder::new());
+}
+
+/// Runs `f`, and records how long it took as the given stage.
+pub fn time<T>(stage: Stage, f: impl FnOnce() -> T) -> T {
+    let started_at = telemetry::now_millis();
+    let result = f();
+    let millis = telemetry::now_millis() - started_at;
+    LATENCY_RECORDER.with(|recorder| recorder.borrow_mut().record(stage, millis));
+    result
+}
+
+pub fn report() -> LatencyReport {
+    LATENCY_RECORDER.with(|recorder| recorder.borrow().report())
+}
+
+pub fn clear() {
+    LATENCY_RECORDER.with(|recorder| recorder.borrow_mut().clear());
+}
@@ -0,0 +1,340 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use js_sys::{JsString, Promise};
+use serde::Serialize;
+use thiserror::Error;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::future_to_promise;
+
+use ot::writing_proto::{ChangeSet, DocumentRevision, GetDocumentRevisionsRequest};
+use ot::OtError;
+
+use crate::backend_api::{BackendApi, BackendApiError};
+use crate::document_editor::slice_to_js_string;
+use crate::encryption::{self, DocumentCipher, EncryptionError};
+
+#[derive(Debug, Error)]
+pub enum RevisionPlayerError {
+    #[error("Backend API Error: {0}")]
+    BackendApiError(BackendApiError),
+    #[error("Ot Error: {0}")]
+    OtError(OtError),
+    #[error("Encryption Error: {0}")]
+    EncryptionError(EncryptionError),
+    #[error("Invalid Input Error: {0}")]
+    InvalidInputError(String),
+    #[error("Invalid Response Error: {0}")]
+    InvalidResponseError(String),
+}
+
+/// Plays back a range of a document's revision history, one revision at a time.
+///
+/// The player keeps a copy of the document as of the current revision. Stepping forward applies
+/// the next revision to it, and stepping backward applies the inverse of the current revision. We
+/// compute each inverse the first time we step forward over its revision and keep it, so stepping
+/// back and forth over the same revisions does no extra work.
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct RevisionPlayer {
+    inner: Rc<RefCell<RevisionPlayerInner>>,
+}
+
+struct RevisionPlayerInner {
+    doc_id: String,
+    // Sent with every request when the document was opened through a share link. Empty otherwise.
+    share_token: String,
+    // Set for end-to-end encrypted documents.
+    cipher: Option<DocumentCipher>,
+    // The revision number of the document before the first loaded revision was applied.
+    base_revision_number: i64,
+    // The loaded revisions, in order, starting with revision number `base_revision_number + 1`.
+    revisions: Vec<DocumentRevision>,
+    // `inverses[i]` undoes `revisions[i]`. Only covers the revisions we have stepped forward over.
+    inverses: Vec<ChangeSet>,
+    // The number of loaded revisions that have been applied to `value`.
+    position: usize,
+    value: Vec<u16>,
+    // The ranges of `value` that changed in the last step.
+    changed_ranges: Vec<ChangedRange>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+struct ChangedRange {
+    start: usize,
+    end: usize,
+}
+
+#[wasm_bindgen]
+impl RevisionPlayer {
+    pub fn new(doc_id: String, share_token: Option<String>) -> Self {
+        Self {
+            inner: Rc::new(RefCell::new(RevisionPlayerInner {
+                doc_id,
+                share_token: share_token.unwrap_or_default(),
+                cipher: None,
+                base_revision_number: 0,
+                revisions: Vec::new(),
+                inverses: Vec::new(),
+                position: 0,
+                value: Vec::new(),
+                changed_ranges: Vec::new(),
+            })),
+        }
+    }
+
+    /// Plays back an end-to-end encrypted document with its key. `fingerprint` is the document's
+    /// encryption key fingerprint. Returns false if the key does not match it. Must be called
+    /// before loading.
+    #[wasm_bindgen(js_name = setEncryptionKey)]
+    pub fn set_encryption_key(&self, key: &[u8], fingerprint: String) -> bool {
+        let mut self_ = self.inner.borrow_mut();
+        match DocumentCipher::new(&self_.doc_id, key) {
+            Ok(cipher) if cipher.fingerprint() == fingerprint => {
+                self_.cipher = Some(cipher);
+                true
+            }
+            Ok(_) => {
+                web_sys::console::error_1(&"Encryption key does not match the document".into());
+                false
+            }
+            Err(e) => {
+                web_sys::console::error_1(&format!("Error setting encryption key: {}", e).into());
+                false
+            }
+        }
+    }
+
+    /// Loads revisions `from_revision_number` through `to_revision_number`, inclusive, and moves
+    /// the player to just before `from_revision_number`. If the document has fewer revisions, loads
+    /// as many as there are.
+    #[wasm_bindgen(js_name = load)]
+    pub fn load(&self, from_revision_number: u32, to_revision_number: u32) -> Promise {
+        let self_ = self.clone();
+        let future = async move {
+            match self_
+                .load_impl(from_revision_number as i64, to_revision_number as i64)
+                .await
+            {
+                Ok(_) => Ok(JsValue::UNDEFINED),
+                Err(e) => {
+                    let error_message = format!("Revision Player load error: {:?}", e);
+                    let mut map = HashMap::new();
+                    map.insert("error".to_string(), error_message);
+                    Err(JsValue::from_serde(&map).unwrap())
+                }
+            }
+        };
+        future_to_promise(future)
+    }
+
+    /// Applies the next revision. Returns false if there are no more loaded revisions.
+    #[wasm_bindgen(js_name = stepForward)]
+    pub fn step_forward(&self) -> bool {
+        match self.inner.borrow_mut().step_forward() {
+            Ok(stepped) => stepped,
+            Err(e) => {
+                web_sys::console::error_1(&format!("Error stepping forward: {}", e).into());
+                false
+            }
+        }
+    }
+
+    /// Undoes the current revision. Returns false if the player is already at the start.
+    #[wasm_bindgen(js_name = stepBackward)]
+    pub fn step_backward(&self) -> bool {
+        match self.inner.borrow_mut().step_backward() {
+            Ok(stepped) => stepped,
+            Err(e) => {
+                web_sys::console::error_1(&format!("Error stepping backward: {}", e).into());
+                false
+            }
+        }
+    }
+
+    /// Steps forward or backward until the document is at the given revision number, or as close
+    /// to it as the loaded revisions allow.
+    #[wasm_bindgen(js_name = seek)]
+    pub fn seek(&self, revision_number: u32) {
+        let mut self_ = self.inner.borrow_mut();
+        let revision_number = revision_number as i64;
+        let result = loop {
+            let current = self_.current_revision_number();
+            let step = if current < revision_number {
+                self_.step_forward()
+            } else if current > revision_number {
+                self_.step_backward()
+            } else {
+                Ok(false)
+            };
+            match step {
+                Ok(true) => continue,
+                result => break result,
+            }
+        };
+        if let Err(e) = result {
+            web_sys::console::error_1(&format!("Error seeking: {}", e).into());
+        }
+    }
+
+    /// The revision number of the document as currently shown.
+    #[wasm_bindgen(js_name = getRevisionNumber)]
+    pub fn get_revision_number(&self) -> u32 {
+        self.inner.borrow().current_revision_number() as u32
+    }
+
+    /// The number of the last loaded revision.
+    #[wasm_bindgen(js_name = getLastRevisionNumber)]
+    pub fn get_last_revision_number(&self) -> u32 {
+        let self_ = self.inner.borrow();
+        (self_.base_revision_number + self_.revisions.len() as i64) as u32
+    }
+
+    #[wasm_bindgen(js_name = getValue)]
+    pub fn get_value(&self) -> JsString {
+        slice_to_js_string(&self.inner.borrow().value)
+    }
+
+    /// The ranges of the document, as `{start, end}` objects, that changed in the last step. A
+    /// range where characters were only deleted is empty.
+    #[wasm_bindgen(js_name = getChangedRanges)]
+    pub fn get_changed_ranges(&self) -> JsValue {
+        JsValue::from_serde(&self.inner.borrow().changed_ranges).unwrap()
+    }
+
+    /// The author of the current revision, or an empty string before the first loaded revision.
+    #[wasm_bindgen(js_name = getAuthorUserId)]
+    pub fn get_author_user_id(&self) -> String {
+        let self_ = self.inner.borrow();
+        match self_.position {
+            0 => String::new(),
+            position => self_.revisions[position - 1].author_user_id.clone(),
+        }
+    }
+
+    /// When the current revision was committed, or an empty string before the first loaded
+    /// revision.
+    #[wasm_bindgen(js_name = getCommittedAt)]
+    pub fn get_committed_at(&self) -> String {
+        let self_ = self.inner.borrow();
+        match self_.position {
+            0 => String::new(),
+            position => self_.revisions[position - 1].committed_at.clone(),
+        }
+    }
+}
+
+impl RevisionPlayer {
+    async fn load_impl(
+        &self,
+        from_revision_number: i64,
+        to_revision_number: i64,
+    ) -> Result<(), RevisionPlayerError> {
+        if from_revision_number < 1 || from_revision_number > to_revision_number {
+            return Err(RevisionPlayerError::InvalidInputError(format!(
+                "Invalid revision range: {} to {}",
+                from_revision_number, to_revision_number
+            )));
+        }
+        // Pin the reads to the last revision we want, so that we neither fetch revisions past it
+        // nor see the log change under us while edits continue.
+        let mut request = GetDocumentRevisionsRequest {
+            doc_id: self.inner.borrow().doc_id.clone(),
+            share_token: self.inner.borrow().share_token.clone(),
+            pinned_revision_number: to_revision_number,
+            ..GetDocumentRevisionsRequest::default()
+        };
+
+        // Every document starts out empty, so to show the document as of `from_revision_number`,
+        // we need every revision before it too.
+        let mut revisions: Vec<DocumentRevision> = Vec::new();
+        loop {
+            request.after_revision_number = revisions.last().map_or(0, |r| r.revision_number);
+            let mut response = BackendApi::get_document_revisions(&request)
+                .await
+                .map_err(RevisionPlayerError::BackendApiError)?;
+            encryption::decrypt_revisions(
+                self.inner.borrow().cipher.as_ref(),
+                &mut response.revisions,
+            )
+            .map_err(RevisionPlayerError::EncryptionError)?;
+            let end_of_revisions = response.end_of_revisions || response.revisions.is_empty();
+            for revision in response.revisions.into_iter() {
+                if revision.revision_number > to_revision_number {
+                    break;
+                }
+                let expected_revision_number = revisions.len() as i64 + 1;
+                if revision.revision_number != expected_revision_number {
+                    return Err(RevisionPlayerError::InvalidResponseError(format!(
+                        "Received revision number {}, but expected {}",
+                        revision.revision_number, expected_revision_number
+                    )));
+                }
+                revisions.push(revision);
+            }
+            if end_of_revisions || revisions.len() as i64 >= to_revision_number {
+                break;
+            }
+        }
+
+        let base_revision_number = std::cmp::min(from_revision_number - 1, revisions.len() as i64);
+        let loaded_revisions = revisions.split_off(base_revision_number as usize);
+        let base_change_set =
+            ot::compose_iter(revisions.iter().map(|r| r.change_set.as_ref().unwrap()))
+                .map_err(RevisionPlayerError::OtError)?;
+        let value = ot::apply_slice(&[], &base_change_set).map_err(RevisionPlayerError::OtError)?;
+
+        let mut self_ = self.inner.borrow_mut();
+        self_.base_revision_number = base_revision_number;
+        self_.revisions = loaded_revisions;
+        self_.inverses.clear();
+        self_.position = 0;
+        self_.value = value;
+        self_.changed_ranges.clear();
+        Ok(())
+    }
+}
+
+impl RevisionPlayerInner {
+    fn current_revision_number(&self) -> i64 {
+        self.base_revision_number + self.position as i64
+    }
+
+    fn step_forward(&mut self) -> Result<bool, OtError> {
+        let revision = match self.revisions.get(self.position) {
+            Some(revision) => revision,
+            None => return Ok(false),
+        };
+        let change_set = revision.change_set.as_ref().unwrap();
+        if self.inverses.len() == self.position {
+            let inverse = ot::invert_slice(&self.value, change_set)?;
+            self.inverses.push(inverse);
+        }
+        self.value = ot::apply_slice(&self.value, change_set)?;
+        self.changed_ranges = get_changed_ranges(change_set);
+        self.position += 1;
+        Ok(true)
+    }
+
+    fn step_backward(&mut self) -> Result<bool, OtError> {
+        if self.position == 0 {
+            return Ok(false);
+        }
+        let inverse = &self.inverses[self.position - 1];
+        self.value = ot::apply_slice(&self.value, inverse)?;
+        self.changed_ranges = get_changed_ranges(inverse);
+        self.position -= 1;
+        Ok(true)
+    }
+}
+
+fn get_changed_ranges(change_set: &ChangeSet) -> Vec<ChangedRange> {
+    change_set
+        .affected_ranges()
+        .map(|range| ChangedRange {
+            start: range.start as usize,
+            end: range.end as usize,
+        })
+        .collect()
+}
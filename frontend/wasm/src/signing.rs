@@ -0,0 +1,111 @@
+//! Signed revisions.
+//!
+//! Authors may sign the revisions they commit with an Ed25519 key, so that org admins can later
+//! check that the revision log has not been changed since. The host keeps the secret key and
+//! registers the public key with the server, which gives back the key id to send with signatures.
+//!
+//! A signature covers the document id, the revision number, and the SHA-256 hash of the change set
+//! exactly as it was sent, which for encrypted documents is the encrypted change set.
+
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signer, SECRET_KEY_LENGTH};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use wasm_bindgen::prelude::*;
+
+use ot::writing_proto::RevisionSignature;
+
+#[derive(Debug, Error)]
+pub enum SigningError {
+    #[error("Invalid Key Error: expected 32 bytes, got {0}")]
+    InvalidKeyError(usize),
+}
+
+/// Signs revisions with one key.
+pub struct RevisionSigner {
+    key_id: String,
+    keypair: Keypair,
+}
+
+impl RevisionSigner {
+    pub fn new(key_id: &str, secret_key: &[u8]) -> Result<Self, SigningError> {
+        let secret = SecretKey::from_bytes(secret_key)
+            .map_err(|_| SigningError::InvalidKeyError(secret_key.len()))?;
+        let public = PublicKey::from(&secret);
+        Ok(Self {
+            key_id: key_id.to_string(),
+            keypair: Keypair { secret, public },
+        })
+    }
+
+    /// Signs the revision that the given change set, as sent to the server, will become.
+    pub fn sign(
+        &self,
+        doc_id: &str,
+        revision_number: i64,
+        change_set_binary: &[u8],
+    ) -> RevisionSignature {
+        let message = ot::revision_signing_message(
+            doc_id,
+            revision_number,
+            &Sha256::digest(change_set_binary),
+        );
+        RevisionSignature {
+            key_id: self.key_id.clone(),
+            signature: self.keypair.sign(&message).to_bytes().to_vec(),
+        }
+    }
+}
+
+#[wasm_bindgen]
+pub struct JsSigning {}
+
+#[wasm_bindgen]
+impl JsSigning {
+    /// Generates a new random secret key to sign revisions with. The host must keep the secret key
+    /// and register its public key. Returns `undefined` if no randomness is available.
+    #[wasm_bindgen(js_name = generateKey)]
+    pub fn generate_key() -> Option<Vec<u8>> {
+        let mut secret_key = vec![0u8; SECRET_KEY_LENGTH];
+        match getrandom::getrandom(&mut secret_key) {
+            Ok(_) => Some(secret_key),
+            Err(e) => {
+                web_sys::console::error_1(&format!("Error generating key: {}", e).into());
+                None
+            }
+        }
+    }
+
+    /// The public key to register for the given secret key. Returns `undefined` if the secret key
+    /// is not valid.
+    #[wasm_bindgen(js_name = publicKey)]
+    pub fn public_key(secret_key: &[u8]) -> Option<Vec<u8>> {
+        let secret = SecretKey::from_bytes(secret_key).ok()?;
+        Some(PublicKey::from(&secret).to_bytes().to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ed25519_dalek::{Signature, Verifier};
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_sign() {
+        let signer = RevisionSigner::new("sk_1", &[1u8; SECRET_KEY_LENGTH]).unwrap();
+        let signature = signer.sign("doc1", 3, b"change set");
+        assert_eq!(signature.key_id, "sk_1");
+
+        let public_key =
+            PublicKey::from_bytes(&JsSigning::public_key(&[1u8; SECRET_KEY_LENGTH]).unwrap())
+                .unwrap();
+        let signature = Signature::try_from(&signature.signature[..]).unwrap();
+        let message = ot::revision_signing_message("doc1", 3, &Sha256::digest(b"change set"));
+        assert!(public_key.verify(&message, &signature).is_ok());
+        let message = ot::revision_signing_message("doc1", 4, &Sha256::digest(b"change set"));
+        assert!(public_key.verify(&message, &signature).is_err());
+
+        assert!(RevisionSigner::new("sk_1", &[1u8; 16]).is_err());
+    }
+}
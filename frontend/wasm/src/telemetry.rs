@@ -0,0 +1,69 @@
+use js_sys::{Date, Function};
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+/// Something the editor measured while syncing. These are what we need to tune how long typing
+/// composes into one revision, and how often we sync.
+///
+/// Serialized for the host with a `type` field naming the event, e.g.
+/// `{ "type": "rebase", "remote_revisions": 2, ... }`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TelemetryEvent {
+    /// New remote revisions were loaded, and the pending log, undo stacks, annotations, and
+    /// selection were transformed on top of them.
+    Rebase {
+        remote_revisions: i64,
+        pending_change_sets: usize,
+        transform_millis: f64,
+    },
+    /// The server rejected a commit because someone else committed first.
+    DiscoveredNewRevisions { pending_change_sets: usize },
+    /// A sync finished, successfully or not. `trace_id` is that of its last round, which the
+    /// server logs its requests with.
+    SyncFinished {
+        ok: bool,
+        trace_id: String,
+        latency_millis: f64,
+        rebases: u32,
+        discovered_new_revisions: u32,
+        undo_stack_depth: usize,
+        redo_stack_depth: usize,
+    },
+}
+
+pub trait Telemetry {
+    fn report(&self, event: &TelemetryEvent);
+}
+
+/// Reports events by calling a function supplied by the host with each serialized event.
+pub struct JsTelemetry {
+    callback: Function,
+}
+
+impl JsTelemetry {
+    pub fn new(callback: Function) -> Self {
+        Self { callback }
+    }
+}
+
+impl Telemetry for JsTelemetry {
+    fn report(&self, event: &TelemetryEvent) {
+        let value = match JsValue::from_serde(event) {
+            Ok(value) => value,
+            Err(_) => return,
+        };
+        if let Err(e) = self.callback.call1(&JsValue::NULL, &value) {
+            web_sys::console::error_1(&format!("Error reporting telemetry: {:?}", e).into());
+        }
+    }
+}
+
+/// A timestamp in milliseconds for measuring durations. Uses the high resolution clock when there
+/// is one, since transforms often take well under a millisecond.
+pub fn now_millis() -> f64 {
+    web_sys::window()
+        .and_then(|window| window.performance())
+        .map(|performance| performance.now())
+        .unwrap_or_else(Date::now)
+}
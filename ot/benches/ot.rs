@@ -0,0 +1,56 @@
+//! Benchmarks for the operations that run on every keystroke and every sync.
+//!
+//! Run with `cargo bench`. The long compose chain mirrors what `compose_iter` does when a client
+//! loads a document with a long revision history.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use ot::writing_proto::ChangeSet;
+
+/// A change set that types one character at `offset` into a document of length `document_len`,
+/// like a keystroke.
+fn keystroke(document_len: i64, offset: i64) -> ChangeSet {
+    let mut change_set = ChangeSet::new();
+    change_set.retain(offset);
+    change_set.insert("a");
+    change_set.retain(document_len - offset);
+    change_set
+}
+
+/// A series of keystrokes scattered around a document that starts out with `initial_len`
+/// characters.
+fn keystrokes(initial_len: i64, count: i64) -> Vec<ChangeSet> {
+    let mut initial = ChangeSet::new();
+    initial.insert(&"x".repeat(initial_len as usize));
+    let mut change_sets = vec![initial];
+    for i in 0..count {
+        let document_len = initial_len + i;
+        change_sets.push(keystroke(document_len, (i * 7919) % (document_len + 1)));
+    }
+    change_sets
+}
+
+fn bench_compose(c: &mut Criterion) {
+    let change_sets = keystrokes(1_000, 1_000);
+    c.bench_function("compose_iter 1000 keystrokes", |b| {
+        b.iter(|| ot::compose_iter(black_box(&change_sets)).unwrap())
+    });
+
+    let a = ot::compose_iter(&change_sets[..500]).unwrap();
+    let b_change_set = ot::compose_iter(&change_sets[500..]).unwrap();
+    c.bench_function("compose two long change sets", |b| {
+        b.iter(|| ot::compose(black_box(&a), black_box(&b_change_set)).unwrap())
+    });
+}
+
+fn bench_transform(c: &mut Criterion) {
+    let base = 10_000;
+    let local = keystroke(base, 100);
+    let remote = keystroke(base, 5_000);
+    c.bench_function("transform keystrokes", |b| {
+        b.iter(|| ot::transform(black_box(&local), black_box(&remote)).unwrap())
+    });
+}
+
+criterion_group!(benches, bench_compose, bench_transform);
+criterion_main!(benches);
@@ -6,6 +6,17 @@ fn main() -> Result<(), std::io::Error> {
         .type_attribute("writing.GetDocumentResponse", "#[derive(serde::Serialize)]")
         .type_attribute("writing.Document", "#[derive(serde::Serialize)]")
         .type_attribute("writing.ListMyDocumentsResponse", "#[derive(serde::Serialize)]")
+        .type_attribute("writing.GetMyPermissionsResponse", "#[derive(serde::Serialize)]")
+        .type_attribute("writing.DocumentPermission", "#[derive(serde::Serialize)]")
+        .type_attribute(
+            "writing.RegisterSigningKeyResponse",
+            "#[derive(serde::Serialize)]",
+        )
+        .type_attribute(
+            "writing.ListReadReceiptsResponse",
+            "#[derive(serde::Serialize)]",
+        )
+        .type_attribute("writing.ReadReceipt", "#[derive(serde::Serialize)]")
         .compile(&["../proto/document.proto"], &["../proto"])?;
     Ok(())
 }
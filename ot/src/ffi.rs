@@ -0,0 +1,129 @@
+//! `apply`, `compose`, and `transform` over protobuf-encoded change sets.
+//!
+//! Bindings for other languages can pass change sets as the bytes of the `ChangeSet` message in
+//! `document.proto`, which every platform can encode, instead of building the prost structs that
+//! this crate uses.
+
+use prost::Message;
+
+use crate::writing_proto::ChangeSet;
+use crate::OtError;
+
+/// Like `apply`, taking an encoded change set.
+///
+/// # Error
+///
+/// - Returns `OtError::InvalidInput` when the change set cannot be decoded.
+///
+/// - Returns any error that `apply` returns.
+pub fn apply_bytes(document: &str, change_set: &[u8]) -> Result<String, OtError> {
+    crate::apply(document, &decode_change_set(change_set)?)
+}
+
+/// Like `compose`, taking and returning encoded change sets.
+///
+/// # Error
+///
+/// - Returns `OtError::InvalidInput` when a change set cannot be decoded.
+///
+/// - Returns any error that `compose` returns.
+pub fn compose_bytes(a: &[u8], b: &[u8]) -> Result<Vec<u8>, OtError> {
+    let composed = crate::compose(&decode_change_set(a)?, &decode_change_set(b)?)?;
+    Ok(encode_change_set(&composed))
+}
+
+/// Like `transform`, taking and returning encoded change sets.
+///
+/// # Error
+///
+/// - Returns `OtError::InvalidInput` when a change set cannot be decoded.
+///
+/// - Returns any error that `transform` returns.
+pub fn transform_bytes(a: &[u8], b: &[u8]) -> Result<(Vec<u8>, Vec<u8>), OtError> {
+    let (a_prime, b_prime) = crate::transform(&decode_change_set(a)?, &decode_change_set(b)?)?;
+    Ok((encode_change_set(&a_prime), encode_change_set(&b_prime)))
+}
+
+/// Decodes a change set from the bytes of a `ChangeSet` message.
+///
+/// # Error
+///
+/// - Returns `OtError::InvalidInput` when the bytes are not a valid `ChangeSet` message.
+pub fn decode_change_set(bytes: &[u8]) -> Result<ChangeSet, OtError> {
+    ChangeSet::decode(bytes)
+        .map_err(|e| OtError::InvalidInput(format!("Could not decode change set: {}", e)))
+}
+
+/// Encodes a change set as the bytes of a `ChangeSet` message.
+pub fn encode_change_set(change_set: &ChangeSet) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(change_set.encoded_len());
+    // Encoding only fails when the buffer runs out of room, and a `Vec` grows as needed.
+    change_set
+        .encode(&mut bytes)
+        .expect("Encoding into a Vec cannot fail");
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(build: impl FnOnce(&mut ChangeSet)) -> Vec<u8> {
+        let mut change_set = ChangeSet::new();
+        build(&mut change_set);
+        encode_change_set(&change_set)
+    }
+
+    #[test]
+    fn test_apply_bytes() {
+        let change_set = encoded(|c| {
+            c.retain(6);
+            c.delete(5);
+            c.insert("there");
+        });
+        assert_eq!(
+            apply_bytes("Hello world", &change_set).unwrap(),
+            "Hello there"
+        );
+        assert!(matches!(
+            apply_bytes("Hello", &change_set),
+            Err(OtError::LengthMismatch { .. })
+        ));
+        assert!(matches!(
+            apply_bytes("Hello", &[0xff, 0xff]),
+            Err(OtError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_compose_bytes() {
+        let a = encoded(|c| {
+            c.retain(5);
+            c.insert(" world");
+        });
+        let b = encoded(|c| {
+            c.insert("Oh, ");
+            c.retain(11);
+        });
+        let composed = compose_bytes(&a, &b).unwrap();
+        assert_eq!(apply_bytes("Hello", &composed).unwrap(), "Oh, Hello world");
+        assert!(compose_bytes(&b, &a).is_err());
+    }
+
+    #[test]
+    fn test_transform_bytes() {
+        let a = encoded(|c| {
+            c.insert("Oh, ");
+            c.retain(5);
+        });
+        let b = encoded(|c| {
+            c.retain(5);
+            c.insert("!");
+        });
+        let (a_prime, b_prime) = transform_bytes(&a, &b).unwrap();
+        let a_then_b_prime = apply_bytes(&apply_bytes("Hello", &a).unwrap(), &b_prime).unwrap();
+        let b_then_a_prime = apply_bytes(&apply_bytes("Hello", &b).unwrap(), &a_prime).unwrap();
+        assert_eq!(a_then_b_prime, "Oh, Hello!");
+        assert_eq!(b_then_a_prime, "Oh, Hello!");
+    }
+}
@@ -11,33 +11,109 @@
 //! - `Delete({ count: 7 })` deletes seven UTF-16 code points.
 //! - `Selection { offset: 10, count: 3 }` skips ten UTF-16 code points and includes the next
 //! three.
-//! - `Insert({ content: "foo".encode_utf16().map(u16::into).collect() })` inserts the
+//! - `Insert({ content: utils::str_to_u32_vec("foo") })` inserts the
 //! string "foo", which consists of three UTF-16 code points.
 //!
-//! We use the `std::str::encode_utf16` and `String::from_utf16_lossy` methods to translate between
-//! Rust `str` objects and UTF-16 code point sequences.
+//! The `utils` module translates between Rust `str` objects and UTF-16 code point sequences.
 //!
-//! Using `String::from_utf16_lossy` seems dangerous, but we will not lose data if changes
+//! `apply` uses `String::from_utf16_lossy` to return a string. That seems dangerous, but we will
+//! not lose data if changes
 //! submitted to this library originate from valid web browser UI events. The web browser will not
 //! allow UI actions to modify a DOM node's text such that the text becomes invalid UTF-16.
 
+pub mod ffi;
 mod proto;
+pub mod protocol;
+#[cfg(test)]
+mod reference;
+#[cfg(feature = "ropey")]
+mod rope;
+mod summary;
+pub mod utils;
 
 use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::ops::Range;
 
 use thiserror::Error;
 
 pub use proto::writing as writing_proto;
+#[cfg(feature = "ropey")]
+pub use rope::{apply_to_rope, invert_rope};
+pub use summary::{summarize, ChangeSummary, EditSummary, TextAmount};
 
 use writing_proto::{change_op::Op, ChangeOp, ChangeSet, Delete, Insert, Retain, Selection};
 
 /// An operational transformation error.
+///
+/// The structured variants let callers tell bad input apart from bugs in this library. Their
+/// messages match the ones we logged before they existed, so existing log searches keep working.
 #[derive(Debug, Error)]
 pub enum OtError {
     #[error("Invalid Input: {0}")]
     InvalidInput(String),
+    /// The change set has an op whose `op` field is missing.
+    #[error("Invalid Input: Unexpected empty op at index {index}")]
+    EmptyOp { index: usize },
+    /// The change set expects a document of length `expected`, but was given one of length
+    /// `actual`.
+    #[error(
+        "Invalid Input: The change set must be based on a document with length {expected}, but the \
+        document had length {actual}"
+    )]
+    LengthMismatch { expected: i64, actual: i64 },
+    /// The lengths of change sets `A` and `B` do not line up, so they cannot be transformed or
+    /// composed together.
+    #[error("Invalid Input: {}", describe_incompatible_change_sets(.operation, .a_len, .b_len))]
+    IncompatibleChangeSets {
+        operation: ChangeSetOperation,
+        /// The input length of `A` for transform, or its output length for compose.
+        a_len: i64,
+        /// The input length of `B`.
+        b_len: i64,
+    },
     #[error("Post Condition Failed: {0}")]
     PostConditionFailed(String),
+    /// Text passed to the `utils` conversions, or inserted into a rope, has a value at `index` that
+    /// is not a UTF-16 code unit, or a lone surrogate where only valid text is allowed.
+    #[error("Invalid Input: Invalid UTF-16 at index {index}")]
+    InvalidUtf16 { index: usize },
+    /// The insert at op `index` has a value above `0xFFFF` at `content_index` of its content.
+    #[error(
+        "Invalid Input: Value above 0xFFFF at index {content_index} of the insert in op at index \
+        {index}"
+    )]
+    InvalidInsertContent { index: usize, content_index: usize },
+    /// The op at `index` retains or deletes a negative number of characters.
+    #[error("Invalid Input: Negative count {count} in op at index {index}")]
+    NegativeCount { index: usize, count: i64 },
+    /// The length of the document before or after the change set does not fit in an `i64`.
+    #[error("Invalid Input: Document length overflows")]
+    LengthOverflow,
+}
+
+/// An operation that combines two change sets.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChangeSetOperation {
+    Transform,
+    Compose,
+}
+
+fn describe_incompatible_change_sets(
+    operation: &ChangeSetOperation,
+    a_len: &i64,
+    b_len: &i64,
+) -> String {
+    match operation {
+        ChangeSetOperation::Transform => format!(
+            "Cannot transform A (input length {}) and B (input length {})",
+            a_len, b_len
+        ),
+        ChangeSetOperation::Compose => format!(
+            "Cannot compose change sets A (output length {}) and B (input length {})",
+            a_len, b_len
+        ),
+    }
 }
 
 /// Transforms two concurrent changes `(A, B)` into changes `(A', B')` such that `A * B' == B *
@@ -58,23 +134,29 @@ pub enum OtError {
 ///
 /// # Error
 ///
-/// - Returns `OtError::InvalidInput` when:
-///   - The local and remote change sets have different input document lengths.
-///   - A change set contains an empty op.
-///   - A change set seems malformed.
+/// - Returns `OtError::IncompatibleChangeSets` when the change sets have different input document
+///   lengths.
+///
+/// - Returns `OtError::EmptyOp` when a change set contains an empty op.
+///
+/// - Returns `OtError::InvalidInput` when a change set seems malformed.
 ///
 pub fn transform(a: &ChangeSet, b: &ChangeSet) -> Result<(ChangeSet, ChangeSet), OtError> {
     let (a_input_len, _) = get_input_output_doc_lengths(a)?;
     let (b_input_len, _) = get_input_output_doc_lengths(b)?;
     if a_input_len != b_input_len {
-        return Err(OtError::InvalidInput(format!(
-            "Cannot transform A (input length {}) and B (input length {})",
-            a_input_len, b_input_len,
-        )));
+        return Err(OtError::IncompatibleChangeSets {
+            operation: ChangeSetOperation::Transform,
+            a_len: a_input_len,
+            b_len: b_input_len,
+        });
     }
 
-    let mut a_transform = ChangeSet::new();
-    let mut b_transform = ChangeSet::new();
+    // Each step of the loop below pushes at most one op onto each result, and consumes at least one
+    // op of A or B, so this is enough room for every op without reallocating.
+    let capacity = a.ops.len() + b.ops.len();
+    let mut a_transform = ChangeSet::with_capacity(capacity);
+    let mut b_transform = ChangeSet::with_capacity(capacity);
 
     let mut a_ops_iter = a.ops.iter();
     let mut b_ops_iter = b.ops.iter();
@@ -210,27 +292,40 @@ pub fn transform(a: &ChangeSet, b: &ChangeSet) -> Result<(ChangeSet, ChangeSet),
 ///
 /// # Errors
 ///
-/// - Returns `OtError::InvalidInput` when:
-///   - The input document length of `B` is not equal to the output document length of `A` (i.e. it
-///   is not possible to compose `A` and `B`).
-///   - A change set contains an empty op.
-///   - A change set seems malformed.
+/// - Returns `OtError::IncompatibleChangeSets` when the input document length of `B` is not equal to
+///   the output document length of `A` (i.e. it is not possible to compose `A` and `B`).
+///
+/// - Returns `OtError::EmptyOp` when a change set contains an empty op.
+///
+/// - Returns `OtError::InvalidInput` when a change set seems malformed.
 ///
 /// - Returns `OtError::PostConditionFailed` when the composed change set does not have the correct
 /// input and output document lengths.
 ///
 pub fn compose(a: &ChangeSet, b: &ChangeSet) -> Result<ChangeSet, OtError> {
-    let (a_input_len, a_output_len) = get_input_output_doc_lengths(a)?;
-    let (b_input_len, b_output_len) = get_input_output_doc_lengths(b)?;
+    let (composed, _) = compose_with_lengths(a, a.lengths()?, b)?;
+    Ok(composed)
+}
+
+/// `compose`, for callers that already know the lengths of `A`. Returns the composed change set
+/// along with its lengths, so that a chain of compositions only measures each change set once.
+fn compose_with_lengths(
+    a: &ChangeSet,
+    (a_input_len, a_output_len): (i64, i64),
+    b: &ChangeSet,
+) -> Result<(ChangeSet, (i64, i64)), OtError> {
+    let (b_input_len, b_output_len) = b.lengths()?;
 
     if a_output_len != b_input_len {
-        return Err(OtError::InvalidInput(format!(
-            "Cannot compose change sets A (output length {}) and B (input length {})",
-            a_output_len, b_input_len
-        )));
+        return Err(OtError::IncompatibleChangeSets {
+            operation: ChangeSetOperation::Compose,
+            a_len: a_output_len,
+            b_len: b_input_len,
+        });
     }
 
-    let mut composed = ChangeSet::new();
+    // As in `transform`, each step pushes at most one op and consumes at least one op of A or B.
+    let mut composed = ChangeSet::with_capacity(a.ops.len() + b.ops.len());
 
     let mut a_ops_iter = a.ops.iter();
     let mut b_ops_iter = b.ops.iter();
@@ -352,7 +447,7 @@ pub fn compose(a: &ChangeSet, b: &ChangeSet) -> Result<ChangeSet, OtError> {
         }
     }
 
-    let (composed_input_len, composed_output_len) = get_input_output_doc_lengths(&composed)?;
+    let (composed_input_len, composed_output_len) = composed.lengths()?;
     if composed_input_len != a_input_len || composed_output_len != b_output_len {
         return Err(OtError::PostConditionFailed(format!(
             "The composed change set must have input_len {} and output_len {}. It had input_len {} \
@@ -360,27 +455,218 @@ pub fn compose(a: &ChangeSet, b: &ChangeSet) -> Result<ChangeSet, OtError> {
         )));
     }
 
-    Ok(composed)
+    Ok((composed, (composed_input_len, composed_output_len)))
 }
 
 /// Composes a series of change sets into a single change set.
+///
+/// `ChangeSet` is generated from its protobuf definition, so it has nowhere to cache its lengths.
+/// Instead, the lengths of the running composition are carried from one step to the next, so that
+/// each change set is only measured once.
 pub fn compose_iter<'a, I>(change_sets: I) -> Result<ChangeSet, OtError>
 where
     I: IntoIterator<Item = &'a ChangeSet>,
 {
-    let mut composed = ChangeSet::new();
-    let mut first = true;
+    let mut change_sets = change_sets.into_iter();
+    let mut composed = match change_sets.next() {
+        Some(change_set) => change_set.clone(),
+        None => return Ok(ChangeSet::new()),
+    };
+    let mut lengths = None;
     for change_set in change_sets {
-        if first {
-            composed = change_set.clone();
-            first = false;
-        } else {
-            composed = compose(&composed, &change_set)?;
+        let composed_lengths = match lengths {
+            Some(lengths) => lengths,
+            None => composed.lengths()?,
+        };
+        let (next_composed, next_lengths) =
+            compose_with_lengths(&composed, composed_lengths, change_set)?;
+        composed = next_composed;
+        lengths = Some(next_lengths);
+    }
+    Ok(composed)
+}
+
+/// The result of `merge`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MergeResult {
+    /// The change from the base document to the merged document, which has both sides' changes.
+    pub merged: ChangeSet,
+    /// The local changes, transformed to apply on top of the remote changes. This is what a client
+    /// that was offline commits after the remote revisions.
+    pub rebased_local: ChangeSet,
+    /// The places where both sides replaced the same text, in order.
+    pub conflicts: Vec<MergeConflict>,
+}
+
+/// A place where both sides of a `merge` deleted overlapping text and inserted something in its
+/// place. `merge` keeps both insertions, one after the other, which is rarely what either side
+/// meant, so the UI should ask the user which to keep.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MergeConflict {
+    /// The range of the base document that the two replacements cover between them.
+    pub base_range: Range<i64>,
+    /// The range of the merged document that replaced `base_range`, including both insertions.
+    pub merged_range: Range<i64>,
+    /// The text that the local side inserted.
+    pub local_content: Vec<u32>,
+    /// The text that the remote side inserted.
+    pub remote_content: Vec<u32>,
+}
+
+/// Merges two series of changes made concurrently to the same base document, for example the
+/// revisions a client made during a long offline session and the revisions others committed
+/// meanwhile.
+///
+/// Each side is composed into a single change set, and the two are transformed against each other,
+/// so no edit is lost. Where both sides deleted overlapping text and inserted new text instead,
+/// the merged document has both insertions, and the overlap is reported as a `MergeConflict`.
+/// Deletions alone, and insertions alone, never conflict.
+///
+/// # Errors
+///
+/// - Returns `OtError::LengthMismatch` when a side's changes do not apply to a document of length
+///   `base_len`.
+///
+/// - Returns the errors of `compose` when a side's change sets do not follow one another.
+///
+pub fn merge(
+    base_len: i64,
+    local: &[ChangeSet],
+    remote: &[ChangeSet],
+) -> Result<MergeResult, OtError> {
+    let local = compose_merge_side(base_len, local)?;
+    let remote = compose_merge_side(base_len, remote)?;
+    let (rebased_local, _) = transform(&local, &remote)?;
+    let merged = compose(&remote, &rebased_local)?;
+
+    let local_replacements = get_replacements(&local);
+    let remote_replacements = get_replacements(&remote);
+    let mut conflicts = Vec::new();
+    let mut remote_index = 0;
+    for local_replacement in local_replacements.iter() {
+        // Skip the remote replacements that end before this one starts. They cannot overlap any
+        // later local replacement either.
+        while remote_index < remote_replacements.len()
+            && remote_replacements[remote_index].range.end <= local_replacement.range.start
+        {
+            remote_index += 1;
+        }
+        for remote_replacement in remote_replacements[remote_index..].iter() {
+            if remote_replacement.range.start >= local_replacement.range.end {
+                break;
+            }
+            let (local_range, remote_range) = (&local_replacement.range, &remote_replacement.range);
+            let base_range =
+                local_range.start.min(remote_range.start)..local_range.end.max(remote_range.end);
+            conflicts.push(MergeConflict {
+                merged_range: map_base_offset(&merged, base_range.start, false)
+                    ..map_base_offset(&merged, base_range.end, true),
+                base_range,
+                local_content: local_replacement.content.clone(),
+                remote_content: remote_replacement.content.clone(),
+            });
         }
     }
+
+    Ok(MergeResult {
+        merged,
+        rebased_local,
+        conflicts,
+    })
+}
+
+/// Composes one side of a merge, checking that it applies to the base document. No changes at all
+/// become a change set that retains the whole base document.
+fn compose_merge_side(base_len: i64, change_sets: &[ChangeSet]) -> Result<ChangeSet, OtError> {
+    if change_sets.is_empty() {
+        let mut identity = ChangeSet::new();
+        identity.retain(base_len);
+        return Ok(identity);
+    }
+    let composed = compose_iter(change_sets)?;
+    let (input_len, _) = get_input_output_doc_lengths(&composed)?;
+    if input_len != base_len {
+        return Err(OtError::LengthMismatch {
+            expected: input_len,
+            actual: base_len,
+        });
+    }
     Ok(composed)
 }
 
+/// A run of ops that deletes some of the input document and inserts something in its place.
+struct Replacement {
+    range: Range<i64>,
+    content: Vec<u32>,
+}
+
+/// Returns the replacements in the change set, in order. Runs of ops that only delete or only
+/// insert are left out.
+fn get_replacements(change_set: &ChangeSet) -> Vec<Replacement> {
+    let mut replacements = Vec::new();
+    let mut offset = 0;
+    let mut current = Replacement {
+        range: 0..0,
+        content: Vec::new(),
+    };
+    let mut finish = |current: &mut Replacement, offset: i64| {
+        if current.range.start < offset && !current.content.is_empty() {
+            replacements.push(Replacement {
+                range: current.range.start..offset,
+                content: std::mem::take(&mut current.content),
+            });
+        }
+        current.content.clear();
+    };
+    for change_op in change_set.ops.iter() {
+        match change_op.op.as_ref() {
+            None => {}
+            Some(Op::Retain(retain)) => {
+                finish(&mut current, offset);
+                offset += retain.count;
+                current.range.start = offset;
+            }
+            Some(Op::Delete(delete)) => offset += delete.count,
+            Some(Op::Insert(insert)) => current.content.extend(insert.content.iter()),
+        }
+    }
+    finish(&mut current, offset);
+    replacements
+}
+
+/// Returns where the offset of the input document ends up in the output document. Text inserted
+/// at the offset is counted as coming before it if `after_inserts` is true, and after it
+/// otherwise.
+fn map_base_offset(change_set: &ChangeSet, offset: i64, after_inserts: bool) -> i64 {
+    let mut input_offset = 0;
+    let mut output_offset = 0;
+    for change_op in change_set.ops.iter() {
+        match change_op.op.as_ref() {
+            None => {}
+            Some(Op::Insert(insert)) => {
+                if input_offset == offset && !after_inserts {
+                    return output_offset;
+                }
+                output_offset += insert.content.len() as i64;
+            }
+            Some(Op::Retain(retain)) => {
+                if input_offset + retain.count > offset {
+                    return output_offset + (offset - input_offset);
+                }
+                input_offset += retain.count;
+                output_offset += retain.count;
+            }
+            Some(Op::Delete(delete)) => {
+                if input_offset + delete.count > offset {
+                    return output_offset;
+                }
+                input_offset += delete.count;
+            }
+        }
+    }
+    output_offset
+}
+
 /// Applies the change set to the document, returning a new document.
 ///
 /// You can think of a change set as a list of commands to send to an imaginary cursor. The cursor
@@ -392,38 +678,36 @@ where
 ///
 /// # Errors
 ///
-/// - Returns `OtError::InvalidInput` when: the change set is incompatible with the document (i.e.
-/// the change set has a input document length that is different from the document's length).
+/// - Returns `OtError::LengthMismatch` when the change set is incompatible with the document (i.e.
+///   the change set has a input document length that is different from the document's length).
+///
+/// - Returns `OtError::EmptyOp` when the change set contains an empty op.
 ///
 /// - Returns `OtError::PostConditionFailed` when the resulting document does not have the same
 /// length as the output document length that the change set should produce.
 ///
 pub fn apply(document: &str, change_set: &ChangeSet) -> Result<String, OtError> {
-    let document_u16: Vec<u16> = document.encode_utf16().collect();
+    let document_u16 = utils::str_to_u16_vec(document);
     apply_slice(&document_u16, change_set)
         .map(|new_document_u16| String::from_utf16_lossy(&new_document_u16))
 }
 
 pub fn apply_slice(document_u16: &[u16], change_set: &ChangeSet) -> Result<Vec<u16>, OtError> {
-    let (input_len, output_len) = get_input_output_doc_lengths(change_set)?;
+    let (input_len, output_len) = validate(change_set)?;
     let doc_len = document_u16.len();
     if input_len as usize != doc_len {
-        return Err(OtError::InvalidInput(format!(
-            "The change set must be based on a document with length {}, but the document had length {}",
-            input_len, doc_len,
-        )));
+        return Err(OtError::LengthMismatch {
+            expected: input_len,
+            actual: doc_len as i64,
+        });
     }
     let mut i = 0;
     let mut new_document_u16: Vec<u16> = Vec::with_capacity(document_u16.len());
     let mut new_doc_len = 0;
-    for change_op in change_set.ops.iter() {
-        let op = change_op
-            .op
-            .as_ref()
-            .ok_or_else(|| OtError::InvalidInput(String::from("Change set had an empty op")))?;
+    for_each_op(change_set, |op| {
         match op {
             Op::Insert(insert) => {
-                new_document_u16.extend(insert.content.iter().map(|ch| *ch as u16));
+                new_document_u16.extend(utils::u32_slice_to_u16_vec(&insert.content)?);
                 new_doc_len += insert.content.len();
             }
             Op::Delete(delete) => {
@@ -435,7 +719,8 @@ pub fn apply_slice(document_u16: &[u16], change_set: &ChangeSet) -> Result<Vec<u
                 new_doc_len += retain.count as usize;
             }
         }
-    }
+        Ok(())
+    })?;
     if output_len as usize != new_doc_len {
         return Err(OtError::PostConditionFailed(format!(
             "After applying changes, the document should have length {}, but it had length {}",
@@ -451,15 +736,15 @@ pub fn apply_chunks(
     document_chunks: Vec<Vec<u16>>,
     change_set: &ChangeSet,
 ) -> Result<Vec<Vec<u16>>, OtError> {
-    let (input_len, output_len) = get_input_output_doc_lengths(change_set)?;
+    let (input_len, output_len) = validate(change_set)?;
     let doc_len: usize = document_chunks
         .iter()
         .fold(0, |sum, chunk| sum + chunk.len());
     if input_len as usize != doc_len {
-        return Err(OtError::InvalidInput(format!(
-            "The change set must be based on a document with length {}, but the document had length {}",
-            input_len, doc_len,
-        )));
+        return Err(OtError::LengthMismatch {
+            expected: input_len,
+            actual: doc_len as i64,
+        });
     }
 
     let mut new_document_chunks: Vec<Vec<u16>> = Vec::new();
@@ -476,8 +761,7 @@ pub fn apply_chunks(
         match (maybe_chunk, maybe_op) {
             (None, None) => break,
             (chunk, Some(Op::Insert(insert))) => {
-                let content: Vec<u16> = insert.content.iter().map(|ch| *ch as u16).collect();
-                new_document_chunks.push(content);
+                new_document_chunks.push(utils::u32_slice_to_u16_vec(&insert.content)?);
                 maybe_chunk = chunk;
                 maybe_op = next_op(&mut ops_iter)?;
             }
@@ -548,7 +832,7 @@ pub fn apply_chunks(
 ///
 /// Returns an error if the document's length is incompatible with the change set.
 pub fn invert(document: &str, change_set: &ChangeSet) -> Result<ChangeSet, OtError> {
-    let document_u16: Vec<u16> = document.encode_utf16().collect();
+    let document_u16 = utils::str_to_u16_vec(document);
     invert_slice(&document_u16, change_set)
 }
 
@@ -556,18 +840,14 @@ pub fn invert_slice(document_u16: &[u16], change_set: &ChangeSet) -> Result<Chan
     let (input_len, _output_len) = get_input_output_doc_lengths(change_set)?;
     let doc_len = document_u16.len();
     if input_len as usize != doc_len {
-        return Err(OtError::InvalidInput(format!(
-            "The change set must be based on a document with length {}, but the document had length {}",
-            input_len, doc_len,
-        )));
+        return Err(OtError::LengthMismatch {
+            expected: input_len,
+            actual: doc_len as i64,
+        });
     }
-    let mut inverted_change_set = ChangeSet::new();
+    let mut inverted_change_set = ChangeSet::with_capacity(change_set.ops.len());
     let mut index: usize = 0;
-    for change_op in change_set.ops.iter() {
-        let op = change_op
-            .op
-            .as_ref()
-            .ok_or_else(|| OtError::InvalidInput(String::from("Change set had an empty op")))?;
+    for_each_op(change_set, |op| {
         match op {
             Op::Insert(insert) => {
                 inverted_change_set.delete(insert.content.len() as i64);
@@ -575,8 +855,7 @@ pub fn invert_slice(document_u16: &[u16], change_set: &ChangeSet) -> Result<Chan
             Op::Delete(delete) => {
                 let delete_count = delete.count as usize;
                 let content = &document_u16[index..(index + delete_count)];
-                let content: Vec<u32> = content.iter().map(|ch| *ch as u32).collect();
-                inverted_change_set.insert_vec(content);
+                inverted_change_set.insert_vec(utils::u16_slice_to_u32_vec(content));
                 index += delete_count;
             }
             Op::Retain(retain) => {
@@ -584,10 +863,214 @@ pub fn invert_slice(document_u16: &[u16], change_set: &ChangeSet) -> Result<Chan
                 index += retain.count as usize;
             }
         }
-    }
+        Ok(())
+    })?;
     Ok(inverted_change_set)
 }
 
+/// Returns a change set that turns `old_document` into `new_document`. It retains the longest
+/// common prefix and suffix, and replaces everything in between.
+///
+/// The result is not a minimal edit, but it is what a single edit, like typing or pasting over a
+/// selection, looks like, and it never splits a surrogate pair.
+pub fn diff(old_document: &str, new_document: &str) -> ChangeSet {
+    let old_u16 = utils::str_to_u16_vec(old_document);
+    let new_u16 = utils::str_to_u16_vec(new_document);
+    diff_slices(&old_u16, &new_u16)
+}
+
+pub fn diff_slices(old_u16: &[u16], new_u16: &[u16]) -> ChangeSet {
+    let is_high_surrogate = |c: u16| (0xd800..0xdc00).contains(&c);
+    let is_low_surrogate = |c: u16| (0xdc00..0xe000).contains(&c);
+
+    let mut prefix_len = old_u16
+        .iter()
+        .zip(new_u16.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    if prefix_len > 0 && is_high_surrogate(old_u16[prefix_len - 1]) {
+        prefix_len -= 1;
+    }
+    let max_suffix_len = old_u16.len().min(new_u16.len()) - prefix_len;
+    let mut suffix_len = old_u16
+        .iter()
+        .rev()
+        .zip(new_u16.iter().rev())
+        .take(max_suffix_len)
+        .take_while(|(a, b)| a == b)
+        .count();
+    if suffix_len > 0 && is_low_surrogate(old_u16[old_u16.len() - suffix_len]) {
+        suffix_len -= 1;
+    }
+
+    let mut change_set = ChangeSet::new();
+    change_set.retain(prefix_len as i64);
+    change_set.delete((old_u16.len() - prefix_len - suffix_len) as i64);
+    change_set.insert_vec_u16(new_u16[prefix_len..new_u16.len() - suffix_len].to_vec());
+    change_set.retain(suffix_len as i64);
+    change_set
+}
+
+/// Returns true if applying the change set leaves the document unchanged.
+///
+/// Without a document, this is a structural check: the change set is an identity if it only
+/// retains characters. With a document, change sets that replace text with the same text, like
+/// deleting "x" and inserting "x" in its place, are recognized as identities too.
+///
+/// Returns false if the change set has an empty op, or if the document's length does not match the
+/// change set's input document length.
+pub fn is_identity(change_set: &ChangeSet, document_u16: Option<&[u16]>) -> bool {
+    let (input_len, output_len) = match get_input_output_doc_lengths(change_set) {
+        Ok(lengths) => lengths,
+        Err(_) => return false,
+    };
+    if let Some(document_u16) = document_u16 {
+        if input_len as usize != document_u16.len() {
+            return false;
+        }
+    }
+    let only_retains = change_set
+        .ops
+        .iter()
+        .all(|change_op| matches!(change_op.op, Some(Op::Retain(_))));
+    if only_retains {
+        return true;
+    }
+    let document_u16 = match document_u16 {
+        Some(document_u16) => document_u16,
+        None => return false,
+    };
+    if input_len != output_len {
+        return false;
+    }
+    // Each run of deletes and inserts between retains replaces the deleted text with the inserted
+    // text. The change set is an identity if every run puts back exactly what it removed.
+    let mut offset = 0;
+    let mut run_start = 0;
+    let mut inserted: Vec<u16> = Vec::new();
+    let mut runs_match = true;
+    for_each_op(change_set, |op| {
+        match op {
+            Op::Retain(retain) => {
+                runs_match = runs_match && document_u16[run_start..offset] == inserted[..];
+                inserted.clear();
+                offset += retain.count as usize;
+                run_start = offset;
+            }
+            Op::Delete(delete) => {
+                offset += delete.count as usize;
+            }
+            Op::Insert(insert) => {
+                inserted.extend(utils::u32_slice_to_u16_vec(&insert.content)?);
+            }
+        }
+        Ok(())
+    })
+    .is_ok()
+        && runs_match
+        && document_u16[run_start..offset] == inserted[..]
+}
+
+/// A possible problem with a change set, found by `diagnose`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Diagnostic {
+    /// The index of the op that the diagnostic is about.
+    pub index: usize,
+    pub kind: DiagnosticKind,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DiagnosticKind {
+    /// The op has no `op` field. Most functions in this crate reject change sets like this.
+    EmptyOp,
+    /// The op does not retain, delete or insert any characters.
+    ZeroLengthOp,
+    /// The op retains or deletes a negative number of characters.
+    NegativeCount,
+    /// The op has the same type as the op before it. The two could be merged into one op.
+    AdjacentOpsOfSameType,
+    /// The op is an insert that comes right after a delete. Inserting before deleting gives the
+    /// same result.
+    InsertAfterDelete,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.kind {
+            DiagnosticKind::EmptyOp => write!(f, "empty op at index {}", self.index),
+            DiagnosticKind::ZeroLengthOp => write!(f, "zero-length op at index {}", self.index),
+            DiagnosticKind::NegativeCount => write!(f, "negative count at index {}", self.index),
+            DiagnosticKind::AdjacentOpsOfSameType => write!(
+                f,
+                "adjacent ops of same type at indices {} and {}",
+                self.index - 1,
+                self.index
+            ),
+            DiagnosticKind::InsertAfterDelete => write!(
+                f,
+                "delete+insert pair at indices {} and {} could be reordered",
+                self.index - 1,
+                self.index
+            ),
+        }
+    }
+}
+
+/// Lints the change set, returning diagnostics in the order of the ops they refer to.
+///
+/// Change sets built with the `ChangeSet` methods never have empty ops, zero-length ops or adjacent
+/// ops of the same type, so those diagnostics usually point to a bug in the code that built the
+/// change set, or to a corrupted message. An insert after a delete is harmless, but it is worth
+/// knowing about when comparing change sets that should be equal.
+pub fn diagnose(change_set: &ChangeSet) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut prev_op: Option<&Op> = None;
+    for (index, change_op) in change_set.ops.iter().enumerate() {
+        let op = match change_op.op.as_ref() {
+            Some(op) => op,
+            None => {
+                diagnostics.push(Diagnostic {
+                    index,
+                    kind: DiagnosticKind::EmptyOp,
+                });
+                prev_op = None;
+                continue;
+            }
+        };
+        let len = match op {
+            Op::Retain(retain) => retain.count,
+            Op::Delete(delete) => delete.count,
+            Op::Insert(insert) => insert.content.len() as i64,
+        };
+        if len < 0 {
+            diagnostics.push(Diagnostic {
+                index,
+                kind: DiagnosticKind::NegativeCount,
+            });
+        } else if len == 0 {
+            diagnostics.push(Diagnostic {
+                index,
+                kind: DiagnosticKind::ZeroLengthOp,
+            });
+        }
+        if let Some(prev_op) = prev_op {
+            if std::mem::discriminant(prev_op) == std::mem::discriminant(op) {
+                diagnostics.push(Diagnostic {
+                    index,
+                    kind: DiagnosticKind::AdjacentOpsOfSameType,
+                });
+            } else if matches!((prev_op, op), (Op::Delete(_), Op::Insert(_))) {
+                diagnostics.push(Diagnostic {
+                    index,
+                    kind: DiagnosticKind::InsertAfterDelete,
+                });
+            }
+        }
+        prev_op = Some(op);
+    }
+    diagnostics
+}
+
 /// Transforms the text selection according to the changes included in the change set.
 ///
 /// A selection describes the current cursor position in the text and how many characters are
@@ -619,18 +1102,15 @@ pub fn transform_selection(
     let mut new_selection_offset = selection.offset;
     let mut new_selection_count = selection.count;
     let (selection_start, selection_end) = (selection.offset, selection.offset + selection.count);
-    for change_op in change_set.ops.iter() {
+    // Not `for_each_op`, since the walk stops at the end of the selection. Changes after it do not
+    // affect it, and empty ops there are ignored.
+    for op in change_set.iter_ops() {
         if change_set_offset >= selection_end {
             break;
         }
-        let op = change_op
-            .op
-            .as_ref()
-            .ok_or_else(|| OtError::InvalidInput(String::from("Unexpected missing op")))?;
-        match op {
+        match op? {
             Op::Retain(retain) => {
                 change_set_offset += retain.count;
-                continue;
             }
             Op::Insert(insert) => {
                 let insert_chars_count = insert.content.len() as i64;
@@ -663,6 +1143,194 @@ pub fn transform_selection(
     })
 }
 
+/// Transforms a position in the document, like a cursor without a selection, according to the
+/// changes included in the change set, like `transform_selection` does an empty selection. Text
+/// inserted at the position ends up after it. If the character after the position is deleted, the
+/// position moves to the start of the deletion.
+pub fn transform_position(position: i64, change_set: &ChangeSet) -> Result<i64, OtError> {
+    let selection = Selection {
+        offset: position,
+        count: 0,
+    };
+    Ok(transform_selection(&selection, change_set)?.offset)
+}
+
+/// Transforms many selections according to the changes included in the change set, like calling
+/// `transform_selection` on each one.
+///
+/// This walks the change set only once, and then transforms each selection with a binary search,
+/// so it stays fast for a change set with many ops and many selections, like all the spelling
+/// errors in a long document.
+pub fn transform_selections(
+    selections: &[Selection],
+    change_set: &ChangeSet,
+) -> Result<Vec<Selection>, OtError> {
+    // The input offset of each insert, with the total length of all inserts up to and including it.
+    let mut inserts: Vec<(i64, i64)> = Vec::new();
+    // The input bounds of each delete, with the total length of all deletes before it.
+    let mut deletes: Vec<(i64, i64, i64)> = Vec::new();
+    let mut change_set_offset = 0;
+    let mut inserted_count = 0;
+    let mut deleted_count = 0;
+    for_each_op(change_set, |op| {
+        match op {
+            Op::Retain(retain) => {
+                change_set_offset += retain.count;
+            }
+            Op::Insert(insert) => {
+                inserted_count += insert.content.len() as i64;
+                inserts.push((change_set_offset, inserted_count));
+            }
+            Op::Delete(delete) => {
+                let delete_end = change_set_offset + delete.count;
+                deletes.push((change_set_offset, delete_end, deleted_count));
+                deleted_count += delete.count;
+                change_set_offset = delete_end;
+            }
+        }
+        Ok(())
+    })?;
+
+    // Maps an input offset to an output offset. Text inserted exactly at the offset counts as
+    // before it only if `include_inserts_at_offset` is set.
+    let transform_offset = |offset: i64, include_inserts_at_offset: bool| -> i64 {
+        let inserts_before = inserts.partition_point(|(insert_offset, _)| {
+            *insert_offset < offset || (include_inserts_at_offset && *insert_offset == offset)
+        });
+        let inserted_before = match inserts_before {
+            0 => 0,
+            n => inserts[n - 1].1,
+        };
+        let deletes_before = deletes.partition_point(|(delete_start, _, _)| *delete_start < offset);
+        let deleted_before = match deletes_before {
+            0 => 0,
+            n => {
+                let (delete_start, delete_end, deleted_count) = deletes[n - 1];
+                deleted_count + get_overlap_len((0, offset), (delete_start, delete_end))
+            }
+        };
+        offset + inserted_before - deleted_before
+    };
+
+    Ok(selections
+        .iter()
+        .map(|selection| {
+            let selection_end = selection.offset + selection.count;
+            // Like `transform_selection`, text inserted at the start of a selection moves the
+            // selection, but text inserted at a cursor goes after the cursor.
+            let new_offset = transform_offset(selection.offset, selection.count > 0);
+            let new_end = transform_offset(selection_end, false);
+            Selection {
+                offset: new_offset,
+                count: new_end - new_offset,
+            }
+        })
+        .collect())
+}
+
+/// Transforms a region that someone is editing, like the paragraph that they are typing in,
+/// according to the changes included in the change set.
+///
+/// Unlike a selection, a region also grows to take in text inserted exactly at its start or end,
+/// since that is most likely its editor's own typing. An empty region, like an empty paragraph,
+/// grows to take in text inserted at it.
+///
+/// Returns `None` if the change set deletes all of a non-empty region.
+pub fn transform_region(
+    region: &Selection,
+    change_set: &ChangeSet,
+) -> Result<Option<Selection>, OtError> {
+    let mut change_set_offset = 0;
+    let (region_start, region_end) = (region.offset, region.offset + region.count);
+    let (mut new_region_start, mut new_region_end) = (region_start, region_end);
+    for_each_op(change_set, |op| {
+        // Changes after the end of the region do not affect it.
+        if change_set_offset > region_end {
+            return Ok(());
+        }
+        match op {
+            Op::Retain(retain) => {
+                change_set_offset += retain.count;
+            }
+            Op::Insert(insert) => {
+                let insert_chars_count = insert.content.len() as i64;
+                if change_set_offset < region_start {
+                    new_region_start += insert_chars_count;
+                }
+                new_region_end += insert_chars_count;
+            }
+            Op::Delete(delete) => {
+                let delete_bounds = (change_set_offset, change_set_offset + delete.count);
+                new_region_start -= get_overlap_len((0, region_start), delete_bounds);
+                new_region_end -= get_overlap_len((0, region_end), delete_bounds);
+                change_set_offset += delete.count;
+            }
+        }
+        Ok(())
+    })?;
+    if region.count > 0 && new_region_end == new_region_start {
+        return Ok(None);
+    }
+    Ok(Some(Selection {
+        offset: new_region_start,
+        count: new_region_end - new_region_start,
+    }))
+}
+
+/// Receives the ops of a change set one at a time, in order. See `visit_ops`.
+///
+/// Each method does nothing by default, so a visitor only needs to implement the ops it cares
+/// about. Returning an error stops the visit.
+pub trait OpVisitor {
+    fn visit_retain(&mut self, _count: i64) -> Result<(), OtError> {
+        Ok(())
+    }
+
+    fn visit_insert(&mut self, _content: &[u32]) -> Result<(), OtError> {
+        Ok(())
+    }
+
+    fn visit_delete(&mut self, _count: i64) -> Result<(), OtError> {
+        Ok(())
+    }
+}
+
+/// Calls the visitor method matching each op in the change set, in order.
+///
+/// # Errors
+///
+/// - Returns `OtError::EmptyOp` if the change set contains an empty op.
+/// - Returns the first error returned by the visitor.
+pub fn visit_ops<V>(change_set: &ChangeSet, visitor: &mut V) -> Result<(), OtError>
+where
+    V: OpVisitor + ?Sized,
+{
+    for_each_op(change_set, |op| match op {
+        Op::Retain(retain) => visitor.visit_retain(retain.count),
+        Op::Insert(insert) => visitor.visit_insert(&insert.content),
+        Op::Delete(delete) => visitor.visit_delete(delete.count),
+    })
+}
+
+/// Calls `f` with each op in the change set, in order. The closure form of `visit_ops`.
+///
+/// This covers functions that walk one change set. Functions that walk two change sets, or a
+/// change set and a document, in lockstep, like `transform` and `compose`, use `next_op` instead.
+///
+/// # Errors
+///
+/// - Returns `OtError::EmptyOp` if the change set contains an empty op.
+/// - Returns the first error returned by `f`.
+pub fn for_each_op<F>(change_set: &ChangeSet, mut f: F) -> Result<(), OtError>
+where
+    F: FnMut(&Op) -> Result<(), OtError>,
+{
+    for op in change_set.iter_ops() {
+        f(op?)?;
+    }
+    Ok(())
+}
+
 pub fn next_op<'a>(iter: &mut dyn Iterator<Item = &'a ChangeOp>) -> Result<Option<&'a Op>, OtError> {
     match iter.next() {
         None => Ok(None),
@@ -722,10 +1390,49 @@ impl ChangeSet {
     }
 
     /// Creates an empty change set, allocating enough capacity for the given number of operations.
+    ///
+    /// `transform`, `compose`, and `invert` use this to size their results up front. The ops live in
+    /// a plain `Vec` because `ChangeSet` is generated from its protobuf definition by prost.
     pub fn with_capacity(capacity: usize) -> Self {
         Self { ops: Vec::with_capacity(capacity) }
     }
 
+    /// Iterates over the change set's ops, in order. Yields `OtError::EmptyOp` in place of each op
+    /// whose `op` field is missing, so callers never have to unwrap the `Option` themselves.
+    pub fn iter_ops(&self) -> impl Iterator<Item = Result<&Op, OtError>> + '_ {
+        self.ops
+            .iter()
+            .enumerate()
+            .map(|(index, change_op)| change_op.op.as_ref().ok_or(OtError::EmptyOp { index }))
+    }
+
+    /// Like `iter_ops`, but yields mutable references to the ops.
+    pub fn iter_ops_mut(&mut self) -> impl Iterator<Item = Result<&mut Op, OtError>> + '_ {
+        self.ops
+            .iter_mut()
+            .enumerate()
+            .map(|(index, change_op)| change_op.op.as_mut().ok_or(OtError::EmptyOp { index }))
+    }
+
+    /// The lengths of the documents before and after the change set applies. The same as
+    /// `get_input_output_doc_lengths`.
+    ///
+    /// The lengths are computed each time, by walking every op. To avoid walking them again, hold
+    /// on to the result rather than calling this in a loop.
+    pub fn lengths(&self) -> Result<(i64, i64), OtError> {
+        get_input_output_doc_lengths(self)
+    }
+
+    /// The length of the document that the change set applies to.
+    pub fn input_len(&self) -> Result<i64, OtError> {
+        self.lengths().map(|(input_len, _)| input_len)
+    }
+
+    /// The length of the document after the change set applies.
+    pub fn output_len(&self) -> Result<i64, OtError> {
+        self.lengths().map(|(_, output_len)| output_len)
+    }
+
     /// Appends a `Retain` operation to the change set. If the last operation was a `Retain`, it
     /// will be extended.
     ///
@@ -752,7 +1459,7 @@ impl ChangeSet {
     /// will be extended to include the new content.
     pub fn insert(&mut self, content: &str) {
         self.push_op(Op::Insert(Insert {
-            content: content.encode_utf16().map(u16::into).collect(),
+            content: utils::str_to_u32_vec(content),
         }));
     }
 
@@ -769,8 +1476,7 @@ impl ChangeSet {
     }
 
     pub fn insert_vec_u16(&mut self, content: Vec<u16>) {
-        let content: Vec<u32> = content.into_iter().map(|ch| ch as u32).collect();
-        self.insert_vec(content);
+        self.insert_vec(utils::u16_slice_to_u32_vec(&content));
     }
 
     /// Appends an `Insert` operation to the change set. If the last operation was an `Insert`, it
@@ -832,63 +1538,471 @@ impl ChangeSet {
     pub fn is_empty(&self) -> bool {
         self.ops.is_empty()
     }
-}
 
-impl std::fmt::Display for ChangeSet {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "Change Set:")?;
-        let result = get_input_output_doc_lengths(&self);
-        if let Ok((input_len, output_len)) = result {
-            writeln!(f, "input/output lengths: ({}, {})", input_len, output_len)?;
+    /// Returns the ranges of the output document that the change set modified, in order. Use this
+    /// to redraw only the parts of a document that changed.
+    ///
+    /// Each run of inserts and deletes that is not interrupted by a retain yields one range,
+    /// covering the inserted characters. A run of deletes with no inserts yields an empty range at
+    /// the position where the characters were removed.
+    ///
+    /// Ranges never overlap, but one range may end where the next begins. Empty ops are skipped.
+    pub fn affected_ranges(&self) -> AffectedRanges<'_> {
+        AffectedRanges {
+            ops: self.ops.iter(),
+            offset: 0,
         }
+    }
+
+    /// Returns true if the change set changes any of the text in `range` of the input document: it
+    /// deletes a character in the range, or inserts text strictly inside it.
+    ///
+    /// Like `transform_selection`, text inserted exactly at `range.start` or `range.end` goes
+    /// outside of the range, so it does not count, and nothing can change an empty range. Use
+    /// `intersects_range` to count it. Empty ops are skipped.
+    pub fn modifies_range(&self, range: Range<i64>) -> bool {
+        self.touches_range(range, false)
+    }
+
+    /// Like `modifies_range`, but text inserted exactly at `range.start` or `range.end` counts
+    /// too, so an empty range intersects the inserts at its position.
+    pub fn intersects_range(&self, range: Range<i64>) -> bool {
+        self.touches_range(range, true)
+    }
+
+    fn touches_range(&self, range: Range<i64>, include_inserts_at_edges: bool) -> bool {
+        let mut offset = 0;
         for change_op in self.ops.iter() {
+            // Changes after the end of the range do not touch it.
+            if offset > range.end {
+                break;
+            }
             match change_op.op.as_ref() {
-                None => {
-                    writeln!(f, "- EMPTY OP")?;
-                }
+                None => {}
                 Some(Op::Retain(retain)) => {
-                    writeln!(f, "- Retain({})", retain.count)?;
+                    offset += retain.count;
                 }
-                Some(Op::Delete(delete)) => {
-                    writeln!(f, "- Delete({})", delete.count)?;
+                Some(Op::Insert(_)) => {
+                    let touches = if include_inserts_at_edges {
+                        range.start <= offset && offset <= range.end
+                    } else {
+                        range.start < offset && offset < range.end
+                    };
+                    if touches {
+                        return true;
+                    }
                 }
-                Some(Op::Insert(insert)) => {
-                    let content: Vec<u16> = insert.content.iter().map(|ch| *ch as u16).collect();
-                    let content_str = String::from_utf16_lossy(&content);
-                    writeln!(f, "- Insert(\"{}\")", &content_str)?;
+                Some(Op::Delete(delete)) => {
+                    let op_bounds = (offset, offset + delete.count);
+                    if get_overlap_len((range.start, range.end), op_bounds) > 0 {
+                        return true;
+                    }
+                    offset += delete.count;
                 }
             }
         }
-        Ok(())
+        false
     }
-}
 
-pub fn get_input_output_doc_lengths(change_set: &ChangeSet) -> Result<(i64, i64), OtError> {
-    let mut retained: i64 = 0;
-    let mut deleted: i64 = 0;
-    let mut inserted: i64 = 0;
-    for i in 0..change_set.ops.len() {
-        match &change_set.ops[i].op {
-            Some(Op::Retain(retain)) => {
-                retained += retain.count;
-            }
-            Some(Op::Insert(insert)) => {
-                inserted += insert.content.len() as i64;
-            }
-            Some(Op::Delete(delete)) => {
-                deleted += delete.count;
+    /// Returns the ranges of the input document that the change set deletes, in order. Deletes
+    /// that follow one another, even with inserts between them, make one range. Empty ops are
+    /// skipped.
+    pub fn deleted_ranges(&self) -> Vec<Range<i64>> {
+        let mut ranges: Vec<Range<i64>> = Vec::new();
+        let mut offset = 0;
+        for change_op in self.ops.iter() {
+            match change_op.op.as_ref() {
+                None | Some(Op::Insert(_)) => {}
+                Some(Op::Retain(retain)) => {
+                    offset += retain.count;
+                }
+                Some(Op::Delete(delete)) => {
+                    match ranges.last_mut() {
+                        Some(last) if last.end == offset => last.end += delete.count,
+                        _ => ranges.push(offset..offset + delete.count),
+                    }
+                    offset += delete.count;
+                }
             }
-            None => {
-                return Err(OtError::InvalidInput(format!(
-                    "Unexpected empty op at index {}",
-                    i
-                )));
+        }
+        ranges
+    }
+
+    /// Returns the positions in the input document where the change set inserts text, in order.
+    /// Each position is listed once, even if several inserts are made there. Empty ops are
+    /// skipped.
+    pub fn inserted_positions(&self) -> Vec<i64> {
+        let mut positions: Vec<i64> = Vec::new();
+        let mut offset = 0;
+        for change_op in self.ops.iter() {
+            match change_op.op.as_ref() {
+                None => {}
+                Some(Op::Retain(retain)) => {
+                    offset += retain.count;
+                }
+                Some(Op::Delete(delete)) => {
+                    offset += delete.count;
+                }
+                Some(Op::Insert(insert))
+                    if !insert.content.is_empty() && positions.last() != Some(&offset) =>
+                {
+                    positions.push(offset);
+                }
+                Some(Op::Insert(_)) => {}
+            }
+        }
+        positions
+    }
+
+    /// Returns the part of the change set that affects the given range of the input document.
+    ///
+    /// The result is a change set whose input document is just the characters in `range`. It
+    /// retains and deletes what this change set retains and deletes within the range, and includes
+    /// the inserts made within the range.
+    ///
+    /// An insert at input position `p` belongs to the range that contains `p`, so an insert at
+    /// `range.start` is included and an insert at `range.end` is not. The one exception is an
+    /// insert at the very end of the input document, which belongs to the range that ends there.
+    /// With this rule, slicing the input document into adjacent ranges splits the change set into
+    /// pieces that concatenate back into the original.
+    ///
+    /// The range is clamped to the bounds of the input document. Empty ops are skipped.
+    pub fn slice(&self, range: Range<i64>) -> ChangeSet {
+        let input_len: i64 = self
+            .ops
+            .iter()
+            .map(|change_op| match change_op.op.as_ref() {
+                Some(Op::Retain(retain)) => retain.count,
+                Some(Op::Delete(delete)) => delete.count,
+                _ => 0,
+            })
+            .sum();
+        let start = range.start.max(0).min(input_len);
+        let end = range.end.max(start).min(input_len);
+
+        let mut sliced = ChangeSet::new();
+        let mut offset = 0;
+        for change_op in self.ops.iter() {
+            match change_op.op.as_ref() {
+                None => {}
+                Some(Op::Insert(insert))
+                    if (start <= offset && offset < end) || (offset == end && end == input_len) =>
+                {
+                    sliced.insert_slice(&insert.content);
+                }
+                Some(Op::Insert(_)) => {}
+                Some(Op::Retain(retain)) => {
+                    let op_bounds = (offset, offset + retain.count);
+                    sliced.retain(get_overlap_len((start, end), op_bounds));
+                    offset += retain.count;
+                }
+                Some(Op::Delete(delete)) => {
+                    let op_bounds = (offset, offset + delete.count);
+                    sliced.delete(get_overlap_len((start, end), op_bounds));
+                    offset += delete.count;
+                }
+            }
+            if offset > end {
+                break;
+            }
+        }
+        sliced
+    }
+}
+
+/// Iterator over the output document ranges that a change set modified. See
+/// `ChangeSet::affected_ranges`.
+pub struct AffectedRanges<'a> {
+    ops: std::slice::Iter<'a, ChangeOp>,
+    offset: i64,
+}
+
+impl<'a> Iterator for AffectedRanges<'a> {
+    type Item = Range<i64>;
+
+    fn next(&mut self) -> Option<Range<i64>> {
+        let mut range: Option<Range<i64>> = None;
+        for change_op in &mut self.ops {
+            match change_op.op.as_ref() {
+                None => {}
+                Some(Op::Retain(retain)) => {
+                    self.offset += retain.count;
+                    if let Some(range) = range {
+                        return Some(range);
+                    }
+                }
+                Some(Op::Insert(insert)) => {
+                    let range = range.get_or_insert(self.offset..self.offset);
+                    self.offset += insert.content.len() as i64;
+                    range.end = self.offset;
+                }
+                Some(Op::Delete(_)) => {
+                    range.get_or_insert(self.offset..self.offset);
+                }
+            }
+        }
+        range
+    }
+}
+
+/// Builds a change set that replaces ranges of a document with new content, like a series of
+/// `Array.prototype.splice` calls.
+///
+/// Splices must be added in order, and must not overlap. Each one becomes an insert followed by a
+/// delete, with the untouched text in between retained, so the same splices always build the same
+/// change set.
+///
+/// ```
+/// use ot::SpliceBuilder;
+///
+/// // "Hello world" -> "Hi world!"
+/// let mut builder = SpliceBuilder::new(11);
+/// builder.splice(0..5, &[72, 105]).unwrap();
+/// builder.splice(11..11, &[33]).unwrap();
+/// let change_set = builder.finish();
+/// assert_eq!(ot::apply("Hello world", &change_set).unwrap(), "Hi world!");
+/// ```
+pub struct SpliceBuilder {
+    change_set: ChangeSet,
+    document_len: i64,
+    offset: i64,
+}
+
+impl SpliceBuilder {
+    /// Starts building a change set for a document of the given length.
+    pub fn new(document_len: i64) -> Self {
+        Self {
+            change_set: ChangeSet::new(),
+            document_len,
+            offset: 0,
+        }
+    }
+
+    /// Replaces `range` of the document with `content`, a sequence of UTF-16 code units.
+    ///
+    /// # Errors
+    ///
+    /// - Returns `OtError::InvalidInput` if the range is out of order, overlaps a previous splice,
+    ///   or extends past the end of the document.
+    pub fn splice(&mut self, range: Range<i64>, content: &[u32]) -> Result<(), OtError> {
+        if range.start < self.offset || range.start > range.end || range.end > self.document_len {
+            return Err(OtError::InvalidInput(format!(
+                "Cannot splice range {:?} after offset {} in a document with length {}",
+                range, self.offset, self.document_len
+            )));
+        }
+        self.change_set.retain(range.start - self.offset);
+        self.change_set.insert_slice(content);
+        self.change_set.delete(range.end - range.start);
+        self.offset = range.end;
+        Ok(())
+    }
+
+    /// Returns the change set, retaining the rest of the document after the last splice.
+    pub fn finish(mut self) -> ChangeSet {
+        self.change_set.retain(self.document_len - self.offset);
+        self.change_set
+    }
+}
+
+impl ChangeSet {
+    /// Renders the change set on one line, showing at most `max_insert_chars` characters of each
+    /// insert. Use this when logging change sets, which can hold whole pasted documents.
+    ///
+    /// ```
+    /// # use ot::writing_proto::ChangeSet;
+    /// let mut change_set = ChangeSet::new();
+    /// change_set.retain(3);
+    /// change_set.insert("Hello, world!");
+    /// change_set.delete(2);
+    /// assert_eq!(
+    ///     change_set.fmt_compact(5).to_string(),
+    ///     "[retain 3, insert \"Hello\"... (13 chars), delete 2] (5 -> 16)"
+    /// );
+    /// ```
+    pub fn fmt_compact(&self, max_insert_chars: usize) -> CompactChangeSet<'_> {
+        CompactChangeSet {
+            change_set: self,
+            max_insert_chars,
+        }
+    }
+}
+
+/// A change set rendered on one line, with long inserts truncated. See `ChangeSet::fmt_compact`.
+pub struct CompactChangeSet<'a> {
+    change_set: &'a ChangeSet,
+    max_insert_chars: usize,
+}
+
+impl std::fmt::Display for CompactChangeSet<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[")?;
+        for (i, change_op) in self.change_set.ops.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match change_op.op.as_ref() {
+                None => write!(f, "empty op")?,
+                Some(Op::Retain(retain)) => write!(f, "retain {}", retain.count)?,
+                Some(Op::Delete(delete)) => write!(f, "delete {}", delete.count)?,
+                Some(Op::Insert(insert)) => {
+                    let len = insert.content.len();
+                    if len <= self.max_insert_chars {
+                        let content = utils::u32_slice_to_string_lossy(&insert.content);
+                        write!(f, "insert {:?}", content)?;
+                    } else {
+                        let content = utils::u32_slice_to_string_lossy(
+                            &insert.content[..self.max_insert_chars],
+                        );
+                        write!(f, "insert {:?}... ({} chars)", content, len)?;
+                    }
+                }
+            }
+        }
+        write!(f, "]")?;
+        match get_input_output_doc_lengths(self.change_set) {
+            Ok((input_len, output_len)) => write!(f, " ({} -> {})", input_len, output_len),
+            Err(_) => write!(f, " (invalid)"),
+        }
+    }
+}
+
+/// Summarizes the change set by counting its ops, without any of the inserted content. See
+/// `ChangeSet::fmt_compact` to see the ops themselves.
+impl std::fmt::Display for ChangeSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (mut retains, mut inserts, mut deletes, mut empty_ops) = (0, 0, 0, 0);
+        for change_op in self.ops.iter() {
+            match change_op.op.as_ref() {
+                None => empty_ops += 1,
+                Some(Op::Retain(_)) => retains += 1,
+                Some(Op::Insert(_)) => inserts += 1,
+                Some(Op::Delete(_)) => deletes += 1,
+            }
+        }
+        write!(
+            f,
+            "Change set with {} ops ({} retains, {} inserts, {} deletes",
+            self.ops.len(),
+            retains,
+            inserts,
+            deletes
+        )?;
+        if empty_ops > 0 {
+            write!(f, ", {} empty", empty_ops)?;
+        }
+        match get_input_output_doc_lengths(self) {
+            Ok((input_len, output_len)) => write!(
+                f,
+                "), input length {}, output length {}",
+                input_len, output_len
+            ),
+            Err(_) => write!(f, "), invalid lengths"),
+        }
+    }
+}
+
+/// Returns the lengths of the documents before and after the change set applies.
+///
+/// Returns `OtError::NegativeCount` or `OtError::LengthOverflow` if no document could have those
+/// lengths, and `OtError::EmptyOp` if the change set contains an empty op.
+pub fn get_input_output_doc_lengths(change_set: &ChangeSet) -> Result<(i64, i64), OtError> {
+    let mut lengths = DocLengths::default();
+    visit_ops(change_set, &mut lengths)?;
+    let before = lengths.retained.checked_add(lengths.deleted);
+    let after = lengths.retained.checked_add(lengths.inserted);
+    match (before, after) {
+        (Some(before), Some(after)) => Ok((before, after)),
+        _ => Err(OtError::LengthOverflow),
+    }
+}
+
+/// Checks a change set from an untrusted source, like one received over the network, before it is
+/// stored or applied: every op must be present, no count may be negative, the document lengths
+/// before and after must fit in an `i64`, and every inserted value must be a UTF-16 code unit.
+///
+/// Unlike `diagnose`, this only rejects change sets that this crate cannot handle. Zero-length ops,
+/// for example, are allowed.
+///
+/// Returns the lengths of the documents before and after the change set applies, like
+/// `get_input_output_doc_lengths`.
+///
+/// # Errors
+///
+/// - Returns `OtError::EmptyOp` if the change set contains an empty op.
+/// - Returns `OtError::NegativeCount` if a retain or delete has a negative count.
+/// - Returns `OtError::LengthOverflow` if a document length does not fit in an `i64`.
+/// - Returns `OtError::InvalidInsertContent` if an insert has a value above `0xFFFF`, with the
+///   op's index and the value's index in the insert's content.
+pub fn validate(change_set: &ChangeSet) -> Result<(i64, i64), OtError> {
+    let lengths = get_input_output_doc_lengths(change_set)?;
+    for (index, change_op) in change_set.ops.iter().enumerate() {
+        if let Some(Op::Insert(insert)) = &change_op.op {
+            if let Some(content_index) = insert.content.iter().position(|&ch| ch > 0xFFFF) {
+                return Err(OtError::InvalidInsertContent {
+                    index,
+                    content_index,
+                });
             }
         }
     }
-    let before = retained + deleted;
-    let after = retained + inserted;
-    Ok((before, after))
+    Ok(lengths)
+}
+
+#[derive(Default)]
+struct DocLengths {
+    retained: i64,
+    deleted: i64,
+    inserted: i64,
+    // The index of the next op, for errors.
+    index: usize,
+}
+
+impl DocLengths {
+    fn add(&mut self, count: i64, select: fn(&mut Self) -> &mut i64) -> Result<(), OtError> {
+        if count < 0 {
+            return Err(OtError::NegativeCount {
+                index: self.index,
+                count,
+            });
+        }
+        let len = select(self);
+        *len = len.checked_add(count).ok_or(OtError::LengthOverflow)?;
+        self.index += 1;
+        Ok(())
+    }
+}
+
+impl OpVisitor for DocLengths {
+    fn visit_retain(&mut self, count: i64) -> Result<(), OtError> {
+        self.add(count, |lengths| &mut lengths.retained)
+    }
+
+    fn visit_insert(&mut self, content: &[u32]) -> Result<(), OtError> {
+        let count = i64::try_from(content.len()).map_err(|_| OtError::LengthOverflow)?;
+        self.add(count, |lengths| &mut lengths.inserted)
+    }
+
+    fn visit_delete(&mut self, count: i64) -> Result<(), OtError> {
+        self.add(count, |lengths| &mut lengths.deleted)
+    }
+}
+
+/// Returns the message that a client signs to vouch for a revision, and that auditors check the
+/// revision's signature against.
+///
+/// `change_set_hash` is the SHA-256 hash of the change set as it was submitted: the encoded
+/// `ChangeSet`, or the encrypted change set of an end-to-end encrypted document. The document id
+/// and revision number are signed too, so that a signature cannot be copied onto another revision.
+pub fn revision_signing_message(
+    doc_id: &str,
+    revision_number: i64,
+    change_set_hash: &[u8],
+) -> Vec<u8> {
+    let mut message =
+        format!("writing-revision-v1\n{}\n{}\n", doc_id, revision_number).into_bytes();
+    message.extend_from_slice(change_set_hash);
+    message
 }
 
 fn get_overlap_len(bounds1: (i64, i64), bounds2: (i64, i64)) -> i64 {
@@ -913,7 +2027,7 @@ mod tests {
                 if let Some(rest) = op.strip_prefix("I:") {
                     ChangeOp {
                         op: Some(Op::Insert(Insert {
-                            content: rest.encode_utf16().map(u16::into).collect(),
+                            content: utils::str_to_u32_vec(rest),
                         })),
                     }
                 } else if let Some(rest) = op.strip_prefix("R:") {
@@ -936,12 +2050,61 @@ mod tests {
         ChangeSet { ops }
     }
 
-    fn string_to_vec_u16(string: &str) -> Vec<u16> {
-        string.to_string().chars().map(|ch| ch as u16).collect()
+    #[test]
+    fn test_display() {
+        let change_set = create_change_set(&["R:3", "I:Hello", "D:2", "R:6"]);
+        assert_eq!(
+            change_set.to_string(),
+            "Change set with 4 ops (2 retains, 1 inserts, 1 deletes), input length 11, output \
+            length 14"
+        );
+        assert_eq!(
+            change_set.fmt_compact(3).to_string(),
+            "[retain 3, insert \"Hel\"... (5 chars), delete 2, retain 6] (11 -> 14)"
+        );
+        assert_eq!(
+            change_set.fmt_compact(5).to_string(),
+            "[retain 3, insert \"Hello\", delete 2, retain 6] (11 -> 14)"
+        );
+
+        let mut change_set = create_change_set(&["R:3"]);
+        change_set.ops.push(ChangeOp { op: None });
+        assert_eq!(
+            change_set.to_string(),
+            "Change set with 2 ops (1 retains, 0 inserts, 0 deletes, 1 empty), invalid lengths"
+        );
+        assert_eq!(
+            change_set.fmt_compact(10).to_string(),
+            "[retain 3, empty op] (invalid)"
+        );
     }
 
-    fn slice_u16_to_string(slice_u16: &[u16]) -> String {
-        slice_u16.iter().map(|ch| (*ch as u8) as char).collect()
+    #[test]
+    fn test_iter_ops() {
+        let mut change_set = create_change_set(&["R:3", "I:Hi", "D:2"]);
+        let ops: Vec<&Op> = change_set.iter_ops().collect::<Result<_, _>>().unwrap();
+        assert_eq!(
+            ops,
+            vec![&retain_op(3), &insert_op(&[72, 105]), &delete_op(2)]
+        );
+        assert_eq!(change_set.lengths().unwrap(), (5, 5));
+        assert_eq!(change_set.input_len().unwrap(), 5);
+        assert_eq!(change_set.output_len().unwrap(), 5);
+
+        for op in change_set.iter_ops_mut() {
+            if let Op::Retain(retain) = op.unwrap() {
+                retain.count += 1;
+            }
+        }
+        assert_eq!(change_set.lengths().unwrap(), (6, 6));
+
+        change_set.ops.insert(1, ChangeOp { op: None });
+        let results: Vec<_> = change_set.iter_ops().collect();
+        assert!(matches!(results[1], Err(OtError::EmptyOp { index: 1 })));
+        assert!(matches!(
+            change_set.output_len(),
+            Err(OtError::EmptyOp { index: 1 })
+        ));
     }
 
     #[test]
@@ -1125,7 +2288,11 @@ mod tests {
 
         let result = transform(&local_change_set, &remote_change_set);
         match result {
-            Err(OtError::InvalidInput(_)) => {}
+            Err(OtError::IncompatibleChangeSets {
+                operation: ChangeSetOperation::Transform,
+                a_len: 5,
+                b_len: 10,
+            }) => {}
             _ => {
                 panic!("Unexpected result: {:?}", result);
             }
@@ -1170,7 +2337,10 @@ mod tests {
 
         let result = apply(document, &change_set);
         match result {
-            Err(OtError::InvalidInput(_)) => {}
+            Err(OtError::LengthMismatch {
+                expected: 8,
+                actual: 9,
+            }) => {}
             _ => {
                 panic!("Unexpected result: {:?}", result);
             }
@@ -1180,26 +2350,26 @@ mod tests {
     #[test]
     fn test_apply_chunks() {
         let document = "AAABBCCCC";
-        let document_vec: Vec<u16> = string_to_vec_u16(&document);
+        let document_vec: Vec<u16> = utils::str_to_u16_vec(document);
         let change_set = create_change_set(&["R:2", "D:2", "I:DDD", "R:3", "I:E", "R:2"]);
         let new_document_vec = apply_slice(&document_vec, &change_set).unwrap();
-        let new_document = slice_u16_to_string(&new_document_vec);
+        let new_document = utils::utf16_to_string(&new_document_vec).unwrap();
         let expected_new_document = "AADDDBCCECC";
         assert_eq!(new_document, expected_new_document);
 
         let document_chunks = vec![
-            string_to_vec_u16("AAA"),
-            string_to_vec_u16("BB"),
-            string_to_vec_u16("CCCC"),
+            utils::str_to_u16_vec("AAA"),
+            utils::str_to_u16_vec("BB"),
+            utils::str_to_u16_vec("CCCC"),
         ];
         let new_document_chunks = apply_chunks(document_chunks, &change_set).unwrap();
         let expected_new_document_chunks = vec![
-            string_to_vec_u16("AA"),
-            string_to_vec_u16("DDD"),
-            string_to_vec_u16("B"),
-            string_to_vec_u16("CC"),
-            string_to_vec_u16("E"),
-            string_to_vec_u16("CC"),
+            utils::str_to_u16_vec("AA"),
+            utils::str_to_u16_vec("DDD"),
+            utils::str_to_u16_vec("B"),
+            utils::str_to_u16_vec("CC"),
+            utils::str_to_u16_vec("E"),
+            utils::str_to_u16_vec("CC"),
         ];
         assert_eq!(new_document_chunks, expected_new_document_chunks);
 
@@ -1207,9 +2377,7 @@ mod tests {
         let change_set = create_change_set(&["I:Hello"]);
         let new_document_chunks = apply_chunks(document_chunks, &change_set).unwrap();
         assert_eq!(new_document_chunks.len(), 1);
-        let expected_new_document_chunks = vec![
-            string_to_vec_u16("Hello"),
-        ];
+        let expected_new_document_chunks = vec![utils::str_to_u16_vec("Hello")];
         assert_eq!(new_document_chunks, expected_new_document_chunks);
     }
 
@@ -1275,13 +2443,201 @@ mod tests {
             create_change_set(&["I:hello"]),
             create_change_set(&["D:10"]),
         ];
-        if let Err(OtError::InvalidInput(_)) = compose_iter(&change_sets) {
+        if let Err(OtError::IncompatibleChangeSets { .. }) = compose_iter(&change_sets) {
             assert!(true);
         } else {
             assert!(false, "Expected invalid input error");
         }
     }
 
+    #[test]
+    fn test_merge_without_conflicts() {
+        let base = "Hello world";
+        // "Hello world" -> "Hello world!" -> "Hello, world!"
+        let local = vec![
+            create_change_set(&["R:11", "I:!"]),
+            create_change_set(&["R:5", "I:,", "R:7"]),
+        ];
+        // "Hello world" -> "Hi world"
+        let remote = vec![create_change_set(&["I:Hi", "D:5", "R:6"])];
+        let result = merge(11, &local, &remote).unwrap();
+        assert!(result.conflicts.is_empty());
+        assert_eq!(apply(base, &result.merged).unwrap(), "Hi, world!");
+        let remote_document = apply(base, &remote[0]).unwrap();
+        assert_eq!(
+            apply(&remote_document, &result.rebased_local).unwrap(),
+            "Hi, world!"
+        );
+    }
+
+    #[test]
+    fn test_merge_with_conflict() {
+        let base = "Hello world";
+        let local = vec![create_change_set(&["R:6", "I:there", "D:5"])];
+        let remote = vec![create_change_set(&["R:6", "D:5", "I:everyone"])];
+        let result = merge(11, &local, &remote).unwrap();
+        let merged = apply(base, &result.merged).unwrap();
+        assert!(merged == "Hello thereeveryone" || merged == "Hello everyonethere");
+        assert_eq!(
+            result.conflicts,
+            vec![MergeConflict {
+                base_range: 6..11,
+                merged_range: 6..19,
+                local_content: utils::str_to_u32_vec("there"),
+                remote_content: utils::str_to_u32_vec("everyone"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_merge_only_deletes_or_inserts_do_not_conflict() {
+        let base = "Hello world";
+        let local = vec![create_change_set(&["R:6", "D:5"])];
+        let remote = vec![create_change_set(&["R:4", "D:4", "R:3"])];
+        let result = merge(11, &local, &remote).unwrap();
+        assert!(result.conflicts.is_empty());
+        assert_eq!(apply(base, &result.merged).unwrap(), "Hell");
+
+        let local = vec![create_change_set(&["R:6", "I:big ", "R:5"])];
+        let remote = vec![create_change_set(&["R:6", "D:5", "I:there"])];
+        let result = merge(11, &local, &remote).unwrap();
+        assert!(result.conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_merge_with_no_changes_on_one_side() {
+        let base = "Hello world";
+        let change_sets = vec![create_change_set(&["R:6", "D:5", "I:there"])];
+        let result = merge(11, &change_sets, &[]).unwrap();
+        assert_eq!(apply(base, &result.rebased_local).unwrap(), "Hello there");
+        assert_eq!(apply(base, &result.merged).unwrap(), "Hello there");
+        let result = merge(11, &[], &change_sets).unwrap();
+        assert_eq!(apply(base, &result.merged).unwrap(), "Hello there");
+        assert_eq!(
+            apply("Hello there", &result.rebased_local).unwrap(),
+            "Hello there"
+        );
+    }
+
+    #[test]
+    fn test_merge_length_mismatch() {
+        let local = vec![create_change_set(&["R:11"])];
+        let result = merge(10, &local, &[]);
+        assert!(matches!(
+            result,
+            Err(OtError::LengthMismatch {
+                expected: 11,
+                actual: 10
+            })
+        ));
+    }
+
+    #[test]
+    fn test_splice_builder() {
+        let document = "one two one three one";
+        let mut builder = SpliceBuilder::new(document.len() as i64);
+        for start in &[0, 8, 18] {
+            builder
+                .splice(*start..(*start + 3), &[u32::from(b'1')])
+                .unwrap();
+        }
+        let change_set = builder.finish();
+        assert_eq!(
+            change_set,
+            create_change_set(&["I:1", "D:3", "R:5", "I:1", "D:3", "R:7", "I:1", "D:3"])
+        );
+        assert_eq!(apply(document, &change_set).unwrap(), "1 two 1 three 1");
+
+        let mut builder = SpliceBuilder::new(10);
+        builder.splice(4..6, &[]).unwrap();
+        assert!(matches!(
+            builder.splice(5..7, &[]),
+            Err(OtError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            builder.splice(8..11, &[]),
+            Err(OtError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_transform_selections() {
+        let change_set =
+            create_change_set(&["R:2", "I:ab", "R:3", "D:4", "I:xyz", "R:1", "D:2", "R:3"]);
+        let mut selections = Vec::new();
+        for offset in 0..=15 {
+            for count in 0..=(15 - offset) {
+                selections.push(Selection { offset, count });
+            }
+        }
+        let new_selections = transform_selections(&selections, &change_set).unwrap();
+        assert_eq!(new_selections.len(), selections.len());
+        for (selection, new_selection) in selections.iter().zip(new_selections.iter()) {
+            assert_eq!(
+                *new_selection,
+                transform_selection(selection, &change_set).unwrap(),
+                "Selection: {:?}",
+                selection
+            );
+        }
+    }
+
+    #[test]
+    fn test_transform_region() {
+        // Each case is a change set, a region as (offset, count), and the transformed region.
+        let cases: Vec<(Vec<&str>, (i64, i64), Option<(i64, i64)>)> = vec![
+            // Text inserted at either edge of the region is taken in.
+            (vec!["R:5", "I:ab", "R:5"], (5, 3), Some((5, 5))),
+            (vec!["R:8", "I:ab", "R:2"], (5, 3), Some((5, 5))),
+            (vec!["R:4", "I:ab", "R:6"], (5, 3), Some((7, 3))),
+            (vec!["R:9", "I:ab", "R:1"], (5, 3), Some((5, 3))),
+            (vec!["R:5", "I:ab", "R:5"], (5, 0), Some((5, 2))),
+            // Deleting across an edge shrinks the region.
+            (vec!["R:3", "D:3", "R:4"], (5, 3), Some((3, 2))),
+            (vec!["R:7", "D:3"], (5, 3), Some((5, 2))),
+            (vec!["D:2", "R:8"], (5, 3), Some((3, 3))),
+            // Deleting the whole region removes it, unless it was empty.
+            (vec!["R:4", "D:5", "R:1"], (5, 3), None),
+            (vec!["R:4", "D:5", "I:new", "R:1"], (5, 3), None),
+            (vec!["R:4", "D:5", "R:1"], (5, 0), Some((4, 0))),
+        ];
+        for (change_set, (offset, count), expected) in cases {
+            let region = Selection { offset, count };
+            let new_region = transform_region(&region, &create_change_set(&change_set)).unwrap();
+            assert_eq!(
+                new_region.map(|r| (r.offset, r.count)),
+                expected,
+                "Change set: {:?}, region: {:?}",
+                change_set,
+                region
+            );
+        }
+    }
+
+    #[test]
+    fn test_transform_position() {
+        // Each case is a change set, a position, and the transformed position.
+        let cases: Vec<(Vec<&str>, i64, i64)> = vec![
+            (vec!["R:5", "I:ab", "R:5"], 5, 5),
+            (vec!["R:5", "I:ab", "R:5"], 6, 8),
+            (vec!["R:5", "I:ab", "R:5"], 4, 4),
+            (vec!["R:2", "D:3", "R:5"], 7, 4),
+            (vec!["R:2", "D:3", "R:5"], 3, 2),
+            (vec!["R:2", "D:3", "R:5"], 2, 2),
+            (vec!["R:4", "D:2", "R:4"], 5, 4),
+            (vec!["D:10", "I:new"], 10, 0),
+        ];
+        for (change_set, position, expected) in cases {
+            assert_eq!(
+                transform_position(position, &create_change_set(&change_set)).unwrap(),
+                expected,
+                "Change set: {:?}, position: {}",
+                change_set,
+                position
+            );
+        }
+    }
+
     #[test]
     fn test_transform_selection_insert_before() {
         let change_set = create_change_set(&["R:5", "I:Hello", "R:5"]);
@@ -1437,6 +2793,440 @@ mod tests {
             count: 3,
         };
         assert_eq!(new_selection, expected);
+
+        // An empty op after the selection is never reached.
+        let mut change_set = change_set;
+        change_set.ops.push(ChangeOp { op: None });
+        let new_selection = transform_selection(&selection, &change_set).unwrap();
+        assert_eq!(new_selection, expected);
+    }
+
+    #[test]
+    fn test_slice_retain_and_delete_overlap() {
+        let change_set = create_change_set(&["R:4", "D:4", "R:4"]);
+        // Entirely inside one op.
+        assert_eq!(change_set.slice(1..3), create_change_set(&["R:2"]));
+        assert_eq!(change_set.slice(5..7), create_change_set(&["D:2"]));
+        // Overlapping the left and right edges of the delete.
+        assert_eq!(change_set.slice(2..6), create_change_set(&["R:2", "D:2"]));
+        assert_eq!(change_set.slice(6..10), create_change_set(&["D:2", "R:2"]));
+        // Covering the delete exactly, and covering everything.
+        assert_eq!(change_set.slice(4..8), create_change_set(&["D:4"]));
+        assert_eq!(change_set.slice(0..12), change_set);
+    }
+
+    #[test]
+    fn test_slice_insert_boundaries() {
+        let change_set = create_change_set(&["I:A", "R:2", "I:B", "D:2", "I:C"]);
+        // Inserts at the start of the range are included. Inserts at the end are not.
+        assert_eq!(change_set.slice(0..2), create_change_set(&["I:A", "R:2"]));
+        assert_eq!(change_set.slice(2..3), create_change_set(&["I:B", "D:1"]));
+        assert_eq!(change_set.slice(1..2), create_change_set(&["R:1"]));
+        // Inserts at the end of the input document belong to the range that ends there.
+        assert_eq!(change_set.slice(3..4), create_change_set(&["D:1", "I:C"]));
+        assert_eq!(change_set.slice(4..4), create_change_set(&["I:C"]));
+        // Other empty ranges are empty.
+        assert_eq!(change_set.slice(2..2), ChangeSet::new());
+    }
+
+    #[test]
+    fn test_slice_clamps_range() {
+        let change_set = create_change_set(&["R:2", "D:2"]);
+        assert_eq!(change_set.slice(-5..3), create_change_set(&["R:2", "D:1"]));
+        assert_eq!(change_set.slice(3..100), create_change_set(&["D:1"]));
+        assert_eq!(change_set.slice(10..20), ChangeSet::new());
+        // Reversed ranges are empty.
+        let reversed = std::ops::Range { start: 3, end: 1 };
+        assert_eq!(change_set.slice(reversed), ChangeSet::new());
+        assert_eq!(
+            create_change_set(&["I:Hello"]).slice(0..0),
+            create_change_set(&["I:Hello"])
+        );
+    }
+
+    #[test]
+    fn test_slice_adjacent_ranges_concatenate_to_original() {
+        let change_set = create_change_set(&["I:A", "R:3", "I:B", "D:3", "R:1", "I:C"]);
+        let (input_len, _) = get_input_output_doc_lengths(&change_set).unwrap();
+        for split in 0..input_len {
+            let mut concatenated = change_set.slice(0..split);
+            for change_op in change_set.slice(split..input_len).ops {
+                concatenated.push_op(change_op.op.unwrap());
+            }
+            assert_eq!(concatenated, change_set, "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn test_modifies_range() {
+        // Each case is a change set, a range, and whether the change set modifies it.
+        let cases: Vec<(Vec<&str>, Range<i64>, bool)> = vec![
+            // Text inserted at either edge of the range goes outside of it.
+            (vec!["R:5", "I:ab", "R:5"], 5..8, false),
+            (vec!["R:8", "I:ab", "R:2"], 5..8, false),
+            (vec!["R:6", "I:ab", "R:4"], 5..8, true),
+            (vec!["I:ab", "R:10"], 5..8, false),
+            // Deleting any character of the range modifies it.
+            (vec!["R:3", "D:2", "R:5"], 5..8, false),
+            (vec!["R:3", "D:3", "R:4"], 5..8, true),
+            (vec!["R:7", "D:3"], 5..8, true),
+            (vec!["R:8", "D:2"], 5..8, false),
+            (vec!["R:5", "D:3", "I:abc", "R:2"], 5..8, true),
+            // Nothing modifies an empty range.
+            (vec!["R:4", "D:2", "I:ab", "R:4"], 5..5, false),
+        ];
+        for (change_set, range, expected) in cases {
+            assert_eq!(
+                create_change_set(&change_set).modifies_range(range.clone()),
+                expected,
+                "Change set: {:?}, range: {:?}",
+                change_set,
+                range
+            );
+        }
+    }
+
+    #[test]
+    fn test_intersects_range() {
+        // Unlike modifying a range, text inserted at its edges intersects it.
+        let cases: Vec<(Vec<&str>, Range<i64>, bool)> = vec![
+            (vec!["R:5", "I:ab", "R:5"], 5..8, true),
+            (vec!["R:8", "I:ab", "R:2"], 5..8, true),
+            (vec!["R:9", "I:ab", "R:1"], 5..8, false),
+            (vec!["R:5", "I:ab", "R:5"], 5..5, true),
+            (vec!["R:3", "D:2", "R:5"], 5..8, false),
+            (vec!["R:4", "D:2", "R:4"], 5..8, true),
+            (vec!["R:10"], 0..10, false),
+        ];
+        for (change_set, range, expected) in cases {
+            assert_eq!(
+                create_change_set(&change_set).intersects_range(range.clone()),
+                expected,
+                "Change set: {:?}, range: {:?}",
+                change_set,
+                range
+            );
+        }
+    }
+
+    #[test]
+    fn test_deleted_ranges_and_inserted_positions() {
+        let change_set =
+            create_change_set(&["I:a", "R:2", "D:2", "I:xyz", "D:1", "R:3", "D:1", "I:b"]);
+        assert_eq!(change_set.deleted_ranges(), vec![2..5, 8..9]);
+        assert_eq!(change_set.inserted_positions(), vec![0, 4, 9]);
+
+        let change_set = create_change_set(&["R:10"]);
+        assert!(change_set.deleted_ranges().is_empty());
+        assert!(change_set.inserted_positions().is_empty());
+    }
+
+    #[test]
+    fn test_affected_ranges() {
+        let change_set = create_change_set(&["R:2", "I:ab", "R:3", "D:2", "R:1", "I:c"]);
+        let ranges: Vec<Range<i64>> = change_set.affected_ranges().collect();
+        assert_eq!(ranges, vec![2..4, 7..7, 8..9]);
+
+        // Inserts and deletes that are not separated by a retain are one range.
+        let change_set = create_change_set(&["D:2", "I:xyz", "D:1", "R:4"]);
+        let ranges: Vec<Range<i64>> = change_set.affected_ranges().collect();
+        assert_eq!(ranges, vec![0..3]);
+
+        // Nothing is affected by retains alone.
+        let change_set = create_change_set(&["R:10"]);
+        assert_eq!(change_set.affected_ranges().count(), 0);
+        assert_eq!(ChangeSet::new().affected_ranges().count(), 0);
+    }
+
+    #[test]
+    fn test_affected_ranges_cover_all_changed_text() {
+        let document = "Hello, world!";
+        let change_set = create_change_set(&["I:Oh, ", "D:1", "I:h", "R:4", "D:1", "R:7", "I:?"]);
+        let new_document: Vec<u16> = apply(document, &change_set)
+            .unwrap()
+            .encode_utf16()
+            .collect();
+        assert_eq!(String::from_utf16_lossy(&new_document), "Oh, hello world!?");
+        // Outside the affected ranges, the new document is made of retained text.
+        let ranges: Vec<Range<i64>> = change_set.affected_ranges().collect();
+        assert_eq!(ranges, vec![0..5, 9..9, 16..17]);
+        let unaffected: Vec<String> = vec![5..9, 9..16]
+            .into_iter()
+            .map(|r| String::from_utf16_lossy(&new_document[r.start..r.end]))
+            .collect();
+        assert_eq!(unaffected, vec!["ello", " world!"]);
+    }
+
+    #[test]
+    fn test_is_identity() {
+        // Structural identities.
+        assert!(is_identity(&ChangeSet::new(), None));
+        assert!(is_identity(&create_change_set(&["R:5"]), None));
+        assert!(is_identity(
+            &create_change_set(&["R:5"]),
+            Some(&utils::str_to_u16_vec("Hello"))
+        ));
+        assert!(!is_identity(
+            &create_change_set(&["R:5"]),
+            Some(&utils::str_to_u16_vec("Hi"))
+        ));
+
+        // Replacing text with the same text is only detected with the document.
+        let change_set = create_change_set(&["R:2", "D:2", "I:ll", "R:1"]);
+        assert!(!is_identity(&change_set, None));
+        assert!(is_identity(
+            &change_set,
+            Some(&utils::str_to_u16_vec("Hello"))
+        ));
+        assert!(!is_identity(
+            &change_set,
+            Some(&utils::str_to_u16_vec("Heyyo"))
+        ));
+        let change_set = create_change_set(&["I:x", "D:1"]);
+        assert!(is_identity(&change_set, Some(&utils::str_to_u16_vec("x"))));
+
+        // Changes that alter the document, or are malformed, are not identities.
+        let document = utils::str_to_u16_vec("Hello");
+        for ops in &[
+            &["R:5", "I:!"][..],
+            &["D:1", "R:4"][..],
+            &["D:1", "R:1", "I:H", "R:3"][..],
+        ] {
+            assert!(!is_identity(&create_change_set(ops), Some(&document)));
+        }
+        let mut change_set = create_change_set(&["R:5"]);
+        change_set.ops.push(ChangeOp { op: None });
+        assert!(!is_identity(&change_set, None));
+    }
+
+    #[test]
+    fn test_visit_ops() {
+        #[derive(Default)]
+        struct InsertCounter {
+            inserted: usize,
+        }
+        impl OpVisitor for InsertCounter {
+            fn visit_insert(&mut self, content: &[u32]) -> Result<(), OtError> {
+                self.inserted += content.len();
+                Ok(())
+            }
+        }
+        let change_set = create_change_set(&["R:3", "I:Hello", "D:2", "I:!"]);
+        let mut counter = InsertCounter::default();
+        visit_ops(&change_set, &mut counter).unwrap();
+        assert_eq!(counter.inserted, 6);
+
+        // Empty ops are an error.
+        let mut change_set = change_set;
+        change_set.ops.insert(1, ChangeOp { op: None });
+        match visit_ops(&change_set, &mut InsertCounter::default()) {
+            Err(OtError::EmptyOp { index: 1 }) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_for_each_op_stops_at_first_error() {
+        let change_set = create_change_set(&["R:3", "D:2", "I:x", "R:1"]);
+        let mut visited = 0;
+        let result = for_each_op(&change_set, |op| {
+            visited += 1;
+            match op {
+                Op::Delete(_) => Err(OtError::InvalidInput("no deletes".to_string())),
+                _ => Ok(()),
+            }
+        });
+        assert!(result.is_err());
+        assert_eq!(visited, 2);
+    }
+
+    #[test]
+    fn test_validate() {
+        let change_set = create_change_set(&["R:3", "I:foo", "D:2", "R:1"]);
+        assert_eq!(validate(&change_set).unwrap(), (6, 7));
+
+        let change_set = create_change_set(&["R:3", "D:-1"]);
+        assert!(matches!(
+            validate(&change_set),
+            Err(OtError::NegativeCount {
+                index: 1,
+                count: -1
+            })
+        ));
+
+        let change_set = create_change_set(&[format!("R:{}", i64::MAX).as_str(), "I:a"]);
+        assert!(matches!(
+            validate(&change_set),
+            Err(OtError::LengthOverflow)
+        ));
+
+        let change_set = create_change_set(&[format!("R:{}", i64::MAX).as_str(), "D:1"]);
+        assert!(matches!(
+            validate(&change_set),
+            Err(OtError::LengthOverflow)
+        ));
+
+        let mut change_set = create_change_set(&["R:1", "I:a"]);
+        change_set.ops.push(ChangeOp {
+            op: Some(insert_op(&[0x61, 0x11_0000])),
+        });
+        assert!(matches!(
+            validate(&change_set),
+            Err(OtError::InvalidInsertContent {
+                index: 2,
+                content_index: 1
+            })
+        ));
+
+        change_set.ops.push(ChangeOp { op: None });
+        assert!(matches!(
+            validate(&change_set),
+            Err(OtError::EmptyOp { index: 3 })
+        ));
+    }
+
+    #[test]
+    fn test_apply_rejects_negative_counts() {
+        // A negative delete would otherwise move the read position back past the start.
+        let change_set = create_change_set(&["R:3", "D:-2", "R:2"]);
+        assert!(matches!(
+            apply("abc", &change_set),
+            Err(OtError::NegativeCount {
+                index: 1,
+                count: -2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_diagnose_well_formed_change_set() {
+        let change_set = create_change_set(&["R:3", "I:foo", "D:2", "R:1"]);
+        assert!(diagnose(&change_set).is_empty());
+    }
+
+    #[test]
+    fn test_diagnose_malformed_change_set() {
+        let change_set = ChangeSet {
+            ops: vec![
+                ChangeOp {
+                    op: Some(retain_op(3)),
+                },
+                ChangeOp {
+                    op: Some(retain_op(2)),
+                },
+                ChangeOp { op: None },
+                ChangeOp {
+                    op: Some(delete_op(-1)),
+                },
+                ChangeOp {
+                    op: Some(insert_op(&[])),
+                },
+            ],
+        };
+        let diagnostics = diagnose(&change_set);
+        let expected_diagnostics = vec![
+            Diagnostic {
+                index: 1,
+                kind: DiagnosticKind::AdjacentOpsOfSameType,
+            },
+            Diagnostic {
+                index: 2,
+                kind: DiagnosticKind::EmptyOp,
+            },
+            Diagnostic {
+                index: 3,
+                kind: DiagnosticKind::NegativeCount,
+            },
+            Diagnostic {
+                index: 4,
+                kind: DiagnosticKind::ZeroLengthOp,
+            },
+            Diagnostic {
+                index: 4,
+                kind: DiagnosticKind::InsertAfterDelete,
+            },
+        ];
+        assert_eq!(diagnostics, expected_diagnostics);
+        let messages: Vec<String> = diagnostics.iter().map(Diagnostic::to_string).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "adjacent ops of same type at indices 0 and 1",
+                "empty op at index 2",
+                "negative count at index 3",
+                "zero-length op at index 4",
+                "delete+insert pair at indices 3 and 4 could be reordered",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let cases = vec![
+            (
+                OtError::EmptyOp { index: 3 },
+                "Invalid Input: Unexpected empty op at index 3",
+            ),
+            (
+                OtError::LengthMismatch {
+                    expected: 8,
+                    actual: 9,
+                },
+                "Invalid Input: The change set must be based on a document with length 8, but the \
+                document had length 9",
+            ),
+            (
+                OtError::IncompatibleChangeSets {
+                    operation: ChangeSetOperation::Transform,
+                    a_len: 5,
+                    b_len: 10,
+                },
+                "Invalid Input: Cannot transform A (input length 5) and B (input length 10)",
+            ),
+            (
+                OtError::IncompatibleChangeSets {
+                    operation: ChangeSetOperation::Compose,
+                    a_len: 5,
+                    b_len: 10,
+                },
+                "Invalid Input: Cannot compose change sets A (output length 5) and B (input length \
+                10)",
+            ),
+        ];
+        for (error, expected_message) in cases {
+            assert_eq!(error.to_string(), expected_message);
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let cases = [
+            ("", "", vec![]),
+            ("", "abc", vec!["I:abc"]),
+            ("abc", "", vec!["D:3"]),
+            (
+                "Hello world",
+                "Hello there world",
+                vec!["R:6", "I:there ", "R:5"],
+            ),
+            ("Hello world", "Hello", vec!["R:5", "D:6"]),
+            ("aaa", "aaaa", vec!["R:3", "I:a"]),
+            ("one two", "one 2", vec!["R:4", "D:3", "I:2"]),
+            // 😀 and 😁 share their high surrogate, which must not be split from the low one.
+            ("x😀", "x😁", vec!["R:1", "D:2", "I:😁"]),
+            ("😀x", "😁x", vec!["D:2", "I:😁", "R:1"]),
+        ];
+        for (old_document, new_document, expected_ops) in cases.iter() {
+            let change_set = diff(old_document, new_document);
+            assert_eq!(
+                change_set,
+                create_change_set(expected_ops),
+                "{:?} -> {:?}",
+                old_document,
+                new_document
+            );
+            assert_eq!(apply(old_document, &change_set).unwrap(), *new_document);
+        }
     }
 
     #[test]
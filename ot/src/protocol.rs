@@ -0,0 +1,21 @@
+//! Versioning for the protocol between clients and the backend.
+
+/// The version of the protocol that this build speaks.
+///
+/// New fields do not need a new version, since protobuf decoders skip fields they do not know.
+/// Bump it when older builds would misbehave instead, e.g. when change sets gain a kind of op that
+/// older clients cannot apply.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Clients send their `PROTOCOL_VERSION` in this header with every API request. Requests without
+/// it come from clients that predate the header, which speak version 1.
+pub const PROTOCOL_VERSION_HEADER: &str = "x-writing-protocol-version";
+
+/// The Content-Type of API request and response bodies, which are encoded protobuf messages. The
+/// server rejects API requests with any other Content-Type.
+pub const PROTOBUF_CONTENT_TYPE: &str = "application/protobuf";
+
+/// Clients send an id in this header that ties together the requests of one unit of work, like a
+/// sync round, so that a failure can be followed from the client's logs into the server's. The
+/// server echoes it in the response, and makes one up for requests that come without it.
+pub const TRACE_ID_HEADER: &str = "x-writing-trace-id";
@@ -0,0 +1,398 @@
+//! A naive reference implementation of `apply`, `compose`, and `transform`, for differential
+//! testing.
+//!
+//! The optimized functions walk two op lists at once and split ops as they go, which is easy to
+//! get subtly wrong. This implementation instead works on explicit lists of characters, where
+//! every character remembers whether it came from the original document or which change set
+//! inserted it. Composing and transforming then come down to applying change sets to character
+//! lists and reading off the differences, which is slow but hard to get wrong.
+//!
+//! Results are compared as `Edit`s, which describe what a change set does to each character of
+//! its input. Two change sets with the same normalized `Edit` produce the same document from
+//! every input, even if their ops differ (e.g. `[delete 1, insert "x"]` and
+//! `[insert "x", delete 1]`).
+
+use std::collections::HashMap;
+
+use crate::writing_proto::{change_op::Op, ChangeSet};
+use crate::OtError;
+
+/// What a change set does to a document of length `kept.len()`. `kept[i]` says whether the `i`th
+/// character is retained or deleted, and `inserts[i]` holds the characters inserted right before
+/// it. `inserts[kept.len()]` holds the characters inserted at the end.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Edit {
+    pub inserts: Vec<Vec<u32>>,
+    pub kept: Vec<bool>,
+}
+
+/// Who wrote a character.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Author {
+    Original,
+    A,
+    B,
+}
+
+/// A character, along with who wrote it and when, so that it can be told apart from equal
+/// characters.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Char {
+    pub author: Author,
+    pub index: usize,
+    pub value: u32,
+}
+
+impl Edit {
+    pub fn from_change_set(change_set: &ChangeSet) -> Result<Self, OtError> {
+        let (input_len, _) = change_set.lengths()?;
+        let input_len = input_len as usize;
+        let mut edit = Edit {
+            inserts: vec![Vec::new(); input_len + 1],
+            kept: vec![false; input_len],
+        };
+        let mut pos = 0;
+        for op in change_set.iter_ops() {
+            match op? {
+                Op::Insert(insert) => edit.inserts[pos].extend_from_slice(&insert.content),
+                Op::Retain(retain) => {
+                    for kept in edit.kept[pos..pos + retain.count as usize].iter_mut() {
+                        *kept = true;
+                    }
+                    pos += retain.count as usize;
+                }
+                Op::Delete(delete) => pos += delete.count as usize,
+            }
+        }
+        Ok(edit)
+    }
+
+    /// Moves characters inserted among deleted characters to before the first of them. Inserting
+    /// anywhere in a run of deleted characters has the same result, so two edits have the same
+    /// effect exactly when their normalized forms are equal.
+    pub fn normalize(&self) -> Self {
+        let mut normalized = Edit {
+            inserts: vec![Vec::new(); self.inserts.len()],
+            kept: self.kept.clone(),
+        };
+        let mut target = 0;
+        for (i, inserts) in self.inserts.iter().enumerate() {
+            if i == 0 || self.kept[i - 1] {
+                target = i;
+            }
+            normalized.inserts[target].extend_from_slice(inserts);
+        }
+        normalized
+    }
+
+    /// Applies the edit to `chars`, attributing inserted characters to `author`.
+    pub fn apply(&self, chars: &[Char], author: Author) -> Vec<Char> {
+        assert_eq!(chars.len(), self.kept.len());
+        let mut result = Vec::new();
+        let mut index = 0;
+        for (i, inserts) in self.inserts.iter().enumerate() {
+            for &value in inserts.iter() {
+                result.push(Char {
+                    author,
+                    index,
+                    value,
+                });
+                index += 1;
+            }
+            if i < chars.len() && self.kept[i] {
+                result.push(chars[i]);
+            }
+        }
+        result
+    }
+
+    /// The edit that turns `from` into `to`, where `to` holds some of the characters of `from` in
+    /// the same order, and new characters among them.
+    pub fn between(from: &[Char], to: &[Char]) -> Self {
+        let positions: HashMap<Char, usize> =
+            from.iter().enumerate().map(|(i, &c)| (c, i)).collect();
+        let mut edit = Edit {
+            inserts: vec![Vec::new(); from.len() + 1],
+            kept: vec![false; from.len()],
+        };
+        let mut gap = 0;
+        for c in to.iter() {
+            match positions.get(c) {
+                Some(&i) => {
+                    assert!(i >= gap, "characters were reordered");
+                    edit.kept[i] = true;
+                    gap = i + 1;
+                }
+                None => edit.inserts[gap].push(c.value),
+            }
+        }
+        edit
+    }
+}
+
+pub fn original_chars(document: &[u32]) -> Vec<Char> {
+    document
+        .iter()
+        .enumerate()
+        .map(|(index, &value)| Char {
+            author: Author::Original,
+            index,
+            value,
+        })
+        .collect()
+}
+
+pub fn values(chars: &[Char]) -> Vec<u32> {
+    chars.iter().map(|c| c.value).collect()
+}
+
+pub fn apply(document: &[u32], edit: &Edit) -> Vec<u32> {
+    values(&edit.apply(&original_chars(document), Author::A))
+}
+
+/// The edit that has the effect of `a` followed by `b`.
+pub fn compose(a: &Edit, b: &Edit) -> Edit {
+    // The values do not matter, only which characters survive.
+    let original = original_chars(&vec![0; a.kept.len()]);
+    let after_a = a.apply(&original, Author::A);
+    let after_b = b.apply(&after_a, Author::B);
+    Edit::between(&original, &after_b).normalize()
+}
+
+/// Returns `(a', b')` such that `a * b' == b * a'`. Characters deleted by either edit are deleted,
+/// and where both edits insert at the same place, the characters inserted by `a` come first.
+pub fn transform(a: &Edit, b: &Edit) -> (Edit, Edit) {
+    let original = original_chars(&vec![0; a.kept.len()]);
+    let after_a = a.apply(&original, Author::A);
+    let after_b = b.apply(&original, Author::B);
+
+    // Number inserted characters the same way `Edit::apply` does, so that they match the ones in
+    // `after_a` and `after_b`.
+    let mut merged = Vec::new();
+    let mut a_index = 0;
+    let mut b_index = 0;
+    let push_inserts = |merged: &mut Vec<Char>, author, inserts: &[u32], index: &mut usize| {
+        for &value in inserts.iter() {
+            merged.push(Char {
+                author,
+                index: *index,
+                value,
+            });
+            *index += 1;
+        }
+    };
+    for (i, a_inserts) in a.inserts.iter().enumerate() {
+        push_inserts(&mut merged, Author::A, a_inserts, &mut a_index);
+        push_inserts(&mut merged, Author::B, &b.inserts[i], &mut b_index);
+        if i < original.len() && a.kept[i] && b.kept[i] {
+            merged.push(original[i]);
+        }
+    }
+    (
+        Edit::between(&after_b, &merged).normalize(),
+        Edit::between(&after_a, &merged).normalize(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils;
+    use crate::writing_proto::{ChangeOp, Delete, Insert, Retain};
+
+    const ITERATIONS: usize = 5000;
+    const MAX_DOCUMENT_LEN: u64 = 16;
+
+    /// A xorshift generator, so that failures reproduce from the seed alone.
+    struct Rng(u64);
+
+    impl Rng {
+        fn new(seed: u64) -> Self {
+            // The state must not be zero.
+            Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        /// A number in `0..n`.
+        fn below(&mut self, n: u64) -> u64 {
+            self.next_u64() % n
+        }
+    }
+
+    fn random_content(rng: &mut Rng, max_len: u64) -> Vec<u32> {
+        // A small alphabet, so that equal characters from different authors are common.
+        (0..rng.below(max_len + 1))
+            .map(|_| 'a' as u32 + rng.below(4) as u32)
+            .collect()
+    }
+
+    /// A random change set for a document of length `input_len`. Ops are pushed as they are, so
+    /// the change set may have adjacent ops of the same type, like the ones clients send.
+    fn random_change_set(rng: &mut Rng, input_len: i64) -> ChangeSet {
+        let mut change_set = ChangeSet::new();
+        let mut remaining = input_len;
+        loop {
+            let op = match rng.below(4) {
+                0 => Op::Insert(Insert {
+                    content: random_content(rng, 3),
+                }),
+                1 if remaining > 0 => {
+                    let count = 1 + rng.below(remaining as u64) as i64;
+                    remaining -= count;
+                    Op::Retain(Retain { count })
+                }
+                2 if remaining > 0 => {
+                    let count = 1 + rng.below(remaining as u64) as i64;
+                    remaining -= count;
+                    Op::Delete(Delete { count })
+                }
+                _ if remaining == 0 => break,
+                _ => continue,
+            };
+            if let Op::Insert(insert) = &op {
+                if insert.content.is_empty() {
+                    continue;
+                }
+            }
+            change_set.ops.push(ChangeOp { op: Some(op) });
+        }
+        change_set
+    }
+
+    /// Applies a change set with the optimized implementation.
+    fn apply_optimized(document: &[u32], change_set: &ChangeSet) -> Vec<u32> {
+        let document_u16 = utils::u32_slice_to_u16_vec(document).unwrap();
+        utils::u16_slice_to_u32_vec(&crate::apply_slice(&document_u16, change_set).unwrap())
+    }
+
+    fn fmt(change_set: &ChangeSet) -> String {
+        change_set.fmt_compact(10).to_string()
+    }
+
+    #[test]
+    fn test_reference_transform() {
+        // "abc": A inserts "x" after "a" and deletes "c". B inserts "y" after "a" and deletes "b".
+        let a = Edit {
+            inserts: vec![vec![], vec!['x' as u32], vec![], vec![]],
+            kept: vec![true, true, false],
+        };
+        let b = Edit {
+            inserts: vec![vec![], vec!['y' as u32], vec![], vec![]],
+            kept: vec![true, false, true],
+        };
+        let document = utils::str_to_u32_vec("abc");
+        let (a_prime, b_prime) = transform(&a, &b);
+        let expected = utils::str_to_u32_vec("axy");
+        assert_eq!(apply(&apply(&document, &a), &b_prime), expected);
+        assert_eq!(apply(&apply(&document, &b), &a_prime), expected);
+        assert_eq!(compose(&a, &b_prime), compose(&b, &a_prime));
+    }
+
+    #[test]
+    fn test_normalize() {
+        // Deleting "b" and inserting "x" in its place, in either order.
+        let insert_first = Edit {
+            inserts: vec![vec![], vec!['x' as u32], vec![], vec![]],
+            kept: vec![true, false, true],
+        };
+        let delete_first = Edit {
+            inserts: vec![vec![], vec![], vec!['x' as u32], vec![]],
+            kept: vec![true, false, true],
+        };
+        assert_ne!(insert_first, delete_first);
+        assert_eq!(insert_first.normalize(), delete_first.normalize());
+        assert_eq!(insert_first.normalize(), insert_first);
+    }
+
+    #[test]
+    fn test_apply_matches_reference() {
+        let mut rng = Rng::new(1);
+        for iteration in 0..ITERATIONS {
+            let document = random_content(&mut rng, MAX_DOCUMENT_LEN);
+            let change_set = random_change_set(&mut rng, document.len() as i64);
+            assert_eq!(
+                apply_optimized(&document, &change_set),
+                apply(&document, &Edit::from_change_set(&change_set).unwrap()),
+                "iteration {}, change set {}",
+                iteration,
+                fmt(&change_set)
+            );
+        }
+    }
+
+    #[test]
+    fn test_compose_matches_reference() {
+        let mut rng = Rng::new(2);
+        for iteration in 0..ITERATIONS {
+            let document = random_content(&mut rng, MAX_DOCUMENT_LEN);
+            let a = random_change_set(&mut rng, document.len() as i64);
+            let b = random_change_set(&mut rng, a.output_len().unwrap());
+            let context = format!("iteration {}, a {}, b {}", iteration, fmt(&a), fmt(&b));
+
+            let a_edit = Edit::from_change_set(&a).unwrap();
+            let b_edit = Edit::from_change_set(&b).unwrap();
+
+            let composed = crate::compose(&a, &b).unwrap();
+            assert_eq!(
+                Edit::from_change_set(&composed).unwrap().normalize(),
+                compose(&a_edit, &b_edit),
+                "{}, composed {}",
+                context,
+                fmt(&composed)
+            );
+            assert_eq!(
+                apply_optimized(&document, &composed),
+                apply(&apply(&document, &a_edit), &b_edit),
+                "{}",
+                context
+            );
+        }
+    }
+
+    #[test]
+    fn test_transform_matches_reference() {
+        let mut rng = Rng::new(3);
+        for iteration in 0..ITERATIONS {
+            let document = random_content(&mut rng, MAX_DOCUMENT_LEN);
+            let a = random_change_set(&mut rng, document.len() as i64);
+            let b = random_change_set(&mut rng, document.len() as i64);
+            let context = format!("iteration {}, a {}, b {}", iteration, fmt(&a), fmt(&b));
+
+            let (a_prime, b_prime) = crate::transform(&a, &b).unwrap();
+            let (expected_a_prime, expected_b_prime) = transform(
+                &Edit::from_change_set(&a).unwrap(),
+                &Edit::from_change_set(&b).unwrap(),
+            );
+            assert_eq!(
+                Edit::from_change_set(&a_prime).unwrap().normalize(),
+                expected_a_prime,
+                "{}, a' {}",
+                context,
+                fmt(&a_prime)
+            );
+            assert_eq!(
+                Edit::from_change_set(&b_prime).unwrap().normalize(),
+                expected_b_prime,
+                "{}, b' {}",
+                context,
+                fmt(&b_prime)
+            );
+
+            // Both orders converge on the same document.
+            let a_then_b_prime = crate::compose(&a, &b_prime).unwrap();
+            let b_then_a_prime = crate::compose(&b, &a_prime).unwrap();
+            assert_eq!(
+                Edit::from_change_set(&a_then_b_prime).unwrap().normalize(),
+                Edit::from_change_set(&b_then_a_prime).unwrap().normalize(),
+                "{}",
+                context
+            );
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! Change sets applied to ropes, for the optional `ropey` feature.
+//!
+//! `apply_slice` copies the whole document for every change set, which adds up when replaying
+//! thousands of revisions to build a snapshot. A `ropey::Rope` is edited in place in O(log n)
+//! instead. Ropes are indexed by `char` and change sets by UTF-16 code unit, so each op's position
+//! is converted with the rope's own index, also in O(log n).
+//!
+//! A rope only holds valid text, so ops may not start or end inside a surrogate pair, and inserts
+//! may not contain lone surrogates. Change sets made by browsers never do.
+
+use ropey::Rope;
+
+use crate::writing_proto::{change_op::Op, ChangeSet};
+use crate::{for_each_op, get_input_output_doc_lengths, utils, validate, OtError};
+
+/// Applies the change set to the rope in place. Like `apply_slice`, but O(log n) per op rather
+/// than O(n) per change set.
+///
+/// # Error
+///
+/// - Returns `OtError::LengthMismatch` when the rope's UTF-16 length is not the change set's input
+///   length.
+///
+/// - Returns `OtError::InvalidUtf16` when an insert contains a lone surrogate.
+///
+/// - Returns `OtError::InvalidInput` when an op starts or ends inside a surrogate pair.
+///
+/// On error, the rope is left as it was.
+pub fn apply_to_rope(rope: &mut Rope, change_set: &ChangeSet) -> Result<(), OtError> {
+    let (input_len, output_len) = validate(change_set)?;
+    check_rope_len(rope, input_len)?;
+    // Cloning a rope only copies a pointer, so keeping the original to restore on error is cheap.
+    let original = rope.clone();
+    let result = apply_ops(rope, change_set);
+    if result.is_err() {
+        *rope = original;
+        return result;
+    }
+    let new_doc_len = rope.len_utf16_cu();
+    if output_len as usize != new_doc_len {
+        *rope = original;
+        return Err(OtError::PostConditionFailed(format!(
+            "After applying changes, the document should have length {}, but it had length {}",
+            output_len, new_doc_len,
+        )));
+    }
+    Ok(())
+}
+
+fn apply_ops(rope: &mut Rope, change_set: &ChangeSet) -> Result<(), OtError> {
+    // The position in the rope as edited so far, in UTF-16 code units.
+    let mut i = 0;
+    for_each_op(change_set, |op| {
+        match op {
+            Op::Insert(insert) => {
+                let content = utils::u32_slice_to_string(&insert.content)?;
+                rope.insert(utf16_to_char_idx(rope, i)?, &content);
+                i += insert.content.len();
+            }
+            Op::Delete(delete) => {
+                let start = utf16_to_char_idx(rope, i)?;
+                let end = utf16_to_char_idx(rope, i + delete.count as usize)?;
+                rope.remove(start..end);
+            }
+            Op::Retain(retain) => {
+                i += retain.count as usize;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Inverts the change set, reading the text that it deletes from the rope, which is the document
+/// before the change set. Like `invert_slice`.
+///
+/// # Error
+///
+/// - Returns `OtError::LengthMismatch` when the rope's UTF-16 length is not the change set's input
+///   length.
+///
+/// - Returns `OtError::InvalidInput` when a delete starts or ends inside a surrogate pair.
+pub fn invert_rope(rope: &Rope, change_set: &ChangeSet) -> Result<ChangeSet, OtError> {
+    let (input_len, _output_len) = get_input_output_doc_lengths(change_set)?;
+    check_rope_len(rope, input_len)?;
+    let mut inverted_change_set = ChangeSet::with_capacity(change_set.ops.len());
+    let mut index: usize = 0;
+    for_each_op(change_set, |op| {
+        match op {
+            Op::Insert(insert) => {
+                inverted_change_set.delete(insert.content.len() as i64);
+            }
+            Op::Delete(delete) => {
+                let delete_count = delete.count as usize;
+                let start = utf16_to_char_idx(rope, index)?;
+                let end = utf16_to_char_idx(rope, index + delete_count)?;
+                let mut content = Vec::with_capacity(delete_count);
+                for chunk in rope.slice(start..end).chunks() {
+                    content.extend(chunk.encode_utf16().map(u32::from));
+                }
+                inverted_change_set.insert_vec(content);
+                index += delete_count;
+            }
+            Op::Retain(retain) => {
+                inverted_change_set.retain(retain.count);
+                index += retain.count as usize;
+            }
+        }
+        Ok(())
+    })?;
+    Ok(inverted_change_set)
+}
+
+fn check_rope_len(rope: &Rope, input_len: i64) -> Result<(), OtError> {
+    let doc_len = rope.len_utf16_cu();
+    if input_len as usize != doc_len {
+        return Err(OtError::LengthMismatch {
+            expected: input_len,
+            actual: doc_len as i64,
+        });
+    }
+    Ok(())
+}
+
+/// Converts a UTF-16 position to the index of the `char` that starts there.
+fn utf16_to_char_idx(rope: &Rope, utf16_idx: usize) -> Result<usize, OtError> {
+    // Positions inside a surrogate pair are rounded down to the pair's char.
+    let char_idx = rope.utf16_cu_to_char(utf16_idx);
+    if rope.char_to_utf16_cu(char_idx) != utf16_idx {
+        return Err(OtError::InvalidInput(format!(
+            "Position {} is inside a surrogate pair",
+            utf16_idx
+        )));
+    }
+    Ok(char_idx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::utils::str_to_u16_vec;
+
+    #[test]
+    fn test_apply_to_rope() {
+        let documents = [
+            ("", "Hello"),
+            ("Hello, world!", "Hello there, world"),
+            ("😀 smile\nfrown 🙁", "frown 🙁\n😀 smile 😀"),
+            ("Introduction\n\nWe write.", "Introduction"),
+        ];
+        for (before, after) in documents.iter() {
+            let change_set = crate::diff(before, after);
+            let mut rope = Rope::from_str(before);
+            apply_to_rope(&mut rope, &change_set).unwrap();
+            assert_eq!(rope.to_string(), *after);
+            assert_eq!(
+                str_to_u16_vec(&rope.to_string()),
+                crate::apply_slice(&str_to_u16_vec(before), &change_set).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_apply_to_rope_many_revisions() {
+        let mut rope = Rope::new();
+        let mut document = String::new();
+        for i in 0..1000 {
+            let mut change_set = ChangeSet::new();
+            let len = rope.len_utf16_cu() as i64;
+            if i % 3 == 2 {
+                // Delete the middle character.
+                change_set.retain(len / 2);
+                change_set.delete(1);
+                change_set.retain(len - len / 2 - 1);
+            } else {
+                change_set.retain(len / 2);
+                change_set.insert(if i % 2 == 0 { "ab" } else { "é" });
+                change_set.retain(len - len / 2);
+            }
+            apply_to_rope(&mut rope, &change_set).unwrap();
+            document = crate::apply(&document, &change_set).unwrap();
+        }
+        assert_eq!(rope.to_string(), document);
+    }
+
+    #[test]
+    fn test_apply_to_rope_invalid() {
+        // The change set is for a longer document.
+        let mut rope = Rope::from_str("abc");
+        let mut change_set = ChangeSet::new();
+        change_set.retain(4);
+        assert!(matches!(
+            apply_to_rope(&mut rope, &change_set),
+            Err(OtError::LengthMismatch {
+                expected: 4,
+                actual: 3
+            })
+        ));
+
+        // Ops may not split a surrogate pair. The rope is left as it was, even though the insert
+        // before the delete succeeded.
+        let mut rope = Rope::from_str("a😀");
+        let mut change_set = ChangeSet::new();
+        change_set.insert("x");
+        change_set.retain(2);
+        change_set.delete(1);
+        assert!(matches!(
+            apply_to_rope(&mut rope, &change_set),
+            Err(OtError::InvalidInput(_))
+        ));
+        assert_eq!(rope.to_string(), "a😀");
+
+        // Inserts may not contain lone surrogates.
+        let mut change_set = ChangeSet::new();
+        change_set.insert_vec(vec![0xD83D]);
+        change_set.retain(3);
+        assert!(matches!(
+            apply_to_rope(&mut rope, &change_set),
+            Err(OtError::InvalidUtf16 { index: 0 })
+        ));
+    }
+
+    #[test]
+    fn test_invert_rope() {
+        let before = "😀 smile\nfrown 🙁";
+        let change_set = crate::diff(before, "😀\nfrown");
+        let rope = Rope::from_str(before);
+        let inverted = invert_rope(&rope, &change_set).unwrap();
+        assert_eq!(
+            inverted,
+            crate::invert_slice(&str_to_u16_vec(before), &change_set).unwrap()
+        );
+
+        let mut rope = rope;
+        apply_to_rope(&mut rope, &change_set).unwrap();
+        apply_to_rope(&mut rope, &inverted).unwrap();
+        assert_eq!(rope.to_string(), before);
+    }
+}
@@ -0,0 +1,338 @@
+//! Plain-text descriptions of change sets, like "inserted 42 characters near 'Introduction'", for
+//! people reading about an edit rather than applying it.
+
+use std::fmt;
+use std::ops::Range;
+
+use crate::writing_proto::{change_op::Op, ChangeSet};
+use crate::{for_each_op, utils, validate, OtError};
+
+/// At most this many edits are described one by one. The rest are only counted.
+const MAX_DESCRIBED_EDITS: usize = 3;
+
+/// Context snippets longer than this many characters are cut at a word boundary.
+const MAX_CONTEXT_CHARS: usize = 30;
+
+const NEWLINE: u16 = b'\n' as u16;
+
+/// What a change set does to a document, edit by edit. Its `Display` is a sentence fragment like
+/// "inserted 42 characters near 'Introduction'; deleted one paragraph near 'Summary'".
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChangeSummary {
+    /// The changed places in the document, in order.
+    pub edits: Vec<EditSummary>,
+}
+
+/// One changed place: a run of deletes and inserts between retains.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EditSummary {
+    pub deleted: TextAmount,
+    pub inserted: TextAmount,
+    /// The start of a paragraph of the document before, around the edit, to say where it was.
+    /// Empty if the document had no text besides what the edit deleted.
+    pub context: String,
+}
+
+/// How much text an edit deleted or inserted. Text that makes up whole paragraphs is counted in
+/// paragraphs, and anything else in characters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextAmount {
+    Nothing,
+    Characters(usize),
+    Paragraphs(usize),
+}
+
+impl ChangeSummary {
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+}
+
+impl fmt::Display for ChangeSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.edits.is_empty() {
+            return write!(f, "no changes");
+        }
+        for (i, edit) in self.edits.iter().take(MAX_DESCRIBED_EDITS).enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", edit)?;
+        }
+        match self.edits.len().saturating_sub(MAX_DESCRIBED_EDITS) {
+            0 => Ok(()),
+            1 => write!(f, "; and one more edit"),
+            more => write!(f, "; and {} more edits", more),
+        }
+    }
+}
+
+impl fmt::Display for EditSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.deleted, self.inserted) {
+            (TextAmount::Nothing, inserted) => write!(f, "inserted {}", inserted)?,
+            (deleted, TextAmount::Nothing) => write!(f, "deleted {}", deleted)?,
+            (deleted, inserted) => write!(f, "replaced {} with {}", deleted, inserted)?,
+        }
+        if !self.context.is_empty() {
+            write!(f, " near '{}'", self.context)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for TextAmount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (count, noun) = match *self {
+            TextAmount::Nothing => return write!(f, "nothing"),
+            TextAmount::Characters(count) => (count, "character"),
+            TextAmount::Paragraphs(count) => (count, "paragraph"),
+        };
+        if count == 1 {
+            write!(f, "one {}", noun)
+        } else {
+            write!(f, "{} {}s", count, noun)
+        }
+    }
+}
+
+/// Describes what the change set does to `document_before`, the document it applies to.
+///
+/// Characters are counted as people count them, so a character outside the Basic Multilingual
+/// Plane counts once, even though it is two UTF-16 code units. Each edit's context is the start of
+/// its paragraph, or of the nearest paragraph before it if the edit left nothing of its own, or
+/// after it if there is none before.
+///
+/// Returns an error if the change set is invalid, or does not apply to the document.
+pub fn summarize(
+    change_set: &ChangeSet,
+    document_before: &[u16],
+) -> Result<ChangeSummary, OtError> {
+    let (input_len, _) = validate(change_set)?;
+    if input_len as usize != document_before.len() {
+        return Err(OtError::LengthMismatch {
+            expected: input_len,
+            actual: document_before.len() as i64,
+        });
+    }
+    // Each edit as the range of the document before that it deletes, and the text it inserts.
+    let mut edits: Vec<(Range<usize>, Vec<u16>)> = Vec::new();
+    let mut in_edit = false;
+    let mut i = 0;
+    for_each_op(change_set, |op| {
+        let len = match op {
+            Op::Retain(retain) => {
+                i += retain.count as usize;
+                in_edit = false;
+                return Ok(());
+            }
+            Op::Delete(delete) => delete.count as usize,
+            Op::Insert(_) => 0,
+        };
+        if !in_edit {
+            edits.push((i..i, Vec::new()));
+            in_edit = true;
+        }
+        let (deleted, inserted) = edits.last_mut().unwrap();
+        match op {
+            Op::Insert(insert) => inserted.extend(utils::u32_slice_to_u16_vec(&insert.content)?),
+            _ => deleted.end += len,
+        }
+        i += len;
+        Ok(())
+    })?;
+    let edits = edits
+        .into_iter()
+        .filter(|(deleted, inserted)| !deleted.is_empty() || !inserted.is_empty())
+        .map(|(deleted, inserted)| {
+            let at_paragraph_start =
+                deleted.start == 0 || document_before[deleted.start - 1] == NEWLINE;
+            let at_paragraph_end =
+                deleted.end == document_before.len() || document_before[deleted.end] == NEWLINE;
+            EditSummary {
+                deleted: measure(
+                    &document_before[deleted.clone()],
+                    at_paragraph_start,
+                    at_paragraph_end,
+                ),
+                inserted: measure(&inserted, at_paragraph_start, at_paragraph_end),
+                context: find_context(document_before, &deleted),
+            }
+        })
+        .collect();
+    Ok(ChangeSummary { edits })
+}
+
+/// Measures text deleted from or inserted between the given boundaries. It makes up whole
+/// paragraphs if, together with the boundaries, it starts and ends at line breaks.
+fn measure(text: &[u16], at_paragraph_start: bool, at_paragraph_end: bool) -> TextAmount {
+    if text.is_empty() {
+        return TextAmount::Nothing;
+    }
+    let paragraphs = if at_paragraph_start && text.ends_with(&[NEWLINE]) {
+        Some(&text[..text.len() - 1])
+    } else if at_paragraph_end && text.starts_with(&[NEWLINE]) {
+        Some(&text[1..])
+    } else if at_paragraph_start && at_paragraph_end {
+        Some(text)
+    } else {
+        None
+    };
+    let paragraph_count = paragraphs.map_or(0, |paragraphs| {
+        paragraphs
+            .split(|c| *c == NEWLINE)
+            .filter(|paragraph| !is_blank(paragraph))
+            .count()
+    });
+    if paragraph_count > 0 {
+        TextAmount::Paragraphs(paragraph_count)
+    } else {
+        TextAmount::Characters(String::from_utf16_lossy(text).chars().count())
+    }
+}
+
+fn find_context(document: &[u16], deleted: &Range<usize>) -> String {
+    let paragraph_start = document[..deleted.start]
+        .iter()
+        .rposition(|c| *c == NEWLINE)
+        .map_or(0, |i| i + 1);
+    let paragraph_end = document[deleted.end..]
+        .iter()
+        .position(|c| *c == NEWLINE)
+        .map_or(document.len(), |i| deleted.end + i);
+    let mut unchanged = document[paragraph_start..deleted.start].to_vec();
+    unchanged.extend_from_slice(&document[deleted.end..paragraph_end]);
+    if !is_blank(&unchanged) {
+        return snippet(&document[paragraph_start..paragraph_end]);
+    }
+    let before = document[..paragraph_start.saturating_sub(1)]
+        .split(|c| *c == NEWLINE)
+        .rev()
+        .find(|paragraph| !is_blank(paragraph));
+    let after = || {
+        document
+            .get(paragraph_end + 1..)
+            .unwrap_or_default()
+            .split(|c| *c == NEWLINE)
+            .find(|paragraph| !is_blank(paragraph))
+    };
+    before.or_else(after).map(snippet).unwrap_or_default()
+}
+
+fn snippet(text: &[u16]) -> String {
+    let text = String::from_utf16_lossy(text);
+    let text = text.trim();
+    if text.chars().count() <= MAX_CONTEXT_CHARS {
+        return text.to_string();
+    }
+    let cut: String = text.chars().take(MAX_CONTEXT_CHARS).collect();
+    let cut = match cut.rfind(char::is_whitespace) {
+        Some(i) if i > 0 => &cut[..i],
+        _ => &cut[..],
+    };
+    format!("{}...", cut.trim_end())
+}
+
+fn is_blank(text: &[u16]) -> bool {
+    String::from_utf16_lossy(text).trim().is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::utils::str_to_u16_vec;
+
+    fn summarize_str(document_before: &str, document_after: &str) -> String {
+        let change_set = crate::diff(document_before, document_after);
+        summarize(&change_set, &str_to_u16_vec(document_before))
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_summarize() {
+        let document = "Introduction\nWe write.\n\nSummary\nWe wrote.";
+        assert_eq!(
+            summarize_str(
+                document,
+                "Introduction\nWe write a lot.\n\nSummary\nWe wrote."
+            ),
+            "inserted 6 characters near 'We write.'"
+        );
+        assert_eq!(
+            summarize_str(document, "Introduction\n\nSummary\nWe wrote."),
+            "deleted one paragraph near 'Introduction'"
+        );
+        assert_eq!(
+            summarize_str(
+                document,
+                "Introduction\nWe write.\n\nSummary\nWe wrote.\nThe end.\nBye."
+            ),
+            "inserted 2 paragraphs near 'We wrote.'"
+        );
+        assert_eq!(
+            summarize_str(document, "Introduction\nWe read.\n\nSummary\nWe wrote."),
+            "replaced 5 characters with 4 characters near 'We write.'"
+        );
+        assert_eq!(summarize_str(document, document), "no changes");
+
+        // Characters are counted once, even outside the Basic Multilingual Plane.
+        assert_eq!(
+            summarize_str("Hi", "Hi😀!"),
+            "inserted 2 characters near 'Hi'"
+        );
+    }
+
+    #[test]
+    fn test_summarize_context() {
+        // An edit that leaves its paragraph blank is placed by the paragraph before it, or after
+        // it if there is none before.
+        assert_eq!(
+            summarize_str("Title\n\nOld text\n", "Title\n\n\n"),
+            "deleted one paragraph near 'Title'"
+        );
+        assert_eq!(
+            summarize_str("Old text\n\nNext", "\n\nNext"),
+            "deleted one paragraph near 'Next'"
+        );
+
+        // Long paragraphs are cut at a word boundary.
+        assert_eq!(
+            summarize_str(
+                "The quick brown fox jumps over the lazy dog",
+                "The quick brown fox jumps over the lazy dog!"
+            ),
+            "inserted one character near 'The quick brown fox jumps...'"
+        );
+    }
+
+    #[test]
+    fn test_summarize_many_edits() {
+        let mut change_set = ChangeSet::new();
+        for _ in 0..5 {
+            change_set.insert("x");
+            change_set.retain(1);
+        }
+        let summary = summarize(&change_set, &str_to_u16_vec("abcde")).unwrap();
+        assert_eq!(summary.edits.len(), 5);
+        assert_eq!(
+            summary.to_string(),
+            "inserted one character near 'abcde'; inserted one character near 'abcde'; \
+            inserted one character near 'abcde'; and 2 more edits"
+        );
+    }
+
+    #[test]
+    fn test_summarize_invalid() {
+        let mut change_set = ChangeSet::new();
+        change_set.retain(3);
+        assert!(matches!(
+            summarize(&change_set, &str_to_u16_vec("ab")),
+            Err(OtError::LengthMismatch {
+                expected: 3,
+                actual: 2
+            })
+        ));
+    }
+}
@@ -0,0 +1,109 @@
+//! Conversions between Rust strings and the UTF-16 code units that change sets operate on.
+//!
+//! Change sets store inserted content as `u32`s, because Protobufs have no `u16` type, but every
+//! value must be a UTF-16 code unit. The checked conversions here return
+//! `OtError::InvalidUtf16` instead of silently truncating values or replacing lone surrogates.
+
+use std::convert::TryFrom;
+
+use crate::OtError;
+
+/// Encodes the string as UTF-16 code units.
+pub fn str_to_u16_vec(s: &str) -> Vec<u16> {
+    s.encode_utf16().collect()
+}
+
+/// Encodes the string as UTF-16 code units, widened to `u32` for use as `Insert` content.
+pub fn str_to_u32_vec(s: &str) -> Vec<u32> {
+    s.encode_utf16().map(u32::from).collect()
+}
+
+/// Widens UTF-16 code units to `u32` for use as `Insert` content.
+pub fn u16_slice_to_u32_vec(content: &[u16]) -> Vec<u32> {
+    content.iter().map(|&ch| u32::from(ch)).collect()
+}
+
+/// Narrows `Insert` content back to UTF-16 code units.
+///
+/// Returns `OtError::InvalidUtf16` if a value does not fit in a `u16`.
+pub fn u32_slice_to_u16_vec(content: &[u32]) -> Result<Vec<u16>, OtError> {
+    content
+        .iter()
+        .enumerate()
+        .map(|(index, &ch)| u16::try_from(ch).map_err(|_| OtError::InvalidUtf16 { index }))
+        .collect()
+}
+
+/// Decodes UTF-16 code units into a string.
+///
+/// Returns `OtError::InvalidUtf16` with the index of the first lone surrogate, if there is one.
+pub fn utf16_to_string(content: &[u16]) -> Result<String, OtError> {
+    let mut ret = String::with_capacity(content.len());
+    let mut index = 0;
+    for ch in std::char::decode_utf16(content.iter().copied()) {
+        let ch = ch.map_err(|_| OtError::InvalidUtf16 { index })?;
+        ret.push(ch);
+        index += ch.len_utf16();
+    }
+    Ok(ret)
+}
+
+/// Decodes `Insert` content into a string.
+///
+/// Returns `OtError::InvalidUtf16` if a value does not fit in a `u16`, or if there is a lone
+/// surrogate.
+pub fn u32_slice_to_string(content: &[u32]) -> Result<String, OtError> {
+    utf16_to_string(&u32_slice_to_u16_vec(content)?)
+}
+
+/// Decodes `Insert` content into a string, replacing values that are not valid UTF-16 with
+/// U+FFFD. Meant for logs and debug output, where showing something beats failing.
+pub fn u32_slice_to_string_lossy(content: &[u32]) -> String {
+    std::char::decode_utf16(
+        content
+            .iter()
+            .map(|&ch| u16::try_from(ch).unwrap_or(0xFFFD)),
+    )
+    .map(|ch| ch.unwrap_or(std::char::REPLACEMENT_CHARACTER))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let s = "héllo 😀";
+        assert_eq!(str_to_u16_vec(s).len(), 8);
+        assert_eq!(utf16_to_string(&str_to_u16_vec(s)).unwrap(), s);
+        assert_eq!(u32_slice_to_string(&str_to_u32_vec(s)).unwrap(), s);
+        assert_eq!(u16_slice_to_u32_vec(&str_to_u16_vec(s)), str_to_u32_vec(s));
+    }
+
+    #[test]
+    fn test_invalid_utf16() {
+        // A high surrogate without its low surrogate.
+        let content = [u16::from(b'a'), 0xD83D, u16::from(b'b')];
+        assert!(matches!(
+            utf16_to_string(&content),
+            Err(OtError::InvalidUtf16 { index: 1 })
+        ));
+        // The index counts code units, not characters.
+        let mut content = str_to_u16_vec("😀");
+        content.push(0xDE00);
+        assert!(matches!(
+            utf16_to_string(&content),
+            Err(OtError::InvalidUtf16 { index: 2 })
+        ));
+        // Values that do not fit in a u16.
+        assert!(matches!(
+            u32_slice_to_string(&[97, 0x1F600]),
+            Err(OtError::InvalidUtf16 { index: 1 })
+        ));
+        assert_eq!(
+            u32_slice_to_string_lossy(&[97, 0x1F600, 0xD83D]),
+            "a\u{FFFD}\u{FFFD}"
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! Node.js bindings for the `ot` crate, for tooling that runs OT on the server instead of in the
+//! browser.
+//!
+//! Change sets are passed as `Buffer`s holding `ChangeSet` protobuf messages, the same bytes that
+//! the backend stores and sends, and documents as strings. The batch functions run on the libuv
+//! thread pool and return promises, so that composing a long revision log does not block the event
+//! loop.
+
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+
+use ot::ffi::{decode_change_set, encode_change_set};
+use ot::writing_proto::ChangeSet;
+use ot::OtError;
+
+fn to_napi_error(error: OtError) -> Error {
+    Error::new(Status::InvalidArg, error.to_string())
+}
+
+fn decode(change_set: &[u8]) -> Result<ChangeSet> {
+    decode_change_set(change_set).map_err(to_napi_error)
+}
+
+fn decode_all(change_sets: &[Vec<u8>]) -> Result<Vec<ChangeSet>> {
+    change_sets
+        .iter()
+        .map(|change_set| decode(change_set))
+        .collect()
+}
+
+#[napi(object)]
+pub struct TransformResult {
+    pub a_prime: Buffer,
+    pub b_prime: Buffer,
+}
+
+#[napi(object)]
+pub struct TransformSequenceResult {
+    /// The local change sets, transformed to apply after the remote change set.
+    pub change_sets: Vec<Buffer>,
+    /// The remote change set, transformed to apply after the local change sets.
+    pub remote: Buffer,
+}
+
+/// Applies the change set to the document, returning the new document.
+#[napi]
+pub fn apply(document: String, change_set: Buffer) -> Result<String> {
+    ot::apply(&document, &decode(&change_set)?).map_err(to_napi_error)
+}
+
+/// Returns a change set with the effect of `a` followed by `b`.
+#[napi]
+pub fn compose(a: Buffer, b: Buffer) -> Result<Buffer> {
+    ot::compose(&decode(&a)?, &decode(&b)?)
+        .map(|composed| encode_change_set(&composed).into())
+        .map_err(to_napi_error)
+}
+
+/// Transforms concurrent change sets `a` and `b` into `a'` and `b'`, such that `a` followed by `b'`
+/// has the same result as `b` followed by `a'`.
+#[napi]
+pub fn transform(a: Buffer, b: Buffer) -> Result<TransformResult> {
+    let (a_prime, b_prime) = ot::transform(&decode(&a)?, &decode(&b)?).map_err(to_napi_error)?;
+    Ok(TransformResult {
+        a_prime: encode_change_set(&a_prime).into(),
+        b_prime: encode_change_set(&b_prime).into(),
+    })
+}
+
+/// Returns the change set that undoes `change_set`, which applies to `document`.
+#[napi]
+pub fn invert(document: String, change_set: Buffer) -> Result<Buffer> {
+    ot::invert(&document, &decode(&change_set)?)
+        .map(|inverted| encode_change_set(&inverted).into())
+        .map_err(to_napi_error)
+}
+
+/// Returns a change set that turns `old_document` into `new_document`.
+#[napi]
+pub fn diff(old_document: String, new_document: String) -> Buffer {
+    encode_change_set(&ot::diff(&old_document, &new_document)).into()
+}
+
+pub struct ComposeMany {
+    change_sets: Vec<Vec<u8>>,
+}
+
+impl Task for ComposeMany {
+    type Output = Vec<u8>;
+    type JsValue = Buffer;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        let change_sets = decode_all(&self.change_sets)?;
+        let composed = ot::compose_iter(change_sets.iter()).map_err(to_napi_error)?;
+        Ok(encode_change_set(&composed))
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(output.into())
+    }
+}
+
+/// Composes a list of change sets, e.g. a revision log, into one change set, off the main thread.
+#[napi]
+pub fn compose_many(change_sets: Vec<Buffer>) -> AsyncTask<ComposeMany> {
+    AsyncTask::new(ComposeMany {
+        change_sets: change_sets.into_iter().map(Vec::from).collect(),
+    })
+}
+
+pub struct TransformSequence {
+    change_sets: Vec<Vec<u8>>,
+    remote: Vec<u8>,
+}
+
+impl Task for TransformSequence {
+    type Output = (Vec<Vec<u8>>, Vec<u8>);
+    type JsValue = TransformSequenceResult;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        let mut remote = decode(&self.remote)?;
+        let mut transformed = Vec::with_capacity(self.change_sets.len());
+        for change_set in decode_all(&self.change_sets)?.iter() {
+            let (change_set, transformed_remote) =
+                ot::transform(change_set, &remote).map_err(to_napi_error)?;
+            transformed.push(encode_change_set(&change_set));
+            remote = transformed_remote;
+        }
+        Ok((transformed, encode_change_set(&remote)))
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        let (change_sets, remote) = output;
+        Ok(TransformSequenceResult {
+            change_sets: change_sets.into_iter().map(Buffer::from).collect(),
+            remote: remote.into(),
+        })
+    }
+}
+
+/// Transforms a sequence of local change sets `L1, ..., LN` against a concurrent remote change set
+/// `R`, off the main thread, the way the editor rebases its pending changes. The results satisfy
+/// `R * L1' * ... * LN' == L1 * ... * LN * R'`.
+#[napi]
+pub fn transform_sequence(
+    change_sets: Vec<Buffer>,
+    remote: Buffer,
+) -> AsyncTask<TransformSequence> {
+    AsyncTask::new(TransformSequence {
+        change_sets: change_sets.into_iter().map(Vec::from).collect(),
+        remote: remote.into(),
+    })
+}
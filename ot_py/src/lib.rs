@@ -0,0 +1,206 @@
+//! Python bindings for the `ot` crate, so that revision logs can be replayed and analyzed offline
+//! with the same OT semantics as the editor and the backend.
+//!
+//! ```python
+//! import writing_ot
+//!
+//! change_set = writing_ot.ChangeSet.from_bytes(revision_bytes)
+//! document = change_set.apply(document)
+//! ```
+//!
+//! Errors from the `ot` crate are raised as `writing_ot.OtError`, a subclass of `ValueError`.
+
+use prost::Message;
+use pyo3::basic::CompareOp;
+use pyo3::create_exception;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use ot::writing_proto::{change_op::Op, ChangeSet as ProtoChangeSet};
+
+create_exception!(writing_ot, OtError, PyValueError);
+
+/// How many characters of each insert `repr` shows.
+const REPR_MAX_INSERT_CHARS: usize = 20;
+
+fn to_py_err(error: ot::OtError) -> PyErr {
+    OtError::new_err(error.to_string())
+}
+
+/// A change set. Build one with `retain`, `insert`, and `delete`, or decode one with
+/// `ChangeSet.from_bytes`.
+#[pyclass(name = "ChangeSet", module = "writing_ot")]
+#[derive(Clone, Default)]
+struct ChangeSet {
+    inner: ProtoChangeSet,
+}
+
+impl From<ProtoChangeSet> for ChangeSet {
+    fn from(inner: ProtoChangeSet) -> Self {
+        Self { inner }
+    }
+}
+
+#[pymethods]
+impl ChangeSet {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes a change set from the bytes of a `ChangeSet` protobuf message, e.g. a revision's
+    /// `change_set` field.
+    #[staticmethod]
+    fn from_bytes(data: &[u8]) -> PyResult<Self> {
+        ProtoChangeSet::decode(data)
+            .map(Self::from)
+            .map_err(|e| OtError::new_err(format!("Could not decode change set: {}", e)))
+    }
+
+    /// Encodes the change set as the bytes of a `ChangeSet` protobuf message.
+    fn to_bytes<'py>(&self, py: Python<'py>) -> PyResult<&'py PyBytes> {
+        let mut data = Vec::with_capacity(self.inner.encoded_len());
+        self.inner
+            .encode(&mut data)
+            .map_err(|e| OtError::new_err(e.to_string()))?;
+        Ok(PyBytes::new(py, &data))
+    }
+
+    fn retain(&mut self, count: i64) {
+        self.inner.retain(count);
+    }
+
+    fn insert(&mut self, content: &str) {
+        self.inner.insert(content);
+    }
+
+    fn delete(&mut self, count: i64) {
+        self.inner.delete(count);
+    }
+
+    /// The ops as `(kind, value)` tuples, e.g. `[("retain", 3), ("insert", "foo")]`.
+    #[getter]
+    fn ops(&self, py: Python) -> PyResult<Vec<PyObject>> {
+        self.inner
+            .iter_ops()
+            .map(|op| {
+                Ok(match op.map_err(to_py_err)? {
+                    Op::Retain(retain) => ("retain", retain.count).into_py(py),
+                    Op::Delete(delete) => ("delete", delete.count).into_py(py),
+                    Op::Insert(insert) => (
+                        "insert",
+                        ot::utils::u32_slice_to_string_lossy(&insert.content),
+                    )
+                        .into_py(py),
+                })
+            })
+            .collect()
+    }
+
+    /// The length of the document that the change set applies to, in UTF-16 code units.
+    #[getter]
+    fn input_len(&self) -> PyResult<i64> {
+        self.inner.input_len().map_err(to_py_err)
+    }
+
+    /// The length of the document after the change set applies, in UTF-16 code units.
+    #[getter]
+    fn output_len(&self) -> PyResult<i64> {
+        self.inner.output_len().map_err(to_py_err)
+    }
+
+    fn is_identity(&self) -> bool {
+        ot::is_identity(&self.inner, None)
+    }
+
+    fn apply(&self, document: &str) -> PyResult<String> {
+        apply(document, self)
+    }
+
+    fn compose(&self, other: &ChangeSet) -> PyResult<ChangeSet> {
+        compose(self, other)
+    }
+
+    fn transform(&self, other: &ChangeSet) -> PyResult<(ChangeSet, ChangeSet)> {
+        transform(self, other)
+    }
+
+    fn invert(&self, document: &str) -> PyResult<ChangeSet> {
+        invert(document, self)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "ChangeSet({})",
+            self.inner.fmt_compact(REPR_MAX_INSERT_CHARS)
+        )
+    }
+
+    fn __richcmp__(&self, other: &ChangeSet, op: CompareOp, py: Python) -> PyObject {
+        match op {
+            CompareOp::Eq => (self.inner == other.inner).into_py(py),
+            CompareOp::Ne => (self.inner != other.inner).into_py(py),
+            _ => py.NotImplemented(),
+        }
+    }
+}
+
+/// Applies the change set to the document, returning the new document.
+#[pyfunction]
+fn apply(document: &str, change_set: &ChangeSet) -> PyResult<String> {
+    ot::apply(document, &change_set.inner).map_err(to_py_err)
+}
+
+/// Returns a change set with the effect of `a` followed by `b`.
+#[pyfunction]
+fn compose(a: &ChangeSet, b: &ChangeSet) -> PyResult<ChangeSet> {
+    ot::compose(&a.inner, &b.inner)
+        .map(ChangeSet::from)
+        .map_err(to_py_err)
+}
+
+/// Composes a list of change sets, e.g. a revision log, into one change set. Faster than
+/// composing them one at a time.
+#[pyfunction]
+fn compose_all(change_sets: Vec<PyRef<ChangeSet>>) -> PyResult<ChangeSet> {
+    ot::compose_iter(change_sets.iter().map(|change_set| &change_set.inner))
+        .map(ChangeSet::from)
+        .map_err(to_py_err)
+}
+
+/// Transforms concurrent change sets `a` and `b` into `(a', b')`, such that `a` followed by `b'`
+/// has the same result as `b` followed by `a'`.
+#[pyfunction]
+fn transform(a: &ChangeSet, b: &ChangeSet) -> PyResult<(ChangeSet, ChangeSet)> {
+    ot::transform(&a.inner, &b.inner)
+        .map(|(a_prime, b_prime)| (a_prime.into(), b_prime.into()))
+        .map_err(to_py_err)
+}
+
+/// Returns the change set that undoes `change_set`, which applies to `document`.
+#[pyfunction]
+fn invert(document: &str, change_set: &ChangeSet) -> PyResult<ChangeSet> {
+    ot::invert(document, &change_set.inner)
+        .map(ChangeSet::from)
+        .map_err(to_py_err)
+}
+
+/// Returns a change set that turns `old_document` into `new_document`.
+#[pyfunction]
+fn diff(old_document: &str, new_document: &str) -> ChangeSet {
+    ot::diff(old_document, new_document).into()
+}
+
+#[pymodule]
+fn writing_ot(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add("OtError", py.get_type::<OtError>())?;
+    m.add_class::<ChangeSet>()?;
+    m.add_function(wrap_pyfunction!(apply, m)?)?;
+    m.add_function(wrap_pyfunction!(compose, m)?)?;
+    m.add_function(wrap_pyfunction!(compose_all, m)?)?;
+    m.add_function(wrap_pyfunction!(transform, m)?)?;
+    m.add_function(wrap_pyfunction!(invert, m)?)?;
+    m.add_function(wrap_pyfunction!(diff, m)?)?;
+    Ok(())
+}